@@ -2,6 +2,73 @@
 
 -- TODO make changelog change types constants
 
+--- CLUSTER
+
+-- In a redis cluster every key starts with this hash tag, which puts them all in the same slot
+-- so the functions can use the keys of many objects in one call.
+-- Callers in a cluster start every key they pass with the tag,
+-- and pass the tag alone as the key of functions that take none, so the call is sent to the right node.
+local KEY_TAG = "{netdox}"
+
+-- Prefix of the keys used by the running function: the hash tag in a cluster, and empty otherwise.
+local key_prefix = ""
+
+-- Commands that take only keys as arguments.
+-- Every other command that uses keys takes one as its first argument, except RENAME and KEYS.
+local ONLY_KEYS = { DEL = true, EXISTS = true, SUNIONSTORE = true }
+
+--- Calls a redis command, adding the key prefix to its keys.
+--- The keys returned by KEYS have the prefix removed.
+local function call(command, ...)
+    if key_prefix == "" then
+        return redis.call(command, ...)
+    end
+
+    local args = { ... }
+    command = string.upper(command)
+    local key_count = 1
+    if ONLY_KEYS[command] then
+        key_count = #args
+    elseif command == "RENAME" then
+        key_count = 2
+    elseif command == "TIME" then
+        key_count = 0
+    end
+    for i = 1, key_count do
+        args[i] = key_prefix .. args[i]
+    end
+
+    local result = redis.call(command, unpack(args))
+    if command == "KEYS" then
+        for i, key in ipairs(result) do
+            result[i] = string.sub(key, #key_prefix + 1)
+        end
+    end
+    return result
+end
+
+--- Returns a function callback that uses the key prefix of the keys it is called with.
+--- The callback is given the keys without the hash tag, and without the tag alone.
+local function with_key_tag(callback)
+    return function(keys, args)
+        key_prefix = ""
+        if keys[1] == nil or string.sub(keys[1], 1, #KEY_TAG) ~= KEY_TAG then
+            return callback(keys, args)
+        end
+
+        local names = {}
+        for _, key in ipairs(keys) do
+            if string.sub(key, 1, #KEY_TAG) ~= KEY_TAG then
+                error("Every key must start with " .. KEY_TAG .. " if the first one does, but got: " .. key)
+            elseif key ~= KEY_TAG then
+                table.insert(names, string.sub(key, #KEY_TAG + 1))
+            end
+        end
+        key_prefix = KEY_TAG
+        return callback(names, args)
+    end
+end
+
 --- UTIL
 
 local function list_to_map(list)
@@ -62,11 +129,111 @@ local function is_qualified(name)
     return end_index
 end
 
+local NETWORK_SUFFIXES_KEY = "network_suffixes"
+
+--- Returns the network for an unqualified DNS name.
+--- This is the network of the longest configured suffix of the name, or the default network.
+local function infer_network(name)
+    if call("EXISTS", NETWORK_SUFFIXES_KEY) == 1 then
+        local suffix = name
+        while suffix do
+            local network = call("HGET", NETWORK_SUFFIXES_KEY, suffix)
+            if network then
+                return network
+            end
+            suffix = string.match(suffix, "^[^.]*%.(.+)$")
+        end
+    end
+
+    return call("GET", DEFAULT_NETWORK_KEY)
+end
+
+--- Returns the groups of one side of an IPv6 address, split around any "::".
+--- Nil if any group is not one to four hex digits.
+local function ipv6_groups(part)
+    local groups = {}
+    if part == "" then
+        return groups
+    end
+
+    for group in string.gmatch(part .. ":", "([^:]*):") do
+        if #group < 1 or #group > 4 then
+            return nil
+        end
+        table.insert(groups, tonumber(group, 16))
+    end
+    return groups
+end
+
+--- Returns the canonical form of an IPv6 address as described in RFC 5952, e.g. 2001:db8::1.
+--- Nil if the name is not an IPv6 address in hexadecimal notation.
+local function canonical_ipv6(name)
+    if not string.find(name, "^[%x:]+$") then
+        return nil
+    end
+
+    local groups
+    local split = string.find(name, "::", 1, true)
+    if split then
+        local head = ipv6_groups(string.sub(name, 1, split - 1))
+        local tail = ipv6_groups(string.sub(name, split + 2))
+        if head == nil or tail == nil or #head + #tail > 7 then
+            return nil
+        end
+
+        groups = head
+        for _ = 1, 8 - #head - #tail do
+            table.insert(groups, 0)
+        end
+        for _, group in ipairs(tail) do
+            table.insert(groups, group)
+        end
+    else
+        groups = ipv6_groups(name)
+        if groups == nil or #groups ~= 8 then
+            return nil
+        end
+    end
+
+    -- Longest run of at least two zero groups, and the first if there is a tie.
+    local best_start, best_len = 0, 1
+    local start, len = 0, 0
+    for i, group in ipairs(groups) do
+        if group == 0 then
+            if len == 0 then
+                start = i
+            end
+            len = len + 1
+            if len > best_len then
+                best_start, best_len = start, len
+            end
+        else
+            len = 0
+        end
+    end
+
+    local hex = {}
+    for i, group in ipairs(groups) do
+        hex[i] = string.format("%x", group)
+    end
+    if best_start == 0 then
+        return table.concat(hex, ":")
+    end
+    return table.concat(hex, ":", 1, best_start - 1) .. "::" .. table.concat(hex, ":", best_start + best_len, 8)
+end
+
 local function qualify_dns_name(name)
-    if is_qualified(name) then
-        return string.lower(name)
+    local lower = string.lower(name)
+    local net_end = is_qualified(lower)
+    if net_end then
+        local address = canonical_ipv6(string.sub(lower, net_end + 1))
+        if address then
+            return string.sub(lower, 1, net_end) .. address
+        end
+        return lower
     else
-        return string.format("[%s]%s", redis.call("GET", DEFAULT_NETWORK_KEY), string.lower(name))
+        lower = canonical_ipv6(lower) or lower
+        return string.format("[%s]%s", infer_network(lower), lower)
     end
 end
 
@@ -77,45 +244,207 @@ local function qualify_dns_names(names, _)
     return names
 end
 
-local ADDRESS_RTYPES = { ["CNAME"] = true, ["A"] = true, ["PTR"] = true, ["NAT"] = true }
+local NETWORKS_KEY = "networks"
+
+--- Returns true if the network of a qualified DNS name is registered,
+--- or if there are no registered networks.
+local function is_registered_network(qname)
+    if call("SCARD", NETWORKS_KEY) == 0 then
+        return true
+    end
+
+    local net_end = is_qualified(qname)
+    return call("SISMEMBER", NETWORKS_KEY, string.sub(qname, 2, net_end - 1)) == 1
+end
+
+local ADDRESS_RTYPES = { ["CNAME"] = true, ["A"] = true, ["AAAA"] = true, ["PTR"] = true, ["NAT"] = true }
+--- Record types that imply a record from their value back to their name.
+local IMPLYING_RTYPES = { ["CNAME"] = true, ["A"] = true, ["AAAA"] = true, ["PTR"] = true }
+
+--- Returns the current time in seconds since the epoch.
+local function now()
+    return tonumber(call("TIME")[1])
+end
 
 --- CHANGELOG
 
 local CHANGELOG_KEY = "changelog"
 
-local function create_change(change, value, plugin)
-    redis.call("XADD", CHANGELOG_KEY, "*", "change", change, "value", value, "plugin", plugin)
+--- Adds an entry to the changelog.
+--- Fields name each part of the changed object's ID, so readers need not split the value.
+local function create_change(change, value, plugin, fields)
+    local entry = { "change", change, "value", value, "plugin", plugin }
+    for name, field in pairs(fields or {}) do
+        table.insert(entry, name)
+        table.insert(entry, field)
+    end
+
+    call("XADD", CHANGELOG_KEY, "*", unpack(entry))
+end
+
+--- Marks the start of a batch of changes from a plugin.
+--- Returns the ID of the batch, which is the ID of the marker in the changelog.
+local function begin_batch(_keys, args)
+    local plugin = args[1]
+    return call("XADD", CHANGELOG_KEY, "*", "change", "begin batch", "value", "", "plugin", plugin)
+end
+
+local function end_batch(_keys, args)
+    local plugin, batch_id = unpack(args)
+    create_change("end batch", batch_id, plugin, { batch_id = batch_id })
 end
 
 --- DNS
 
 local DNS_KEY = "dns"
 local DNS_IGNORE_KEY = "dns_ignore"
+local DNS_IGNORE_PATTERNS_KEY = "dns_ignore;patterns"
+
+--- Returns the Lua pattern that matches the same names as a glob from the DNS ignore list.
+local function glob_to_pattern(glob)
+    local pattern = string.gsub(string.lower(glob), "[%^%$%(%)%%%.%[%]%+%-]", "%%%0")
+    pattern = string.gsub(pattern, "%*", ".*")
+    pattern = string.gsub(pattern, "%?", ".")
+    return "^" .. pattern .. "$"
+end
 
-local function create_dns(names, args)
-    local qname = qualify_dns_name(names[1])
+--- Returns true if a qualified DNS name is in the ignore list,
+--- or if it or the name without its network matches a pattern in the list.
+local function is_ignored(qname)
+    if call("SISMEMBER", DNS_IGNORE_KEY, qname) == 1 then
+        return true
+    end
 
-    if redis.call("SISMEMBER", DNS_IGNORE_KEY, qname) == 1 then
-        return
+    local lower = string.lower(qname)
+    local name = string.sub(lower, (is_qualified(lower) or 0) + 1)
+    for _, glob in ipairs(call("SMEMBERS", DNS_IGNORE_PATTERNS_KEY)) do
+        local pattern = glob_to_pattern(glob)
+        if string.find(lower, pattern) or string.find(name, pattern) then
+            return true
+        end
+    end
+    return false
+end
+
+local PLUGIN_SCOPES_KEY = "plugin_scopes"
+local SCOPE_VIOLATIONS_KEY = "scope_violations"
+
+--- Returns true if an unqualified DNS name is an IPv4 or IPv6 address.
+local function is_address(name)
+    return string.find(name, "^%d+%.%d+%.%d+%.%d+$") ~= nil or canonical_ipv6(name) ~= nil
+end
+
+--- Returns true if a plugin may create a qualified DNS name.
+--- Otherwise records the name as a violation of the plugin's scope.
+local function in_scope(plugin, qname)
+    if call("SISMEMBER", PLUGIN_SCOPES_KEY, plugin) == 0 then
+        return true
+    end
+
+    local lower = string.lower(qname)
+    local net_end = is_qualified(lower)
+    local network, name = string.sub(lower, 2, net_end - 1), string.sub(lower, net_end + 1)
+    local scope_key = string.format("%s;%s", PLUGIN_SCOPES_KEY, plugin)
+
+    local allowed = true
+    local networks_key = scope_key .. ";networks"
+    if call("SCARD", networks_key) > 0 and call("SISMEMBER", networks_key, network) == 0 then
+        allowed = false
+    end
+
+    local zones = call("SMEMBERS", scope_key .. ";zones")
+    if allowed and #zones > 0 and not is_address(name) then
+        allowed = false
+        for _, zone in ipairs(zones) do
+            if name == zone or string.sub(name, -#zone - 1) == "." .. zone then
+                allowed = true
+                break
+            end
+        end
+    end
+
+    if not allowed then
+        call("SADD", SCOPE_VIOLATIONS_KEY, plugin)
+        call("SADD", string.format("%s;%s", SCOPE_VIOLATIONS_KEY, plugin), qname)
+    end
+    return allowed
+end
+
+--- Returns true if a plugin may create all of the qualified DNS names.
+--- Records every name it may not.
+local function all_in_scope(plugin, qnames)
+    local allowed = true
+    for _, qname in ipairs(qnames) do
+        allowed = in_scope(plugin, qname) and allowed
+    end
+    return allowed
+end
+
+--- Raises an error if a plugin would create a new DNS name in a network that is not registered.
+--- Names that already exist can still be written to, and netdox itself is never refused,
+--- as processing only warns about names in unregistered networks.
+local function check_network(qname, plugin)
+    if plugin ~= "netdox"
+        and call("SISMEMBER", DNS_KEY, qname) == 0
+        and not is_registered_network(qname)
+    then
+        error(string.format("Cannot create DNS name in unregistered network: %s", qname))
     end
+end
 
+local function create_dns(names, args)
+    local qname = qualify_dns_name(names[1])
     local plugin, rtype, value = unpack(args)
 
-    if redis.call("SADD", DNS_KEY, qname) ~= 0 then
-        create_change("create dns name", qname, plugin)
+    if is_ignored(qname) or not in_scope(plugin, qname) then
+        return
     end
 
+    -- Every name that would be created is checked before anything is written.
+    local value_allowed = true
     if value ~= nil and rtype ~= nil then
-        -- Qualify value if it is an address.
         rtype = string.upper(rtype)
         if ADDRESS_RTYPES[rtype] then
             value = qualify_dns_name(value)
+            value_allowed = in_scope(plugin, value)
+        end
+    end
+    check_network(qname, plugin)
+    if value ~= nil and value_allowed and ADDRESS_RTYPES[rtype] and not is_ignored(value) then
+        check_network(value, plugin)
+    end
+
+    local seen_key = string.format("%s;%s;seen", DNS_KEY, qname)
+
+    if call("SADD", DNS_KEY, qname) ~= 0 then
+        create_change("create dns name", qname, plugin, { qname = qname })
+    end
+    -- Names are not kept alive by the metadata netdox writes while processing.
+    if plugin ~= "netdox" then
+        call("HSET", seen_key, plugin, now())
+    end
+
+    if value ~= nil and rtype ~= nil then
+        if not value_allowed then
+            return
+        end
+        if ADDRESS_RTYPES[rtype] then
             create_dns({ value }, { plugin })
         end
 
         local record = string.format("%s;%s;%s", plugin, rtype, value)
-        if redis.call("SADD", string.format("%s;%s", DNS_KEY, qname), record) then
-            create_change("create dns record", string.format("%s;%s;%s", DNS_KEY, qname, record), plugin)
+        call("HSET", seen_key, record, now())
+        if call("SADD", string.format("%s;%s", DNS_KEY, qname), record) ~= 0 then
+            create_change(
+                "create dns record",
+                string.format("%s;%s;%s", DNS_KEY, qname, record),
+                plugin,
+                { qname = qname, rtype = rtype, rvalue = value }
+            )
+        end
+
+        if IMPLYING_RTYPES[rtype] then
+            call("SADD", string.format("%s;%s;implied", DNS_KEY, value), qname)
         end
     end
 end
@@ -123,6 +452,9 @@ end
 local function map_dns(names, args)
     local origin = qualify_dns_name(names[1])
     local plugin, reverse = table.remove(args, 1), table.remove(args, 1)
+    if not in_scope(plugin, origin) then
+        return "Cannot map DNS name outside the scope of the plugin."
+    end
     create_dns({ origin }, { plugin })
 
     for _, dest in pairs(args) do
@@ -136,13 +468,16 @@ local function map_dns(names, args)
             return "Cannot map DNS name to its own network."
         end
 
+        if not in_scope(plugin, dest) then
+            return "Cannot map DNS name outside the scope of the plugin."
+        end
         create_dns({ dest }, { plugin })
 
         local dest_name = string.sub(dest, net_end + 1)
         local maps_key = string.format("%s;%s;maps", DNS_KEY, origin)
-        if redis.call("HGET", maps_key, dest_net) ~= dest_name then
-            create_change("updated network mapping", origin, plugin)
-            redis.call("HSET", maps_key, dest_net, dest_name)
+        if call("HGET", maps_key, dest_net) ~= dest_name then
+            create_change("updated network mapping", origin, plugin, { qname = origin, dest = dest })
+            call("HSET", maps_key, dest_net, dest_name)
         end
 
         if reverse == "true" then
@@ -163,43 +498,50 @@ local function create_node(dns_names, args)
     local plugin, name, exclusive, link_id = unpack(args)
     exclusive = exclusive or "false"
 
+    if not all_in_scope(plugin, dns_qnames) then
+        return
+    end
+
     for _, qname in ipairs(dns_qnames) do
         create_dns({ qname }, { plugin })
     end
 
     local node_id = dns_names_to_node_id(dns_qnames)
-    redis.call("SADD", NODES_KEY, node_id)
+    call("SADD", NODES_KEY, node_id)
 
     local node_key = string.format("%s;%s", NODES_KEY, node_id)
-    local node_count = tonumber(redis.call("GET", node_key))
+    local seen_key = string.format("%s;seen", node_key)
+    local node_count = tonumber(call("GET", node_key))
     if node_count == nil then
         node_count = 0
     end
 
     for index = 1, node_count do
-        local details = list_to_map(redis.call("HGETALL", string.format("%s;%s", node_key, index)))
+        local details = list_to_map(call("HGETALL", string.format("%s;%s", node_key, index)))
         if
             details["plugin"] == plugin
             and details["name"] == name
             and details["exclusive"] == exclusive
             and details["link_id"] == link_id
         then
+            call("HSET", seen_key, index, now())
             return
         end
     end
 
-    local index = redis.call("INCR", node_key)
+    local index = call("INCR", node_key)
+    call("HSET", seen_key, index, now())
     local node_details = string.format("%s;%s", node_key, index)
-    redis.call("HSET", node_details, "plugin", plugin)
+    call("HSET", node_details, "plugin", plugin)
     if name ~= nil then
-        redis.call("HSET", node_details, "name", name)
+        call("HSET", node_details, "name", name)
     end
-    redis.call("HSET", node_details, "exclusive", exclusive)
+    call("HSET", node_details, "exclusive", exclusive)
     if link_id ~= nil then
-        redis.call("HSET", node_details, "link_id", link_id)
+        call("HSET", node_details, "link_id", link_id)
     end
 
-    create_change("create plugin node", node_id, plugin)
+    create_change("create plugin node", node_id, plugin, { node_id = node_id })
 
     return node_details
 end
@@ -207,30 +549,157 @@ end
 --- METADATA
 
 local METADATA_KEY = "meta"
+local ROLES_KEY = "roles"
+local ROLE_META_KEY = "role"
+
+--- Returns true if there are no valid roles configured or the role is one of them.
+local function is_valid_role(role)
+    return call("SCARD", ROLES_KEY) == 0 or call("SISMEMBER", ROLES_KEY, role) == 1
+end
+
+local TAGS_KEY = "tags"
+local TAGS_META_KEY = "tags"
+local TAG_PATTERN = "^[%w_-]+$"
+
+--- Adds tags to an object, and adds a change if it did not already have all of them.
+--- Tags are trimmed and lowercased, and can only contain letters, digits, hyphens and underscores.
+local function add_tags(id, plugin, tags)
+    local valid = {}
+    for _, tag in ipairs(tags) do
+        tag = string.lower(string.match(tag, "^%s*(.-)%s*$"))
+        if not string.find(tag, TAG_PATTERN) then
+            error(string.format("Cannot set invalid tag on %s: %s", id, tag))
+        end
+        table.insert(valid, tag)
+    end
+
+    local tags_key = string.format("%s;%s;tags", METADATA_KEY, id)
+    local changed = false
+    for _, tag in ipairs(valid) do
+        call("SADD", TAGS_KEY, tag)
+        call("SADD", string.format("%s;%s", TAGS_KEY, tag), id)
+        if call("SADD", tags_key, tag) ~= 0 then
+            changed = true
+        end
+    end
+
+    if changed then
+        create_change("updated tags", tags_key, plugin, { obj_id = id })
+    end
+end
+
+--- Removes all the tags from an object, and any tags left with no objects.
+local function remove_tags(id)
+    local tags_key = string.format("%s;%s;tags", METADATA_KEY, id)
+    for _, tag in ipairs(call("SMEMBERS", tags_key)) do
+        local tag_key = string.format("%s;%s", TAGS_KEY, tag)
+        call("SREM", tag_key, id)
+        if call("SCARD", tag_key) == 0 then
+            call("SREM", TAGS_KEY, tag)
+        end
+    end
+    call("DEL", tags_key)
+end
+
+--- Splits a comma separated list of tags, skipping blank ones.
+local function split_tags(list)
+    local tags = {}
+    for tag in string.gmatch(list, "[^,]+") do
+        if string.find(tag, "%S") then
+            table.insert(tags, tag)
+        end
+    end
+    return tags
+end
+
+local DAMPENING_KEY = "dampening"
+
+--- Records a value reported for a metadata key, and returns true if changing to it should be
+--- held back because the value has changed more than the configured number of times within the window.
+--- The history of each key is a JSON list of the times and values of the changes reported within the window.
+local function is_dampened(meta_key, key, current, value)
+    local window = tonumber(call("HGET", DAMPENING_KEY, "window"))
+    local max_changes = tonumber(call("HGET", DAMPENING_KEY, "max_changes"))
+    if window == nil or max_changes == nil or current == nil then
+        return false
+    end
+
+    local history_key = string.format("%s;history", meta_key)
+    local encoded = call("HGET", history_key, key)
+    if not encoded and current == value then
+        return false
+    end
+
+    local time = now()
+    local history = {}
+    if encoded then
+        for _, change in ipairs(cjson.decode(encoded)) do
+            if change[1] >= time - window then
+                table.insert(history, change)
+            end
+        end
+    end
+
+    local previous = current
+    if #history > 0 then
+        previous = history[#history][2]
+    end
+    if value ~= previous then
+        table.insert(history, { time, value })
+    end
+
+    if #history == 0 then
+        call("HDEL", history_key, key)
+    else
+        call("HSET", history_key, key, cjson.encode(history))
+    end
+
+    return value ~= current and #history > max_changes
+end
 
 local function create_metadata(id, plugin, args)
     local changed = false
+    local new_vals = list_to_map(args)
+
+    if new_vals[ROLE_META_KEY] ~= nil and not is_valid_role(new_vals[ROLE_META_KEY]) then
+        error(string.format("Cannot set invalid role on %s: %s", id, new_vals[ROLE_META_KEY]))
+    end
+
+    -- Tags are added to the tags of the object instead of being stored as metadata.
+    if new_vals[TAGS_META_KEY] ~= nil then
+        add_tags(id, plugin, split_tags(new_vals[TAGS_META_KEY]))
+        new_vals[TAGS_META_KEY] = nil
+    end
 
-    redis.call("SADD", METADATA_KEY, id)
+    call("SADD", METADATA_KEY, id)
 
     local meta_key = string.format("meta;%s", id)
     local meta_plugins = string.format("%s;plugins", meta_key)
+    local meta_sources = string.format("%s;sources", meta_key)
+    local meta_changed = string.format("%s;changed", meta_key)
 
-    if redis.call("SADD", meta_plugins, plugin) ~= 0 then
+    if call("SADD", meta_plugins, plugin) ~= 0 then
         changed = true
     end
 
-    local old_vals = list_to_map(redis.call("HGETALL", meta_key))
+    local old_vals = list_to_map(call("HGETALL", meta_key))
 
-    for key, value in pairs(list_to_map(args)) do
-        if old_vals[key] ~= value then
+    -- The source of a key is the last plugin to change its value.
+    for key, value in pairs(new_vals) do
+        if is_dampened(meta_key, key, old_vals[key], value) then
+            -- The value is flapping, so it is not changed until it is stable.
+        elseif old_vals[key] ~= value then
             changed = true
-            redis.call("HSET", meta_key, key, value)
+            call("HSET", meta_key, key, value)
+            call("HSET", meta_sources, key, plugin)
+            call("HSET", meta_changed, key, now())
+        elseif call("HEXISTS", meta_sources, key) == 0 then
+            call("HSET", meta_sources, key, plugin)
         end
     end
 
     if changed then
-        create_change("updated metadata", meta_key, plugin)
+        create_change("updated metadata", meta_key, plugin, { obj_id = id })
     end
 end
 
@@ -238,6 +707,9 @@ local function create_dns_metadata(names, args)
     local qname = qualify_dns_name(names[1])
     local plugin = table.remove(args, 1)
 
+    if not in_scope(plugin, qname) then
+        return
+    end
     create_dns({ qname }, { plugin })
     create_metadata(string.format("%s;%s", DNS_KEY, qname), plugin, args)
 end
@@ -246,8 +718,12 @@ local function create_node_metadata(names, args)
     local qnames = qualify_dns_names(names)
     local plugin = table.remove(args, 1)
 
+    if not all_in_scope(plugin, qnames) then
+        return
+    end
+
     local node_id = dns_names_to_node_id(qnames)
-    if redis.call("SISMEMBER", NODES_KEY, node_id) == 0 then
+    if call("SISMEMBER", NODES_KEY, node_id) == 0 then
         create_node(qnames, { plugin })
     end
 
@@ -260,23 +736,82 @@ local function create_proc_node_metadata(keys, args)
 
     create_metadata(string.format("%s;%s", PROC_NODES_KEY, link_id), plugin, args)
 end
--- DATA
 
-local function create_data_str(data_key, plugin, title, content_type, content)
-    local created = false
-    local changed = false
-    local details_key = string.format("%s;details", data_key)
-    local dtype = redis.call("TYPE", details_key)["ok"]
+local function create_dns_tags(names, args)
+    local qname = qualify_dns_name(names[1])
+    local plugin = table.remove(args, 1)
 
-    if dtype == "none" then
-        created = true
-    elseif dtype ~= "hash" then
-        redis.call("DEL", data_key)
-        changed = true
+    if not in_scope(plugin, qname) then
+        return
+    end
+    create_dns({ qname }, { plugin })
+    add_tags(string.format("%s;%s", DNS_KEY, qname), plugin, args)
+end
+
+local function create_node_tags(names, args)
+    local qnames = qualify_dns_names(names)
+    local plugin = table.remove(args, 1)
+
+    if not all_in_scope(plugin, qnames) then
+        return
+    end
+
+    local node_id = dns_names_to_node_id(qnames)
+    if call("SISMEMBER", NODES_KEY, node_id) == 0 then
+        create_node(qnames, { plugin })
+    end
+
+    add_tags(string.format("%s;%s", NODES_KEY, node_id), plugin, args)
+end
+
+local function create_proc_node_tags(keys, args)
+    local link_id = keys[1]
+    local plugin = table.remove(args, 1)
+
+    add_tags(string.format("%s;%s", PROC_NODES_KEY, link_id), plugin, args)
+end
+
+--- Creates a node, and sets its role if one is given as the fifth argument.
+local function create_node_with_role(dns_names, args)
+    local plugin, role = args[1], args[5]
+    if role ~= nil and not is_valid_role(role) then
+        error(string.format("Cannot create node with invalid role: %s", role))
+    end
+
+    local node_details = create_node(dns_names, { unpack(args, 1, 4) })
+
+    if role ~= nil then
+        create_node_metadata(dns_names, { plugin, ROLE_META_KEY, role })
+    end
+
+    return node_details
+end
+
+-- DATA
+
+--- Deletes the content of some data if it was previously stored as another type.
+--- Returns true if the data existed with another type.
+local function clear_retyped_data(data_key, old_type, new_type)
+    if old_type == nil or old_type == new_type then
+        return false
     end
 
+    call(
+        "DEL",
+        data_key,
+        string.format("%s;order", data_key),
+        string.format("%s;names", data_key),
+        string.format("%s;titles", data_key)
+    )
+    return true
+end
+
+local function create_data_str(data_key, fields, plugin, title, content_type, content)
     local details_key = string.format("%s;details", data_key)
-    local old_details = list_to_map(redis.call("HGETALL", details_key))
+    local created = call("EXISTS", details_key) == 0
+    local old_details = list_to_map(call("HGETALL", details_key))
+    local changed = clear_retyped_data(data_key, old_details["type"], "string")
+
     local new_details = {
         type = "string",
         plugin = plugin,
@@ -292,37 +827,29 @@ local function create_data_str(data_key, plugin, title, content_type, content)
             and old_details["content_type"] == new_details["content_type"]
         )
     then
-        redis.call("HSET", details_key, unpack(map_to_list(new_details)))
+        call("HSET", details_key, unpack(map_to_list(new_details)))
         changed = true
     end
 
-    if redis.call("GET", data_key) ~= content then
-        redis.call("SET", data_key, content)
+    if call("GET", data_key) ~= content then
+        call("SET", data_key, content)
         changed = true
     end
 
     if created == true then
-        create_change("created data", data_key, plugin)
+        create_change("created data", data_key, plugin, fields)
     elseif changed == true and created == false then
-        create_change("updated data", data_key, plugin)
+        create_change("updated data", data_key, plugin, fields)
     end
 end
 
-local function create_data_hash(data_key, plugin, title, content)
-    local created = false
-    local changed = false
+--- Content is a flat list of keys and values, in the order they should be displayed.
+local function create_data_hash(data_key, fields, plugin, title, content)
     local details_key = string.format("%s;details", data_key)
-    local dtype = redis.call("TYPE", details_key)["ok"]
-
-    if dtype == "none" then
-        created = true
-    elseif dtype ~= "hash" then
-        redis.call("DEL", data_key)
-        changed = true
-    end
+    local created = call("EXISTS", details_key) == 0
+    local old_details = list_to_map(call("HGETALL", details_key))
+    local changed = clear_retyped_data(data_key, old_details["type"], "hash")
 
-    local details_key = string.format("%s;details", data_key)
-    local old_details = list_to_map(redis.call("HGETALL", details_key))
     local new_details = {
         type = "hash",
         plugin = plugin,
@@ -336,61 +863,56 @@ local function create_data_hash(data_key, plugin, title, content)
             and old_details["title"] == new_details["title"]
         )
     then
-        redis.call("HSET", details_key, unpack(map_to_list(new_details)))
+        call("HSET", details_key, unpack(map_to_list(new_details)))
         changed = true
     end
 
-    local index = 1
+    -- Keys are ordered as they were given, as iterating over a table has no defined order.
     local order = {}
-    local old_vals = list_to_map(redis.call("HGETALL", data_key))
-
-    local data_changed = false
-    for key, val in pairs(content) do
-        order[index] = key
-        index = index + 1
-
-        if old_vals[key] ~= val then
-            data_changed = true
+    local values = {}
+    for index = 1, #content - 1, 2 do
+        local key = content[index]
+        if values[key] == nil then
+            order[#order + 1] = key
         end
+        values[key] = content[index + 1]
     end
 
+    local old_vals = list_to_map(call("HGETALL", data_key))
     local order_key = string.format("%s;order", data_key)
-    if not cmp_lists(order, redis.call("LRANGE", order_key, 0, -1)) then
-        data_changed = true
+    local data_changed = not cmp_lists(order, call("LRANGE", order_key, 0, -1))
+    for key, val in pairs(values) do
+        if old_vals[key] ~= val then
+            data_changed = true
+        end
     end
 
-    -- TODO add size diffing
-
     if data_changed == true then
-        redis.call("DEL", data_key)
-        redis.call("HSET", data_key, unpack(map_to_list(content)))
+        call("DEL", data_key)
+        call("HSET", data_key, unpack(map_to_list(values)))
 
-        redis.call("DEL", order_key)
-        redis.call("RPUSH", order_key, unpack(order))
+        call("DEL", order_key)
+        call("RPUSH", order_key, unpack(order))
 
         changed = true
     end
 
     if created == true then
-        create_change("created data", data_key, plugin)
+        create_change("created data", data_key, plugin, fields)
     elseif changed == true and created == false then
-        create_change("updated data", data_key, plugin)
+        create_change("updated data", data_key, plugin, fields)
     end
 end
 
-local function create_data_list(data_key, plugin, title, content)
+local function create_data_list(data_key, fields, plugin, title, content)
     local names_key = string.format("%s;names", data_key)
     local titles_key = string.format("%s;titles", data_key)
     local details_key = string.format("%s;details", data_key)
 
-    local created = false
-    local changed = false
-
-    if redis.call("TYPE", details_key)["ok"] == "none" then
-        created = true
-    end
+    local created = call("EXISTS", details_key) == 0
+    local old_details = list_to_map(call("HGETALL", details_key))
+    local changed = clear_retyped_data(data_key, old_details["type"], "list")
 
-    local old_details = list_to_map(redis.call("HGETALL", details_key))
     local new_details = {
         type = "list",
         plugin = plugin,
@@ -404,7 +926,7 @@ local function create_data_list(data_key, plugin, title, content)
             and old_details["title"] == new_details["title"]
         )
     then
-        redis.call("HSET", details_key, unpack(map_to_list(new_details)))
+        call("HSET", details_key, unpack(map_to_list(new_details)))
         changed = true
     end
 
@@ -418,42 +940,33 @@ local function create_data_list(data_key, plugin, title, content)
         target[#target + 1] = item
     end
 
-    local names = redis.call("LRANGE", names_key, 0, -1)
-    local titles = redis.call("LRANGE", titles_key, 0, -1)
-    local values = redis.call("LRANGE", data_key, 0, -1)
+    local names = call("LRANGE", names_key, 0, -1)
+    local titles = call("LRANGE", titles_key, 0, -1)
+    local values = call("LRANGE", data_key, 0, -1)
     if not (cmp_lists(proplist[1], names) and cmp_lists(proplist[2], titles) and cmp_lists(proplist[3], values)) then
-        redis.call("DEL", names_key, titles_key, data_key)
+        call("DEL", names_key, titles_key, data_key)
         if #proplist[1] > 0 then
-            redis.call("RPUSH", names_key, unpack(proplist[1]))
-            redis.call("RPUSH", titles_key, unpack(proplist[2]))
-            redis.call("RPUSH", data_key, unpack(proplist[3]))
+            call("RPUSH", names_key, unpack(proplist[1]))
+            call("RPUSH", titles_key, unpack(proplist[2]))
+            call("RPUSH", data_key, unpack(proplist[3]))
         end
 
         changed = true
     end
 
     if created == true then
-        create_change("created data", data_key, plugin)
+        create_change("created data", data_key, plugin, fields)
     elseif changed == true and created == false then
-        create_change("updated data", data_key, plugin)
+        create_change("updated data", data_key, plugin, fields)
     end
 end
 
-local function create_data_table(data_key, plugin, title, columns, content)
-    local created = false
-    local changed = false
+local function create_data_table(data_key, fields, plugin, title, columns, content)
     local details_key = string.format("%s;details", data_key)
-    local dtype = redis.call("TYPE", details_key)["ok"]
+    local created = call("EXISTS", details_key) == 0
+    local old_details = list_to_map(call("HGETALL", details_key))
+    local changed = clear_retyped_data(data_key, old_details["type"], "table")
 
-    if dtype == "none" then
-        created = true
-    elseif dtype ~= "hash" then
-        redis.call("DEL", data_key)
-        changed = true
-    end
-
-    local details_key = string.format("%s;details", data_key)
-    local old_details = list_to_map(redis.call("HGETALL", details_key))
     local new_details = {
         type = "table",
         plugin = plugin,
@@ -469,39 +982,42 @@ local function create_data_table(data_key, plugin, title, columns, content)
             and old_details["columns"] == new_details["columns"]
         )
     then
-        redis.call("HSET", details_key, unpack(map_to_list(new_details)))
+        call("HSET", details_key, unpack(map_to_list(new_details)))
         changed = true
     end
 
-    if not cmp_lists(content, redis.call("LRANGE", data_key, 0, -1)) then
-        redis.call("DEL", data_key)
-        redis.call("RPUSH", data_key, unpack(content))
+    if not cmp_lists(content, call("LRANGE", data_key, 0, -1)) then
+        call("DEL", data_key)
+        if #content > 0 then
+            call("RPUSH", data_key, unpack(content))
+        end
         changed = true
     end
 
     if created == true then
-        create_change("created data", data_key, plugin)
+        create_change("created data", data_key, plugin, fields)
     elseif changed == true and created == false then
-        create_change("updated data", data_key, plugin)
+        create_change("updated data", data_key, plugin, fields)
     end
 end
 
-local function create_data(data_key, plugin, dtype, args)
+--- Fields are the changelog fields identifying the data: obj_id, data_id, and kind.
+local function create_data(data_key, fields, plugin, dtype, args)
     if dtype == "list" then
         local title = table.remove(args, 1)
-        create_data_list(data_key, plugin, title, args)
+        create_data_list(data_key, fields, plugin, title, args)
     elseif dtype == "hash" then
         local title = table.remove(args, 1)
-        create_data_hash(data_key, plugin, title, list_to_map(args))
+        create_data_hash(data_key, fields, plugin, title, args)
     elseif dtype == "string" then
         local title = table.remove(args, 1)
         local content_type = table.remove(args, 1)
         local content = table.remove(args, 1)
-        create_data_str(data_key, plugin, title, content_type, content)
+        create_data_str(data_key, fields, plugin, title, content_type, content)
     elseif dtype == "table" then
         local title = table.remove(args, 1)
         local columns = table.remove(args, 1)
-        create_data_table(data_key, plugin, title, columns, args)
+        create_data_table(data_key, fields, plugin, title, columns, args)
     end
 end
 
@@ -515,16 +1031,19 @@ local function create_plugin_data(obj_key, args)
     local pdata_id = table.remove(args, 1)
 
     local pdata_key = string.format("%s;%s", PLUGIN_DATA_KEY, obj_key)
-    redis.call("SADD", pdata_key, pdata_id)
+    call("SADD", pdata_key, pdata_id)
 
     local data_key = string.format("%s;%s", pdata_key, pdata_id)
-    create_data(data_key, plugin, dtype, args)
+    create_data(data_key, { obj_id = obj_key, data_id = pdata_id, kind = "plugin" }, plugin, dtype, args)
 end
 
 local function create_dns_plugin_data(names, args)
     local qname = qualify_dns_name(names[1])
     local plugin = args[1]
 
+    if not in_scope(plugin, qname) then
+        return
+    end
     create_dns({ qname }, { plugin })
     return create_plugin_data(string.format("%s;%s", DNS_KEY, qname), args)
 end
@@ -533,8 +1052,12 @@ local function create_node_plugin_data(names, args)
     local qnames = qualify_dns_names(names)
     local plugin = args[1]
 
+    if not all_in_scope(plugin, qnames) then
+        return
+    end
+
     local node_id = dns_names_to_node_id(qnames)
-    if redis.call("SISMEMBER", NODES_KEY, node_id) == 0 then
+    if call("SISMEMBER", NODES_KEY, node_id) == 0 then
         create_node(qnames, { plugin })
     end
 
@@ -548,6 +1071,46 @@ local function create_proc_node_plugin_data(_link_id, args)
     return create_plugin_data(string.format("%s;%s", PROC_NODES_KEY, link_id), args)
 end
 
+--- Deletes some plugin data attached to an object.
+--- Returns 1 if the data was deleted, and 0 if it did not exist.
+local function delete_plugin_data(obj_key, args)
+    local plugin = args[1]
+    local pdata_id = args[2]
+
+    local pdata_key = string.format("%s;%s", PLUGIN_DATA_KEY, obj_key)
+    local data_key = string.format("%s;%s", pdata_key, pdata_id)
+    local owner = call("HGET", string.format("%s;details", data_key), "plugin")
+    if owner == false then
+        call("SREM", pdata_key, pdata_id)
+        return 0
+    elseif owner ~= plugin then
+        error(string.format("Cannot delete plugin data %s created by another plugin: %s", pdata_id, owner))
+    end
+
+    call("SREM", pdata_key, pdata_id)
+    for _, suffix in ipairs({ "", ";details", ";order", ";names", ";titles" }) do
+        call("DEL", data_key .. suffix)
+    end
+
+    create_change("deleted data", data_key, plugin, { obj_id = obj_key, data_id = pdata_id, kind = "plugin" })
+    return 1
+end
+
+local function delete_dns_plugin_data(names, args)
+    local qname = qualify_dns_name(names[1])
+    return delete_plugin_data(string.format("%s;%s", DNS_KEY, qname), args)
+end
+
+local function delete_node_plugin_data(names, args)
+    local node_id = dns_names_to_node_id(qualify_dns_names(names))
+    return delete_plugin_data(string.format("%s;%s", NODES_KEY, node_id), args)
+end
+
+local function delete_proc_node_plugin_data(_link_id, args)
+    local link_id = _link_id[1]
+    return delete_plugin_data(string.format("%s;%s", PROC_NODES_KEY, link_id), args)
+end
+
 --- REPORTS
 
 local REPORTS_KEY = "reports"
@@ -556,7 +1119,7 @@ local function create_report(_id, args)
     local id = _id[1]
 
     local changed = false
-    if redis.call("SADD", REPORTS_KEY, id) ~= 0 then
+    if call("SADD", REPORTS_KEY, id) ~= 0 then
         changed = true
     end
 
@@ -565,7 +1128,7 @@ local function create_report(_id, args)
     local title = table.remove(args, 1)
     local length = table.remove(args, 1)
 
-    local old_details = list_to_map(redis.call("HGETALL", data_key))
+    local old_details = list_to_map(call("HGETALL", data_key))
     local new_details = {
         plugin = plugin,
         title = title,
@@ -579,12 +1142,12 @@ local function create_report(_id, args)
             and old_details["length"] == new_details["length"]
         )
     then
-        redis.call("HSET", data_key, unpack(map_to_list(new_details)))
+        call("HSET", data_key, unpack(map_to_list(new_details)))
         changed = true
     end
 
     if changed == true then
-        create_change("create report", id, plugin)
+        create_change("create report", id, plugin, { report_id = id })
     end
 end
 
@@ -594,61 +1157,1042 @@ local function create_report_data(_id, args)
     local index = table.remove(args, 1)
     local dtype = table.remove(args, 1)
     local data_key = string.format("%s;%s;%s", REPORTS_KEY, id, index)
-    create_data(data_key, plugin, dtype, args)
+    local fields = { obj_id = string.format("%s;%s", REPORTS_KEY, id), data_id = index, kind = "report" }
+    create_data(data_key, fields, plugin, dtype, args)
 end
 
---- INITIALISATION
-local function setup(keys, args)
-    local default_network = keys[1]
-    redis.call("DEL", DEFAULT_NETWORK_KEY)
-    redis.call("SET", DEFAULT_NETWORK_KEY, default_network)
+--- DEPENDENCIES
 
-    redis.call("DEL", DNS_IGNORE_KEY)
-    if #args ~= 0 then
-        redis.call("SADD", DNS_IGNORE_KEY, unpack(args))
-    end
-end
+local DEPENDENCIES_KEY = "dependencies"
 
-local function init(keys, args)
-    redis.call("DEL", CHANGELOG_KEY)
-    create_change("init", "", "netdox")
-end
+--- Declares that the first processed node depends on the second, like an app server on its database.
+local function create_node_dependency(link_ids, args)
+    local source, dest = link_ids[1], link_ids[2]
+    local plugin, label = args[1], args[2] or ""
 
---- FUNCTION REGISTRATION
+    if source == nil or dest == nil then
+        error("Cannot create dependency without the link IDs of two nodes.")
+    elseif source == dest then
+        error(string.format("Cannot create dependency of node on itself: %s", source))
+    end
 
-redis.register_function({
+    for _, link_id in ipairs({ source, dest }) do
+        if call("SISMEMBER", PROC_NODES_KEY, link_id) == 0 then
+            error(string.format("Cannot create dependency on node that does not exist: %s", link_id))
+        end
+    end
+
+    local deps_key = string.format("%s;%s", DEPENDENCIES_KEY, source)
+    call("SADD", string.format("%s;%s;dependents", DEPENDENCIES_KEY, dest), source)
+    if call("HGET", deps_key, dest) ~= label then
+        call("HSET", deps_key, dest, label)
+        create_change("updated dependency", source, plugin, { source = source, dest = dest })
+    end
+end
+
+--- RELATIONS
+
+local RELATIONS_KEY = "relations"
+local RELATION_PATTERN = "^[%w_-]+$"
+
+--- Declares a typed relationship from the first processed node to the second, like a VM on its host.
+local function create_node_relation(link_ids, args)
+    local source, dest = link_ids[1], link_ids[2]
+    local plugin, relation = args[1], string.lower(args[2] or "")
+
+    if source == nil or dest == nil then
+        error("Cannot create relation without the link IDs of two nodes.")
+    elseif source == dest then
+        error(string.format("Cannot create relation of node to itself: %s", source))
+    elseif not string.match(relation, RELATION_PATTERN) then
+        error(string.format("Cannot create relation with invalid type: %s", relation))
+    end
+
+    for _, link_id in ipairs({ source, dest }) do
+        if call("SISMEMBER", PROC_NODES_KEY, link_id) == 0 then
+            error(string.format("Cannot create relation on node that does not exist: %s", link_id))
+        end
+    end
+
+    -- Relation types cannot contain semicolons, so members split on the first one.
+    local incoming_key = string.format("%s;%s;incoming", RELATIONS_KEY, dest)
+    call("SADD", incoming_key, string.format("%s;%s", relation, source))
+
+    local relations_key = string.format("%s;%s", RELATIONS_KEY, source)
+    if call("SADD", relations_key, string.format("%s;%s", relation, dest)) ~= 0 then
+        create_change("updated relation", source, plugin, { source = source, dest = dest, relation = relation })
+    end
+end
+
+--- Removes the relations of a processed node to other nodes and of other nodes to it.
+--- Members referring to the node in the sets of other nodes are skipped when read.
+local function remove_relations(link_id)
+    local relations_key = string.format("%s;%s", RELATIONS_KEY, link_id)
+    call("DEL", relations_key, relations_key .. ";incoming")
+end
+
+--- SERVICES
+
+local SERVICES_KEY = "services"
+local SERVICE_PROTOCOL_PATTERN = "^%a+$"
+
+--- Returns the ID of the service on a port and protocol, like 443/tcp.
+local function service_id(port, protocol)
+    local number = tonumber(port)
+    if number == nil or number % 1 ~= 0 or number < 1 or number > 65535 then
+        error(string.format("Cannot create service with invalid port: %s", tostring(port)))
+    end
+
+    protocol = string.lower(protocol or "")
+    if not string.match(protocol, SERVICE_PROTOCOL_PATTERN) then
+        error(string.format("Cannot create service with invalid protocol: %s", protocol))
+    end
+
+    return string.format("%d/%s", number, protocol)
+end
+
+--- Declares a service that a processed node exposes on a port, with details like the product running it.
+--- Details from every plugin that declares the same service are merged.
+local function create_node_service(link_ids, args)
+    local link_id = link_ids[1]
+    local plugin = table.remove(args, 1)
+    local id = service_id(table.remove(args, 1), table.remove(args, 1))
+
+    if link_id == nil then
+        error("Cannot create service without the link ID of a node.")
+    elseif call("SISMEMBER", PROC_NODES_KEY, link_id) == 0 then
+        error(string.format("Cannot create service on node that does not exist: %s", link_id))
+    end
+
+    local service_key = string.format("%s;%s;%s", SERVICES_KEY, link_id, id)
+    local changed = call("SADD", string.format("%s;%s", SERVICES_KEY, link_id), id) ~= 0
+    if call("SADD", string.format("%s;plugins", service_key), plugin) ~= 0 then
+        changed = true
+    end
+
+    for key, value in pairs(list_to_map(args)) do
+        if call("HGET", service_key, key) ~= value then
+            call("HSET", service_key, key, value)
+            changed = true
+        end
+    end
+
+    if changed then
+        create_change("updated service", service_key, plugin, { link_id = link_id, service = id })
+    end
+end
+
+--- Removes every service of a processed node.
+local function remove_services(link_id)
+    local services_key = string.format("%s;%s", SERVICES_KEY, link_id)
+    for _, id in ipairs(call("SMEMBERS", services_key)) do
+        local service_key = string.format("%s;%s", services_key, id)
+        call("DEL", service_key, string.format("%s;plugins", service_key))
+    end
+    call("DEL", services_key)
+end
+
+--- INDEXES
+
+local DNS_NODES_KEY = "dns_nodes"
+local PROC_NODE_REVS_KEY = "proc_node_revs"
+
+--- Makes the hash at key match the expected map.
+--- Returns the number of fields that were added, changed, or removed.
+local function sync_hash(key, expected)
+    local fixed = 0
+
+    for field, _ in pairs(list_to_map(call("HGETALL", key))) do
+        if expected[field] == nil then
+            call("HDEL", key, field)
+            fixed = fixed + 1
+        end
+    end
+
+    for field, value in pairs(expected) do
+        if call("HGET", key, field) ~= value then
+            call("HSET", key, field, value)
+            fixed = fixed + 1
+        end
+    end
+
+    return fixed
+end
+
+--- Makes the set at key contain exactly the keys of the expected map.
+--- Returns the number of members that were added or removed.
+local function sync_set(key, expected)
+    local fixed = 0
+
+    for _, member in ipairs(call("SMEMBERS", key)) do
+        if expected[member] == nil then
+            call("SREM", key, member)
+            fixed = fixed + 1
+        end
+    end
+
+    for member, _ in pairs(expected) do
+        fixed = fixed + call("SADD", key, member)
+    end
+
+    return fixed
+end
+
+local function rebuild_index(_keys, _args)
+    local dns_nodes = {}
+    local node_revs = {}
+    for _, link_id in ipairs(call("SMEMBERS", PROC_NODES_KEY)) do
+        local node_key = string.format("%s;%s", PROC_NODES_KEY, link_id)
+        for _, qname in ipairs(call("SMEMBERS", string.format("%s;dns_names", node_key))) do
+            dns_nodes[qname] = link_id
+        end
+        for _, raw_id in ipairs(call("SMEMBERS", string.format("%s;raw_ids", node_key))) do
+            node_revs[raw_id] = link_id
+        end
+    end
+
+    -- Every piece of plugin data has a details hash at pdata;${OBJECT_KEY};${DATA_ID};details
+    local pdata_sets = {}
+    for _, details_key in ipairs(call("KEYS", string.format("%s;*;details", PLUGIN_DATA_KEY))) do
+        local set_key, pdata_id = string.match(details_key, "^(.+);([^;]+);details$")
+        if pdata_sets[set_key] == nil then
+            pdata_sets[set_key] = {}
+        end
+        pdata_sets[set_key][pdata_id] = true
+    end
+
+    local implied = {}
+    local qnames = call("SMEMBERS", DNS_KEY)
+    for _, qname in ipairs(qnames) do
+        for _, record in ipairs(call("SMEMBERS", string.format("%s;%s", DNS_KEY, qname))) do
+            local _, rtype, value = string.match(record, "^([^;]*);([^;]*);(.*)$")
+            if IMPLYING_RTYPES[rtype] then
+                if implied[value] == nil then
+                    implied[value] = {}
+                end
+                implied[value][qname] = true
+            end
+        end
+    end
+
+    local implied_fixed = 0
+    for _, qname in ipairs(qnames) do
+        implied_fixed = implied_fixed
+            + sync_set(string.format("%s;%s;implied", DNS_KEY, qname), implied[qname] or {})
+    end
+
+    local pdata_fixed = 0
+    for _, key in ipairs(call("KEYS", string.format("%s;*", PLUGIN_DATA_KEY))) do
+        if pdata_sets[key] == nil and call("TYPE", key)["ok"] == "set" then
+            pdata_fixed = pdata_fixed + call("SCARD", key)
+            call("DEL", key)
+        end
+    end
+
+    for set_key, members in pairs(pdata_sets) do
+        pdata_fixed = pdata_fixed + sync_set(set_key, members)
+    end
+
+    return {
+        sync_hash(DNS_NODES_KEY, dns_nodes),
+        sync_hash(PROC_NODE_REVS_KEY, node_revs),
+        implied_fixed,
+        pdata_fixed,
+    }
+end
+
+--- Moves metadata stored under the legacy layout, meta;${ID}, to meta;${OBJECT_KEY};${ID}.
+--- Nothing is written if the first arg is "dry-run".
+--- Returns a flat list of legacy and new object IDs that were (or would be) moved,
+--- and a list of legacy object IDs that could not be resolved to a single object.
+local function migrate_metadata(_keys, args)
+    local dry_run = args[1] == "dry-run"
+
+    -- Legacy object IDs are qualified DNS names or raw node IDs, both of which start with [network].
+    local legacy = {}
+    for _, key in ipairs(call("KEYS", string.format("%s;[[]*", METADATA_KEY))) do
+        local id = string.match(key, "^meta;(.-);plugins$") or string.match(key, "^meta;(.+)$")
+        legacy[id] = true
+    end
+
+    local moved, unresolved = {}, {}
+    for id, _ in pairs(legacy) do
+        local is_dns = call("SISMEMBER", DNS_KEY, id) == 1
+        local is_node = call("SISMEMBER", NODES_KEY, id) == 1
+
+        if is_dns == is_node then
+            table.insert(unresolved, id)
+        else
+            local new_id = string.format("%s;%s", is_dns and DNS_KEY or NODES_KEY, id)
+            table.insert(moved, id)
+            table.insert(moved, new_id)
+
+            if not dry_run then
+                local old_key = string.format("%s;%s", METADATA_KEY, id)
+                local new_key = string.format("%s;%s", METADATA_KEY, new_id)
+
+                -- Values written under the new layout are newer, so they are kept.
+                local values = call("HGETALL", old_key)
+                for i = 1, #values, 2 do
+                    call("HSETNX", new_key, values[i], values[i + 1])
+                end
+
+                local plugins = string.format("%s;plugins", old_key)
+                if call("EXISTS", plugins) == 1 then
+                    call("SUNIONSTORE", string.format("%s;plugins", new_key), string.format("%s;plugins", new_key), plugins)
+                end
+
+                call("DEL", old_key, plugins)
+                call("SREM", METADATA_KEY, id)
+                call("SADD", METADATA_KEY, new_id)
+                create_change("updated metadata", new_key, "netdox", { obj_id = new_id })
+            end
+        end
+    end
+
+    table.sort(unresolved)
+    return { moved, unresolved }
+end
+
+--- PRUNING
+
+--- Metadata keys that processing sets on a DNS name to link it to its processed node.
+local NODE_META_KEYS = { "node", "_node" }
+
+--- Returns true if something a plugin last reported at a time is older than the cutoff for the plugin.
+--- Cutoffs map plugin names to times, and the empty string to the default time.
+local function is_stale(cutoffs, plugin, seen)
+    local cutoff = cutoffs[plugin] or cutoffs[""]
+    return cutoff ~= nil and tonumber(seen) < cutoff
+end
+
+--- Deletes the plugin data, metadata and tags attached to an object.
+local function delete_object_data(obj_id)
+    local pdata_key = string.format("%s;%s", PLUGIN_DATA_KEY, obj_id)
+    for _, pdata_id in ipairs(call("SMEMBERS", pdata_key)) do
+        local data_key = string.format("%s;%s", pdata_key, pdata_id)
+        for _, suffix in ipairs({ "", ";details", ";order", ";names", ";titles" }) do
+            call("DEL", data_key .. suffix)
+        end
+    end
+    call("DEL", pdata_key)
+
+    local meta_key = string.format("%s;%s", METADATA_KEY, obj_id)
+    call(
+        "DEL",
+        meta_key,
+        meta_key .. ";plugins",
+        meta_key .. ";sources",
+        meta_key .. ";changed",
+        meta_key .. ";history"
+    )
+    call("SREM", METADATA_KEY, obj_id)
+    remove_tags(obj_id)
+end
+
+--- Removes the raw node details at some indices, moving the rest down to fill the gaps.
+--- Returns the number of details left.
+local function remove_node_details(node_key, count, removed)
+    local seen_key = string.format("%s;seen", node_key)
+    local seen = list_to_map(call("HGETALL", seen_key))
+    local kept = {}
+    for index = 1, count do
+        local details_key = string.format("%s;%s", node_key, index)
+        if not removed[index] then
+            table.insert(kept, { call("HGETALL", details_key), seen[tostring(index)] })
+        end
+        call("DEL", details_key)
+    end
+
+    call("DEL", seen_key)
+    for index, node in ipairs(kept) do
+        call("HSET", string.format("%s;%s", node_key, index), unpack(node[1]))
+        if node[2] ~= nil then
+            call("HSET", seen_key, index, node[2])
+        end
+    end
+
+    return #kept
+end
+
+--- Returns true if some of the raw nodes of a processed node were deleted and none of the others exist.
+local function is_orphaned(link_id, deleted_nodes)
+    local raw_ids = call("SMEMBERS", string.format("%s;%s;raw_ids", PROC_NODES_KEY, link_id))
+    local orphaned = false
+    for _, raw_id in ipairs(raw_ids) do
+        if deleted_nodes[raw_id] then
+            orphaned = true
+        end
+    end
+    for _, raw_id in ipairs(raw_ids) do
+        if not deleted_nodes[raw_id] and call("SISMEMBER", NODES_KEY, raw_id) == 1 then
+            orphaned = false
+        end
+    end
+    return orphaned
+end
+
+--- Deletes a processed node, and the references to it from its DNS names and raw nodes.
+local function delete_proc_node(link_id)
+    local node_key = string.format("%s;%s", PROC_NODES_KEY, link_id)
+    for _, qname in ipairs(call("SMEMBERS", string.format("%s;dns_names", node_key))) do
+        if call("HGET", DNS_NODES_KEY, qname) == link_id then
+            call("HDEL", DNS_NODES_KEY, qname)
+            call("HDEL", string.format("%s;%s;%s", METADATA_KEY, DNS_KEY, qname), unpack(NODE_META_KEYS))
+        end
+    end
+    local raw_ids = call("SMEMBERS", string.format("%s;raw_ids", node_key))
+    if #raw_ids > 0 then
+        call("HDEL", PROC_NODE_REVS_KEY, unpack(raw_ids))
+    end
+
+    call("SREM", PROC_NODES_KEY, link_id)
+    for _, suffix in ipairs({ "", ";alt_names", ";dns_names", ";plugins", ";raw_ids" }) do
+        call("DEL", node_key .. suffix)
+    end
+    remove_tags(node_key)
+    remove_relations(link_id)
+    remove_services(link_id)
+    create_change("delete proc node", link_id, "netdox", { link_id = link_id })
+end
+
+--- Deletes a processed node that processing no longer produces. Key is the link ID of the node.
+local function delete_stale_proc_node(_link_id, _args)
+    local link_id = _link_id[1]
+    if call("SISMEMBER", PROC_NODES_KEY, link_id) == 1 then
+        delete_proc_node(link_id)
+    end
+end
+
+--- Removes DNS records and raw nodes that have not been reported by their plugin since a cutoff time,
+--- then the DNS names and processed nodes left with nothing to describe.
+--- Plugin data and metadata are removed with their objects, but operator notes are kept.
+--- Args are "dry-run" or "write", the default cutoff in seconds since the epoch or an empty string for none,
+--- and then pairs of plugin name and cutoff for that plugin.
+--- Anything with no last seen time is treated as seen now, so data from before the times were kept is not removed.
+--- Returns flat lists of the removed records as qname, plugin, rtype and value,
+--- the removed raw nodes as node ID and plugin, the removed DNS names, and the removed processed nodes.
+local function prune(_keys, args)
+    local dry_run = args[1] == "dry-run"
+    local cutoffs = { [""] = tonumber(args[2]) }
+    for i = 3, #args, 2 do
+        cutoffs[args[i]] = tonumber(args[i + 1])
+    end
+    local time = now()
+
+    local removed_records, removed_nodes, removed_names, removed_proc_nodes = {}, {}, {}, {}
+    -- DNS names that are still used by a record or a raw node.
+    local used = {}
+    -- Raw nodes with no details left.
+    local deleted_nodes = {}
+
+    for _, node_id in ipairs(call("SMEMBERS", NODES_KEY)) do
+        local node_key = string.format("%s;%s", NODES_KEY, node_id)
+        local seen_key = string.format("%s;seen", node_key)
+        local seen = list_to_map(call("HGETALL", seen_key))
+        local count = tonumber(call("GET", node_key)) or 0
+
+        local removed, plugins, kept = {}, {}, count
+        for index = 1, count do
+            local plugin = call("HGET", string.format("%s;%s", node_key, index), "plugin")
+            local last_seen = seen[tostring(index)]
+            if last_seen == nil then
+                if not dry_run then
+                    call("HSET", seen_key, index, time)
+                end
+            elseif is_stale(cutoffs, plugin, last_seen) then
+                removed[index] = true
+                table.insert(plugins, plugin)
+                table.insert(removed_nodes, node_id)
+                table.insert(removed_nodes, plugin)
+                kept = kept - 1
+            end
+        end
+
+        if #plugins > 0 and not dry_run then
+            remove_node_details(node_key, count, removed)
+            if kept == 0 then
+                call("DEL", node_key, seen_key)
+                call("SREM", NODES_KEY, node_id)
+                delete_object_data(string.format("%s;%s", NODES_KEY, node_id))
+            else
+                call("SET", node_key, kept)
+            end
+
+            for _, plugin in ipairs(plugins) do
+                create_change("delete plugin node", node_id, plugin, { node_id = node_id })
+            end
+        end
+
+        if kept > 0 then
+            for qname in string.gmatch(node_id, "[^;]+") do
+                used[qname] = true
+            end
+        elseif count > 0 then
+            deleted_nodes[node_id] = true
+        end
+    end
+
+    local qnames = call("SMEMBERS", DNS_KEY)
+    -- Records that remain on each DNS name.
+    local remaining = {}
+    for _, qname in ipairs(qnames) do
+        local dns_key = string.format("%s;%s", DNS_KEY, qname)
+        local seen_key = string.format("%s;seen", dns_key)
+        local seen = list_to_map(call("HGETALL", seen_key))
+        local records = {}
+
+        for _, record in ipairs(call("SMEMBERS", dns_key)) do
+            local plugin, rtype, value = string.match(record, "^([^;]*);([^;]*);(.*)$")
+            local last_seen = seen[record]
+            seen[record] = nil
+
+            if last_seen ~= nil and is_stale(cutoffs, plugin, last_seen) then
+                for _, part in ipairs({ qname, plugin, rtype, value }) do
+                    table.insert(removed_records, part)
+                end
+
+                if not dry_run then
+                    call("SREM", dns_key, record)
+                    call("HDEL", seen_key, record)
+                    create_change(
+                        "delete dns record",
+                        string.format("%s;%s", dns_key, record),
+                        plugin,
+                        { qname = qname, rtype = rtype, rvalue = value }
+                    )
+                end
+            else
+                if last_seen == nil and not dry_run then
+                    call("HSET", seen_key, record, time)
+                end
+                table.insert(records, { rtype, value })
+                used[qname] = true
+                if ADDRESS_RTYPES[rtype] then
+                    used[value] = true
+                end
+            end
+        end
+        remaining[qname] = records
+
+        -- Times left over from records that were removed some other way.
+        for field, _ in pairs(seen) do
+            if string.find(field, ";") and not dry_run then
+                call("HDEL", seen_key, field)
+            end
+        end
+
+        if call("HLEN", string.format("%s;maps", dns_key)) > 0 then
+            used[qname] = true
+            for _, dest in ipairs(call("HVALS", string.format("%s;maps", dns_key))) do
+                used[dest] = true
+            end
+        end
+    end
+
+    if not dry_run then
+        -- Implied records are removed once no remaining record on their origin implies them.
+        for _, qname in ipairs(qnames) do
+            local implied_key = string.format("%s;%s;implied", DNS_KEY, qname)
+            for _, origin in ipairs(call("SMEMBERS", implied_key)) do
+                local implied = false
+                for _, record in ipairs(remaining[origin] or {}) do
+                    if IMPLYING_RTYPES[record[1]] and record[2] == qname then
+                        implied = true
+                    end
+                end
+                if remaining[origin] ~= nil and not implied then
+                    call("SREM", implied_key, origin)
+                end
+            end
+        end
+    end
+
+    for _, qname in ipairs(qnames) do
+        local dns_key = string.format("%s;%s", DNS_KEY, qname)
+        local seen_key = string.format("%s;seen", dns_key)
+
+        if not used[qname] then
+            local stale, any = true, false
+            for plugin, last_seen in pairs(list_to_map(call("HGETALL", seen_key))) do
+                if not string.find(plugin, ";") then
+                    any = true
+                    stale = stale and is_stale(cutoffs, plugin, last_seen)
+                end
+            end
+
+            if not any then
+                if not dry_run then
+                    call("HSET", seen_key, "", time)
+                end
+            elseif stale then
+                table.insert(removed_names, qname)
+                if not dry_run then
+                    call("SREM", DNS_KEY, qname)
+                    call(
+                        "DEL",
+                        dns_key,
+                        seen_key,
+                        string.format("%s;implied", dns_key),
+                        string.format("%s;maps", dns_key)
+                    )
+                    call("HDEL", DNS_NODES_KEY, qname)
+                    delete_object_data(string.format("%s;%s", DNS_KEY, qname))
+                    create_change("delete dns name", qname, "netdox", { qname = qname })
+                end
+            end
+        end
+    end
+
+    -- Processed nodes are removed once the last of their raw nodes is.
+    for _, link_id in ipairs(call("SMEMBERS", PROC_NODES_KEY)) do
+        if is_orphaned(link_id, deleted_nodes) then
+            table.insert(removed_proc_nodes, link_id)
+            if not dry_run then
+                delete_proc_node(link_id)
+            end
+        end
+    end
+
+    return { removed_records, removed_nodes, removed_names, removed_proc_nodes }
+end
+
+--- Deletes a DNS name with its records, metadata and plugin data, and everything that refers to it:
+--- records on other names whose value is the name, network mappings to it, and the raw nodes that use it,
+--- which are deleted whole since their IDs are made of their DNS names.
+--- The name is removed from the processed nodes that have it, and processed nodes left with no raw nodes are deleted.
+--- Operator notes are kept. Keys must be the qualified DNS name, and args "dry-run" or "write".
+--- Returns flat lists of the records of the name and the records referring to it as qname, plugin, rtype and value,
+--- the deleted raw nodes as node ID and plugin, the processed nodes the name was removed from,
+--- and the deleted processed nodes.
+local function delete_dns(keys, args)
+    local qname, dry_run = keys[1], args[1] == "dry-run"
+    if call("SISMEMBER", DNS_KEY, qname) == 0 then
+        error(string.format("No DNS name %s", qname))
+    end
+    local dns_key = string.format("%s;%s", DNS_KEY, qname)
+    local net_end = is_qualified(qname)
+    local network, name = string.sub(qname, 1, net_end), string.sub(qname, net_end + 1)
+
+    local records, references, nodes, detached, removed_proc_nodes = {}, {}, {}, {}, {}
+    -- Other DNS names whose documents change.
+    local changed = {}
+
+    for _, record in ipairs(call("SMEMBERS", dns_key)) do
+        local plugin, rtype, value = string.match(record, "^([^;]*);([^;]*);(.*)$")
+        for _, part in ipairs({ qname, plugin, rtype, value }) do
+            table.insert(records, part)
+        end
+        if not dry_run then
+            create_change(
+                "delete dns record",
+                string.format("%s;%s", dns_key, record),
+                plugin,
+                { qname = qname, rtype = rtype, rvalue = value }
+            )
+            if IMPLYING_RTYPES[rtype] then
+                call("SREM", string.format("%s;%s;implied", DNS_KEY, value), qname)
+                changed[value] = true
+            end
+        end
+    end
+
+    for _, other in ipairs(call("SMEMBERS", DNS_KEY)) do
+        local other_key = string.format("%s;%s", DNS_KEY, other)
+        if other ~= qname then
+            for _, record in ipairs(call("SMEMBERS", other_key)) do
+                local plugin, rtype, value = string.match(record, "^([^;]*);([^;]*);(.*)$")
+                if value == qname then
+                    for _, part in ipairs({ other, plugin, rtype, value }) do
+                        table.insert(references, part)
+                    end
+                    if not dry_run then
+                        call("SREM", other_key, record)
+                        call("HDEL", string.format("%s;seen", other_key), record)
+                        create_change(
+                            "delete dns record",
+                            string.format("%s;%s", other_key, record),
+                            plugin,
+                            { qname = other, rtype = rtype, rvalue = value }
+                        )
+                    end
+                end
+            end
+
+            local maps_key = string.format("%s;maps", other_key)
+            if call("HGET", maps_key, network) == name and not dry_run then
+                call("HDEL", maps_key, network)
+                changed[other] = true
+            end
+        end
+    end
+
+    -- Raw nodes with no details left.
+    local deleted_nodes = {}
+    for _, node_id in ipairs(call("SMEMBERS", NODES_KEY)) do
+        local uses = false
+        for node_qname in string.gmatch(node_id, "[^;]+") do
+            uses = uses or node_qname == qname
+        end
+
+        if uses then
+            local node_key = string.format("%s;%s", NODES_KEY, node_id)
+            local count = tonumber(call("GET", node_key)) or 0
+            local plugins = {}
+            for index = 1, count do
+                local plugin = call("HGET", string.format("%s;%s", node_key, index), "plugin")
+                table.insert(plugins, plugin)
+                table.insert(nodes, node_id)
+                table.insert(nodes, plugin)
+            end
+            deleted_nodes[node_id] = true
+
+            if not dry_run then
+                for index = 1, count do
+                    call("DEL", string.format("%s;%s", node_key, index))
+                end
+                call("DEL", node_key, string.format("%s;seen", node_key))
+                call("SREM", NODES_KEY, node_id)
+                delete_object_data(node_key)
+                for _, plugin in ipairs(plugins) do
+                    create_change("delete plugin node", node_id, plugin, { node_id = node_id })
+                end
+            end
+        end
+    end
+
+    for _, link_id in ipairs(call("SMEMBERS", PROC_NODES_KEY)) do
+        local dns_names_key = string.format("%s;%s;dns_names", PROC_NODES_KEY, link_id)
+        if is_orphaned(link_id, deleted_nodes) then
+            table.insert(removed_proc_nodes, link_id)
+            if not dry_run then
+                delete_proc_node(link_id)
+            end
+        elseif call("SISMEMBER", dns_names_key, qname) == 1 then
+            table.insert(detached, link_id)
+            if not dry_run then
+                call("SREM", dns_names_key, qname)
+            end
+        end
+    end
+
+    if not dry_run then
+        call("SREM", DNS_KEY, qname)
+        for _, suffix in ipairs({ "", ";seen", ";implied", ";maps" }) do
+            call("DEL", dns_key .. suffix)
+        end
+        call("HDEL", DNS_NODES_KEY, qname)
+        delete_object_data(dns_key)
+        create_change("delete dns name", qname, "netdox", { qname = qname })
+
+        for other, _ in pairs(changed) do
+            if other ~= qname and call("SISMEMBER", DNS_KEY, other) == 1 then
+                create_change("create dns name", other, "netdox", { qname = other })
+            end
+        end
+    end
+
+    return { records, references, nodes, detached, removed_proc_nodes }
+end
+
+--- RENAMING
+
+--- Returns the qualified DNS name moved from one network prefix to another,
+--- or nil if it is not in the old network.
+local function rename_qname(qname, old_prefix, new_prefix)
+    if string.sub(qname, 1, #old_prefix) == old_prefix then
+        return new_prefix .. string.sub(qname, #old_prefix + 1)
+    end
+end
+
+--- Returns the node ID with its DNS names moved from one network prefix to another,
+--- or nil if none of them are in the old network.
+local function rename_node_id(node_id, old_prefix, new_prefix)
+    local names, renamed = {}, false
+    for qname in string.gmatch(node_id, "[^;]+") do
+        local new_qname = rename_qname(qname, old_prefix, new_prefix)
+        renamed = renamed or new_qname ~= nil
+        table.insert(names, new_qname or qname)
+    end
+
+    if renamed then
+        return dns_names_to_node_id(names)
+    end
+end
+
+--- Returns the key of an object moved to its new ID, or nil if the key does not belong to a moved object.
+--- Keys are ${OBJECT_KEY};${ID}..., optionally prefixed with meta;, pdata; or notes;.
+--- Node IDs contain semicolons, so the longest moved node ID that the key continues with is used.
+local function rename_object_key(key, old_prefix, new_prefix, node_ids)
+    for _, prefix in ipairs({ "", "meta;", "pdata;", "notes;" }) do
+        local dns_prefix = string.format("%s%s;", prefix, DNS_KEY)
+        if string.sub(key, 1, #dns_prefix) == dns_prefix then
+            local renamed = rename_qname(string.sub(key, #dns_prefix + 1), old_prefix, new_prefix)
+            if renamed then
+                return dns_prefix .. renamed
+            end
+        end
+
+        local nodes_prefix = string.format("%s%s;", prefix, NODES_KEY)
+        if string.sub(key, 1, #nodes_prefix) == nodes_prefix then
+            local rest, matched = string.sub(key, #nodes_prefix + 1), nil
+            for node_id, _ in pairs(node_ids) do
+                if
+                    (rest == node_id or string.sub(rest, 1, #node_id + 1) == node_id .. ";")
+                    and (matched == nil or #node_id > #matched)
+                then
+                    matched = node_id
+                end
+            end
+
+            if matched then
+                return nodes_prefix .. node_ids[matched] .. string.sub(rest, #matched + 1)
+            end
+        end
+    end
+end
+
+--- Replaces the members of a set for which the function returns a new member.
+--- Returns true if any were replaced.
+local function rename_members(key, rename)
+    local renamed = false
+    for _, member in ipairs(call("SMEMBERS", key)) do
+        local new_member = rename(member)
+        if new_member then
+            call("SREM", key, member)
+            call("SADD", key, new_member)
+            renamed = true
+        end
+    end
+    return renamed
+end
+
+--- Replaces the fields of a hash for which the function returns a new field, keeping their values.
+--- Returns true if any were replaced.
+local function rename_fields(key, rename)
+    local renamed = false
+    local values = call("HGETALL", key)
+    for i = 1, #values, 2 do
+        local new_field = rename(values[i])
+        if new_field then
+            call("HDEL", key, values[i])
+            call("HSET", key, new_field, values[i + 1])
+            renamed = true
+        end
+    end
+    return renamed
+end
+
+--- Returns a flat list of the keys and values of a table, sorted by key.
+local function sorted_pairs(map)
+    local keys = {}
+    for key, _ in pairs(map) do
+        table.insert(keys, key)
+    end
+    table.sort(keys)
+
+    local list = {}
+    for _, key in ipairs(keys) do
+        table.insert(list, key)
+        table.insert(list, map[key])
+    end
+    return list
+end
+
+--- Moves every DNS name in a network to another network, along with its records, metadata, notes
+--- and plugin data, and moves the raw nodes that use those names to their new node IDs.
+--- References to the moved objects are rewritten, and processed nodes keep their link IDs.
+--- Nothing is written if the third arg is "dry-run".
+--- Returns a flat list of old and new DNS names, and a flat list of old and new raw node IDs.
+local function rename_network(_keys, args)
+    local old, new, dry_run = args[1], args[2], args[3] == "dry-run"
+    local old_prefix, new_prefix = string.format("[%s]", old), string.format("[%s]", new)
+    for _, prefix in ipairs({ old_prefix, new_prefix }) do
+        if is_qualified(prefix) ~= #prefix then
+            error(string.format("Invalid network name: %s", string.sub(prefix, 2, -2)))
+        end
+    end
+
+    local qnames = {}
+    for _, qname in ipairs(call("SMEMBERS", DNS_KEY)) do
+        local new_qname = rename_qname(qname, old_prefix, new_prefix)
+        if new_qname then
+            if call("SISMEMBER", DNS_KEY, new_qname) == 1 then
+                error(string.format("Cannot move DNS name %s to %s because it already exists.", qname, new_qname))
+            end
+            qnames[qname] = new_qname
+        end
+    end
+
+    local node_ids = {}
+    for _, node_id in ipairs(call("SMEMBERS", NODES_KEY)) do
+        local new_id = rename_node_id(node_id, old_prefix, new_prefix)
+        if new_id then
+            if call("SISMEMBER", NODES_KEY, new_id) == 1 then
+                error(string.format("Cannot move node %s to %s because it already exists.", node_id, new_id))
+            end
+            node_ids[node_id] = new_id
+        end
+    end
+
+    if dry_run then
+        return { sorted_pairs(qnames), sorted_pairs(node_ids) }
+    end
+
+    -- Keys are moved first, so references are rewritten under the new keys.
+    for _, key in ipairs(call("KEYS", string.format("*\\[%s\\]*", old))) do
+        local new_key = rename_object_key(key, old_prefix, new_prefix, node_ids)
+        if new_key then
+            call("RENAME", key, new_key)
+        end
+    end
+
+    local function rename_ref(ref)
+        return node_ids[ref]
+            or rename_qname(ref, old_prefix, new_prefix)
+            or rename_object_key(ref, old_prefix, new_prefix, node_ids)
+    end
+
+    for _, key in ipairs({ DNS_KEY, NODES_KEY, METADATA_KEY, DNS_IGNORE_KEY }) do
+        rename_members(key, rename_ref)
+    end
+    for _, tag in ipairs(call("SMEMBERS", TAGS_KEY)) do
+        rename_members(string.format("%s;%s", TAGS_KEY, tag), rename_ref)
+    end
+    for _, key in ipairs({ DNS_NODES_KEY, PROC_NODE_REVS_KEY }) do
+        rename_fields(key, rename_ref)
+    end
+    for _, link_id in ipairs(call("SMEMBERS", PROC_NODES_KEY)) do
+        local node_key = string.format("%s;%s", PROC_NODES_KEY, link_id)
+        rename_members(string.format("%s;dns_names", node_key), rename_ref)
+        rename_members(string.format("%s;raw_ids", node_key), rename_ref)
+    end
+
+    -- Names outside the network may have records that point into it.
+    local moved = {}
+    for _, new_qname in pairs(qnames) do
+        moved[new_qname] = true
+    end
+
+    for _, qname in ipairs(call("SMEMBERS", DNS_KEY)) do
+        local dns_key = string.format("%s;%s", DNS_KEY, qname)
+        local function rename_record(record)
+            local head, value = string.match(record, "^([^;]*;[^;]*;)(.*)$")
+            local new_value = value and rename_qname(value, old_prefix, new_prefix)
+            if new_value then
+                return head .. new_value
+            end
+        end
+        local records = rename_members(dns_key, rename_record)
+        rename_fields(string.format("%s;seen", dns_key), rename_record)
+        local implied = rename_members(string.format("%s;implied", dns_key), rename_ref)
+        local maps = rename_fields(string.format("%s;maps", dns_key), rename_ref)
+
+        if moved[qname] or records or implied or maps then
+            create_change("create dns name", qname, "netdox", { qname = qname })
+        end
+    end
+
+    for _, new_id in pairs(node_ids) do
+        create_change("create plugin node", new_id, "netdox", { node_id = new_id })
+    end
+
+    if call("SREM", NETWORKS_KEY, old) == 1 then
+        call("SADD", NETWORKS_KEY, new)
+    end
+    if call("GET", DEFAULT_NETWORK_KEY) == old then
+        call("SET", DEFAULT_NETWORK_KEY, new)
+    end
+    local suffixes = call("HGETALL", NETWORK_SUFFIXES_KEY)
+    for i = 1, #suffixes, 2 do
+        if suffixes[i + 1] == old then
+            call("HSET", NETWORK_SUFFIXES_KEY, suffixes[i], new)
+        end
+    end
+
+    return { sorted_pairs(qnames), sorted_pairs(node_ids) }
+end
+
+--- INITIALISATION
+local function setup(keys, args)
+    local default_network = keys[1]
+    call("DEL", DEFAULT_NETWORK_KEY)
+    call("SET", DEFAULT_NETWORK_KEY, default_network)
+
+    call("DEL", DNS_IGNORE_KEY)
+    if #args ~= 0 then
+        call("SADD", DNS_IGNORE_KEY, unpack(args))
+    end
+end
+
+local function init(keys, args)
+    call("DEL", CHANGELOG_KEY)
+    create_change("init", "", "netdox")
+end
+
+--- FUNCTION REGISTRATION
+
+redis.register_function({
     function_name = "netdox_qualify_dns_names",
-    callback = qualify_dns_names,
-    description = "Keys should be a list of DNS names. Prepends the default network to each and returns them.",
+    callback = with_key_tag(qualify_dns_names),
+    description = "Keys should be a list of DNS names. "
+        .. "Prepends the network inferred from the suffix, or the default network, to each unqualified name and returns them.",
 })
 
 redis.register_function({
     function_name = "netdox_create_dns",
-    callback = create_dns,
+    callback = with_key_tag(create_dns),
     description = "Create a DNS name with optionally a record as well. "
         .. "First argument must be the name, second the plugin creating the record. "
-        .. "Third and fourth arguments are the optional record type (one of A, CNAME, PTR, NAT)"
+        .. "Third and fourth arguments are the optional record type (one of A, AAAA, CNAME, PTR, NAT)"
         .. " and the record value (some other DNS name). "
         .. "If the third argument is given, the fourth must also be present.",
 })
 
 redis.register_function({
     function_name = "netdox_map_dns",
-    callback = map_dns,
+    callback = with_key_tag(map_dns),
     description = "As yet unused function for mapping one DNS name to another between logical networks.",
 })
 
 redis.register_function({
     function_name = "netdox_create_node",
-    callback = create_node,
+    callback = with_key_tag(create_node_with_role),
     description = "Creates a node. Keys are DNS names the node believes resolve to itself. "
         .. "Arguments are, in order, the plugin creating the node, the name of the node, whether the node is exclusive, "
-        .. "and finally the immutable and unique Link ID of the node. Arguments three and four are optional.",
+        .. "the immutable and unique Link ID of the node, and finally the role of the node. "
+        .. "Arguments three to five are optional.",
+})
+
+redis.register_function({
+    function_name = "netdox_create_node_dependency",
+    callback = with_key_tag(create_node_dependency),
+    description = "Declares that one processed node depends on another. "
+        .. "Keys are the Link IDs of the dependent node and the node it depends on, in that order. "
+        .. "Arguments are the plugin creating the dependency and an optional label describing it.",
+})
+
+redis.register_function({
+    function_name = "netdox_create_node_relation",
+    callback = with_key_tag(create_node_relation),
+    description = "Declares a typed relationship from one processed node to another, like vm-on-host. "
+        .. "Keys are the Link IDs of the two nodes, in the direction of the relationship. "
+        .. "Arguments are the plugin creating the relation and its type, "
+        .. "which may only contain letters, numbers, underscores and hyphens.",
+})
+
+redis.register_function({
+    function_name = "netdox_create_node_service",
+    callback = with_key_tag(create_node_service),
+    description = "Declares a service that a processed node exposes on a port. "
+        .. "Key is the Link ID of the node. "
+        .. "Arguments are, in order, the plugin creating the service, the port, the protocol like tcp or udp, "
+        .. "and then any number of key-value pairs describing the service, like product and version.",
 })
 
 redis.register_function({
     function_name = "netdox_create_dns_metadata",
-    callback = create_dns_metadata,
+    callback = with_key_tag(create_dns_metadata),
     description = "Create metadata attached to a DNS name. "
         .. "Key is the DNS name. First agument must be the plugin creating the metadata. "
         .. "All remaining arguments should be a sequence of key/value metadata pairs.",
@@ -656,7 +2200,7 @@ redis.register_function({
 
 redis.register_function({
     function_name = "netdox_create_node_metadata",
-    callback = create_node_metadata,
+    callback = with_key_tag(create_node_metadata),
     description = 'Create metadata attached to a "soft" node. '
         .. "Keys are a series of DNS names used to identify the node. "
         .. "First agument must be the plugin creating the metadata. "
@@ -665,15 +2209,40 @@ redis.register_function({
 
 redis.register_function({
     function_name = "netdox_create_proc_node_metadata",
-    callback = create_proc_node_metadata,
+    callback = with_key_tag(create_proc_node_metadata),
     description = "Create metadata attached to a processed node. "
         .. "Key is the Link ID of the node. First agument must be the plugin creating the metadata. "
         .. "All remaining arguments should be a sequence of key/value metadata pairs.",
 })
 
+redis.register_function({
+    function_name = "netdox_create_dns_tags",
+    callback = with_key_tag(create_dns_tags),
+    description = "Add tags to a DNS name. "
+        .. "Key is the DNS name. First argument must be the plugin adding the tags. "
+        .. "All remaining arguments are tags, which may contain only letters, digits, underscores and hyphens.",
+})
+
+redis.register_function({
+    function_name = "netdox_create_node_tags",
+    callback = with_key_tag(create_node_tags),
+    description = 'Add tags to a "soft" node. '
+        .. "Keys are a series of DNS names used to identify the node. "
+        .. "First argument must be the plugin adding the tags. "
+        .. "All remaining arguments are tags, which may contain only letters, digits, underscores and hyphens.",
+})
+
+redis.register_function({
+    function_name = "netdox_create_proc_node_tags",
+    callback = with_key_tag(create_proc_node_tags),
+    description = "Add tags to a processed node. "
+        .. "Key is the Link ID of the node. First argument must be the plugin adding the tags. "
+        .. "All remaining arguments are tags, which may contain only letters, digits, underscores and hyphens.",
+})
+
 redis.register_function({
     function_name = "netdox_create_dns_plugin_data",
-    callback = create_dns_plugin_data,
+    callback = with_key_tag(create_dns_plugin_data),
     description = "Create plugin data attached to a DNS name. "
         .. "Key is the DNS name. First argument must be the plugin creating the plugin data. "
         .. 'Second argument must be the data type: one of "list", "hash", "string", "table". '
@@ -683,7 +2252,7 @@ redis.register_function({
 
 redis.register_function({
     function_name = "netdox_create_node_plugin_data",
-    callback = create_node_plugin_data,
+    callback = with_key_tag(create_node_plugin_data),
     description = 'Create plugin data attached to a "soft" node. '
         .. "Keys are a series of DNS names used to identify the node. "
         .. "First argument must be the plugin creating the plugin data. "
@@ -694,7 +2263,7 @@ redis.register_function({
 
 redis.register_function({
     function_name = "netdox_create_proc_node_plugin_data",
-    callback = create_proc_node_plugin_data,
+    callback = with_key_tag(create_proc_node_plugin_data),
     description = "Create plugin data attached to a processed node. "
         .. "Key is the Link ID of the node. First argument must be the plugin creating the plugin data. "
         .. 'Second argument must be the data type: one of "list", "hash", "string", "table". '
@@ -702,30 +2271,126 @@ redis.register_function({
         .. "This function will create the node if not already present.",
 })
 
+redis.register_function({
+    function_name = "netdox_delete_dns_plugin_data",
+    callback = with_key_tag(delete_dns_plugin_data),
+    description = "Delete plugin data attached to a DNS name. "
+        .. "Key is the DNS name. First argument must be the plugin that created the plugin data. "
+        .. "Second argument must be the ID of the plugin data. "
+        .. "Returns 1 if the data was deleted and 0 if it did not exist.",
+})
+
+redis.register_function({
+    function_name = "netdox_delete_node_plugin_data",
+    callback = with_key_tag(delete_node_plugin_data),
+    description = 'Delete plugin data attached to a "soft" node. '
+        .. "Keys are a series of DNS names used to identify the node. "
+        .. "First argument must be the plugin that created the plugin data. "
+        .. "Second argument must be the ID of the plugin data. "
+        .. "Returns 1 if the data was deleted and 0 if it did not exist.",
+})
+
+redis.register_function({
+    function_name = "netdox_delete_proc_node_plugin_data",
+    callback = with_key_tag(delete_proc_node_plugin_data),
+    description = "Delete plugin data attached to a processed node. "
+        .. "Key is the Link ID of the node. First argument must be the plugin that created the plugin data. "
+        .. "Second argument must be the ID of the plugin data. "
+        .. "Returns 1 if the data was deleted and 0 if it did not exist.",
+})
+
 redis.register_function({
     function_name = "netdox_create_report",
-    callback = create_report,
+    callback = with_key_tag(create_report),
     description = "Create a report. Key is the ID of the report. "
         .. "Arguments must be, in order: the plugin creating the report, the title of the report, "
         .. "and how many pieces of data will be attached.",
 })
 redis.register_function({
     function_name = "netdox_create_report_data",
-    callback = create_report_data,
+    callback = with_key_tag(create_report_data),
     description = "Create report data attached to a report. Key is the ID of the report. "
         .. "First three arguments should be, in order: the plugin creating the data, "
         .. 'the position of the data in the report, and the data type (one of "list", "hash", "string", "table").',
 })
 
+redis.register_function({
+    function_name = "netdox_begin_batch",
+    callback = with_key_tag(begin_batch),
+    description = "Marks the start of a batch of changes, which will be published together. "
+        .. "Argument must be the plugin making the changes. Returns the ID of the batch.",
+})
+
+redis.register_function({
+    function_name = "netdox_end_batch",
+    callback = with_key_tag(end_batch),
+    description = "Marks the end of a batch of changes. "
+        .. "Arguments must be the plugin making the changes and the ID returned by netdox_begin_batch.",
+})
+
 redis.register_function({
     function_name = "netdox_setup",
-    callback = setup,
+    callback = with_key_tag(setup),
     description = "Setup the database for use by Netdox.",
 })
 
+redis.register_function({
+    function_name = "netdox_rebuild_index",
+    callback = with_key_tag(rebuild_index),
+    description = "Regenerates the indexes derived from processed nodes and plugin data. "
+        .. "Returns the number of entries fixed in dns_nodes, proc_node_revs, the implied DNS record sets, "
+        .. "and the plugin data sets.",
+})
+
+redis.register_function({
+    function_name = "netdox_migrate_metadata",
+    callback = with_key_tag(migrate_metadata),
+    description = "Moves metadata stored under the legacy key layout to the current one. "
+        .. 'Nothing is written if the first argument is "dry-run". '
+        .. "Returns the legacy and new object IDs that were moved, and the legacy IDs that could not be resolved.",
+})
+
+redis.register_function({
+    function_name = "netdox_prune",
+    callback = with_key_tag(prune),
+    description = "Removes DNS records and raw nodes that have not been reported by their plugin since a cutoff time, "
+        .. "and the DNS names and processed nodes left with nothing to describe. "
+        .. 'First argument must be "dry-run" or "write", the second the default cutoff in seconds since the epoch '
+        .. "or an empty string for none, and the remaining arguments pairs of plugin name and cutoff for that plugin. "
+        .. "Returns the removed records, raw nodes, DNS names and processed nodes.",
+})
+
+redis.register_function({
+    function_name = "netdox_delete_dns",
+    callback = with_key_tag(delete_dns),
+    description = "Deletes the DNS name given as the only key, with its records, metadata and plugin data, "
+        .. "the records on other names that point to it, and the raw nodes that use it, "
+        .. "and removes it from processed nodes. "
+        .. 'Nothing is written if the first argument is "dry-run". '
+        .. "Returns the records of the name, the records pointing to it, the deleted raw nodes, "
+        .. "the processed nodes it was removed from, and the deleted processed nodes.",
+})
+
+redis.register_function({
+    function_name = "netdox_delete_proc_node",
+    callback = with_key_tag(delete_stale_proc_node),
+    description = "Deletes the processed node with the Link ID given as the only key, "
+        .. "the references to it from its DNS names and raw nodes, and its tags, relations and services. "
+        .. "Used by processing to remove the nodes it no longer produces.",
+})
+
+redis.register_function({
+    function_name = "netdox_rename_network",
+    callback = with_key_tag(rename_network),
+    description = "Moves every DNS name in the network given by the first argument to the network given by the second, "
+        .. "along with the objects and references that use them. "
+        .. 'Nothing is written if the third argument is "dry-run". '
+        .. "Returns the old and new DNS names, and the old and new raw node IDs.",
+})
+
 redis.register_function({
     function_name = "netdox_init",
-    callback = init,
+    callback = with_key_tag(init),
     description = 'Wipe the changelog and insert an "init" change.',
 })
 
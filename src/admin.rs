@@ -0,0 +1,314 @@
+use std::process::exit;
+
+use tracing::{error, info, warn};
+
+use crate::{
+    annotate::resolve_object_id,
+    config::LocalConfig,
+    data::{
+        model::{DNS_KEY, PROC_NODES_KEY},
+        DataConn,
+    },
+    remote::RemoteInterface,
+    success, AdminCommand,
+};
+
+/// Performs the given admin command.
+#[tokio::main]
+pub async fn admin(cmd: &AdminCommand) {
+    match cmd {
+        AdminCommand::RebuildIndex => rebuild_index().await,
+        AdminCommand::RenameNetwork {
+            old,
+            new,
+            dry_run,
+            archive,
+        } => rename_network(old, new, *dry_run, *archive).await,
+        AdminCommand::DeleteDns {
+            qname,
+            cascade,
+            dry_run,
+        } => delete_dns(qname, *cascade, *dry_run).await,
+        AdminCommand::DeleteData {
+            object_id,
+            data_id,
+            plugin,
+        } => delete_data(object_id, data_id, plugin).await,
+    }
+}
+
+async fn rebuild_index() {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to rebuild indexes: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to rebuild indexes: {err}");
+            exit(1);
+        }
+    };
+
+    match con.rebuild_index().await {
+        Ok(repairs) => {
+            info!("Fixed {} entries in dns_nodes.", repairs.dns_nodes);
+            info!(
+                "Fixed {} entries in proc_node_revs.",
+                repairs.proc_node_revs
+            );
+            info!(
+                "Fixed {} entries in implied DNS record sets.",
+                repairs.dns_implied
+            );
+            info!("Fixed {} entries in plugin data sets.", repairs.pdata);
+            success!("Rebuilt indexes with {} fixes.", repairs.total());
+        }
+        Err(err) => {
+            error!("Failed to rebuild indexes: {err}");
+            exit(1);
+        }
+    }
+}
+
+async fn rename_network(old: &str, new: &str, dry_run: bool, archive: bool) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to rename network: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to rename network: {err}");
+            exit(1);
+        }
+    };
+
+    let rename = match con.rename_network(old, new, dry_run).await {
+        Ok(rename) => rename,
+        Err(err) => {
+            error!("Failed to rename network {old} to {new}: {err}");
+            exit(1);
+        }
+    };
+
+    let verb = if dry_run { "Would move" } else { "Moved" };
+    for (old_qname, new_qname) in &rename.dns_names {
+        info!("{verb} DNS name {old_qname} to {new_qname}");
+    }
+    for (old_id, new_id) in &rename.nodes {
+        info!("{verb} node {old_id} to {new_id}");
+    }
+
+    if dry_run {
+        success!(
+            "Would move {} DNS names and {} nodes from {old} to {new}.",
+            rename.dns_names.len(),
+            rename.nodes.len()
+        );
+        return;
+    }
+
+    success!(
+        "Moved {} DNS names and {} nodes from {old} to {new}.",
+        rename.dns_names.len(),
+        rename.nodes.len()
+    );
+    warn!(
+        "Rename the network in the config too, wherever it is registered, the default network, \
+        or a network suffix; otherwise plugins will create names in {old} again."
+    );
+
+    if archive {
+        let mut failed = false;
+        for remote in &cfg.remotes {
+            match remote.remote.archive_moved_dns(&rename.dns_names).await {
+                Ok(archived) => {
+                    success!("Archived {archived} documents on remote {}.", remote.name)
+                }
+                Err(err) => {
+                    error!(
+                        "Failed to archive documents on remote {}: {err}",
+                        remote.name
+                    );
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            exit(1);
+        }
+    }
+}
+
+async fn delete_dns(name: &str, cascade: bool, dry_run: bool) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to delete DNS name: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to delete DNS name: {err}");
+            exit(1);
+        }
+    };
+
+    let qname = match con.qualify_dns_names(&[name]).await.map(|mut q| q.pop()) {
+        Ok(Some(qname)) => qname,
+        Ok(None) => {
+            error!("Failed to qualify DNS name {name}.");
+            exit(1);
+        }
+        Err(err) => {
+            error!("Failed to qualify DNS name {name}: {err}");
+            exit(1);
+        }
+    };
+
+    // Finds what uses the name before deleting anything.
+    let deletion = match con.delete_dns(&qname, true).await {
+        Ok(deletion) => deletion,
+        Err(err) => {
+            error!("Failed to delete DNS name {qname}: {err}");
+            exit(1);
+        }
+    };
+    if !cascade && deletion.is_referenced() {
+        for record in &deletion.references {
+            warn!(
+                "{} has a {} record from {} pointing to {qname}",
+                record.name, record.rtype, record.plugin
+            );
+        }
+        for (node_id, plugin) in &deletion.nodes {
+            warn!("Node {node_id} from {plugin} uses {qname}");
+        }
+        for link_id in deletion.detached.iter().chain(&deletion.proc_nodes) {
+            warn!("Processed node {link_id} uses {qname}");
+        }
+        error!(
+            "DNS name {qname} is still used. Delete it with --cascade to remove what uses it too."
+        );
+        exit(1);
+    }
+
+    let deletion = if dry_run {
+        deletion
+    } else {
+        match con.delete_dns(&qname, false).await {
+            Ok(deletion) => deletion,
+            Err(err) => {
+                error!("Failed to delete DNS name {qname}: {err}");
+                exit(1);
+            }
+        }
+    };
+
+    let verb = if dry_run { "Would delete" } else { "Deleted" };
+    for record in deletion.records.iter().chain(&deletion.references) {
+        info!(
+            "{verb} {} record {} -> {} from {}",
+            record.rtype, record.name, record.value, record.plugin
+        );
+    }
+    for (node_id, plugin) in &deletion.nodes {
+        info!("{verb} node {node_id} from {plugin}");
+    }
+    for link_id in &deletion.proc_nodes {
+        info!("{verb} processed node {link_id}");
+    }
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    for link_id in &deletion.detached {
+        info!("{verb} {qname} from processed node {link_id}");
+    }
+
+    if dry_run {
+        success!(
+            "Would delete {qname} with {} records and {} nodes.",
+            deletion.records.len() + deletion.references.len(),
+            deletion.nodes.len() + deletion.proc_nodes.len()
+        );
+        return;
+    }
+
+    success!(
+        "Deleted {qname} with {} records and {} nodes.",
+        deletion.records.len() + deletion.references.len(),
+        deletion.nodes.len() + deletion.proc_nodes.len()
+    );
+
+    let qnames = [qname];
+    let mut failed = false;
+    for remote in &cfg.remotes {
+        match remote.remote.archive_deleted_dns(&qnames).await {
+            Ok(archived) => success!("Archived {archived} documents on remote {}.", remote.name),
+            Err(err) => {
+                error!(
+                    "Failed to archive documents on remote {}: {err}",
+                    remote.name
+                );
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        exit(1);
+    }
+}
+
+async fn delete_data(object_id: &str, data_id: &str, plugin: &str) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to delete plugin data: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to delete plugin data: {err}");
+            exit(1);
+        }
+    };
+
+    let obj_id = match resolve_object_id(&mut con, object_id).await {
+        Ok(obj_id) => obj_id,
+        Err(err) => {
+            error!("{err}");
+            exit(1);
+        }
+    };
+
+    let deleted = match obj_id.split_once(';') {
+        Some((DNS_KEY, qname)) => con.delete_dns_pdata(qname, plugin, data_id).await,
+        Some((PROC_NODES_KEY, link_id)) => match con.get_node(link_id).await {
+            Ok(node) => con.delete_node_pdata(&node, plugin, data_id).await,
+            Err(err) => Err(err),
+        },
+        _ => unreachable!("Resolved object IDs are DNS names or processed nodes."),
+    };
+
+    match deleted {
+        Ok(true) => success!("Deleted plugin data {data_id} from {object_id}."),
+        Ok(false) => warn!("No plugin data {data_id} on {object_id}."),
+        Err(err) => {
+            error!("Failed to delete plugin data: {err}");
+            exit(1);
+        }
+    }
+}
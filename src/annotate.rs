@@ -0,0 +1,81 @@
+use std::process::exit;
+
+use tracing::error;
+
+use crate::{
+    config::LocalConfig,
+    config_err,
+    data::{
+        model::{DNS_KEY, PROC_NODES_KEY},
+        DataConn, DataStore,
+    },
+    error::{NetdoxError, NetdoxResult},
+    redis_err, success,
+};
+
+/// Sets or removes an operator note on an object.
+#[tokio::main]
+pub async fn annotate(object_id: &str, key: &str, value: Option<&str>) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to annotate object: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to annotate object: {err}");
+            exit(1);
+        }
+    };
+
+    let obj_id = match resolve_object_id(&mut con, object_id).await {
+        Ok(obj_id) => obj_id,
+        Err(err) => {
+            error!("{err}");
+            exit(1);
+        }
+    };
+
+    if let Err(err) = con.put_note(&obj_id, key, value).await {
+        error!("Failed to annotate object: {err}");
+        exit(1);
+    }
+
+    match value {
+        Some(_) => success!("Set note {key} on {object_id}."),
+        None => success!("Removed note {key} from {object_id}."),
+    }
+}
+
+/// Resolves an object ID of the form `dns;<name>` or `node;<link id>`
+/// to the ID the object is stored under, checking that the object exists.
+pub async fn resolve_object_id(con: &mut DataStore, object_id: &str) -> NetdoxResult<String> {
+    match object_id.split_once(';') {
+        Some(("dns", name)) => {
+            let qname = match con.qualify_dns_names(&[name]).await?.pop() {
+                Some(qname) => qname,
+                None => return redis_err!(format!("Failed to qualify DNS name: {name}")),
+            };
+
+            if con.get_dns_names().await?.contains(&qname) {
+                Ok(format!("{DNS_KEY};{qname}"))
+            } else {
+                config_err!(format!("No DNS name {qname} in the data store."))
+            }
+        }
+        Some(("node", link_id)) => {
+            if con.get_node_ids().await?.contains(link_id) {
+                Ok(format!("{PROC_NODES_KEY};{link_id}"))
+            } else {
+                config_err!(format!("No node with link ID {link_id} in the data store."))
+            }
+        }
+        _ => config_err!(format!(
+            "Object ID must be of the form dns;<name> or node;<link id>: {object_id}"
+        )),
+    }
+}
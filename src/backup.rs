@@ -0,0 +1,370 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{Read, Seek, Write},
+    path::Path,
+    process::exit,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::{error, info};
+use zip::{write::FileOptions, ZipArchive, ZipWriter};
+
+use crate::{
+    config::LocalConfig,
+    config_err,
+    data::{
+        model::{
+            DataDump, StoredValue, DEPENDENCIES_KEY, DNS_KEY, METADATA_KEY, NETWORKS_KEY,
+            NETWORK_SUFFIXES_KEY, NODES_KEY, PDATA_KEY, PROC_NODES_KEY, PROC_NODE_REVS_KEY,
+            RELATIONS_KEY, REPORTS_KEY, ROLES_KEY, SCHEMA_VERSION, SERVICES_KEY, SUBNETS_KEY,
+            TAGS_KEY,
+        },
+        DataConn,
+    },
+    error::{NetdoxError, NetdoxResult},
+    io_err, success,
+};
+
+/// Version of the backup archive format.
+/// Increased when archives written by older versions can no longer be read.
+const BACKUP_FORMAT_VERSION: u32 = 1;
+
+const MANIFEST_FILE: &str = "manifest.json";
+const CHANGELOG_FILE: &str = "changelog.json";
+
+/// Files in the archive that keys are written to, by the kind of object they describe.
+const KEY_SECTIONS: [&str; 6] = ["dns", "nodes", "metadata", "pdata", "reports", "other"];
+
+/// Writes a backup archive of the whole data store.
+#[tokio::main]
+pub async fn backup(path: &Path) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to write backup: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to write backup: {err}");
+            exit(1);
+        }
+    };
+
+    let dump = match con.dump().await {
+        Ok(dump) => dump,
+        Err(err) => {
+            error!("Failed to read data store for backup: {err}");
+            exit(1);
+        }
+    };
+
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+
+    let result = match File::create(path) {
+        Ok(file) => write_archive(file, &dump, created),
+        Err(err) => io_err!(format!("Failed to create {}: {err}", path.display())),
+    };
+
+    match result {
+        Ok(()) => success!(
+            "Wrote backup of {} keys and {} changes to {}",
+            dump.keys.len(),
+            dump.changelog.len(),
+            path.display()
+        ),
+        Err(err) => {
+            error!("Failed to write backup: {err}");
+            exit(1);
+        }
+    }
+}
+
+/// Loads a backup archive into the data store, which must be empty unless `force` is set.
+#[tokio::main]
+pub async fn restore(path: &Path, force: bool) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to restore backup: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to restore backup: {err}");
+            exit(1);
+        }
+    };
+
+    let (manifest, dump) = match read_backup(path) {
+        Ok(backup) => backup,
+        Err(err) => {
+            error!("{err}");
+            exit(1);
+        }
+    };
+
+    info!(
+        "Restoring backup written by netdox {} with {} keys and {} changes.",
+        manifest.netdox_version, manifest.keys, manifest.changes
+    );
+
+    if force {
+        if let Err(err) = con.flush().await {
+            error!("Failed to flush data store before restoring backup: {err}");
+            exit(1);
+        }
+    }
+
+    if let Err(err) = con.restore(&dump).await {
+        error!("Failed to restore backup: {err}");
+        exit(1);
+    }
+
+    // Restores the functions and config that are not part of the keyspace.
+    if let Err(err) = con.setup(&cfg).await {
+        error!("Restored backup but failed to set up data store: {err}");
+        exit(1);
+    }
+
+    success!("Restored backup from {}", path.display());
+}
+
+/// Reads the manifest and contents of the backup archive at a path.
+pub fn read_backup(path: &Path) -> NetdoxResult<(Manifest, DataDump)> {
+    match File::open(path) {
+        Ok(file) => match read_archive(file) {
+            Ok(backup) => Ok(backup),
+            Err(err) => io_err!(format!(
+                "Failed to read backup from {}: {err}",
+                path.display()
+            )),
+        },
+        Err(err) => io_err!(format!(
+            "Failed to open backup at {}: {err}",
+            path.display()
+        )),
+    }
+}
+
+/// Describes the contents of a backup archive.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    /// Version of the archive format.
+    pub format_version: u32,
+    /// Version of the layout of the keys in the archive.
+    pub schema_version: u32,
+    /// Version of netdox that wrote the archive.
+    pub netdox_version: String,
+    /// Unix timestamp of when the archive was written.
+    pub created: u64,
+    pub keys: usize,
+    pub changes: usize,
+}
+
+/// Returns the section of the archive that a key is written to.
+fn key_section(key: &str) -> &'static str {
+    match key.split(';').next().unwrap_or_default() {
+        DNS_KEY | NETWORKS_KEY | NETWORK_SUFFIXES_KEY | SUBNETS_KEY | "dns_nodes"
+        | "dns_ignore" | "default_network" => "dns",
+        NODES_KEY | PROC_NODES_KEY | PROC_NODE_REVS_KEY | DEPENDENCIES_KEY | RELATIONS_KEY
+        | SERVICES_KEY | ROLES_KEY => "nodes",
+        METADATA_KEY | TAGS_KEY => "metadata",
+        PDATA_KEY => "pdata",
+        REPORTS_KEY => "reports",
+        _ => "other",
+    }
+}
+
+/// Writes a dump of the data store to a zip of JSON files.
+fn write_archive<W: Write + Seek>(writer: W, dump: &DataDump, created: u64) -> NetdoxResult<()> {
+    let manifest = Manifest {
+        format_version: BACKUP_FORMAT_VERSION,
+        schema_version: SCHEMA_VERSION,
+        netdox_version: env!("CARGO_PKG_VERSION").to_string(),
+        created,
+        keys: dump.keys.len(),
+        changes: dump.changelog.len(),
+    };
+
+    let mut sections: BTreeMap<&str, BTreeMap<&str, &StoredValue>> = KEY_SECTIONS
+        .iter()
+        .map(|section| (*section, BTreeMap::new()))
+        .collect();
+    for (key, value) in &dump.keys {
+        sections
+            .entry(key_section(key))
+            .or_default()
+            .insert(key, value);
+    }
+
+    let mut zip = ZipWriter::new(writer);
+    write_file(&mut zip, MANIFEST_FILE, &manifest)?;
+    for (section, keys) in &sections {
+        write_file(&mut zip, &format!("{section}.json"), keys)?;
+    }
+    write_file(&mut zip, CHANGELOG_FILE, &dump.changelog)?;
+
+    match zip.finish() {
+        Ok(_) => Ok(()),
+        Err(err) => io_err!(format!("Failed to finish zip: {err}")),
+    }
+}
+
+fn write_file<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    name: &str,
+    content: &impl Serialize,
+) -> NetdoxResult<()> {
+    if let Err(err) = zip.start_file(name, FileOptions::default()) {
+        return io_err!(format!("Failed to start {name} in zip: {err}"));
+    }
+    match serde_json::to_writer(zip, content) {
+        Ok(()) => Ok(()),
+        Err(err) => io_err!(format!("Failed to write {name} to zip: {err}")),
+    }
+}
+
+/// Reads a dump of the data store from a zip written by `write_archive`.
+fn read_archive<R: Read + Seek>(reader: R) -> NetdoxResult<(Manifest, DataDump)> {
+    let mut zip = match ZipArchive::new(reader) {
+        Ok(zip) => zip,
+        Err(err) => return io_err!(format!("Failed to read zip: {err}")),
+    };
+
+    let manifest: Manifest = read_file(&mut zip, MANIFEST_FILE)?;
+    if manifest.format_version != BACKUP_FORMAT_VERSION {
+        return config_err!(format!(
+            "Backup has format version {} but only version {BACKUP_FORMAT_VERSION} can be read.",
+            manifest.format_version
+        ));
+    }
+    if manifest.schema_version != SCHEMA_VERSION {
+        return config_err!(format!(
+            "Backup has data schema version {} but the data store uses version {SCHEMA_VERSION}.",
+            manifest.schema_version
+        ));
+    }
+
+    let mut dump = DataDump::default();
+    for section in KEY_SECTIONS {
+        let keys: BTreeMap<String, StoredValue> = read_file(&mut zip, &format!("{section}.json"))?;
+        dump.keys.extend(keys);
+    }
+    dump.changelog = read_file(&mut zip, CHANGELOG_FILE)?;
+
+    if dump.keys.len() != manifest.keys || dump.changelog.len() != manifest.changes {
+        return io_err!(format!(
+            "Backup contains {} keys and {} changes but its manifest lists {} keys and {} changes.",
+            dump.keys.len(),
+            dump.changelog.len(),
+            manifest.keys,
+            manifest.changes
+        ));
+    }
+
+    Ok((manifest, dump))
+}
+
+fn read_file<R: Read + Seek, T: DeserializeOwned>(
+    zip: &mut ZipArchive<R>,
+    name: &str,
+) -> NetdoxResult<T> {
+    let file = match zip.by_name(name) {
+        Ok(file) => file,
+        Err(err) => return io_err!(format!("Failed to find {name} in zip: {err}")),
+    };
+    match serde_json::from_reader(file) {
+        Ok(content) => Ok(content),
+        Err(err) => io_err!(format!("Failed to parse {name} in zip: {err}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, BTreeSet, HashSet},
+        io::Cursor,
+    };
+
+    use super::{key_section, read_archive, write_archive, BACKUP_FORMAT_VERSION};
+    use crate::data::{
+        model::{DataDump, StoredChange, StoredValue},
+        store::memory_store::MemoryStore,
+        DataConn,
+    };
+
+    fn dump() -> DataDump {
+        DataDump {
+            keys: BTreeMap::from([
+                (
+                    "dns".to_string(),
+                    StoredValue::Set(BTreeSet::from(["[net]domain.com".to_string()])),
+                ),
+                (
+                    "meta;dns;[net]domain.com".to_string(),
+                    StoredValue::Hash(BTreeMap::from([("owner".to_string(), "ops".to_string())])),
+                ),
+                (
+                    "reports;report-id".to_string(),
+                    StoredValue::List(vec!["b".to_string(), "a".to_string()]),
+                ),
+                (
+                    "default_network".to_string(),
+                    StoredValue::String("net".to_string()),
+                ),
+            ]),
+            changelog: vec![StoredChange {
+                id: "1700000000000-0".to_string(),
+                fields: BTreeMap::from([
+                    ("change".to_string(), "init".to_string()),
+                    ("value".to_string(), String::new()),
+                    ("plugin".to_string(), "netdox".to_string()),
+                ]),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_key_section() {
+        assert_eq!(key_section("dns;[net]domain.com"), "dns");
+        assert_eq!(key_section("proc_nodes;link-id;dns_names"), "nodes");
+        assert_eq!(key_section("meta;dns;[net]domain.com"), "metadata");
+        assert_eq!(key_section("tags;prod"), "metadata");
+        assert_eq!(key_section("pdata;dns;[net]domain.com"), "pdata");
+        assert_eq!(key_section("runs"), "other");
+    }
+
+    #[test]
+    fn test_archive_roundtrip() {
+        let mut archive = Cursor::new(vec![]);
+        write_archive(&mut archive, &dump(), 1_700_000_000).unwrap();
+
+        let (manifest, restored) = read_archive(archive).unwrap();
+        assert_eq!(manifest.format_version, BACKUP_FORMAT_VERSION);
+        assert_eq!(manifest.created, 1_700_000_000);
+        assert_eq!(manifest.keys, 4);
+        assert_eq!(restored, dump());
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_roundtrip() {
+        let mut store = MemoryStore::from_dump(&dump()).unwrap();
+        assert_eq!(store.dump().await.unwrap(), dump());
+        assert_eq!(
+            store.get_dns_names().await.unwrap(),
+            HashSet::from(["[net]domain.com".to_string()])
+        );
+    }
+}
@@ -0,0 +1,578 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+    process::exit,
+};
+
+use itertools::Itertools;
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{LocalConfig, PluginConfig},
+    data::{
+        model::{Node, DNS, DNS_KEY, NETDOX_PLUGIN, PROC_NODES_KEY, REPORTS_KEY},
+        DataConn, DataStore,
+    },
+    error::NetdoxResult,
+    remote::{Remote, RemoteInterface},
+    success,
+};
+
+/// Metadata of a raw node, and the plugin that set each key.
+type RawMetadata = (HashMap<String, String>, HashMap<String, String>);
+
+/// How serious an issue found by a check is, in order of severity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Worth knowing about, but documents are still correct.
+    Info,
+    /// Documents may be missing data or show the wrong data.
+    Warning,
+    /// An object will not be documented correctly, or at all.
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Info => write!(f, "INFO"),
+            Self::Warning => write!(f, "WARNING"),
+            Self::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+/// An inconsistency in the data store found by a check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub severity: Severity,
+    /// Name of the check that found the issue.
+    pub check: &'static str,
+    /// Object ID of the object with the issue.
+    pub object: String,
+    pub message: String,
+    /// The processed node with the issue repaired, if it can be repaired safely.
+    pub repair: Option<Node>,
+}
+
+impl Issue {
+    fn new(severity: Severity, check: &'static str, object: String, message: String) -> Self {
+        Issue {
+            severity,
+            check,
+            object,
+            message,
+            repair: None,
+        }
+    }
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} [{}] {}: {}",
+            self.severity, self.check, self.object, self.message
+        )
+    }
+}
+
+/// Checks the data store for inconsistent data and prints each issue found.
+/// If `fix` is true, repairs the issues that can be repaired safely and then rebuilds the indexes.
+/// Exits with 1 if any errors are left.
+#[tokio::main]
+pub async fn check(fix: bool) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to check data: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to check data: {err}");
+            exit(1);
+        }
+    };
+
+    let mut issues = match run_checks(&mut con, &cfg).await {
+        Ok(issues) => issues,
+        Err(err) => {
+            error!("Failed to check data: {err}");
+            exit(1);
+        }
+    };
+
+    if fix {
+        let repairs = issues
+            .iter()
+            .filter_map(|issue| issue.repair.as_ref())
+            .collect_vec();
+        if !repairs.is_empty() {
+            if let Err(err) = con.put_processed(&[], &repairs).await {
+                error!("Failed to repair processed nodes: {err}");
+                exit(1);
+            }
+        }
+        match con.rebuild_index().await {
+            Ok(index) => info!("Fixed {} entries in the indexes.", index.total()),
+            Err(err) => {
+                error!("Failed to rebuild indexes: {err}");
+                exit(1);
+            }
+        }
+        info!("Repaired {} issues.", repairs.len());
+        issues.retain(|issue| issue.repair.is_none());
+    }
+
+    for issue in &issues {
+        match issue.severity {
+            Severity::Info => info!("{issue}"),
+            Severity::Warning | Severity::Error => warn!("{issue}"),
+        }
+    }
+
+    let counts = issues.iter().counts_by(|issue| issue.severity);
+    let count = |severity| counts.get(&severity).copied().unwrap_or(0);
+    let repairable = issues.iter().filter(|issue| issue.repair.is_some()).count();
+    if repairable > 0 {
+        info!("Pass --fix to repair {repairable} of these issues.");
+    }
+
+    if count(Severity::Error) > 0 {
+        error!(
+            "Found {} errors, {} warnings and {} other issues.",
+            count(Severity::Error),
+            count(Severity::Warning),
+            count(Severity::Info)
+        );
+        exit(1);
+    }
+    success!(
+        "Found no errors, {} warnings and {} other issues.",
+        count(Severity::Warning),
+        count(Severity::Info)
+    );
+}
+
+/// Runs every check, returning the issues found ordered by severity, worst first.
+pub async fn run_checks(con: &mut DataStore, cfg: &LocalConfig) -> NetdoxResult<Vec<Issue>> {
+    let dns = con.get_dns().await?;
+    let ignored = cfg.dns_ignore.names()?;
+    let ignored = con
+        .qualify_dns_names(&ignored.iter().map(String::as_str).collect_vec())
+        .await?;
+    let mut issues = ignored_names(&dns, &ignored.into_iter().collect());
+    issues.extend(out_of_scope(&dns, &cfg.plugins));
+
+    let raw_ids = con
+        .get_raw_nodes()
+        .await?
+        .iter()
+        .map(|node| node.id())
+        .collect::<HashSet<_>>();
+    let plugins = cfg
+        .plugins
+        .iter()
+        .map(|plugin| plugin.name.as_str())
+        .chain([NETDOX_PLUGIN])
+        .collect::<HashSet<_>>();
+
+    let mut node_ids = con.get_node_ids().await?.into_iter().collect_vec();
+    node_ids.sort();
+    for link_id in &node_ids {
+        let node = con.get_node(link_id).await?;
+        issues.extend(node_issues(&node, &raw_ids, &plugins));
+
+        // Only raw nodes that exist have metadata worth comparing.
+        let mut raw_meta = BTreeMap::new();
+        for raw_id in node.raw_ids.iter().filter(|id| raw_ids.contains(*id)) {
+            let raw = Node {
+                raw_ids: HashSet::from([raw_id.clone()]),
+                ..node.clone()
+            };
+            raw_meta.insert(
+                raw_id.clone(),
+                (
+                    con.get_node_metadata(&raw).await?,
+                    con.get_node_metadata_sources(&raw).await?,
+                ),
+            );
+        }
+        let overrides = con.get_proc_node_metadata(link_id).await?;
+        issues.extend(metadata_collisions(&node, &raw_meta, &overrides));
+    }
+
+    let mut obj_ids = dns
+        .qnames
+        .iter()
+        .map(|qname| format!("{DNS_KEY};{qname}"))
+        .chain(node_ids.iter().map(|id| format!("{PROC_NODES_KEY};{id}")))
+        .chain(
+            con.get_report_ids()
+                .await?
+                .iter()
+                .map(|id| format!("{REPORTS_KEY};{id}")),
+        )
+        .collect_vec();
+    obj_ids.sort();
+    for remote in &cfg.remotes {
+        issues.extend(long_docids(&remote.remote, &obj_ids));
+    }
+
+    issues.sort_by_key(|issue| std::cmp::Reverse(issue.severity));
+    Ok(issues)
+}
+
+/// Finds DNS names that are in the ignore list, and records that point to them.
+/// Both were created before the name was ignored, and will not be updated by plugins again.
+fn ignored_names(dns: &DNS, ignored: &HashSet<String>) -> Vec<Issue> {
+    let mut issues = vec![];
+    for qname in dns.qnames.iter().sorted() {
+        let object = format!("{DNS_KEY};{qname}");
+        if ignored.contains(qname) {
+            issues.push(Issue::new(
+                Severity::Warning,
+                "ignored-name",
+                object.clone(),
+                "DNS name is in the ignore list".to_string(),
+            ));
+        }
+        for record in dns.get_records(qname) {
+            if ignored.contains(&record.value) {
+                issues.push(Issue::new(
+                    Severity::Warning,
+                    "ignored-name",
+                    object.clone(),
+                    format!(
+                        "{} record from {} points to ignored DNS name {}",
+                        record.rtype, record.plugin, record.value
+                    ),
+                ));
+            }
+        }
+    }
+    issues
+}
+
+/// Finds DNS records from plugins that are outside the scope of the plugin.
+/// They were created before the scope was configured, and will not be updated by the plugin again.
+fn out_of_scope(dns: &DNS, plugins: &[PluginConfig]) -> Vec<Issue> {
+    let scopes = plugins
+        .iter()
+        .filter(|plugin| !plugin.scope.is_empty())
+        .map(|plugin| (plugin.name.as_str(), &plugin.scope))
+        .collect::<HashMap<_, _>>();
+
+    let mut issues = vec![];
+    for qname in dns.qnames.iter().sorted() {
+        for record in dns
+            .get_records(qname)
+            .into_iter()
+            .sorted_by_key(|r| &r.plugin)
+        {
+            let Some(scope) = scopes.get(record.plugin.as_str()) else {
+                continue;
+            };
+            if !scope.allows(qname) {
+                issues.push(Issue::new(
+                    Severity::Warning,
+                    "out-of-scope",
+                    format!("{DNS_KEY};{qname}"),
+                    format!(
+                        "{} record from {} is outside the scope of the plugin",
+                        record.rtype, record.plugin
+                    ),
+                ));
+            }
+        }
+    }
+    issues
+}
+
+/// Finds the source plugins of a processed node that are not configured,
+/// and the raw nodes it was built from that no longer exist.
+fn node_issues(node: &Node, raw_ids: &HashSet<String>, plugins: &HashSet<&str>) -> Vec<Issue> {
+    let object = format!("{PROC_NODES_KEY};{}", node.link_id);
+    let mut issues = vec![];
+
+    let missing = node
+        .plugins
+        .iter()
+        .filter(|plugin| !plugins.contains(plugin.as_str()))
+        .sorted()
+        .collect_vec();
+    if !missing.is_empty() {
+        issues.push(Issue::new(
+            Severity::Warning,
+            "missing-plugin",
+            object.clone(),
+            format!(
+                "created by plugins that are not configured: {}",
+                missing.into_iter().join(", ")
+            ),
+        ));
+    }
+
+    let (live, stale): (HashSet<_>, HashSet<_>) = node
+        .raw_ids
+        .iter()
+        .cloned()
+        .partition(|id| raw_ids.contains(id));
+    if live.is_empty() {
+        issues.push(Issue::new(
+            Severity::Error,
+            "stale-raw-id",
+            object,
+            "none of the raw nodes it was built from exist; run an update to remove it".to_string(),
+        ));
+    } else if !stale.is_empty() {
+        let mut issue = Issue::new(
+            Severity::Warning,
+            "stale-raw-id",
+            object,
+            format!(
+                "built from raw nodes that no longer exist: {}",
+                stale.into_iter().sorted().join(", ")
+            ),
+        );
+        issue.repair = Some(Node {
+            raw_ids: live,
+            ..node.clone()
+        });
+        issues.push(issue);
+    }
+
+    issues
+}
+
+/// Finds metadata keys that different plugins set to different values on the raw nodes
+/// a processed node was built from. Takes the metadata and sources of each raw node,
+/// and the metadata set on the processed node itself, which overrides both.
+/// Only the value the conflict policy chooses for a colliding key is shown.
+fn metadata_collisions(
+    node: &Node,
+    raw_meta: &BTreeMap<String, RawMetadata>,
+    overrides: &HashMap<String, String>,
+) -> Vec<Issue> {
+    let mut values: BTreeMap<&str, BTreeMap<&str, HashSet<&str>>> = BTreeMap::new();
+    for (meta, sources) in raw_meta.values() {
+        for (key, value) in meta {
+            if overrides.contains_key(key) {
+                continue;
+            }
+            let plugin = sources.get(key).map_or("unknown", String::as_str);
+            values
+                .entry(key)
+                .or_default()
+                .entry(value)
+                .or_default()
+                .insert(plugin);
+        }
+    }
+
+    values
+        .into_iter()
+        .filter(|(_, by_value)| by_value.len() > 1)
+        .filter(|(_, by_value)| by_value.values().flatten().collect::<HashSet<_>>().len() > 1)
+        .map(|(key, by_value)| {
+            Issue::new(
+                Severity::Info,
+                "metadata-collision",
+                format!("{PROC_NODES_KEY};{}", node.link_id),
+                format!(
+                    "metadata key {key} has different values from plugins: {}",
+                    by_value
+                        .iter()
+                        .map(|(value, plugins)| format!(
+                            "{} ({value})",
+                            plugins.iter().sorted().join(", ")
+                        ))
+                        .join(", ")
+                ),
+            )
+        })
+        .collect()
+}
+
+/// Finds objects whose document location is too long for the remote,
+/// so their documents are never published.
+fn long_docids(remote: &Remote, obj_ids: &[String]) -> Vec<Issue> {
+    let max_len = match remote {
+        #[cfg(feature = "pageseeder")]
+        Remote::PageSeeder(_) => crate::remote::pageseeder::MAX_DOCID_LEN,
+        _ => return vec![],
+    };
+
+    obj_ids
+        .iter()
+        .filter_map(|obj_id| {
+            let location = remote.document_location(obj_id)?;
+            (location.len() > max_len).then(|| {
+                Issue::new(
+                    Severity::Error,
+                    "long-docid",
+                    obj_id.clone(),
+                    format!(
+                        "docid is {} characters long, more than the maximum of {max_len}, so the document is not published",
+                        location.len()
+                    ),
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap, HashSet};
+
+    use super::{ignored_names, metadata_collisions, node_issues, out_of_scope, Severity};
+    use crate::{
+        config::PluginConfig,
+        data::model::{DNSRecord, Node, PluginScope, DNS},
+    };
+
+    fn node() -> Node {
+        Node {
+            name: "server".to_string(),
+            link_id: "server-id".to_string(),
+            alt_names: HashSet::new(),
+            dns_names: HashSet::from(["[net]a.com".to_string(), "[net]b.com".to_string()]),
+            plugins: HashSet::from(["plugin".to_string(), "removed".to_string()]),
+            raw_ids: HashSet::from(["[net]a.com".to_string(), "[net]b.com".to_string()]),
+        }
+    }
+
+    #[test]
+    fn test_ignored_names() {
+        let mut dns = DNS::new();
+        dns.add_record(DNSRecord {
+            name: "[net]www.com".to_string(),
+            value: "[net]old.com".to_string(),
+            rtype: "CNAME".to_string(),
+            plugin: "plugin".to_string(),
+        });
+
+        let issues = ignored_names(&dns, &HashSet::from(["[net]old.com".to_string()]));
+        assert_eq!(
+            issues
+                .iter()
+                .map(|issue| (issue.object.as_str(), issue.message.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("dns;[net]old.com", "DNS name is in the ignore list"),
+                (
+                    "dns;[net]www.com",
+                    "CNAME record from plugin points to ignored DNS name [net]old.com"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_out_of_scope() {
+        let mut dns = DNS::new();
+        for (name, plugin) in [
+            ("[net]www.corp.com", "scoped"),
+            ("[net]www.other.com", "scoped"),
+            ("[net]www.other.com", "plugin"),
+        ] {
+            dns.add_record(DNSRecord {
+                name: name.to_string(),
+                value: "[net]10.0.0.1".to_string(),
+                rtype: "A".to_string(),
+                plugin: plugin.to_string(),
+            });
+        }
+        let plugin = PluginConfig {
+            name: "scoped".to_string(),
+            display: Default::default(),
+            scope: PluginScope {
+                networks: vec![],
+                zones: vec!["corp.com".to_string()],
+            },
+            timeout_seconds: None,
+            schema_version: None,
+            fields: HashMap::new(),
+            stages: HashMap::new(),
+        };
+
+        let issues = out_of_scope(&dns, &[plugin]);
+        assert_eq!(
+            issues
+                .iter()
+                .map(|issue| (issue.object.as_str(), issue.message.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(
+                "dns;[net]www.other.com",
+                "A record from scoped is outside the scope of the plugin"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_node_issues() {
+        let node = node();
+        let issues = node_issues(
+            &node,
+            &HashSet::from(["[net]a.com".to_string()]),
+            &HashSet::from(["plugin"]),
+        );
+
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0].check, "missing-plugin");
+        assert!(issues[0].repair.is_none());
+        assert_eq!(issues[1].severity, Severity::Warning);
+        assert_eq!(
+            issues[1].repair.as_ref().map(|node| &node.raw_ids),
+            Some(&HashSet::from(["[net]a.com".to_string()]))
+        );
+
+        // Nodes with no raw nodes left cannot be repaired.
+        let issues = node_issues(
+            &node,
+            &HashSet::new(),
+            &HashSet::from(["plugin", "removed"]),
+        );
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, Severity::Error);
+        assert!(issues[0].repair.is_none());
+    }
+
+    #[test]
+    fn test_metadata_collisions() {
+        let meta = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+        let raw_meta = BTreeMap::from([
+            (
+                "[net]a.com".to_string(),
+                (
+                    meta(&[("os", "linux"), ("owner", "ops"), ("site", "syd")]),
+                    meta(&[("os", "one"), ("owner", "one"), ("site", "one")]),
+                ),
+            ),
+            (
+                "[net]b.com".to_string(),
+                (
+                    meta(&[("os", "windows"), ("owner", "dev"), ("site", "syd")]),
+                    meta(&[("os", "two"), ("owner", "two"), ("site", "two")]),
+                ),
+            ),
+        ]);
+
+        let issues = metadata_collisions(&node(), &raw_meta, &meta(&[("owner", "it")]));
+        assert_eq!(issues.len(), 1);
+        assert_eq!(
+            issues[0].message,
+            "metadata key os has different values from plugins: one (linux), two (windows)"
+        );
+    }
+}
@@ -1,5 +1,10 @@
 pub mod local;
 pub mod remote;
+pub mod secret;
 
-pub use local::{IgnoreList, LocalConfig, PluginConfig, PluginStage, PluginStageConfig};
+pub use local::{
+    EventKind, HealthConfig, LocalConfig, NotificationsConfig, PluginConfig, PluginStage,
+    PluginStageConfig, SaveStrategy, WebhookConfig, WebhookFormat, WorkerPoolConfig,
+};
 pub use remote::RemoteConfig;
+pub use secret::SecretSource;
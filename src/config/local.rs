@@ -8,16 +8,34 @@ use std::{
     time::Duration,
 };
 
+#[cfg(feature = "sqlite")]
+use crate::data::store::sqlite_store::SqliteStore;
 use crate::{
+    config::secret::SecretSource,
     config_err,
-    data::{DataConn, DataStore},
+    data::{
+        model::{is_ignore_pattern, ConflictPolicy, Node, PluginDisplay, PluginScope},
+        store::{
+            memory_store::offline_store,
+            read_only::{read_only_enabled, ReadOnlyStore},
+            redis_store::{self, RedisConnection},
+        },
+        DataConn, DataStore,
+    },
     error::{NetdoxError, NetdoxResult},
-    io_err, redis_err,
+    io_err,
+    logging::redact_url,
+    redis_err,
     remote::Remote,
 };
 use age::{secrecy::SecretString, Decryptor, Encryptor};
-use redis::{AsyncConnectionConfig, Client};
-use serde::{Deserialize, Serialize};
+use ipnet::IpNet;
+use redis::{
+    cluster::ClusterClientBuilder,
+    sentinel::{SentinelClientBuilder, SentinelServerType},
+    AsyncConnectionConfig, Client, ClientTlsConfig, ConnectionAddr, TlsCertificates, TlsMode,
+};
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
 use toml::Value;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -27,6 +45,40 @@ pub enum IgnoreList {
     Path(String),
 }
 
+impl IgnoreList {
+    /// Returns the entries in the list, reading them from the file if it is a path.
+    fn entries(&self) -> NetdoxResult<HashSet<String>> {
+        match self {
+            IgnoreList::Set(set) => Ok(set.clone()),
+            IgnoreList::Path(path) => match fs::read_to_string(path) {
+                Ok(str_list) => Ok(str_list
+                    .lines()
+                    .map(std::borrow::ToOwned::to_owned)
+                    .collect()),
+                Err(err) => io_err!(format!("Failed to read DNS ignorelist from {path}: {err}")),
+            },
+        }
+    }
+
+    /// Returns the DNS names in the list that are not patterns.
+    pub fn names(&self) -> NetdoxResult<HashSet<String>> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .filter(|entry| !is_ignore_pattern(entry))
+            .collect())
+    }
+
+    /// Returns the glob patterns in the list, like `*.dyn.example.com`.
+    pub fn patterns(&self) -> NetdoxResult<HashSet<String>> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .filter(|entry| is_ignore_pattern(entry))
+            .collect())
+    }
+}
+
 /// Default Redis port.
 fn default_port() -> usize {
     6379
@@ -37,15 +89,164 @@ fn default_db() -> usize {
     0
 }
 
+/// How to persist the data store at the end of an update.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SaveStrategy {
+    /// Starts a background save and waits for it to finish.
+    #[default]
+    Bgsave,
+    /// Saves in the foreground, blocking all other clients until it is done.
+    Save,
+    /// Relies on the append only file, which must be enabled.
+    Aof,
+    /// Does not save at all.
+    Skip,
+}
+
+/// Default time in seconds to wait for a save to finish.
+fn default_save_timeout() -> u64 {
+    300
+}
+
+/// Default port of a redis sentinel.
+const DEFAULT_SENTINEL_PORT: u16 = 26379;
+
+/// Config for finding the redis master through sentinel.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SentinelConfig {
+    /// Name of the master monitored by the sentinels.
+    pub master: String,
+    /// Addresses of the sentinels, like `sentinel-1.redis.net:26379`.
+    pub nodes: Vec<String>,
+    /// Username to use when authenticating with the sentinels - if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Password to use when authenticating with the sentinels - if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+impl SentinelConfig {
+    /// Returns the address of each sentinel as `host:port`, with the default port if it has none.
+    fn node_addrs(&self) -> Vec<String> {
+        self.nodes
+            .iter()
+            .map(|node| match node.rsplit_once(':') {
+                Some(_) => node.clone(),
+                None => format!("{node}:{DEFAULT_SENTINEL_PORT}"),
+            })
+            .collect()
+    }
+
+    /// Returns the address of each sentinel, using TLS if it is enabled.
+    fn addrs(&self, tls: bool) -> NetdoxResult<Vec<ConnectionAddr>> {
+        let mut addrs = vec![];
+        for node in &self.nodes {
+            let (host, port) = match node.rsplit_once(':') {
+                Some((host, port)) => match port.parse() {
+                    Ok(port) => (host.to_string(), port),
+                    Err(err) => {
+                        return config_err!(format!(
+                            "Invalid port in sentinel address {node}: {err}"
+                        ))
+                    }
+                },
+                None => (node.to_string(), DEFAULT_SENTINEL_PORT),
+            };
+
+            addrs.push(if tls {
+                ConnectionAddr::TcpTls {
+                    host,
+                    port,
+                    insecure: false,
+                    tls_params: None,
+                }
+            } else {
+                ConnectionAddr::Tcp(host, port)
+            });
+        }
+
+        Ok(addrs)
+    }
+}
+
+/// Config for connecting to a redis cluster.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ClusterConfig {
+    /// Addresses of some of the nodes in the cluster, like `redis-1.redis.net:6379`.
+    /// The rest of the cluster is found through them.
+    pub nodes: Vec<String>,
+}
+
+impl ClusterConfig {
+    /// Returns the address of each node as `host:port`, with the default port if it has none.
+    fn node_addrs(&self) -> Vec<String> {
+        self.nodes
+            .iter()
+            .map(|node| match node.rsplit_once(':') {
+                Some(_) => node.clone(),
+                None => format!("{node}:{}", default_port()),
+            })
+            .collect()
+    }
+}
+
+/// Config for connecting to redis with TLS.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RedisTlsConfig {
+    /// Path to the CA certificate to verify the server with, in PEM format.
+    /// If unset, the system trust store is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_cert: Option<PathBuf>,
+    /// Path to the certificate to authenticate with, in PEM format - if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<PathBuf>,
+    /// Path to the private key of the client certificate, in PEM format - if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<PathBuf>,
+}
+
+impl RedisTlsConfig {
+    /// Reads the configured certificates.
+    pub(crate) fn certificates(&self) -> NetdoxResult<TlsCertificates> {
+        let read = |path: &PathBuf| match fs::read(path) {
+            Ok(pem) => Ok(pem),
+            Err(err) => io_err!(format!(
+                "Failed to read redis TLS file {}: {err}",
+                path.display()
+            )),
+        };
+
+        let client_tls = match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => Some(ClientTlsConfig {
+                client_cert: read(cert)?,
+                client_key: read(key)?,
+            }),
+            (None, None) => None,
+            _ => {
+                return config_err!(
+                    "Redis TLS client_cert and client_key must be set together.".to_string()
+                )
+            }
+        };
+
+        Ok(TlsCertificates {
+            client_tls,
+            root_cert: self.ca_cert.as_ref().map(read).transpose()?,
+        })
+    }
+}
+
 /// Config for a redis data store.
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct RedisConfig {
-    /// Hostname of the redis server to use.
+    /// Hostname of the redis server to use. Ignored if sentinel or cluster is configured.
     pub host: String,
-    /// Port of the redis server to use.
+    /// Port of the redis server to use. Ignored if sentinel or cluster is configured.
     #[serde(default = "default_port")]
     pub port: usize,
-    /// Logical database in the redis instance to use.
+    /// Logical database in the redis instance to use. Must be 0 for a cluster.
     #[serde(default = "default_db")]
     pub db: usize,
     /// Username to use when authenticating with redis - if any.
@@ -56,35 +257,506 @@ pub struct RedisConfig {
     pub connection_timeout: Option<u64>,
     /// Timeout in milliseconds for a response from an operation. Default 500.
     pub response_timeout: Option<u64>,
+    /// How to persist the data at the end of an update.
+    #[serde(default)]
+    pub save: SaveStrategy,
+    /// Time in seconds to wait for a save to finish. Default 300.
+    #[serde(default = "default_save_timeout")]
+    pub save_timeout: u64,
+    /// Connect with TLS, optionally with a custom CA and client certificate.
+    /// The sentinels are connected to with TLS as well.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<RedisTlsConfig>,
+    /// Sentinels to ask for the address of the master to use - if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sentinel: Option<SentinelConfig>,
+    /// Nodes of the redis cluster to use - if any. Cannot be used with sentinel.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<ClusterConfig>,
+    /// Allows connecting without TLS or a password, which sends the data in plain text
+    /// to a server that anyone who can reach it can read and write. Default false.
+    #[serde(default)]
+    pub allow_insecure: bool,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        RedisConfig {
+            host: "localhost".to_string(),
+            port: default_port(),
+            db: default_db(),
+            username: None,
+            password: None,
+            connection_timeout: None,
+            response_timeout: None,
+            save: SaveStrategy::default(),
+            save_timeout: default_save_timeout(),
+            tls: None,
+            sentinel: None,
+            cluster: None,
+            allow_insecure: false,
+        }
+    }
 }
 
 impl RedisConfig {
     pub fn url(&self) -> String {
-        format!(
-            "redis://{host}:{port}/{db}",
-            host = self.host,
-            port = self.port,
-            db = self.db
-        )
+        let scheme = if self.tls.is_some() {
+            "rediss"
+        } else {
+            "redis"
+        };
+        match (&self.sentinel, &self.cluster) {
+            (Some(sentinel), _) => format!(
+                "{scheme}+sentinel://{nodes}/{master}/{db}",
+                nodes = sentinel.node_addrs().join(","),
+                master = sentinel.master,
+                db = self.db
+            ),
+            (None, Some(cluster)) => format!(
+                "{scheme}+cluster://{nodes}/{db}",
+                nodes = cluster.node_addrs().join(","),
+                db = self.db
+            ),
+            (None, None) => format!(
+                "{scheme}://{host}:{port}/{db}",
+                host = self.host,
+                port = self.port,
+                db = self.db
+            ),
+        }
+    }
+
+    /// Refuses credentials that redis would reject, and connecting without TLS or a password
+    /// unless that is explicitly allowed.
+    fn check_security(&self) -> NetdoxResult<()> {
+        if self.username.is_some() && self.password.is_none() {
+            return config_err!(
+                "Redis username is set without a password. ACL users must authenticate with one."
+                    .to_string()
+            );
+        }
+        if self.tls.is_none() && self.password.is_none() && !self.allow_insecure {
+            return config_err!(
+                "Redis has neither TLS nor a password configured, so anyone who can reach the server \
+                can read and change the data. Configure redis.tls or redis.password, \
+                or set redis.allow_insecure = true to connect anyway."
+                    .to_string()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Creates a client for the redis server to use.
+    /// If sentinel is configured, the client is for the master the sentinels report.
+    pub async fn client(&self) -> NetdoxResult<Client> {
+        self.check_security()?;
+        if self.cluster.is_some() {
+            return config_err!(
+                "Redis cluster is configured, so there is no single server to create a client for."
+                    .to_string()
+            );
+        }
+        let certs = self
+            .tls
+            .as_ref()
+            .map(|tls| tls.certificates())
+            .transpose()?;
+
+        let sentinel_cfg = match &self.sentinel {
+            Some(sentinel_cfg) => sentinel_cfg,
+            None => {
+                let client = match certs {
+                    Some(certs) => Client::build_with_tls(self.url(), certs),
+                    None => Client::open(self.url()),
+                };
+                return match client {
+                    Ok(client) => Ok(client),
+                    Err(err) => redis_err!(format!("Failed to open redis client: {err}")),
+                };
+            }
+        };
+
+        let mut builder = match SentinelClientBuilder::new(
+            sentinel_cfg.addrs(certs.is_some())?,
+            &sentinel_cfg.master,
+            SentinelServerType::Master,
+        ) {
+            Ok(builder) => builder.set_client_to_redis_db(self.db as i64),
+            Err(err) => return redis_err!(format!("Failed to open sentinel client: {err}")),
+        };
+        if let Some(username) = &self.username {
+            builder = builder.set_client_to_redis_username(username);
+        }
+        if let Some(password) = &self.password {
+            builder = builder.set_client_to_redis_password(password);
+        }
+        if let Some(username) = &sentinel_cfg.username {
+            builder = builder.set_client_to_sentinel_username(username);
+        }
+        if let Some(password) = &sentinel_cfg.password {
+            builder = builder.set_client_to_sentinel_password(password);
+        }
+        if let Some(certs) = certs {
+            builder = builder
+                .set_client_to_redis_tls_mode(TlsMode::Secure)
+                .set_client_to_redis_certificates(certs.clone())
+                .set_client_to_sentinel_tls_mode(TlsMode::Secure)
+                .set_client_to_sentinel_certificates(certs);
+        }
+
+        let mut sentinel = match builder.build() {
+            Ok(sentinel) => sentinel,
+            Err(err) => return redis_err!(format!("Failed to open sentinel client: {err}")),
+        };
+        match sentinel.async_get_client().await {
+            Ok(client) => Ok(client),
+            Err(err) => redis_err!(format!(
+                "Failed to find redis master {} through sentinel: {err}",
+                sentinel_cfg.master
+            )),
+        }
+    }
+
+    /// Connects to the redis server or cluster to use.
+    pub async fn connect(&self) -> NetdoxResult<RedisConnection> {
+        let cluster_cfg = match &self.cluster {
+            Some(cluster_cfg) => cluster_cfg,
+            None => return self.connect_single().await,
+        };
+        self.check_security()?;
+        if self.sentinel.is_some() {
+            return config_err!(
+                "Redis sentinel and cluster are both configured, but only one can be used."
+                    .to_string()
+            );
+        }
+        if self.db != 0 {
+            return config_err!(format!(
+                "Redis db is {}, but a cluster only has database 0.",
+                self.db
+            ));
+        }
+
+        let scheme = if self.tls.is_some() {
+            "rediss"
+        } else {
+            "redis"
+        };
+        let nodes = cluster_cfg
+            .node_addrs()
+            .into_iter()
+            .map(|addr| format!("{scheme}://{addr}"))
+            .collect::<Vec<_>>();
+        let mut builder = ClusterClientBuilder::new(nodes);
+        if let Some(conn_timeout) = self.connection_timeout {
+            builder = builder.connection_timeout(Duration::from_millis(conn_timeout));
+        }
+        if let Some(resp_timeout) = self.response_timeout {
+            builder = builder.response_timeout(Duration::from_millis(resp_timeout));
+        }
+        if let Some(username) = &self.username {
+            builder = builder.username(username);
+        }
+        if let Some(password) = &self.password {
+            builder = builder.password(password);
+        }
+        if let Some(tls) = &self.tls {
+            builder = builder.tls(TlsMode::Secure).certs(tls.certificates()?);
+        }
+
+        let client = match builder.build() {
+            Ok(client) => client,
+            Err(err) => return redis_err!(format!("Failed to open redis cluster client: {err}")),
+        };
+        match client.get_async_connection().await {
+            Ok(con) => Ok(RedisConnection::Cluster(con)),
+            Err(err) => redis_err!(format!("Failed to open redis cluster connection: {err}")),
+        }
+    }
+
+    /// Connects to the redis server to use, or the master the sentinels report.
+    async fn connect_single(&self) -> NetdoxResult<RedisConnection> {
+        let client = self.client().await?;
+        let mut config = AsyncConnectionConfig::new();
+
+        if let Some(conn_timeout) = self.connection_timeout {
+            config = config.set_connection_timeout(Some(Duration::from_millis(conn_timeout)));
+        }
+
+        if let Some(resp_timeout) = self.response_timeout {
+            config = config.set_response_timeout(Some(Duration::from_millis(resp_timeout)));
+        }
+
+        match client
+            .get_multiplexed_async_connection_with_config(&config)
+            .await
+        {
+            Ok(con) => {
+                let mut con = RedisConnection::Single(con);
+                if let Some(pass) = &self.password {
+                    con.auth(pass, self.username.as_ref()).await?;
+                }
+                Ok(con)
+            }
+            Err(err) => redis_err!(format!("Failed to open redis connection: {err}",)),
+        }
+    }
+
+    /// Returns this config with the host and port of the server to use,
+    /// which is the current master if sentinel is configured.
+    pub async fn resolved(&self) -> NetdoxResult<RedisConfig> {
+        let mut resolved = self.clone();
+        if self.sentinel.is_some() {
+            match self.client().await?.get_connection_info().addr() {
+                ConnectionAddr::Tcp(host, port) | ConnectionAddr::TcpTls { host, port, .. } => {
+                    resolved.host = host.clone();
+                    resolved.port = *port as usize;
+                }
+                addr => return redis_err!(format!("Unexpected redis master address: {addr}")),
+            }
+        }
+        Ok(resolved)
     }
 }
 
+/// Config for an SQLite database to use as data store instead of redis.
+#[cfg(feature = "sqlite")]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct SqliteConfig {
+    /// Path to the database file, which is created if it does not exist.
+    pub path: PathBuf,
+}
+
+/// Thresholds for `netdox health`, in minutes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct HealthConfig {
+    /// Age of the last successful update that produces a warning.
+    pub update_warn: u64,
+    /// Age of the last successful update that is critical.
+    pub update_crit: u64,
+    /// Age of the oldest unpublished change that produces a warning.
+    pub publish_warn: u64,
+    /// Age of the oldest unpublished change that is critical.
+    pub publish_crit: u64,
+    /// Whether plugins failing in the last update is critical instead of a warning.
+    pub failed_plugins_crit: bool,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            update_warn: 25 * 60,
+            update_crit: 49 * 60,
+            publish_warn: 2 * 60,
+            publish_crit: 25 * 60,
+            failed_plugins_crit: false,
+        }
+    }
+}
+
+/// Policy for holding back changes to metadata values that flap between runs.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DampeningConfig {
+    /// Minutes that changes to a metadata value are remembered for.
+    pub window_minutes: u64,
+    /// Number of changes to a metadata value within the window above which
+    /// further changes are held back, until it stops changing for the length of the window.
+    pub max_changes: usize,
+}
+
+/// Default number of times to retry a webhook that fails.
+fn default_notification_retries() -> u32 {
+    2
+}
+
+/// Webhooks to notify when updates and publishes finish.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NotificationsConfig {
+    /// Number of times to retry a webhook that fails. Default 2.
+    #[serde(default = "default_notification_retries")]
+    pub retries: u32,
+    #[serde(default, rename = "webhook")]
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        NotificationsConfig {
+            retries: default_notification_retries(),
+            webhooks: vec![],
+        }
+    }
+}
+
+/// Shape of the body posted to a webhook.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// A Slack incoming webhook message.
+    Slack,
+    /// A Microsoft Teams connector card.
+    Teams,
+    /// The event and its details as JSON.
+    #[default]
+    Json,
+}
+
+/// Kinds of event that webhooks can be notified of.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventKind {
+    /// An update finished or was aborted.
+    Update,
+    /// A plugin failed or timed out during an update.
+    PluginFailure,
+    /// A publish finished or was aborted.
+    Publish,
+}
+
+impl Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Update => write!(f, "update"),
+            Self::PluginFailure => write!(f, "plugin-failure"),
+            Self::Publish => write!(f, "publish"),
+        }
+    }
+}
+
+/// A URL that events are posted to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub format: WebhookFormat,
+    /// Events to post to this webhook. All events are posted if empty.
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    pub events: HashSet<EventKind>,
+}
+
 /// Stores info about the remote, plugins, and extensions.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LocalConfig {
     /// Config for redis server to use as data store.
+    #[serde(default)]
     pub redis: RedisConfig,
+    /// Config for an SQLite database to use as data store.
+    /// If set, the redis config is ignored.
+    #[cfg(feature = "sqlite")]
+    #[serde(default)]
+    pub sqlite: Option<SqliteConfig>,
     /// Default network name.
     pub default_network: String,
+    /// Names of all valid networks.
+    /// If empty, DNS names may be created in any network.
+    #[serde(default)]
+    pub networks: HashSet<String>,
+    /// Names of all valid node roles.
+    /// If empty, nodes may have any role.
+    #[serde(default)]
+    pub roles: HashSet<String>,
+    /// Maps a DNS suffix to the network that unqualified names with the suffix are in.
+    /// Names with no matching suffix are in the default network.
+    #[serde(default)]
+    pub network_suffixes: HashMap<String, String>,
+    /// Subnets to group addresses by, in CIDR notation like `10.0.0.0/16`.
+    /// Addresses outside all of them are grouped by their /24 or /64.
+    #[serde(default)]
+    pub subnets: Vec<String>,
     /// DNS names to ignore when added to datastore.
     pub dns_ignore: IgnoreList,
-    /// Configuration of the remote server to display on.
-    pub remote: Remote,
+    /// Maximum number of plugins to run at once in each stage.
+    /// If unset, every plugin in a stage runs at once.
+    #[serde(default)]
+    pub max_concurrent_plugins: Option<usize>,
+    /// Directory to write the output of plugins to, in a subdirectory for each update.
+    #[serde(default)]
+    pub plugin_log_dir: Option<PathBuf>,
+    /// Directory to write Prometheus textfiles to after each update and publish.
+    #[serde(default)]
+    pub metrics_dir: Option<PathBuf>,
+    /// Number of updates a plugin must succeed in without reporting a DNS record or node
+    /// before it is removed during processing. If unset, nothing is removed automatically.
+    #[serde(default)]
+    pub prune_after_updates: Option<usize>,
+    /// Refuse to write to the data store, so it can only be queried.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Paths to additional Lua function libraries to load into redis alongside the netdox functions.
+    #[serde(default)]
+    pub lua_functions: Vec<PathBuf>,
+    /// Thresholds for health checks.
+    #[serde(default)]
+    pub health: HealthConfig,
+    /// Policy for dampening flapping metadata values. If unset, every change is written.
+    #[serde(default)]
+    pub dampening: Option<DampeningConfig>,
+    /// Policy for choosing between values from different plugins for the metadata
+    /// and name of a processed node. By default the value that changed last wins.
+    #[serde(default, skip_serializing_if = "ConflictPolicy::is_default")]
+    pub conflicts: ConflictPolicy,
+    /// Webhooks to notify when updates and publishes finish.
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    /// Cron schedule for the daemon command to run updates and publishes on, like `0 */4 * * *`.
+    #[serde(default)]
+    pub schedule: Option<String>,
+    /// Configuration of the remote servers to display on, each with a name.
+    /// A single remote without a name is read as a remote named `default`.
+    #[serde(rename = "remote", deserialize_with = "deserialize_remotes")]
+    pub remotes: Vec<NamedRemote>,
     /// Plugin configuration.
     #[serde(rename = "plugin", default)]
     pub plugins: Vec<PluginConfig>,
 }
 
+/// Name of the remote in configs written before there could be more than one.
+pub const DEFAULT_REMOTE_NAME: &str = "default";
+
+/// A remote server to display on, with a name that identifies it in the data store and on the command line.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NamedRemote {
+    pub name: String,
+    #[serde(flatten)]
+    pub remote: Remote,
+}
+
+/// Reads either a list of named remotes or a single remote, which is given the default name.
+/// Fails if there are no remotes, or two share a name.
+fn deserialize_remotes<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<NamedRemote>, D::Error> {
+    let remotes = match Value::deserialize(deserializer)? {
+        array @ Value::Array(_) => array
+            .try_into::<Vec<NamedRemote>>()
+            .map_err(D::Error::custom)?,
+        table => vec![NamedRemote {
+            name: DEFAULT_REMOTE_NAME.to_string(),
+            remote: table.try_into().map_err(D::Error::custom)?,
+        }],
+    };
+
+    if remotes.is_empty() {
+        return Err(D::Error::custom("At least one remote must be configured"));
+    }
+    let mut names = HashSet::new();
+    for remote in &remotes {
+        if remote.name.is_empty() {
+            return Err(D::Error::custom("Remote names must not be empty"));
+        }
+        if !names.insert(remote.name.as_str()) {
+            return Err(D::Error::custom(format!(
+                "Remote name {} is used more than once",
+                remote.name
+            )));
+        }
+    }
+    Ok(remotes)
+}
+
 #[derive(Serialize, Deserialize, Debug, Hash, PartialEq, Eq, Clone, Copy)]
 pub enum PluginStage {
     #[serde(rename = "write-only")]
@@ -110,16 +782,98 @@ impl Display for PluginStage {
 pub struct PluginStageConfig {
     /// Path to the executable for this stage.
     pub path: String,
+    /// Number of times to run the stage again if it exits with a nonzero code.
+    #[serde(default)]
+    pub retries: u32,
+    /// Path to an executable that prints a cursor for the upstream data of this stage.
+    /// If the cursor is the same as after the last successful run, the stage is skipped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probe: Option<String>,
+    /// Runs the stage once for each batch of the processed nodes that match a filter,
+    /// instead of once for the whole stage. Only allowed in the connectors stage.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workers: Option<WorkerPoolConfig>,
     /// Plugin-specific configuration map for this stage.
     #[serde(flatten)]
     pub fields: HashMap<String, Value>,
 }
 
+/// Default number of batches of a worker pool to run at once.
+fn default_worker_concurrency() -> usize {
+    4
+}
+
+/// Default number of nodes in each batch of a worker pool.
+fn default_worker_batch_size() -> usize {
+    1
+}
+
+/// Stores configuration for running a stage once for each batch of some processed nodes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WorkerPoolConfig {
+    /// Maximum number of batches to run at once. Default 4.
+    #[serde(default = "default_worker_concurrency")]
+    pub concurrency: usize,
+    /// Maximum number of nodes in each batch. Default 1.
+    #[serde(default = "default_worker_batch_size")]
+    pub batch_size: usize,
+    /// Nodes the stage is run for.
+    #[serde(default)]
+    pub filter: NodeFilter,
+}
+
+/// Selects processed nodes. An empty filter matches every node.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct NodeFilter {
+    /// Plugins the node must have been created by at least one of.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub plugins: Vec<String>,
+    /// Tags the node must have all of.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Metadata keys the node must have, with these values.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, String>,
+}
+
+impl NodeFilter {
+    /// Returns true if a node with some tags and metadata matches the filter.
+    pub fn matches(
+        &self,
+        node: &Node,
+        tags: &HashSet<String>,
+        metadata: &HashMap<String, String>,
+    ) -> bool {
+        (self.plugins.is_empty() || self.plugins.iter().any(|p| node.plugins.contains(p)))
+            && self
+                .tags
+                .iter()
+                .all(|tag| tags.contains(&tag.trim().to_lowercase()))
+            && self
+                .metadata
+                .iter()
+                .all(|(key, value)| metadata.get(key) == Some(value))
+    }
+}
+
 /// Stores configuration for a plugin.
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct PluginConfig {
     /// Name of the plugin.
     pub name: String,
+    /// How data from this plugin should be displayed in documents.
+    #[serde(default, skip_serializing_if = "PluginDisplay::is_empty")]
+    pub display: PluginDisplay,
+    /// DNS names and nodes this plugin may create.
+    #[serde(default, skip_serializing_if = "PluginScope::is_empty")]
+    pub scope: PluginScope,
+    /// Number of seconds a stage of this plugin may run for before it is killed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_seconds: Option<u64>,
+    /// Version of the data schema the plugin was written for.
+    /// If set, the plugin is not run unless it matches the schema of this netdox.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_version: Option<u32>,
     /// Plugin-specific configuration map for all stages.
     #[serde(flatten)]
     pub fields: HashMap<String, Value>,
@@ -128,18 +882,6 @@ pub struct PluginConfig {
 }
 
 pub const CFG_PATH_VAR: &str = "NETDOX_CONFIG";
-const CFG_SECRET_VAR: &str = "NETDOX_SECRET";
-
-fn secret() -> NetdoxResult<SecretString> {
-    match env::var(CFG_SECRET_VAR) {
-        Err(err) => {
-            config_err!(format!(
-                "Failed to read environment variable {CFG_SECRET_VAR}: {err}"
-            ))
-        }
-        Ok(txt) => Ok(SecretString::from(txt)),
-    }
-}
 
 impl LocalConfig {
     /// Creates a template instance with no config.
@@ -153,52 +895,179 @@ impl LocalConfig {
                 password: Some("redis-password-123!?".to_string()),
                 connection_timeout: None,
                 response_timeout: None,
+                save: SaveStrategy::default(),
+                save_timeout: default_save_timeout(),
+                tls: None,
+                sentinel: None,
+                cluster: None,
+                allow_insecure: false,
             },
+            #[cfg(feature = "sqlite")]
+            sqlite: None,
             default_network: "name for your default network".to_string(),
+            networks: HashSet::new(),
+            roles: HashSet::new(),
+            network_suffixes: HashMap::new(),
+            subnets: vec![],
             dns_ignore: IgnoreList::Set(HashSet::new()),
-            remote,
+            max_concurrent_plugins: None,
+            plugin_log_dir: None,
+            metrics_dir: None,
+            prune_after_updates: None,
+            read_only: false,
+            lua_functions: vec![],
+            health: HealthConfig::default(),
+            dampening: None,
+            conflicts: ConflictPolicy::default(),
+            notifications: NotificationsConfig::default(),
+            schedule: None,
+            remotes: vec![NamedRemote {
+                name: DEFAULT_REMOTE_NAME.to_string(),
+                remote,
+            }],
             plugins: vec![],
         }
     }
 
-    /// Creates a `DataClient` for the configured redis instance and returns it.
-    pub async fn con(&self) -> NetdoxResult<DataStore> {
-        match Client::open(self.redis.url().as_str()) {
-            Ok(client) => {
-                let mut config = AsyncConnectionConfig::new();
+    /// Returns the first remote, whose config applies to the data store
+    /// and which is used by commands that only read from one remote.
+    pub fn remote(&self) -> &Remote {
+        &self.remotes[0].remote
+    }
 
-                if let Some(conn_timeout) = self.redis.connection_timeout {
-                    config =
-                        config.set_connection_timeout(Some(Duration::from_millis(conn_timeout)));
-                }
+    /// Returns the remote with a name, or every remote if no name is given.
+    pub fn select_remotes(&self, name: Option<&str>) -> NetdoxResult<Vec<&NamedRemote>> {
+        match name {
+            None => Ok(self.remotes.iter().collect()),
+            Some(name) => match self.remotes.iter().find(|remote| remote.name == name) {
+                Some(remote) => Ok(vec![remote]),
+                None => config_err!(format!(
+                    "No remote named {name}. The remotes are: {}",
+                    self.remotes
+                        .iter()
+                        .map(|remote| remote.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )),
+            },
+        }
+    }
 
-                if let Some(resp_timeout) = self.redis.response_timeout {
-                    config = config.set_response_timeout(Some(Duration::from_millis(resp_timeout)));
-                }
+    /// Returns the network suffixes in the form they are matched against names,
+    /// lowercase and without a leading wildcard or dot.
+    pub fn network_suffixes(&self) -> NetdoxResult<HashMap<String, String>> {
+        let mut suffixes = HashMap::new();
+        for (suffix, network) in &self.network_suffixes {
+            let normalised = suffix
+                .trim_start_matches('*')
+                .trim_start_matches('.')
+                .to_lowercase();
+            let network = network.to_lowercase();
 
-                match client
-                    .get_multiplexed_async_connection_with_config(&config)
-                    .await
-                {
-                    Ok(con) => match &self.redis.password {
-                        None => Ok(DataStore::Redis(con)),
-                        Some(pass) => {
-                            let mut con = DataStore::Redis(con);
-                            con.auth(pass, self.redis.username.as_ref()).await?;
-                            Ok(con)
-                        }
-                    },
-                    Err(err) => redis_err!(format!("Failed to open redis connection: {err}",)),
-                }
+            if normalised.is_empty() {
+                return config_err!(format!("Network suffix is empty: {suffix}"));
             }
-            Err(err) => {
-                redis_err!(format!("Failed to open redis client: {err}"))
+            if network.is_empty()
+                || !network
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+            {
+                return config_err!(format!(
+                    "Network for suffix {suffix} must contain only letters, digits, '-' and '_': {network}"
+                ));
+            }
+            if !self.networks.is_empty()
+                && network != self.default_network.to_lowercase()
+                && !self
+                    .networks
+                    .iter()
+                    .any(|net| net.to_lowercase() == network)
+            {
+                return config_err!(format!(
+                    "Network for suffix {suffix} is not registered: {network}"
+                ));
             }
+
+            suffixes.insert(normalised, network);
         }
+
+        Ok(suffixes)
     }
 
-    /// Encrypts this config and writes it to the appropriate location.
-    pub fn write(&self) -> NetdoxResult<PathBuf> {
+    /// Returns the configured subnets, with any host bits cleared.
+    pub fn subnets(&self) -> NetdoxResult<Vec<IpNet>> {
+        let mut subnets = vec![];
+        for subnet in &self.subnets {
+            match subnet.parse::<IpNet>() {
+                Ok(net) => subnets.push(net.trunc()),
+                Err(err) => return config_err!(format!("Invalid subnet {subnet}: {err}")),
+            }
+        }
+
+        Ok(subnets)
+    }
+
+    /// Describes the configured data store for display.
+    pub fn store_name(&self) -> String {
+        #[cfg(feature = "sqlite")]
+        if let Some(sqlite) = &self.sqlite {
+            return format!("sqlite://{}", sqlite.path.to_string_lossy());
+        }
+
+        redact_url(&self.redis.url())
+    }
+
+    /// Returns the config of the data store for plugins, serialised as TOML.
+    /// The redis config has the address of the current master if sentinel is configured.
+    pub async fn store_config(&self) -> NetdoxResult<String> {
+        #[cfg(feature = "sqlite")]
+        if let Some(sqlite) = &self.sqlite {
+            return match toml::to_string(sqlite) {
+                Ok(string) => Ok(string),
+                Err(err) => io_err!(format!("Failed to serialise SQLite config: {err}")),
+            };
+        }
+
+        match toml::to_string(&self.redis.resolved().await?) {
+            Ok(string) => Ok(string),
+            Err(err) => io_err!(format!("Failed to serialise redis config: {err}")),
+        }
+    }
+
+    /// Returns true if the data store must not be written to,
+    /// because of this config or the command line.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only || read_only_enabled()
+    }
+
+    /// Creates a `DataClient` for the configured data store and returns it.
+    /// In read-only mode, the client refuses to write.
+    pub async fn con(&self) -> NetdoxResult<DataStore> {
+        let con = self.writable_con().await?;
+        if self.is_read_only() {
+            Ok(DataStore::ReadOnly(ReadOnlyStore::new(con)))
+        } else {
+            Ok(con)
+        }
+    }
+
+    async fn writable_con(&self) -> NetdoxResult<DataStore> {
+        if let Some(store) = offline_store() {
+            return Ok(DataStore::Memory(store));
+        }
+
+        #[cfg(feature = "sqlite")]
+        if let Some(sqlite) = &self.sqlite {
+            return Ok(DataStore::Sqlite(SqliteStore::open(&sqlite.path)?));
+        }
+
+        let mut con = self.redis.connect().await?;
+        redis_store::check_server(&mut con).await?;
+        Ok(DataStore::Redis(con))
+    }
+
+    /// Returns the path to write the encrypted config to.
+    fn write_path() -> NetdoxResult<PathBuf> {
         let path = {
             if let Ok(path) = env::var(CFG_PATH_VAR) {
                 path
@@ -217,38 +1086,70 @@ impl LocalConfig {
             }
         };
 
-        if let Err(err) = fs::write(&path, self.encrypt()?) {
-            config_err!(format!("Failed to write encrypted config to {path}: {err}"))
-        } else {
-            Ok(PathBuf::from(path))
-        }
+        Ok(PathBuf::from(path))
     }
 
-    pub fn read() -> NetdoxResult<Self> {
-        let path = match env::var(CFG_PATH_VAR) {
-            Ok(path) => path,
+    /// Returns the path to read the encrypted config from.
+    fn read_path() -> NetdoxResult<PathBuf> {
+        match env::var(CFG_PATH_VAR) {
+            Ok(path) => Ok(PathBuf::from(path)),
             Err(_) => match env::var("HOME") {
-                Ok(home) => format!("{home}/.config/.netdox"),
-                Err(_) => {
-                    return io_err!(format!(
-                        "Cannot find path to store encrypted config: \
+                Ok(home) => Ok(PathBuf::from(format!("{home}/.config/.netdox"))),
+                Err(_) => io_err!(format!(
+                    "Cannot find path to store encrypted config: \
                     please set ${CFG_PATH_VAR} or $HOME."
-                    ))
-                }
+                )),
             },
-        };
+        }
+    }
+
+    /// Encrypts this config and writes it to the appropriate location,
+    /// with the secret from the source stored there.
+    pub fn write(&self) -> NetdoxResult<PathBuf> {
+        let path = Self::write_path()?;
+        let source = SecretSource::stored(&path)?;
+        self.write_to(&path, &source)
+    }
 
+    /// Encrypts this config with the secret from a source and writes it to the appropriate location,
+    /// storing the source next to it so the config can be read again.
+    pub fn write_with_source(&self, source: &SecretSource) -> NetdoxResult<PathBuf> {
+        let path = Self::write_path()?;
+        self.write_to(&path, source)
+    }
+
+    fn write_to(&self, path: &Path, source: &SecretSource) -> NetdoxResult<PathBuf> {
+        let cipher = self.encrypt(&source.secret()?)?;
+        source.store(path)?;
+        if let Err(err) = fs::write(path, cipher) {
+            config_err!(format!(
+                "Failed to write encrypted config to {}: {err}",
+                path.display()
+            ))
+        } else {
+            Ok(path.to_path_buf())
+        }
+    }
+
+    /// Reads the encrypted config and decrypts it with the secret from the source stored next to it.
+    pub fn read() -> NetdoxResult<Self> {
+        let path = Self::read_path()?;
         let bytes = match fs::read(&path) {
-            Err(err) => return config_err!(format!("Failed to read config file at {path}: {err}")),
+            Err(err) => {
+                return config_err!(format!(
+                    "Failed to read config file at {}: {err}",
+                    path.display()
+                ))
+            }
             Ok(bytes) => bytes,
         };
 
-        Self::decrypt(&bytes)
+        Self::decrypt(&bytes, &SecretSource::stored(&path)?.secret()?)
     }
 
-    /// Encrypts this config.
-    pub fn encrypt(&self) -> NetdoxResult<Vec<u8>> {
-        let enc = Encryptor::with_user_passphrase(secret()?);
+    /// Encrypts this config with a secret.
+    pub fn encrypt(&self, secret: &SecretString) -> NetdoxResult<Vec<u8>> {
+        let enc = Encryptor::with_user_passphrase(secret.clone());
 
         let plain = match toml::to_string(&self) {
             Err(err) => return config_err!(format!("Failed to serialize config: {err}")),
@@ -269,8 +1170,8 @@ impl LocalConfig {
         Ok(cipher)
     }
 
-    /// Decrypts a config from some cipher bytes.
-    pub fn decrypt(cipher: &[u8]) -> NetdoxResult<Self> {
+    /// Decrypts a config from some cipher bytes with a secret.
+    pub fn decrypt(cipher: &[u8], secret: &SecretString) -> NetdoxResult<Self> {
         let dec = match Decryptor::new(cipher) {
             Err(err) => return config_err!(format!("Failed creating decryptor: {err}")),
             Ok(decryptor) => match decryptor {
@@ -284,7 +1185,7 @@ impl LocalConfig {
         };
 
         let mut plain = vec![];
-        let mut reader = match dec.decrypt(&secret()?, None) {
+        let mut reader = match dec.decrypt(secret, None) {
             Err(err) => return config_err!(format!("Failed creating decrypting reader: {err}")),
             Ok(reader) => reader,
         };
@@ -308,19 +1209,34 @@ impl LocalConfig {
 mod tests {
     use std::{
         collections::{HashMap, HashSet},
-        env::set_var,
+        env::{set_var, temp_dir},
+        fs,
+        path::PathBuf,
         str::FromStr,
     };
 
     use age::secrecy::{ExposeSecret, SecretString};
+    use redis::ConnectionAddr;
+    use serde::Deserialize;
     use toml::Value;
 
     use crate::{
-        config::local::{secret, IgnoreList, PluginStage, PluginStageConfig, RedisConfig},
+        config::{
+            local::{
+                ClusterConfig, IgnoreList, NodeFilter, PluginStage, PluginStageConfig, RedisConfig,
+                RedisTlsConfig, SentinelConfig, WorkerPoolConfig,
+            },
+            secret::{SecretSource, CFG_SECRET_VAR},
+        },
+        data::model::{ConflictPolicy, ConflictStrategy, Node, PluginDisplay, PluginScope},
         remote::{DummyRemote, Remote},
     };
 
-    use super::{LocalConfig, PluginConfig, CFG_SECRET_VAR};
+    use super::{
+        deserialize_remotes, DampeningConfig, EventKind, HealthConfig, LocalConfig, NamedRemote,
+        NotificationsConfig, PluginConfig, SaveStrategy, WebhookConfig, WebhookFormat,
+        DEFAULT_REMOTE_NAME,
+    };
 
     const FAKE_SECRET: &str = "secret-key!";
 
@@ -328,14 +1244,12 @@ mod tests {
     fn test_secret_success() {
         set_var(CFG_SECRET_VAR, FAKE_SECRET);
         let expected = SecretString::from_str(FAKE_SECRET).unwrap();
-        let actual = secret().unwrap();
+        let actual = SecretSource::default().secret().unwrap();
         assert_eq!(*expected.expose_secret(), *actual.expose_secret());
     }
 
     #[test]
     fn test_cfg_crypt_roundtrip() {
-        set_var(CFG_SECRET_VAR, FAKE_SECRET);
-
         let cfg = LocalConfig {
             redis: RedisConfig {
                 host: "my.redis.net".to_string(),
@@ -345,14 +1259,94 @@ mod tests {
                 password: Some("redis-password-123!?".to_string()),
                 connection_timeout: None,
                 response_timeout: None,
+                save: SaveStrategy::Aof,
+                save_timeout: 60,
+                tls: Some(RedisTlsConfig {
+                    ca_cert: Some(PathBuf::from("/etc/netdox/redis-ca.pem")),
+                    client_cert: None,
+                    client_key: None,
+                }),
+                sentinel: Some(SentinelConfig {
+                    master: "mymaster".to_string(),
+                    nodes: vec![
+                        "sentinel-1.redis.net:26379".to_string(),
+                        "sentinel-2.redis.net".to_string(),
+                    ],
+                    username: None,
+                    password: Some("sentinel-password".to_string()),
+                }),
+                cluster: None,
+                allow_insecure: false,
             },
+            #[cfg(feature = "sqlite")]
+            sqlite: None,
             default_network: "default-net".to_string(),
+            networks: HashSet::from(["default-net".to_string(), "other-net".to_string()]),
+            roles: HashSet::from(["web server".to_string(), "database".to_string()]),
+            network_suffixes: HashMap::from([(
+                "*.corp.local".to_string(),
+                "other-net".to_string(),
+            )]),
+            subnets: vec!["10.0.0.0/16".to_string()],
             dns_ignore: IgnoreList::Set(HashSet::new()),
-            remote: Remote::Dummy(DummyRemote {
-                field: "some-value".to_string(),
+            max_concurrent_plugins: None,
+            plugin_log_dir: None,
+            metrics_dir: None,
+            prune_after_updates: None,
+            read_only: false,
+            lua_functions: vec![],
+            health: HealthConfig {
+                update_warn: 60,
+                update_crit: 120,
+                publish_warn: 30,
+                publish_crit: 90,
+                failed_plugins_crit: true,
+            },
+            dampening: Some(DampeningConfig {
+                window_minutes: 24 * 60,
+                max_changes: 3,
             }),
+            conflicts: ConflictPolicy {
+                priority: vec!["test-plugin".to_string()],
+                strategy: ConflictStrategy::FirstWins,
+                report: true,
+            },
+            notifications: NotificationsConfig {
+                retries: 1,
+                webhooks: vec![WebhookConfig {
+                    url: "https://hooks.slack.com/services/T000/B000/XXXX".to_string(),
+                    format: WebhookFormat::Slack,
+                    events: HashSet::from([EventKind::Update, EventKind::PluginFailure]),
+                }],
+            },
+            schedule: Some("0 */4 * * *".to_string()),
+            remotes: vec![
+                NamedRemote {
+                    name: "primary".to_string(),
+                    remote: Remote::Dummy(DummyRemote {
+                        field: "some-value".to_string(),
+                    }),
+                },
+                NamedRemote {
+                    name: "mirror".to_string(),
+                    remote: Remote::Dummy(DummyRemote {
+                        field: "other-value".to_string(),
+                    }),
+                },
+            ],
             plugins: vec![PluginConfig {
                 name: "test-plugin".to_string(),
+                display: PluginDisplay {
+                    name: Some("Test Plugin".to_string()),
+                    label: Some("test".to_string()),
+                    colour: None,
+                },
+                scope: PluginScope {
+                    networks: vec!["test-net".to_string()],
+                    zones: vec!["test.com".to_string()],
+                },
+                timeout_seconds: Some(300),
+                schema_version: Some(1),
                 fields: HashMap::from([(
                     "global-key".to_string(),
                     Value::String("global-value".to_string()),
@@ -362,6 +1356,9 @@ mod tests {
                         PluginStage::WriteOnly,
                         PluginStageConfig {
                             path: "/path/to/write/only/exe".to_string(),
+                            retries: 2,
+                            probe: Some("/path/to/write/only/probe".to_string()),
+                            workers: None,
                             fields: HashMap::from([(
                                 "write-only-key".to_string(),
                                 Value::String("write-only-value".to_string()),
@@ -372,22 +1369,280 @@ mod tests {
                         PluginStage::ReadWrite,
                         PluginStageConfig {
                             path: "/path/to/read/write/exe".to_string(),
+                            retries: 0,
+                            probe: None,
+                            workers: None,
                             fields: HashMap::from([(
                                 "read-write-key".to_string(),
                                 Value::String("read-write-value".to_string()),
                             )]),
                         },
                     ),
+                    (
+                        PluginStage::Connectors,
+                        PluginStageConfig {
+                            path: "/path/to/connectors/exe".to_string(),
+                            retries: 0,
+                            probe: None,
+                            workers: Some(WorkerPoolConfig {
+                                concurrency: 8,
+                                batch_size: 10,
+                                filter: NodeFilter {
+                                    plugins: vec!["test-plugin".to_string()],
+                                    tags: vec!["prod".to_string()],
+                                    metadata: HashMap::from([(
+                                        "role".to_string(),
+                                        "web server".to_string(),
+                                    )]),
+                                },
+                            }),
+                            fields: HashMap::new(),
+                        },
+                    ),
                 ]),
             }],
         };
 
-        let enc = cfg.encrypt().unwrap();
-        let dec = LocalConfig::decrypt(&enc).unwrap();
+        let secret = SecretString::from(FAKE_SECRET.to_string());
+        let enc = cfg.encrypt(&secret).unwrap();
+        let dec = LocalConfig::decrypt(&enc, &secret).unwrap();
+        assert!(LocalConfig::decrypt(&enc, &SecretString::from("other".to_string())).is_err());
 
         assert_eq!(cfg.redis, dec.redis);
         assert_eq!(cfg.default_network, dec.default_network);
-        assert!(matches!(dec.remote, Remote::Dummy(_)));
+        assert_eq!(cfg.networks, dec.networks);
+        assert_eq!(cfg.roles, dec.roles);
+        assert_eq!(cfg.network_suffixes, dec.network_suffixes);
+        assert_eq!(cfg.subnets, dec.subnets);
+        assert_eq!(cfg.health, dec.health);
+        assert_eq!(cfg.dampening, dec.dampening);
+        assert_eq!(cfg.conflicts, dec.conflicts);
+        assert_eq!(cfg.notifications, dec.notifications);
+        assert_eq!(
+            dec.remotes
+                .iter()
+                .map(|remote| remote.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["primary", "mirror"]
+        );
+        assert!(matches!(dec.remote(), Remote::Dummy(_)));
         assert_eq!(cfg.plugins, dec.plugins);
     }
+
+    #[test]
+    fn test_node_filter() {
+        let node = Node {
+            name: "Web".to_string(),
+            link_id: "web".to_string(),
+            alt_names: HashSet::new(),
+            dns_names: HashSet::from(["[net]web.com".to_string()]),
+            plugins: HashSet::from(["vmware".to_string(), "snmp".to_string()]),
+            raw_ids: HashSet::new(),
+        };
+        let tags = HashSet::from(["prod".to_string()]);
+        let metadata = HashMap::from([("role".to_string(), "web server".to_string())]);
+
+        assert!(NodeFilter::default().matches(&node, &HashSet::new(), &HashMap::new()));
+        let filter = NodeFilter {
+            plugins: vec!["vmware".to_string(), "aws".to_string()],
+            tags: vec![" Prod ".to_string()],
+            metadata: metadata.clone(),
+        };
+        assert!(filter.matches(&node, &tags, &metadata));
+        assert!(!filter.matches(&node, &HashSet::new(), &metadata));
+        assert!(!filter.matches(&node, &tags, &HashMap::new()));
+
+        let filter = NodeFilter {
+            plugins: vec!["aws".to_string()],
+            ..NodeFilter::default()
+        };
+        assert!(!filter.matches(&node, &tags, &metadata));
+    }
+
+    #[test]
+    fn test_sentinel_nodes() {
+        let sentinel = SentinelConfig {
+            master: "mymaster".to_string(),
+            nodes: vec![
+                "sentinel-1.redis.net:26380".to_string(),
+                "sentinel-2.redis.net".to_string(),
+            ],
+            username: None,
+            password: Some("sentinel-password".to_string()),
+        };
+
+        assert_eq!(
+            sentinel.addrs(false).unwrap(),
+            vec![
+                ConnectionAddr::Tcp("sentinel-1.redis.net".to_string(), 26380),
+                ConnectionAddr::Tcp("sentinel-2.redis.net".to_string(), 26379)
+            ]
+        );
+        assert!(matches!(
+            &sentinel.addrs(true).unwrap()[1],
+            ConnectionAddr::TcpTls { host, port: 26379, insecure: false, .. }
+                if host == "sentinel-2.redis.net"
+        ));
+
+        let mut redis = RedisConfig {
+            sentinel: Some(sentinel),
+            ..RedisConfig::default()
+        };
+        assert_eq!(
+            redis.url(),
+            "redis+sentinel://sentinel-1.redis.net:26380,sentinel-2.redis.net:26379/mymaster/0"
+        );
+        redis.sentinel = None;
+        redis.tls = Some(RedisTlsConfig::default());
+        assert_eq!(redis.url(), "rediss://localhost:6379/0");
+    }
+
+    #[tokio::test]
+    async fn test_redis_cluster() {
+        let mut redis = RedisConfig {
+            cluster: Some(ClusterConfig {
+                nodes: vec![
+                    "redis-1.redis.net:7000".to_string(),
+                    "redis-2.redis.net".to_string(),
+                ],
+            }),
+            allow_insecure: true,
+            ..RedisConfig::default()
+        };
+        assert_eq!(
+            redis.url(),
+            "redis+cluster://redis-1.redis.net:7000,redis-2.redis.net:6379/0"
+        );
+        assert!(redis.client().await.is_err());
+
+        // Both are refused before connecting.
+        redis.db = 1;
+        assert!(redis.connect().await.is_err());
+        redis.db = 0;
+        redis.sentinel = Some(SentinelConfig {
+            master: "mymaster".to_string(),
+            nodes: vec!["sentinel-1.redis.net".to_string()],
+            username: None,
+            password: None,
+        });
+        assert!(redis.connect().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_redis_client_insecure() {
+        let mut redis = RedisConfig::default();
+        assert!(redis.client().await.is_err());
+
+        redis.allow_insecure = true;
+        assert!(redis.client().await.is_ok());
+
+        redis.allow_insecure = false;
+        redis.password = Some("password".to_string());
+        assert!(redis.client().await.is_ok());
+    }
+
+    #[test]
+    fn test_redis_tls_certificates() {
+        let cert = temp_dir().join(format!("netdox-redis-cert-{}", std::process::id()));
+        fs::write(&cert, "cert").unwrap();
+
+        let tls = RedisTlsConfig {
+            ca_cert: Some(cert.clone()),
+            client_cert: Some(cert.clone()),
+            client_key: None,
+        };
+        assert!(tls.certificates().is_err());
+
+        let tls = RedisTlsConfig {
+            client_key: Some(cert.clone()),
+            ..tls
+        };
+        let certs = tls.certificates().unwrap();
+        assert_eq!(certs.root_cert.unwrap(), b"cert");
+        assert_eq!(certs.client_tls.unwrap().client_key, b"cert");
+
+        fs::remove_file(cert).unwrap();
+    }
+
+    #[test]
+    fn test_remotes() {
+        #[derive(Deserialize)]
+        struct Remotes {
+            #[serde(deserialize_with = "deserialize_remotes")]
+            remote: Vec<NamedRemote>,
+        }
+
+        // Configs with a single remote are still read.
+        let single: Remotes = toml::from_str("[remote.Dummy]\nfield = \"a\"\n").unwrap();
+        assert_eq!(single.remote.len(), 1);
+        assert_eq!(single.remote[0].name, DEFAULT_REMOTE_NAME);
+
+        let list: Remotes = toml::from_str(
+            r#"
+            [[remote]]
+            name = "pageseeder"
+            Dummy.field = "a"
+
+            [[remote]]
+            name = "mirror"
+            Dummy.field = "b"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(list.remote[1].name, "mirror");
+        assert!(matches!(
+            &list.remote[1].remote,
+            Remote::Dummy(DummyRemote { field }) if field == "b"
+        ));
+
+        assert!(toml::from_str::<Remotes>("remote = []").is_err());
+        assert!(toml::from_str::<Remotes>(
+            "[[remote]]\nname = \"a\"\nDummy.field = \"\"\n[[remote]]\nname = \"a\"\nDummy.field = \"\"\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_network_suffixes() {
+        let mut cfg = LocalConfig::template(Remote::Dummy(DummyRemote {
+            field: "value".to_string(),
+        }));
+        cfg.default_network = "default-net".to_string();
+        cfg.networks = HashSet::from(["Corp-Net".to_string()]);
+        cfg.network_suffixes = HashMap::from([
+            ("*.Corp.Local".to_string(), "corp-net".to_string()),
+            (".lab".to_string(), "default-net".to_string()),
+        ]);
+
+        assert_eq!(
+            cfg.network_suffixes().unwrap(),
+            HashMap::from([
+                ("corp.local".to_string(), "corp-net".to_string()),
+                ("lab".to_string(), "default-net".to_string()),
+            ])
+        );
+
+        cfg.network_suffixes = HashMap::from([("corp.local".to_string(), "other".to_string())]);
+        assert!(cfg.network_suffixes().is_err());
+    }
+
+    #[test]
+    fn test_subnets() {
+        let mut cfg = LocalConfig::template(Remote::Dummy(DummyRemote {
+            field: "value".to_string(),
+        }));
+        cfg.subnets = vec!["10.1.2.3/16".to_string(), "2001:db8::/48".to_string()];
+
+        assert_eq!(
+            cfg.subnets()
+                .unwrap()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec!["10.1.0.0/16", "2001:db8::/48"]
+        );
+
+        cfg.subnets = vec!["10.1.2.3".to_string()];
+        assert!(cfg.subnets().is_err());
+    }
 }
@@ -6,11 +6,11 @@ use std::{
 
 use ipnet::Ipv4Net;
 use itertools::{Either, Itertools};
-use paris::warn;
+use tracing::warn;
 
 use crate::{
     data::{
-        model::{ObjectID, LOCATIONS_META_KEY, LOCATIONS_PLUGIN, NETDOX_PLUGIN},
+        model::{ObjectID, LOCATIONS_META_KEY, LOCATIONS_PLUGIN, NETDOX_PLUGIN, NODE_ID_META_KEY},
         store::DataStore,
         DataConn,
     },
@@ -109,7 +109,7 @@ impl RemoteConfig {
                     continue;
                 }
 
-                if let Some(node_id) = con.get_dns_metadata(name).await?.get("_node") {
+                if let Some(node_id) = con.get_dns_metadata(name).await?.get(NODE_ID_META_KEY) {
                     let node = &con.get_node(node_id).await?;
                     let node_meta = con.get_node_metadata(node).await?;
                     if let Some(location) = node_meta.get(LOCATIONS_META_KEY) {
@@ -173,7 +173,7 @@ impl RemoteConfig {
         )
         .await?;
 
-        if let Some(node_id) = con.get_dns_metadata(name).await?.get("_node") {
+        if let Some(node_id) = con.get_dns_metadata(name).await?.get(NODE_ID_META_KEY) {
             self.set_node_location(con, node_id, location).await?;
         }
 
@@ -194,6 +194,96 @@ impl RemoteConfig {
         .await
     }
 
+    /// Returns warnings for the parts of the config that would not apply as intended,
+    /// given the qualified DNS names and node link IDs in the data store,
+    /// and the objects of the documents with each label in the metadata overrides.
+    pub fn check(
+        &self,
+        qnames: &HashSet<String>,
+        node_ids: &HashSet<String>,
+        labeled: &HashMap<String, Vec<ObjectID>>,
+    ) -> Vec<String> {
+        let mut warnings = vec![];
+
+        let names = qnames
+            .iter()
+            .filter_map(|qname| qname.rsplit_once(']').map(|(_, name)| name))
+            .collect::<HashSet<_>>();
+        for exclusion in self.exclusions.iter().sorted() {
+            if !names.contains(exclusion.as_str()) && !qnames.contains(exclusion) {
+                warnings.push(format!(
+                    "Exclusion {exclusion} matches no DNS name in the data store."
+                ));
+            }
+        }
+
+        let addrs = names
+            .iter()
+            .filter_map(|name| name.parse::<Ipv4Addr>().ok())
+            .collect::<Vec<_>>();
+        for (subnet, location) in self.locations.iter().sorted() {
+            if *subnet != subnet.trunc() {
+                warnings.push(format!(
+                    "Location subnet {subnet} ({location}) has host bits set, \
+                    so it is the same as {}.",
+                    subnet.trunc()
+                ));
+            }
+            if !addrs.iter().any(|addr| subnet.contains(addr)) {
+                warnings.push(format!(
+                    "Location subnet {subnet} ({location}) contains no address in the data store."
+                ));
+            }
+
+            for (outer, outer_location) in self.locations.iter().sorted() {
+                if outer == subnet || !outer.contains(subnet) || outer_location == location {
+                    continue;
+                }
+                let chosen = self
+                    .choose_subnet(subnet.network())
+                    .and_then(|chosen| self.locations.get(chosen))
+                    .unwrap_or(location);
+                warnings.push(format!(
+                    "Location subnet {subnet} ({location}) is inside {outer} ({outer_location}), \
+                    and addresses in it are located in {chosen}."
+                ));
+            }
+        }
+
+        for (label, meta) in self.metadata.iter().sorted_by_key(|(label, _)| *label) {
+            if meta.is_empty() {
+                warnings.push(format!("Metadata for label {label} sets no keys."));
+            }
+            let objects = labeled.get(label).map(Vec::as_slice).unwrap_or_default();
+            if objects.is_empty() {
+                warnings.push(format!(
+                    "Metadata for label {label} applies to no documents on the remote."
+                ));
+            }
+            for obj_id in objects {
+                match obj_id {
+                    ObjectID::DNS(qname) if !qnames.contains(qname) => warnings.push(format!(
+                        "Document labelled {label} is for DNS name {qname}, \
+                        which is not in the data store."
+                    )),
+                    ObjectID::Node(link_id) if !node_ids.contains(link_id) => {
+                        warnings.push(format!(
+                            "Document labelled {label} is for node {link_id}, \
+                            which is not in the data store."
+                        ))
+                    }
+                    ObjectID::Report(id) => warnings.push(format!(
+                        "Document labelled {label} is for report {id}, \
+                        which metadata cannot be set on."
+                    )),
+                    _ => {}
+                }
+            }
+        }
+
+        warnings
+    }
+
     /// Sets label-associated metadata to all applicable objects in the datastore.
     pub async fn set_metadata(&self, mut con: DataStore, remote: &Remote) -> NetdoxResult<()> {
         for (label, meta) in &self.metadata {
@@ -224,3 +314,61 @@ impl RemoteConfig {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::RemoteConfig;
+    use crate::data::model::ObjectID;
+
+    #[test]
+    fn test_check() {
+        let cfg = RemoteConfig {
+            exclusions: HashSet::from(["web.com".to_string(), "gone.com".to_string()]),
+            locations: HashMap::from([
+                ("10.0.0.0/16".parse().unwrap(), "dc-1".to_string()),
+                ("10.0.1.0/24".parse().unwrap(), "dc-2".to_string()),
+                ("192.168.0.1/24".parse().unwrap(), "office".to_string()),
+            ]),
+            metadata: HashMap::from([
+                (
+                    "prod".to_string(),
+                    HashMap::from([("env".to_string(), "prod".to_string())]),
+                ),
+                ("unused".to_string(), HashMap::new()),
+            ]),
+        };
+        let qnames = HashSet::from([
+            "[net]web.com".to_string(),
+            "[net]10.0.1.5".to_string(),
+            "[net]192.168.0.9".to_string(),
+        ]);
+        let labeled = HashMap::from([(
+            "prod".to_string(),
+            vec![
+                ObjectID::DNS("[net]web.com".to_string()),
+                ObjectID::Node("old-node".to_string()),
+            ],
+        )]);
+
+        assert_eq!(
+            cfg.check(&qnames, &HashSet::new(), &labeled),
+            vec![
+                "Exclusion gone.com matches no DNS name in the data store.".to_string(),
+                format!(
+                    "Location subnet 10.0.1.0/24 (dc-2) is inside 10.0.0.0/16 (dc-1), \
+                    and addresses in it are located in {}.",
+                    cfg.locations[cfg.choose_subnet("10.0.1.0".parse().unwrap()).unwrap()]
+                ),
+                "Location subnet 192.168.0.1/24 (office) has host bits set, \
+                so it is the same as 192.168.0.0/24."
+                    .to_string(),
+                "Document labelled prod is for node old-node, which is not in the data store."
+                    .to_string(),
+                "Metadata for label unused sets no keys.".to_string(),
+                "Metadata for label unused applies to no documents on the remote.".to_string(),
+            ]
+        );
+    }
+}
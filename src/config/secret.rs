@@ -0,0 +1,215 @@
+use std::{
+    env,
+    fmt::Display,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use age::secrecy::SecretString;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config_err,
+    error::{NetdoxError, NetdoxResult},
+    io_err,
+};
+
+/// Environment variable the secret is read from if no other source is stored.
+pub const CFG_SECRET_VAR: &str = "NETDOX_SECRET";
+/// Added to the path of the encrypted config to get the path its secret source is stored at.
+const SOURCE_SUFFIX: &str = ".secret-source";
+
+/// Where the secret that encrypts the local config is read from.
+/// Stored in plain text next to the encrypted config, since it is needed to decrypt it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "source", rename_all = "kebab-case")]
+pub enum SecretSource {
+    /// The value of an environment variable.
+    Env { var: String },
+    /// The contents of a file, without a trailing newline.
+    File { path: PathBuf },
+    /// The output of a shell command, without a trailing newline,
+    /// like `pass show netdox` or `vault kv get -field=secret secret/netdox`.
+    Command { command: String },
+}
+
+impl Default for SecretSource {
+    fn default() -> Self {
+        SecretSource::Env {
+            var: CFG_SECRET_VAR.to_string(),
+        }
+    }
+}
+
+impl Display for SecretSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Env { var } => write!(f, "environment variable {var}"),
+            Self::File { path } => write!(f, "file {}", path.display()),
+            Self::Command { command } => write!(f, "command `{command}`"),
+        }
+    }
+}
+
+impl SecretSource {
+    /// Reads the secret from its source. Fails if it is empty.
+    pub fn secret(&self) -> NetdoxResult<SecretString> {
+        let secret = match self {
+            Self::Env { var } => match env::var(var) {
+                Ok(secret) => secret,
+                Err(err) => {
+                    return config_err!(format!("Failed to read environment variable {var}: {err}"))
+                }
+            },
+            Self::File { path } => match fs::read_to_string(path) {
+                Ok(secret) => secret.trim_end_matches(['\r', '\n']).to_string(),
+                Err(err) => {
+                    return config_err!(format!(
+                        "Failed to read secret from {}: {err}",
+                        path.display()
+                    ))
+                }
+            },
+            Self::Command { command } => match Command::new("sh").arg("-c").arg(command).output() {
+                Ok(output) if output.status.success() => match String::from_utf8(output.stdout) {
+                    Ok(secret) => secret.trim_end_matches(['\r', '\n']).to_string(),
+                    Err(err) => {
+                        return config_err!(format!(
+                            "Secret printed by `{command}` is not valid UTF-8: {err}"
+                        ))
+                    }
+                },
+                Ok(output) => {
+                    return config_err!(format!(
+                        "Secret command `{command}` failed with {}: {}",
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ))
+                }
+                Err(err) => {
+                    return config_err!(format!("Failed to run secret command `{command}`: {err}"))
+                }
+            },
+        };
+
+        if secret.is_empty() {
+            return config_err!(format!("The secret from the {self} is empty"));
+        }
+        Ok(SecretString::from(secret))
+    }
+
+    /// Returns the path the secret source of the config at a path is stored at.
+    fn path(cfg_path: &Path) -> PathBuf {
+        let mut path = cfg_path.as_os_str().to_owned();
+        path.push(SOURCE_SUFFIX);
+        PathBuf::from(path)
+    }
+
+    /// Reads the secret source stored for the config at a path,
+    /// or returns the default if there is none.
+    pub fn stored(cfg_path: &Path) -> NetdoxResult<Self> {
+        let path = Self::path(cfg_path);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                return io_err!(format!(
+                    "Failed to read secret source at {}: {err}",
+                    path.display()
+                ))
+            }
+        };
+
+        match toml::from_str(&content) {
+            Ok(source) => Ok(source),
+            Err(err) => config_err!(format!(
+                "Failed to parse secret source at {}: {err}",
+                path.display()
+            )),
+        }
+    }
+
+    /// Stores this as the secret source of the config at a path.
+    /// The default source is stored by removing any other.
+    pub fn store(&self, cfg_path: &Path) -> NetdoxResult<()> {
+        let path = Self::path(cfg_path);
+        if *self == Self::default() {
+            return match fs::remove_file(&path) {
+                Ok(()) => Ok(()),
+                Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+                Err(err) => io_err!(format!("Failed to remove {}: {err}", path.display())),
+            };
+        }
+
+        let content = match toml::to_string(self) {
+            Ok(content) => content,
+            Err(err) => return io_err!(format!("Failed to serialize secret source: {err}")),
+        };
+        match fs::write(&path, content) {
+            Ok(()) => Ok(()),
+            Err(err) => io_err!(format!(
+                "Failed to write secret source to {}: {err}",
+                path.display()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, fs, path::PathBuf};
+
+    use age::secrecy::ExposeSecret;
+
+    use super::SecretSource;
+
+    #[test]
+    fn test_secret_sources() {
+        let path = temp_dir().join(format!("netdox-secret-{}", std::process::id()));
+        fs::write(&path, "from-file\n").unwrap();
+        let file = SecretSource::File { path: path.clone() };
+        assert_eq!(file.secret().unwrap().expose_secret(), "from-file");
+
+        let command = SecretSource::Command {
+            command: "echo from-command".to_string(),
+        };
+        assert_eq!(command.secret().unwrap().expose_secret(), "from-command");
+        assert!(SecretSource::Command {
+            command: "exit 3".to_string()
+        }
+        .secret()
+        .is_err());
+        assert!(SecretSource::Command {
+            command: "true".to_string()
+        }
+        .secret()
+        .is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_stored_source() {
+        let cfg_path = temp_dir().join(format!("netdox-cfg-{}", std::process::id()));
+        assert_eq!(
+            SecretSource::stored(&cfg_path).unwrap(),
+            SecretSource::default()
+        );
+
+        let source = SecretSource::File {
+            path: PathBuf::from("/etc/netdox/secret"),
+        };
+        source.store(&cfg_path).unwrap();
+        assert_eq!(SecretSource::stored(&cfg_path).unwrap(), source);
+
+        // Storing the default removes the stored source.
+        SecretSource::default().store(&cfg_path).unwrap();
+        assert_eq!(
+            SecretSource::stored(&cfg_path).unwrap(),
+            SecretSource::default()
+        );
+        assert!(!SecretSource::path(&cfg_path).exists());
+    }
+}
@@ -0,0 +1,491 @@
+use std::{
+    env,
+    fs::{File, OpenOptions, TryLockError},
+    io::Write,
+    path::{Path, PathBuf},
+    process::{exit, Stdio},
+    str::FromStr,
+};
+
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, TimeDelta, Timelike};
+use tokio::{process::Command, time::sleep};
+use tracing::{error, info, warn};
+
+use crate::{
+    config::LocalConfig,
+    config_err,
+    error::{NetdoxError, NetdoxResult},
+    io_err, success,
+};
+
+/// Name of the lock file used when no path is given, in the temp directory.
+const LOCK_FILE: &str = "netdox-daemon.lock";
+/// Number of days to search for the next time a schedule matches.
+/// Long enough to find the 29th of February.
+const SEARCH_DAYS: i64 = 366 * 8;
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// Schedule
+
+/// A cron schedule with the five standard fields: minute, hour, day of month, month, and day of week.
+/// Fields can be a wildcard, a value, a range, a list, or any of those with a step like `*/4`.
+/// Months and days of the week can also be given by their first three letters.
+///
+/// As in cron, if both the day of month and day of week are restricted,
+/// a day matches if it matches either of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// Parses one field of a cron expression into a bitmask of the values it matches.
+/// Returns the mask and whether the field was a wildcard.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<(u64, bool), String> {
+    let value = |part: &str| -> Result<u32, String> {
+        let lower = part.to_lowercase();
+        if let Some(index) = names.iter().position(|name| *name == lower) {
+            return Ok(min + index as u32);
+        }
+        match part.parse::<u32>() {
+            Ok(value) if (min..=max).contains(&value) => Ok(value),
+            Ok(value) => Err(format!("{value} is not between {min} and {max}")),
+            Err(_) => Err(format!("{part} is not a valid value")),
+        }
+    };
+
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("{step} is not a valid step")),
+            },
+            None => (part, 1),
+        };
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (value(start)?, value(end)?)
+        } else if step > 1 {
+            // A single value with a step runs until the end of the range.
+            (value(range)?, max)
+        } else {
+            let value = value(range)?;
+            (value, value)
+        };
+
+        if start > end {
+            return Err(format!("{range} is not a valid range"));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+
+    Ok((mask, field == "*"))
+}
+
+impl FromStr for Schedule {
+    type Err = NetdoxError;
+
+    fn from_str(expr: &str) -> Result<Self, Self::Err> {
+        let expr = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+
+        let fields = expr.split_whitespace().collect::<Vec<_>>();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return config_err!(format!(
+                "Schedule \"{expr}\" must have five fields: minute, hour, day of month, month, and day of week."
+            ));
+        };
+
+        let parse = |field, min, max, names| {
+            parse_field(field, min, max, names).map_err(|err| {
+                NetdoxError::Config(format!(
+                    "Invalid field \"{field}\" in schedule \"{expr}\": {err}"
+                ))
+            })
+        };
+
+        let (weekdays, any_weekday) = parse(weekdays, 0, 7, &WEEKDAYS)?;
+        let (days, any_day) = parse(days, 1, 31, &[])?;
+        Ok(Schedule {
+            minutes: parse(minutes, 0, 59, &[])?.0,
+            hours: parse(hours, 0, 23, &[])?.0,
+            days,
+            months: parse(months, 1, 12, &MONTHS)?.0,
+            // Sunday is both 0 and 7.
+            weekdays: if weekdays & 1 << 7 == 0 {
+                weekdays
+            } else {
+                weekdays | 1
+            },
+            any_day,
+            any_weekday,
+        })
+    }
+}
+
+impl Schedule {
+    /// Returns true if the schedule matches any time on a date.
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        if self.months & 1 << date.month() == 0 {
+            return false;
+        }
+
+        let day = self.days & 1 << date.day() != 0;
+        let weekday = self.weekdays & 1 << date.weekday().num_days_from_sunday() != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// Returns the first time after some time that the schedule matches,
+    /// or none if it never matches, like on the 31st of February.
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let first = after.with_second(0)?.with_nanosecond(0)? + TimeDelta::minutes(1);
+        for offset in 0..SEARCH_DAYS {
+            let date = first.date() + TimeDelta::days(offset);
+            if !self.matches_date(date) {
+                continue;
+            }
+
+            for hour in 0..24 {
+                if self.hours & 1 << hour == 0 {
+                    continue;
+                }
+                for minute in 0..60 {
+                    if self.minutes & 1 << minute == 0 {
+                        continue;
+                    }
+                    let time = date.and_hms_opt(hour, minute, 0)?;
+                    if time >= first {
+                        return Some(time);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns the first local time after now that the schedule matches.
+    /// Times that are skipped when clocks go forward are skipped by the schedule too.
+    fn next_local(&self) -> Option<chrono::DateTime<Local>> {
+        let mut after = Local::now().naive_local();
+        loop {
+            let next = self.next_after(after)?;
+            if let Some(time) = next.and_local_timezone(Local).earliest() {
+                if time > Local::now() {
+                    return Some(time);
+                }
+            }
+            after = next;
+        }
+    }
+}
+
+// Lock
+
+/// Takes an exclusive lock on a file that is held until the returned file is dropped,
+/// or the process exits.
+fn lock(path: &Path) -> NetdoxResult<File> {
+    let mut file = match OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+    {
+        Ok(file) => file,
+        Err(err) => {
+            return io_err!(format!(
+                "Failed to open lock file {}: {err}",
+                path.display()
+            ))
+        }
+    };
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            return io_err!(format!(
+                "Another netdox daemon holds the lock file {}.",
+                path.display()
+            ))
+        }
+        Err(TryLockError::Error(err)) => {
+            return io_err!(format!("Failed to lock {}: {err}", path.display()))
+        }
+    }
+
+    // The PID is only for the curious; the lock is what matters.
+    if let Err(err) = file
+        .set_len(0)
+        .and_then(|_| writeln!(file, "{}", std::process::id()))
+    {
+        warn!("Failed to write PID to lock file {}: {err}", path.display());
+    }
+
+    Ok(file)
+}
+
+// Daemon
+
+/// Waits until the process is asked to stop.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = term.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Err(err) => {
+                warn!("Failed to listen for SIGTERM: {err}");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Runs netdox as a child process with some arguments, and returns true if it succeeded.
+/// Sets stopping if a shutdown signal is received in the meantime,
+/// in which case the child is left to finish unless a second signal is received.
+async fn run_child(args: &[String], stopping: &mut bool) -> NetdoxResult<bool> {
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(err) => return io_err!(format!("Failed to find the netdox executable: {err}")),
+    };
+
+    let mut command = Command::new(exe);
+    command.args(args).stdin(Stdio::null()).kill_on_drop(true);
+    // Keeps the child out of the terminal's process group, so interrupting the daemon
+    // does not interrupt the run.
+    #[cfg(unix)]
+    command.process_group(0);
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) => return io_err!(format!("Failed to start netdox {}: {err}", args[0])),
+    };
+
+    let status = loop {
+        tokio::select! {
+            status = child.wait() => break status,
+            _ = shutdown_signal() => {
+                if *stopping {
+                    warn!("Stopping netdox {} before it has finished.", args[0]);
+                    if let Err(err) = child.kill().await {
+                        error!("Failed to stop netdox {}: {err}", args[0]);
+                    }
+                } else {
+                    info!("Waiting for netdox {} to finish before stopping. Signal again to stop it now.", args[0]);
+                    *stopping = true;
+                }
+            }
+        }
+    };
+
+    match status {
+        Ok(status) => Ok(status.success()),
+        Err(err) => io_err!(format!("Failed to wait for netdox {}: {err}", args[0])),
+    }
+}
+
+/// Runs an update and then a publish with some global arguments.
+/// The publish is skipped if the update fails.
+async fn run_cycle(global: &[String], stopping: &mut bool) {
+    for cmd in ["update", "publish"] {
+        let args = [vec![cmd.to_string()], global.to_vec()].concat();
+        match run_child(&args, stopping).await {
+            Ok(true) => {}
+            Ok(false) => {
+                error!("Scheduled {cmd} failed.");
+                return;
+            }
+            Err(err) => {
+                error!("{err}");
+                return;
+            }
+        }
+    }
+    success!("Finished scheduled update and publish.");
+}
+
+/// Runs updates and publishes on the schedule in the config until a shutdown signal is received.
+/// Global is the arguments to pass to each run, like --debug.
+#[tokio::main]
+pub async fn daemon(global: Vec<String>, lock_file: Option<PathBuf>, run_now: bool) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to start daemon: {err}");
+            exit(1);
+        }
+    };
+
+    if cfg.is_read_only() {
+        error!("Cannot run the daemon in read-only mode.");
+        exit(1);
+    }
+
+    let schedule = match &cfg.schedule {
+        Some(expr) => match Schedule::from_str(expr) {
+            Ok(schedule) => schedule,
+            Err(err) => {
+                error!("{err}");
+                exit(1);
+            }
+        },
+        None => {
+            error!(
+                "Set a schedule in the config to run the daemon, like: schedule = \"0 */4 * * *\""
+            );
+            exit(1);
+        }
+    };
+
+    let lock_path = lock_file.unwrap_or_else(|| env::temp_dir().join(LOCK_FILE));
+    let _lock = match lock(&lock_path) {
+        Ok(lock) => lock,
+        Err(err) => {
+            error!("{err}");
+            exit(1);
+        }
+    };
+
+    info!(
+        "Started daemon with schedule {:?}",
+        cfg.schedule.unwrap_or_default()
+    );
+    let mut stopping = false;
+    if run_now {
+        run_cycle(&global, &mut stopping).await;
+    }
+
+    while !stopping {
+        let Some(next) = schedule.next_local() else {
+            error!("Schedule never matches a time.");
+            exit(1);
+        };
+        info!("Next update at {}", next.format("%Y-%m-%d %H:%M %Z"));
+
+        let wait = (next - Local::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = sleep(wait) => {}
+            _ = shutdown_signal() => break,
+        }
+
+        // Times missed while a cycle was running are skipped, as the next time is found after it ends.
+        run_cycle(&global, &mut stopping).await;
+    }
+
+    info!("Stopped daemon.");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::NaiveDateTime;
+
+    use super::{lock, Schedule};
+
+    fn time(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn next(expr: &str, after: &str) -> Option<NaiveDateTime> {
+        Schedule::from_str(expr).unwrap().next_after(time(after))
+    }
+
+    #[test]
+    fn test_schedule_next() {
+        assert_eq!(
+            next("0 */4 * * *", "2024-03-01 05:30"),
+            Some(time("2024-03-01 08:00"))
+        );
+        assert_eq!(
+            next("0 */4 * * *", "2024-03-01 20:00"),
+            Some(time("2024-03-02 00:00"))
+        );
+        assert_eq!(
+            next("*/15 9-17 * * mon-fri", "2024-03-01 17:50"),
+            Some(time("2024-03-04 09:00"))
+        );
+        assert_eq!(
+            next("30 2 1,15 * *", "2024-03-01 02:30"),
+            Some(time("2024-03-15 02:30"))
+        );
+        // Either the day of month or the day of week matches when both are restricted.
+        assert_eq!(
+            next("0 0 13 * 5", "2024-03-01 00:00"),
+            Some(time("2024-03-08 00:00"))
+        );
+        assert_eq!(
+            next("0 0 * * 7", "2024-03-01 00:00"),
+            Some(time("2024-03-03 00:00"))
+        );
+        assert_eq!(
+            next("@yearly", "2024-03-01 00:00"),
+            Some(time("2025-01-01 00:00"))
+        );
+        assert_eq!(
+            next("0 0 29 feb *", "2024-03-01 00:00"),
+            Some(time("2028-02-29 00:00"))
+        );
+        assert_eq!(next("0 0 31 2 *", "2024-03-01 00:00"), None);
+    }
+
+    #[test]
+    fn test_schedule_invalid() {
+        for expr in [
+            "0 */4 * *",
+            "60 * * * *",
+            "* 5-2 * * *",
+            "*/0 * * * *",
+            "* * * smarch *",
+            "* * 0 * *",
+        ] {
+            assert!(
+                Schedule::from_str(expr).is_err(),
+                "{expr} should be invalid"
+            );
+        }
+    }
+
+    #[test]
+    fn test_lock() {
+        let path = std::env::temp_dir().join(format!("netdox-test-{}.lock", std::process::id()));
+        let held = lock(&path).unwrap();
+        assert!(lock(&path).is_err());
+        drop(held);
+        assert!(lock(&path).is_ok());
+        std::fs::remove_file(path).unwrap();
+    }
+}
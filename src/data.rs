@@ -1,5 +1,6 @@
 pub mod model;
 pub mod store;
+pub mod stream;
 #[cfg(test)]
 mod tests;
 
@@ -1,12 +1,17 @@
 use std::{
-    collections::{hash_map::Entry, HashMap, HashSet},
+    cmp::Reverse,
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
     fmt::Display,
     hash::Hash,
+    net::{IpAddr, Ipv6Addr},
 };
 
 use indexmap::IndexMap;
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
 use itertools::Itertools;
 use redis::{FromRedisValue, ParsingError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use crate::{
     error::{NetdoxError, NetdoxResult},
@@ -20,13 +25,190 @@ pub const DNS_KEY: &str = "dns";
 pub const NODES_KEY: &str = "nodes";
 pub const PROC_NODES_KEY: &str = "proc_nodes";
 pub const PROC_NODE_REVS_KEY: &str = "proc_node_revs";
+pub const DNS_NODES_KEY: &str = "dns_nodes";
+pub const PROC_VERSION_KEY: &str = "proc_version";
+pub const PROC_STAGING_KEY: &str = "proc_staging";
 pub const REPORTS_KEY: &str = "reports";
 pub const PDATA_KEY: &str = "pdata";
 pub const METADATA_KEY: &str = "meta";
+pub const PLUGIN_DISPLAY_KEY: &str = "plugin_display";
+pub const NETWORKS_KEY: &str = "networks";
+pub const DNS_IGNORE_PATTERNS_KEY: &str = "dns_ignore;patterns";
+pub const NETWORK_SUFFIXES_KEY: &str = "network_suffixes";
+pub const RUNS_KEY: &str = "runs";
+pub const NOTES_KEY: &str = "notes";
+pub const ROLES_KEY: &str = "roles";
+pub const TAGS_KEY: &str = "tags";
+pub const PUBLISH_QUEUE_KEY: &str = "publish_queue";
+pub const PUBLISH_CHECKPOINT_KEY: &str = "publish_checkpoint";
+pub const PUBLISH_CURSORS_KEY: &str = "publish_cursors";
+pub const REMOTE_CURSORS_KEY: &str = "remote_cursors";
+pub const FRAGMENT_IDS_KEY: &str = "fragment_ids";
+pub const PROCESS_RUN_KEY: &str = "process_run";
+pub const RUN_LOCK_KEY: &str = "run_lock";
+pub const DEPENDENCIES_KEY: &str = "dependencies";
+pub const DAMPENING_KEY: &str = "dampening";
+pub const SUBNETS_KEY: &str = "subnets";
+pub const DEFERRED_PLUGINS_KEY: &str = "deferred_plugins";
+pub const PLUGIN_SCOPES_KEY: &str = "plugin_scopes";
+pub const SCOPE_VIOLATIONS_KEY: &str = "scope_violations";
+pub const PLUGIN_CURSORS_KEY: &str = "plugin_cursors";
+pub const CONFLICT_POLICY_KEY: &str = "conflict_policy";
+pub const SERVICES_KEY: &str = "services";
+pub const RELATIONS_KEY: &str = "relations";
+
+/// Maximum number of run records to keep in the data store.
+pub const MAX_RUNS: usize = 100;
+
+/// Version of the layout of keys in the data store.
+/// Increases whenever plugins that write keys themselves would need to change.
+pub const SCHEMA_VERSION: u32 = 1;
 
 pub const LOCATIONS_PLUGIN: &str = "locations";
 pub const LOCATIONS_META_KEY: &str = "location";
 
+/// DNS metadata key that holds a link to the node a DNS name resolves to.
+pub const NODE_META_KEY: &str = "node";
+/// DNS metadata key that holds the link ID of the node a DNS name resolves to.
+pub const NODE_ID_META_KEY: &str = "_node";
+
+/// Metadata key that holds the role of a node.
+pub const ROLE_META_KEY: &str = "role";
+
+/// Metadata key that adds a comma separated list of tags to an object instead of being stored as metadata.
+pub const TAGS_META_KEY: &str = "tags";
+
+/// ID of plugin data on a node that holds its status, as a hash with a `state` field.
+/// It is displayed as a banner at the top of the node's document.
+pub const STATUS_DATA_ID: &str = "status";
+/// Field of the status data that holds the state of the node.
+pub const STATUS_STATE_FIELD: &str = "state";
+
+/// Groups metadata by the plugin that last changed each key, ordered by plugin name.
+/// Keys with no recorded source are grouped under `None`, which comes first.
+pub fn group_metadata(
+    metadata: HashMap<String, String>,
+    sources: &HashMap<String, String>,
+) -> BTreeMap<Option<String>, HashMap<String, String>> {
+    let mut groups: BTreeMap<Option<String>, HashMap<String, String>> = BTreeMap::new();
+    for (key, value) in metadata {
+        groups
+            .entry(sources.get(&key).cloned())
+            .or_default()
+            .insert(key, value);
+    }
+    groups
+}
+
+/// A metadata value along with the plugin that last changed it and when.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MetadataProvenance {
+    pub value: String,
+    /// Plugin that last changed the value, if it is known.
+    pub plugin: Option<String>,
+    /// Unix timestamp of when the value last changed, if it is known.
+    /// Values written before change times were recorded have none.
+    pub changed: Option<u64>,
+}
+
+/// Combines the metadata, sources and change times stored for a single object.
+pub fn metadata_provenance(
+    metadata: HashMap<String, String>,
+    sources: &HashMap<String, String>,
+    changed: &HashMap<String, String>,
+) -> HashMap<String, MetadataProvenance> {
+    metadata
+        .into_iter()
+        .map(|(key, value)| {
+            let provenance = MetadataProvenance {
+                value,
+                plugin: sources.get(&key).cloned(),
+                changed: changed.get(&key).and_then(|time| time.parse().ok()),
+            };
+            (key, provenance)
+        })
+        .collect()
+}
+
+/// Values from more than one plugin for the same field of a processed node.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// Link ID of the processed node.
+    pub link_id: String,
+    /// Metadata key, or none for the name of the node.
+    pub key: Option<String>,
+    /// Value the conflict policy chose.
+    pub chosen: MetadataProvenance,
+    /// Values that differ from the chosen one, in the order they were merged in.
+    pub rejected: Vec<MetadataProvenance>,
+}
+
+impl Conflict {
+    /// Returns the name of the field, for logs and reports.
+    pub fn field(&self) -> &str {
+        self.key.as_deref().unwrap_or("name")
+    }
+}
+
+/// Merges the metadata of the raw nodes a processed node was built from,
+/// choosing between differing values for a key with the conflict policy,
+/// and then the metadata set on the processed node itself, which overrides both.
+/// Raw nodes are merged in order of their ID, so the result never depends on the order they were read in.
+/// Returns the merged metadata and the conflicts between raw nodes.
+pub fn merge_metadata(
+    node: &Node,
+    mut raw: Vec<(String, HashMap<String, MetadataProvenance>)>,
+    proc: HashMap<String, MetadataProvenance>,
+    policy: &ConflictPolicy,
+) -> (HashMap<String, MetadataProvenance>, Vec<Conflict>) {
+    raw.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut candidates: BTreeMap<String, Vec<MetadataProvenance>> = BTreeMap::new();
+    for (_, metadata) in raw {
+        for (key, meta) in metadata {
+            let values = candidates.entry(key).or_default();
+            if !values.contains(&meta) {
+                values.push(meta);
+            }
+        }
+    }
+
+    let mut merged = HashMap::new();
+    let mut conflicts = vec![];
+    for (key, mut values) in candidates {
+        let chosen = values.remove(policy.choose(&values));
+        values.retain(|meta| meta.value != chosen.value);
+        if !proc.contains_key(&key) && !values.is_empty() {
+            conflicts.push(Conflict {
+                link_id: node.link_id.clone(),
+                key: Some(key.clone()),
+                chosen: chosen.clone(),
+                rejected: values,
+            });
+        }
+        merged.insert(key, chosen);
+    }
+    merged.extend(proc);
+
+    (merged, conflicts)
+}
+
+/// Splits metadata with its provenance into the values and the plugins that last changed them.
+#[cfg(feature = "pageseeder")]
+pub fn split_provenance(
+    provenance: HashMap<String, MetadataProvenance>,
+) -> (HashMap<String, String>, HashMap<String, String>) {
+    let mut metadata = HashMap::new();
+    let mut sources = HashMap::new();
+    for (key, meta) in provenance {
+        if let Some(plugin) = meta.plugin {
+            sources.insert(key.clone(), plugin);
+        }
+        metadata.insert(key, meta.value);
+    }
+    (metadata, sources)
+}
+
 #[allow(clippy::upper_case_acronyms)]
 /// An ID for each object that creates a document.
 pub enum ObjectID {
@@ -37,7 +219,225 @@ pub enum ObjectID {
 
 // DNS
 
-#[derive(Debug)]
+/// Splits a qualified DNS name into its network and unqualified name.
+/// Returns None if the name is not qualified.
+pub fn split_qname(qname: &str) -> Option<(&str, &str)> {
+    let (network, name) = qname.split_once(']')?;
+    Some((network.strip_prefix('[')?, name))
+}
+
+/// Returns true if an entry in the DNS ignore list is a glob pattern instead of a name.
+pub fn is_ignore_pattern(entry: &str) -> bool {
+    entry.contains(['*', '?'])
+}
+
+/// Returns true if a glob pattern from the DNS ignore list matches a qualified DNS name,
+/// or the name without its network. `*` matches any run of characters and `?` any one character.
+/// Case is ignored. This must match `is_ignored` in the Redis function library.
+pub fn matches_ignore_pattern(pattern: &str, qname: &str) -> bool {
+    let pattern = pattern.to_lowercase().chars().collect::<Vec<_>>();
+    let qname = qname.to_lowercase();
+    glob_matches(&pattern, &qname.chars().collect::<Vec<_>>())
+        || split_qname(&qname)
+            .is_some_and(|(_, name)| glob_matches(&pattern, &name.chars().collect::<Vec<_>>()))
+}
+
+fn glob_matches(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position in the pattern after the last star, and in the text it has matched up to.
+    let mut star = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p + 1, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // Let the star match one more character.
+            p = star_p;
+            t = star_t + 1;
+            star = Some((star_p, t));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Returns the address if the unqualified part of a DNS name is an IPv4 or IPv6 address.
+pub fn qname_address(qname: &str) -> Option<IpAddr> {
+    split_qname(qname)?.1.parse().ok()
+}
+
+/// Returns the canonical form of an IPv6 address as described in RFC 5952, e.g. `2001:db8::1`.
+/// Returns None if the name is not an IPv6 address in hexadecimal notation.
+/// This must match `canonical_ipv6` in the Redis function library.
+pub fn canonical_ipv6(name: &str) -> Option<String> {
+    if name.contains('.') {
+        return None;
+    }
+    let segments = name.parse::<Ipv6Addr>().ok()?.segments();
+
+    // Longest run of at least two zero segments, and the first if there is a tie.
+    let (mut best_start, mut best_len) = (0, 1);
+    let (mut start, mut len) = (0, 0);
+    for (i, segment) in segments.iter().enumerate() {
+        if *segment == 0 {
+            if len == 0 {
+                start = i;
+            }
+            len += 1;
+            if len > best_len {
+                (best_start, best_len) = (start, len);
+            }
+        } else {
+            len = 0;
+        }
+    }
+
+    let hex = |segments: &[u16]| segments.iter().map(|s| format!("{s:x}")).join(":");
+    if best_len < 2 {
+        Some(hex(&segments))
+    } else {
+        Some(format!(
+            "{}::{}",
+            hex(&segments[..best_start]),
+            hex(&segments[best_start + best_len..])
+        ))
+    }
+}
+
+/// Returns the first `bits` bits of an IPv6 address as reversed nibbles, e.g. `8.b.d.0.1.0.0.2`.
+fn reverse_nibbles(addr: &Ipv6Addr, bits: usize) -> String {
+    let nibbles = addr
+        .octets()
+        .iter()
+        .flat_map(|octet| [octet >> 4, octet & 0xf])
+        .take(bits / 4)
+        .collect::<Vec<_>>();
+    nibbles
+        .iter()
+        .rev()
+        .map(|nibble| format!("{nibble:x}"))
+        .join(".")
+}
+
+/// Returns the name a PTR record for the address would have,
+/// e.g. `1.0.0.10.in-addr.arpa` or `1.0.0.0.[...].8.b.d.0.1.0.0.2.ip6.arpa`.
+pub fn ptr_name(addr: &IpAddr) -> String {
+    match addr {
+        IpAddr::V4(addr) => {
+            let [a, b, c, d] = addr.octets();
+            format!("{d}.{c}.{b}.{a}.in-addr.arpa")
+        }
+        IpAddr::V6(addr) => format!("{}.ip6.arpa", reverse_nibbles(addr, 128)),
+    }
+}
+
+/// Returns the reverse DNS zone of the subnet the address is in,
+/// e.g. `0.0.10.in-addr.arpa` or `0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa`.
+pub fn reverse_zone(addr: &IpAddr) -> String {
+    match addr {
+        IpAddr::V4(addr) => {
+            let [a, b, c, _] = addr.octets();
+            format!("{c}.{b}.{a}.in-addr.arpa")
+        }
+        IpAddr::V6(addr) => format!("{}.ip6.arpa", reverse_nibbles(addr, 64)),
+    }
+}
+
+/// Returns the subnet of the address's reverse zone: its /24 for IPv4 and its /64 for IPv6.
+pub fn address_subnet(addr: &IpAddr) -> IpNet {
+    match addr {
+        IpAddr::V4(addr) => Ipv4Net::new(*addr, 24)
+            .expect("/24 is a valid prefix length")
+            .trunc()
+            .into(),
+        IpAddr::V6(addr) => Ipv6Net::new(*addr, 64)
+            .expect("/64 is a valid prefix length")
+            .trunc()
+            .into(),
+    }
+}
+
+/// An address in a subnet, and the processed node it resolves to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubnetMember {
+    /// Qualified DNS name of the address.
+    pub qname: String,
+    /// Link ID of the processed node the address resolves to, if any.
+    pub node: Option<String>,
+}
+
+/// A subnet in one logical network and the addresses in it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subnet {
+    pub network: String,
+    pub net: IpNet,
+    /// Whether the subnet is configured, rather than derived from the addresses in it.
+    pub configured: bool,
+    /// Addresses in the subnet, in order.
+    pub members: Vec<SubnetMember>,
+}
+
+/// Returns the subnet an address is grouped into: the smallest configured subnet containing it,
+/// or its reverse zone subnet if there is none, and whether it was configured.
+pub fn containing_subnet(addr: &IpAddr, configured: &[IpNet]) -> (IpNet, bool) {
+    match configured
+        .iter()
+        .filter(|net| net.contains(addr))
+        .max_by_key(|net| net.prefix_len())
+    {
+        Some(net) => (*net, true),
+        None => (address_subnet(addr), false),
+    }
+}
+
+/// Addresses in a subnet and the DNS names they came from, keyed by network and subnet.
+type SubnetGroups<'a> = BTreeMap<(&'a str, IpNet), (bool, Vec<(IpAddr, &'a String)>)>;
+
+/// Groups the DNS names that are addresses into subnets within each network,
+/// ordered by network and then subnet.
+/// Maps qualified DNS names to the link ID of the node they resolve to.
+pub fn group_subnets(
+    qnames: &HashSet<String>,
+    configured: &[IpNet],
+    dns_nodes: &HashMap<String, String>,
+) -> Vec<Subnet> {
+    let mut subnets: SubnetGroups = BTreeMap::new();
+    for qname in qnames {
+        let (Some((network, _)), Some(addr)) = (split_qname(qname), qname_address(qname)) else {
+            continue;
+        };
+        let (net, is_configured) = containing_subnet(&addr, configured);
+        let (_, members) = subnets
+            .entry((network, net))
+            .or_insert((is_configured, vec![]));
+        members.push((addr, qname));
+    }
+
+    subnets
+        .into_iter()
+        .map(|((network, net), (configured, mut members))| {
+            members.sort();
+            Subnet {
+                network: network.to_string(),
+                net,
+                configured,
+                members: members
+                    .into_iter()
+                    .map(|(_, qname)| SubnetMember {
+                        qname: qname.clone(),
+                        node: dns_nodes.get(qname).cloned(),
+                    })
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, PartialEq, Eq)]
 #[allow(clippy::upper_case_acronyms)]
 /// A set of DNS records and network translations.
 pub struct DNS {
@@ -81,7 +481,7 @@ impl DNS {
 
         for record in self.get_records(name) {
             match record.rtype.as_str() {
-                "A" | "CNAME" | "PTR" | "NAT" => {
+                "A" | "AAAA" | "CNAME" | "PTR" | "NAT" => {
                     superset.extend(self.recurse_dns_superset(&record.value, seen)?);
                 }
                 _ => {}
@@ -170,6 +570,34 @@ impl DNS {
 
     // SETTERS
 
+    /// Adds all the names, records and translations of another set of DNS data.
+    pub fn extend(&mut self, other: DNS) {
+        self.qnames.extend(other.qnames);
+        for (qname, records) in other.records {
+            self.records.entry(qname).or_default().extend(records);
+        }
+        for (qname, dests) in other.net_translations {
+            self.net_translations
+                .entry(qname)
+                .or_default()
+                .extend(dests);
+        }
+        for (qname, implied) in other.implied_records {
+            self.implied_records
+                .entry(qname)
+                .or_default()
+                .extend(implied);
+        }
+    }
+
+    pub fn add_translation(&mut self, origin: &str, dest: &str) {
+        self.qnames.insert(origin.to_string());
+        self.net_translations
+            .entry(origin.to_string())
+            .or_default()
+            .insert(dest.to_string());
+    }
+
     pub fn add_record(&mut self, record: DNSRecord) {
         self.qnames.insert(record.name.clone());
         if let Some(implied) = record.clone().implies() {
@@ -204,13 +632,16 @@ pub struct DNSRecord {
     pub plugin: String,
 }
 
-pub const ADDRESS_RTYPES: [&str; 4] = ["A", "PTR", "CNAME", "NAT"];
+pub const ADDRESS_RTYPES: [&str; 5] = ["A", "AAAA", "PTR", "CNAME", "NAT"];
 
 impl DNSRecord {
     pub fn implies(&self) -> Option<ImpliedDNSRecord> {
         let new_rtype = match self.rtype.as_str() {
-            "A" => "PTR".to_string(),
-            "PTR" => "A".to_string(),
+            "A" | "AAAA" => "PTR".to_string(),
+            "PTR" => match qname_address(&self.name) {
+                Some(IpAddr::V6(_)) => "AAAA".to_string(),
+                _ => "A".to_string(),
+            },
             "CNAME" => self.rtype.clone(),
             _ => return None,
         };
@@ -279,7 +710,7 @@ impl DNSRecords {
 
 // Nodes
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 /// An unprocessed node.
 pub struct RawNode {
     pub name: Option<String>,
@@ -335,6 +766,630 @@ pub struct Node {
     pub raw_ids: HashSet<String>,
 }
 
+/// The data shown in the document of a DNS name, read from the data store together.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DnsBundle {
+    /// The DNS data reachable from the name.
+    pub dns: DNS,
+    pub tags: HashSet<String>,
+    pub metadata: HashMap<String, MetadataProvenance>,
+    pub notes: HashMap<String, String>,
+    pub pdata: Vec<Data>,
+    /// Display settings of every plugin that has any.
+    pub displays: HashMap<String, PluginDisplay>,
+}
+
+/// The data shown in the document of a processed node, read from the data store together.
+#[derive(Debug, PartialEq, Eq)]
+pub struct NodeBundle {
+    pub tags: HashSet<String>,
+    pub metadata: HashMap<String, MetadataProvenance>,
+    pub notes: HashMap<String, String>,
+    pub dependencies: Vec<Dependency>,
+    pub relations: Vec<Relation>,
+    pub services: Vec<Service>,
+    pub pdata: Vec<Data>,
+    /// Display settings of every plugin that has any.
+    pub displays: HashMap<String, PluginDisplay>,
+}
+
+/// Returns the form of an alt name used to compare it with others.
+fn alt_name_key(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_lowercase()
+}
+
+impl Node {
+    /// Returns the alt names of this node with near-duplicates removed.
+    /// Names are trimmed and lose any trailing dot, and names that only differ by case
+    /// are merged, preferring the lowercase one. Names that match the node name are removed,
+    /// as are short names when the node is also known by an FQDN that starts with them.
+    pub fn normalised_alt_names(&self) -> HashSet<String> {
+        let mut variants: HashMap<String, String> = HashMap::new();
+        for name in &self.alt_names {
+            let name = name.trim().trim_end_matches('.');
+            let key = name.to_lowercase();
+            if key.is_empty() {
+                continue;
+            }
+
+            let preferred = match variants.get(&key) {
+                Some(existing) if existing.as_str() == key => existing.clone(),
+                Some(existing) if name != key && existing.as_str() < name => existing.clone(),
+                _ => name.to_string(),
+            };
+            variants.insert(key, preferred);
+        }
+
+        let name_key = alt_name_key(&self.name);
+        let fqdns = variants
+            .keys()
+            .chain([&name_key])
+            .filter(|key| key.contains('.'))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        variants
+            .into_iter()
+            .filter(|(key, _)| *key != name_key)
+            .filter(|(key, _)| {
+                key.contains('.')
+                    || key.contains(char::is_whitespace)
+                    || !fqdns.iter().any(|fqdn| {
+                        fqdn.strip_prefix(key.as_str())
+                            .is_some_and(|rest| rest.starts_with('.'))
+                    })
+            })
+            .map(|(_, name)| name)
+            .collect()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+/// A directed dependency of one processed node on another.
+pub struct Dependency {
+    /// Link ID of the node that depends on the other.
+    pub source: String,
+    /// Link ID of the node that is depended on.
+    pub dest: String,
+    /// Describes the dependency, like "database".
+    pub label: Option<String>,
+}
+
+impl Dependency {
+    /// Creates a dependency, treating an empty label as no label.
+    pub fn new(source: &str, dest: &str, label: String) -> Self {
+        Dependency {
+            source: source.to_string(),
+            dest: dest.to_string(),
+            label: Some(label).filter(|label| !label.is_empty()),
+        }
+    }
+
+    /// Returns the link ID of the node at the other end of the dependency from the given node.
+    pub fn other(&self, link_id: &str) -> &str {
+        if self.source == link_id {
+            &self.dest
+        } else {
+            &self.source
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+/// A typed relationship from one processed node to another.
+pub struct Relation {
+    /// Link ID of the node the relationship is from.
+    pub source: String,
+    /// Link ID of the node the relationship is to.
+    pub dest: String,
+    /// Type of the relationship, like "vm-on-host".
+    pub kind: String,
+}
+
+impl Relation {
+    /// Parses a relation from a member of the relations of a node, like `vm-on-host;${LINK_ID}`.
+    /// If `incoming` is true, the link ID in the member is the source instead of the destination.
+    pub fn parse(link_id: &str, member: &str, incoming: bool) -> Option<Self> {
+        let (kind, other) = member.split_once(';')?;
+        let (source, dest) = if incoming {
+            (other, link_id)
+        } else {
+            (link_id, other)
+        };
+
+        Some(Relation {
+            source: source.to_string(),
+            dest: dest.to_string(),
+            kind: kind.to_string(),
+        })
+    }
+
+    /// Returns the link ID of the node at the other end of the relation from the given node.
+    pub fn other(&self, link_id: &str) -> &str {
+        if self.source == link_id {
+            &self.dest
+        } else {
+            &self.source
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+/// A service that a processed node exposes on a port.
+pub struct Service {
+    /// Link ID of the node exposing the service.
+    pub link_id: String,
+    pub port: u16,
+    /// Lowercase name of the protocol, like "tcp".
+    pub protocol: String,
+    /// Details about the service, like its product and version, from every plugin that declared it.
+    pub details: HashMap<String, String>,
+    /// Plugins that declared the service.
+    pub plugins: HashSet<String>,
+}
+
+impl Service {
+    /// Returns the ID of the service on the node, like "443/tcp".
+    pub fn id(&self) -> String {
+        format!("{}/{}", self.port, self.protocol)
+    }
+
+    /// Parses the port and protocol from the ID of a service.
+    pub fn parse_id(id: &str) -> Option<(u16, String)> {
+        let (port, protocol) = id.split_once('/')?;
+        Some((port.parse().ok()?, protocol.to_string()))
+    }
+}
+
+// Indexes
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+/// Number of entries fixed in each derived index by a rebuild.
+pub struct IndexRepairs {
+    /// Entries in the map of DNS names to processed nodes.
+    pub dns_nodes: usize,
+    /// Entries in the map of raw node IDs to processed nodes.
+    pub proc_node_revs: usize,
+    /// Members of the sets of DNS names with records implying each DNS name.
+    pub dns_implied: usize,
+    /// Members of the sets of plugin data IDs for each object.
+    pub pdata: usize,
+}
+
+impl IndexRepairs {
+    pub fn total(&self) -> usize {
+        self.dns_nodes + self.proc_node_revs + self.dns_implied + self.pdata
+    }
+}
+
+/// Metadata moved from the legacy key layout by a migration.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct MetadataMigration {
+    /// Pairs of legacy and new object IDs.
+    pub moved: Vec<(String, String)>,
+    /// Legacy object IDs that matched no object, or both a DNS name and a node.
+    pub unresolved: Vec<String>,
+}
+
+/// DNS names and raw nodes moved from one network to another.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct NetworkRename {
+    /// Pairs of old and new qualified DNS names.
+    pub dns_names: Vec<(String, String)>,
+    /// Pairs of old and new raw node IDs.
+    pub nodes: Vec<(String, String)>,
+}
+
+/// Objects removed or changed by deleting a DNS name.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct DnsDeletion {
+    /// Records of the DNS name.
+    pub records: Vec<DNSRecord>,
+    /// Records on other DNS names whose value is the DNS name.
+    pub references: Vec<DNSRecord>,
+    /// Pairs of raw node ID and the plugin of the raw nodes that used the DNS name.
+    pub nodes: Vec<(String, String)>,
+    /// Link IDs of processed nodes the DNS name was removed from.
+    pub detached: Vec<String>,
+    /// Link IDs of processed nodes left with no raw nodes.
+    pub proc_nodes: Vec<String>,
+}
+
+impl DnsDeletion {
+    /// Returns true if anything other than the DNS name and its own records uses it.
+    pub fn is_referenced(&self) -> bool {
+        !self.references.is_empty()
+            || !self.nodes.is_empty()
+            || !self.detached.is_empty()
+            || !self.proc_nodes.is_empty()
+    }
+}
+
+/// Objects removed because their plugins stopped reporting them.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Prune {
+    /// DNS records that were not reported since the cutoff.
+    pub records: Vec<DNSRecord>,
+    /// Pairs of raw node ID and the plugin whose node was not reported since the cutoff.
+    pub nodes: Vec<(String, String)>,
+    /// Qualified DNS names left with no records, nodes, or references.
+    pub dns_names: Vec<String>,
+    /// Link IDs of processed nodes left with no raw nodes.
+    pub proc_nodes: Vec<String>,
+}
+
+impl Prune {
+    /// Returns true if nothing was removed.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+            && self.nodes.is_empty()
+            && self.dns_names.is_empty()
+            && self.proc_nodes.is_empty()
+    }
+}
+
+// Runs
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// The command that performed a run.
+pub enum RunKind {
+    Update,
+    Publish,
+}
+
+impl Display for RunKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Update => write!(f, "update"),
+            Self::Publish => write!(f, "publish"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+/// Duration of one part of a run, such as a plugin or a publishing phase.
+pub struct RunTiming {
+    pub name: String,
+    pub millis: u64,
+    /// False if this part of the run failed.
+    pub success: bool,
+    /// Whether this part of the run was skipped, like a plugin stage with no upstream changes.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skipped: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+/// Record of the timing and outcome of a single update or publish.
+pub struct RunRecord {
+    pub kind: RunKind,
+    /// Unix timestamp of when the run started.
+    pub started: u64,
+    pub millis: u64,
+    /// False if the run was aborted or any part of it failed.
+    pub success: bool,
+    /// Timings in the order they completed.
+    pub timings: Vec<RunTiming>,
+    /// Snapshots of the data store taken around each plugin stage that ran.
+    #[serde(default)]
+    pub stages: Vec<StageSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+/// The state of the data store when a plugin stage started.
+/// Every plugin in the stage is given the same snapshot.
+pub struct StageSnapshot {
+    pub stage: String,
+    /// ID of the last change in the changelog when the stage started, if there was one.
+    /// Changes after this one were made during the stage.
+    pub start_change: Option<String>,
+    /// ID of the last change in the changelog when the stage ended.
+    /// Not set in the snapshot given to plugins.
+    pub end_change: Option<String>,
+    /// Number of changes in the changelog when the stage started.
+    pub changes: usize,
+    /// Number of DNS names when the stage started.
+    pub dns_names: usize,
+    /// Number of processed nodes when the stage started.
+    pub nodes: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+/// An advisory lock on the data store, held by the update or publish that is writing to it.
+pub struct RunLock {
+    /// Unique ID of the process holding the lock.
+    pub owner: String,
+    pub kind: RunKind,
+    /// Host the holder runs on.
+    pub host: String,
+    /// Process ID of the holder on its host.
+    pub pid: u32,
+    /// Unix timestamp of when the lock was first acquired.
+    pub acquired: u64,
+    /// Unix timestamp after which the lock is free unless it is refreshed.
+    pub expires: u64,
+}
+
+// Processing
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+/// Marks a processing run that is writing to the processed data layer, and what it writes.
+/// If it is still present when processing starts, the run that wrote it was interrupted.
+pub struct ProcessRun {
+    /// Unix timestamp of when the run started.
+    pub started: u64,
+    /// Link IDs of the processed nodes the run writes.
+    pub nodes: HashSet<String>,
+    /// DNS names the run resolves to a node.
+    pub dns_names: HashSet<String>,
+}
+
+// Publish Queue
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+/// A fragment update that has been prepared for the remote but not yet applied.
+pub struct QueuedUpdate {
+    pub docid: String,
+    pub fragment: String,
+    /// Section to add the fragment to. If none, the existing fragment is replaced.
+    pub section: Option<String>,
+    /// Whether to replace the fragment if it already exists when adding it.
+    pub replace: bool,
+    pub content: String,
+    /// SHA-256 hash of the content.
+    pub hash: String,
+}
+
+impl QueuedUpdate {
+    pub fn new(
+        docid: String,
+        fragment: String,
+        section: Option<String>,
+        replace: bool,
+        content: String,
+    ) -> Self {
+        let hash = Sha256::digest(content.as_bytes())
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        QueuedUpdate {
+            docid,
+            fragment,
+            section,
+            replace,
+            content,
+            hash,
+        }
+    }
+
+    /// Key of this update in the queue. Later updates to the same fragment replace earlier ones.
+    pub fn key(&self) -> String {
+        format!("{};{}", self.docid, self.fragment)
+    }
+}
+
+// Backup
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+/// The value at a key in the data store, independent of how the data store holds it.
+pub enum StoredValue {
+    String(String),
+    Set(BTreeSet<String>),
+    Hash(BTreeMap<String, String>),
+    List(Vec<String>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+/// An entry in the changelog with its ID and unparsed fields.
+pub struct StoredChange {
+    pub id: String,
+    pub fields: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+/// Every key in the data store and every entry in the changelog.
+pub struct DataDump {
+    pub keys: BTreeMap<String, StoredValue>,
+    /// Changelog entries in the order they were made.
+    pub changelog: Vec<StoredChange>,
+}
+
+// Plugins
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+/// Settings for how data from a plugin is displayed in documents.
+pub struct PluginDisplay {
+    /// Name to display instead of the plugin name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Label or icon name attached to content from the plugin.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Colour used to distinguish content from the plugin.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub colour: Option<String>,
+}
+
+impl PluginDisplay {
+    /// Returns true if no display settings are configured.
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.label.is_none() && self.colour.is_none()
+    }
+
+    /// Builds the comma separated list of labels to attach to content from the plugin.
+    pub fn labels(&self) -> Option<String> {
+        let labels = self
+            .label
+            .iter()
+            .cloned()
+            .chain(self.colour.iter().map(|colour| format!("colour-{colour}")))
+            .collect::<Vec<_>>();
+
+        if labels.is_empty() {
+            None
+        } else {
+            Some(labels.join(","))
+        }
+    }
+
+    /// Builds the display settings from the hash stored in redis.
+    pub fn from_hash(mut details: HashMap<String, String>) -> Self {
+        PluginDisplay {
+            name: details.remove("name"),
+            label: details.remove("label"),
+            colour: details.remove("colour"),
+        }
+    }
+
+    /// Returns the display settings as a list of key/value pairs to store in redis.
+    pub fn to_hash(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("name", &self.name),
+            ("label", &self.label),
+            ("colour", &self.colour),
+        ]
+        .into_iter()
+        .filter_map(|(key, val)| val.as_ref().map(|val| (key, val.as_str())))
+        .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+/// Limits the DNS names a plugin may create, and so the nodes it may create from them.
+/// An empty list allows anything.
+pub struct PluginScope {
+    /// Networks the DNS names must be in.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub networks: Vec<String>,
+    /// Zones the DNS names must be in or at the apex of. Addresses are in every zone.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub zones: Vec<String>,
+}
+
+impl PluginScope {
+    /// Returns true if the scope allows any DNS name.
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty() && self.zones.is_empty()
+    }
+
+    /// Returns the scope as it is stored in the data store:
+    /// in lowercase, and with no leading or trailing dots on the zones.
+    pub fn normalised(&self) -> PluginScope {
+        PluginScope {
+            networks: self.networks.iter().map(|net| net.to_lowercase()).collect(),
+            zones: self
+                .zones
+                .iter()
+                .map(|zone| zone.trim_matches('.').to_lowercase())
+                .collect(),
+        }
+    }
+
+    /// Returns true if the scope allows a qualified DNS name.
+    /// This must match `in_scope` in the Redis function library.
+    pub fn allows(&self, qname: &str) -> bool {
+        let qname = qname.to_lowercase();
+        let Some((network, name)) = split_qname(&qname) else {
+            return false;
+        };
+        let scope = self.normalised();
+
+        if !scope.networks.is_empty() && !scope.networks.iter().any(|net| net == network) {
+            return false;
+        }
+
+        scope.zones.is_empty()
+            || name.parse::<IpAddr>().is_ok()
+            || scope.zones.iter().any(|zone| {
+                name == zone
+                    || name
+                        .strip_suffix(zone.as_str())
+                        .is_some_and(|prefix| prefix.ends_with('.'))
+            })
+    }
+}
+
+/// How to choose between values from different plugins for the same field of a processed node.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictStrategy {
+    /// The value that changed first is kept.
+    FirstWins,
+    /// The value that changed last is kept.
+    #[default]
+    LastWins,
+}
+
+impl Display for ConflictStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FirstWins => write!(f, "first-wins"),
+            Self::LastWins => write!(f, "last-wins"),
+        }
+    }
+}
+
+impl TryFrom<&str> for ConflictStrategy {
+    type Error = NetdoxError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "first-wins" => Ok(Self::FirstWins),
+            "last-wins" => Ok(Self::LastWins),
+            _ => redis_err!(format!("Invalid conflict strategy: {value}")),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+/// Chooses between values from different plugins for the metadata keys and name of a processed node.
+pub struct ConflictPolicy {
+    /// Plugins whose values win, highest priority first. Values from plugins
+    /// in the list beat values from plugins that are not.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub priority: Vec<String>,
+    /// How to choose between values from plugins of the same priority.
+    #[serde(default)]
+    pub strategy: ConflictStrategy,
+    /// Write a report of the conflicts found during processing.
+    #[serde(default)]
+    pub report: bool,
+}
+
+impl ConflictPolicy {
+    /// Returns true if the policy is the default.
+    pub fn is_default(&self) -> bool {
+        self == &ConflictPolicy::default()
+    }
+
+    /// Returns the index of the value to keep out of some values for the same field,
+    /// which must not be empty. Values are ranked by the priority of their plugin,
+    /// then by when they changed, and then by their position.
+    /// Values with no change time are treated as the oldest.
+    pub fn choose(&self, values: &[MetadataProvenance]) -> usize {
+        let rank = |meta: &MetadataProvenance| {
+            meta.plugin
+                .as_ref()
+                .and_then(|plugin| self.priority.iter().position(|p| p == plugin))
+                .unwrap_or(self.priority.len())
+        };
+        let ranked = values
+            .iter()
+            .enumerate()
+            .map(|(idx, meta)| (rank(meta), meta.changed.unwrap_or_default(), idx));
+
+        match self.strategy {
+            ConflictStrategy::FirstWins => ranked.min(),
+            ConflictStrategy::LastWins => {
+                ranked.max_by_key(|&(rank, changed, idx)| (Reverse(rank), changed, idx))
+            }
+        }
+        .map_or(0, |(_, _, idx)| idx)
+    }
+}
+
 // Other data
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -420,7 +1475,28 @@ pub enum Data {
     },
 }
 
+/// Returns the colour used to display the state of a node.
+/// Unrecognised states are grey.
+pub fn status_colour(state: &str) -> &'static str {
+    match state.to_lowercase().as_str() {
+        "up" | "ok" | "online" | "running" | "healthy" => "green",
+        "degraded" | "warning" | "warn" | "maintenance" => "orange",
+        "down" | "offline" | "stopped" | "failed" | "error" | "critical" | "crit" => "red",
+        _ => "grey",
+    }
+}
+
 impl Data {
+    /// Returns the state of the node if this is its status data.
+    pub fn status_state(&self) -> Option<&str> {
+        match self {
+            Self::Hash { id, content, .. } if id == STATUS_DATA_ID => content
+                .get(STATUS_STATE_FIELD)
+                .map(std::string::String::as_str),
+            _ => None,
+        }
+    }
+
     pub fn id(&self) -> &str {
         match self {
             Self::Hash { id, .. }
@@ -430,6 +1506,121 @@ impl Data {
         }
     }
 
+    pub fn plugin(&self) -> &str {
+        match self {
+            Self::Hash { plugin, .. }
+            | Self::List { plugin, .. }
+            | Self::String { plugin, .. }
+            | Self::Table { plugin, .. } => plugin,
+        }
+    }
+
+    pub fn title(&self) -> &str {
+        match self {
+            Self::Hash { title, .. }
+            | Self::List { title, .. }
+            | Self::String { title, .. }
+            | Self::Table { title, .. } => title,
+        }
+    }
+
+    /// Returns this data with the plugin field replaced.
+    #[must_use]
+    pub fn with_plugin(mut self, new_plugin: String) -> Self {
+        match &mut self {
+            Self::Hash { plugin, .. }
+            | Self::List { plugin, .. }
+            | Self::String { plugin, .. }
+            | Self::Table { plugin, .. } => *plugin = new_plugin,
+        }
+        self
+    }
+
+    /// Number of rows this data will occupy when displayed.
+    /// Strings count as one row per line.
+    pub fn rows(&self) -> usize {
+        match self {
+            Self::Hash { content, .. } => content.len(),
+            Self::List { content, .. } => content.len(),
+            Self::String { content, .. } => content.lines().count(),
+            Self::Table {
+                content, columns, ..
+            } => content.len().div_ceil((*columns).max(1)),
+        }
+        .max(1)
+    }
+
+    /// Splits this data into pieces with at most `max_rows` rows each.
+    /// Every piece keeps the ID of the original; pieces after the first have a
+    /// title marking them as continued. Strings are never split.
+    pub fn split(self, max_rows: usize) -> Vec<Data> {
+        let max_rows = max_rows.max(1);
+        if self.rows() <= max_rows {
+            return vec![self];
+        }
+
+        let continued = |title: &str, index: usize| {
+            if index == 0 {
+                title.to_string()
+            } else {
+                format!("{title} (continued)")
+            }
+        };
+
+        match self {
+            Self::Hash {
+                id,
+                title,
+                plugin,
+                content,
+            } => content
+                .into_iter()
+                .chunks(max_rows)
+                .into_iter()
+                .enumerate()
+                .map(|(index, chunk)| Self::Hash {
+                    id: id.clone(),
+                    title: continued(&title, index),
+                    plugin: plugin.clone(),
+                    content: chunk.collect(),
+                })
+                .collect(),
+            Self::List {
+                id,
+                title,
+                plugin,
+                content,
+            } => content
+                .chunks(max_rows)
+                .enumerate()
+                .map(|(index, chunk)| Self::List {
+                    id: id.clone(),
+                    title: continued(&title, index),
+                    plugin: plugin.clone(),
+                    content: chunk.to_vec(),
+                })
+                .collect(),
+            Self::Table {
+                id,
+                title,
+                columns,
+                plugin,
+                content,
+            } => content
+                .chunks(max_rows * columns.max(1))
+                .enumerate()
+                .map(|(index, chunk)| Self::Table {
+                    id: id.clone(),
+                    title: continued(&title, index),
+                    columns,
+                    plugin: plugin.clone(),
+                    content: chunk.to_vec(),
+                })
+                .collect(),
+            string @ Self::String { .. } => vec![string],
+        }
+    }
+
     pub fn from_hash(
         id: String,
         mut content: HashMap<String, String>,
@@ -619,6 +1810,7 @@ pub struct Report {
     pub content: Vec<Data>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ChangelogEntry {
     pub id: String,
     pub change: Change,
@@ -636,10 +1828,26 @@ pub enum Change {
         plugin: String,
         record: DNSRecord,
     },
+    DeleteDnsName {
+        plugin: String,
+        qname: String,
+    },
+    DeleteDnsRecord {
+        plugin: String,
+        record: DNSRecord,
+    },
     CreatePluginNode {
         plugin: String,
         node_id: String,
     },
+    DeletePluginNode {
+        plugin: String,
+        node_id: String,
+    },
+    DeleteProcNode {
+        plugin: String,
+        link_id: String,
+    },
     CreateReport {
         plugin: String,
         report_id: String,
@@ -656,15 +1864,210 @@ pub enum Change {
         data_id: String,
         kind: DataKind,
     },
+    DeletedData {
+        plugin: String,
+        obj_id: String,
+        data_id: String,
+        kind: DataKind,
+    },
     UpdatedMetadata {
         plugin: String,
         obj_id: String,
     },
+    UpdatedNotes {
+        plugin: String,
+        obj_id: String,
+    },
+    UpdatedTags {
+        plugin: String,
+        obj_id: String,
+    },
     UpdatedNetworkMapping {
         plugin: String,
         source: String,
         dest: String,
     },
+    UpdatedDependency {
+        plugin: String,
+        source: String,
+        dest: String,
+    },
+    UpdatedService {
+        plugin: String,
+        link_id: String,
+        service: String,
+    },
+    UpdatedRelation {
+        plugin: String,
+        source: String,
+        dest: String,
+        relation: String,
+    },
+    BeginBatch {
+        plugin: String,
+        batch_id: String,
+    },
+    EndBatch {
+        plugin: String,
+        batch_id: String,
+    },
+}
+
+impl Change {
+    /// Name of the plugin that made this change.
+    pub fn plugin(&self) -> &str {
+        match self {
+            Self::Init => NETDOX_PLUGIN,
+            Self::CreateDnsName { plugin, .. }
+            | Self::CreateDnsRecord { plugin, .. }
+            | Self::DeleteDnsName { plugin, .. }
+            | Self::DeleteDnsRecord { plugin, .. }
+            | Self::CreatePluginNode { plugin, .. }
+            | Self::DeletePluginNode { plugin, .. }
+            | Self::DeleteProcNode { plugin, .. }
+            | Self::CreateReport { plugin, .. }
+            | Self::CreatedData { plugin, .. }
+            | Self::UpdatedData { plugin, .. }
+            | Self::DeletedData { plugin, .. }
+            | Self::UpdatedMetadata { plugin, .. }
+            | Self::UpdatedNotes { plugin, .. }
+            | Self::UpdatedTags { plugin, .. }
+            | Self::UpdatedNetworkMapping { plugin, .. }
+            | Self::UpdatedDependency { plugin, .. }
+            | Self::UpdatedService { plugin, .. }
+            | Self::UpdatedRelation { plugin, .. }
+            | Self::BeginBatch { plugin, .. }
+            | Self::EndBatch { plugin, .. } => plugin,
+        }
+    }
+
+    /// Returns the changes that regenerate every document affected by this change.
+    fn regeneration(&self) -> Vec<Change> {
+        let object_regeneration = |plugin: &str, obj_id: &str| match obj_id.split_once(';') {
+            Some((DNS_KEY, qname)) => Some(Change::CreateDnsName {
+                plugin: plugin.to_string(),
+                qname: qname.to_string(),
+            }),
+            Some((NODES_KEY, node_id)) => Some(Change::CreatePluginNode {
+                plugin: plugin.to_string(),
+                node_id: node_id.to_string(),
+            }),
+            Some((REPORTS_KEY, report_id)) => Some(Change::CreateReport {
+                plugin: plugin.to_string(),
+                report_id: report_id.to_string(),
+            }),
+            _ => None,
+        };
+
+        match self {
+            Self::CreateDnsRecord { plugin, record } => {
+                let mut changes = vec![Change::CreateDnsName {
+                    plugin: plugin.clone(),
+                    qname: record.name.clone(),
+                }];
+                if record.implies().is_some() {
+                    changes.push(Change::CreateDnsName {
+                        plugin: plugin.clone(),
+                        qname: record.value.clone(),
+                    });
+                }
+                changes
+            }
+            Self::CreatedData { plugin, obj_id, .. }
+            | Self::UpdatedData { plugin, obj_id, .. }
+            | Self::DeletedData { plugin, obj_id, .. }
+            | Self::UpdatedMetadata { plugin, obj_id }
+            | Self::UpdatedNotes { plugin, obj_id }
+            | Self::UpdatedTags { plugin, obj_id } => match object_regeneration(plugin, obj_id) {
+                Some(change) => vec![change],
+                None => vec![self.clone()],
+            },
+            Self::UpdatedNetworkMapping { plugin, source, .. } => vec![Change::CreateDnsName {
+                plugin: plugin.clone(),
+                qname: source.clone(),
+            }],
+            _ => vec![self.clone()],
+        }
+    }
+}
+
+/// Returns the changes from some changelog entries with batch markers removed.
+/// The changes a plugin makes inside a batch are replaced by changes that
+/// regenerate each affected document once, at the position the batch began,
+/// so they stay in order with the changes other plugins made during the batch.
+/// Changes in a batch that was never ended are returned as they are.
+pub fn coalesce_batches(entries: &[ChangelogEntry]) -> Vec<Change> {
+    // Changes at each position, where a batch has one position for all of its changes.
+    let mut positions: Vec<Vec<Change>> = vec![];
+    // Maps batch ID to the plugin that began it, its position, and the changes in it.
+    let mut open: IndexMap<&str, (&str, usize, Vec<&Change>)> = IndexMap::new();
+
+    for entry in entries {
+        match &entry.change {
+            Change::BeginBatch { plugin, batch_id } => {
+                open.insert(batch_id, (plugin, positions.len(), vec![]));
+                positions.push(vec![]);
+            }
+            Change::EndBatch { batch_id, .. } => {
+                if let Some((_, position, batch)) = open.shift_remove(batch_id.as_str()) {
+                    positions[position] = batch
+                        .into_iter()
+                        .flat_map(Change::regeneration)
+                        .unique()
+                        .collect();
+                }
+            }
+            change => match open
+                .values_mut()
+                .rev()
+                .find(|(plugin, _, _)| *plugin == change.plugin())
+            {
+                Some((_, _, batch)) => batch.push(change),
+                None => positions.push(vec![change.clone()]),
+            },
+        }
+    }
+
+    for (_, position, batch) in open.into_values() {
+        positions[position] = batch.into_iter().cloned().collect();
+    }
+
+    positions.into_iter().flatten().collect()
+}
+
+/// Splits changelog entries into batches of at least `size` entries, to be published in order.
+/// Batches only end where no plugin batch is open, so plugin batches are still coalesced.
+/// A plugin batch that has not ended within `size` entries of where it began is treated as ended,
+/// as the plugin may have stopped before ending it, so it cannot hold every later change in one batch.
+pub fn publish_batches(entries: &[ChangelogEntry], size: usize) -> Vec<&[ChangelogEntry]> {
+    let mut batches = vec![];
+    // Maps the ID of each open plugin batch to the index of the entry that began it.
+    let mut open = HashMap::new();
+    let mut start = 0;
+
+    for (idx, entry) in entries.iter().enumerate() {
+        match &entry.change {
+            Change::BeginBatch { batch_id, .. } => {
+                open.insert(batch_id.as_str(), idx);
+            }
+            Change::EndBatch { batch_id, .. } => {
+                open.remove(batch_id.as_str());
+            }
+            _ => {}
+        }
+        open.retain(|_, begin| idx - *begin < size);
+
+        if open.is_empty() && idx + 1 - start >= size {
+            batches.push(&entries[start..=idx]);
+            start = idx + 1;
+        }
+    }
+
+    if start < entries.len() {
+        batches.push(&entries[start..]);
+    }
+
+    batches
 }
 
 impl From<&Change> for String {
@@ -673,18 +2076,29 @@ impl From<&Change> for String {
             Change::Init => "init".to_string(),
             Change::CreateDnsName { .. } => "create dns name".to_string(),
             Change::CreateDnsRecord { .. } => "create dns record".to_string(),
+            Change::DeleteDnsName { .. } => "delete dns name".to_string(),
+            Change::DeleteDnsRecord { .. } => "delete dns record".to_string(),
+            Change::DeletePluginNode { .. } => "delete plugin node".to_string(),
+            Change::DeleteProcNode { .. } => "delete proc node".to_string(),
             Change::UpdatedNetworkMapping { .. } => "updated network mapping".to_string(),
+            Change::UpdatedDependency { .. } => "updated dependency".to_string(),
+            Change::UpdatedService { .. } => "updated service".to_string(),
+            Change::UpdatedRelation { .. } => "updated relation".to_string(),
             Change::CreatePluginNode { .. } => "create plugin node".to_string(),
             Change::CreatedData { .. } => "created data".to_string(),
             Change::UpdatedMetadata { .. } => "updated metadata".to_string(),
+            Change::UpdatedNotes { .. } => "updated notes".to_string(),
+            Change::UpdatedTags { .. } => "updated tags".to_string(),
             Change::UpdatedData { .. } => "updated data".to_string(),
+            Change::DeletedData { .. } => "deleted data".to_string(),
             Change::CreateReport { .. } => "create report".to_string(),
+            Change::BeginBatch { .. } => "begin batch".to_string(),
+            Change::EndBatch { .. } => "end batch".to_string(),
         }
     }
 }
 
 impl FromRedisValue for ChangelogEntry {
-    #[allow(clippy::too_many_lines)]
     fn from_redis_value(v: redis::Value) -> Result<ChangelogEntry, ParsingError> {
         let redis::Value::Array(vals) = v else {
             return Err("Each changelog stream value must be an array of values.".into());
@@ -700,7 +2114,7 @@ impl FromRedisValue for ChangelogEntry {
             return Err("Failed ot parse changelog entry ID as UTF-8.".into());
         };
 
-        let mut map: HashMap<String, String> = match vals.get(1) {
+        let map: HashMap<String, String> = match vals.get(1) {
             Some(bulk) => match HashMap::from_redis_value(bulk.clone()) {
                 Ok(map) => map,
                 Err(err) => {
@@ -712,6 +2126,17 @@ impl FromRedisValue for ChangelogEntry {
             _ => return Err("Changelog stream sequence second value must be a map".into()),
         };
 
+        ChangelogEntry::from_fields(id, map)
+    }
+}
+
+impl ChangelogEntry {
+    /// Parses a changelog entry from its ID and fields.
+    #[allow(clippy::too_many_lines)]
+    pub fn from_fields(
+        id: &str,
+        mut map: HashMap<String, String>,
+    ) -> Result<ChangelogEntry, ParsingError> {
         let (Some(change), Some(value), Some(plugin)) = (
             map.remove("change"),
             map.remove("value"),
@@ -720,154 +2145,299 @@ impl FromRedisValue for ChangelogEntry {
             return Err("Changelog item did not have required fields.".into());
         };
 
-        let mut val_parts = value.split(';');
-        match change.as_str() {
-            "init" => Ok(ChangelogEntry {
-                id: id.to_string(),
-                change: Change::Init,
+        // Entries name each part of the changed object's ID in typed fields.
+        // Entries from before the fields were added are parsed from the value instead.
+        let typed = TypedChangeFields::take(&mut map);
+        let change = match change.as_str() {
+            "init" => Ok(Change::Init),
+
+            "create dns name" => match typed.qname {
+                Some(qname) => Ok(Change::CreateDnsName { plugin, qname }),
+                None => legacy_change(&change, &value, plugin),
+            },
+
+            "create dns record" => match (typed.qname, typed.rtype, typed.rvalue) {
+                (Some(qname), Some(rtype), Some(rvalue)) => Ok(Change::CreateDnsRecord {
+                    plugin: plugin.clone(),
+                    record: DNSRecord {
+                        name: qname,
+                        value: rvalue,
+                        rtype,
+                        plugin,
+                    },
+                }),
+                _ => legacy_change(&change, &value, plugin),
+            },
+
+            "create plugin node" => Ok(Change::CreatePluginNode {
+                plugin,
+                node_id: typed.node_id.unwrap_or(value),
             }),
 
-            "create dns name" => match val_parts.next() {
-                Some(qname) => Ok(ChangelogEntry {
-                    id: id.to_string(),
-                    change: Change::CreateDnsName {
+            "delete dns name" => Ok(Change::DeleteDnsName {
+                plugin,
+                qname: typed.qname.unwrap_or(value),
+            }),
+
+            "delete dns record" => match (typed.qname, typed.rtype, typed.rvalue) {
+                (Some(qname), Some(rtype), Some(rvalue)) => Ok(Change::DeleteDnsRecord {
+                    plugin: plugin.clone(),
+                    record: DNSRecord {
+                        name: qname,
+                        value: rvalue,
+                        rtype,
                         plugin,
-                        qname: qname.to_string(),
                     },
                 }),
-                None => Err(format!("Invalid change value for CreateDnsName: {value}").into()),
+                _ => Err(format!("Invalid change value for DeleteDnsRecord: {value}").into()),
             },
 
-            "create dns record" => match val_parts.nth(1) {
-                Some(start) => match (val_parts.nth(1), val_parts.next()) {
-                    (Some(rtype), Some(dest)) => Ok(ChangelogEntry {
-                        id: id.to_string(),
-                        change: Change::CreateDnsRecord {
-                            plugin: plugin.clone(),
-                            record: DNSRecord {
-                                name: start.to_string(),
-                                value: dest.to_string(),
-                                rtype: rtype.to_string(),
+            "delete plugin node" => Ok(Change::DeletePluginNode {
+                plugin,
+                node_id: typed.node_id.unwrap_or(value),
+            }),
+
+            "delete proc node" => Ok(Change::DeleteProcNode {
+                plugin,
+                link_id: typed.link_id.unwrap_or(value),
+            }),
+
+            "updated metadata" | "updated notes" | "updated tags" | "created data"
+            | "updated data" | "deleted data" => {
+                match (typed.obj_id, typed.data_id, typed.kind) {
+                    (Some(obj_id), None, None) if change == "updated metadata" => {
+                        Ok(Change::UpdatedMetadata { plugin, obj_id })
+                    }
+                    (Some(obj_id), None, None) if change == "updated notes" => {
+                        Ok(Change::UpdatedNotes { plugin, obj_id })
+                    }
+                    (Some(obj_id), None, None) if change == "updated tags" => {
+                        Ok(Change::UpdatedTags { plugin, obj_id })
+                    }
+                    (Some(obj_id), Some(data_id), Some(kind)) => {
+                        let kind = match kind.as_str() {
+                            "plugin" => DataKind::Plugin,
+                            "report" => DataKind::Report,
+                            other => {
+                                return Err(
+                                    format!("Invalid data kind in changelog: {other}").into()
+                                )
+                            }
+                        };
+
+                        match change.as_str() {
+                            "created data" => Ok(Change::CreatedData {
                                 plugin,
-                            },
-                        },
-                    }),
-                    _ => Err(format!("Invalid change value for CreateDnsRecord: {value}").into()),
-                },
-                None => Err(format!("Invalid change value for CreateDnsRecord: {value}").into()),
+                                obj_id,
+                                data_id,
+                                kind,
+                            }),
+                            "deleted data" => Ok(Change::DeletedData {
+                                plugin,
+                                obj_id,
+                                data_id,
+                                kind,
+                            }),
+                            _ => Ok(Change::UpdatedData {
+                                plugin,
+                                obj_id,
+                                data_id,
+                                kind,
+                            }),
+                        }
+                    }
+                    // Deleted data was always logged with typed fields.
+                    _ if change == "deleted data" => {
+                        Err(format!("Invalid change value for DeletedData: {value}").into())
+                    }
+                    _ => legacy_change(&change, &value, plugin),
+                }
+            }
+
+            "create report" => Ok(Change::CreateReport {
+                plugin,
+                report_id: typed.report_id.unwrap_or(value),
+            }),
+
+            // Entries from before the destination was logged have an empty dest.
+            "updated network mapping" => Ok(Change::UpdatedNetworkMapping {
+                plugin,
+                source: typed.qname.unwrap_or(value),
+                dest: typed.dest.unwrap_or_default(),
+            }),
+
+            "updated dependency" => match (typed.source, typed.dest) {
+                (Some(source), Some(dest)) => Ok(Change::UpdatedDependency {
+                    plugin,
+                    source,
+                    dest,
+                }),
+                _ => Err(format!("Invalid change value for UpdatedDependency: {value}").into()),
             },
 
-            "create plugin node" => Ok(ChangelogEntry {
-                id: id.to_string(),
-                change: Change::CreatePluginNode {
+            "updated service" => match (typed.link_id, typed.service) {
+                (Some(link_id), Some(service)) => Ok(Change::UpdatedService {
                     plugin,
-                    node_id: value,
-                },
-            }),
+                    link_id,
+                    service,
+                }),
+                _ => Err(format!("Invalid change value for UpdatedService: {value}").into()),
+            },
 
-            "updated metadata" => Ok(ChangelogEntry {
-                id: id.to_string(),
-                change: Change::UpdatedMetadata {
+            "updated relation" => match (typed.source, typed.dest, typed.relation) {
+                (Some(source), Some(dest), Some(relation)) => Ok(Change::UpdatedRelation {
                     plugin,
-                    obj_id: val_parts.skip(1).collect::<Vec<_>>().join(";"),
-                },
+                    source,
+                    dest,
+                    relation,
+                }),
+                _ => Err(format!("Invalid change value for UpdatedRelation: {value}").into()),
+            },
+
+            "begin batch" => Ok(Change::BeginBatch {
+                plugin,
+                batch_id: id.to_string(),
             }),
 
-            "created data" => {
-                let data_id = match val_parts.clone().next_back() {
-                    Some(id) => id.to_string(),
-                    None => {
-                        return Err(format!("Invalid change value for CreatedData: {value}").into())
-                    }
-                };
+            "end batch" => Ok(Change::EndBatch {
+                plugin,
+                batch_id: typed.batch_id.unwrap_or(value),
+            }),
 
-                let (obj_id, kind) = match val_parts.next() {
-                    Some(PDATA_KEY) => (
-                        val_parts
-                            .take_while(|i| *i != data_id)
-                            .collect::<Vec<_>>()
-                            .join(";"),
-                        DataKind::Plugin,
-                    ),
-                    Some(REPORTS_KEY) => (
-                        format!(
-                            "{REPORTS_KEY};{}",
-                            val_parts
-                                .take_while(|i| *i != data_id)
-                                .collect::<Vec<_>>()
-                                .join(";")
-                        ),
-                        DataKind::Report,
-                    ),
-                    _ => {
-                        return Err(format!("Invalid change value for CreatedData: {value}").into())
-                    }
-                };
+            other => Err(format!("Unrecognised change in log: {other}").into()),
+        }?;
 
-                Ok(ChangelogEntry {
-                    id: id.to_string(),
-                    change: Change::CreatedData {
+        Ok(ChangelogEntry {
+            id: id.to_string(),
+            change,
+        })
+    }
+}
+
+/// Typed fields of a changelog entry that identify the changed object.
+#[derive(Default)]
+struct TypedChangeFields {
+    qname: Option<String>,
+    rtype: Option<String>,
+    rvalue: Option<String>,
+    node_id: Option<String>,
+    link_id: Option<String>,
+    obj_id: Option<String>,
+    data_id: Option<String>,
+    kind: Option<String>,
+    report_id: Option<String>,
+    batch_id: Option<String>,
+    source: Option<String>,
+    dest: Option<String>,
+    service: Option<String>,
+    relation: Option<String>,
+}
+
+impl TypedChangeFields {
+    /// Removes the typed fields from the fields of a changelog entry.
+    fn take(map: &mut HashMap<String, String>) -> Self {
+        TypedChangeFields {
+            qname: map.remove("qname"),
+            rtype: map.remove("rtype"),
+            rvalue: map.remove("rvalue"),
+            node_id: map.remove("node_id"),
+            link_id: map.remove("link_id"),
+            obj_id: map.remove("obj_id"),
+            data_id: map.remove("data_id"),
+            kind: map.remove("kind"),
+            report_id: map.remove("report_id"),
+            batch_id: map.remove("batch_id"),
+            source: map.remove("source"),
+            dest: map.remove("dest"),
+            service: map.remove("service"),
+            relation: map.remove("relation"),
+        }
+    }
+}
+
+/// Parses a change from the value of a changelog entry without typed fields.
+/// Object IDs are split on semicolons, so this fails for IDs that contain them.
+fn legacy_change(change: &str, value: &str, plugin: String) -> Result<Change, ParsingError> {
+    let mut val_parts = value.split(';');
+    match change {
+        "create dns name" => match val_parts.next() {
+            Some(qname) => Ok(Change::CreateDnsName {
+                plugin,
+                qname: qname.to_string(),
+            }),
+            None => Err(format!("Invalid change value for CreateDnsName: {value}").into()),
+        },
+
+        "create dns record" => match val_parts.nth(1) {
+            Some(start) => match (val_parts.nth(1), val_parts.next()) {
+                (Some(rtype), Some(dest)) => Ok(Change::CreateDnsRecord {
+                    plugin: plugin.clone(),
+                    record: DNSRecord {
+                        name: start.to_string(),
+                        value: dest.to_string(),
+                        rtype: rtype.to_string(),
                         plugin,
-                        obj_id,
-                        data_id,
-                        kind,
                     },
-                })
-            }
+                }),
+                _ => Err(format!("Invalid change value for CreateDnsRecord: {value}").into()),
+            },
+            None => Err(format!("Invalid change value for CreateDnsRecord: {value}").into()),
+        },
 
-            "updated data" => {
-                let data_id = match val_parts.clone().next_back() {
-                    Some(id) => id.to_string(),
-                    None => {
-                        return Err(format!("Invalid change value for UpdatedData: {value}").into())
-                    }
-                };
+        "updated metadata" => Ok(Change::UpdatedMetadata {
+            plugin,
+            obj_id: val_parts.skip(1).collect::<Vec<_>>().join(";"),
+        }),
 
-                let (obj_id, kind) = match val_parts.next() {
-                    Some(PDATA_KEY) => (
+        "updated notes" => Ok(Change::UpdatedNotes {
+            plugin,
+            obj_id: val_parts.skip(1).collect::<Vec<_>>().join(";"),
+        }),
+
+        "created data" | "updated data" => {
+            let data_id = match val_parts.clone().next_back() {
+                Some(id) => id.to_string(),
+                None => return Err(format!("Invalid change value for {change}: {value}").into()),
+            };
+
+            let (obj_id, kind) = match val_parts.next() {
+                Some(PDATA_KEY) => (
+                    val_parts
+                        .take_while(|i| *i != data_id)
+                        .collect::<Vec<_>>()
+                        .join(";"),
+                    DataKind::Plugin,
+                ),
+                Some(REPORTS_KEY) => (
+                    format!(
+                        "{REPORTS_KEY};{}",
                         val_parts
                             .take_while(|i| *i != data_id)
                             .collect::<Vec<_>>()
-                            .join(";"),
-                        DataKind::Plugin,
-                    ),
-                    Some(REPORTS_KEY) => (
-                        format!(
-                            "{REPORTS_KEY};{}",
-                            val_parts
-                                .take_while(|i| *i != data_id)
-                                .collect::<Vec<_>>()
-                                .join(";")
-                        ),
-                        DataKind::Report,
+                            .join(";")
                     ),
-                    _ => {
-                        return Err(format!("Invalid change value for UpdatedData: {value}").into())
-                    }
-                };
+                    DataKind::Report,
+                ),
+                _ => return Err(format!("Invalid change value for {change}: {value}").into()),
+            };
 
-                Ok(ChangelogEntry {
-                    id: id.to_string(),
-                    change: Change::UpdatedData {
-                        plugin,
-                        obj_id,
-                        data_id,
-                        kind,
-                    },
+            if change == "created data" {
+                Ok(Change::CreatedData {
+                    plugin,
+                    obj_id,
+                    data_id,
+                    kind,
                 })
-            }
-
-            "create report" => Ok(ChangelogEntry {
-                id: id.to_string(),
-                change: Change::CreateReport {
+            } else {
+                Ok(Change::UpdatedData {
                     plugin,
-                    report_id: value,
-                },
-            }),
-
-            "updated network mapping" => todo!("network mapping change parsing"),
-
-            other => Err(format!("Unrecognised change in log: {other}").into()),
+                    obj_id,
+                    data_id,
+                    kind,
+                })
+            }
         }
+
+        other => Err(format!("Cannot parse change from value: {other}").into()),
     }
 }
@@ -1,12 +1,26 @@
+pub mod keyspace;
+pub mod memory_store;
+pub mod read_only;
+pub mod redis_cluster;
 pub mod redis_store;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_store;
 
 use async_trait::async_trait;
 use enum_dispatch::enum_dispatch;
-use std::collections::{HashMap, HashSet};
+use ipnet::IpNet;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
 use crate::{
-    config::LocalConfig,
-    data::model::{Data, Node, RawNode, DNS},
+    config::{LocalConfig, SaveStrategy},
+    data::model::{
+        Conflict, ConflictPolicy, Data, DataDump, Dependency, DnsBundle, DnsDeletion, IndexRepairs,
+        MetadataMigration, MetadataProvenance, NetworkRename, Node, NodeBundle, PluginDisplay,
+        ProcessRun, Prune, QueuedUpdate, RawNode, Relation, RunLock, RunRecord, Service, DNS,
+    },
     error::NetdoxResult,
 };
 
@@ -24,39 +38,85 @@ pub trait DataConn: Send + Clone {
     /// Perform setup and reset the changelog, then insert an init change.
     async fn init(&mut self) -> NetdoxResult<()>;
 
+    /// Removes all data from the datastore.
+    async fn flush(&mut self) -> NetdoxResult<()>;
+
     // DNS
 
     /// Gets all DNS data.
     async fn get_dns(&mut self) -> NetdoxResult<DNS>;
 
+    /// Gets the DNS data reachable from some qualified DNS names
+    /// through records, implied records, and network translations.
+    async fn get_dns_subset(&mut self, names: &[&str]) -> NetdoxResult<DNS>;
+
     /// Gets all DNS names.
     async fn get_dns_names(&mut self) -> NetdoxResult<HashSet<String>>;
 
+    /// Gets the DNS data of a batch of about `count` DNS names, starting from a cursor.
+    /// Returns the cursor to continue from, which is 0 once every name has been read.
+    /// Start from a cursor of 0.
+    async fn scan_dns(&mut self, cursor: u64, count: usize) -> NetdoxResult<(u64, DNS)>;
+
+    /// Returns true if a qualified DNS name exists.
+    async fn dns_name_exists(&mut self, qname: &str) -> NetdoxResult<bool>;
+
     /// Qualifies some DNS names if they are not already.
     async fn qualify_dns_names(&mut self, names: &[&str]) -> NetdoxResult<Vec<String>>;
 
+    /// Gets the names of all registered networks.
+    /// If empty, DNS names may be created in any network.
+    async fn get_networks(&mut self) -> NetdoxResult<HashSet<String>>;
+
+    /// Gets the glob patterns in the DNS ignore list.
+    async fn get_dns_ignore_patterns(&mut self) -> NetdoxResult<HashSet<String>>;
+
+    /// Gets the configured subnets to group addresses by.
+    async fn get_subnets(&mut self) -> NetdoxResult<HashSet<IpNet>>;
+
     // Nodes
 
-    /// Gets a raw node from its redis key.
-    async fn get_raw_node(&mut self, key: &str) -> NetdoxResult<RawNode>;
+    /// Gets the names of all valid node roles.
+    /// If empty, nodes may have any role.
+    async fn get_roles(&mut self) -> NetdoxResult<HashSet<String>>;
 
     /// Gets raw nodes from unprocessed data layer.
     async fn get_raw_nodes(&mut self) -> NetdoxResult<Vec<RawNode>>;
 
+    /// Gets the raw nodes with about `count` sets of DNS names, starting from a cursor.
+    /// Returns the cursor to continue from, which is 0 once every raw node has been read.
+    async fn scan_raw_nodes(
+        &mut self,
+        cursor: u64,
+        count: usize,
+    ) -> NetdoxResult<(u64, Vec<RawNode>)>;
+
     /// Gets a process node from the processed data layer.
     async fn get_node(&mut self, id: &str) -> NetdoxResult<Node>;
 
     /// Gets all node IDs from the processed data layer.
     async fn get_node_ids(&mut self) -> NetdoxResult<HashSet<String>>;
 
+    /// Gets about `count` processed nodes, starting from a cursor.
+    /// Returns the cursor to continue from, which is 0 once every node has been read.
+    async fn scan_nodes(&mut self, cursor: u64, count: usize) -> NetdoxResult<(u64, Vec<Node>)>;
+
     /// Gets the ID of the processed node that a raw node was consumed by.
     async fn get_node_from_raw(&mut self, raw_id: &str) -> NetdoxResult<Option<String>>;
 
     /// Builds the ID of a raw node from the given qnames.
     async fn get_raw_id_from_qnames(&mut self, qnames: &[&str]) -> NetdoxResult<String>;
 
-    /// Puts a processed node into the data store.
-    async fn put_node(&mut self, node: &Node) -> NetdoxResult<()>;
+    /// Gets the dependencies of a processed node on other nodes, and of other nodes on it.
+    /// Dependencies involving nodes that no longer exist are skipped.
+    async fn get_node_dependencies(&mut self, link_id: &str) -> NetdoxResult<Vec<Dependency>>;
+
+    /// Gets the typed relations of a processed node to other nodes, and of other nodes to it,
+    /// ordered by type. Relations involving nodes that no longer exist are skipped.
+    async fn get_node_relations(&mut self, link_id: &str) -> NetdoxResult<Vec<Relation>>;
+
+    /// Gets the services a processed node exposes, ordered by port and then protocol.
+    async fn get_node_services(&mut self, link_id: &str) -> NetdoxResult<Vec<Service>>;
 
     // Plugin Data
 
@@ -69,8 +129,29 @@ pub trait DataConn: Send + Clone {
     /// Gets all plugin data for a node.
     async fn get_node_pdata(&mut self, node: &Node) -> NetdoxResult<Vec<Data>>;
 
+    /// Deletes some plugin data from a DNS object.
+    /// Returns true if the data existed. Plugins cannot delete data created by other plugins.
+    async fn delete_dns_pdata(
+        &mut self,
+        qname: &str,
+        plugin: &str,
+        data_id: &str,
+    ) -> NetdoxResult<bool>;
+
+    /// Deletes some plugin data from a node, or any of the raw nodes it consumed.
+    /// Returns true if the data existed. Plugins cannot delete data created by other plugins.
+    async fn delete_node_pdata(
+        &mut self,
+        node: &Node,
+        plugin: &str,
+        data_id: &str,
+    ) -> NetdoxResult<bool>;
+
     // Reports
 
+    /// Gets the IDs of all reports.
+    async fn get_report_ids(&mut self) -> NetdoxResult<HashSet<String>>;
+
     /// Gets a report.
     async fn get_report(&mut self, id: &str) -> NetdoxResult<Report>;
 
@@ -102,6 +183,38 @@ pub trait DataConn: Send + Clone {
     /// Gets the metadata for a node.
     async fn get_node_metadata(&mut self, node: &Node) -> NetdoxResult<HashMap<String, String>>;
 
+    /// Gets the plugin that last changed each metadata key on a DNS object.
+    async fn get_dns_metadata_sources(
+        &mut self,
+        qname: &str,
+    ) -> NetdoxResult<HashMap<String, String>>;
+
+    /// Gets the plugin that last changed each metadata key on a node,
+    /// for the values the node has.
+    async fn get_node_metadata_sources(
+        &mut self,
+        node: &Node,
+    ) -> NetdoxResult<HashMap<String, String>>;
+
+    /// Gets the metadata for a DNS object, with the plugin that last changed each value and when.
+    async fn get_dns_metadata_provenance(
+        &mut self,
+        qname: &str,
+    ) -> NetdoxResult<HashMap<String, MetadataProvenance>>;
+
+    /// Gets the metadata for a node, with the plugin that last changed each value and when.
+    async fn get_node_metadata_provenance(
+        &mut self,
+        node: &Node,
+    ) -> NetdoxResult<HashMap<String, MetadataProvenance>>;
+
+    /// Gets the policy for choosing between values from different plugins for the same field of a node.
+    async fn get_conflict_policy(&mut self) -> NetdoxResult<ConflictPolicy>;
+
+    /// Gets the metadata keys that the raw nodes of a node have different values for,
+    /// with the value the conflict policy chose. Keys set on the processed node are not conflicts.
+    async fn get_node_metadata_conflicts(&mut self, node: &Node) -> NetdoxResult<Vec<Conflict>>;
+
     /// Adds some metadata to a node.
     async fn put_node_metadata(
         &mut self,
@@ -110,6 +223,46 @@ pub trait DataConn: Send + Clone {
         data: HashMap<&str, &str>,
     ) -> NetdoxResult<()>;
 
+    /// Moves metadata stored under the legacy key layout to the current one.
+    /// If `dry_run` is true, only reports what would be moved.
+    async fn migrate_metadata(&mut self, dry_run: bool) -> NetdoxResult<MetadataMigration>;
+
+    // Notes
+
+    /// Gets the operator notes attached to an object.
+    async fn get_notes(&mut self, obj_id: &str) -> NetdoxResult<HashMap<String, String>>;
+
+    /// Sets an operator note on an object, or removes it if value is None.
+    /// Operator notes are kept separate from metadata so plugins cannot overwrite them.
+    async fn put_note(&mut self, obj_id: &str, key: &str, value: Option<&str>) -> NetdoxResult<()>;
+
+    // Tags
+
+    /// Gets all of the tags set on any object.
+    async fn get_tags(&mut self) -> NetdoxResult<HashSet<String>>;
+
+    /// Gets the IDs of the objects with a tag.
+    async fn get_tagged(&mut self, tag: &str) -> NetdoxResult<HashSet<String>>;
+
+    /// Gets the tags of a DNS object.
+    async fn get_dns_tags(&mut self, qname: &str) -> NetdoxResult<HashSet<String>>;
+
+    /// Gets the tags of a node, including those of its raw nodes.
+    async fn get_node_tags(&mut self, node: &Node) -> NetdoxResult<HashSet<String>>;
+
+    // Plugins
+
+    /// Gets the configured display settings for each plugin that has any.
+    async fn get_plugin_displays(&mut self) -> NetdoxResult<HashMap<String, PluginDisplay>>;
+
+    // Documents
+
+    /// Gets everything the document of a DNS name shows at once.
+    async fn get_dns_bundle(&mut self, qname: &str) -> NetdoxResult<DnsBundle>;
+
+    /// Gets everything the document of a processed node shows at once.
+    async fn get_node_bundle(&mut self, node: &Node) -> NetdoxResult<NodeBundle>;
+
     // Changelog
 
     /// Gets all changes from log after a given change ID.
@@ -121,14 +274,192 @@ pub trait DataConn: Send + Clone {
     /// Gets the total number of changes in the changelog.
     async fn total_change_count(&mut self) -> NetdoxResult<usize>;
 
+    // Indexes
+
+    /// Regenerates all derived indexes from the primary data in one atomic operation.
+    async fn rebuild_index(&mut self) -> NetdoxResult<IndexRepairs>;
+
+    /// Moves every DNS name in a network to another network, along with the objects
+    /// and references that use them. Processed nodes keep their link IDs.
+    /// If `dry_run` is true, only reports what would be moved.
+    async fn rename_network(
+        &mut self,
+        old: &str,
+        new: &str,
+        dry_run: bool,
+    ) -> NetdoxResult<NetworkRename>;
+
+    /// Deletes a DNS name with its records, metadata and plugin data, the records on other names
+    /// that point to it, and the raw nodes that use it, and removes it from processed nodes.
+    /// Processed nodes left with no raw nodes are deleted. Operator notes are kept.
+    /// If `dry_run` is true, only reports what would be deleted.
+    async fn delete_dns(&mut self, qname: &str, dry_run: bool) -> NetdoxResult<DnsDeletion>;
+
+    /// Removes DNS records and raw nodes that their plugin has not reported since a cutoff,
+    /// and then the DNS names and processed nodes left with nothing to describe.
+    /// Cutoffs are in seconds since the epoch. Plugins with no cutoff of their own use the default,
+    /// and nothing is removed for plugins with neither.
+    /// If `dry_run` is true, only reports what would be removed.
+    async fn prune(
+        &mut self,
+        default_cutoff: Option<u64>,
+        plugin_cutoffs: &HashMap<String, u64>,
+        dry_run: bool,
+    ) -> NetdoxResult<Prune>;
+
+    // Runs
+
+    /// Records a completed run, discarding the oldest records beyond the cap.
+    async fn put_run(&mut self, run: &RunRecord) -> NetdoxResult<()>;
+
+    /// Gets up to `count` of the most recent runs, newest first.
+    async fn get_runs(&mut self, count: usize) -> NetdoxResult<Vec<RunRecord>>;
+
+    /// Takes the run lock if it is free, has expired, or is already held by the owner of the lock,
+    /// in which case it is replaced. Returns the lock held by another owner instead, if any.
+    async fn acquire_run_lock(&mut self, lock: &RunLock) -> NetdoxResult<Option<RunLock>>;
+
+    /// Removes the run lock if it is held by an owner.
+    async fn release_run_lock(&mut self, owner: &str) -> NetdoxResult<()>;
+
+    /// Removes the run lock whoever holds it, and returns the lock that was removed, if any.
+    async fn force_run_unlock(&mut self) -> NetdoxResult<Option<RunLock>>;
+
+    /// Gets the plugins that the last time-boxed update ran out of time to run.
+    async fn get_deferred_plugins(&mut self) -> NetdoxResult<HashSet<String>>;
+
+    /// Replaces the set of plugins deferred to the next update.
+    async fn set_deferred_plugins(&mut self, plugins: &HashSet<String>) -> NetdoxResult<()>;
+
+    /// Gets the DNS names each plugin was refused because they are outside its scope,
+    /// since the violations were last cleared.
+    async fn get_scope_violations(&mut self) -> NetdoxResult<HashMap<String, HashSet<String>>>;
+
+    /// Forgets the recorded scope violations.
+    async fn clear_scope_violations(&mut self) -> NetdoxResult<()>;
+
+    /// Gets the cursor a plugin stage's probe printed before the stage last succeeded, if any.
+    async fn get_plugin_cursor(
+        &mut self,
+        plugin: &str,
+        stage: &str,
+    ) -> NetdoxResult<Option<String>>;
+
+    /// Sets the cursor of a plugin stage.
+    async fn set_plugin_cursor(
+        &mut self,
+        plugin: &str,
+        stage: &str,
+        cursor: &str,
+    ) -> NetdoxResult<()>;
+
+    /// Forgets the cursors of every plugin stage, so they all run in the next update.
+    async fn clear_plugin_cursors(&mut self) -> NetdoxResult<()>;
+
+    // Processing
+
+    /// Records that a processing run is about to write to the processed data layer.
+    async fn begin_process(&mut self, run: &ProcessRun) -> NetdoxResult<()>;
+
+    /// Writes the results of processing in one transaction, so nothing reading the data store
+    /// sees some of them without the rest: the node metadata on each DNS name that resolved
+    /// to a node, given as pairs of DNS name and link ID, and the processed nodes.
+    async fn put_processed(
+        &mut self,
+        resolved: &[(String, String)],
+        nodes: &[&Node],
+    ) -> NetdoxResult<()>;
+
+    /// Removes the record of the processing run once it has written everything.
+    async fn end_process(&mut self) -> NetdoxResult<()>;
+
+    /// Gets the record of a processing run that never finished, if any.
+    async fn get_interrupted_process(&mut self) -> NetdoxResult<Option<ProcessRun>>;
+
+    /// Removes anything an interrupted processing run may have written:
+    /// its processed nodes, and the node metadata on its DNS names.
+    async fn clear_process(&mut self, run: &ProcessRun) -> NetdoxResult<()>;
+
+    // Publish Queue
+
+    /// Persists fragment updates before they are applied to the remote.
+    async fn put_queued_updates(&mut self, updates: &[QueuedUpdate]) -> NetdoxResult<()>;
+
+    /// Gets the fragment updates that were queued but never applied.
+    async fn get_queued_updates(&mut self) -> NetdoxResult<Vec<QueuedUpdate>>;
+
+    /// Marks a queued fragment update as applied.
+    /// Does nothing if the update was replaced by a newer one for the same fragment.
+    async fn remove_queued_update(&mut self, update: &QueuedUpdate) -> NetdoxResult<()>;
+
+    /// Gets the ID of the last change applied to a remote by a publish that has not finished.
+    async fn get_publish_checkpoint(&mut self, remote: &str) -> NetdoxResult<Option<String>>;
+
+    /// Records that changes up to some change ID have been applied to a remote.
+    async fn put_publish_checkpoint(&mut self, remote: &str, change_id: &str) -> NetdoxResult<()>;
+
+    /// Removes the checkpoint for a remote, once its changelog has caught up.
+    async fn clear_publish_checkpoint(&mut self, remote: &str) -> NetdoxResult<()>;
+
+    /// Gets the ID of the last change published to each named remote from this data store.
+    async fn get_publish_cursors(&mut self) -> NetdoxResult<HashMap<String, String>>;
+
+    /// Records that changes up to some change ID have been published to a named remote.
+    async fn put_publish_cursor(&mut self, remote: &str, change_id: &str) -> NetdoxResult<()>;
+
+    /// Gets the ID of the change the changelog on a remote was last advanced to from this data store.
+    async fn get_remote_cursor(&mut self, remote: &str) -> NetdoxResult<Option<String>>;
+
+    /// Atomically records that the changelog on a remote was advanced to some change ID,
+    /// if the recorded change ID is still `expected`.
+    /// Returns false and changes nothing if another publish recorded a different one.
+    async fn swap_remote_cursor(
+        &mut self,
+        remote: &str,
+        expected: Option<&str>,
+        change_id: &str,
+    ) -> NetdoxResult<bool>;
+
+    /// Records fragments in the document of an object that were replaced by fragments with new IDs,
+    /// as pairs of old and new fragment ID, so the old fragments can be removed from the remote later.
+    /// Nothing is recorded for objects whose documents were regenerated since the IDs changed.
+    async fn put_superseded_fragments(
+        &mut self,
+        obj_id: &str,
+        ids: &[(String, String)],
+    ) -> NetdoxResult<()>;
+
+    /// Gets the old and new IDs of the superseded fragments of each object that has any.
+    async fn get_superseded_fragments(
+        &mut self,
+    ) -> NetdoxResult<HashMap<String, HashMap<String, String>>>;
+
+    /// Forgets the superseded fragments of objects whose documents were regenerated,
+    /// and stops recording them for those objects.
+    async fn clear_superseded_fragments(&mut self, obj_ids: &[String]) -> NetdoxResult<()>;
+
+    // Backup
+
+    /// Gets the value of every key in the datastore and every entry in the changelog.
+    async fn dump(&mut self) -> NetdoxResult<DataDump>;
+
+    /// Writes the keys and changelog entries of a dump into the datastore,
+    /// keeping the IDs of the changelog entries. Fails if the datastore contains any keys.
+    async fn restore(&mut self, dump: &DataDump) -> NetdoxResult<()>;
+
     // Persistence
 
-    /// Writes a save of the datastore to ensure persistence.
-    async fn write_save(&mut self) -> NetdoxResult<()>;
+    /// Writes a save of the datastore to ensure persistence,
+    /// waiting up to `timeout` for it to finish.
+    async fn write_save(&mut self, strategy: SaveStrategy, timeout: Duration) -> NetdoxResult<()>;
 }
 
 #[derive(Clone)]
 #[enum_dispatch(DataConn)]
 pub enum DataStore {
-    Redis(redis::aio::MultiplexedConnection),
+    Redis(redis_store::RedisConnection),
+    #[cfg(feature = "sqlite")]
+    Sqlite(sqlite_store::SqliteStore),
+    Memory(memory_store::MemoryStore),
+    ReadOnly(read_only::ReadOnlyStore),
 }
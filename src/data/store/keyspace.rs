@@ -0,0 +1,4581 @@
+use crate::{
+    config::{LocalConfig, SaveStrategy},
+    data::{
+        model::{
+            canonical_ipv6, matches_ignore_pattern, merge_metadata, metadata_provenance,
+            split_qname, ChangelogEntry, Conflict, ConflictPolicy, ConflictStrategy, DNSRecord,
+            Data, DataDump, Dependency, DnsBundle, DnsDeletion, IndexRepairs, MetadataMigration,
+            MetadataProvenance, NetworkRename, Node, NodeBundle, PluginDisplay, PluginScope,
+            ProcessRun, Prune, QueuedUpdate, RawNode, Relation, Report, RunLock, RunRecord,
+            Service, StoredChange, StoredValue, ADDRESS_RTYPES, CONFLICT_POLICY_KEY, DAMPENING_KEY,
+            DEFERRED_PLUGINS_KEY, DEPENDENCIES_KEY, DNS, DNS_IGNORE_PATTERNS_KEY, DNS_KEY,
+            DNS_NODES_KEY, FRAGMENT_IDS_KEY, MAX_RUNS, METADATA_KEY, NETDOX_PLUGIN, NETWORKS_KEY,
+            NETWORK_SUFFIXES_KEY, NODES_KEY, NODE_ID_META_KEY, NODE_META_KEY, NOTES_KEY, PDATA_KEY,
+            PLUGIN_CURSORS_KEY, PLUGIN_DISPLAY_KEY, PLUGIN_SCOPES_KEY, PROCESS_RUN_KEY,
+            PROC_NODES_KEY, PROC_NODE_REVS_KEY, PUBLISH_CHECKPOINT_KEY, PUBLISH_CURSORS_KEY,
+            PUBLISH_QUEUE_KEY, RELATIONS_KEY, REMOTE_CURSORS_KEY, REPORTS_KEY, ROLES_KEY,
+            ROLE_META_KEY, RUNS_KEY, RUN_LOCK_KEY, SCOPE_VIOLATIONS_KEY, SERVICES_KEY, SUBNETS_KEY,
+            TAGS_KEY, TAGS_META_KEY,
+        },
+        store::DataConn,
+    },
+    error::{NetdoxError, NetdoxResult},
+    store_err,
+};
+use async_trait::async_trait;
+use ipnet::IpNet;
+use itertools::{izip, Itertools};
+use tracing::{debug, warn};
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const DEFAULT_NETWORK_KEY: &str = "default_network";
+const DNS_IGNORE_KEY: &str = "dns_ignore";
+
+/// Record types that imply a record from their value back to their name.
+const IMPLYING_RTYPES: [&str; 4] = ["CNAME", "A", "AAAA", "PTR"];
+
+/// A field of a changelog entry and its value, with the ID of the entry.
+pub type ChangeField = ((i64, i64), String, String);
+
+/// The values in a data store, laid out the same as in redis.
+/// A key may hold a value of each type, like the tables of an SQLite store.
+/// Sets, hashes and lists that become empty are removed, as in redis.
+pub trait Keyspace {
+    fn get(&self, key: &str) -> NetdoxResult<Option<String>>;
+
+    fn set(&self, key: &str, value: &str) -> NetdoxResult<()>;
+
+    /// Removes the value at a key, whatever its type.
+    fn del(&self, key: &str) -> NetdoxResult<()>;
+
+    /// Returns every key that starts with the prefix, whatever the type of its value.
+    fn keys(&self, prefix: &str) -> NetdoxResult<HashSet<String>>;
+
+    /// Returns every key of a set that starts with the prefix.
+    fn set_keys(&self, prefix: &str) -> NetdoxResult<HashSet<String>>;
+
+    /// Moves the value at a key to another key, replacing any value there.
+    fn rename_key(&self, key: &str, new_key: &str) -> NetdoxResult<()>;
+
+    fn smembers(&self, key: &str) -> NetdoxResult<HashSet<String>>;
+
+    /// Reads up to `count` members of a set, after the member a cursor points to.
+    /// Returns the cursor to continue from, which is 0 once every member has been read.
+    fn sscan(&self, key: &str, cursor: u64, count: usize) -> NetdoxResult<(u64, Vec<String>)>;
+
+    fn sismember(&self, key: &str, member: &str) -> NetdoxResult<bool>;
+
+    fn scard(&self, key: &str) -> NetdoxResult<usize>;
+
+    /// Adds a member to a set. Returns true if it was not already a member.
+    fn sadd(&self, key: &str, member: &str) -> NetdoxResult<bool>;
+
+    /// Removes a member from a set. Returns true if it was a member.
+    fn srem(&self, key: &str, member: &str) -> NetdoxResult<bool>;
+
+    fn hgetall(&self, key: &str) -> NetdoxResult<HashMap<String, String>>;
+
+    fn hget(&self, key: &str, field: &str) -> NetdoxResult<Option<String>>;
+
+    fn hset(&self, key: &str, field: &str, value: &str) -> NetdoxResult<()>;
+
+    fn hsetnx(&self, key: &str, field: &str, value: &str) -> NetdoxResult<()>;
+
+    fn hdel(&self, key: &str, field: &str) -> NetdoxResult<()>;
+
+    fn lrange(&self, key: &str) -> NetdoxResult<Vec<String>>;
+
+    /// Adds a value to the end of a list.
+    fn rpush(&self, key: &str, value: &str) -> NetdoxResult<()>;
+
+    /// Adds a value to the start of a list.
+    fn lpush(&self, key: &str, value: &str) -> NetdoxResult<()>;
+
+    /// Removes all but the first `len` items in a list.
+    fn ltrim(&self, key: &str, len: usize) -> NetdoxResult<()>;
+
+    /// Returns the value at every key, whatever its type.
+    fn values(&self) -> NetdoxResult<BTreeMap<String, StoredValue>>;
+
+    /// Removes every value, leaving the changelog.
+    fn clear(&self) -> NetdoxResult<()>;
+
+    // Changelog
+
+    /// Sets a field of the changelog entry with an ID.
+    fn add_change_field(&self, id: (i64, i64), field: &str, value: &str) -> NetdoxResult<()>;
+
+    /// Returns the fields of the changelog entries with IDs after `start`, ordered by ID.
+    /// The fields of each entry are adjacent.
+    fn change_fields(&self, start: (i64, i64)) -> NetdoxResult<Vec<ChangeField>>;
+
+    /// Returns the ID of the last entry in the changelog.
+    fn last_change_id(&self) -> NetdoxResult<Option<(i64, i64)>>;
+
+    /// Returns the number of entries in the changelog.
+    fn change_count(&self) -> NetdoxResult<usize>;
+
+    /// Removes every entry from the changelog.
+    fn clear_changes(&self) -> NetdoxResult<()>;
+}
+
+/// Holds the keyspace of a `KeyspaceStore`.
+pub trait Backend: Clone + Send + Sync {
+    /// Calls the function with the keyspace.
+    fn read<T>(&self, func: impl FnOnce(&dyn Keyspace) -> NetdoxResult<T>) -> NetdoxResult<T>;
+
+    /// Calls the function with the keyspace. Its writes are only kept if it succeeds.
+    fn write<T>(&self, func: impl FnOnce(&dyn Keyspace) -> NetdoxResult<T>) -> NetdoxResult<T>;
+}
+
+/// A data store that lays out its keys the same as in redis, in a keyspace held by a backend.
+/// The functions that redis runs in Lua are implemented here instead.
+#[derive(Clone)]
+pub struct KeyspaceStore<B: Backend> {
+    backend: B,
+}
+
+impl<B: Backend> KeyspaceStore<B> {
+    pub(super) fn with_backend(backend: B) -> Self {
+        KeyspaceStore { backend }
+    }
+
+    /// Calls the function with the keyspace.
+    pub(crate) fn read<T>(
+        &self,
+        func: impl FnOnce(&dyn Keyspace) -> NetdoxResult<T>,
+    ) -> NetdoxResult<T> {
+        self.backend.read(func)
+    }
+
+    /// Calls the function with the keyspace. Its writes are only kept if it succeeds.
+    pub(crate) fn write<T>(
+        &self,
+        func: impl FnOnce(&dyn Keyspace) -> NetdoxResult<T>,
+    ) -> NetdoxResult<T> {
+        self.backend.write(func)
+    }
+}
+
+#[async_trait]
+impl<B: Backend> DataConn for KeyspaceStore<B> {
+    async fn auth(&mut self, _password: &str, _username: Option<&String>) -> NetdoxResult<()> {
+        store_err!("Local data stores do not support authentication.".to_string())
+    }
+
+    async fn setup(&mut self, cfg: &LocalConfig) -> NetdoxResult<()> {
+        debug!(
+            "Setting up SQLite with default network: {}",
+            cfg.default_network
+        );
+        if !cfg.lua_functions.is_empty() {
+            warn!(
+                "Ignoring {} additional Lua function libraries, as they can only be loaded into redis.",
+                cfg.lua_functions.len()
+            );
+        }
+        let dns_ignore = cfg.dns_ignore.names()?;
+        let ignore_patterns = cfg.dns_ignore.patterns()?;
+        let suffixes = cfg.network_suffixes()?;
+        let subnets = cfg.subnets()?;
+
+        self.write(|con| {
+            con.set(DEFAULT_NETWORK_KEY, &cfg.default_network)?;
+
+            con.del(DNS_IGNORE_KEY)?;
+            for name in &dns_ignore {
+                con.sadd(DNS_IGNORE_KEY, name)?;
+            }
+            con.del(DNS_IGNORE_PATTERNS_KEY)?;
+            for pattern in &ignore_patterns {
+                con.sadd(DNS_IGNORE_PATTERNS_KEY, pattern)?;
+            }
+
+            con.del(NETWORKS_KEY)?;
+            if !cfg.networks.is_empty() {
+                // Qualified names are lowercased on creation, except for the default network.
+                for network in cfg.networks.iter().map(|net| net.to_lowercase()) {
+                    con.sadd(NETWORKS_KEY, &network)?;
+                }
+                con.sadd(NETWORKS_KEY, &cfg.default_network)?;
+            }
+
+            con.del(NETWORK_SUFFIXES_KEY)?;
+            for (suffix, network) in &suffixes {
+                con.hset(NETWORK_SUFFIXES_KEY, suffix, network)?;
+            }
+
+            con.del(DAMPENING_KEY)?;
+            if let Some(dampening) = &cfg.dampening {
+                let window = (dampening.window_minutes * 60).to_string();
+                con.hset(DAMPENING_KEY, "window", &window)?;
+                con.hset(
+                    DAMPENING_KEY,
+                    "max_changes",
+                    &dampening.max_changes.to_string(),
+                )?;
+            }
+
+            con.del(CONFLICT_POLICY_KEY)?;
+            con.del(&format!("{CONFLICT_POLICY_KEY};priority"))?;
+            if !cfg.conflicts.is_default() {
+                let strategy = cfg.conflicts.strategy.to_string();
+                con.hset(CONFLICT_POLICY_KEY, "strategy", &strategy)?;
+                let report = cfg.conflicts.report.to_string();
+                con.hset(CONFLICT_POLICY_KEY, "report", &report)?;
+                rpush(
+                    con,
+                    &format!("{CONFLICT_POLICY_KEY};priority"),
+                    &cfg.conflicts.priority,
+                )?;
+            }
+
+            con.del(ROLES_KEY)?;
+            for role in &cfg.roles {
+                con.sadd(ROLES_KEY, role)?;
+            }
+
+            con.del(SUBNETS_KEY)?;
+            for subnet in &subnets {
+                con.sadd(SUBNETS_KEY, &subnet.to_string())?;
+            }
+
+            for plugin in con.smembers(PLUGIN_DISPLAY_KEY)? {
+                con.del(&format!("{PLUGIN_DISPLAY_KEY};{plugin}"))?;
+            }
+            con.del(PLUGIN_DISPLAY_KEY)?;
+
+            for plugin in &cfg.plugins {
+                let display = plugin.display.to_hash();
+                if display.is_empty() {
+                    continue;
+                }
+
+                let key = format!("{PLUGIN_DISPLAY_KEY};{}", plugin.name);
+                for (field, value) in &display {
+                    con.hset(&key, field, value)?;
+                }
+                con.sadd(PLUGIN_DISPLAY_KEY, &plugin.name)?;
+            }
+
+            for plugin in con.smembers(PLUGIN_SCOPES_KEY)? {
+                con.del(&format!("{PLUGIN_SCOPES_KEY};{plugin};networks"))?;
+                con.del(&format!("{PLUGIN_SCOPES_KEY};{plugin};zones"))?;
+            }
+            con.del(PLUGIN_SCOPES_KEY)?;
+
+            for plugin in &cfg.plugins {
+                if plugin.scope.is_empty() {
+                    continue;
+                }
+
+                let scope = plugin.scope.normalised();
+                for network in &scope.networks {
+                    con.sadd(
+                        &format!("{PLUGIN_SCOPES_KEY};{};networks", plugin.name),
+                        network,
+                    )?;
+                }
+                for zone in &scope.zones {
+                    con.sadd(&format!("{PLUGIN_SCOPES_KEY};{};zones", plugin.name), zone)?;
+                }
+                con.sadd(PLUGIN_SCOPES_KEY, &plugin.name)?;
+            }
+
+            // The upstream data may not have changed, but the config plugins are given might have.
+            con.del(PLUGIN_CURSORS_KEY)
+        })
+    }
+
+    async fn init(&mut self) -> NetdoxResult<()> {
+        self.write(|con| {
+            con.clear_changes()?;
+            create_change(con, "init", "", NETDOX_PLUGIN, &[])
+        })
+    }
+
+    async fn flush(&mut self) -> NetdoxResult<()> {
+        self.write(|con| {
+            con.clear()?;
+            con.clear_changes()
+        })
+    }
+
+    // DNS
+
+    async fn get_dns(&mut self) -> NetdoxResult<DNS> {
+        self.read(|con| {
+            let mut dns = DNS::new();
+            for qname in con.smembers(DNS_KEY)? {
+                for record in get_dns_records(con, &qname)? {
+                    dns.add_record(record);
+                }
+
+                dns.qnames.insert(qname);
+            }
+
+            Ok(dns)
+        })
+    }
+
+    async fn get_dns_subset(&mut self, names: &[&str]) -> NetdoxResult<DNS> {
+        self.read(|con| {
+            let mut dns = DNS::new();
+            let mut seen = HashSet::new();
+            let mut queue = names
+                .iter()
+                .map(|name| (*name).to_string())
+                .collect::<Vec<_>>();
+
+            while let Some(qname) = queue.pop() {
+                if !seen.insert(qname.clone()) {
+                    continue;
+                }
+
+                for record in get_dns_records(con, &qname)? {
+                    if ADDRESS_RTYPES.contains(&record.rtype.as_str()) {
+                        queue.push(record.value.clone());
+                    }
+                    dns.add_record(record);
+                }
+
+                queue.extend(con.smembers(&format!("{DNS_KEY};{qname};implied"))?);
+
+                for (network, name) in con.hgetall(&format!("{DNS_KEY};{qname};maps"))? {
+                    let dest = format!("{network}{name}");
+                    dns.add_translation(&qname, &dest);
+                    queue.push(dest);
+                }
+
+                dns.qnames.insert(qname);
+            }
+
+            debug!(
+                "Loaded {} DNS names reachable from: {}",
+                dns.qnames.len(),
+                names.join(", ")
+            );
+
+            Ok(dns)
+        })
+    }
+
+    async fn get_dns_names(&mut self) -> NetdoxResult<HashSet<String>> {
+        self.read(|con| con.smembers(DNS_KEY))
+    }
+
+    async fn scan_dns(&mut self, cursor: u64, count: usize) -> NetdoxResult<(u64, DNS)> {
+        self.read(|con| {
+            let (next, qnames) = con.sscan(DNS_KEY, cursor, count)?;
+            let mut dns = DNS::new();
+            for qname in qnames {
+                for record in get_dns_records(con, &qname)? {
+                    dns.add_record(record);
+                }
+                dns.qnames.insert(qname);
+            }
+
+            Ok((next, dns))
+        })
+    }
+
+    async fn dns_name_exists(&mut self, qname: &str) -> NetdoxResult<bool> {
+        self.read(|con| con.sismember(DNS_KEY, qname))
+    }
+
+    async fn qualify_dns_names(&mut self, names: &[&str]) -> NetdoxResult<Vec<String>> {
+        self.read(|con| {
+            names
+                .iter()
+                .map(|name| qualify_dns_name(con, name))
+                .collect()
+        })
+    }
+
+    async fn get_networks(&mut self) -> NetdoxResult<HashSet<String>> {
+        self.read(|con| con.smembers(NETWORKS_KEY))
+    }
+
+    async fn get_dns_ignore_patterns(&mut self) -> NetdoxResult<HashSet<String>> {
+        self.read(|con| con.smembers(DNS_IGNORE_PATTERNS_KEY))
+    }
+
+    async fn get_subnets(&mut self) -> NetdoxResult<HashSet<IpNet>> {
+        self.read(|con| {
+            let mut parsed = HashSet::new();
+            for subnet in con.smembers(SUBNETS_KEY)? {
+                match subnet.parse() {
+                    Ok(net) => {
+                        parsed.insert(net);
+                    }
+                    Err(err) => {
+                        return store_err!(format!("Invalid configured subnet {subnet}: {err}"))
+                    }
+                }
+            }
+            Ok(parsed)
+        })
+    }
+
+    // Nodes
+
+    async fn get_roles(&mut self) -> NetdoxResult<HashSet<String>> {
+        self.read(|con| con.smembers(ROLES_KEY))
+    }
+
+    async fn get_raw_nodes(&mut self) -> NetdoxResult<Vec<RawNode>> {
+        self.read(|con| {
+            let mut raw = vec![];
+            for node in con.smembers(NODES_KEY)? {
+                raw.extend(get_raw_node_group(con, &node)?);
+            }
+
+            Ok(raw)
+        })
+    }
+
+    async fn scan_raw_nodes(
+        &mut self,
+        cursor: u64,
+        count: usize,
+    ) -> NetdoxResult<(u64, Vec<RawNode>)> {
+        self.read(|con| {
+            let (next, nodes) = con.sscan(NODES_KEY, cursor, count)?;
+            let mut raw = vec![];
+            for node in nodes {
+                raw.extend(get_raw_node_group(con, &node)?);
+            }
+
+            Ok((next, raw))
+        })
+    }
+
+    async fn get_node(&mut self, id: &str) -> NetdoxResult<Node> {
+        self.read(|con| get_node(con, id))
+    }
+
+    async fn get_node_ids(&mut self) -> NetdoxResult<HashSet<String>> {
+        self.read(|con| con.smembers(PROC_NODES_KEY))
+    }
+
+    async fn scan_nodes(&mut self, cursor: u64, count: usize) -> NetdoxResult<(u64, Vec<Node>)> {
+        self.read(|con| {
+            let (next, ids) = con.sscan(PROC_NODES_KEY, cursor, count)?;
+            let nodes = ids
+                .iter()
+                .map(|id| get_node(con, id))
+                .collect::<NetdoxResult<_>>()?;
+            Ok((next, nodes))
+        })
+    }
+
+    async fn get_node_from_raw(&mut self, raw_id: &str) -> NetdoxResult<Option<String>> {
+        self.read(|con| con.hget(PROC_NODE_REVS_KEY, raw_id))
+    }
+
+    async fn get_raw_id_from_qnames(&mut self, qnames: &[&str]) -> NetdoxResult<String> {
+        let mut qnames = self.qualify_dns_names(qnames).await?;
+        qnames.sort();
+
+        Ok(qnames.join(";"))
+    }
+
+    async fn get_node_dependencies(&mut self, link_id: &str) -> NetdoxResult<Vec<Dependency>> {
+        self.read(|con| {
+            let node_ids = con.smembers(PROC_NODES_KEY)?;
+            let deps_key = format!("{DEPENDENCIES_KEY};{link_id}");
+
+            let mut dependencies = vec![];
+            for (dest, label) in con.hgetall(&deps_key)? {
+                if node_ids.contains(&dest) {
+                    dependencies.push(Dependency::new(link_id, &dest, label));
+                }
+            }
+
+            for source in con.smembers(&format!("{deps_key};dependents"))? {
+                if !node_ids.contains(&source) {
+                    continue;
+                }
+                if let Some(label) = con.hget(&format!("{DEPENDENCIES_KEY};{source}"), link_id)? {
+                    dependencies.push(Dependency::new(&source, link_id, label));
+                }
+            }
+
+            dependencies
+                .sort_by(|lhs, rhs| (&lhs.source, &lhs.dest).cmp(&(&rhs.source, &rhs.dest)));
+            Ok(dependencies)
+        })
+    }
+
+    async fn get_node_relations(&mut self, link_id: &str) -> NetdoxResult<Vec<Relation>> {
+        self.read(|con| {
+            let node_ids = con.smembers(PROC_NODES_KEY)?;
+            let relations_key = format!("{RELATIONS_KEY};{link_id}");
+
+            let outgoing = con.smembers(&relations_key)?;
+            let incoming = con.smembers(&format!("{relations_key};incoming"))?;
+            let mut relations = outgoing
+                .iter()
+                .filter_map(|member| Relation::parse(link_id, member, false))
+                .chain(
+                    incoming
+                        .iter()
+                        .filter_map(|member| Relation::parse(link_id, member, true)),
+                )
+                .filter(|relation| node_ids.contains(relation.other(link_id)))
+                .collect::<Vec<_>>();
+
+            relations.sort_by(|lhs, rhs| {
+                (&lhs.kind, &lhs.source, &lhs.dest).cmp(&(&rhs.kind, &rhs.source, &rhs.dest))
+            });
+            Ok(relations)
+        })
+    }
+
+    async fn get_node_services(&mut self, link_id: &str) -> NetdoxResult<Vec<Service>> {
+        self.read(|con| {
+            let services_key = format!("{SERVICES_KEY};{link_id}");
+
+            let mut services = vec![];
+            for id in con.smembers(&services_key)? {
+                let Some((port, protocol)) = Service::parse_id(&id) else {
+                    return store_err!(format!("Invalid service {id} on node {link_id}"));
+                };
+
+                let service_key = format!("{services_key};{id}");
+                services.push(Service {
+                    link_id: link_id.to_string(),
+                    port,
+                    protocol,
+                    details: con.hgetall(&service_key)?,
+                    plugins: con.smembers(&format!("{service_key};plugins"))?,
+                });
+            }
+
+            services.sort_by(|lhs, rhs| (lhs.port, &lhs.protocol).cmp(&(rhs.port, &rhs.protocol)));
+            Ok(services)
+        })
+    }
+
+    // Plugin Data
+
+    async fn get_data(&mut self, key: &str) -> NetdoxResult<Data> {
+        self.read(|con| get_data(con, key))
+    }
+
+    async fn get_dns_pdata(&mut self, qname: &str) -> NetdoxResult<Vec<Data>> {
+        self.read(|con| get_pdata(con, &format!("{DNS_KEY};{qname}")))
+    }
+
+    async fn get_node_pdata(&mut self, node: &Node) -> NetdoxResult<Vec<Data>> {
+        self.read(|con| {
+            let mut dataset = vec![];
+            for raw in &node.raw_ids {
+                dataset.extend(get_pdata(con, &format!("{NODES_KEY};{raw}"))?);
+            }
+            dataset.extend(get_pdata(
+                con,
+                &format!("{PROC_NODES_KEY};{}", node.link_id),
+            )?);
+
+            Ok(dataset)
+        })
+    }
+
+    async fn delete_dns_pdata(
+        &mut self,
+        qname: &str,
+        plugin: &str,
+        data_id: &str,
+    ) -> NetdoxResult<bool> {
+        self.write(|con| delete_pdata(con, &format!("{DNS_KEY};{qname}"), plugin, data_id))
+    }
+
+    async fn delete_node_pdata(
+        &mut self,
+        node: &Node,
+        plugin: &str,
+        data_id: &str,
+    ) -> NetdoxResult<bool> {
+        self.write(|con| {
+            let mut deleted = false;
+            for raw in &node.raw_ids {
+                deleted |= delete_pdata(con, &format!("{NODES_KEY};{raw}"), plugin, data_id)?;
+            }
+            deleted |= delete_pdata(
+                con,
+                &format!("{PROC_NODES_KEY};{}", node.link_id),
+                plugin,
+                data_id,
+            )?;
+
+            Ok(deleted)
+        })
+    }
+
+    // Reports
+
+    async fn get_report_ids(&mut self) -> NetdoxResult<HashSet<String>> {
+        self.read(|con| con.smembers(REPORTS_KEY))
+    }
+
+    async fn get_report(&mut self, id: &str) -> NetdoxResult<Report> {
+        self.read(|con| {
+            let details = con.hgetall(&format!("{REPORTS_KEY};{id}"))?;
+
+            let plugin = match details.get("plugin") {
+                Some(plugin) => plugin.to_owned(),
+                None => {
+                    return store_err!(format!("Failed to get plugin for report with id: {id}"))
+                }
+            };
+
+            let title = match details.get("title") {
+                Some(title) => title.to_owned(),
+                None => return store_err!(format!("Failed to get title for report with id: {id}")),
+            };
+
+            let length = match details.get("length") {
+                Some(length) => match length.parse::<usize>() {
+                    Ok(int) => int,
+                    Err(_err) => {
+                        return store_err!(format!(
+                            "Failed to parse length {length} of report {id} as an int."
+                        ))
+                    }
+                },
+                None => {
+                    return store_err!(format!("Failed to get length for report with id: {id}"))
+                }
+            };
+
+            let mut content = Vec::with_capacity(length);
+            for i in 0..length {
+                content.push(get_data(con, &format!("{REPORTS_KEY};{id};{i}"))?);
+            }
+
+            Ok(Report {
+                id: id.to_string(),
+                title,
+                plugin,
+                content,
+            })
+        })
+    }
+
+    async fn put_report(&mut self, id: &str, title: &str, length: usize) -> NetdoxResult<()> {
+        self.write(|con| {
+            let mut changed = con.sadd(REPORTS_KEY, id)?;
+
+            let key = format!("{REPORTS_KEY};{id}");
+            let length = length.to_string();
+            changed |= sync_fields(
+                con,
+                &key,
+                &[
+                    ("plugin", NETDOX_PLUGIN),
+                    ("title", title),
+                    ("length", &length),
+                ],
+            )?;
+
+            if changed {
+                create_change(
+                    con,
+                    "create report",
+                    id,
+                    NETDOX_PLUGIN,
+                    &[("report_id", id)],
+                )?;
+            }
+
+            Ok(())
+        })
+    }
+
+    async fn put_report_data(&mut self, id: &str, idx: usize, data: &Data) -> NetdoxResult<()> {
+        self.write(|con| {
+            let obj_id = format!("{REPORTS_KEY};{id}");
+            let idx = idx.to_string();
+            create_data(
+                con,
+                &format!("{obj_id};{idx}"),
+                &[("obj_id", &obj_id), ("data_id", &idx), ("kind", "report")],
+                data,
+            )
+        })
+    }
+
+    // Metadata
+
+    async fn get_dns_metadata(&mut self, qname: &str) -> NetdoxResult<HashMap<String, String>> {
+        self.read(|con| con.hgetall(&format!("{METADATA_KEY};{DNS_KEY};{qname}")))
+    }
+
+    async fn put_dns_metadata(
+        &mut self,
+        qname: &str,
+        plugin: &str,
+        data: HashMap<&str, &str>,
+    ) -> NetdoxResult<()> {
+        debug!("Writing metadata for DNS name {qname} from plugin {plugin}: {data:?}");
+        self.write(|con| {
+            let qname = qualify_dns_name(con, qname)?;
+            if !in_scope(con, plugin, &qname)? {
+                return Ok(());
+            }
+            create_dns_name(con, &qname, plugin)?;
+            create_metadata(con, &format!("{DNS_KEY};{qname}"), plugin, &data)
+        })
+    }
+
+    async fn get_proc_node_metadata(
+        &mut self,
+        node_id: &str,
+    ) -> NetdoxResult<HashMap<String, String>> {
+        self.read(|con| con.hgetall(&format!("{METADATA_KEY};{PROC_NODES_KEY};{node_id}")))
+    }
+
+    async fn get_node_metadata(&mut self, node: &Node) -> NetdoxResult<HashMap<String, String>> {
+        self.read(|con| {
+            let (provenance, _) = get_node_metadata_merged(con, node)?;
+            Ok(provenance
+                .into_iter()
+                .map(|(key, meta)| (key, meta.value))
+                .collect())
+        })
+    }
+
+    async fn get_dns_metadata_sources(
+        &mut self,
+        qname: &str,
+    ) -> NetdoxResult<HashMap<String, String>> {
+        self.read(|con| con.hgetall(&format!("{METADATA_KEY};{DNS_KEY};{qname};sources")))
+    }
+
+    async fn get_dns_metadata_provenance(
+        &mut self,
+        qname: &str,
+    ) -> NetdoxResult<HashMap<String, MetadataProvenance>> {
+        self.read(|con| get_metadata_provenance(con, &format!("{METADATA_KEY};{DNS_KEY};{qname}")))
+    }
+
+    async fn get_node_metadata_provenance(
+        &mut self,
+        node: &Node,
+    ) -> NetdoxResult<HashMap<String, MetadataProvenance>> {
+        self.read(|con| Ok(get_node_metadata_merged(con, node)?.0))
+    }
+
+    async fn get_conflict_policy(&mut self) -> NetdoxResult<ConflictPolicy> {
+        self.read(get_conflict_policy)
+    }
+
+    async fn get_node_metadata_conflicts(&mut self, node: &Node) -> NetdoxResult<Vec<Conflict>> {
+        self.read(|con| Ok(get_node_metadata_merged(con, node)?.1))
+    }
+
+    async fn get_node_metadata_sources(
+        &mut self,
+        node: &Node,
+    ) -> NetdoxResult<HashMap<String, String>> {
+        self.read(|con| {
+            let (provenance, _) = get_node_metadata_merged(con, node)?;
+            Ok(provenance
+                .into_iter()
+                .filter_map(|(key, meta)| meta.plugin.map(|plugin| (key, plugin)))
+                .collect())
+        })
+    }
+
+    async fn put_node_metadata(
+        &mut self,
+        node_id: &str,
+        plugin: &str,
+        data: HashMap<&str, &str>,
+    ) -> NetdoxResult<()> {
+        self.write(|con| {
+            create_metadata(con, &format!("{PROC_NODES_KEY};{node_id}"), plugin, &data)
+        })
+    }
+
+    async fn migrate_metadata(&mut self, dry_run: bool) -> NetdoxResult<MetadataMigration> {
+        self.write(|con| {
+            // Legacy object IDs are qualified DNS names or raw node IDs, both of which start with [network].
+            let mut legacy = HashSet::new();
+            for key in con.keys(&format!("{METADATA_KEY};["))? {
+                let id = &key[METADATA_KEY.len() + 1..];
+                legacy.insert(id.strip_suffix(";plugins").unwrap_or(id).to_string());
+            }
+
+            let mut migration = MetadataMigration {
+                moved: vec![],
+                unresolved: vec![],
+            };
+            for id in legacy {
+                let is_dns = con.sismember(DNS_KEY, &id)?;
+                let is_node = con.sismember(NODES_KEY, &id)?;
+
+                if is_dns == is_node {
+                    migration.unresolved.push(id);
+                    continue;
+                }
+
+                let new_id = format!("{};{id}", if is_dns { DNS_KEY } else { NODES_KEY });
+                if !dry_run {
+                    let old_key = format!("{METADATA_KEY};{id}");
+                    let new_key = format!("{METADATA_KEY};{new_id}");
+
+                    // Values written under the new layout are newer, so they are kept.
+                    for (field, value) in con.hgetall(&old_key)? {
+                        con.hsetnx(&new_key, &field, &value)?;
+                    }
+                    for plugin in con.smembers(&format!("{old_key};plugins"))? {
+                        con.sadd(&format!("{new_key};plugins"), &plugin)?;
+                    }
+
+                    con.del(&old_key)?;
+                    con.del(&format!("{old_key};plugins"))?;
+                    con.srem(METADATA_KEY, &id)?;
+                    con.sadd(METADATA_KEY, &new_id)?;
+                    create_change(
+                        con,
+                        "updated metadata",
+                        &new_key,
+                        NETDOX_PLUGIN,
+                        &[("obj_id", &new_id)],
+                    )?;
+                }
+                migration.moved.push((id, new_id));
+            }
+
+            migration.moved.sort();
+            migration.unresolved.sort();
+            Ok(migration)
+        })
+    }
+
+    // Notes
+
+    async fn get_notes(&mut self, obj_id: &str) -> NetdoxResult<HashMap<String, String>> {
+        self.read(|con| con.hgetall(&format!("{NOTES_KEY};{obj_id}")))
+    }
+
+    async fn put_note(&mut self, obj_id: &str, key: &str, value: Option<&str>) -> NetdoxResult<()> {
+        debug!("Setting note {key} on {obj_id} to: {value:?}");
+        self.write(|con| {
+            let notes_key = format!("{NOTES_KEY};{obj_id}");
+            match value {
+                Some(value) => con.hset(&notes_key, key, value)?,
+                None => con.hdel(&notes_key, key)?,
+            }
+
+            create_change(
+                con,
+                "updated notes",
+                &notes_key,
+                NETDOX_PLUGIN,
+                &[("obj_id", obj_id)],
+            )
+        })
+    }
+
+    // Tags
+
+    async fn get_tags(&mut self) -> NetdoxResult<HashSet<String>> {
+        self.read(|con| con.smembers(TAGS_KEY))
+    }
+
+    async fn get_tagged(&mut self, tag: &str) -> NetdoxResult<HashSet<String>> {
+        self.read(|con| con.smembers(&format!("{TAGS_KEY};{tag}")))
+    }
+
+    async fn get_dns_tags(&mut self, qname: &str) -> NetdoxResult<HashSet<String>> {
+        self.read(|con| con.smembers(&format!("{METADATA_KEY};{DNS_KEY};{qname};tags")))
+    }
+
+    async fn get_node_tags(&mut self, node: &Node) -> NetdoxResult<HashSet<String>> {
+        self.read(|con| {
+            let mut tags = HashSet::new();
+            for raw_id in &node.raw_ids {
+                tags.extend(con.smembers(&format!("{METADATA_KEY};{NODES_KEY};{raw_id};tags"))?);
+            }
+            tags.extend(con.smembers(&format!(
+                "{METADATA_KEY};{PROC_NODES_KEY};{};tags",
+                node.link_id
+            ))?);
+
+            Ok(tags)
+        })
+    }
+
+    // Plugins
+
+    async fn get_plugin_displays(&mut self) -> NetdoxResult<HashMap<String, PluginDisplay>> {
+        self.read(|con| {
+            let mut displays = HashMap::new();
+            for plugin in con.smembers(PLUGIN_DISPLAY_KEY)? {
+                let details = con.hgetall(&format!("{PLUGIN_DISPLAY_KEY};{plugin}"))?;
+                displays.insert(plugin, PluginDisplay::from_hash(details));
+            }
+
+            Ok(displays)
+        })
+    }
+
+    // Documents
+
+    async fn get_dns_bundle(&mut self, qname: &str) -> NetdoxResult<DnsBundle> {
+        Ok(DnsBundle {
+            dns: self.get_dns_subset(&[qname]).await?,
+            tags: self.get_dns_tags(qname).await?,
+            metadata: self.get_dns_metadata_provenance(qname).await?,
+            notes: self.get_notes(&format!("{DNS_KEY};{qname}")).await?,
+            pdata: self.get_dns_pdata(qname).await?,
+            displays: self.get_plugin_displays().await?,
+        })
+    }
+
+    async fn get_node_bundle(&mut self, node: &Node) -> NetdoxResult<NodeBundle> {
+        Ok(NodeBundle {
+            tags: self.get_node_tags(node).await?,
+            metadata: self.get_node_metadata_provenance(node).await?,
+            notes: self
+                .get_notes(&format!("{PROC_NODES_KEY};{}", node.link_id))
+                .await?,
+            dependencies: self.get_node_dependencies(&node.link_id).await?,
+            relations: self.get_node_relations(&node.link_id).await?,
+            services: self.get_node_services(&node.link_id).await?,
+            pdata: self.get_node_pdata(node).await?,
+            displays: self.get_plugin_displays().await?,
+        })
+    }
+
+    // Changelog
+
+    async fn get_changes(&mut self, start_id: Option<&str>) -> NetdoxResult<Vec<ChangelogEntry>> {
+        let start = match start_id {
+            Some(id) => parse_change_id(id)?,
+            None => (-1, 0),
+        };
+
+        let changes = self.read(|con| {
+            let rows = con.change_fields(start)?;
+
+            // Rows for the fields of each entry are adjacent.
+            let mut entries: Vec<((i64, i64), HashMap<String, String>)> = vec![];
+            for ((millis, seq), field, value) in rows {
+                match entries.last_mut() {
+                    Some((id, fields)) if *id == (millis, seq) => {
+                        fields.insert(field, value);
+                    }
+                    _ => entries.push(((millis, seq), HashMap::from([(field, value)]))),
+                }
+            }
+
+            let mut changes = Vec::with_capacity(entries.len());
+            for ((millis, seq), fields) in entries {
+                match ChangelogEntry::from_fields(&format!("{millis}-{seq}"), fields) {
+                    Ok(change) => changes.push(change),
+                    Err(err) => {
+                        return store_err!(format!(
+                            "Failed to parse changelog entry {millis}-{seq}: {err}"
+                        ))
+                    }
+                }
+            }
+
+            Ok(changes)
+        })?;
+
+        debug!(
+            "Read {} changes from changelog after: {}",
+            changes.len(),
+            start_id.unwrap_or("start")
+        );
+        Ok(changes)
+    }
+
+    async fn last_change_id(&mut self) -> NetdoxResult<String> {
+        match self.read(|con| con.last_change_id())? {
+            Some((millis, seq)) => Ok(format!("{millis}-{seq}")),
+            None => {
+                store_err!("Found 0 changes in changelog when trying to get last one.".to_string())
+            }
+        }
+    }
+
+    async fn total_change_count(&mut self) -> NetdoxResult<usize> {
+        self.read(|con| con.change_count())
+    }
+
+    // Indexes
+
+    async fn rebuild_index(&mut self) -> NetdoxResult<IndexRepairs> {
+        self.write(|con| {
+            let mut dns_nodes = HashMap::new();
+            let mut node_revs = HashMap::new();
+            for link_id in con.smembers(PROC_NODES_KEY)? {
+                let node_key = format!("{PROC_NODES_KEY};{link_id}");
+                for qname in con.smembers(&format!("{node_key};dns_names"))? {
+                    dns_nodes.insert(qname, link_id.clone());
+                }
+                for raw_id in con.smembers(&format!("{node_key};raw_ids"))? {
+                    node_revs.insert(raw_id, link_id.clone());
+                }
+            }
+
+            // Every piece of plugin data has a details hash at pdata;${OBJECT_KEY};${DATA_ID};details
+            let mut pdata_sets: HashMap<String, HashSet<String>> = HashMap::new();
+            for details_key in con.keys(&format!("{PDATA_KEY};"))? {
+                if let Some((set_key, pdata_id)) = details_key
+                    .strip_suffix(";details")
+                    .and_then(|key| key.rsplit_once(';'))
+                {
+                    pdata_sets
+                        .entry(set_key.to_string())
+                        .or_default()
+                        .insert(pdata_id.to_string());
+                }
+            }
+
+            let mut implied: HashMap<String, HashSet<String>> = HashMap::new();
+            let qnames = con.smembers(DNS_KEY)?;
+            for qname in &qnames {
+                for record in get_dns_records(con, qname)? {
+                    if IMPLYING_RTYPES.contains(&record.rtype.as_str()) {
+                        implied
+                            .entry(record.value)
+                            .or_default()
+                            .insert(qname.clone());
+                    }
+                }
+            }
+
+            let mut dns_implied = 0;
+            for qname in &qnames {
+                dns_implied += sync_set(
+                    con,
+                    &format!("{DNS_KEY};{qname};implied"),
+                    &implied.remove(qname).unwrap_or_default(),
+                )?;
+            }
+
+            let mut pdata = 0;
+            for key in con.set_keys(&format!("{PDATA_KEY};"))? {
+                if !pdata_sets.contains_key(&key) {
+                    pdata += con.scard(&key)?;
+                    con.del(&key)?;
+                }
+            }
+            for (set_key, members) in &pdata_sets {
+                pdata += sync_set(con, set_key, members)?;
+            }
+
+            Ok(IndexRepairs {
+                dns_nodes: sync_hash(con, DNS_NODES_KEY, &dns_nodes)?,
+                proc_node_revs: sync_hash(con, PROC_NODE_REVS_KEY, &node_revs)?,
+                dns_implied,
+                pdata,
+            })
+        })
+    }
+
+    async fn rename_network(
+        &mut self,
+        old: &str,
+        new: &str,
+        dry_run: bool,
+    ) -> NetdoxResult<NetworkRename> {
+        for network in [old, new] {
+            if !is_network_name(network) {
+                return store_err!(format!("Invalid network name: {network}"));
+            }
+        }
+        let (old_prefix, new_prefix) = (format!("[{old}]"), format!("[{new}]"));
+
+        self.write(|con| {
+            let mut qnames = HashMap::new();
+            for qname in con.smembers(DNS_KEY)? {
+                if let Some(new_qname) = rename_qname(&qname, &old_prefix, &new_prefix) {
+                    if con.sismember(DNS_KEY, &new_qname)? {
+                        return store_err!(format!(
+                            "Cannot move DNS name {qname} to {new_qname} because it already exists."
+                        ));
+                    }
+                    qnames.insert(qname, new_qname);
+                }
+            }
+
+            let mut node_ids = HashMap::new();
+            for node_id in con.smembers(NODES_KEY)? {
+                if let Some(new_id) = rename_node_id(&node_id, &old_prefix, &new_prefix) {
+                    if con.sismember(NODES_KEY, &new_id)? {
+                        return store_err!(format!(
+                            "Cannot move node {node_id} to {new_id} because it already exists."
+                        ));
+                    }
+                    node_ids.insert(node_id, new_id);
+                }
+            }
+
+            let rename = NetworkRename {
+                dns_names: qnames.clone().into_iter().sorted().collect(),
+                nodes: node_ids.clone().into_iter().sorted().collect(),
+            };
+            if dry_run {
+                return Ok(rename);
+            }
+
+            // Keys are moved first, so references are rewritten under the new keys.
+            for key in con.keys("")? {
+                if !key.contains(&old_prefix) {
+                    continue;
+                }
+                if let Some(new_key) = rename_object_key(&key, &old_prefix, &new_prefix, &node_ids)
+                {
+                    con.rename_key(&key, &new_key)?;
+                }
+            }
+
+            let rename_ref = |reference: &str| {
+                node_ids
+                    .get(reference)
+                    .cloned()
+                    .or_else(|| rename_qname(reference, &old_prefix, &new_prefix))
+                    .or_else(|| rename_object_key(reference, &old_prefix, &new_prefix, &node_ids))
+            };
+
+            for key in [DNS_KEY, NODES_KEY, METADATA_KEY, DNS_IGNORE_KEY] {
+                rename_members(con, key, rename_ref)?;
+            }
+            for tag in con.smembers(TAGS_KEY)? {
+                rename_members(con, &format!("{TAGS_KEY};{tag}"), rename_ref)?;
+            }
+            for key in [DNS_NODES_KEY, PROC_NODE_REVS_KEY] {
+                rename_fields(con, key, rename_ref)?;
+            }
+            for link_id in con.smembers(PROC_NODES_KEY)? {
+                let node_key = format!("{PROC_NODES_KEY};{link_id}");
+                rename_members(con, &format!("{node_key};dns_names"), rename_ref)?;
+                rename_members(con, &format!("{node_key};raw_ids"), rename_ref)?;
+            }
+
+            // Names outside the network may have records that point into it.
+            let moved = qnames.values().collect::<HashSet<_>>();
+            for qname in con.smembers(DNS_KEY)? {
+                let dns_key = format!("{DNS_KEY};{qname}");
+                let rename_record = |record: &str| {
+                    let mut parts = record.splitn(3, ';');
+                    let (plugin, rtype) = (parts.next()?, parts.next()?);
+                    let value = rename_qname(parts.next()?, &old_prefix, &new_prefix)?;
+                    Some(format!("{plugin};{rtype};{value}"))
+                };
+                let records = rename_members(con, &dns_key, rename_record)?;
+                rename_fields(con, &format!("{dns_key};seen"), rename_record)?;
+                let implied = rename_members(con, &format!("{dns_key};implied"), rename_ref)?;
+                let maps = rename_fields(con, &format!("{dns_key};maps"), rename_ref)?;
+
+                if moved.contains(&qname) || records || implied || maps {
+                    create_change(
+                        con,
+                        "create dns name",
+                        &qname,
+                        NETDOX_PLUGIN,
+                        &[("qname", &qname)],
+                    )?;
+                }
+            }
+
+            for new_id in node_ids.values() {
+                create_change(
+                    con,
+                    "create plugin node",
+                    new_id,
+                    NETDOX_PLUGIN,
+                    &[("node_id", new_id)],
+                )?;
+            }
+
+            if con.srem(NETWORKS_KEY, old)? {
+                con.sadd(NETWORKS_KEY, new)?;
+            }
+            if con.get(DEFAULT_NETWORK_KEY)?.as_deref() == Some(old) {
+                con.set(DEFAULT_NETWORK_KEY, new)?;
+            }
+            for (suffix, network) in con.hgetall(NETWORK_SUFFIXES_KEY)? {
+                if network == old {
+                    con.hset(NETWORK_SUFFIXES_KEY, &suffix, new)?;
+                }
+            }
+
+            Ok(rename)
+        })
+    }
+
+    async fn delete_dns(&mut self, qname: &str, dry_run: bool) -> NetdoxResult<DnsDeletion> {
+        self.write(|con| {
+            if !con.sismember(DNS_KEY, qname)? {
+                return store_err!(format!("No DNS name {qname}"));
+            }
+            let Some((network, name)) = split_qname(qname) else {
+                return store_err!(format!("DNS name {qname} is not qualified"));
+            };
+            let dns_key = format!("{DNS_KEY};{qname}");
+            let mut deletion = DnsDeletion::default();
+            // Other DNS names whose documents change.
+            let mut changed = HashSet::new();
+
+            for record in get_dns_records(con, qname)? {
+                if !dry_run {
+                    create_change(
+                        con,
+                        "delete dns record",
+                        &format!(
+                            "{dns_key};{};{};{}",
+                            record.plugin, record.rtype, record.value
+                        ),
+                        &record.plugin,
+                        &[
+                            ("qname", qname),
+                            ("rtype", &record.rtype),
+                            ("rvalue", &record.value),
+                        ],
+                    )?;
+                    if IMPLYING_RTYPES.contains(&record.rtype.as_str()) {
+                        con.srem(&format!("{DNS_KEY};{};implied", record.value), qname)?;
+                        changed.insert(record.value.clone());
+                    }
+                }
+                deletion.records.push(record);
+            }
+
+            for other in con.smembers(DNS_KEY)? {
+                if other == qname {
+                    continue;
+                }
+                let other_key = format!("{DNS_KEY};{other}");
+                for record in get_dns_records(con, &other)? {
+                    if record.value != qname {
+                        continue;
+                    }
+                    if !dry_run {
+                        let member = format!("{};{};{}", record.plugin, record.rtype, record.value);
+                        con.srem(&other_key, &member)?;
+                        con.hdel(&format!("{other_key};seen"), &member)?;
+                        create_change(
+                            con,
+                            "delete dns record",
+                            &format!("{other_key};{member}"),
+                            &record.plugin,
+                            &[
+                                ("qname", &other),
+                                ("rtype", &record.rtype),
+                                ("rvalue", &record.value),
+                            ],
+                        )?;
+                    }
+                    deletion.references.push(record);
+                }
+
+                let maps_key = format!("{other_key};maps");
+                let network = format!("[{network}]");
+                if !dry_run && con.hget(&maps_key, &network)?.as_deref() == Some(name) {
+                    con.hdel(&maps_key, &network)?;
+                    changed.insert(other);
+                }
+            }
+
+            // Raw nodes with no details left.
+            let mut deleted_nodes = HashSet::new();
+            for node_id in con.smembers(NODES_KEY)? {
+                if !node_id.split(';').any(|node_qname| node_qname == qname) {
+                    continue;
+                }
+
+                let node_key = format!("{NODES_KEY};{node_id}");
+                let count = con
+                    .get(&node_key)?
+                    .and_then(|count| count.parse::<usize>().ok())
+                    .unwrap_or_default();
+                let mut plugins = vec![];
+                for index in 1..=count {
+                    let plugin = con
+                        .hget(&format!("{node_key};{index}"), "plugin")?
+                        .unwrap_or_default();
+                    deletion.nodes.push((node_id.clone(), plugin.clone()));
+                    plugins.push(plugin);
+                }
+
+                if !dry_run {
+                    for index in 1..=count {
+                        con.del(&format!("{node_key};{index}"))?;
+                    }
+                    con.del(&node_key)?;
+                    con.del(&format!("{node_key};seen"))?;
+                    con.srem(NODES_KEY, &node_id)?;
+                    delete_object_data(con, &node_key)?;
+                    for plugin in plugins {
+                        create_change(
+                            con,
+                            "delete plugin node",
+                            &node_id,
+                            &plugin,
+                            &[("node_id", &node_id)],
+                        )?;
+                    }
+                }
+                deleted_nodes.insert(node_id);
+            }
+
+            for link_id in con.smembers(PROC_NODES_KEY)? {
+                let dns_names_key = format!("{PROC_NODES_KEY};{link_id};dns_names");
+                if is_orphaned(con, &link_id, &deleted_nodes)? {
+                    if !dry_run {
+                        delete_proc_node(con, &link_id)?;
+                    }
+                    deletion.proc_nodes.push(link_id);
+                } else if con.sismember(&dns_names_key, qname)? {
+                    if !dry_run {
+                        con.srem(&dns_names_key, qname)?;
+                    }
+                    deletion.detached.push(link_id);
+                }
+            }
+
+            if !dry_run {
+                con.srem(DNS_KEY, qname)?;
+                for suffix in ["", ";seen", ";implied", ";maps"] {
+                    con.del(&format!("{dns_key}{suffix}"))?;
+                }
+                con.hdel(DNS_NODES_KEY, qname)?;
+                delete_object_data(con, &dns_key)?;
+                create_change(
+                    con,
+                    "delete dns name",
+                    qname,
+                    NETDOX_PLUGIN,
+                    &[("qname", qname)],
+                )?;
+
+                for other in changed.into_iter().sorted() {
+                    if other != qname && con.sismember(DNS_KEY, &other)? {
+                        create_change(
+                            con,
+                            "create dns name",
+                            &other,
+                            NETDOX_PLUGIN,
+                            &[("qname", &other)],
+                        )?;
+                    }
+                }
+            }
+
+            Ok(deletion)
+        })
+    }
+
+    async fn prune(
+        &mut self,
+        default_cutoff: Option<u64>,
+        plugin_cutoffs: &HashMap<String, u64>,
+        dry_run: bool,
+    ) -> NetdoxResult<Prune> {
+        let is_stale = |plugin: &str, seen: &str| {
+            let cutoff = plugin_cutoffs.get(plugin).copied().or(default_cutoff);
+            match (cutoff, seen.parse::<u64>()) {
+                (Some(cutoff), Ok(seen)) => seen < cutoff,
+                _ => false,
+            }
+        };
+        let time = now_secs().to_string();
+
+        self.write(|con| {
+            let mut prune = Prune::default();
+            // DNS names that are still used by a record or a raw node.
+            let mut used = HashSet::new();
+            // Raw nodes with no details left.
+            let mut deleted_nodes = HashSet::new();
+
+            for node_id in con.smembers(NODES_KEY)? {
+                let node_key = format!("{NODES_KEY};{node_id}");
+                let seen_key = format!("{node_key};seen");
+                let seen = con.hgetall(&seen_key)?;
+                let count = con
+                    .get(&node_key)?
+                    .and_then(|count| count.parse::<usize>().ok())
+                    .unwrap_or_default();
+
+                let (mut removed, mut plugins) = (HashSet::new(), vec![]);
+                for index in 1..=count {
+                    let plugin = con
+                        .hget(&format!("{node_key};{index}"), "plugin")?
+                        .unwrap_or_default();
+                    match seen.get(&index.to_string()) {
+                        None if !dry_run => con.hset(&seen_key, &index.to_string(), &time)?,
+                        Some(last_seen) if is_stale(&plugin, last_seen) => {
+                            removed.insert(index);
+                            prune.nodes.push((node_id.clone(), plugin.clone()));
+                            plugins.push(plugin);
+                        }
+                        _ => {}
+                    }
+                }
+                let kept = count - removed.len();
+
+                if !plugins.is_empty() && !dry_run {
+                    remove_node_details(con, &node_key, count, &removed)?;
+                    if kept == 0 {
+                        con.del(&node_key)?;
+                        con.del(&seen_key)?;
+                        con.srem(NODES_KEY, &node_id)?;
+                        delete_object_data(con, &node_key)?;
+                    } else {
+                        con.set(&node_key, &kept.to_string())?;
+                    }
+
+                    for plugin in plugins {
+                        create_change(
+                            con,
+                            "delete plugin node",
+                            &node_id,
+                            &plugin,
+                            &[("node_id", &node_id)],
+                        )?;
+                    }
+                }
+
+                if kept > 0 {
+                    used.extend(node_id.split(';').map(str::to_string));
+                } else if count > 0 {
+                    deleted_nodes.insert(node_id);
+                }
+            }
+
+            let qnames = con.smembers(DNS_KEY)?;
+            // Records that remain on each DNS name, as their type and value.
+            let mut remaining = HashMap::new();
+            for qname in &qnames {
+                let dns_key = format!("{DNS_KEY};{qname}");
+                let seen_key = format!("{dns_key};seen");
+                let mut seen = con.hgetall(&seen_key)?;
+                let mut records = vec![];
+
+                for record in con.smembers(&dns_key)? {
+                    let mut parts = record.splitn(3, ';');
+                    let (Some(plugin), Some(rtype), Some(value)) =
+                        (parts.next(), parts.next(), parts.next())
+                    else {
+                        continue;
+                    };
+
+                    match seen.remove(&record) {
+                        Some(last_seen) if is_stale(plugin, &last_seen) => {
+                            prune.records.push(DNSRecord {
+                                name: qname.to_string(),
+                                plugin: plugin.to_string(),
+                                rtype: rtype.to_string(),
+                                value: value.to_string(),
+                            });
+
+                            if !dry_run {
+                                con.srem(&dns_key, &record)?;
+                                con.hdel(&seen_key, &record)?;
+                                create_change(
+                                    con,
+                                    "delete dns record",
+                                    &format!("{dns_key};{record}"),
+                                    plugin,
+                                    &[("qname", qname), ("rtype", rtype), ("rvalue", value)],
+                                )?;
+                            }
+                        }
+                        last_seen => {
+                            if last_seen.is_none() && !dry_run {
+                                con.hset(&seen_key, &record, &time)?;
+                            }
+                            records.push((rtype.to_string(), value.to_string()));
+                            used.insert(qname.to_string());
+                            if ADDRESS_RTYPES.contains(&rtype) {
+                                used.insert(value.to_string());
+                            }
+                        }
+                    }
+                }
+                remaining.insert(qname.to_string(), records);
+
+                // Times left over from records that were removed some other way.
+                if !dry_run {
+                    for field in seen.keys().filter(|field| field.contains(';')) {
+                        con.hdel(&seen_key, field)?;
+                    }
+                }
+
+                let maps = con.hgetall(&format!("{dns_key};maps"))?;
+                if !maps.is_empty() {
+                    used.insert(qname.to_string());
+                    used.extend(maps.into_values());
+                }
+            }
+
+            if !dry_run {
+                // Implied records are removed once no remaining record on their origin implies them.
+                for qname in &qnames {
+                    let implied_key = format!("{DNS_KEY};{qname};implied");
+                    for origin in con.smembers(&implied_key)? {
+                        if let Some(records) = remaining.get(&origin) {
+                            if !records.iter().any(|(rtype, value)| {
+                                IMPLYING_RTYPES.contains(&rtype.as_str()) && value == qname
+                            }) {
+                                con.srem(&implied_key, &origin)?;
+                            }
+                        }
+                    }
+                }
+            }
+
+            for qname in qnames.iter().filter(|qname| !used.contains(*qname)) {
+                let dns_key = format!("{DNS_KEY};{qname}");
+                let seen_key = format!("{dns_key};seen");
+                let name_seen = con
+                    .hgetall(&seen_key)?
+                    .into_iter()
+                    .filter(|(plugin, _)| !plugin.contains(';'))
+                    .collect::<Vec<_>>();
+
+                if name_seen.is_empty() {
+                    if !dry_run {
+                        con.hset(&seen_key, "", &time)?;
+                    }
+                } else if name_seen
+                    .iter()
+                    .all(|(plugin, last_seen)| is_stale(plugin, last_seen))
+                {
+                    prune.dns_names.push(qname.to_string());
+                    if !dry_run {
+                        con.srem(DNS_KEY, qname)?;
+                        for suffix in ["", ";seen", ";implied", ";maps"] {
+                            con.del(&format!("{dns_key}{suffix}"))?;
+                        }
+                        con.hdel(DNS_NODES_KEY, qname)?;
+                        delete_object_data(con, &dns_key)?;
+                        create_change(
+                            con,
+                            "delete dns name",
+                            qname,
+                            NETDOX_PLUGIN,
+                            &[("qname", qname)],
+                        )?;
+                    }
+                }
+            }
+
+            // Processed nodes are removed once the last of their raw nodes is.
+            for link_id in con.smembers(PROC_NODES_KEY)? {
+                if is_orphaned(con, &link_id, &deleted_nodes)? {
+                    prune.proc_nodes.push(link_id.clone());
+                    if !dry_run {
+                        delete_proc_node(con, &link_id)?;
+                    }
+                }
+            }
+
+            Ok(prune)
+        })
+    }
+
+    // Runs
+
+    async fn put_run(&mut self, run: &RunRecord) -> NetdoxResult<()> {
+        let value = match toml::to_string(run) {
+            Ok(value) => value,
+            Err(err) => return store_err!(format!("Failed to serialize run record: {err}")),
+        };
+
+        debug!("Recording {} run that took {}ms.", run.kind, run.millis);
+        self.write(|con| {
+            con.lpush(RUNS_KEY, &value)?;
+            con.ltrim(RUNS_KEY, MAX_RUNS)
+        })
+    }
+
+    async fn get_runs(&mut self, count: usize) -> NetdoxResult<Vec<RunRecord>> {
+        let values = self.read(|con| con.lrange(RUNS_KEY))?;
+
+        let mut runs = Vec::with_capacity(values.len().min(count));
+        for value in values.into_iter().take(count) {
+            match toml::from_str(&value) {
+                Ok(run) => runs.push(run),
+                Err(err) => return store_err!(format!("Failed to parse run record: {err}")),
+            }
+        }
+
+        Ok(runs)
+    }
+
+    async fn acquire_run_lock(&mut self, lock: &RunLock) -> NetdoxResult<Option<RunLock>> {
+        let value = match toml::to_string(lock) {
+            Ok(value) => value,
+            Err(err) => return store_err!(format!("Failed to serialize run lock: {err}")),
+        };
+
+        self.write(|con| {
+            if let Some(held) = con.hget(RUN_LOCK_KEY, "lock")? {
+                match toml::from_str::<RunLock>(&held) {
+                    Ok(held) if held.owner != lock.owner && held.expires > now_secs() => {
+                        return Ok(Some(held))
+                    }
+                    Ok(_) => {}
+                    Err(err) => return store_err!(format!("Failed to parse run lock: {err}")),
+                }
+            }
+
+            con.hset(RUN_LOCK_KEY, "owner", &lock.owner)?;
+            con.hset(RUN_LOCK_KEY, "lock", &value)?;
+            Ok(None)
+        })
+    }
+
+    async fn release_run_lock(&mut self, owner: &str) -> NetdoxResult<()> {
+        self.write(|con| {
+            if con.hget(RUN_LOCK_KEY, "owner")?.as_deref() == Some(owner) {
+                con.del(RUN_LOCK_KEY)?;
+            }
+            Ok(())
+        })
+    }
+
+    async fn force_run_unlock(&mut self) -> NetdoxResult<Option<RunLock>> {
+        let held = self.write(|con| {
+            let held = con.hget(RUN_LOCK_KEY, "lock")?;
+            con.del(RUN_LOCK_KEY)?;
+            Ok(held)
+        })?;
+
+        match held.map(|value| toml::from_str(&value)) {
+            None => Ok(None),
+            Some(Ok(held)) => Ok(Some(held)),
+            Some(Err(err)) => store_err!(format!("Failed to parse run lock: {err}")),
+        }
+    }
+
+    async fn get_deferred_plugins(&mut self) -> NetdoxResult<HashSet<String>> {
+        self.read(|con| con.smembers(DEFERRED_PLUGINS_KEY))
+    }
+
+    async fn set_deferred_plugins(&mut self, plugins: &HashSet<String>) -> NetdoxResult<()> {
+        self.write(|con| {
+            con.del(DEFERRED_PLUGINS_KEY)?;
+            for plugin in plugins {
+                con.sadd(DEFERRED_PLUGINS_KEY, plugin)?;
+            }
+            Ok(())
+        })
+    }
+
+    async fn get_scope_violations(&mut self) -> NetdoxResult<HashMap<String, HashSet<String>>> {
+        self.read(|con| {
+            let mut violations = HashMap::new();
+            for plugin in con.smembers(SCOPE_VIOLATIONS_KEY)? {
+                let names = con.smembers(&format!("{SCOPE_VIOLATIONS_KEY};{plugin}"))?;
+                violations.insert(plugin, names);
+            }
+            Ok(violations)
+        })
+    }
+
+    async fn clear_scope_violations(&mut self) -> NetdoxResult<()> {
+        self.write(|con| {
+            for plugin in con.smembers(SCOPE_VIOLATIONS_KEY)? {
+                con.del(&format!("{SCOPE_VIOLATIONS_KEY};{plugin}"))?;
+            }
+            con.del(SCOPE_VIOLATIONS_KEY)
+        })
+    }
+
+    async fn get_plugin_cursor(
+        &mut self,
+        plugin: &str,
+        stage: &str,
+    ) -> NetdoxResult<Option<String>> {
+        self.read(|con| con.hget(PLUGIN_CURSORS_KEY, &format!("{plugin};{stage}")))
+    }
+
+    async fn set_plugin_cursor(
+        &mut self,
+        plugin: &str,
+        stage: &str,
+        cursor: &str,
+    ) -> NetdoxResult<()> {
+        self.write(|con| con.hset(PLUGIN_CURSORS_KEY, &format!("{plugin};{stage}"), cursor))
+    }
+
+    async fn clear_plugin_cursors(&mut self) -> NetdoxResult<()> {
+        self.write(|con| con.del(PLUGIN_CURSORS_KEY))
+    }
+
+    // Processing
+
+    async fn begin_process(&mut self, run: &ProcessRun) -> NetdoxResult<()> {
+        let value = match toml::to_string(run) {
+            Ok(value) => value,
+            Err(err) => return store_err!(format!("Failed to serialize process run: {err}")),
+        };
+
+        self.write(|con| con.set(PROCESS_RUN_KEY, &value))
+    }
+
+    async fn put_processed(
+        &mut self,
+        resolved: &[(String, String)],
+        nodes: &[&Node],
+    ) -> NetdoxResult<()> {
+        self.write(|con| {
+            // The nodes of this run replace the processed layer whole,
+            // so nodes it did not produce are deleted and the indices rebuilt from its nodes.
+            let link_ids = nodes
+                .iter()
+                .map(|node| node.link_id.as_str())
+                .collect::<HashSet<_>>();
+            for link_id in con.smembers(PROC_NODES_KEY)? {
+                if !link_ids.contains(link_id.as_str()) {
+                    delete_proc_node(con, &link_id)?;
+                }
+            }
+
+            let dns_names = nodes
+                .iter()
+                .flat_map(|node| &node.dns_names)
+                .map(String::as_str)
+                .collect::<HashSet<_>>();
+            for qname in con.hgetall(DNS_NODES_KEY)?.into_keys() {
+                if !dns_names.contains(qname.as_str()) {
+                    con.hdel(DNS_NODES_KEY, &qname)?;
+                    let meta_key = format!("{METADATA_KEY};{DNS_KEY};{qname}");
+                    for field in [NODE_META_KEY, NODE_ID_META_KEY] {
+                        con.hdel(&meta_key, field)?;
+                    }
+                }
+            }
+            con.del(PROC_NODE_REVS_KEY)?;
+            for node in nodes {
+                for suffix in [";plugins", ";raw_ids"] {
+                    con.del(&format!("{PROC_NODES_KEY};{}{suffix}", node.link_id))?;
+                }
+            }
+
+            for (qname, link_id) in resolved {
+                let qname = qualify_dns_name(con, qname)?;
+                create_dns_name(con, &qname, NETDOX_PLUGIN)?;
+                create_metadata(
+                    con,
+                    &format!("{DNS_KEY};{qname}"),
+                    NETDOX_PLUGIN,
+                    &HashMap::from([
+                        (
+                            NODE_META_KEY,
+                            format!("(!(procnode|!|{link_id})!)").as_str(),
+                        ),
+                        (NODE_ID_META_KEY, link_id.as_str()),
+                    ]),
+                )?;
+            }
+
+            debug!("Writing {} processed nodes.", nodes.len());
+            for node in nodes {
+                write_node(con, node)?;
+            }
+            Ok(())
+        })
+    }
+
+    async fn end_process(&mut self) -> NetdoxResult<()> {
+        self.write(|con| con.del(PROCESS_RUN_KEY))
+    }
+
+    async fn get_interrupted_process(&mut self) -> NetdoxResult<Option<ProcessRun>> {
+        match self.read(|con| con.get(PROCESS_RUN_KEY))? {
+            Some(value) => match toml::from_str(&value) {
+                Ok(run) => Ok(Some(run)),
+                Err(err) => store_err!(format!("Failed to parse process run: {err}")),
+            },
+            None => Ok(None),
+        }
+    }
+
+    async fn clear_process(&mut self, run: &ProcessRun) -> NetdoxResult<()> {
+        self.write(|con| {
+            for link_id in &run.nodes {
+                let key = format!("{PROC_NODES_KEY};{link_id}");
+                for name in con.smembers(&format!("{key};dns_names"))? {
+                    con.hdel(DNS_NODES_KEY, &name)?;
+                }
+                for raw_id in con.smembers(&format!("{key};raw_ids"))? {
+                    con.hdel(PROC_NODE_REVS_KEY, &raw_id)?;
+                }
+
+                con.srem(PROC_NODES_KEY, link_id)?;
+                for suffix in ["", ";alt_names", ";dns_names", ";plugins", ";raw_ids"] {
+                    con.del(&format!("{key}{suffix}"))?;
+                }
+            }
+
+            for qname in &run.dns_names {
+                let meta_key = format!("{METADATA_KEY};{DNS_KEY};{qname}");
+                con.hdel(&meta_key, NODE_META_KEY)?;
+                con.hdel(&meta_key, NODE_ID_META_KEY)?;
+            }
+
+            con.del(PROCESS_RUN_KEY)
+        })
+    }
+
+    // Publish Queue
+
+    async fn put_queued_updates(&mut self, updates: &[QueuedUpdate]) -> NetdoxResult<()> {
+        let mut values = Vec::with_capacity(updates.len());
+        for update in updates {
+            match toml::to_string(update) {
+                Ok(value) => values.push((update.key(), value)),
+                Err(err) => return store_err!(format!("Failed to serialize queued update: {err}")),
+            }
+        }
+
+        debug!("Queueing {} fragment updates.", updates.len());
+        self.write(|con| {
+            for (key, value) in &values {
+                con.hset(PUBLISH_QUEUE_KEY, key, value)?;
+            }
+            Ok(())
+        })
+    }
+
+    async fn get_queued_updates(&mut self) -> NetdoxResult<Vec<QueuedUpdate>> {
+        let values = self.read(|con| con.hgetall(PUBLISH_QUEUE_KEY))?;
+
+        let mut updates = Vec::with_capacity(values.len());
+        for (key, value) in values {
+            match toml::from_str(&value) {
+                Ok(update) => updates.push(update),
+                Err(err) => {
+                    return store_err!(format!("Failed to parse queued update for {key}: {err}"))
+                }
+            }
+        }
+
+        Ok(updates)
+    }
+
+    async fn remove_queued_update(&mut self, update: &QueuedUpdate) -> NetdoxResult<()> {
+        let key = update.key();
+        self.write(|con| {
+            // Only remove the entry if it has not been replaced since it was read.
+            match con
+                .hget(PUBLISH_QUEUE_KEY, &key)?
+                .map(|value| toml::from_str::<QueuedUpdate>(&value))
+            {
+                Some(Ok(queued)) if queued.hash == update.hash => con.hdel(PUBLISH_QUEUE_KEY, &key),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    async fn get_publish_checkpoint(&mut self, remote: &str) -> NetdoxResult<Option<String>> {
+        self.read(|con| con.hget(PUBLISH_CHECKPOINT_KEY, remote))
+    }
+
+    async fn put_publish_checkpoint(&mut self, remote: &str, change_id: &str) -> NetdoxResult<()> {
+        self.write(|con| con.hset(PUBLISH_CHECKPOINT_KEY, remote, change_id))
+    }
+
+    async fn clear_publish_checkpoint(&mut self, remote: &str) -> NetdoxResult<()> {
+        self.write(|con| con.hdel(PUBLISH_CHECKPOINT_KEY, remote))
+    }
+
+    async fn get_publish_cursors(&mut self) -> NetdoxResult<HashMap<String, String>> {
+        self.read(|con| con.hgetall(PUBLISH_CURSORS_KEY))
+    }
+
+    async fn put_publish_cursor(&mut self, remote: &str, change_id: &str) -> NetdoxResult<()> {
+        self.write(|con| con.hset(PUBLISH_CURSORS_KEY, remote, change_id))
+    }
+
+    async fn get_remote_cursor(&mut self, remote: &str) -> NetdoxResult<Option<String>> {
+        self.read(|con| con.hget(REMOTE_CURSORS_KEY, remote))
+    }
+
+    async fn swap_remote_cursor(
+        &mut self,
+        remote: &str,
+        expected: Option<&str>,
+        change_id: &str,
+    ) -> NetdoxResult<bool> {
+        self.write(|con| {
+            if con.hget(REMOTE_CURSORS_KEY, remote)?.as_deref() != expected {
+                return Ok(false);
+            }
+            con.hset(REMOTE_CURSORS_KEY, remote, change_id)?;
+            Ok(true)
+        })
+    }
+
+    async fn put_superseded_fragments(
+        &mut self,
+        obj_id: &str,
+        ids: &[(String, String)],
+    ) -> NetdoxResult<()> {
+        self.write(|con| {
+            if ids.is_empty()
+                || con.sismember(&format!("{FRAGMENT_IDS_KEY};regenerated"), obj_id)?
+            {
+                return Ok(());
+            }
+
+            con.sadd(FRAGMENT_IDS_KEY, obj_id)?;
+            for (old, new) in ids {
+                con.hset(&format!("{FRAGMENT_IDS_KEY};{obj_id}"), old, new)?;
+            }
+            Ok(())
+        })
+    }
+
+    async fn get_superseded_fragments(
+        &mut self,
+    ) -> NetdoxResult<HashMap<String, HashMap<String, String>>> {
+        self.read(|con| {
+            let mut superseded = HashMap::new();
+            for obj_id in con.smembers(FRAGMENT_IDS_KEY)? {
+                let ids = con.hgetall(&format!("{FRAGMENT_IDS_KEY};{obj_id}"))?;
+                superseded.insert(obj_id, ids);
+            }
+            Ok(superseded)
+        })
+    }
+
+    async fn clear_superseded_fragments(&mut self, obj_ids: &[String]) -> NetdoxResult<()> {
+        self.write(|con| {
+            for obj_id in obj_ids {
+                con.srem(FRAGMENT_IDS_KEY, obj_id)?;
+                con.sadd(&format!("{FRAGMENT_IDS_KEY};regenerated"), obj_id)?;
+                con.del(&format!("{FRAGMENT_IDS_KEY};{obj_id}"))?;
+            }
+            Ok(())
+        })
+    }
+
+    // Backup
+
+    async fn dump(&mut self) -> NetdoxResult<DataDump> {
+        let dump = self.read(|con| {
+            Ok(DataDump {
+                keys: dump_keys(con)?,
+                changelog: dump_changelog(con)?,
+            })
+        })?;
+
+        debug!(
+            "Dumped {} keys and {} changes.",
+            dump.keys.len(),
+            dump.changelog.len()
+        );
+        Ok(dump)
+    }
+
+    async fn restore(&mut self, dump: &DataDump) -> NetdoxResult<()> {
+        self.write(|con| {
+            let size = con.keys("")?.len();
+            if size > 0 || con.last_change_id()?.is_some() {
+                return store_err!(format!(
+                    "Refusing to restore a backup into a database that contains {size} keys."
+                ));
+            }
+
+            restore_dump(con, dump)
+        })
+    }
+
+    // Persistence
+
+    async fn write_save(
+        &mut self,
+        _strategy: SaveStrategy,
+        _timeout: Duration,
+    ) -> NetdoxResult<()> {
+        // Every write is kept by the backend as it happens.
+        Ok(())
+    }
+}
+
+// Keyspace
+
+/// Adds values to the end of a list.
+fn rpush(con: &dyn Keyspace, key: &str, values: &[impl AsRef<str>]) -> NetdoxResult<()> {
+    for value in values {
+        con.rpush(key, value.as_ref())?;
+    }
+    Ok(())
+}
+
+/// Writes the keys and changelog entries of a dump into the keyspace.
+pub(super) fn restore_dump(con: &dyn Keyspace, dump: &DataDump) -> NetdoxResult<()> {
+    for (key, value) in &dump.keys {
+        match value {
+            StoredValue::String(value) => con.set(key, value)?,
+            StoredValue::Set(members) => {
+                for member in members {
+                    con.sadd(key, member)?;
+                }
+            }
+            StoredValue::Hash(fields) => {
+                for (field, value) in fields {
+                    con.hset(key, field, value)?;
+                }
+            }
+            StoredValue::List(values) => rpush(con, key, values)?,
+        }
+    }
+
+    for change in &dump.changelog {
+        let (millis, seq) = parse_change_id(&change.id)?;
+        for (field, value) in &change.fields {
+            con.add_change_field((millis, seq), field, value)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the value at every key, whatever its type.
+fn dump_keys(con: &dyn Keyspace) -> NetdoxResult<BTreeMap<String, StoredValue>> {
+    let mut keys = con.values()?;
+    // The lock belongs to the run that held it, not to the data.
+    keys.remove(RUN_LOCK_KEY);
+    Ok(keys)
+}
+
+/// Makes the hash at key match the expected map.
+/// Returns the number of fields that were added, changed, or removed.
+fn sync_hash(
+    con: &dyn Keyspace,
+    key: &str,
+    expected: &HashMap<String, String>,
+) -> NetdoxResult<usize> {
+    let mut fixed = 0;
+    let current = con.hgetall(key)?;
+
+    for field in current.keys() {
+        if !expected.contains_key(field) {
+            con.hdel(key, field)?;
+            fixed += 1;
+        }
+    }
+
+    for (field, value) in expected {
+        if current.get(field) != Some(value) {
+            con.hset(key, field, value)?;
+            fixed += 1;
+        }
+    }
+
+    Ok(fixed)
+}
+
+/// Makes the set at key contain exactly the expected members.
+/// Returns the number of members that were added or removed.
+fn sync_set(con: &dyn Keyspace, key: &str, expected: &HashSet<String>) -> NetdoxResult<usize> {
+    let mut fixed = 0;
+
+    for member in con.smembers(key)? {
+        if !expected.contains(&member) {
+            con.srem(key, &member)?;
+            fixed += 1;
+        }
+    }
+
+    for member in expected {
+        if con.sadd(key, member)? {
+            fixed += 1;
+        }
+    }
+
+    Ok(fixed)
+}
+
+/// Replaces the members of a set for which the function returns a new member.
+/// Returns true if any were replaced.
+fn rename_members(
+    con: &dyn Keyspace,
+    key: &str,
+    rename: impl Fn(&str) -> Option<String>,
+) -> NetdoxResult<bool> {
+    let mut renamed = false;
+    for member in con.smembers(key)? {
+        if let Some(new_member) = rename(&member) {
+            con.srem(key, &member)?;
+            con.sadd(key, &new_member)?;
+            renamed = true;
+        }
+    }
+    Ok(renamed)
+}
+
+/// Replaces the fields of a hash for which the function returns a new field, keeping their values.
+/// Returns true if any were replaced.
+fn rename_fields(
+    con: &dyn Keyspace,
+    key: &str,
+    rename: impl Fn(&str) -> Option<String>,
+) -> NetdoxResult<bool> {
+    let mut renamed = false;
+    for (field, value) in con.hgetall(key)? {
+        if let Some(new_field) = rename(&field) {
+            con.hdel(key, &field)?;
+            con.hset(key, &new_field, &value)?;
+            renamed = true;
+        }
+    }
+    Ok(renamed)
+}
+
+/// Sets fields of the hash at key. Returns true if any of them changed.
+fn sync_fields(con: &dyn Keyspace, key: &str, fields: &[(&str, &str)]) -> NetdoxResult<bool> {
+    let current = con.hgetall(key)?;
+    let mut changed = false;
+    for (field, value) in fields {
+        if current.get(*field).map(String::as_str) != Some(*value) {
+            con.hset(key, field, value)?;
+            changed = true;
+        }
+    }
+
+    Ok(changed)
+}
+
+/// Deletes the plugin data, metadata and tags attached to an object.
+fn delete_object_data(con: &dyn Keyspace, obj_id: &str) -> NetdoxResult<()> {
+    let pdata_key = format!("{PDATA_KEY};{obj_id}");
+    for pdata_id in con.smembers(&pdata_key)? {
+        let data_key = format!("{pdata_key};{pdata_id}");
+        for suffix in ["", ";details", ";order", ";names", ";titles"] {
+            con.del(&format!("{data_key}{suffix}"))?;
+        }
+    }
+    con.del(&pdata_key)?;
+
+    let meta_key = format!("{METADATA_KEY};{obj_id}");
+    for suffix in ["", ";plugins", ";sources", ";changed", ";history"] {
+        con.del(&format!("{meta_key}{suffix}"))?;
+    }
+    con.srem(METADATA_KEY, obj_id)?;
+    remove_tags(con, obj_id)?;
+
+    Ok(())
+}
+
+/// Gets the metadata stored at a key, with the plugin that last changed each value and when.
+fn get_metadata_provenance(
+    con: &dyn Keyspace,
+    meta_key: &str,
+) -> NetdoxResult<HashMap<String, MetadataProvenance>> {
+    Ok(metadata_provenance(
+        con.hgetall(meta_key)?,
+        &con.hgetall(&format!("{meta_key};sources"))?,
+        &con.hgetall(&format!("{meta_key};changed"))?,
+    ))
+}
+
+/// Gets the policy for choosing between values from different plugins.
+fn get_conflict_policy(con: &dyn Keyspace) -> NetdoxResult<ConflictPolicy> {
+    Ok(ConflictPolicy {
+        priority: con.lrange(&format!("{CONFLICT_POLICY_KEY};priority"))?,
+        strategy: match con.hget(CONFLICT_POLICY_KEY, "strategy")? {
+            Some(strategy) => ConflictStrategy::try_from(strategy.as_str())?,
+            None => ConflictStrategy::default(),
+        },
+        report: con
+            .hget(CONFLICT_POLICY_KEY, "report")?
+            .is_some_and(|report| report == "true"),
+    })
+}
+
+/// Gets the metadata for a node merged with the conflict policy, and the conflicts between its raw nodes.
+fn get_node_metadata_merged(
+    con: &dyn Keyspace,
+    node: &Node,
+) -> NetdoxResult<(HashMap<String, MetadataProvenance>, Vec<Conflict>)> {
+    let policy = get_conflict_policy(con)?;
+    let mut raw = vec![];
+    for raw_id in &node.raw_ids {
+        raw.push((
+            raw_id.clone(),
+            get_metadata_provenance(con, &format!("{METADATA_KEY};{NODES_KEY};{raw_id}"))?,
+        ));
+    }
+    let proc = get_metadata_provenance(
+        con,
+        &format!("{METADATA_KEY};{PROC_NODES_KEY};{}", node.link_id),
+    )?;
+
+    Ok(merge_metadata(node, raw, proc, &policy))
+}
+
+/// Seconds since the epoch, as used for the times objects were last seen.
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+// Changelog
+
+/// Parses a changelog entry ID of the form `millis-seq`, or just `millis`.
+fn parse_change_id(id: &str) -> NetdoxResult<(i64, i64)> {
+    let (millis, seq) = id.split_once('-').unwrap_or((id, "0"));
+    match (millis.parse::<u64>(), seq.parse::<u64>()) {
+        (Ok(millis), Ok(seq)) => Ok((
+            i64::try_from(millis).unwrap_or(i64::MAX),
+            i64::try_from(seq).unwrap_or(i64::MAX),
+        )),
+        _ => store_err!(format!("Invalid changelog entry ID: {id}")),
+    }
+}
+
+/// Adds an entry to the changelog, with an ID that is greater than any before it.
+/// Fields name each part of the changed object's ID, so readers need not split the value.
+fn create_change(
+    con: &dyn Keyspace,
+    change: &str,
+    value: &str,
+    plugin: &str,
+    fields: &[(&str, &str)],
+) -> NetdoxResult<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| i64::try_from(duration.as_millis()).unwrap_or(i64::MAX))
+        .unwrap_or_default();
+    let (millis, seq) = match con.last_change_id()? {
+        Some((millis, seq)) if millis >= now => (millis, seq + 1),
+        _ => (now, 0),
+    };
+
+    for (field, value) in [("change", change), ("value", value), ("plugin", plugin)]
+        .iter()
+        .chain(fields)
+    {
+        con.add_change_field((millis, seq), field, value)?;
+    }
+
+    Ok(())
+}
+
+/// Returns every entry in the changelog with its unparsed fields.
+fn dump_changelog(con: &dyn Keyspace) -> NetdoxResult<Vec<StoredChange>> {
+    let rows = con.change_fields((-1, 0))?;
+
+    // Rows for the fields of each entry are adjacent.
+    let mut changes: Vec<StoredChange> = vec![];
+    for ((millis, seq), field, value) in rows {
+        let id = format!("{millis}-{seq}");
+        match changes.last_mut() {
+            Some(change) if change.id == id => {
+                change.fields.insert(field, value);
+            }
+            _ => changes.push(StoredChange {
+                id,
+                fields: BTreeMap::from([(field, value)]),
+            }),
+        }
+    }
+
+    Ok(changes)
+}
+
+// DNS
+
+/// Returns true if the name starts with a network, like `[network]domain.com`.
+fn is_qualified(name: &str) -> bool {
+    match split_qname(name) {
+        Some((network, _)) => {
+            !network.is_empty()
+                && network
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        }
+        None => false,
+    }
+}
+
+/// Returns the network for an unqualified DNS name.
+/// This is the network of the longest configured suffix of the name, or the default network.
+fn infer_network(con: &dyn Keyspace, name: &str) -> NetdoxResult<String> {
+    let mut suffix = name;
+    loop {
+        if let Some(network) = con.hget(NETWORK_SUFFIXES_KEY, suffix)? {
+            return Ok(network);
+        }
+
+        match suffix.split_once('.') {
+            Some((_, rest)) if !rest.is_empty() => suffix = rest,
+            _ => break,
+        }
+    }
+
+    match con.get(DEFAULT_NETWORK_KEY)? {
+        Some(network) => Ok(network),
+        None => store_err!("No default network is set; run netdox init first.".to_string()),
+    }
+}
+
+/// Returns true if the name is valid for a network, like the `network` in `[network]domain.com`.
+fn is_network_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || char == '_' || char == '-')
+}
+
+/// Returns the qualified DNS name moved from one network prefix to another,
+/// or None if it is not in the old network.
+fn rename_qname(qname: &str, old_prefix: &str, new_prefix: &str) -> Option<String> {
+    qname
+        .strip_prefix(old_prefix)
+        .map(|name| format!("{new_prefix}{name}"))
+}
+
+/// Returns the node ID with its DNS names moved from one network prefix to another,
+/// or None if none of them are in the old network.
+fn rename_node_id(node_id: &str, old_prefix: &str, new_prefix: &str) -> Option<String> {
+    let mut renamed = false;
+    let mut names = node_id
+        .split(';')
+        .map(|qname| match rename_qname(qname, old_prefix, new_prefix) {
+            Some(new_qname) => {
+                renamed = true;
+                new_qname
+            }
+            None => qname.to_string(),
+        })
+        .collect::<Vec<_>>();
+
+    names.sort();
+    renamed.then(|| names.join(";"))
+}
+
+/// Returns the key of an object moved to its new ID, or None if the key does not belong to a moved object.
+/// Keys are ${OBJECT_KEY};${ID}..., optionally prefixed with meta;, pdata; or notes;.
+/// Node IDs contain semicolons, so the longest moved node ID that the key continues with is used.
+fn rename_object_key(
+    key: &str,
+    old_prefix: &str,
+    new_prefix: &str,
+    node_ids: &HashMap<String, String>,
+) -> Option<String> {
+    for prefix in ["", "meta;", "pdata;", "notes;"] {
+        let dns_prefix = format!("{prefix}{DNS_KEY};");
+        if let Some(renamed) = key
+            .strip_prefix(&dns_prefix)
+            .and_then(|rest| rename_qname(rest, old_prefix, new_prefix))
+        {
+            return Some(format!("{dns_prefix}{renamed}"));
+        }
+
+        let nodes_prefix = format!("{prefix}{NODES_KEY};");
+        if let Some(rest) = key.strip_prefix(&nodes_prefix) {
+            let matched = node_ids
+                .keys()
+                .filter(|node_id| {
+                    rest.strip_prefix(node_id.as_str())
+                        .is_some_and(|suffix| suffix.is_empty() || suffix.starts_with(';'))
+                })
+                .max_by_key(|node_id| node_id.len());
+
+            if let Some(node_id) = matched {
+                return Some(format!(
+                    "{nodes_prefix}{}{}",
+                    node_ids[node_id],
+                    &rest[node_id.len()..]
+                ));
+            }
+        }
+    }
+
+    None
+}
+
+fn qualify_dns_name(con: &dyn Keyspace, name: &str) -> NetdoxResult<String> {
+    let lower = name.to_lowercase();
+    if is_qualified(name) {
+        let (network, unqualified) = split_qname(&lower).expect("qualified names can be split");
+        Ok(match canonical_ipv6(unqualified) {
+            Some(address) => format!("[{network}]{address}"),
+            None => lower,
+        })
+    } else {
+        let lower = canonical_ipv6(&lower).unwrap_or(lower);
+        Ok(format!("[{}]{lower}", infer_network(con, &lower)?))
+    }
+}
+
+/// Returns true if the network of a qualified DNS name is registered,
+/// or if there are no registered networks.
+fn is_registered_network(con: &dyn Keyspace, qname: &str) -> NetdoxResult<bool> {
+    if con.scard(NETWORKS_KEY)? == 0 {
+        return Ok(true);
+    }
+
+    match split_qname(qname) {
+        Some((network, _)) => con.sismember(NETWORKS_KEY, network),
+        None => Ok(false),
+    }
+}
+
+/// Returns true if a plugin may create a qualified DNS name.
+/// Otherwise records the name as a violation of the plugin's scope.
+fn in_scope(con: &dyn Keyspace, plugin: &str, qname: &str) -> NetdoxResult<bool> {
+    if !con.sismember(PLUGIN_SCOPES_KEY, plugin)? {
+        return Ok(true);
+    }
+
+    let key = format!("{PLUGIN_SCOPES_KEY};{plugin}");
+    let scope = PluginScope {
+        networks: con
+            .smembers(&format!("{key};networks"))?
+            .into_iter()
+            .collect(),
+        zones: con.smembers(&format!("{key};zones"))?.into_iter().collect(),
+    };
+    if scope.allows(qname) {
+        return Ok(true);
+    }
+
+    con.sadd(SCOPE_VIOLATIONS_KEY, plugin)?;
+    con.sadd(&format!("{SCOPE_VIOLATIONS_KEY};{plugin}"), qname)?;
+    Ok(false)
+}
+
+/// Adds a qualified DNS name to the set of all names,
+/// unless it is ignored or outside the scope of the plugin.
+fn create_dns_name(con: &dyn Keyspace, qname: &str, plugin: &str) -> NetdoxResult<()> {
+    if con.sismember(DNS_IGNORE_KEY, qname)?
+        || con
+            .smembers(DNS_IGNORE_PATTERNS_KEY)?
+            .iter()
+            .any(|pattern| matches_ignore_pattern(pattern, qname))
+        || !in_scope(con, plugin, qname)?
+    {
+        return Ok(());
+    }
+
+    // Existing names can still be written to, and processing only warns about them.
+    if plugin != NETDOX_PLUGIN
+        && !con.sismember(DNS_KEY, qname)?
+        && !is_registered_network(con, qname)?
+    {
+        return store_err!(format!(
+            "Cannot create DNS name in unregistered network: {qname}"
+        ));
+    }
+
+    if con.sadd(DNS_KEY, qname)? {
+        create_change(con, "create dns name", qname, plugin, &[("qname", qname)])?;
+    }
+    if plugin != NETDOX_PLUGIN {
+        con.hset(
+            &format!("{DNS_KEY};{qname};seen"),
+            plugin,
+            &now_secs().to_string(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Gets the DNS records with the given name.
+fn get_dns_records(con: &dyn Keyspace, qname: &str) -> NetdoxResult<Vec<DNSRecord>> {
+    let mut records = vec![];
+    for record in con.smembers(&format!("{DNS_KEY};{qname}"))? {
+        let mut rsplit = record.splitn(3, ';');
+        let (Some(plugin), Some(rtype), Some(value)) =
+            (rsplit.next(), rsplit.next(), rsplit.next())
+        else {
+            return store_err!(format!("Invalid DNS record on qname {qname}: {record}"));
+        };
+
+        records.push(DNSRecord {
+            name: qname.to_string(),
+            value: value.to_string(),
+            rtype: rtype.to_string(),
+            plugin: plugin.to_string(),
+        });
+    }
+
+    Ok(records)
+}
+
+// Nodes
+
+/// Gets the raw nodes with the same set of DNS names, given the member of the set of nodes.
+fn get_raw_node_group(con: &dyn Keyspace, node: &str) -> NetdoxResult<Vec<RawNode>> {
+    let key = format!("{NODES_KEY};{node}");
+    let count = match con.get(&key)? {
+        Some(count) => match count.parse::<u64>() {
+            Ok(count) => count,
+            Err(_) => {
+                return store_err!(format!(
+                    "Failed to parse number of nodes with key {key}: {count}"
+                ))
+            }
+        },
+        None => 0,
+    };
+
+    (1..=count)
+        .map(|index| get_raw_node(con, &format!("{key};{index}")))
+        .collect()
+}
+
+fn get_raw_node(con: &dyn Keyspace, key: &str) -> NetdoxResult<RawNode> {
+    // Keys are nodes;${NODE_ID};${INDEX}, where the node ID is the DNS names joined by semicolons.
+    let dns_names = match key
+        .strip_prefix(&format!("{NODES_KEY};"))
+        .and_then(|rest| rest.rsplit_once(';'))
+    {
+        Some((node_id, _)) => node_id
+            .split(';')
+            .map(std::string::ToString::to_string)
+            .collect::<HashSet<_>>(),
+        None => return store_err!(format!("Invalid node key: {key}")),
+    };
+
+    let mut details = con.hgetall(key)?;
+
+    let plugin = match details.remove("plugin") {
+        Some(plugin) => plugin,
+        None => return store_err!(format!("Node details at key {key} missing plugin field.")),
+    };
+
+    let exclusive = match details.get("exclusive") {
+        Some(val) => match val.parse::<bool>() {
+            Ok(exclusive) => exclusive,
+            Err(_) => {
+                return store_err!(format!(
+                    "Unable to parse boolean from exclusive value at {key}: {val}"
+                ))
+            }
+        },
+        None => {
+            return store_err!(format!(
+                "Node details at key {key} missing exclusive field."
+            ))
+        }
+    };
+
+    Ok(RawNode {
+        name: details.remove("name"),
+        exclusive,
+        link_id: details.remove("link_id"),
+        dns_names,
+        plugin,
+    })
+}
+
+/// Returns true if some of the raw nodes of a processed node were deleted and none of the others exist.
+fn is_orphaned(
+    con: &dyn Keyspace,
+    link_id: &str,
+    deleted_nodes: &HashSet<String>,
+) -> NetdoxResult<bool> {
+    let raw_ids = con.smembers(&format!("{PROC_NODES_KEY};{link_id};raw_ids"))?;
+    if !raw_ids.iter().any(|raw_id| deleted_nodes.contains(raw_id)) {
+        return Ok(false);
+    }
+
+    for raw_id in &raw_ids {
+        if !deleted_nodes.contains(raw_id) && con.sismember(NODES_KEY, raw_id)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Deletes a processed node, and the references to it from its DNS names and raw nodes.
+fn delete_proc_node(con: &dyn Keyspace, link_id: &str) -> NetdoxResult<()> {
+    let node_key = format!("{PROC_NODES_KEY};{link_id}");
+    for qname in con.smembers(&format!("{node_key};dns_names"))? {
+        if con.hget(DNS_NODES_KEY, &qname)?.as_deref() == Some(link_id) {
+            con.hdel(DNS_NODES_KEY, &qname)?;
+            let meta_key = format!("{METADATA_KEY};{DNS_KEY};{qname}");
+            for field in [NODE_META_KEY, NODE_ID_META_KEY] {
+                con.hdel(&meta_key, field)?;
+            }
+        }
+    }
+    for raw_id in con.smembers(&format!("{node_key};raw_ids"))? {
+        con.hdel(PROC_NODE_REVS_KEY, &raw_id)?;
+    }
+
+    con.srem(PROC_NODES_KEY, link_id)?;
+    for suffix in ["", ";alt_names", ";dns_names", ";plugins", ";raw_ids"] {
+        con.del(&format!("{node_key}{suffix}"))?;
+    }
+    remove_tags(con, &node_key)?;
+    remove_relations(con, link_id)?;
+    remove_services(con, link_id)?;
+    create_change(
+        con,
+        "delete proc node",
+        link_id,
+        NETDOX_PLUGIN,
+        &[("link_id", link_id)],
+    )
+}
+
+/// Removes the raw node details at some indices, moving the rest down to fill the gaps.
+fn remove_node_details(
+    con: &dyn Keyspace,
+    node_key: &str,
+    count: usize,
+    removed: &HashSet<usize>,
+) -> NetdoxResult<()> {
+    let seen_key = format!("{node_key};seen");
+    let seen = con.hgetall(&seen_key)?;
+    let mut kept = vec![];
+    for index in 1..=count {
+        let details_key = format!("{node_key};{index}");
+        if !removed.contains(&index) {
+            kept.push((
+                con.hgetall(&details_key)?,
+                seen.get(&index.to_string()).cloned(),
+            ));
+        }
+        con.del(&details_key)?;
+    }
+
+    con.del(&seen_key)?;
+    for (index, (details, last_seen)) in (1..).zip(kept) {
+        for (field, value) in details {
+            con.hset(&format!("{node_key};{index}"), &field, &value)?;
+        }
+        if let Some(last_seen) = last_seen {
+            con.hset(&seen_key, &index.to_string(), &last_seen)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn get_node(con: &dyn Keyspace, id: &str) -> NetdoxResult<Node> {
+    let key = format!("{PROC_NODES_KEY};{id}");
+    let name = match con.get(&key)? {
+        Some(name) => name,
+        None => return store_err!(format!("No name for linkable node with id {id}")),
+    };
+
+    Ok(Node {
+        name,
+        link_id: id.to_string(),
+        alt_names: con.smembers(&format!("{key};alt_names"))?,
+        dns_names: con.smembers(&format!("{key};dns_names"))?,
+        plugins: con.smembers(&format!("{key};plugins"))?,
+        raw_ids: con.smembers(&format!("{key};raw_ids"))?,
+    })
+}
+
+// Data
+
+fn get_data(con: &dyn Keyspace, key: &str) -> NetdoxResult<Data> {
+    debug!("Reading data from key: {key}");
+    let id = match key.rsplit_once(';') {
+        Some((_, id)) => id.to_string(),
+        None => return store_err!(format!("Failed to get plugin data id from key: {key}")),
+    };
+
+    let details = con.hgetall(&format!("{key};details"))?;
+    match details.get("type").map(String::as_str) {
+        Some("hash") => Data::from_hash(
+            id,
+            con.hgetall(key)?,
+            con.lrange(&format!("{key};order"))?,
+            &details,
+        ),
+        Some("list") => Data::from_list(
+            id,
+            izip!(
+                con.lrange(&format!("{key};names"))?,
+                con.lrange(&format!("{key};titles"))?,
+                con.lrange(key)?
+            )
+            .collect(),
+            &details,
+        ),
+        Some("string") => match con.get(key)? {
+            Some(content) => Data::from_string(id, content, &details),
+            None => store_err!(format!("No content for string plugin data at {key}")),
+        },
+        Some("table") => Data::from_table(id, con.lrange(key)?, &details),
+        other => store_err!(format!(
+            "Plugin data details for data at {key} had invalid type: {other:?}"
+        )),
+    }
+}
+
+/// Gets the plugin data attached to the object with the given key.
+fn get_pdata(con: &dyn Keyspace, obj_key: &str) -> NetdoxResult<Vec<Data>> {
+    let mut dataset = vec![];
+    for id in con.smembers(&format!("{PDATA_KEY};{obj_key}"))? {
+        dataset.push(get_data(con, &format!("{PDATA_KEY};{obj_key};{id}"))?);
+    }
+
+    Ok(dataset)
+}
+
+/// Deletes some plugin data attached to the object with the given key, and adds a change.
+/// Returns true if the data existed.
+fn delete_pdata(
+    con: &dyn Keyspace,
+    obj_key: &str,
+    plugin: &str,
+    data_id: &str,
+) -> NetdoxResult<bool> {
+    let pdata_key = format!("{PDATA_KEY};{obj_key}");
+    let data_key = format!("{pdata_key};{data_id}");
+    match con.hget(&format!("{data_key};details"), "plugin")? {
+        None => {
+            con.srem(&pdata_key, data_id)?;
+            return Ok(false);
+        }
+        Some(owner) if owner != plugin => {
+            return store_err!(format!(
+                "Cannot delete plugin data {data_id} created by another plugin: {owner}"
+            ))
+        }
+        Some(_) => {}
+    }
+
+    con.srem(&pdata_key, data_id)?;
+    for suffix in ["", ";details", ";order", ";names", ";titles"] {
+        con.del(&format!("{data_key}{suffix}"))?;
+    }
+
+    create_change(
+        con,
+        "deleted data",
+        &data_key,
+        plugin,
+        &[
+            ("obj_id", obj_key),
+            ("data_id", data_id),
+            ("kind", "plugin"),
+        ],
+    )?;
+    Ok(true)
+}
+
+/// Writes data to the key, and adds a change if it was created or changed.
+/// Fields are the changelog fields identifying the data: `obj_id`, `data_id`, and kind.
+fn create_data(
+    con: &dyn Keyspace,
+    key: &str,
+    fields: &[(&str, &str)],
+    data: &Data,
+) -> NetdoxResult<()> {
+    let details_key = format!("{key};details");
+    let order_key = format!("{key};order");
+    let names_key = format!("{key};names");
+    let titles_key = format!("{key};titles");
+
+    let old_details = con.hgetall(&details_key)?;
+    let created = old_details.is_empty();
+
+    let (dtype, plugin, title, extra) = match data {
+        Data::Hash { plugin, title, .. } => ("hash", plugin, title, None),
+        Data::List { plugin, title, .. } => ("list", plugin, title, None),
+        Data::String {
+            plugin,
+            title,
+            content_type,
+            ..
+        } => (
+            "string",
+            plugin,
+            title,
+            Some(("content_type", content_type.to_string())),
+        ),
+        Data::Table {
+            plugin,
+            title,
+            columns,
+            ..
+        } => (
+            "table",
+            plugin,
+            title,
+            Some(("columns", columns.to_string())),
+        ),
+    };
+
+    // Content of another type would be stored under different keys.
+    if old_details.get("type").is_some_and(|old| old != dtype) {
+        for key in [key, &order_key, &names_key, &titles_key] {
+            con.del(key)?;
+        }
+    }
+
+    let mut new_details = vec![("type", dtype), ("plugin", plugin), ("title", title)];
+    if let Some((field, value)) = &extra {
+        new_details.push((field, value));
+    }
+    let mut changed = sync_fields(con, &details_key, &new_details)?;
+
+    match data {
+        Data::Hash { content, .. } => {
+            let order = content.keys().collect::<Vec<_>>();
+            let old = con.hgetall(key)?;
+            if content.iter().any(|(k, v)| old.get(k) != Some(v))
+                || con.lrange(&order_key)?.iter().ne(order.iter().copied())
+            {
+                con.del(key)?;
+                for (field, value) in content {
+                    con.hset(key, field, value)?;
+                }
+                con.del(&order_key)?;
+                rpush(con, &order_key, &order)?;
+                changed = true;
+            }
+        }
+        Data::List { content, .. } => {
+            let names = content.iter().map(|item| &item.0).collect::<Vec<_>>();
+            let titles = content.iter().map(|item| &item.1).collect::<Vec<_>>();
+            let values = content.iter().map(|item| &item.2).collect::<Vec<_>>();
+            if con.lrange(&names_key)?.iter().ne(names.iter().copied())
+                || con.lrange(&titles_key)?.iter().ne(titles.iter().copied())
+                || con.lrange(key)?.iter().ne(values.iter().copied())
+            {
+                for key in [&names_key, &titles_key, key] {
+                    con.del(key)?;
+                }
+                rpush(con, &names_key, &names)?;
+                rpush(con, &titles_key, &titles)?;
+                rpush(con, key, &values)?;
+                changed = true;
+            }
+        }
+        Data::String { content, .. } => {
+            if con.get(key)?.as_ref() != Some(content) {
+                con.set(key, content)?;
+                changed = true;
+            }
+        }
+        Data::Table { content, .. } => {
+            if con.lrange(key)? != *content {
+                con.del(key)?;
+                rpush(con, key, content)?;
+                changed = true;
+            }
+        }
+    }
+
+    if created {
+        create_change(con, "created data", key, plugin, fields)?;
+    } else if changed {
+        create_change(con, "updated data", key, plugin, fields)?;
+    }
+
+    Ok(())
+}
+
+// Metadata
+
+/// Records a value reported for a metadata key, and returns true if changing to it should be
+/// held back because the value has changed more than the configured number of times within the window.
+/// The history of each key is a JSON list of the times and values of the changes reported within the window.
+fn is_dampened(
+    con: &dyn Keyspace,
+    meta_key: &str,
+    key: &str,
+    current: Option<&str>,
+    value: &str,
+) -> NetdoxResult<bool> {
+    let policy = con.hgetall(DAMPENING_KEY)?;
+    let (Some(window), Some(max_changes), Some(current)) = (
+        policy
+            .get("window")
+            .and_then(|window| window.parse::<u64>().ok()),
+        policy
+            .get("max_changes")
+            .and_then(|max| max.parse::<usize>().ok()),
+        current,
+    ) else {
+        return Ok(false);
+    };
+
+    let history_key = format!("{meta_key};history");
+    let encoded = con.hget(&history_key, key)?;
+    if encoded.is_none() && current == value {
+        return Ok(false);
+    }
+
+    let time = now_secs();
+    let mut history: Vec<(u64, String)> = match encoded {
+        Some(encoded) => match serde_json::from_str(&encoded) {
+            Ok(history) => history,
+            Err(err) => {
+                return store_err!(format!(
+                    "Failed to parse history of metadata {key} at {meta_key}: {err}"
+                ))
+            }
+        },
+        None => vec![],
+    };
+    history.retain(|(changed, _)| *changed >= time.saturating_sub(window));
+
+    let previous = history.last().map_or(current, |(_, value)| value.as_str());
+    if value != previous {
+        history.push((time, value.to_string()));
+    }
+
+    if history.is_empty() {
+        con.hdel(&history_key, key)?;
+    } else {
+        match serde_json::to_string(&history) {
+            Ok(encoded) => con.hset(&history_key, key, &encoded)?,
+            Err(err) => {
+                return store_err!(format!(
+                    "Failed to serialise history of metadata {key} at {meta_key}: {err}"
+                ))
+            }
+        }
+    }
+
+    Ok(value != current && history.len() > max_changes)
+}
+
+/// Adds tags to an object, and adds a change if it did not already have all of them.
+/// Tags are trimmed and lowercased, and can only contain letters, digits, hyphens and underscores.
+fn add_tags(con: &dyn Keyspace, id: &str, plugin: &str, tags: &[&str]) -> NetdoxResult<()> {
+    let mut valid = vec![];
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if !is_valid_tag(&tag) {
+            return store_err!(format!("Cannot set invalid tag on {id}: {tag}"));
+        }
+        valid.push(tag);
+    }
+
+    let tags_key = format!("{METADATA_KEY};{id};tags");
+    let mut changed = false;
+    for tag in &valid {
+        con.sadd(TAGS_KEY, tag)?;
+        con.sadd(&format!("{TAGS_KEY};{tag}"), id)?;
+        changed |= con.sadd(&tags_key, tag)?;
+    }
+
+    if changed {
+        create_change(con, "updated tags", &tags_key, plugin, &[("obj_id", id)])?;
+    }
+
+    Ok(())
+}
+
+/// Removes all the tags from an object, and any tags left with no objects.
+fn remove_tags(con: &dyn Keyspace, id: &str) -> NetdoxResult<()> {
+    let tags_key = format!("{METADATA_KEY};{id};tags");
+    for tag in con.smembers(&tags_key)? {
+        let tag_key = format!("{TAGS_KEY};{tag}");
+        con.srem(&tag_key, id)?;
+        if con.scard(&tag_key)? == 0 {
+            con.srem(TAGS_KEY, &tag)?;
+        }
+    }
+    con.del(&tags_key)
+}
+
+/// Writes a processed node. Its alt names and DNS names replace any from earlier runs.
+fn write_node(con: &dyn Keyspace, node: &Node) -> NetdoxResult<()> {
+    debug!(
+        "Writing processed node to key: {PROC_NODES_KEY};{}",
+        node.link_id
+    );
+    if node.dns_names.is_empty() {
+        return store_err!(format!(
+            "Cannot write node {} with no dns names.",
+            node.name
+        ));
+    } else if node.plugins.is_empty() {
+        return store_err!(format!(
+            "Cannot write node {} with no source plugins",
+            node.name
+        ));
+    } else if node.raw_ids.is_empty() {
+        return store_err!(format!(
+            "Cannot write node {} with no source raw ids",
+            node.name
+        ));
+    }
+
+    con.sadd(PROC_NODES_KEY, &node.link_id)?;
+
+    let key = format!("{PROC_NODES_KEY};{}", node.link_id);
+    con.set(&key, &node.name)?;
+
+    con.del(&format!("{key};alt_names"))?;
+    for name in &node.normalised_alt_names() {
+        con.sadd(&format!("{key};alt_names"), name)?;
+    }
+
+    con.del(&format!("{key};dns_names"))?;
+    for name in &node.dns_names {
+        con.sadd(&format!("{key};dns_names"), name)?;
+        con.hset(DNS_NODES_KEY, name, &node.link_id)?;
+    }
+
+    for plugin in &node.plugins {
+        con.sadd(&format!("{key};plugins"), plugin)?;
+    }
+
+    for raw_id in &node.raw_ids {
+        con.sadd(&format!("{key};raw_ids"), raw_id)?;
+        con.hset(PROC_NODE_REVS_KEY, raw_id, &node.link_id)?;
+    }
+
+    Ok(())
+}
+
+/// Removes the relations of a processed node to other nodes and of other nodes to it.
+fn remove_relations(con: &dyn Keyspace, link_id: &str) -> NetdoxResult<()> {
+    let relations_key = format!("{RELATIONS_KEY};{link_id}");
+    con.del(&format!("{relations_key};incoming"))?;
+    con.del(&relations_key)
+}
+
+/// Removes every service of a processed node.
+fn remove_services(con: &dyn Keyspace, link_id: &str) -> NetdoxResult<()> {
+    let services_key = format!("{SERVICES_KEY};{link_id}");
+    for id in con.smembers(&services_key)? {
+        let service_key = format!("{services_key};{id}");
+        con.del(&service_key)?;
+        con.del(&format!("{service_key};plugins"))?;
+    }
+    con.del(&services_key)
+}
+
+/// Returns true if the tag is not empty and only contains the characters allowed in tags.
+fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Sets metadata on an object, and adds a change if any of it changed.
+fn create_metadata(
+    con: &dyn Keyspace,
+    id: &str,
+    plugin: &str,
+    values: &HashMap<&str, &str>,
+) -> NetdoxResult<()> {
+    if let Some(role) = values.get(ROLE_META_KEY) {
+        if con.scard(ROLES_KEY)? > 0 && !con.sismember(ROLES_KEY, role)? {
+            return store_err!(format!("Cannot set invalid role on {id}: {role}"));
+        }
+    }
+
+    // Tags are added to the tags of the object instead of being stored as metadata.
+    if let Some(tags) = values.get(TAGS_META_KEY) {
+        let tags = tags
+            .split(',')
+            .filter(|tag| !tag.trim().is_empty())
+            .collect_vec();
+        add_tags(con, id, plugin, &tags)?;
+    }
+
+    con.sadd(METADATA_KEY, id)?;
+
+    let meta_key = format!("{METADATA_KEY};{id}");
+    let mut changed = con.sadd(&format!("{meta_key};plugins"), plugin)?;
+
+    let mut fields = vec![];
+    for (key, value) in values {
+        if *key == TAGS_META_KEY {
+            continue;
+        }
+        let current = con.hget(&meta_key, key)?;
+        if !is_dampened(con, &meta_key, key, current.as_deref(), value)? {
+            fields.push((*key, *value));
+        }
+    }
+
+    // The source of a key is the last plugin to change its value.
+    let sources_key = format!("{meta_key};sources");
+    let changed_key = format!("{meta_key};changed");
+    let time = now_secs().to_string();
+    for (key, value) in &fields {
+        if con.hget(&meta_key, key)?.as_deref() != Some(*value) {
+            con.hset(&sources_key, key, plugin)?;
+            con.hset(&changed_key, key, &time)?;
+        } else if con.hget(&sources_key, key)?.is_none() {
+            con.hset(&sources_key, key, plugin)?;
+        }
+    }
+
+    changed |= sync_fields(con, &meta_key, &fields)?;
+
+    if changed {
+        create_change(
+            con,
+            "updated metadata",
+            &meta_key,
+            plugin,
+            &[("obj_id", id)],
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use futures::TryStreamExt;
+    use indexmap::IndexMap;
+    use itertools::Itertools;
+
+    use super::{
+        add_tags, create_change, create_data, create_dns_name, create_metadata, delete_object_data,
+        get_dns_records, now_secs, rpush, write_node, DNS_NODES_KEY, PROC_NODE_REVS_KEY,
+    };
+    use crate::data::{
+        model::{
+            Change, Data, DataKind, Dependency, Node, ProcessRun, Relation, RunKind, RunLock,
+            Service, CONFLICT_POLICY_KEY, DAMPENING_KEY, DEPENDENCIES_KEY, DNS,
+            DNS_IGNORE_PATTERNS_KEY, DNS_KEY, METADATA_KEY, NETDOX_PLUGIN, NETWORKS_KEY, NODES_KEY,
+            NODE_ID_META_KEY, PDATA_KEY, PLUGIN_SCOPES_KEY, PROC_NODES_KEY, RELATIONS_KEY,
+            RUN_LOCK_KEY, SERVICES_KEY,
+        },
+        store::memory_store::MemoryStore,
+        stream::{dns_batches, node_batches, raw_node_batches},
+        DataConn, DataStore,
+    };
+    use crate::{error::NetdoxError, store_err};
+
+    const PLUGIN: &str = "test-plugin";
+
+    /// Returns an empty store with a default network.
+    fn store() -> MemoryStore {
+        let store = MemoryStore::new();
+        store
+            .write(|con| con.set(super::DEFAULT_NETWORK_KEY, "default-net"))
+            .unwrap();
+        store
+    }
+
+    #[tokio::test]
+    async fn test_node_roundtrip() {
+        let mut con = store();
+        let node = Node {
+            name: "server".to_string(),
+            link_id: "server-id".to_string(),
+            alt_names: HashSet::from(["alt".to_string()]),
+            dns_names: HashSet::from(["[net]a.com".to_string(), "[net]b.com".to_string()]),
+            plugins: HashSet::from([PLUGIN.to_string()]),
+            raw_ids: HashSet::from(["[net]a.com;[net]b.com".to_string()]),
+        };
+
+        con.put_processed(&[], &[&node]).await.unwrap();
+
+        assert_eq!(con.get_node("server-id").await.unwrap(), node);
+        assert_eq!(
+            con.get_node_ids().await.unwrap(),
+            HashSet::from(["server-id".to_string()])
+        );
+        assert_eq!(
+            con.get_node_from_raw("[net]a.com;[net]b.com")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("server-id")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_put_processed_atomic() {
+        let mut con = store();
+        let node = Node {
+            name: "server".to_string(),
+            link_id: "server-id".to_string(),
+            alt_names: HashSet::new(),
+            dns_names: HashSet::from(["[net]a.com".to_string()]),
+            plugins: HashSet::from([PLUGIN.to_string()]),
+            raw_ids: HashSet::from(["[net]a.com".to_string()]),
+        };
+        let invalid = Node {
+            link_id: "invalid-id".to_string(),
+            plugins: HashSet::new(),
+            ..node.clone()
+        };
+        let resolved = [("[net]a.com".to_string(), "server-id".to_string())];
+
+        // Nothing is written if any node is invalid.
+        assert!(con
+            .put_processed(&resolved, &[&node, &invalid])
+            .await
+            .is_err());
+        assert!(con.get_node_ids().await.unwrap().is_empty());
+        assert!(con.get_dns_metadata("[net]a.com").await.unwrap().is_empty());
+
+        con.put_processed(&resolved, &[&node]).await.unwrap();
+        assert_eq!(con.get_node("server-id").await.unwrap(), node);
+        assert_eq!(
+            con.get_dns_metadata("[net]a.com")
+                .await
+                .unwrap()
+                .get(NODE_ID_META_KEY)
+                .map(String::as_str),
+            Some("server-id")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_put_processed_replaces() {
+        let mut con = store();
+        let server = Node {
+            name: "server".to_string(),
+            link_id: "server-id".to_string(),
+            alt_names: HashSet::new(),
+            dns_names: HashSet::from(["[net]a.com".to_string()]),
+            plugins: HashSet::from([PLUGIN.to_string()]),
+            raw_ids: HashSet::from(["[net]a.com".to_string()]),
+        };
+        let old = Node {
+            name: "old".to_string(),
+            link_id: "old-id".to_string(),
+            dns_names: HashSet::from(["[net]b.com".to_string()]),
+            raw_ids: HashSet::from(["[net]b.com".to_string()]),
+            ..server.clone()
+        };
+        con.put_processed(
+            &[
+                ("[net]a.com".to_string(), "server-id".to_string()),
+                ("[net]b.com".to_string(), "old-id".to_string()),
+            ],
+            &[&server, &old],
+        )
+        .await
+        .unwrap();
+
+        // Nodes and index entries that the next run does not produce are removed.
+        let server = Node {
+            plugins: HashSet::from(["other-plugin".to_string()]),
+            raw_ids: HashSet::from(["[net]a.com;[net]c.com".to_string()]),
+            ..server
+        };
+        con.put_processed(
+            &[("[net]a.com".to_string(), "server-id".to_string())],
+            &[&server],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            con.get_node_ids().await.unwrap(),
+            HashSet::from(["server-id".to_string()])
+        );
+        assert_eq!(con.get_node("server-id").await.unwrap(), server);
+        assert_eq!(con.get_node_from_raw("[net]a.com").await.unwrap(), None);
+        assert_eq!(con.get_node_from_raw("[net]b.com").await.unwrap(), None);
+        assert!(!con
+            .get_dns_metadata("[net]b.com")
+            .await
+            .unwrap()
+            .contains_key(NODE_ID_META_KEY));
+    }
+
+    #[tokio::test]
+    async fn test_node_alt_names() {
+        let mut con = store();
+        let mut node = Node {
+            name: "server".to_string(),
+            link_id: "server-id".to_string(),
+            alt_names: HashSet::from(["Web01".to_string(), "old".to_string()]),
+            dns_names: HashSet::from(["[net]a.com".to_string()]),
+            plugins: HashSet::from([PLUGIN.to_string()]),
+            raw_ids: HashSet::from(["[net]a.com".to_string()]),
+        };
+        con.put_processed(&[], &[&node]).await.unwrap();
+
+        // Alt names from earlier runs are replaced.
+        node.alt_names = HashSet::from([
+            "web01".to_string(),
+            "WEB01.corp.com.".to_string(),
+            "web01.corp.com".to_string(),
+        ]);
+        con.put_processed(&[], &[&node]).await.unwrap();
+
+        assert_eq!(
+            con.get_node("server-id").await.unwrap().alt_names,
+            HashSet::from(["web01.corp.com".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_node_dependencies() {
+        let mut con = store();
+        con.write(|con| {
+            for link_id in ["app", "db", "proxy"] {
+                con.sadd(PROC_NODES_KEY, link_id)?;
+            }
+            con.hset(&format!("{DEPENDENCIES_KEY};app"), "db", "database")?;
+            con.sadd(&format!("{DEPENDENCIES_KEY};db;dependents"), "app")?;
+            con.hset(&format!("{DEPENDENCIES_KEY};proxy"), "app", "")?;
+            con.sadd(&format!("{DEPENDENCIES_KEY};app;dependents"), "proxy")?;
+            // Dependencies on nodes that no longer exist are skipped.
+            con.hset(&format!("{DEPENDENCIES_KEY};app"), "gone", "")
+        })
+        .unwrap();
+
+        assert_eq!(
+            con.get_node_dependencies("app").await.unwrap(),
+            vec![
+                Dependency::new("app", "db", "database".to_string()),
+                Dependency::new("proxy", "app", String::new()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_node_relations() {
+        let mut con = store();
+        let relation = |source: &str, dest: &str, kind: &str| Relation {
+            source: source.to_string(),
+            dest: dest.to_string(),
+            kind: kind.to_string(),
+        };
+        con.write(|con| {
+            for link_id in ["vm", "host", "replica"] {
+                con.sadd(PROC_NODES_KEY, link_id)?;
+            }
+            con.sadd(&format!("{RELATIONS_KEY};vm"), "vm-on-host;host")?;
+            con.sadd(&format!("{RELATIONS_KEY};host;incoming"), "vm-on-host;vm")?;
+            con.sadd(&format!("{RELATIONS_KEY};replica"), "replicates-to;vm")?;
+            con.sadd(
+                &format!("{RELATIONS_KEY};vm;incoming"),
+                "replicates-to;replica",
+            )?;
+            // Relations with nodes that no longer exist are skipped.
+            con.sadd(&format!("{RELATIONS_KEY};vm"), "vm-on-host;gone")
+        })
+        .unwrap();
+
+        assert_eq!(
+            con.get_node_relations("vm").await.unwrap(),
+            vec![
+                relation("replica", "vm", "replicates-to"),
+                relation("vm", "host", "vm-on-host"),
+            ]
+        );
+        assert_eq!(
+            con.get_node_relations("host").await.unwrap(),
+            vec![relation("vm", "host", "vm-on-host")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_node_services() {
+        let mut con = store();
+        con.write(|con| {
+            for id in ["443/tcp", "53/udp", "53/tcp"] {
+                con.sadd(&format!("{SERVICES_KEY};app"), id)?;
+                con.sadd(&format!("{SERVICES_KEY};app;{id};plugins"), PLUGIN)?;
+            }
+            con.hset(&format!("{SERVICES_KEY};app;443/tcp"), "product", "nginx")
+        })
+        .unwrap();
+
+        let services = con.get_node_services("app").await.unwrap();
+        assert_eq!(
+            services.iter().map(Service::id).collect::<Vec<_>>(),
+            vec!["53/tcp", "53/udp", "443/tcp"]
+        );
+        assert_eq!(
+            services[2].details,
+            HashMap::from([("product".to_string(), "nginx".to_string())])
+        );
+        assert_eq!(services[2].plugins, HashSet::from([PLUGIN.to_string()]));
+        assert!(con.get_node_services("db").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_run_lock() {
+        let mut con = store();
+        let lock = |owner: &str, expires: u64| RunLock {
+            owner: owner.to_string(),
+            kind: RunKind::Update,
+            host: "host".to_string(),
+            pid: 1,
+            acquired: 0,
+            expires,
+        };
+        let later = now_secs() + 60;
+
+        assert_eq!(
+            con.acquire_run_lock(&lock("first", later)).await.unwrap(),
+            None
+        );
+        // The owner can refresh its own lock, but nobody else can take it.
+        assert_eq!(
+            con.acquire_run_lock(&lock("first", later)).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            con.acquire_run_lock(&lock("second", later)).await.unwrap(),
+            Some(lock("first", later))
+        );
+
+        // Releasing a lock held by someone else does nothing.
+        con.release_run_lock("second").await.unwrap();
+        con.release_run_lock("first").await.unwrap();
+        assert_eq!(
+            con.acquire_run_lock(&lock("second", 1)).await.unwrap(),
+            None
+        );
+
+        // Expired locks are free.
+        assert_eq!(
+            con.acquire_run_lock(&lock("third", later)).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            con.force_run_unlock().await.unwrap(),
+            Some(lock("third", later))
+        );
+        assert_eq!(con.force_run_unlock().await.unwrap(), None);
+
+        // Backups leave out the lock.
+        con.acquire_run_lock(&lock("fourth", later)).await.unwrap();
+        assert!(!con.dump().await.unwrap().keys.contains_key(RUN_LOCK_KEY));
+    }
+
+    #[tokio::test]
+    async fn test_deferred_plugins() {
+        let mut con = store();
+        let plugins = HashSet::from(["dns".to_string(), "monitor".to_string()]);
+
+        con.set_deferred_plugins(&plugins).await.unwrap();
+        assert_eq!(con.get_deferred_plugins().await.unwrap(), plugins);
+
+        con.set_deferred_plugins(&HashSet::new()).await.unwrap();
+        assert!(con.get_deferred_plugins().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_metadata_provenance() {
+        let mut con = store();
+        let qname = "[default-net]domain.com";
+        con.put_dns_metadata("domain.com", PLUGIN, HashMap::from([("os", "linux")]))
+            .await
+            .unwrap();
+        con.put_dns_metadata("domain.com", "other-plugin", HashMap::from([("os", "bsd")]))
+            .await
+            .unwrap();
+        // Reporting the same value again does not change the source.
+        con.put_dns_metadata("domain.com", PLUGIN, HashMap::from([("os", "bsd")]))
+            .await
+            .unwrap();
+
+        let provenance = con.get_dns_metadata_provenance(qname).await.unwrap();
+        let os = &provenance["os"];
+        assert_eq!(os.value, "bsd");
+        assert_eq!(os.plugin.as_deref(), Some("other-plugin"));
+        assert!(os.changed.is_some_and(|changed| changed > 0));
+    }
+
+    #[tokio::test]
+    async fn test_metadata_conflicts() {
+        let mut con = store();
+        let node = Node {
+            name: "server".to_string(),
+            link_id: "server-id".to_string(),
+            alt_names: HashSet::new(),
+            dns_names: HashSet::from(["[net]a.com".to_string(), "[net]b.com".to_string()]),
+            plugins: HashSet::from(["cmdb".to_string(), "vcenter".to_string()]),
+            raw_ids: HashSet::from(["[net]a.com".to_string(), "[net]b.com".to_string()]),
+        };
+        con.write(|con| {
+            create_metadata(
+                con,
+                &format!("{NODES_KEY};[net]a.com"),
+                "cmdb",
+                &HashMap::from([("os", "linux"), ("owner", "ops")]),
+            )?;
+            create_metadata(
+                con,
+                &format!("{NODES_KEY};[net]b.com"),
+                "vcenter",
+                &HashMap::from([("os", "windows"), ("owner", "ops")]),
+            )?;
+            con.hset(CONFLICT_POLICY_KEY, "strategy", "last-wins")?;
+            rpush(con, &format!("{CONFLICT_POLICY_KEY};priority"), &["cmdb"])
+        })
+        .unwrap();
+
+        assert_eq!(
+            con.get_conflict_policy().await.unwrap().priority,
+            vec!["cmdb".to_string()]
+        );
+        assert_eq!(con.get_node_metadata(&node).await.unwrap()["os"], "linux");
+        assert_eq!(
+            con.get_node_metadata_sources(&node).await.unwrap()["os"],
+            "cmdb"
+        );
+
+        let conflicts = con.get_node_metadata_conflicts(&node).await.unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].field(), "os");
+        assert_eq!(conflicts[0].rejected[0].value, "windows");
+
+        // Metadata set on the processed node overrides both, so they no longer conflict.
+        con.put_node_metadata("server-id", "vcenter", HashMap::from([("os", "bsd")]))
+            .await
+            .unwrap();
+        assert_eq!(con.get_node_metadata(&node).await.unwrap()["os"], "bsd");
+        assert!(con
+            .get_node_metadata_conflicts(&node)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_plugin_cursors() {
+        let mut con = store();
+        assert_eq!(
+            con.get_plugin_cursor(PLUGIN, "write-only").await.unwrap(),
+            None
+        );
+
+        con.set_plugin_cursor(PLUGIN, "write-only", "etag-1")
+            .await
+            .unwrap();
+        con.set_plugin_cursor(PLUGIN, "write-only", "etag-2")
+            .await
+            .unwrap();
+        assert_eq!(
+            con.get_plugin_cursor(PLUGIN, "write-only").await.unwrap(),
+            Some("etag-2".to_string())
+        );
+        assert_eq!(
+            con.get_plugin_cursor(PLUGIN, "read-write").await.unwrap(),
+            None
+        );
+
+        con.clear_plugin_cursors().await.unwrap();
+        assert_eq!(
+            con.get_plugin_cursor(PLUGIN, "write-only").await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remote_cursor() {
+        let mut con = store();
+        assert_eq!(con.get_remote_cursor("vault").await.unwrap(), None);
+        assert!(con.swap_remote_cursor("vault", None, "1-0").await.unwrap());
+
+        // A publish that started before the swap cannot advance the cursor.
+        assert!(!con.swap_remote_cursor("vault", None, "2-0").await.unwrap());
+        assert!(con
+            .swap_remote_cursor("vault", Some("1-0"), "3-0")
+            .await
+            .unwrap());
+        assert_eq!(
+            con.get_remote_cursor("vault").await.unwrap(),
+            Some("3-0".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_publish_cursors() {
+        let mut con = store();
+        assert!(con.get_publish_cursors().await.unwrap().is_empty());
+
+        con.put_publish_cursor("pageseeder", "1-0").await.unwrap();
+        con.put_publish_cursor("mirror", "1-0").await.unwrap();
+        con.put_publish_cursor("pageseeder", "2-0").await.unwrap();
+        assert_eq!(
+            con.get_publish_cursors().await.unwrap(),
+            HashMap::from([
+                ("pageseeder".to_string(), "2-0".to_string()),
+                ("mirror".to_string(), "1-0".to_string()),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clear_process() {
+        let mut con = store();
+        let node = Node {
+            name: "server".to_string(),
+            link_id: "server-id".to_string(),
+            alt_names: HashSet::new(),
+            dns_names: HashSet::from(["[net]a.com".to_string()]),
+            plugins: HashSet::from([PLUGIN.to_string()]),
+            raw_ids: HashSet::from(["[net]a.com".to_string()]),
+        };
+        let run = ProcessRun {
+            started: 1,
+            nodes: HashSet::from(["server-id".to_string()]),
+            dns_names: HashSet::from(["[net]a.com".to_string()]),
+        };
+
+        con.begin_process(&run).await.unwrap();
+        con.put_dns_metadata(
+            "[net]a.com",
+            NETDOX_PLUGIN,
+            HashMap::from([(NODE_ID_META_KEY, "server-id"), ("other", "value")]),
+        )
+        .await
+        .unwrap();
+        con.put_processed(&[], &[&node]).await.unwrap();
+
+        assert_eq!(
+            con.get_interrupted_process().await.unwrap(),
+            Some(run.clone())
+        );
+        con.clear_process(&run).await.unwrap();
+
+        assert_eq!(con.get_interrupted_process().await.unwrap(), None);
+        assert!(con.get_node_ids().await.unwrap().is_empty());
+        assert_eq!(con.get_node_from_raw("[net]a.com").await.unwrap(), None);
+        assert_eq!(
+            con.get_dns_metadata("[net]a.com").await.unwrap(),
+            HashMap::from([("other".to_string(), "value".to_string())])
+        );
+
+        con.begin_process(&run).await.unwrap();
+        con.end_process().await.unwrap();
+        assert_eq!(con.get_interrupted_process().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_dns_ignore_patterns() {
+        let mut con = store();
+        con.write(|con| {
+            con.sadd(DNS_IGNORE_PATTERNS_KEY, "*.dyn.com")?;
+            create_dns_name(con, "[net]host.dyn.com", PLUGIN)?;
+            create_dns_name(con, "[net]dyn.com", PLUGIN)
+        })
+        .unwrap();
+
+        assert_eq!(
+            con.get_dns_names().await.unwrap(),
+            HashSet::from(["[net]dyn.com".to_string()])
+        );
+        assert_eq!(
+            con.get_dns_ignore_patterns().await.unwrap(),
+            HashSet::from(["*.dyn.com".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_plugin_scope() {
+        let mut con = store();
+        con.write(|con| {
+            con.sadd(PLUGIN_SCOPES_KEY, PLUGIN)?;
+            con.sadd(&format!("{PLUGIN_SCOPES_KEY};{PLUGIN};zones"), "corp.com")?;
+            create_dns_name(con, "[net]web.corp.com", PLUGIN)?;
+            create_dns_name(con, "[net]10.0.0.1", PLUGIN)?;
+            create_dns_name(con, "[net]corp.org", PLUGIN)?;
+            create_dns_name(con, "[net]corp.org", NETDOX_PLUGIN)
+        })
+        .unwrap();
+        con.put_dns_metadata("[net]web.org", PLUGIN, HashMap::from([("key", "value")]))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            con.get_dns_names().await.unwrap(),
+            HashSet::from([
+                "[net]web.corp.com".to_string(),
+                "[net]10.0.0.1".to_string(),
+                "[net]corp.org".to_string()
+            ])
+        );
+        assert!(con
+            .get_dns_metadata("[net]web.org")
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            con.get_scope_violations().await.unwrap(),
+            HashMap::from([(
+                PLUGIN.to_string(),
+                HashSet::from(["[net]corp.org".to_string(), "[net]web.org".to_string()])
+            )])
+        );
+
+        con.clear_scope_violations().await.unwrap();
+        assert!(con.get_scope_violations().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unregistered_network() {
+        let mut con = store();
+        con.write(|con| create_dns_name(con, "[old]host.com", PLUGIN))
+            .unwrap();
+        con.write(|con| con.sadd(NETWORKS_KEY, "net").map(|_| ()))
+            .unwrap();
+
+        assert!(con
+            .write(|con| create_dns_name(con, "[old]new.com", PLUGIN))
+            .is_err());
+        con.write(|con| {
+            create_dns_name(con, "[old]host.com", PLUGIN)?;
+            create_dns_name(con, "[old]new.com", NETDOX_PLUGIN)?;
+            create_dns_name(con, "[net]host.com", PLUGIN)
+        })
+        .unwrap();
+
+        assert_eq!(
+            con.get_dns_names().await.unwrap(),
+            HashSet::from([
+                "[old]host.com".to_string(),
+                "[old]new.com".to_string(),
+                "[net]host.com".to_string()
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_qualify_ipv6() {
+        let mut con = store();
+        assert_eq!(
+            con.qualify_dns_names(&[
+                "2001:DB8:0:0:0:0:0:1",
+                "[net]2001:db8:0:1:0:0:0:1",
+                "[net]10.0.0.1",
+                "A.com",
+            ])
+            .await
+            .unwrap(),
+            vec![
+                "[default-net]2001:db8::1".to_string(),
+                "[net]2001:db8:0:1::1".to_string(),
+                "[net]10.0.0.1".to_string(),
+                "[default-net]a.com".to_string(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scan_batches() {
+        let mut con = store();
+        con.write(|con| {
+            for index in 0..5 {
+                create_dns_name(con, &format!("[net]{index}.com"), PLUGIN)?;
+                let node = format!("[net]{index}.com");
+                con.sadd(NODES_KEY, &node)?;
+                con.set(&format!("{NODES_KEY};{node}"), "1")?;
+                con.hset(&format!("{NODES_KEY};{node};1"), "plugin", PLUGIN)?;
+                con.hset(&format!("{NODES_KEY};{node};1"), "exclusive", "false")?;
+                write_node(
+                    con,
+                    &Node {
+                        name: format!("node {index}"),
+                        link_id: format!("node-{index}"),
+                        alt_names: HashSet::new(),
+                        dns_names: HashSet::from([node.clone()]),
+                        plugins: HashSet::from([PLUGIN.to_string()]),
+                        raw_ids: HashSet::from([node]),
+                    },
+                )?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        let store = DataStore::Memory(con.clone());
+        let dns = dns_batches(store.clone(), 2)
+            .try_fold(DNS::new(), |mut dns, batch| async move {
+                dns.extend(batch);
+                Ok(dns)
+            })
+            .await
+            .unwrap();
+        assert_eq!(dns, con.get_dns().await.unwrap());
+
+        let batches = raw_node_batches(store.clone(), 2)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(batches.iter().map(Vec::len).collect_vec(), vec![2, 2, 1]);
+        let raw = con.get_raw_nodes().await.unwrap();
+        assert!(batches.iter().flatten().all(|node| raw.contains(node)));
+
+        let nodes = node_batches(store, 5).try_concat().await.unwrap();
+        assert_eq!(
+            nodes
+                .iter()
+                .map(|node| node.link_id.clone())
+                .collect::<HashSet<_>>(),
+            con.get_node_ids().await.unwrap()
+        );
+        assert_eq!(nodes[0], con.get_node(&nodes[0].link_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_document_bundles() {
+        let mut con = store();
+        let qname = "[net]bundle.com";
+        let node = Node {
+            name: "bundle".to_string(),
+            link_id: "bundle-node".to_string(),
+            alt_names: HashSet::new(),
+            dns_names: HashSet::from([qname.to_string()]),
+            plugins: HashSet::from([PLUGIN.to_string()]),
+            raw_ids: HashSet::from([qname.to_string()]),
+        };
+        con.write(|con| {
+            create_dns_name(con, qname, PLUGIN)?;
+            con.sadd(NODES_KEY, qname)?;
+            con.set(&format!("{NODES_KEY};{qname}"), "1")?;
+            con.hset(&format!("{NODES_KEY};{qname};1"), "plugin", PLUGIN)?;
+            con.hset(&format!("{NODES_KEY};{qname};1"), "exclusive", "false")?;
+            add_tags(con, &format!("{DNS_KEY};{qname}"), PLUGIN, &["web"])?;
+            write_node(con, &node)
+        })
+        .unwrap();
+        con.put_note(&format!("{DNS_KEY};{qname}"), "owner", Some("ops"))
+            .await
+            .unwrap();
+        con.put_note(
+            &format!("{PROC_NODES_KEY};{}", node.link_id),
+            "owner",
+            Some("infra"),
+        )
+        .await
+        .unwrap();
+
+        let bundle = con.get_dns_bundle(qname).await.unwrap();
+        assert_eq!(bundle.dns, con.get_dns_subset(&[qname]).await.unwrap());
+        assert_eq!(bundle.tags, HashSet::from(["web".to_string()]));
+        assert_eq!(
+            bundle.metadata,
+            con.get_dns_metadata_provenance(qname).await.unwrap()
+        );
+        assert_eq!(bundle.notes["owner"], "ops");
+
+        let bundle = con.get_node_bundle(&node).await.unwrap();
+        assert_eq!(bundle.tags, con.get_node_tags(&node).await.unwrap());
+        assert_eq!(bundle.notes["owner"], "infra");
+        assert!(bundle.dependencies.is_empty());
+        assert!(bundle.pdata.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_raw_nodes() {
+        let mut con = store();
+        con.write(|con| {
+            con.sadd("nodes", "[net]a.com;[net]b.com")?;
+            con.set("nodes;[net]a.com;[net]b.com", "1")?;
+            con.hset("nodes;[net]a.com;[net]b.com;1", "plugin", PLUGIN)?;
+            con.hset("nodes;[net]a.com;[net]b.com;1", "exclusive", "true")
+        })
+        .unwrap();
+
+        let raw = con.get_raw_nodes().await.unwrap();
+        assert_eq!(raw.len(), 1);
+        assert_eq!(
+            raw[0].dns_names,
+            HashSet::from(["[net]a.com".to_string(), "[net]b.com".to_string()])
+        );
+        assert!(raw[0].exclusive);
+        assert_eq!(raw[0].name, None);
+    }
+
+    #[tokio::test]
+    async fn test_metadata_changes() {
+        let mut con = store();
+        con.init().await.unwrap();
+        let data = HashMap::from([("owner", "ops")]);
+
+        con.put_dns_metadata("Domain.COM", PLUGIN, data.clone())
+            .await
+            .unwrap();
+        con.put_dns_metadata("domain.com", PLUGIN, data)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            con.get_dns_metadata("[default-net]domain.com")
+                .await
+                .unwrap(),
+            HashMap::from([("owner".to_string(), "ops".to_string())])
+        );
+
+        let changes = con
+            .get_changes(None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.change)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec![
+                Change::Init,
+                Change::CreateDnsName {
+                    plugin: PLUGIN.to_string(),
+                    qname: "[default-net]domain.com".to_string()
+                },
+                Change::UpdatedMetadata {
+                    plugin: PLUGIN.to_string(),
+                    obj_id: format!("{DNS_KEY};[default-net]domain.com")
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tags() {
+        let mut con = store();
+        con.init().await.unwrap();
+        let qname = "[default-net]domain.com";
+
+        con.put_dns_metadata(
+            "domain.com",
+            PLUGIN,
+            HashMap::from([("tags", "Web, prod,"), ("owner", "ops")]),
+        )
+        .await
+        .unwrap();
+        con.put_dns_metadata("domain.com", PLUGIN, HashMap::from([("tags", "web")]))
+            .await
+            .unwrap();
+        assert!(con
+            .put_dns_metadata("domain.com", PLUGIN, HashMap::from([("tags", "not a tag")]))
+            .await
+            .is_err());
+
+        assert_eq!(
+            con.get_dns_tags(qname).await.unwrap(),
+            HashSet::from(["web".to_string(), "prod".to_string()])
+        );
+        assert_eq!(
+            con.get_dns_metadata(qname).await.unwrap(),
+            HashMap::from([("owner".to_string(), "ops".to_string())])
+        );
+
+        let tag_changes = con
+            .get_changes(None)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|entry| matches!(entry.change, Change::UpdatedTags { .. }))
+            .count();
+        assert_eq!(tag_changes, 1);
+
+        con.rename_network("default-net", "other-net", false)
+            .await
+            .unwrap();
+        assert_eq!(
+            con.get_tagged("web").await.unwrap(),
+            HashSet::from([format!("{DNS_KEY};[other-net]domain.com")])
+        );
+        assert_eq!(
+            con.get_dns_tags("[other-net]domain.com")
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+
+        con.write(|con| delete_object_data(con, &format!("{DNS_KEY};[other-net]domain.com")))
+            .unwrap();
+        assert!(con.get_tags().await.unwrap().is_empty());
+        assert!(con.get_tagged("web").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_metadata_dampening() {
+        let mut con = store();
+        con.write(|con| {
+            con.hset(DAMPENING_KEY, "window", "3600")?;
+            con.hset(DAMPENING_KEY, "max_changes", "2")
+        })
+        .unwrap();
+
+        let qname = "[default-net]domain.com";
+        for (value, expected) in [
+            ("10.0.0.1", "10.0.0.1"),
+            ("10.0.0.2", "10.0.0.2"),
+            ("10.0.0.1", "10.0.0.1"),
+            // Third change within the window is held back.
+            ("10.0.0.2", "10.0.0.1"),
+            ("10.0.0.1", "10.0.0.1"),
+        ] {
+            con.put_dns_metadata(qname, PLUGIN, HashMap::from([("address", value)]))
+                .await
+                .unwrap();
+            assert_eq!(
+                con.get_dns_metadata(qname).await.unwrap().get("address"),
+                Some(&expected.to_string())
+            );
+        }
+
+        // Once the changes fall outside the window, the value can change again.
+        con.write(|con| {
+            con.hset(
+                &format!("{METADATA_KEY};{DNS_KEY};{qname};history"),
+                "address",
+                "[[0,\"10.0.0.2\"],[0,\"10.0.0.1\"]]",
+            )
+        })
+        .unwrap();
+        con.put_dns_metadata(qname, PLUGIN, HashMap::from([("address", "10.0.0.2")]))
+            .await
+            .unwrap();
+        assert_eq!(
+            con.get_dns_metadata(qname).await.unwrap().get("address"),
+            Some(&"10.0.0.2".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_changelog_order() {
+        let mut con = store();
+        con.write(|con| {
+            for _ in 0..3 {
+                create_change(con, "create report", "report", PLUGIN, &[])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        let changes = con.get_changes(None).await.unwrap();
+        assert_eq!(changes.len(), 3);
+        assert_eq!(con.total_change_count().await.unwrap(), 3);
+        assert_eq!(con.last_change_id().await.unwrap(), changes[2].id);
+
+        let after = con.get_changes(Some(&changes[0].id)).await.unwrap();
+        assert_eq!(
+            after.iter().map(|entry| &entry.id).collect::<Vec<_>>(),
+            vec![&changes[1].id, &changes[2].id]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_report_data() {
+        let mut con = store();
+        let data = Data::Hash {
+            id: "0".to_string(),
+            title: "Summary".to_string(),
+            plugin: PLUGIN.to_string(),
+            content: IndexMap::from([
+                ("b".to_string(), "2".to_string()),
+                ("a".to_string(), "1".to_string()),
+            ]),
+        };
+
+        con.put_report("report", "Report", 1).await.unwrap();
+        con.put_report_data("report", 0, &data).await.unwrap();
+        con.put_report_data("report", 0, &data).await.unwrap();
+
+        let report = con.get_report("report").await.unwrap();
+        assert_eq!(report.title, "Report");
+        assert_eq!(report.content, vec![data]);
+
+        let changes = con.get_changes(None).await.unwrap();
+        assert_eq!(changes.len(), 2);
+        assert!(matches!(
+            &changes[1].change,
+            Change::CreatedData { obj_id, kind: DataKind::Report, .. } if obj_id == "reports;report"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_index() {
+        let mut con = store();
+        let node = Node {
+            name: "server".to_string(),
+            link_id: "server-id".to_string(),
+            alt_names: HashSet::new(),
+            dns_names: HashSet::from(["[net]a.com".to_string()]),
+            plugins: HashSet::from([PLUGIN.to_string()]),
+            raw_ids: HashSet::from(["[net]a.com".to_string()]),
+        };
+        con.put_processed(&[], &[&node]).await.unwrap();
+        con.write(|con| {
+            con.hset("dns_nodes", "[net]stale.com", "server-id")?;
+            con.sadd(&format!("pdata;{PROC_NODES_KEY};gone"), "data")?;
+            Ok(())
+        })
+        .unwrap();
+
+        let repairs = con.rebuild_index().await.unwrap();
+        assert_eq!((repairs.dns_nodes, repairs.pdata), (1, 1));
+        assert_eq!(con.rebuild_index().await.unwrap().total(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_rename_network() {
+        let mut con = store();
+        let raw_id = "[old]b.com;[other]c.com";
+        con.write(|con| {
+            for qname in ["[old]a.com", "[old]b.com", "[other]c.com"] {
+                con.sadd(DNS_KEY, qname)?;
+            }
+            con.sadd(
+                &format!("{DNS_KEY};[other]c.com"),
+                &format!("{PLUGIN};CNAME;[old]a.com"),
+            )?;
+            con.sadd(&format!("{DNS_KEY};[old]a.com;implied"), "[other]c.com")?;
+            con.sadd(NODES_KEY, raw_id)?;
+            con.set(&format!("{NODES_KEY};{raw_id}"), "1")?;
+            con.hset(&format!("{NODES_KEY};{raw_id};1"), "plugin", PLUGIN)?;
+            con.hset(&format!("{NODES_KEY};{raw_id};1"), "exclusive", "false")?;
+            con.hset(PROC_NODE_REVS_KEY, raw_id, "server-id")
+        })
+        .unwrap();
+        con.put_dns_metadata("[old]a.com", PLUGIN, HashMap::from([("owner", "ops")]))
+            .await
+            .unwrap();
+
+        let dry_run = con.rename_network("old", "new", true).await.unwrap();
+        assert_eq!(
+            dry_run.dns_names,
+            vec![
+                ("[old]a.com".to_string(), "[new]a.com".to_string()),
+                ("[old]b.com".to_string(), "[new]b.com".to_string()),
+            ]
+        );
+        assert_eq!(
+            dry_run.nodes,
+            vec![(raw_id.to_string(), "[new]b.com;[other]c.com".to_string())]
+        );
+        assert!(con.get_dns_names().await.unwrap().contains("[old]a.com"));
+
+        assert_eq!(
+            con.rename_network("old", "new", false).await.unwrap(),
+            dry_run
+        );
+        assert_eq!(
+            con.get_dns_names().await.unwrap(),
+            HashSet::from([
+                "[new]a.com".to_string(),
+                "[new]b.com".to_string(),
+                "[other]c.com".to_string()
+            ])
+        );
+        assert_eq!(
+            con.get_dns_metadata("[new]a.com").await.unwrap(),
+            HashMap::from([("owner".to_string(), "ops".to_string())])
+        );
+
+        let records = con
+            .read(|con| get_dns_records(con, "[other]c.com"))
+            .unwrap();
+        assert_eq!(records[0].value, "[new]a.com");
+        let implied = con
+            .read(|con| con.smembers(&format!("{DNS_KEY};[new]a.com;implied")))
+            .unwrap();
+        assert_eq!(implied, HashSet::from(["[other]c.com".to_string()]));
+
+        let raw_nodes = con.get_raw_nodes().await.unwrap();
+        assert!(raw_nodes[0].dns_names.contains("[new]b.com"));
+        assert_eq!(
+            con.get_node_from_raw("[new]b.com;[other]c.com")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("server-id")
+        );
+
+        // Names that already exist in the new network are never overwritten.
+        con.write(|con| con.sadd(DNS_KEY, "[other]a.com")).unwrap();
+        assert!(con.rename_network("new", "other", false).await.is_err());
+        assert!(con.rename_network("new", "bad]net", true).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_prune() {
+        let mut con = store();
+        let (stale, fresh) = ("100", "1000");
+        con.write(|con| {
+            for qname in ["[net]a.com", "[net]b.com", "[net]c.com", "[net]d.com"] {
+                con.sadd(DNS_KEY, qname)?;
+            }
+            for (qname, record, seen) in [
+                ("[net]a.com", format!("{PLUGIN};CNAME;[net]b.com"), stale),
+                ("[net]b.com", format!("{PLUGIN};CNAME;[net]c.com"), fresh),
+            ] {
+                con.sadd(&format!("{DNS_KEY};{qname}"), &record)?;
+                con.hset(&format!("{DNS_KEY};{qname};seen"), &record, seen)?;
+                con.hset(&format!("{DNS_KEY};{qname};seen"), PLUGIN, seen)?;
+            }
+            con.sadd(&format!("{DNS_KEY};[net]b.com;implied"), "[net]a.com")?;
+            con.hset(&format!("{DNS_KEY};[net]d.com;seen"), PLUGIN, stale)?;
+
+            // A raw node with one stale plugin, and one with only a stale plugin.
+            con.sadd(NODES_KEY, "[net]c.com")?;
+            con.set(&format!("{NODES_KEY};[net]c.com"), "2")?;
+            for (index, plugin, seen) in [(1, PLUGIN, stale), (2, "other", fresh)] {
+                let details_key = format!("{NODES_KEY};[net]c.com;{index}");
+                con.hset(&details_key, "plugin", plugin)?;
+                con.hset(&details_key, "exclusive", "false")?;
+                con.hset(
+                    &format!("{NODES_KEY};[net]c.com;seen"),
+                    &index.to_string(),
+                    seen,
+                )?;
+            }
+            con.sadd(NODES_KEY, "[net]d.com")?;
+            con.set(&format!("{NODES_KEY};[net]d.com"), "1")?;
+            con.hset(&format!("{NODES_KEY};[net]d.com;1"), "plugin", PLUGIN)?;
+            con.hset(&format!("{NODES_KEY};[net]d.com;1"), "exclusive", "false")?;
+            con.hset(&format!("{NODES_KEY};[net]d.com;seen"), "1", stale)?;
+
+            con.sadd(PROC_NODES_KEY, "server")?;
+            con.sadd(&format!("{PROC_NODES_KEY};server;raw_ids"), "[net]d.com")?;
+            con.sadd(&format!("{PROC_NODES_KEY};server;dns_names"), "[net]d.com")?;
+            con.hset(PROC_NODE_REVS_KEY, "[net]d.com", "server")
+        })
+        .unwrap();
+
+        let dry_run = con.prune(Some(500), &HashMap::new(), true).await.unwrap();
+        assert_eq!(dry_run.records.len(), 1);
+        assert_eq!(dry_run.records[0].name, "[net]a.com");
+        assert_eq!(
+            dry_run.nodes.iter().cloned().sorted().collect::<Vec<_>>(),
+            vec![
+                ("[net]c.com".to_string(), PLUGIN.to_string()),
+                ("[net]d.com".to_string(), PLUGIN.to_string())
+            ]
+        );
+        assert_eq!(
+            dry_run.dns_names.iter().sorted().collect::<Vec<_>>(),
+            vec!["[net]a.com", "[net]d.com"]
+        );
+        assert_eq!(dry_run.proc_nodes, vec!["server".to_string()]);
+        assert_eq!(con.get_dns_names().await.unwrap().len(), 4);
+
+        let pruned = con.prune(Some(500), &HashMap::new(), false).await.unwrap();
+        assert_eq!(pruned.records, dry_run.records);
+        assert_eq!(
+            con.get_dns_names().await.unwrap(),
+            HashSet::from(["[net]b.com".to_string(), "[net]c.com".to_string()])
+        );
+        assert!(con
+            .read(|con| con.smembers(&format!("{DNS_KEY};[net]b.com;implied")))
+            .unwrap()
+            .is_empty());
+
+        let raw_nodes = con.get_raw_nodes().await.unwrap();
+        assert_eq!(raw_nodes.len(), 1);
+        assert_eq!(raw_nodes[0].plugin, "other");
+        assert!(con.get_node_ids().await.unwrap().is_empty());
+        assert_eq!(con.get_node_from_raw("[net]d.com").await.unwrap(), None);
+
+        let changes = con
+            .get_changes(None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.change)
+            .collect::<Vec<_>>();
+        assert!(changes.contains(&Change::DeleteDnsName {
+            plugin: NETDOX_PLUGIN.to_string(),
+            qname: "[net]a.com".to_string()
+        }));
+        assert!(changes.contains(&Change::DeleteProcNode {
+            plugin: NETDOX_PLUGIN.to_string(),
+            link_id: "server".to_string()
+        }));
+
+        // A cutoff for one plugin leaves the data from other plugins.
+        let cutoffs = HashMap::from([(PLUGIN.to_string(), u64::MAX)]);
+        let pruned = con.prune(None, &cutoffs, false).await.unwrap();
+        assert_eq!(pruned.records.len(), 1);
+        assert!(pruned.nodes.is_empty());
+        assert_eq!(pruned.dns_names, vec!["[net]b.com".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_delete_dns() {
+        let mut con = store();
+        let raw_id = "[net]a.com;[net]c.com";
+        con.write(|con| {
+            for qname in ["[net]a.com", "[net]b.com", "[net]c.com", "[net]d.com"] {
+                con.sadd(DNS_KEY, qname)?;
+            }
+            con.sadd(
+                &format!("{DNS_KEY};[net]a.com"),
+                &format!("{PLUGIN};CNAME;[net]c.com"),
+            )?;
+            con.sadd(&format!("{DNS_KEY};[net]c.com;implied"), "[net]a.com")?;
+            con.sadd(
+                &format!("{DNS_KEY};[net]b.com"),
+                &format!("{PLUGIN};CNAME;[net]a.com"),
+            )?;
+            con.sadd(&format!("{DNS_KEY};[net]a.com;implied"), "[net]b.com")?;
+
+            // A raw node that uses the name, whose processed node is left with nothing.
+            con.sadd(NODES_KEY, raw_id)?;
+            con.set(&format!("{NODES_KEY};{raw_id}"), "1")?;
+            con.hset(&format!("{NODES_KEY};{raw_id};1"), "plugin", PLUGIN)?;
+            con.sadd(PROC_NODES_KEY, "server")?;
+            con.sadd(&format!("{PROC_NODES_KEY};server;raw_ids"), raw_id)?;
+            con.hset(PROC_NODE_REVS_KEY, raw_id, "server")?;
+
+            // A processed node whose raw node is kept.
+            con.sadd(NODES_KEY, "[net]d.com")?;
+            con.set(&format!("{NODES_KEY};[net]d.com"), "1")?;
+            con.hset(&format!("{NODES_KEY};[net]d.com;1"), "plugin", PLUGIN)?;
+            con.hset(&format!("{NODES_KEY};[net]d.com;1"), "exclusive", "false")?;
+            con.sadd(PROC_NODES_KEY, "web")?;
+            con.set(&format!("{PROC_NODES_KEY};web"), "Web")?;
+            con.sadd(&format!("{PROC_NODES_KEY};web;raw_ids"), "[net]d.com")?;
+            for qname in ["[net]a.com", "[net]d.com"] {
+                con.sadd(&format!("{PROC_NODES_KEY};web;dns_names"), qname)?;
+            }
+            con.hset(DNS_NODES_KEY, "[net]a.com", "web")
+        })
+        .unwrap();
+        con.put_dns_metadata("[net]a.com", PLUGIN, HashMap::from([("owner", "ops")]))
+            .await
+            .unwrap();
+
+        let dry_run = con.delete_dns("[net]a.com", true).await.unwrap();
+        assert!(dry_run.is_referenced());
+        assert_eq!(dry_run.records.len(), 1);
+        assert_eq!(dry_run.references[0].name, "[net]b.com");
+        assert_eq!(
+            dry_run.nodes,
+            vec![(raw_id.to_string(), PLUGIN.to_string())]
+        );
+        assert_eq!(dry_run.detached, vec!["web".to_string()]);
+        assert_eq!(dry_run.proc_nodes, vec!["server".to_string()]);
+        assert_eq!(con.get_dns_names().await.unwrap().len(), 4);
+
+        assert_eq!(con.delete_dns("[net]a.com", false).await.unwrap(), dry_run);
+        assert_eq!(
+            con.get_dns_names().await.unwrap(),
+            HashSet::from([
+                "[net]b.com".to_string(),
+                "[net]c.com".to_string(),
+                "[net]d.com".to_string()
+            ])
+        );
+        assert!(con.get_dns_metadata("[net]a.com").await.unwrap().is_empty());
+        assert!(con
+            .read(|con| get_dns_records(con, "[net]b.com"))
+            .unwrap()
+            .is_empty());
+        assert!(con
+            .read(|con| con.smembers(&format!("{DNS_KEY};[net]c.com;implied")))
+            .unwrap()
+            .is_empty());
+
+        let raw_nodes = con.get_raw_nodes().await.unwrap();
+        assert_eq!(raw_nodes.len(), 1);
+        assert_eq!(
+            con.get_node_ids().await.unwrap(),
+            HashSet::from(["web".to_string()])
+        );
+        assert_eq!(
+            con.get_node("web").await.unwrap().dns_names,
+            HashSet::from(["[net]d.com".to_string()])
+        );
+
+        let changes = con
+            .get_changes(None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.change)
+            .collect::<Vec<_>>();
+        assert!(changes.contains(&Change::DeleteDnsName {
+            plugin: NETDOX_PLUGIN.to_string(),
+            qname: "[net]a.com".to_string()
+        }));
+        assert!(changes.contains(&Change::DeletePluginNode {
+            plugin: PLUGIN.to_string(),
+            node_id: raw_id.to_string()
+        }));
+        assert!(changes.contains(&Change::DeleteProcNode {
+            plugin: NETDOX_PLUGIN.to_string(),
+            link_id: "server".to_string()
+        }));
+
+        assert!(con.delete_dns("[net]a.com", true).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_superseded_fragments() {
+        let mut con = store();
+        let obj_id = "dns;[net]domain.com";
+        let ids = vec![("old".to_string(), "new".to_string())];
+
+        con.put_superseded_fragments(obj_id, &ids).await.unwrap();
+        assert_eq!(
+            con.get_superseded_fragments().await.unwrap(),
+            HashMap::from([(
+                obj_id.to_string(),
+                HashMap::from([("old".to_string(), "new".to_string())])
+            )])
+        );
+
+        // Once the document is regenerated, no more are recorded for it.
+        con.clear_superseded_fragments(&[obj_id.to_string()])
+            .await
+            .unwrap();
+        con.put_superseded_fragments(obj_id, &ids).await.unwrap();
+        assert!(con.get_superseded_fragments().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dump_restore() {
+        let mut con = store();
+        con.init().await.unwrap();
+        con.put_note("dns;[net]domain.com", "owner", Some("ops"))
+            .await
+            .unwrap();
+        con.write(|con| {
+            con.sadd(DNS_KEY, "[net]domain.com")?;
+            rpush(con, "list", &["b", "a"])
+        })
+        .unwrap();
+
+        let dump = con.dump().await.unwrap();
+        assert_eq!(dump.changelog.len(), 2);
+
+        // Restoring requires an empty store.
+        assert!(con.restore(&dump).await.is_err());
+
+        let mut restored = MemoryStore::new();
+        restored.restore(&dump).await.unwrap();
+        assert_eq!(restored.dump().await.unwrap(), dump);
+        assert_eq!(
+            restored.last_change_id().await.unwrap(),
+            con.last_change_id().await.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_failed_write() {
+        let con = store();
+        con.write(|con| {
+            con.set("string", "old")?;
+            con.sadd("set", "old")?;
+            con.hset("hash", "field", "old")?;
+            con.rpush("list", "old")?;
+            con.add_change_field((1, 0), "change", "old")
+        })
+        .unwrap();
+        let before = con.read(|con| con.values()).unwrap();
+
+        let failed = con.write::<()>(|con| {
+            con.set("string", "new")?;
+            con.sadd("set", "new")?;
+            con.srem("set", "old")?;
+            con.hdel("hash", "field")?;
+            con.rename_key("list", "moved")?;
+            con.set("created", "new")?;
+            con.add_change_field((1, 0), "change", "new")?;
+            con.add_change_field((2, 0), "change", "new")?;
+            con.clear_changes()?;
+            con.add_change_field((3, 0), "change", "new")?;
+            con.clear()?;
+            con.set("string", "newer")?;
+            store_err!("Failed partway.".to_string())
+        });
+        assert!(failed.is_err());
+
+        con.read(|con| {
+            assert_eq!(con.values()?, before);
+            assert_eq!(
+                con.change_fields((0, 0))?,
+                vec![((1, 0), "change".to_string(), "old".to_string())]
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_lists() {
+        let con = store();
+        con.write(|con| {
+            for value in ["1", "2", "3"] {
+                con.lpush("list", value)?;
+            }
+            con.ltrim("list", 2)?;
+            assert_eq!(con.lrange("list")?, vec!["3", "2"]);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delete_pdata() {
+        let mut con = store();
+        let node = Node {
+            name: "server".to_string(),
+            link_id: "server-id".to_string(),
+            alt_names: HashSet::new(),
+            dns_names: HashSet::from(["[net]a.com".to_string()]),
+            plugins: HashSet::from([PLUGIN.to_string()]),
+            raw_ids: HashSet::from(["[net]a.com".to_string()]),
+        };
+        let obj_id = "nodes;[net]a.com";
+        con.write(|con| {
+            con.sadd(&format!("{PDATA_KEY};{obj_id}"), "data-id")?;
+            create_data(
+                con,
+                &format!("{PDATA_KEY};{obj_id};data-id"),
+                &[
+                    ("obj_id", obj_id),
+                    ("data_id", "data-id"),
+                    ("kind", "plugin"),
+                ],
+                &Data::Hash {
+                    id: "data-id".to_string(),
+                    title: "Title".to_string(),
+                    plugin: PLUGIN.to_string(),
+                    content: IndexMap::from([("key".to_string(), "val".to_string())]),
+                },
+            )
+        })
+        .unwrap();
+
+        assert!(con
+            .delete_node_pdata(&node, "other-plugin", "data-id")
+            .await
+            .is_err());
+        assert!(con
+            .delete_node_pdata(&node, PLUGIN, "data-id")
+            .await
+            .unwrap());
+        assert!(!con
+            .delete_node_pdata(&node, PLUGIN, "data-id")
+            .await
+            .unwrap());
+        assert!(con.get_node_pdata(&node).await.unwrap().is_empty());
+
+        let changes = con.get_changes(None).await.unwrap();
+        assert_eq!(
+            changes.last().map(|entry| &entry.change),
+            Some(&Change::DeletedData {
+                plugin: PLUGIN.to_string(),
+                obj_id: obj_id.to_string(),
+                data_id: "data-id".to_string(),
+                kind: DataKind::Plugin,
+            })
+        );
+    }
+}
@@ -0,0 +1,524 @@
+use crate::{
+    backup::read_backup,
+    data::model::{DataDump, StoredValue},
+    error::{NetdoxError, NetdoxResult},
+    store_err,
+};
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    ops::Bound,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard, OnceLock},
+};
+
+use super::keyspace::{restore_dump, Backend, ChangeField, Keyspace, KeyspaceStore};
+
+static OFFLINE: OnceLock<MemoryStore> = OnceLock::new();
+
+/// Makes every data store connection opened for the rest of the process
+/// read from the contents of a backup archive instead, which are loaded into memory.
+pub fn set_offline(path: &Path) -> NetdoxResult<()> {
+    let (_, dump) = read_backup(path)?;
+    let store = MemoryStore::from_dump(&dump)?;
+    if OFFLINE.set(store).is_err() {
+        return store_err!("Offline mode was already enabled.".to_string());
+    }
+    Ok(())
+}
+
+/// Returns the store loaded from a backup if offline mode was enabled for the rest of the process.
+pub fn offline_store() -> Option<MemoryStore> {
+    OFFLINE.get().cloned()
+}
+
+/// A data store that only exists in memory, for tests and reading backups offline.
+pub type MemoryStore = KeyspaceStore<MemoryBackend>;
+
+impl MemoryStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        KeyspaceStore::with_backend(MemoryBackend::default())
+    }
+
+    /// Creates a store with the keys and changelog entries of a dump.
+    pub fn from_dump(dump: &DataDump) -> NetdoxResult<Self> {
+        let store = Self::new();
+        store.write(|con| restore_dump(con, dump))?;
+        Ok(store)
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Every value and changelog entry in a memory store.
+#[derive(Default)]
+struct Values {
+    strings: HashMap<String, String>,
+    sets: HashMap<String, BTreeSet<String>>,
+    hashes: HashMap<String, HashMap<String, String>>,
+    lists: HashMap<String, Vec<String>>,
+    changelog: BTreeMap<(i64, i64), BTreeMap<String, String>>,
+}
+
+/// The value at a key, of whichever types it has.
+#[derive(Default)]
+struct SavedKey {
+    string: Option<String>,
+    set: Option<BTreeSet<String>>,
+    hash: Option<HashMap<String, String>>,
+    list: Option<Vec<String>>,
+}
+
+impl Values {
+    /// Copies the value at a key.
+    fn save(&self, key: &str) -> SavedKey {
+        SavedKey {
+            string: self.strings.get(key).cloned(),
+            set: self.sets.get(key).cloned(),
+            hash: self.hashes.get(key).cloned(),
+            list: self.lists.get(key).cloned(),
+        }
+    }
+
+    /// Replaces the value at a key with a saved one.
+    fn restore(&mut self, key: String, saved: SavedKey) {
+        self.strings.remove(&key);
+        self.sets.remove(&key);
+        self.hashes.remove(&key);
+        self.lists.remove(&key);
+        if let Some(string) = saved.string {
+            self.strings.insert(key.clone(), string);
+        }
+        if let Some(set) = saved.set {
+            self.sets.insert(key.clone(), set);
+        }
+        if let Some(hash) = saved.hash {
+            self.hashes.insert(key.clone(), hash);
+        }
+        if let Some(list) = saved.list {
+            self.lists.insert(key, list);
+        }
+    }
+}
+
+/// The values a write has changed, as they were before it changed them.
+#[derive(Default)]
+struct UndoLog {
+    keys: HashMap<String, SavedKey>,
+    changes: HashMap<(i64, i64), Option<BTreeMap<String, String>>>,
+    changelog: Option<BTreeMap<(i64, i64), BTreeMap<String, String>>>,
+}
+
+impl UndoLog {
+    /// Puts back every value that was changed.
+    fn undo(self, values: &mut Values) {
+        for (key, saved) in self.keys {
+            values.restore(key, saved);
+        }
+        if let Some(changelog) = self.changelog {
+            values.changelog = changelog;
+        }
+        for (id, fields) in self.changes {
+            match fields {
+                Some(fields) => values.changelog.insert(id, fields),
+                None => values.changelog.remove(&id),
+            };
+        }
+    }
+}
+
+/// Holds the keyspace of a data store in hash maps.
+#[derive(Clone, Default)]
+pub struct MemoryBackend {
+    values: Arc<Mutex<Values>>,
+}
+
+impl MemoryBackend {
+    fn lock(&self) -> NetdoxResult<MutexGuard<'_, Values>> {
+        match self.values.lock() {
+            Ok(values) => Ok(values),
+            Err(_) => store_err!("Memory store was poisoned by a panic.".to_string()),
+        }
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn read<T>(&self, func: impl FnOnce(&dyn Keyspace) -> NetdoxResult<T>) -> NetdoxResult<T> {
+        let mut values = self.lock()?;
+        func(&MemoryKeyspace::new(&mut values, None))
+    }
+
+    /// Calls the function with the values, recording the old value of everything it changes,
+    /// which is put back if it fails.
+    fn write<T>(&self, func: impl FnOnce(&dyn Keyspace) -> NetdoxResult<T>) -> NetdoxResult<T> {
+        let mut values = self.lock()?;
+        let keyspace = MemoryKeyspace::new(&mut values, Some(UndoLog::default()));
+        match func(&keyspace) {
+            Ok(result) => Ok(result),
+            Err(err) => {
+                keyspace.undo();
+                Err(err)
+            }
+        }
+    }
+}
+
+/// The values of a memory store while they are locked,
+/// and the old values of what has been changed if it is being written to.
+struct MemoryKeyspace<'a> {
+    values: RefCell<&'a mut Values>,
+    undo: Option<RefCell<UndoLog>>,
+}
+
+impl<'a> MemoryKeyspace<'a> {
+    fn new(values: &'a mut Values, undo: Option<UndoLog>) -> Self {
+        MemoryKeyspace {
+            values: RefCell::new(values),
+            undo: undo.map(RefCell::new),
+        }
+    }
+
+    /// Records the value at a key before it is first changed.
+    fn touch(&self, key: &str) {
+        if let Some(undo) = &self.undo {
+            let mut undo = undo.borrow_mut();
+            if !undo.keys.contains_key(key) {
+                undo.keys
+                    .insert(key.to_string(), self.values.borrow().save(key));
+            }
+        }
+    }
+
+    /// Records a changelog entry before it is first changed,
+    /// unless the whole changelog has been recorded already.
+    fn touch_change(&self, id: (i64, i64)) {
+        if let Some(undo) = &self.undo {
+            let mut undo = undo.borrow_mut();
+            if undo.changelog.is_none() {
+                undo.changes
+                    .entry(id)
+                    .or_insert_with(|| self.values.borrow().changelog.get(&id).cloned());
+            }
+        }
+    }
+
+    /// Puts back the old value of everything that has been changed.
+    fn undo(self) {
+        if let Some(undo) = self.undo {
+            undo.into_inner().undo(self.values.into_inner());
+        }
+    }
+}
+
+impl Keyspace for MemoryKeyspace<'_> {
+    fn get(&self, key: &str) -> NetdoxResult<Option<String>> {
+        Ok(self.values.borrow().strings.get(key).cloned())
+    }
+
+    fn set(&self, key: &str, value: &str) -> NetdoxResult<()> {
+        self.touch(key);
+        self.values
+            .borrow_mut()
+            .strings
+            .insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn del(&self, key: &str) -> NetdoxResult<()> {
+        self.touch(key);
+        let mut values = self.values.borrow_mut();
+        values.strings.remove(key);
+        values.sets.remove(key);
+        values.hashes.remove(key);
+        values.lists.remove(key);
+        Ok(())
+    }
+
+    fn keys(&self, prefix: &str) -> NetdoxResult<HashSet<String>> {
+        let values = self.values.borrow();
+        Ok(values
+            .strings
+            .keys()
+            .chain(values.sets.keys())
+            .chain(values.hashes.keys())
+            .chain(values.lists.keys())
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    fn set_keys(&self, prefix: &str) -> NetdoxResult<HashSet<String>> {
+        Ok(self
+            .values
+            .borrow()
+            .sets
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    fn rename_key(&self, key: &str, new_key: &str) -> NetdoxResult<()> {
+        self.touch(key);
+        self.del(new_key)?;
+        let mut values = self.values.borrow_mut();
+        if let Some(value) = values.strings.remove(key) {
+            values.strings.insert(new_key.to_string(), value);
+        }
+        if let Some(members) = values.sets.remove(key) {
+            values.sets.insert(new_key.to_string(), members);
+        }
+        if let Some(fields) = values.hashes.remove(key) {
+            values.hashes.insert(new_key.to_string(), fields);
+        }
+        if let Some(list) = values.lists.remove(key) {
+            values.lists.insert(new_key.to_string(), list);
+        }
+        Ok(())
+    }
+
+    fn smembers(&self, key: &str) -> NetdoxResult<HashSet<String>> {
+        Ok(match self.values.borrow().sets.get(key) {
+            Some(members) => members.iter().cloned().collect(),
+            None => HashSet::new(),
+        })
+    }
+
+    fn sscan(&self, key: &str, cursor: u64, count: usize) -> NetdoxResult<(u64, Vec<String>)> {
+        // The cursor is the number of members already read, in order.
+        let members = match self.values.borrow().sets.get(key) {
+            Some(members) => members
+                .iter()
+                .skip(cursor as usize)
+                .take(count)
+                .cloned()
+                .collect::<Vec<_>>(),
+            None => vec![],
+        };
+
+        let next = match members.len() == count {
+            true => cursor + count as u64,
+            false => 0,
+        };
+        Ok((next, members))
+    }
+
+    fn sismember(&self, key: &str, member: &str) -> NetdoxResult<bool> {
+        Ok(self
+            .values
+            .borrow()
+            .sets
+            .get(key)
+            .is_some_and(|members| members.contains(member)))
+    }
+
+    fn scard(&self, key: &str) -> NetdoxResult<usize> {
+        Ok(self.values.borrow().sets.get(key).map_or(0, BTreeSet::len))
+    }
+
+    fn sadd(&self, key: &str, member: &str) -> NetdoxResult<bool> {
+        self.touch(key);
+        Ok(self
+            .values
+            .borrow_mut()
+            .sets
+            .entry(key.to_string())
+            .or_default()
+            .insert(member.to_string()))
+    }
+
+    fn srem(&self, key: &str, member: &str) -> NetdoxResult<bool> {
+        self.touch(key);
+        let mut values = self.values.borrow_mut();
+        let Some(members) = values.sets.get_mut(key) else {
+            return Ok(false);
+        };
+        let removed = members.remove(member);
+        if members.is_empty() {
+            values.sets.remove(key);
+        }
+        Ok(removed)
+    }
+
+    fn hgetall(&self, key: &str) -> NetdoxResult<HashMap<String, String>> {
+        Ok(self
+            .values
+            .borrow()
+            .hashes
+            .get(key)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn hget(&self, key: &str, field: &str) -> NetdoxResult<Option<String>> {
+        Ok(self
+            .values
+            .borrow()
+            .hashes
+            .get(key)
+            .and_then(|fields| fields.get(field).cloned()))
+    }
+
+    fn hset(&self, key: &str, field: &str, value: &str) -> NetdoxResult<()> {
+        self.touch(key);
+        self.values
+            .borrow_mut()
+            .hashes
+            .entry(key.to_string())
+            .or_default()
+            .insert(field.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn hsetnx(&self, key: &str, field: &str, value: &str) -> NetdoxResult<()> {
+        self.touch(key);
+        self.values
+            .borrow_mut()
+            .hashes
+            .entry(key.to_string())
+            .or_default()
+            .entry(field.to_string())
+            .or_insert_with(|| value.to_string());
+        Ok(())
+    }
+
+    fn hdel(&self, key: &str, field: &str) -> NetdoxResult<()> {
+        self.touch(key);
+        let mut values = self.values.borrow_mut();
+        if let Some(fields) = values.hashes.get_mut(key) {
+            fields.remove(field);
+            if fields.is_empty() {
+                values.hashes.remove(key);
+            }
+        }
+        Ok(())
+    }
+
+    fn lrange(&self, key: &str) -> NetdoxResult<Vec<String>> {
+        Ok(self
+            .values
+            .borrow()
+            .lists
+            .get(key)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn rpush(&self, key: &str, value: &str) -> NetdoxResult<()> {
+        self.touch(key);
+        self.values
+            .borrow_mut()
+            .lists
+            .entry(key.to_string())
+            .or_default()
+            .push(value.to_string());
+        Ok(())
+    }
+
+    fn lpush(&self, key: &str, value: &str) -> NetdoxResult<()> {
+        self.touch(key);
+        self.values
+            .borrow_mut()
+            .lists
+            .entry(key.to_string())
+            .or_default()
+            .insert(0, value.to_string());
+        Ok(())
+    }
+
+    fn ltrim(&self, key: &str, len: usize) -> NetdoxResult<()> {
+        self.touch(key);
+        let mut values = self.values.borrow_mut();
+        if let Some(list) = values.lists.get_mut(key) {
+            list.truncate(len);
+            if list.is_empty() {
+                values.lists.remove(key);
+            }
+        }
+        Ok(())
+    }
+
+    fn values(&self) -> NetdoxResult<BTreeMap<String, StoredValue>> {
+        let values = self.values.borrow();
+        let mut keys = BTreeMap::new();
+        for (key, value) in &values.strings {
+            keys.insert(key.clone(), StoredValue::String(value.clone()));
+        }
+        for (key, members) in &values.sets {
+            keys.insert(key.clone(), StoredValue::Set(members.clone()));
+        }
+        for (key, fields) in &values.hashes {
+            keys.insert(
+                key.clone(),
+                StoredValue::Hash(fields.clone().into_iter().collect()),
+            );
+        }
+        for (key, list) in &values.lists {
+            keys.insert(key.clone(), StoredValue::List(list.clone()));
+        }
+        Ok(keys)
+    }
+
+    fn clear(&self) -> NetdoxResult<()> {
+        for key in self.keys("")? {
+            self.touch(&key);
+        }
+        let mut values = self.values.borrow_mut();
+        values.strings.clear();
+        values.sets.clear();
+        values.hashes.clear();
+        values.lists.clear();
+        Ok(())
+    }
+
+    // Changelog
+
+    fn add_change_field(&self, id: (i64, i64), field: &str, value: &str) -> NetdoxResult<()> {
+        self.touch_change(id);
+        self.values
+            .borrow_mut()
+            .changelog
+            .entry(id)
+            .or_default()
+            .insert(field.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn change_fields(&self, start: (i64, i64)) -> NetdoxResult<Vec<ChangeField>> {
+        let values = self.values.borrow();
+        Ok(values
+            .changelog
+            .range((Bound::Excluded(start), Bound::Unbounded))
+            .flat_map(|(id, fields)| {
+                fields
+                    .iter()
+                    .map(|(field, value)| (*id, field.clone(), value.clone()))
+            })
+            .collect())
+    }
+
+    fn last_change_id(&self) -> NetdoxResult<Option<(i64, i64)>> {
+        Ok(self.values.borrow().changelog.keys().next_back().copied())
+    }
+
+    fn change_count(&self) -> NetdoxResult<usize> {
+        Ok(self.values.borrow().changelog.len())
+    }
+
+    fn clear_changes(&self) -> NetdoxResult<()> {
+        if let Some(undo) = &self.undo {
+            let mut undo = undo.borrow_mut();
+            if undo.changelog.is_none() {
+                undo.changelog = Some(self.values.borrow().changelog.clone());
+            }
+        }
+        self.values.borrow_mut().changelog.clear();
+        Ok(())
+    }
+}
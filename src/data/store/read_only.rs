@@ -0,0 +1,605 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use ipnet::IpNet;
+
+use crate::{
+    config::{LocalConfig, SaveStrategy},
+    config_err,
+    data::model::{
+        ChangelogEntry, Conflict, ConflictPolicy, Data, DataDump, Dependency, DnsBundle,
+        DnsDeletion, IndexRepairs, MetadataMigration, MetadataProvenance, NetworkRename, Node,
+        NodeBundle, PluginDisplay, ProcessRun, Prune, QueuedUpdate, RawNode, Relation, Report,
+        RunLock, RunRecord, Service, DNS,
+    },
+    error::{NetdoxError, NetdoxResult},
+};
+
+use super::{DataConn, DataStore};
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Makes every data store connection opened for the rest of the process read-only.
+pub fn set_read_only(enabled: bool) {
+    READ_ONLY.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns true if read-only mode was enabled for the rest of the process.
+pub fn read_only_enabled() -> bool {
+    READ_ONLY.load(Ordering::Relaxed)
+}
+
+/// A connection to a data store that refuses to write to it.
+/// Operations that report what they would change when given `dry_run` are allowed to do so.
+#[derive(Clone)]
+pub struct ReadOnlyStore {
+    inner: Box<DataStore>,
+}
+
+impl ReadOnlyStore {
+    pub fn new(inner: DataStore) -> Self {
+        ReadOnlyStore {
+            inner: Box::new(inner),
+        }
+    }
+}
+
+fn refuse<T>(action: &str) -> NetdoxResult<T> {
+    config_err!(format!(
+        "Refusing to {action} because the data store is in read-only mode."
+    ))
+}
+
+#[async_trait]
+impl DataConn for ReadOnlyStore {
+    async fn auth(&mut self, password: &str, username: Option<&String>) -> NetdoxResult<()> {
+        self.inner.auth(password, username).await
+    }
+
+    async fn setup(&mut self, _cfg: &LocalConfig) -> NetdoxResult<()> {
+        refuse("set up the data store")
+    }
+
+    async fn init(&mut self) -> NetdoxResult<()> {
+        refuse("initialise the data store")
+    }
+
+    async fn flush(&mut self) -> NetdoxResult<()> {
+        refuse("flush the data store")
+    }
+
+    // DNS
+
+    async fn get_dns(&mut self) -> NetdoxResult<DNS> {
+        self.inner.get_dns().await
+    }
+
+    async fn get_dns_subset(&mut self, names: &[&str]) -> NetdoxResult<DNS> {
+        self.inner.get_dns_subset(names).await
+    }
+
+    async fn scan_dns(&mut self, cursor: u64, count: usize) -> NetdoxResult<(u64, DNS)> {
+        self.inner.scan_dns(cursor, count).await
+    }
+
+    async fn get_dns_names(&mut self) -> NetdoxResult<HashSet<String>> {
+        self.inner.get_dns_names().await
+    }
+
+    async fn dns_name_exists(&mut self, qname: &str) -> NetdoxResult<bool> {
+        self.inner.dns_name_exists(qname).await
+    }
+
+    async fn qualify_dns_names(&mut self, names: &[&str]) -> NetdoxResult<Vec<String>> {
+        self.inner.qualify_dns_names(names).await
+    }
+
+    async fn get_networks(&mut self) -> NetdoxResult<HashSet<String>> {
+        self.inner.get_networks().await
+    }
+
+    async fn get_dns_ignore_patterns(&mut self) -> NetdoxResult<HashSet<String>> {
+        self.inner.get_dns_ignore_patterns().await
+    }
+
+    async fn get_subnets(&mut self) -> NetdoxResult<HashSet<IpNet>> {
+        self.inner.get_subnets().await
+    }
+
+    // Nodes
+
+    async fn get_roles(&mut self) -> NetdoxResult<HashSet<String>> {
+        self.inner.get_roles().await
+    }
+
+    async fn get_raw_nodes(&mut self) -> NetdoxResult<Vec<RawNode>> {
+        self.inner.get_raw_nodes().await
+    }
+
+    async fn scan_raw_nodes(
+        &mut self,
+        cursor: u64,
+        count: usize,
+    ) -> NetdoxResult<(u64, Vec<RawNode>)> {
+        self.inner.scan_raw_nodes(cursor, count).await
+    }
+
+    async fn get_node(&mut self, id: &str) -> NetdoxResult<Node> {
+        self.inner.get_node(id).await
+    }
+
+    async fn get_node_ids(&mut self) -> NetdoxResult<HashSet<String>> {
+        self.inner.get_node_ids().await
+    }
+
+    async fn scan_nodes(&mut self, cursor: u64, count: usize) -> NetdoxResult<(u64, Vec<Node>)> {
+        self.inner.scan_nodes(cursor, count).await
+    }
+
+    async fn get_node_from_raw(&mut self, raw_id: &str) -> NetdoxResult<Option<String>> {
+        self.inner.get_node_from_raw(raw_id).await
+    }
+
+    async fn get_raw_id_from_qnames(&mut self, qnames: &[&str]) -> NetdoxResult<String> {
+        self.inner.get_raw_id_from_qnames(qnames).await
+    }
+
+    async fn get_node_dependencies(&mut self, link_id: &str) -> NetdoxResult<Vec<Dependency>> {
+        self.inner.get_node_dependencies(link_id).await
+    }
+
+    async fn get_node_relations(&mut self, link_id: &str) -> NetdoxResult<Vec<Relation>> {
+        self.inner.get_node_relations(link_id).await
+    }
+
+    async fn get_node_services(&mut self, link_id: &str) -> NetdoxResult<Vec<Service>> {
+        self.inner.get_node_services(link_id).await
+    }
+
+    // Plugin Data
+
+    async fn get_data(&mut self, key: &str) -> NetdoxResult<Data> {
+        self.inner.get_data(key).await
+    }
+
+    async fn get_dns_pdata(&mut self, qname: &str) -> NetdoxResult<Vec<Data>> {
+        self.inner.get_dns_pdata(qname).await
+    }
+
+    async fn get_node_pdata(&mut self, node: &Node) -> NetdoxResult<Vec<Data>> {
+        self.inner.get_node_pdata(node).await
+    }
+
+    async fn delete_dns_pdata(
+        &mut self,
+        _qname: &str,
+        _plugin: &str,
+        _data_id: &str,
+    ) -> NetdoxResult<bool> {
+        refuse("delete plugin data")
+    }
+
+    async fn delete_node_pdata(
+        &mut self,
+        _node: &Node,
+        _plugin: &str,
+        _data_id: &str,
+    ) -> NetdoxResult<bool> {
+        refuse("delete plugin data")
+    }
+
+    // Reports
+
+    async fn get_report_ids(&mut self) -> NetdoxResult<HashSet<String>> {
+        self.inner.get_report_ids().await
+    }
+
+    async fn get_report(&mut self, id: &str) -> NetdoxResult<Report> {
+        self.inner.get_report(id).await
+    }
+
+    async fn put_report(&mut self, _id: &str, _title: &str, _length: usize) -> NetdoxResult<()> {
+        refuse("write a report")
+    }
+
+    async fn put_report_data(&mut self, _id: &str, _idx: usize, _data: &Data) -> NetdoxResult<()> {
+        refuse("write report data")
+    }
+
+    // Metadata
+
+    async fn get_dns_metadata(&mut self, qname: &str) -> NetdoxResult<HashMap<String, String>> {
+        self.inner.get_dns_metadata(qname).await
+    }
+
+    async fn put_dns_metadata(
+        &mut self,
+        _qname: &str,
+        _plugin: &str,
+        _data: HashMap<&str, &str>,
+    ) -> NetdoxResult<()> {
+        refuse("write DNS metadata")
+    }
+
+    async fn get_proc_node_metadata(
+        &mut self,
+        node_id: &str,
+    ) -> NetdoxResult<HashMap<String, String>> {
+        self.inner.get_proc_node_metadata(node_id).await
+    }
+
+    async fn get_node_metadata(&mut self, node: &Node) -> NetdoxResult<HashMap<String, String>> {
+        self.inner.get_node_metadata(node).await
+    }
+
+    async fn get_dns_metadata_sources(
+        &mut self,
+        qname: &str,
+    ) -> NetdoxResult<HashMap<String, String>> {
+        self.inner.get_dns_metadata_sources(qname).await
+    }
+
+    async fn get_dns_metadata_provenance(
+        &mut self,
+        qname: &str,
+    ) -> NetdoxResult<HashMap<String, MetadataProvenance>> {
+        self.inner.get_dns_metadata_provenance(qname).await
+    }
+
+    async fn get_node_metadata_provenance(
+        &mut self,
+        node: &Node,
+    ) -> NetdoxResult<HashMap<String, MetadataProvenance>> {
+        self.inner.get_node_metadata_provenance(node).await
+    }
+
+    async fn get_conflict_policy(&mut self) -> NetdoxResult<ConflictPolicy> {
+        self.inner.get_conflict_policy().await
+    }
+
+    async fn get_node_metadata_conflicts(&mut self, node: &Node) -> NetdoxResult<Vec<Conflict>> {
+        self.inner.get_node_metadata_conflicts(node).await
+    }
+
+    async fn get_node_metadata_sources(
+        &mut self,
+        node: &Node,
+    ) -> NetdoxResult<HashMap<String, String>> {
+        self.inner.get_node_metadata_sources(node).await
+    }
+
+    async fn put_node_metadata(
+        &mut self,
+        _node_id: &str,
+        _plugin: &str,
+        _data: HashMap<&str, &str>,
+    ) -> NetdoxResult<()> {
+        refuse("write node metadata")
+    }
+
+    async fn migrate_metadata(&mut self, dry_run: bool) -> NetdoxResult<MetadataMigration> {
+        if !dry_run {
+            return refuse("migrate metadata");
+        }
+        self.inner.migrate_metadata(dry_run).await
+    }
+
+    // Notes
+
+    async fn get_notes(&mut self, obj_id: &str) -> NetdoxResult<HashMap<String, String>> {
+        self.inner.get_notes(obj_id).await
+    }
+
+    async fn put_note(
+        &mut self,
+        _obj_id: &str,
+        _key: &str,
+        _value: Option<&str>,
+    ) -> NetdoxResult<()> {
+        refuse("write an operator note")
+    }
+
+    // Tags
+
+    async fn get_tags(&mut self) -> NetdoxResult<HashSet<String>> {
+        self.inner.get_tags().await
+    }
+
+    async fn get_tagged(&mut self, tag: &str) -> NetdoxResult<HashSet<String>> {
+        self.inner.get_tagged(tag).await
+    }
+
+    async fn get_dns_tags(&mut self, qname: &str) -> NetdoxResult<HashSet<String>> {
+        self.inner.get_dns_tags(qname).await
+    }
+
+    async fn get_node_tags(&mut self, node: &Node) -> NetdoxResult<HashSet<String>> {
+        self.inner.get_node_tags(node).await
+    }
+
+    // Plugins
+
+    async fn get_plugin_displays(&mut self) -> NetdoxResult<HashMap<String, PluginDisplay>> {
+        self.inner.get_plugin_displays().await
+    }
+
+    // Documents
+
+    async fn get_dns_bundle(&mut self, qname: &str) -> NetdoxResult<DnsBundle> {
+        self.inner.get_dns_bundle(qname).await
+    }
+
+    async fn get_node_bundle(&mut self, node: &Node) -> NetdoxResult<NodeBundle> {
+        self.inner.get_node_bundle(node).await
+    }
+
+    // Changelog
+
+    async fn get_changes(&mut self, start: Option<&str>) -> NetdoxResult<Vec<ChangelogEntry>> {
+        self.inner.get_changes(start).await
+    }
+
+    async fn last_change_id(&mut self) -> NetdoxResult<String> {
+        self.inner.last_change_id().await
+    }
+
+    async fn total_change_count(&mut self) -> NetdoxResult<usize> {
+        self.inner.total_change_count().await
+    }
+
+    // Indexes
+
+    async fn rebuild_index(&mut self) -> NetdoxResult<IndexRepairs> {
+        refuse("rebuild indexes")
+    }
+
+    async fn rename_network(
+        &mut self,
+        old: &str,
+        new: &str,
+        dry_run: bool,
+    ) -> NetdoxResult<NetworkRename> {
+        if !dry_run {
+            return refuse("rename a network");
+        }
+        self.inner.rename_network(old, new, dry_run).await
+    }
+
+    async fn delete_dns(&mut self, qname: &str, dry_run: bool) -> NetdoxResult<DnsDeletion> {
+        if !dry_run {
+            return refuse("delete a DNS name");
+        }
+        self.inner.delete_dns(qname, dry_run).await
+    }
+
+    async fn prune(
+        &mut self,
+        default_cutoff: Option<u64>,
+        plugin_cutoffs: &HashMap<String, u64>,
+        dry_run: bool,
+    ) -> NetdoxResult<Prune> {
+        if !dry_run {
+            return refuse("prune data");
+        }
+        self.inner
+            .prune(default_cutoff, plugin_cutoffs, dry_run)
+            .await
+    }
+
+    // Runs
+
+    async fn put_run(&mut self, _run: &RunRecord) -> NetdoxResult<()> {
+        refuse("record a run")
+    }
+
+    async fn get_runs(&mut self, count: usize) -> NetdoxResult<Vec<RunRecord>> {
+        self.inner.get_runs(count).await
+    }
+
+    async fn acquire_run_lock(&mut self, _lock: &RunLock) -> NetdoxResult<Option<RunLock>> {
+        refuse("take the run lock")
+    }
+
+    async fn release_run_lock(&mut self, _owner: &str) -> NetdoxResult<()> {
+        refuse("release the run lock")
+    }
+
+    async fn force_run_unlock(&mut self) -> NetdoxResult<Option<RunLock>> {
+        refuse("remove the run lock")
+    }
+
+    async fn get_deferred_plugins(&mut self) -> NetdoxResult<HashSet<String>> {
+        self.inner.get_deferred_plugins().await
+    }
+
+    async fn set_deferred_plugins(&mut self, _plugins: &HashSet<String>) -> NetdoxResult<()> {
+        refuse("defer plugins")
+    }
+
+    async fn get_scope_violations(&mut self) -> NetdoxResult<HashMap<String, HashSet<String>>> {
+        self.inner.get_scope_violations().await
+    }
+
+    async fn clear_scope_violations(&mut self) -> NetdoxResult<()> {
+        refuse("clear scope violations")
+    }
+
+    async fn get_plugin_cursor(
+        &mut self,
+        plugin: &str,
+        stage: &str,
+    ) -> NetdoxResult<Option<String>> {
+        self.inner.get_plugin_cursor(plugin, stage).await
+    }
+
+    async fn set_plugin_cursor(
+        &mut self,
+        _plugin: &str,
+        _stage: &str,
+        _cursor: &str,
+    ) -> NetdoxResult<()> {
+        refuse("set a plugin cursor")
+    }
+
+    async fn clear_plugin_cursors(&mut self) -> NetdoxResult<()> {
+        refuse("clear plugin cursors")
+    }
+
+    // Processing
+
+    async fn begin_process(&mut self, _run: &ProcessRun) -> NetdoxResult<()> {
+        refuse("record a processing run")
+    }
+
+    async fn put_processed(
+        &mut self,
+        _resolved: &[(String, String)],
+        _nodes: &[&Node],
+    ) -> NetdoxResult<()> {
+        refuse("write processed nodes")
+    }
+
+    async fn end_process(&mut self) -> NetdoxResult<()> {
+        refuse("record a processing run")
+    }
+
+    async fn get_interrupted_process(&mut self) -> NetdoxResult<Option<ProcessRun>> {
+        self.inner.get_interrupted_process().await
+    }
+
+    async fn clear_process(&mut self, _run: &ProcessRun) -> NetdoxResult<()> {
+        refuse("clear a processing run")
+    }
+
+    // Publish Queue
+
+    async fn put_queued_updates(&mut self, _updates: &[QueuedUpdate]) -> NetdoxResult<()> {
+        refuse("queue fragment updates")
+    }
+
+    async fn get_queued_updates(&mut self) -> NetdoxResult<Vec<QueuedUpdate>> {
+        self.inner.get_queued_updates().await
+    }
+
+    async fn remove_queued_update(&mut self, _update: &QueuedUpdate) -> NetdoxResult<()> {
+        refuse("remove a queued fragment update")
+    }
+
+    async fn get_publish_checkpoint(&mut self, remote: &str) -> NetdoxResult<Option<String>> {
+        self.inner.get_publish_checkpoint(remote).await
+    }
+
+    async fn put_publish_checkpoint(
+        &mut self,
+        _remote: &str,
+        _change_id: &str,
+    ) -> NetdoxResult<()> {
+        refuse("record a publish checkpoint")
+    }
+
+    async fn clear_publish_checkpoint(&mut self, _remote: &str) -> NetdoxResult<()> {
+        refuse("clear a publish checkpoint")
+    }
+
+    async fn get_publish_cursors(&mut self) -> NetdoxResult<HashMap<String, String>> {
+        self.inner.get_publish_cursors().await
+    }
+
+    async fn put_publish_cursor(&mut self, _remote: &str, _change_id: &str) -> NetdoxResult<()> {
+        refuse("record a publish cursor")
+    }
+
+    async fn get_remote_cursor(&mut self, remote: &str) -> NetdoxResult<Option<String>> {
+        self.inner.get_remote_cursor(remote).await
+    }
+
+    async fn swap_remote_cursor(
+        &mut self,
+        _remote: &str,
+        _expected: Option<&str>,
+        _change_id: &str,
+    ) -> NetdoxResult<bool> {
+        refuse("record a remote changelog cursor")
+    }
+
+    async fn put_superseded_fragments(
+        &mut self,
+        _obj_id: &str,
+        _ids: &[(String, String)],
+    ) -> NetdoxResult<()> {
+        refuse("record superseded fragments")
+    }
+
+    async fn get_superseded_fragments(
+        &mut self,
+    ) -> NetdoxResult<HashMap<String, HashMap<String, String>>> {
+        self.inner.get_superseded_fragments().await
+    }
+
+    async fn clear_superseded_fragments(&mut self, _obj_ids: &[String]) -> NetdoxResult<()> {
+        refuse("clear superseded fragments")
+    }
+
+    // Backup
+
+    async fn dump(&mut self) -> NetdoxResult<DataDump> {
+        self.inner.dump().await
+    }
+
+    async fn restore(&mut self, _dump: &DataDump) -> NetdoxResult<()> {
+        refuse("restore a backup")
+    }
+
+    // Persistence
+
+    async fn write_save(
+        &mut self,
+        _strategy: SaveStrategy,
+        _timeout: Duration,
+    ) -> NetdoxResult<()> {
+        refuse("write a save")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::ReadOnlyStore;
+    use crate::data::{store::memory_store::MemoryStore, DataConn, DataStore};
+
+    #[tokio::test]
+    async fn test_read_only() {
+        let mut writable = DataStore::Memory(MemoryStore::new());
+        writable
+            .put_note("dns;[net]domain.com", "owner", Some("ops"))
+            .await
+            .unwrap();
+
+        let mut con = ReadOnlyStore::new(writable.clone());
+        assert_eq!(
+            con.get_notes("dns;[net]domain.com").await.unwrap(),
+            HashMap::from([("owner".to_string(), "ops".to_string())])
+        );
+        assert!(con
+            .put_note("dns;[net]domain.com", "owner", None)
+            .await
+            .is_err());
+        assert!(con.migrate_metadata(true).await.is_ok());
+        assert!(con.migrate_metadata(false).await.is_err());
+        assert!(con.flush().await.is_err());
+
+        assert_eq!(
+            writable
+                .get_notes("dns;[net]domain.com")
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
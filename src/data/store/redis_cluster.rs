@@ -0,0 +1,352 @@
+use redis::{
+    aio::ConnectionLike,
+    cluster_async::ClusterConnection,
+    cluster_routing::{Route, RoutingInfo, SingleNodeRoutingInfo, SlotAddr},
+    Arg, Cmd, ErrorKind, Pipeline, RedisError, RedisResult, Value,
+};
+
+/// Hash tag at the start of every key in a redis cluster.
+/// Putting every key in the same slot lets the Lua functions and pipelines
+/// use the keys of many objects at once.
+pub const KEY_TAG: &str = "{netdox}";
+
+/// Number of hash slots in a redis cluster.
+const SLOT_COUNT: u16 = 16384;
+
+/// Returns the hash slot of the keys that start with the key tag.
+fn tag_slot() -> u16 {
+    let tag = &KEY_TAG[1..KEY_TAG.len() - 1];
+    crc16::State::<crc16::XMODEM>::calculate(tag.as_bytes()) % SLOT_COUNT
+}
+
+/// Returns the route to the primary node holding the keys that start with the key tag.
+fn tag_route() -> Route {
+    Route::new(tag_slot(), SlotAddr::Master)
+}
+
+/// A command rewritten to use the keys of a redis cluster.
+enum Tagged {
+    /// The command with the key tag added to its keys, which is routed by them.
+    Keyed(Cmd),
+    /// A command that must be sent to the node holding the keys,
+    /// and whether to remove the key tag from the keys in its reply.
+    Node { cmd: Cmd, strip: bool },
+    /// A command that does not use keys, which is routed by the cluster client.
+    Unchanged,
+}
+
+/// Commands that take a single key as the first argument.
+const FIRST_KEY: [&str; 29] = [
+    "DUMP",
+    "EXPIRE",
+    "GET",
+    "HDEL",
+    "HEXISTS",
+    "HGET",
+    "HGETALL",
+    "HKEYS",
+    "HLEN",
+    "HSCAN",
+    "HSET",
+    "HSETNX",
+    "HVALS",
+    "INCR",
+    "LLEN",
+    "LRANGE",
+    "RPUSH",
+    "SADD",
+    "SCARD",
+    "SET",
+    "SISMEMBER",
+    "SMEMBERS",
+    "SREM",
+    "SSCAN",
+    "TYPE",
+    "XADD",
+    "XLEN",
+    "XRANGE",
+    "XREVRANGE",
+];
+
+/// Commands that take only keys as arguments.
+const ONLY_KEYS: [&str; 6] = ["DEL", "EXISTS", "MGET", "SUNION", "SUNIONSTORE", "UNLINK"];
+
+/// Commands that do not use keys and are routed by the cluster client,
+/// like loading the Lua functions on every primary.
+const KEYLESS: [&str; 6] = [
+    "CLUSTER", "COMMAND", "FLUSHALL", "FLUSHDB", "FUNCTION", "SCRIPT",
+];
+
+/// Commands about the server itself, which are sent to the node holding the keys.
+const SERVER: [&str; 3] = ["BGSAVE", "INFO", "SAVE"];
+
+/// Returns the arguments of a command, including its name.
+fn args(cmd: &Cmd) -> RedisResult<Vec<Vec<u8>>> {
+    cmd.args_iter()
+        .map(|arg| match arg {
+            Arg::Simple(arg) => Ok(arg.to_vec()),
+            _ => Err(RedisError::from((
+                ErrorKind::Client,
+                "Cursor arguments are not supported with a redis cluster.",
+            ))),
+        })
+        .collect()
+}
+
+/// Returns a command made from a list of arguments.
+fn from_args(args: Vec<Vec<u8>>) -> Cmd {
+    let mut cmd = Cmd::new();
+    for arg in args {
+        cmd.arg(arg);
+    }
+    cmd
+}
+
+/// Returns a key with the key tag added.
+fn tag(key: &[u8]) -> Vec<u8> {
+    [KEY_TAG.as_bytes(), key].concat()
+}
+
+/// Returns the value of an integer argument.
+fn int_arg(arg: Option<&Vec<u8>>) -> RedisResult<usize> {
+    arg.and_then(|arg| std::str::from_utf8(arg).ok())
+        .and_then(|arg| arg.parse().ok())
+        .ok_or_else(|| RedisError::from((ErrorKind::Client, "Expected a number of keys.")))
+}
+
+/// Rewrites a command to use the keys of a redis cluster.
+fn tag_command(cmd: &Cmd) -> RedisResult<Tagged> {
+    let mut args = args(cmd)?;
+    let name = match args.first() {
+        Some(name) => String::from_utf8_lossy(name).to_uppercase(),
+        None => return Err(RedisError::from((ErrorKind::Client, "Empty command."))),
+    };
+
+    match name.as_str() {
+        name if FIRST_KEY.contains(&name) => {
+            if let Some(key) = args.get_mut(1) {
+                *key = tag(key);
+            }
+        }
+        name if ONLY_KEYS.contains(&name) => {
+            for key in &mut args[1..] {
+                *key = tag(key);
+            }
+        }
+        "RENAME" => {
+            for key in args.iter_mut().skip(1).take(2) {
+                *key = tag(key);
+            }
+        }
+        "EVAL" | "EVALSHA" | "FCALL" | "FCALL_RO" => {
+            let count = int_arg(args.get(2))?;
+            if count == 0 && name.starts_with("FCALL") {
+                // The Lua functions take the tag alone as a key, so the call is routed to the keys.
+                args[2] = b"1".to_vec();
+                args.insert(3, KEY_TAG.as_bytes().to_vec());
+            }
+            for key in args.iter_mut().skip(3).take(count) {
+                *key = tag(key);
+            }
+        }
+        "KEYS" => {
+            if let Some(pattern) = args.get_mut(1) {
+                *pattern = tag(pattern);
+            }
+            return Ok(Tagged::Node {
+                cmd: from_args(args),
+                strip: true,
+            });
+        }
+        "SCAN" => {
+            match args
+                .iter()
+                .position(|arg| arg.eq_ignore_ascii_case(b"MATCH"))
+            {
+                Some(index) if index + 1 < args.len() => args[index + 1] = tag(&args[index + 1]),
+                _ => args.extend([b"MATCH".to_vec(), tag(b"*")]),
+            }
+            return Ok(Tagged::Node {
+                cmd: from_args(args),
+                strip: true,
+            });
+        }
+        "DBSIZE" => {
+            let mut count = redis::cmd("CLUSTER");
+            count.arg("COUNTKEYSINSLOT").arg(tag_slot());
+            return Ok(Tagged::Node {
+                cmd: count,
+                strip: false,
+            });
+        }
+        name if SERVER.contains(&name) => {
+            return Ok(Tagged::Node {
+                cmd: from_args(args),
+                strip: false,
+            })
+        }
+        name if KEYLESS.contains(&name) => return Ok(Tagged::Unchanged),
+        _ => {
+            return Err(RedisError::from((
+                ErrorKind::Client,
+                "Command is not supported with a redis cluster",
+                name,
+            )))
+        }
+    }
+
+    Ok(Tagged::Keyed(from_args(args)))
+}
+
+/// Removes the key tag from the start of the keys in a reply.
+fn strip_tag(value: Value) -> Value {
+    match value {
+        Value::BulkString(key) => match key.strip_prefix(KEY_TAG.as_bytes()) {
+            Some(key) => Value::BulkString(key.to_vec()),
+            None => Value::BulkString(key),
+        },
+        Value::Array(values) => Value::Array(values.into_iter().map(strip_tag).collect()),
+        value => value,
+    }
+}
+
+/// Sends a command to a redis cluster, with the key tag added to its keys.
+pub async fn query(con: &mut ClusterConnection, cmd: &Cmd) -> RedisResult<Value> {
+    match tag_command(cmd)? {
+        Tagged::Keyed(cmd) => con.req_packed_command(&cmd).await,
+        Tagged::Node { cmd, strip } => {
+            let routing = RoutingInfo::SingleNode(SingleNodeRoutingInfo::SpecificNode(tag_route()));
+            let value = con.route_command(cmd, routing).await?;
+            Ok(if strip { strip_tag(value) } else { value })
+        }
+        Tagged::Unchanged => con.req_packed_command(cmd).await,
+    }
+}
+
+/// Sends a pipeline to the node of a redis cluster holding the keys,
+/// with the key tag added to the keys of its commands.
+pub async fn query_pipeline(
+    con: &mut ClusterConnection,
+    pipeline: &Pipeline,
+    offset: usize,
+    count: usize,
+) -> RedisResult<Vec<Value>> {
+    let mut tagged = redis::pipe();
+    if pipeline.is_transaction() {
+        tagged.atomic();
+    }
+    for cmd in pipeline.cmd_iter() {
+        match tag_command(cmd)? {
+            Tagged::Keyed(cmd) | Tagged::Node { cmd, strip: false } => tagged.add_command(cmd),
+            _ => {
+                return Err(RedisError::from((
+                    ErrorKind::Client,
+                    "Command cannot be pipelined with a redis cluster",
+                    String::from_utf8_lossy(&args(cmd)?[0]).to_string(),
+                )))
+            }
+        };
+    }
+
+    con.route_pipeline(
+        tagged,
+        offset,
+        count,
+        SingleNodeRoutingInfo::SpecificNode(tag_route()),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use redis::{cmd, Value};
+
+    use super::{args, strip_tag, tag_command, tag_slot, Tagged, KEY_TAG};
+
+    /// Returns the arguments of the command a command is rewritten to, as strings.
+    fn tagged_args(cmd: &redis::Cmd) -> Vec<String> {
+        let cmd = match tag_command(cmd).unwrap() {
+            Tagged::Keyed(cmd) | Tagged::Node { cmd, .. } => cmd,
+            Tagged::Unchanged => cmd.clone(),
+        };
+        args(&cmd)
+            .unwrap()
+            .into_iter()
+            .map(|arg| String::from_utf8(arg).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_tag_slot() {
+        // CRC16 of "netdox" modulo the number of slots, as CLUSTER KEYSLOT {netdox} reports.
+        assert_eq!(tag_slot(), 3732);
+    }
+
+    #[test]
+    fn test_tag_command() {
+        assert_eq!(
+            tagged_args(cmd("SADD").arg("dns").arg("[net]a.com")),
+            vec!["SADD", "{netdox}dns", "[net]a.com"]
+        );
+        assert_eq!(
+            tagged_args(cmd("del").arg("a").arg("b")),
+            vec!["del", "{netdox}a", "{netdox}b"]
+        );
+        assert_eq!(
+            tagged_args(cmd("RENAME").arg("a").arg("b")),
+            vec!["RENAME", "{netdox}a", "{netdox}b"]
+        );
+        assert_eq!(
+            tagged_args(
+                cmd("FCALL")
+                    .arg("netdox_create_dns")
+                    .arg(1)
+                    .arg("a.com")
+                    .arg("plugin")
+            ),
+            vec!["FCALL", "netdox_create_dns", "1", "{netdox}a.com", "plugin"]
+        );
+        assert_eq!(
+            tagged_args(cmd("FCALL").arg("netdox_init").arg(0)),
+            vec!["FCALL", "netdox_init", "1", KEY_TAG]
+        );
+        assert_eq!(
+            tagged_args(cmd("SCAN").arg(0).arg("MATCH").arg("staging;*")),
+            vec!["SCAN", "0", "MATCH", "{netdox}staging;*"]
+        );
+        assert_eq!(
+            tagged_args(cmd("SCAN").arg(0).arg("COUNT").arg(10)),
+            vec!["SCAN", "0", "COUNT", "10", "MATCH", "{netdox}*"]
+        );
+        assert_eq!(
+            tagged_args(&cmd("DBSIZE")),
+            vec!["CLUSTER", "COUNTKEYSINSLOT", "3732"]
+        );
+        assert!(matches!(
+            tag_command(cmd("FUNCTION").arg("LOAD")).unwrap(),
+            Tagged::Unchanged
+        ));
+        assert!(tag_command(cmd("AUTH").arg("password")).is_err());
+    }
+
+    #[test]
+    fn test_strip_tag() {
+        let reply = Value::Array(vec![
+            Value::BulkString(b"0".to_vec()),
+            Value::Array(vec![
+                Value::BulkString(b"{netdox}dns".to_vec()),
+                Value::BulkString(b"other".to_vec()),
+            ]),
+        ]);
+        assert_eq!(
+            strip_tag(reply),
+            Value::Array(vec![
+                Value::BulkString(b"0".to_vec()),
+                Value::Array(vec![
+                    Value::BulkString(b"dns".to_vec()),
+                    Value::BulkString(b"other".to_vec()),
+                ]),
+            ])
+        );
+    }
+}
@@ -1,57 +1,168 @@
 use crate::{
-    config::{IgnoreList, LocalConfig},
+    config::{LocalConfig, SaveStrategy},
+    config_err,
     data::{
         model::{
-            ChangelogEntry, DNSRecord, Data, Node, RawNode, Report, CHANGELOG_KEY, DNS, DNS_KEY,
-            METADATA_KEY, NETDOX_PLUGIN, NODES_KEY, PDATA_KEY, PROC_NODES_KEY, PROC_NODE_REVS_KEY,
-            REPORTS_KEY,
+            merge_metadata, metadata_provenance, ChangelogEntry, Conflict, ConflictPolicy,
+            ConflictStrategy, DNSRecord, Data, DataDump, Dependency, DnsBundle, DnsDeletion,
+            IndexRepairs, MetadataMigration, MetadataProvenance, NetworkRename, Node, NodeBundle,
+            PluginDisplay, ProcessRun, Prune, QueuedUpdate, RawNode, Relation, Report, RunLock,
+            RunRecord, Service, StoredChange, StoredValue, ADDRESS_RTYPES, CHANGELOG_KEY,
+            CONFLICT_POLICY_KEY, DAMPENING_KEY, DEFERRED_PLUGINS_KEY, DEPENDENCIES_KEY, DNS,
+            DNS_IGNORE_PATTERNS_KEY, DNS_KEY, DNS_NODES_KEY, FRAGMENT_IDS_KEY, MAX_RUNS,
+            METADATA_KEY, NETDOX_PLUGIN, NETWORKS_KEY, NETWORK_SUFFIXES_KEY, NODES_KEY,
+            NODE_ID_META_KEY, NODE_META_KEY, NOTES_KEY, PDATA_KEY, PLUGIN_CURSORS_KEY,
+            PLUGIN_DISPLAY_KEY, PLUGIN_SCOPES_KEY, PROCESS_RUN_KEY, PROC_NODES_KEY,
+            PROC_NODE_REVS_KEY, PROC_STAGING_KEY, PROC_VERSION_KEY, PUBLISH_CHECKPOINT_KEY,
+            PUBLISH_CURSORS_KEY, PUBLISH_QUEUE_KEY, RELATIONS_KEY, REMOTE_CURSORS_KEY, REPORTS_KEY,
+            ROLES_KEY, RUNS_KEY, RUN_LOCK_KEY, SCOPE_VIOLATIONS_KEY, SERVICES_KEY, SUBNETS_KEY,
+            TAGS_KEY,
         },
-        store::DataConn,
+        store::{redis_cluster, DataConn},
     },
     error::{NetdoxError, NetdoxResult},
     io_err, redis_err,
 };
 use async_trait::async_trait;
+use ipnet::IpNet;
 use itertools::izip;
-use redis::{cmd, AsyncCommands, Value};
+use redis::{
+    aio::{ConnectionLike, MultiplexedConnection},
+    cluster_async::ClusterConnection,
+    cmd, AsyncCommands, Pipeline, RedisFuture, Script, Value,
+};
+use tokio::time::sleep;
+use tracing::{debug, warn};
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fs,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 const DNS_METADATA_FN: &str = "netdox_create_dns_metadata";
 const PROC_NODE_METADATA_FN: &str = "netdox_create_proc_node_metadata";
+const DELETE_PROC_NODE_FN: &str = "netdox_delete_proc_node";
+
+/// Time between checks for a background save finishing.
+const SAVE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Number of keys or members to ask for in each call to SCAN or SSCAN.
+const SCAN_COUNT: usize = 1000;
 
 const LUA_FUNCTIONS: &str = include_str!("../../../functions.lua");
+/// Oldest version of redis that supports functions, as major and minor version.
+const MIN_REDIS_VERSION: (u32, u32) = (7, 0);
+/// Commands that netdox calls directly, which can be disabled or renamed in the redis config.
+const REQUIRED_COMMANDS: [&str; 5] = ["FUNCTION", "FCALL", "SCAN", "XADD", "XRANGE"];
+
+/// Sets the run lock to the value in the second argument, which expires after the number of seconds
+/// in the third, unless another owner than the first argument holds it.
+/// Returns the value of the lock held by the other owner.
+const ACQUIRE_LOCK_SCRIPT: &str = r#"
+local owner = redis.call('HGET', KEYS[1], 'owner')
+if owner and owner ~= ARGV[1] then
+    return redis.call('HGET', KEYS[1], 'lock')
+end
+redis.call('HSET', KEYS[1], 'owner', ARGV[1], 'lock', ARGV[2])
+redis.call('EXPIRE', KEYS[1], ARGV[3])
+return false
+"#;
+/// Removes the run lock if it is held by the owner in the first argument.
+const RELEASE_LOCK_SCRIPT: &str = r#"
+if redis.call('HGET', KEYS[1], 'owner') == ARGV[1] then
+    redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+/// Sets the field in the first argument to the third argument
+/// if its value is the second argument, or it has no value and the second argument is empty.
+/// Returns 1 if the field was set and 0 otherwise.
+const SWAP_CURSOR_SCRIPT: &str = r#"
+local recorded = redis.call('HGET', KEYS[1], ARGV[1])
+if (recorded or '') ~= ARGV[2] then
+    return 0
+end
+redis.call('HSET', KEYS[1], ARGV[1], ARGV[3])
+return 1
+"#;
+
+/// Name of the Lua library containing the netdox functions.
+const LUA_LIBRARY: &str = "netdox";
+/// Prefix of the names of functions in the netdox Lua library.
+const LUA_FUNCTION_PREFIX: &str = "netdox_";
+
+/// A connection to a standalone redis server, or to a redis cluster.
+/// In a cluster every key starts with the same hash tag,
+/// which is added to and removed from the keys of the commands sent through it.
+#[derive(Clone)]
+pub enum RedisConnection {
+    Single(MultiplexedConnection),
+    Cluster(ClusterConnection),
+}
+
+impl From<MultiplexedConnection> for RedisConnection {
+    fn from(con: MultiplexedConnection) -> Self {
+        RedisConnection::Single(con)
+    }
+}
+
+impl ConnectionLike for RedisConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a redis::Cmd) -> RedisFuture<'a, Value> {
+        match self {
+            RedisConnection::Single(con) => con.req_packed_command(cmd),
+            RedisConnection::Cluster(con) => Box::pin(redis_cluster::query(con, cmd)),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        pipeline: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match self {
+            RedisConnection::Single(con) => con.req_packed_commands(pipeline, offset, count),
+            RedisConnection::Cluster(con) => {
+                Box::pin(redis_cluster::query_pipeline(con, pipeline, offset, count))
+            }
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            RedisConnection::Single(con) => con.get_db(),
+            RedisConnection::Cluster(con) => con.get_db(),
+        }
+    }
+}
 
 #[async_trait]
-impl DataConn for redis::aio::MultiplexedConnection {
+impl DataConn for RedisConnection {
     async fn auth(&mut self, password: &str, username: Option<&String>) -> NetdoxResult<()> {
         let mut auth_cmd = redis::cmd("AUTH");
         if let Some(username) = username {
             auth_cmd.arg(username);
         }
         if let Err(err) = auth_cmd.arg(password).query_async::<()>(self).await {
-            return redis_err!(format!("Failed to authenticate with redis: {err}"));
+            return match username {
+                Some(username) => redis_err!(format!(
+                    "Failed to authenticate with redis as ACL user {username}: {err}"
+                )),
+                None => redis_err!(format!("Failed to authenticate with redis: {err}")),
+            };
         }
 
         Ok(())
     }
 
     async fn setup(&mut self, cfg: &LocalConfig) -> NetdoxResult<()> {
-        let dns_ignore = match &cfg.dns_ignore {
-            IgnoreList::Set(set) => set.clone(),
-            IgnoreList::Path(path) => match fs::read_to_string(path) {
-                Ok(str_list) => str_list
-                    .lines()
-                    .map(std::borrow::ToOwned::to_owned)
-                    .collect(),
-                Err(err) => {
-                    return io_err!(format!("Failed to read DNS ignorelist from {path}: {err}"))
-                }
-            },
-        };
+        debug!(
+            "Setting up redis with default network: {}",
+            cfg.default_network
+        );
+        let dns_ignore = cfg.dns_ignore.names()?;
+        let ignore_patterns = cfg.dns_ignore.patterns()?;
 
         redis::cmd("FUNCTION")
             .arg("LOAD")
@@ -60,6 +171,33 @@ impl DataConn for redis::aio::MultiplexedConnection {
             .query_async::<()>(self)
             .await?;
 
+        for path in &cfg.lua_functions {
+            let library = match fs::read_to_string(path) {
+                Ok(library) => library,
+                Err(err) => {
+                    return io_err!(format!(
+                        "Failed to read Lua functions from {}: {err}",
+                        path.display()
+                    ))
+                }
+            };
+
+            let name = check_lua_library(&library)?;
+            debug!("Loading Lua library {name} from {}", path.display());
+            if let Err(err) = redis::cmd("FUNCTION")
+                .arg("LOAD")
+                .arg("REPLACE")
+                .arg(&library)
+                .query_async::<()>(self)
+                .await
+            {
+                return redis_err!(format!(
+                    "Failed to load Lua functions from {}: {err}",
+                    path.display()
+                ));
+            }
+        }
+
         if let Err(err) = cmd("FCALL")
             .arg("netdox_setup")
             .arg(1)
@@ -71,6 +209,178 @@ impl DataConn for redis::aio::MultiplexedConnection {
             return redis_err!(format!("Failed to call Lua setup function: {err}"));
         }
 
+        self.del::<_, ()>(NETWORKS_KEY).await?;
+        if !cfg.networks.is_empty() {
+            // Qualified names are lowercased on creation, except for the default network.
+            let networks = cfg
+                .networks
+                .iter()
+                .map(|net| net.to_lowercase())
+                .chain([cfg.default_network.clone()])
+                .collect::<HashSet<_>>();
+
+            if let Err(err) = self.sadd::<_, _, ()>(NETWORKS_KEY, networks).await {
+                return redis_err!(format!("Failed to set registered networks: {err}"));
+            }
+        }
+
+        self.del::<_, ()>(DNS_IGNORE_PATTERNS_KEY).await?;
+        if !ignore_patterns.is_empty() {
+            if let Err(err) = self
+                .sadd::<_, _, ()>(DNS_IGNORE_PATTERNS_KEY, ignore_patterns)
+                .await
+            {
+                return redis_err!(format!("Failed to set DNS ignore patterns: {err}"));
+            }
+        }
+
+        let suffixes = cfg.network_suffixes()?;
+        self.del::<_, ()>(NETWORK_SUFFIXES_KEY).await?;
+        if !suffixes.is_empty() {
+            let suffixes = suffixes.into_iter().collect::<Vec<_>>();
+            if let Err(err) = self
+                .hset_multiple::<_, _, _, ()>(NETWORK_SUFFIXES_KEY, &suffixes)
+                .await
+            {
+                return redis_err!(format!("Failed to set network suffixes: {err}"));
+            }
+        }
+
+        self.del::<_, ()>(DAMPENING_KEY).await?;
+        if let Some(dampening) = &cfg.dampening {
+            let policy = [
+                ("window", dampening.window_minutes * 60),
+                ("max_changes", dampening.max_changes as u64),
+            ];
+            if let Err(err) = self
+                .hset_multiple::<_, _, _, ()>(DAMPENING_KEY, &policy)
+                .await
+            {
+                return redis_err!(format!("Failed to set dampening policy: {err}"));
+            }
+        }
+
+        self.del::<_, ()>(&[
+            CONFLICT_POLICY_KEY.to_string(),
+            format!("{CONFLICT_POLICY_KEY};priority"),
+        ])
+        .await?;
+        if !cfg.conflicts.is_default() {
+            let mut pipe = redis::pipe();
+            pipe.atomic()
+                .hset_multiple(
+                    CONFLICT_POLICY_KEY,
+                    &[
+                        ("strategy", cfg.conflicts.strategy.to_string()),
+                        ("report", cfg.conflicts.report.to_string()),
+                    ],
+                )
+                .ignore();
+            if !cfg.conflicts.priority.is_empty() {
+                pipe.rpush(
+                    format!("{CONFLICT_POLICY_KEY};priority"),
+                    &cfg.conflicts.priority,
+                )
+                .ignore();
+            }
+            if let Err(err) = pipe.query_async::<()>(&mut *self).await {
+                return redis_err!(format!("Failed to set conflict policy: {err}"));
+            }
+        }
+
+        self.del::<_, ()>(ROLES_KEY).await?;
+        if !cfg.roles.is_empty() {
+            if let Err(err) = self.sadd::<_, _, ()>(ROLES_KEY, &cfg.roles).await {
+                return redis_err!(format!("Failed to set valid node roles: {err}"));
+            }
+        }
+
+        let subnets = cfg
+            .subnets()?
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        self.del::<_, ()>(SUBNETS_KEY).await?;
+        if !subnets.is_empty() {
+            if let Err(err) = self.sadd::<_, _, ()>(SUBNETS_KEY, &subnets).await {
+                return redis_err!(format!("Failed to set configured subnets: {err}"));
+            }
+        }
+
+        let old_plugins: HashSet<String> = self.smembers(PLUGIN_DISPLAY_KEY).await?;
+        for plugin in old_plugins {
+            self.del::<_, ()>(format!("{PLUGIN_DISPLAY_KEY};{plugin}"))
+                .await?;
+        }
+        self.del::<_, ()>(PLUGIN_DISPLAY_KEY).await?;
+
+        for plugin in &cfg.plugins {
+            let display = plugin.display.to_hash();
+            if display.is_empty() {
+                continue;
+            }
+
+            if let Err(err) = self
+                .hset_multiple::<_, _, _, ()>(
+                    format!("{PLUGIN_DISPLAY_KEY};{}", plugin.name),
+                    &display,
+                )
+                .await
+            {
+                return redis_err!(format!(
+                    "Failed to set display settings for plugin {}: {err}",
+                    plugin.name
+                ));
+            }
+            self.sadd::<_, _, ()>(PLUGIN_DISPLAY_KEY, &plugin.name)
+                .await?;
+        }
+
+        let old_plugins: HashSet<String> = self.smembers(PLUGIN_SCOPES_KEY).await?;
+        for plugin in old_plugins {
+            self.del::<_, ()>(&[
+                format!("{PLUGIN_SCOPES_KEY};{plugin};networks"),
+                format!("{PLUGIN_SCOPES_KEY};{plugin};zones"),
+            ])
+            .await?;
+        }
+        self.del::<_, ()>(PLUGIN_SCOPES_KEY).await?;
+
+        for plugin in &cfg.plugins {
+            if plugin.scope.is_empty() {
+                continue;
+            }
+
+            let scope = plugin.scope.normalised();
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            if !scope.networks.is_empty() {
+                pipe.sadd(
+                    format!("{PLUGIN_SCOPES_KEY};{};networks", plugin.name),
+                    &scope.networks,
+                )
+                .ignore();
+            }
+            if !scope.zones.is_empty() {
+                pipe.sadd(
+                    format!("{PLUGIN_SCOPES_KEY};{};zones", plugin.name),
+                    &scope.zones,
+                )
+                .ignore();
+            }
+            pipe.sadd(PLUGIN_SCOPES_KEY, &plugin.name).ignore();
+
+            if let Err(err) = pipe.query_async::<()>(&mut *self).await {
+                return redis_err!(format!(
+                    "Failed to set scope for plugin {}: {err}",
+                    plugin.name
+                ));
+            }
+        }
+
+        // The upstream data may not have changed, but the config plugins are given might have.
+        self.clear_plugin_cursors().await?;
+
         Ok(())
     }
 
@@ -87,54 +397,84 @@ impl DataConn for redis::aio::MultiplexedConnection {
         Ok(())
     }
 
+    async fn flush(&mut self) -> NetdoxResult<()> {
+        if let Err(err) = cmd("FLUSHALL").query_async::<()>(self).await {
+            return redis_err!(format!("Failed to flush database: {err}"));
+        }
+
+        Ok(())
+    }
+
     // DNS
 
     async fn get_dns(&mut self) -> NetdoxResult<DNS> {
         let mut dns = DNS::new();
-        for qname in self.get_dns_names().await? {
-            for record in self
-                .smembers::<_, Vec<String>>(format!("{DNS_KEY};{qname}"))
-                .await?
-            {
-                let mut rsplit = record.splitn(3, ';');
-                let plugin = match rsplit.next() {
-                    Some(val) => val.to_string(),
-                    None => {
-                        return redis_err!(format!(
-                            "Invalid DNS record (no plugin) on qname {qname}"
-                        ))
-                    }
-                };
+        let mut cursor = 0;
+        loop {
+            let (next, batch) = self.scan_dns(cursor, SCAN_COUNT).await?;
+            dns.extend(batch);
+            if next == 0 {
+                return Ok(dns);
+            }
+            cursor = next;
+        }
+    }
 
-                let rtype = match rsplit.next() {
-                    Some(val) => val.to_string(),
-                    None => {
+    async fn get_dns_subset(&mut self, names: &[&str]) -> NetdoxResult<DNS> {
+        let mut dns = DNS::new();
+        let mut seen = HashSet::new();
+        let mut queue = names
+            .iter()
+            .map(|name| (*name).to_string())
+            .collect::<Vec<_>>();
+
+        while let Some(qname) = queue.pop() {
+            if !seen.insert(qname.clone()) {
+                continue;
+            }
+
+            for record in get_dns_records(self, &qname).await? {
+                if ADDRESS_RTYPES.contains(&record.rtype.as_str()) {
+                    queue.push(record.value.clone());
+                }
+                dns.add_record(record);
+            }
+
+            let implied_by: Vec<String> =
+                match self.smembers(format!("{DNS_KEY};{qname};implied")).await {
+                    Ok(names) => names,
+                    Err(err) => {
                         return redis_err!(format!(
-                            "Invalid DNS record (no rtype) on qname {qname}"
+                            "Failed to get names implying records for {qname}: {err}"
                         ))
                     }
                 };
+            queue.extend(implied_by);
 
-                let value = match rsplit.next() {
-                    Some(val) => val.to_string(),
-                    None => {
+            let maps: HashMap<String, String> =
+                match self.hgetall(format!("{DNS_KEY};{qname};maps")).await {
+                    Ok(maps) => maps,
+                    Err(err) => {
                         return redis_err!(format!(
-                            "Invalid DNS record (no value) on qname {qname}"
+                            "Failed to get network translations for {qname}: {err}"
                         ))
                     }
                 };
-
-                dns.add_record(DNSRecord {
-                    name: qname.clone(),
-                    value,
-                    rtype,
-                    plugin,
-                });
+            for (network, name) in maps {
+                let dest = format!("{network}{name}");
+                dns.add_translation(&qname, &dest);
+                queue.push(dest);
             }
 
             dns.qnames.insert(qname);
         }
 
+        debug!(
+            "Loaded {} DNS names reachable from: {}",
+            dns.qnames.len(),
+            names.join(", ")
+        );
+
         Ok(dns)
     }
 
@@ -149,6 +489,36 @@ impl DataConn for redis::aio::MultiplexedConnection {
         }
     }
 
+    async fn scan_dns(&mut self, cursor: u64, count: usize) -> NetdoxResult<(u64, DNS)> {
+        let (next, qnames) = sscan(self, DNS_KEY, cursor, count).await?;
+
+        let mut pipe = redis::pipe();
+        for qname in &qnames {
+            pipe.smembers(format!("{DNS_KEY};{qname}"));
+        }
+        let records: Vec<Vec<String>> = match pipe.query_async(self).await {
+            Ok(records) => records,
+            Err(err) => return redis_err!(format!("Failed to get batch of DNS records: {err}")),
+        };
+
+        let mut dns = DNS::new();
+        for (qname, records) in qnames.into_iter().zip(records) {
+            for record in records {
+                dns.add_record(parse_dns_record(&qname, &record)?);
+            }
+            dns.qnames.insert(qname);
+        }
+
+        Ok((next, dns))
+    }
+
+    async fn dns_name_exists(&mut self, qname: &str) -> NetdoxResult<bool> {
+        match self.sismember(DNS_KEY, qname).await {
+            Ok(exists) => Ok(exists),
+            Err(err) => redis_err!(format!("Failed to check if DNS name {qname} exists: {err}")),
+        }
+    }
+
     async fn qualify_dns_names(&mut self, names: &[&str]) -> NetdoxResult<Vec<String>> {
         let mut fcall = cmd("FCALL");
         fcall.arg("netdox_qualify_dns_names").arg(names.len());
@@ -162,89 +532,103 @@ impl DataConn for redis::aio::MultiplexedConnection {
         }
     }
 
-    // Nodes
-
-    // TODO maybe refactor this to use ID instead of key?
-    async fn get_raw_node(&mut self, key: &str) -> NetdoxResult<RawNode> {
-        let mut components = key.rsplit(';');
-        let dns_names = match (
-            components.next(), // last component, index
-            components,
-        ) {
-            (Some(_), remainder) => remainder
-                .into_iter()
-                .rev()
-                .skip(1)
-                .map(std::string::ToString::to_string)
-                .collect::<HashSet<String>>(),
-            _ => return redis_err!(format!("Invalid node redis key: {key}")),
-        };
+    async fn get_networks(&mut self) -> NetdoxResult<HashSet<String>> {
+        match self.smembers(NETWORKS_KEY).await {
+            Ok(networks) => Ok(networks),
+            Err(err) => redis_err!(format!("Failed to get registered networks: {err}")),
+        }
+    }
 
-        let mut details: HashMap<String, String> = match self.hgetall(key).await {
-            Err(err) => return redis_err!(format!("Failed to get node details at {key}: {err}")),
-            Ok(val) => val,
-        };
+    async fn get_dns_ignore_patterns(&mut self) -> NetdoxResult<HashSet<String>> {
+        match self.smembers(DNS_IGNORE_PATTERNS_KEY).await {
+            Ok(patterns) => Ok(patterns),
+            Err(err) => redis_err!(format!("Failed to get DNS ignore patterns: {err}")),
+        }
+    }
 
-        let plugin = match details.get("plugin") {
-            Some(plugin) => plugin.to_owned(),
-            None => return redis_err!(format!("Node details at key {key} missing plugin field.")),
+    async fn get_subnets(&mut self) -> NetdoxResult<HashSet<IpNet>> {
+        let subnets: HashSet<String> = match self.smembers(SUBNETS_KEY).await {
+            Ok(subnets) => subnets,
+            Err(err) => return redis_err!(format!("Failed to get configured subnets: {err}")),
         };
 
-        let name = details.get("name").cloned();
-
-        let exclusive = match details.get("exclusive") {
-            Some(val) => match val.as_str().parse::<bool>() {
-                Ok(_val) => _val,
-                Err(_) => {
-                    return redis_err!(format!(
-                        "Unable to parse boolean from exclusive value at {key}: {val}"
-                    ))
+        let mut parsed = HashSet::new();
+        for subnet in subnets {
+            match subnet.parse() {
+                Ok(net) => {
+                    parsed.insert(net);
+                }
+                Err(err) => {
+                    return redis_err!(format!("Invalid configured subnet {subnet}: {err}"))
                 }
-            },
-            None => {
-                return redis_err!(format!(
-                    "Node details at key {key} missing exclusive field."
-                ))
             }
-        };
+        }
+        Ok(parsed)
+    }
 
-        Ok(RawNode {
-            name,
-            exclusive,
-            link_id: details.remove("link_id"),
-            dns_names,
-            plugin,
-        })
+    // Nodes
+
+    async fn get_roles(&mut self) -> NetdoxResult<HashSet<String>> {
+        match self.smembers(ROLES_KEY).await {
+            Ok(roles) => Ok(roles),
+            Err(err) => redis_err!(format!("Failed to get valid node roles: {err}")),
+        }
     }
 
     async fn get_raw_nodes(&mut self) -> NetdoxResult<Vec<RawNode>> {
-        let nodes: HashSet<String> = match self.smembers(NODES_KEY).await {
+        let mut raw = vec![];
+        let mut cursor = 0;
+        loop {
+            let (next, batch) = self.scan_raw_nodes(cursor, SCAN_COUNT).await?;
+            raw.extend(batch);
+            if next == 0 {
+                return Ok(raw);
+            }
+            cursor = next;
+        }
+    }
+
+    async fn scan_raw_nodes(
+        &mut self,
+        cursor: u64,
+        count: usize,
+    ) -> NetdoxResult<(u64, Vec<RawNode>)> {
+        let (next, nodes) = sscan(self, NODES_KEY, cursor, count).await?;
+
+        let mut pipe = redis::pipe();
+        for node in &nodes {
+            pipe.get(format!("{NODES_KEY};{node}"));
+        }
+        let counts: Vec<Option<u64>> = match pipe.query_async(self).await {
+            Ok(counts) => counts,
             Err(err) => {
-                return redis_err!(format!(
-                    "Failed to get set of nodes using key {NODES_KEY}: {err}"
-                ))
+                return redis_err!(format!("Failed to get numbers of nodes in batch: {err}"))
             }
-            Ok(val) => val,
         };
 
-        let mut raw = vec![];
-        for node in nodes {
-            let redis_key = format!("{NODES_KEY};{node}");
-            let count: u64 = match self.get(&redis_key).await {
-                Err(err) => {
-                    return redis_err!(format!(
-                        "Failed to get number of nodes with key {redis_key}: {err}"
-                    ))
-                }
-                Ok(val) => val,
-            };
-
-            for index in 1..=count {
-                raw.push(self.get_raw_node(&format!("{redis_key};{index}")).await?);
-            }
+        let keys = nodes
+            .iter()
+            .zip(counts)
+            .flat_map(|(node, count)| {
+                (1..=count.unwrap_or_default())
+                    .map(move |index| format!("{NODES_KEY};{node};{index}"))
+            })
+            .collect::<Vec<_>>();
+        let mut pipe = redis::pipe();
+        for key in &keys {
+            pipe.hgetall(key);
         }
+        let details: Vec<HashMap<String, String>> = match pipe.query_async(self).await {
+            Ok(details) => details,
+            Err(err) => return redis_err!(format!("Failed to get batch of node details: {err}")),
+        };
 
-        Ok(raw)
+        let raw = keys
+            .iter()
+            .zip(details)
+            .map(|(key, details)| raw_node_from_details(key, details))
+            .collect::<NetdoxResult<Vec<_>>>()?;
+        Ok((next, raw))
     }
 
     async fn get_node(&mut self, id: &str) -> NetdoxResult<Node> {
@@ -295,7 +679,7 @@ impl DataConn for redis::aio::MultiplexedConnection {
     }
 
     async fn get_node_ids(&mut self) -> NetdoxResult<HashSet<String>> {
-        match self.smembers(NODES_KEY).await {
+        match self.smembers(PROC_NODES_KEY).await {
             Ok(set) => Ok(set),
             Err(err) => {
                 redis_err!(format!(
@@ -306,6 +690,55 @@ impl DataConn for redis::aio::MultiplexedConnection {
         }
     }
 
+    async fn scan_nodes(&mut self, cursor: u64, count: usize) -> NetdoxResult<(u64, Vec<Node>)> {
+        let (next, ids) = sscan(self, PROC_NODES_KEY, cursor, count).await?;
+
+        let mut pipe = redis::pipe();
+        for id in &ids {
+            pipe.get(format!("{PROC_NODES_KEY};{id}"));
+        }
+        let names: Vec<Option<String>> = match pipe.query_async(self).await {
+            Ok(names) => names,
+            Err(err) => return redis_err!(format!("Failed to get names of batch of nodes: {err}")),
+        };
+
+        let mut pipe = redis::pipe();
+        for id in &ids {
+            for set in ["alt_names", "dns_names", "plugins", "raw_ids"] {
+                pipe.smembers(format!("{PROC_NODES_KEY};{id};{set}"));
+            }
+        }
+        let sets: Vec<HashSet<String>> = match pipe.query_async(self).await {
+            Ok(sets) => sets,
+            Err(err) => return redis_err!(format!("Failed to get batch of node details: {err}")),
+        };
+
+        let mut nodes = Vec::with_capacity(ids.len());
+        for ((id, name), mut sets) in ids
+            .into_iter()
+            .zip(names)
+            .zip(sets.chunks(4).map(Vec::from))
+        {
+            let Some(name) = name else {
+                return redis_err!(format!("Linkable node with id {id} has no name."));
+            };
+            let raw_ids = sets.pop().unwrap_or_default();
+            let plugins = sets.pop().unwrap_or_default();
+            let dns_names = sets.pop().unwrap_or_default();
+            let alt_names = sets.pop().unwrap_or_default();
+            nodes.push(Node {
+                name,
+                link_id: id,
+                alt_names,
+                dns_names,
+                plugins,
+                raw_ids,
+            });
+        }
+
+        Ok((next, nodes))
+    }
+
     async fn get_node_from_raw(&mut self, raw_id: &str) -> NetdoxResult<Option<String>> {
         match self.hget(PROC_NODE_REVS_KEY, raw_id).await {
             Ok(id) => Ok(id),
@@ -323,111 +756,145 @@ impl DataConn for redis::aio::MultiplexedConnection {
         Ok(qnames.join(";"))
     }
 
-    async fn put_node(&mut self, node: &Node) -> NetdoxResult<()> {
-        let mut sorted_names: Vec<_> = node
-            .dns_names
-            .iter()
-            .map(std::borrow::ToOwned::to_owned)
-            .collect();
-        sorted_names.sort();
+    async fn get_node_dependencies(&mut self, link_id: &str) -> NetdoxResult<Vec<Dependency>> {
+        let node_ids = self.get_node_ids().await?;
+        let deps_key = format!("{DEPENDENCIES_KEY};{link_id}");
 
-        if let Err(err) = self.sadd::<_, _, u8>(PROC_NODES_KEY, &node.link_id).await {
-            return redis_err!(format!(
-                "Failed while adding link ID of resolved node to set: {err}"
-            ));
-        }
+        let dests: HashMap<String, String> = match self.hgetall(&deps_key).await {
+            Ok(dests) => dests,
+            Err(err) => {
+                return redis_err!(format!(
+                    "Failed to get dependencies of node {link_id}: {err}"
+                ))
+            }
+        };
 
-        let key = format!("{PROC_NODES_KEY};{}", node.link_id);
-        if let Err(err) = self.set::<_, _, String>(&key, &node.name).await {
-            return redis_err!(format!(
-                "Failed while setting name for resolved node: {err}"
-            ));
-        }
+        let sources: HashSet<String> = match self.smembers(format!("{deps_key};dependents")).await {
+            Ok(sources) => sources,
+            Err(err) => {
+                return redis_err!(format!("Failed to get dependents of node {link_id}: {err}"))
+            }
+        };
 
-        if !node.alt_names.is_empty() {
-            if let Err(err) = self
-                .sadd::<_, _, u8>(format!("{key};alt_names"), &node.alt_names)
-                .await
-            {
-                return redis_err!(format!(
-                    "Failed while updating alt names for resolved node: {err}"
-                ));
+        let mut dependencies = vec![];
+        for (dest, label) in dests {
+            if node_ids.contains(&dest) {
+                dependencies.push(Dependency::new(link_id, &dest, label));
             }
         }
 
-        if node.dns_names.is_empty() {
-            return redis_err!(format!(
-                "Cannot write node {} with no dns names.",
-                node.name
-            ));
-        } else if let Err(err) = self.del::<_, u8>(format!("{key};dns_names")).await {
-            return redis_err!(format!(
-                "Failed while clearing old dns names for resolved node: {err}"
-            ));
-        } else if let Err(err) = self
-            .sadd::<_, _, u8>(format!("{key};dns_names"), &node.dns_names)
-            .await
-        {
-            return redis_err!(format!(
-                "Failed while setting dns names for resolved node: {err}"
-            ));
-        }
+        for source in sources {
+            if !node_ids.contains(&source) {
+                continue;
+            }
 
-        for name in &node.dns_names {
-            if let Err(err) = self
-                .hset::<_, _, _, u8>("dns_nodes", name, &node.link_id)
+            let label: Option<String> = match self
+                .hget(format!("{DEPENDENCIES_KEY};{source}"), link_id)
                 .await
             {
-                return redis_err!(format!("Failed to set node for dns name: {err}"));
+                Ok(label) => label,
+                Err(err) => {
+                    return redis_err!(format!(
+                        "Failed to get dependency of node {source} on {link_id}: {err}"
+                    ))
+                }
+            };
+
+            if let Some(label) = label {
+                dependencies.push(Dependency::new(&source, link_id, label));
             }
         }
 
-        if node.plugins.is_empty() {
-            return redis_err!(format!(
-                "Cannot write node {} with no source plugins",
-                node.name
-            ));
-        } else if let Err(err) = self
-            .sadd::<_, _, u8>(format!("{key};plugins"), &node.plugins)
-            .await
-        {
-            return redis_err!(format!(
-                "Failed while updating plugins for resolved node: {err}"
-            ));
-        }
+        dependencies.sort_by(|lhs, rhs| (&lhs.source, &lhs.dest).cmp(&(&rhs.source, &rhs.dest)));
+        Ok(dependencies)
+    }
 
-        if node.raw_ids.is_empty() {
-            return redis_err!(format!(
-                "Cannot write node {} with no source raw ids",
-                node.name
-            ));
-        } else if let Err(err) = self
-            .sadd::<_, _, u8>(format!("{key};raw_ids"), &node.raw_ids)
-            .await
-        {
-            return redis_err!(format!(
-                "Failed while updating raw ids for resolved node: {err}"
-            ));
-        }
+    async fn get_node_relations(&mut self, link_id: &str) -> NetdoxResult<Vec<Relation>> {
+        let node_ids = self.get_node_ids().await?;
+        let relations_key = format!("{RELATIONS_KEY};{link_id}");
 
-        for raw_id in &node.raw_ids {
-            if let Err(err) = self
-                .hset::<_, _, _, u8>(PROC_NODE_REVS_KEY.to_string(), raw_id, &node.link_id)
-                .await
-            {
-                return redis_err!(format!(
-                    "Failed to set reverse ptr for raw key {raw_id} to {}: {err}",
-                    &node.link_id
-                ));
+        let outgoing: HashSet<String> = match self.smembers(&relations_key).await {
+            Ok(outgoing) => outgoing,
+            Err(err) => {
+                return redis_err!(format!("Failed to get relations of node {link_id}: {err}"))
+            }
+        };
+
+        let incoming: HashSet<String> =
+            match self.smembers(format!("{relations_key};incoming")).await {
+                Ok(incoming) => incoming,
+                Err(err) => {
+                    return redis_err!(format!("Failed to get relations to node {link_id}: {err}"))
+                }
+            };
+
+        let mut relations = outgoing
+            .iter()
+            .filter_map(|member| Relation::parse(link_id, member, false))
+            .chain(
+                incoming
+                    .iter()
+                    .filter_map(|member| Relation::parse(link_id, member, true)),
+            )
+            .filter(|relation| node_ids.contains(relation.other(link_id)))
+            .collect::<Vec<_>>();
+
+        relations.sort_by(|lhs, rhs| {
+            (&lhs.kind, &lhs.source, &lhs.dest).cmp(&(&rhs.kind, &rhs.source, &rhs.dest))
+        });
+        Ok(relations)
+    }
+
+    async fn get_node_services(&mut self, link_id: &str) -> NetdoxResult<Vec<Service>> {
+        let services_key = format!("{SERVICES_KEY};{link_id}");
+        let ids: HashSet<String> = match self.smembers(&services_key).await {
+            Ok(ids) => ids,
+            Err(err) => {
+                return redis_err!(format!("Failed to get services of node {link_id}: {err}"))
             }
+        };
+
+        let mut services = vec![];
+        for id in ids {
+            let Some((port, protocol)) = Service::parse_id(&id) else {
+                return redis_err!(format!("Invalid service {id} on node {link_id}"));
+            };
+
+            let service_key = format!("{services_key};{id}");
+            let details = match self.hgetall(&service_key).await {
+                Ok(details) => details,
+                Err(err) => {
+                    return redis_err!(format!(
+                        "Failed to get details of service {id} on node {link_id}: {err}"
+                    ))
+                }
+            };
+            let plugins = match self.smembers(format!("{service_key};plugins")).await {
+                Ok(plugins) => plugins,
+                Err(err) => {
+                    return redis_err!(format!(
+                        "Failed to get plugins of service {id} on node {link_id}: {err}"
+                    ))
+                }
+            };
+
+            services.push(Service {
+                link_id: link_id.to_string(),
+                port,
+                protocol,
+                details,
+                plugins,
+            });
         }
 
-        Ok(())
+        services.sort_by(|lhs, rhs| (lhs.port, &lhs.protocol).cmp(&(rhs.port, &rhs.protocol)));
+        Ok(services)
     }
 
     // Data
 
     async fn get_data(&mut self, key: &str) -> NetdoxResult<Data> {
+        debug!("Reading data from key: {key}");
         let id = match key.rsplit_once(';') {
             Some((_, id)) => id.to_string(),
             None => return redis_err!(format!("Failed to get plugin data id from key: {key}")),
@@ -599,8 +1066,81 @@ impl DataConn for redis::aio::MultiplexedConnection {
         Ok(dataset)
     }
 
+    async fn delete_dns_pdata(
+        &mut self,
+        qname: &str,
+        plugin: &str,
+        data_id: &str,
+    ) -> NetdoxResult<bool> {
+        match cmd("FCALL")
+            .arg("netdox_delete_dns_plugin_data")
+            .arg(1)
+            .arg(qname)
+            .arg(plugin)
+            .arg(data_id)
+            .query_async::<bool>(self)
+            .await
+        {
+            Ok(deleted) => Ok(deleted),
+            Err(err) => redis_err!(format!(
+                "Failed to delete plugin data {data_id} from {qname}: {err}"
+            )),
+        }
+    }
+
+    async fn delete_node_pdata(
+        &mut self,
+        node: &Node,
+        plugin: &str,
+        data_id: &str,
+    ) -> NetdoxResult<bool> {
+        let mut deleted = false;
+        for raw in &node.raw_ids {
+            let names = raw.split(';').collect::<Vec<_>>();
+            match cmd("FCALL")
+                .arg("netdox_delete_node_plugin_data")
+                .arg(names.len())
+                .arg(names)
+                .arg(plugin)
+                .arg(data_id)
+                .query_async::<bool>(self)
+                .await
+            {
+                Ok(raw_deleted) => deleted |= raw_deleted,
+                Err(err) => {
+                    return redis_err!(format!(
+                        "Failed to delete plugin data {data_id} from raw node {raw}: {err}"
+                    ))
+                }
+            }
+        }
+
+        match cmd("FCALL")
+            .arg("netdox_delete_proc_node_plugin_data")
+            .arg(1)
+            .arg(&node.link_id)
+            .arg(plugin)
+            .arg(data_id)
+            .query_async::<bool>(self)
+            .await
+        {
+            Ok(proc_deleted) => Ok(deleted || proc_deleted),
+            Err(err) => redis_err!(format!(
+                "Failed to delete plugin data {data_id} from node {}: {err}",
+                node.link_id
+            )),
+        }
+    }
+
     // Reports
 
+    async fn get_report_ids(&mut self) -> NetdoxResult<HashSet<String>> {
+        match self.smembers(REPORTS_KEY).await {
+            Ok(set) => Ok(set),
+            Err(err) => redis_err!(format!("Failed to get report IDs: {err}")),
+        }
+    }
+
     async fn get_report(&mut self, id: &str) -> NetdoxResult<Report> {
         let details: HashMap<String, String> =
             match self.hgetall(format!("{REPORTS_KEY};{id}")).await {
@@ -704,6 +1244,7 @@ impl DataConn for redis::aio::MultiplexedConnection {
         plugin: &str,
         data: HashMap<&str, &str>,
     ) -> NetdoxResult<()> {
+        debug!("Writing metadata for DNS name {qname} from plugin {plugin}: {data:?}");
         let result = cmd("FCALL")
             .arg(DNS_METADATA_FN)
             .arg(1)
@@ -741,26 +1282,76 @@ impl DataConn for redis::aio::MultiplexedConnection {
     }
 
     async fn get_node_metadata(&mut self, node: &Node) -> NetdoxResult<HashMap<String, String>> {
-        let mut meta = HashMap::new();
-        for raw_id in &node.raw_ids {
-            let raw_meta: HashMap<String, String> = match self
-                .hgetall(format!("{METADATA_KEY};{NODES_KEY};{raw_id}"))
-                .await
-            {
-                Ok(map) => map,
-                Err(err) => {
-                    return redis_err!(format!(
-                        "Failed to get metadata for raw node {raw_id}: {}",
-                        err.to_string()
-                    ))
-                }
-            };
-            meta.extend(raw_meta);
+        let (provenance, _) = get_node_metadata_merged(self, node).await?;
+        Ok(provenance
+            .into_iter()
+            .map(|(key, meta)| (key, meta.value))
+            .collect())
+    }
+
+    async fn get_dns_metadata_sources(
+        &mut self,
+        qname: &str,
+    ) -> NetdoxResult<HashMap<String, String>> {
+        match self
+            .hgetall(format!("{METADATA_KEY};{DNS_KEY};{qname};sources"))
+            .await
+        {
+            Ok(map) => Ok(map),
+            Err(err) => redis_err!(format!(
+                "Failed to get metadata sources for dns obj {qname}: {err}"
+            )),
         }
+    }
+
+    async fn get_dns_metadata_provenance(
+        &mut self,
+        qname: &str,
+    ) -> NetdoxResult<HashMap<String, MetadataProvenance>> {
+        get_metadata_provenance(self, &format!("{METADATA_KEY};{DNS_KEY};{qname}")).await
+    }
+
+    async fn get_node_metadata_provenance(
+        &mut self,
+        node: &Node,
+    ) -> NetdoxResult<HashMap<String, MetadataProvenance>> {
+        Ok(get_node_metadata_merged(self, node).await?.0)
+    }
+
+    async fn get_conflict_policy(&mut self) -> NetdoxResult<ConflictPolicy> {
+        let (policy, priority): (HashMap<String, String>, Vec<String>) = match redis::pipe()
+            .hgetall(CONFLICT_POLICY_KEY)
+            .lrange(format!("{CONFLICT_POLICY_KEY};priority"), 0, -1)
+            .query_async(self)
+            .await
+        {
+            Ok(policy) => policy,
+            Err(err) => return redis_err!(format!("Failed to get conflict policy: {err}")),
+        };
+
+        Ok(ConflictPolicy {
+            priority,
+            strategy: match policy.get("strategy") {
+                Some(strategy) => ConflictStrategy::try_from(strategy.as_str())?,
+                None => ConflictStrategy::default(),
+            },
+            report: policy.get("report").is_some_and(|report| report == "true"),
+        })
+    }
 
-        meta.extend(self.get_proc_node_metadata(&node.link_id).await?);
+    async fn get_node_metadata_conflicts(&mut self, node: &Node) -> NetdoxResult<Vec<Conflict>> {
+        Ok(get_node_metadata_merged(self, node).await?.1)
+    }
 
-        Ok(meta)
+    async fn get_node_metadata_sources(
+        &mut self,
+        node: &Node,
+    ) -> NetdoxResult<HashMap<String, String>> {
+        let (provenance, _) = get_node_metadata_merged(self, node).await?;
+        Ok(provenance
+            .into_iter()
+            .filter_map(|(key, meta)| meta.plugin.map(|plugin| (key, plugin)))
+            .collect())
     }
 
     async fn put_node_metadata(
@@ -784,6 +1375,216 @@ impl DataConn for redis::aio::MultiplexedConnection {
         }
     }
 
+    async fn migrate_metadata(&mut self, dry_run: bool) -> NetdoxResult<MetadataMigration> {
+        let mode = if dry_run { "dry-run" } else { "write" };
+        match cmd("FCALL")
+            .arg("netdox_migrate_metadata")
+            .arg(0)
+            .arg(mode)
+            .query_async::<(Vec<String>, Vec<String>)>(self)
+            .await
+        {
+            Ok((moved, unresolved)) => Ok(MetadataMigration {
+                moved: moved
+                    .chunks_exact(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect(),
+                unresolved,
+            }),
+            Err(err) => redis_err!(format!(
+                "Failed to call Lua migrate metadata function: {err}"
+            )),
+        }
+    }
+
+    // Notes
+
+    async fn get_notes(&mut self, obj_id: &str) -> NetdoxResult<HashMap<String, String>> {
+        match self.hgetall(format!("{NOTES_KEY};{obj_id}")).await {
+            Ok(notes) => Ok(notes),
+            Err(err) => redis_err!(format!("Failed to get notes for {obj_id}: {err}")),
+        }
+    }
+
+    async fn put_note(&mut self, obj_id: &str, key: &str, value: Option<&str>) -> NetdoxResult<()> {
+        let notes_key = format!("{NOTES_KEY};{obj_id}");
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        match value {
+            Some(value) => pipe.hset(&notes_key, key, value).ignore(),
+            None => pipe.hdel(&notes_key, key).ignore(),
+        };
+        pipe.xadd(
+            CHANGELOG_KEY,
+            "*",
+            &[
+                ("change", "updated notes"),
+                ("value", notes_key.as_str()),
+                ("plugin", NETDOX_PLUGIN),
+                ("obj_id", obj_id),
+            ],
+        )
+        .ignore();
+
+        debug!("Setting note {key} on {obj_id} to: {value:?}");
+        match pipe.query_async::<()>(self).await {
+            Ok(()) => Ok(()),
+            Err(err) => redis_err!(format!("Failed to set note {key} on {obj_id}: {err}")),
+        }
+    }
+
+    // Tags
+
+    async fn get_tags(&mut self) -> NetdoxResult<HashSet<String>> {
+        match self.smembers(TAGS_KEY).await {
+            Ok(tags) => Ok(tags),
+            Err(err) => redis_err!(format!("Failed to get tags: {err}")),
+        }
+    }
+
+    async fn get_tagged(&mut self, tag: &str) -> NetdoxResult<HashSet<String>> {
+        match self.smembers(format!("{TAGS_KEY};{tag}")).await {
+            Ok(ids) => Ok(ids),
+            Err(err) => redis_err!(format!("Failed to get objects tagged {tag}: {err}")),
+        }
+    }
+
+    async fn get_dns_tags(&mut self, qname: &str) -> NetdoxResult<HashSet<String>> {
+        match self
+            .smembers(format!("{METADATA_KEY};{DNS_KEY};{qname};tags"))
+            .await
+        {
+            Ok(tags) => Ok(tags),
+            Err(err) => redis_err!(format!("Failed to get tags for dns name {qname}: {err}")),
+        }
+    }
+
+    async fn get_node_tags(&mut self, node: &Node) -> NetdoxResult<HashSet<String>> {
+        let mut keys = node
+            .raw_ids
+            .iter()
+            .map(|raw_id| format!("{METADATA_KEY};{NODES_KEY};{raw_id};tags"))
+            .collect::<Vec<_>>();
+        keys.push(format!(
+            "{METADATA_KEY};{PROC_NODES_KEY};{};tags",
+            node.link_id
+        ));
+
+        match self.sunion(keys).await {
+            Ok(tags) => Ok(tags),
+            Err(err) => redis_err!(format!(
+                "Failed to get tags for node {}: {err}",
+                node.link_id
+            )),
+        }
+    }
+
+    // Plugins
+
+    async fn get_plugin_displays(&mut self) -> NetdoxResult<HashMap<String, PluginDisplay>> {
+        let plugins: HashSet<String> = match self.smembers(PLUGIN_DISPLAY_KEY).await {
+            Ok(set) => set,
+            Err(err) => {
+                return redis_err!(format!(
+                    "Failed to get plugins with display settings: {err}"
+                ))
+            }
+        };
+
+        let mut displays = HashMap::new();
+        for plugin in plugins {
+            match self.hgetall(format!("{PLUGIN_DISPLAY_KEY};{plugin}")).await {
+                Ok(details) => {
+                    displays.insert(plugin, PluginDisplay::from_hash(details));
+                }
+                Err(err) => {
+                    return redis_err!(format!(
+                        "Failed to get display settings for plugin {plugin}: {err}"
+                    ))
+                }
+            }
+        }
+
+        Ok(displays)
+    }
+
+    // Documents
+
+    /// Each read runs on a clone of the connection, so they are pipelined together
+    /// instead of waiting on each other.
+    async fn get_dns_bundle(&mut self, qname: &str) -> NetdoxResult<DnsBundle> {
+        let names = [qname];
+        let notes_id = format!("{DNS_KEY};{qname}");
+        let (mut dns_con, mut tags_con, mut meta_con, mut notes_con, mut pdata_con) = (
+            self.clone(),
+            self.clone(),
+            self.clone(),
+            self.clone(),
+            self.clone(),
+        );
+        let (dns, tags, metadata, notes, pdata, displays) = tokio::try_join!(
+            dns_con.get_dns_subset(&names),
+            tags_con.get_dns_tags(qname),
+            meta_con.get_dns_metadata_provenance(qname),
+            notes_con.get_notes(&notes_id),
+            pdata_con.get_dns_pdata(qname),
+            self.get_plugin_displays(),
+        )?;
+
+        Ok(DnsBundle {
+            dns,
+            tags,
+            metadata,
+            notes,
+            pdata,
+            displays,
+        })
+    }
+
+    /// Each read runs on a clone of the connection, so they are pipelined together
+    /// instead of waiting on each other.
+    async fn get_node_bundle(&mut self, node: &Node) -> NetdoxResult<NodeBundle> {
+        let notes_id = format!("{PROC_NODES_KEY};{}", node.link_id);
+        let (
+            mut tags_con,
+            mut meta_con,
+            mut notes_con,
+            mut deps_con,
+            mut rels_con,
+            mut svcs_con,
+            mut pdata_con,
+        ) = (
+            self.clone(),
+            self.clone(),
+            self.clone(),
+            self.clone(),
+            self.clone(),
+            self.clone(),
+            self.clone(),
+        );
+        let (tags, metadata, notes, dependencies, relations, services, pdata, displays) = tokio::try_join!(
+            tags_con.get_node_tags(node),
+            meta_con.get_node_metadata_provenance(node),
+            notes_con.get_notes(&notes_id),
+            deps_con.get_node_dependencies(&node.link_id),
+            rels_con.get_node_relations(&node.link_id),
+            svcs_con.get_node_services(&node.link_id),
+            pdata_con.get_node_pdata(node),
+            self.get_plugin_displays(),
+        )?;
+
+        Ok(NodeBundle {
+            tags,
+            metadata,
+            notes,
+            dependencies,
+            relations,
+            services,
+            pdata,
+            displays,
+        })
+    }
+
     // Changelog
 
     async fn get_changes(&mut self, start_id: Option<&str>) -> NetdoxResult<Vec<ChangelogEntry>> {
@@ -792,8 +1593,18 @@ impl DataConn for redis::aio::MultiplexedConnection {
             None => "-".to_string(),
         };
 
-        match self.xrange(CHANGELOG_KEY, &start, "+").await {
-            Ok(changes) => Ok(changes),
+        match self
+            .xrange::<_, _, _, Vec<ChangelogEntry>>(CHANGELOG_KEY, &start, "+")
+            .await
+        {
+            Ok(changes) => {
+                debug!(
+                    "Read {} changes from changelog after: {}",
+                    changes.len(),
+                    start_id.unwrap_or("start")
+                );
+                Ok(changes)
+            }
             Err(err) => redis_err!(format!(
                 "Failed to fetch changes from {} to present: {}",
                 start_id.unwrap_or("start"),
@@ -851,9 +1662,1428 @@ impl DataConn for redis::aio::MultiplexedConnection {
         }
     }
 
-    // Persistence
+    // Indexes
+
+    async fn rebuild_index(&mut self) -> NetdoxResult<IndexRepairs> {
+        match cmd("FCALL")
+            .arg("netdox_rebuild_index")
+            .arg(0)
+            .query_async::<(usize, usize, usize, usize)>(self)
+            .await
+        {
+            Ok((dns_nodes, proc_node_revs, dns_implied, pdata)) => Ok(IndexRepairs {
+                dns_nodes,
+                proc_node_revs,
+                dns_implied,
+                pdata,
+            }),
+            Err(err) => redis_err!(format!("Failed to call Lua rebuild index function: {err}")),
+        }
+    }
+
+    async fn rename_network(
+        &mut self,
+        old: &str,
+        new: &str,
+        dry_run: bool,
+    ) -> NetdoxResult<NetworkRename> {
+        let mode = if dry_run { "dry-run" } else { "write" };
+        match cmd("FCALL")
+            .arg("netdox_rename_network")
+            .arg(0)
+            .arg(old)
+            .arg(new)
+            .arg(mode)
+            .query_async::<(Vec<String>, Vec<String>)>(self)
+            .await
+        {
+            Ok((dns_names, nodes)) => Ok(NetworkRename {
+                dns_names: dns_names
+                    .chunks_exact(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect(),
+                nodes: nodes
+                    .chunks_exact(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect(),
+            }),
+            Err(err) => redis_err!(format!("Failed to call Lua rename network function: {err}")),
+        }
+    }
+
+    async fn delete_dns(&mut self, qname: &str, dry_run: bool) -> NetdoxResult<DnsDeletion> {
+        let mode = if dry_run { "dry-run" } else { "write" };
+        let records = |parts: Vec<String>| {
+            parts
+                .chunks_exact(4)
+                .map(|parts| DNSRecord {
+                    name: parts[0].clone(),
+                    plugin: parts[1].clone(),
+                    rtype: parts[2].clone(),
+                    value: parts[3].clone(),
+                })
+                .collect()
+        };
+
+        match cmd("FCALL")
+            .arg("netdox_delete_dns")
+            .arg(1)
+            .arg(qname)
+            .arg(mode)
+            .query_async::<(
+                Vec<String>,
+                Vec<String>,
+                Vec<String>,
+                Vec<String>,
+                Vec<String>,
+            )>(self)
+            .await
+        {
+            Ok((own, references, nodes, detached, proc_nodes)) => Ok(DnsDeletion {
+                records: records(own),
+                references: records(references),
+                nodes: nodes
+                    .chunks_exact(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect(),
+                detached,
+                proc_nodes,
+            }),
+            Err(err) => redis_err!(format!("Failed to call Lua delete DNS function: {err}")),
+        }
+    }
+
+    async fn prune(
+        &mut self,
+        default_cutoff: Option<u64>,
+        plugin_cutoffs: &HashMap<String, u64>,
+        dry_run: bool,
+    ) -> NetdoxResult<Prune> {
+        let mut fcall = cmd("FCALL");
+        fcall
+            .arg("netdox_prune")
+            .arg(0)
+            .arg(if dry_run { "dry-run" } else { "write" })
+            .arg(
+                default_cutoff
+                    .map(|cutoff| cutoff.to_string())
+                    .unwrap_or_default(),
+            );
+        for (plugin, cutoff) in plugin_cutoffs {
+            fcall.arg(plugin).arg(cutoff);
+        }
+
+        match fcall
+            .query_async::<(Vec<String>, Vec<String>, Vec<String>, Vec<String>)>(self)
+            .await
+        {
+            Ok((records, nodes, dns_names, proc_nodes)) => Ok(Prune {
+                records: records
+                    .chunks_exact(4)
+                    .map(|parts| DNSRecord {
+                        name: parts[0].clone(),
+                        plugin: parts[1].clone(),
+                        rtype: parts[2].clone(),
+                        value: parts[3].clone(),
+                    })
+                    .collect(),
+                nodes: nodes
+                    .chunks_exact(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect(),
+                dns_names,
+                proc_nodes,
+            }),
+            Err(err) => redis_err!(format!("Failed to call Lua prune function: {err}")),
+        }
+    }
+
+    // Runs
+
+    async fn put_run(&mut self, run: &RunRecord) -> NetdoxResult<()> {
+        let value = match toml::to_string(run) {
+            Ok(value) => value,
+            Err(err) => return redis_err!(format!("Failed to serialize run record: {err}")),
+        };
+
+        debug!("Recording {} run that took {}ms.", run.kind, run.millis);
+        match redis::pipe()
+            .atomic()
+            .lpush(RUNS_KEY, value)
+            .ignore()
+            .ltrim(RUNS_KEY, 0, MAX_RUNS as isize - 1)
+            .ignore()
+            .query_async::<()>(self)
+            .await
+        {
+            Ok(()) => Ok(()),
+            Err(err) => redis_err!(format!("Failed to record run: {err}")),
+        }
+    }
+
+    async fn get_runs(&mut self, count: usize) -> NetdoxResult<Vec<RunRecord>> {
+        if count == 0 {
+            return Ok(vec![]);
+        }
+
+        let values: Vec<String> = match self.lrange(RUNS_KEY, 0, count as isize - 1).await {
+            Ok(values) => values,
+            Err(err) => return redis_err!(format!("Failed to get run records: {err}")),
+        };
+
+        let mut runs = Vec::with_capacity(values.len());
+        for value in values {
+            match toml::from_str(&value) {
+                Ok(run) => runs.push(run),
+                Err(err) => return redis_err!(format!("Failed to parse run record: {err}")),
+            }
+        }
+
+        Ok(runs)
+    }
+
+    async fn acquire_run_lock(&mut self, lock: &RunLock) -> NetdoxResult<Option<RunLock>> {
+        let value = match toml::to_string(lock) {
+            Ok(value) => value,
+            Err(err) => return redis_err!(format!("Failed to serialize run lock: {err}")),
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let ttl = lock.expires.saturating_sub(now).max(1);
+
+        let held: Option<String> = match Script::new(ACQUIRE_LOCK_SCRIPT)
+            .key(RUN_LOCK_KEY)
+            .arg(&lock.owner)
+            .arg(value)
+            .arg(ttl)
+            .invoke_async(self)
+            .await
+        {
+            Ok(held) => held,
+            Err(err) => return redis_err!(format!("Failed to acquire run lock: {err}")),
+        };
+
+        match held.map(|value| toml::from_str(&value)) {
+            None => Ok(None),
+            Some(Ok(held)) => Ok(Some(held)),
+            Some(Err(err)) => redis_err!(format!("Failed to parse run lock: {err}")),
+        }
+    }
+
+    async fn release_run_lock(&mut self, owner: &str) -> NetdoxResult<()> {
+        match Script::new(RELEASE_LOCK_SCRIPT)
+            .key(RUN_LOCK_KEY)
+            .arg(owner)
+            .invoke_async::<()>(self)
+            .await
+        {
+            Ok(()) => Ok(()),
+            Err(err) => redis_err!(format!("Failed to release run lock: {err}")),
+        }
+    }
+
+    async fn force_run_unlock(&mut self) -> NetdoxResult<Option<RunLock>> {
+        let held: Option<String> = match self.hget(RUN_LOCK_KEY, "lock").await {
+            Ok(held) => held,
+            Err(err) => return redis_err!(format!("Failed to get run lock: {err}")),
+        };
+
+        if let Err(err) = self.del::<_, ()>(RUN_LOCK_KEY).await {
+            return redis_err!(format!("Failed to remove run lock: {err}"));
+        }
+
+        match held.map(|value| toml::from_str(&value)) {
+            None => Ok(None),
+            Some(Ok(held)) => Ok(Some(held)),
+            Some(Err(err)) => redis_err!(format!("Failed to parse run lock: {err}")),
+        }
+    }
+
+    async fn get_deferred_plugins(&mut self) -> NetdoxResult<HashSet<String>> {
+        match self.smembers(DEFERRED_PLUGINS_KEY).await {
+            Ok(plugins) => Ok(plugins),
+            Err(err) => redis_err!(format!("Failed to get deferred plugins: {err}")),
+        }
+    }
+
+    async fn set_deferred_plugins(&mut self, plugins: &HashSet<String>) -> NetdoxResult<()> {
+        let mut pipe = redis::pipe();
+        pipe.atomic().del(DEFERRED_PLUGINS_KEY).ignore();
+        if !plugins.is_empty() {
+            pipe.sadd(DEFERRED_PLUGINS_KEY, plugins).ignore();
+        }
+
+        match pipe.query_async::<()>(self).await {
+            Ok(()) => Ok(()),
+            Err(err) => redis_err!(format!("Failed to set deferred plugins: {err}")),
+        }
+    }
+
+    async fn get_scope_violations(&mut self) -> NetdoxResult<HashMap<String, HashSet<String>>> {
+        let plugins: HashSet<String> = match self.smembers(SCOPE_VIOLATIONS_KEY).await {
+            Ok(plugins) => plugins,
+            Err(err) => return redis_err!(format!("Failed to get scope violations: {err}")),
+        };
+
+        let mut violations = HashMap::new();
+        for plugin in plugins {
+            match self
+                .smembers(format!("{SCOPE_VIOLATIONS_KEY};{plugin}"))
+                .await
+            {
+                Ok(names) => {
+                    violations.insert(plugin, names);
+                }
+                Err(err) => {
+                    return redis_err!(format!(
+                        "Failed to get scope violations of plugin {plugin}: {err}"
+                    ))
+                }
+            }
+        }
+
+        Ok(violations)
+    }
+
+    async fn clear_scope_violations(&mut self) -> NetdoxResult<()> {
+        let plugins: HashSet<String> = self.smembers(SCOPE_VIOLATIONS_KEY).await?;
+        let mut pipe = redis::pipe();
+        pipe.atomic().del(SCOPE_VIOLATIONS_KEY).ignore();
+        for plugin in plugins {
+            pipe.del(format!("{SCOPE_VIOLATIONS_KEY};{plugin}"))
+                .ignore();
+        }
+
+        match pipe.query_async::<()>(self).await {
+            Ok(()) => Ok(()),
+            Err(err) => redis_err!(format!("Failed to clear scope violations: {err}")),
+        }
+    }
+
+    async fn get_plugin_cursor(
+        &mut self,
+        plugin: &str,
+        stage: &str,
+    ) -> NetdoxResult<Option<String>> {
+        match self
+            .hget(PLUGIN_CURSORS_KEY, format!("{plugin};{stage}"))
+            .await
+        {
+            Ok(cursor) => Ok(cursor),
+            Err(err) => redis_err!(format!(
+                "Failed to get cursor of {stage} stage for plugin {plugin}: {err}"
+            )),
+        }
+    }
+
+    async fn set_plugin_cursor(
+        &mut self,
+        plugin: &str,
+        stage: &str,
+        cursor: &str,
+    ) -> NetdoxResult<()> {
+        match self
+            .hset::<_, _, _, ()>(PLUGIN_CURSORS_KEY, format!("{plugin};{stage}"), cursor)
+            .await
+        {
+            Ok(()) => Ok(()),
+            Err(err) => redis_err!(format!(
+                "Failed to set cursor of {stage} stage for plugin {plugin}: {err}"
+            )),
+        }
+    }
+
+    async fn clear_plugin_cursors(&mut self) -> NetdoxResult<()> {
+        match self.del::<_, ()>(PLUGIN_CURSORS_KEY).await {
+            Ok(()) => Ok(()),
+            Err(err) => redis_err!(format!("Failed to clear plugin cursors: {err}")),
+        }
+    }
+
+    // Processing
+
+    async fn begin_process(&mut self, run: &ProcessRun) -> NetdoxResult<()> {
+        let value = match toml::to_string(run) {
+            Ok(value) => value,
+            Err(err) => return redis_err!(format!("Failed to serialize process run: {err}")),
+        };
+
+        match self.set::<_, _, ()>(PROCESS_RUN_KEY, value).await {
+            Ok(()) => Ok(()),
+            Err(err) => redis_err!(format!("Failed to record start of processing: {err}")),
+        }
+    }
+
+    async fn put_processed(
+        &mut self,
+        resolved: &[(String, String)],
+        nodes: &[&Node],
+    ) -> NetdoxResult<()> {
+        // The nodes and the indices built from them are written under the prefix
+        // of a new version of the processed layer, and then replace the live ones
+        // in a short transaction that also deletes the nodes this run did not produce.
+        // Staged keys left by an earlier run that did not finish are removed first.
+        clear_staged(self).await?;
+        let version = match self.get::<_, Option<u64>>(PROC_VERSION_KEY).await {
+            Ok(version) => version.unwrap_or_default() + 1,
+            Err(err) => return redis_err!(format!("Failed to get processed data version: {err}")),
+        };
+        let prefix = format!("{PROC_STAGING_KEY};{version}");
+
+        debug!("Writing {} processed nodes.", nodes.len());
+        let mut stage = redis::pipe();
+        let mut link_ids = HashSet::new();
+        let mut dns_nodes = HashMap::new();
+        for node in nodes {
+            node_commands(&mut stage, &prefix, node)?;
+            link_ids.insert(node.link_id.as_str());
+            for name in &node.dns_names {
+                dns_nodes.insert(name.as_str(), node.link_id.as_str());
+            }
+        }
+        let proc_node_revs = nodes
+            .iter()
+            .flat_map(|node| {
+                node.raw_ids
+                    .iter()
+                    .map(|raw_id| (raw_id.as_str(), node.link_id.as_str()))
+            })
+            .collect::<HashMap<_, _>>();
+        if !nodes.is_empty() {
+            stage
+                .sadd(format!("{prefix};{PROC_NODES_KEY}"), &link_ids)
+                .ignore();
+            stage
+                .hset_multiple(
+                    format!("{prefix};{DNS_NODES_KEY}"),
+                    &dns_nodes.iter().collect::<Vec<_>>(),
+                )
+                .ignore();
+            stage
+                .hset_multiple(
+                    format!("{prefix};{PROC_NODE_REVS_KEY}"),
+                    &proc_node_revs.iter().collect::<Vec<_>>(),
+                )
+                .ignore();
+        }
+        if let Err(err) = stage.query_async::<()>(self).await {
+            return redis_err!(format!("Failed to write results of processing: {err}"));
+        }
+
+        // Processing holds the update lock, so nothing else changes the live nodes meanwhile.
+        let live: HashSet<String> = match self.smembers(PROC_NODES_KEY).await {
+            Ok(live) => live,
+            Err(err) => return redis_err!(format!("Failed to get processed node IDs: {err}")),
+        };
+        let live_names: Vec<String> = match self.hkeys(DNS_NODES_KEY).await {
+            Ok(names) => names,
+            Err(err) => return redis_err!(format!("Failed to get resolved DNS names: {err}")),
+        };
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for link_id in live.iter().filter(|id| !link_ids.contains(id.as_str())) {
+            pipe.cmd("FCALL")
+                .arg(DELETE_PROC_NODE_FN)
+                .arg(1)
+                .arg(link_id)
+                .ignore();
+        }
+        for name in live_names
+            .iter()
+            .filter(|name| !dns_nodes.contains_key(name.as_str()))
+        {
+            pipe.hdel(
+                format!("{METADATA_KEY};{DNS_KEY};{name}"),
+                &[NODE_META_KEY, NODE_ID_META_KEY],
+            )
+            .ignore();
+        }
+
+        for node in nodes {
+            swap_commands(&mut pipe, &prefix, node);
+        }
+        for key in [PROC_NODES_KEY, DNS_NODES_KEY, PROC_NODE_REVS_KEY] {
+            if nodes.is_empty() {
+                pipe.del(key).ignore();
+            } else {
+                pipe.rename(format!("{prefix};{key}"), key).ignore();
+            }
+        }
+
+        for (qname, link_id) in resolved {
+            let node_link = format!("(!(procnode|!|{link_id})!)");
+            pipe.cmd("FCALL")
+                .arg(DNS_METADATA_FN)
+                .arg(1)
+                .arg(qname)
+                .arg(NETDOX_PLUGIN)
+                .arg(&[
+                    (NODE_META_KEY, node_link.as_str()),
+                    (NODE_ID_META_KEY, link_id),
+                ])
+                .ignore();
+        }
+        pipe.set(PROC_VERSION_KEY, version).ignore();
+
+        match pipe.query_async::<()>(self).await {
+            Ok(()) => Ok(()),
+            Err(err) => redis_err!(format!("Failed to swap in results of processing: {err}")),
+        }
+    }
+
+    async fn end_process(&mut self) -> NetdoxResult<()> {
+        match self.del::<_, ()>(PROCESS_RUN_KEY).await {
+            Ok(()) => Ok(()),
+            Err(err) => redis_err!(format!("Failed to record end of processing: {err}")),
+        }
+    }
+
+    async fn get_interrupted_process(&mut self) -> NetdoxResult<Option<ProcessRun>> {
+        let value: Option<String> = match self.get(PROCESS_RUN_KEY).await {
+            Ok(value) => value,
+            Err(err) => return redis_err!(format!("Failed to get process run: {err}")),
+        };
+
+        match value.map(|value| toml::from_str(&value)).transpose() {
+            Ok(run) => Ok(run),
+            Err(err) => redis_err!(format!("Failed to parse process run: {err}")),
+        }
+    }
+
+    async fn clear_process(&mut self, run: &ProcessRun) -> NetdoxResult<()> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+
+        for link_id in &run.nodes {
+            let key = format!("{PROC_NODES_KEY};{link_id}");
+            let dns_names: Vec<String> = match self.smembers(format!("{key};dns_names")).await {
+                Ok(names) => names,
+                Err(err) => {
+                    return redis_err!(format!("Failed to get dns names of node {link_id}: {err}"))
+                }
+            };
+            let raw_ids: Vec<String> = match self.smembers(format!("{key};raw_ids")).await {
+                Ok(ids) => ids,
+                Err(err) => {
+                    return redis_err!(format!("Failed to get raw ids of node {link_id}: {err}"))
+                }
+            };
+
+            pipe.srem(PROC_NODES_KEY, link_id).ignore();
+            for suffix in ["", ";alt_names", ";dns_names", ";plugins", ";raw_ids"] {
+                pipe.del(format!("{key}{suffix}")).ignore();
+            }
+            if !dns_names.is_empty() {
+                pipe.hdel(DNS_NODES_KEY, dns_names).ignore();
+            }
+            if !raw_ids.is_empty() {
+                pipe.hdel(PROC_NODE_REVS_KEY, raw_ids).ignore();
+            }
+        }
+
+        for qname in &run.dns_names {
+            pipe.hdel(
+                format!("{METADATA_KEY};{DNS_KEY};{qname}"),
+                &[NODE_META_KEY, NODE_ID_META_KEY],
+            )
+            .ignore();
+        }
+
+        pipe.del(PROCESS_RUN_KEY).ignore();
+
+        match pipe.query_async::<()>(self).await {
+            Ok(()) => Ok(()),
+            Err(err) => redis_err!(format!("Failed to clear interrupted processing: {err}")),
+        }
+    }
+
+    // Publish Queue
+
+    async fn put_queued_updates(&mut self, updates: &[QueuedUpdate]) -> NetdoxResult<()> {
+        if updates.is_empty() {
+            return Ok(());
+        }
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for update in updates {
+            let value = match toml::to_string(update) {
+                Ok(value) => value,
+                Err(err) => return redis_err!(format!("Failed to serialize queued update: {err}")),
+            };
+            pipe.hset(PUBLISH_QUEUE_KEY, update.key(), value).ignore();
+        }
+
+        debug!("Queueing {} fragment updates.", updates.len());
+        match pipe.query_async::<()>(self).await {
+            Ok(()) => Ok(()),
+            Err(err) => redis_err!(format!("Failed to queue fragment updates: {err}")),
+        }
+    }
+
+    async fn get_queued_updates(&mut self) -> NetdoxResult<Vec<QueuedUpdate>> {
+        let values: HashMap<String, String> = match self.hgetall(PUBLISH_QUEUE_KEY).await {
+            Ok(values) => values,
+            Err(err) => return redis_err!(format!("Failed to get queued fragment updates: {err}")),
+        };
+
+        let mut updates = Vec::with_capacity(values.len());
+        for (key, value) in values {
+            match toml::from_str(&value) {
+                Ok(update) => updates.push(update),
+                Err(err) => {
+                    return redis_err!(format!("Failed to parse queued update for {key}: {err}"))
+                }
+            }
+        }
+
+        Ok(updates)
+    }
+
+    async fn remove_queued_update(&mut self, update: &QueuedUpdate) -> NetdoxResult<()> {
+        let key = update.key();
+        let current: Option<String> = match self.hget(PUBLISH_QUEUE_KEY, &key).await {
+            Ok(current) => current,
+            Err(err) => return redis_err!(format!("Failed to get queued update for {key}: {err}")),
+        };
+
+        // Only remove the entry if it has not been replaced since it was read.
+        match current.map(|value| toml::from_str::<QueuedUpdate>(&value)) {
+            Some(Ok(queued)) if queued.hash == update.hash => {
+                match self.hdel::<_, _, ()>(PUBLISH_QUEUE_KEY, &key).await {
+                    Ok(()) => Ok(()),
+                    Err(err) => {
+                        redis_err!(format!("Failed to remove queued update for {key}: {err}"))
+                    }
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn get_publish_checkpoint(&mut self, remote: &str) -> NetdoxResult<Option<String>> {
+        match self.hget(PUBLISH_CHECKPOINT_KEY, remote).await {
+            Ok(checkpoint) => Ok(checkpoint),
+            Err(err) => redis_err!(format!(
+                "Failed to get publish checkpoint for {remote}: {err}"
+            )),
+        }
+    }
+
+    async fn put_publish_checkpoint(&mut self, remote: &str, change_id: &str) -> NetdoxResult<()> {
+        match self
+            .hset::<_, _, _, ()>(PUBLISH_CHECKPOINT_KEY, remote, change_id)
+            .await
+        {
+            Ok(()) => Ok(()),
+            Err(err) => redis_err!(format!(
+                "Failed to set publish checkpoint for {remote}: {err}"
+            )),
+        }
+    }
+
+    async fn clear_publish_checkpoint(&mut self, remote: &str) -> NetdoxResult<()> {
+        match self.hdel::<_, _, ()>(PUBLISH_CHECKPOINT_KEY, remote).await {
+            Ok(()) => Ok(()),
+            Err(err) => redis_err!(format!(
+                "Failed to clear publish checkpoint for {remote}: {err}"
+            )),
+        }
+    }
+
+    async fn get_publish_cursors(&mut self) -> NetdoxResult<HashMap<String, String>> {
+        match self.hgetall(PUBLISH_CURSORS_KEY).await {
+            Ok(cursors) => Ok(cursors),
+            Err(err) => redis_err!(format!("Failed to get publish cursors: {err}")),
+        }
+    }
+
+    async fn put_publish_cursor(&mut self, remote: &str, change_id: &str) -> NetdoxResult<()> {
+        match self
+            .hset::<_, _, _, ()>(PUBLISH_CURSORS_KEY, remote, change_id)
+            .await
+        {
+            Ok(()) => Ok(()),
+            Err(err) => redis_err!(format!("Failed to set publish cursor for {remote}: {err}")),
+        }
+    }
+
+    async fn get_remote_cursor(&mut self, remote: &str) -> NetdoxResult<Option<String>> {
+        match self.hget(REMOTE_CURSORS_KEY, remote).await {
+            Ok(cursor) => Ok(cursor),
+            Err(err) => redis_err!(format!(
+                "Failed to get changelog cursor of remote {remote}: {err}"
+            )),
+        }
+    }
+
+    async fn swap_remote_cursor(
+        &mut self,
+        remote: &str,
+        expected: Option<&str>,
+        change_id: &str,
+    ) -> NetdoxResult<bool> {
+        match Script::new(SWAP_CURSOR_SCRIPT)
+            .key(REMOTE_CURSORS_KEY)
+            .arg(remote)
+            .arg(expected.unwrap_or_default())
+            .arg(change_id)
+            .invoke_async::<bool>(self)
+            .await
+        {
+            Ok(swapped) => Ok(swapped),
+            Err(err) => redis_err!(format!(
+                "Failed to set changelog cursor of remote {remote}: {err}"
+            )),
+        }
+    }
+
+    async fn put_superseded_fragments(
+        &mut self,
+        obj_id: &str,
+        ids: &[(String, String)],
+    ) -> NetdoxResult<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let regenerated_key = format!("{FRAGMENT_IDS_KEY};regenerated");
+        match self.sismember::<_, _, bool>(&regenerated_key, obj_id).await {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(err) => {
+                return redis_err!(format!(
+                    "Failed to check if document of {obj_id} was regenerated: {err}"
+                ))
+            }
+        }
+
+        match redis::pipe()
+            .atomic()
+            .sadd(FRAGMENT_IDS_KEY, obj_id)
+            .ignore()
+            .hset_multiple(format!("{FRAGMENT_IDS_KEY};{obj_id}"), ids)
+            .ignore()
+            .query_async::<()>(self)
+            .await
+        {
+            Ok(()) => Ok(()),
+            Err(err) => redis_err!(format!(
+                "Failed to record superseded fragments of {obj_id}: {err}"
+            )),
+        }
+    }
+
+    async fn get_superseded_fragments(
+        &mut self,
+    ) -> NetdoxResult<HashMap<String, HashMap<String, String>>> {
+        let obj_ids: HashSet<String> = match self.smembers(FRAGMENT_IDS_KEY).await {
+            Ok(obj_ids) => obj_ids,
+            Err(err) => {
+                return redis_err!(format!(
+                    "Failed to get objects with superseded fragments: {err}"
+                ))
+            }
+        };
+
+        let mut superseded = HashMap::with_capacity(obj_ids.len());
+        for obj_id in obj_ids {
+            match self.hgetall(format!("{FRAGMENT_IDS_KEY};{obj_id}")).await {
+                Ok(ids) => {
+                    superseded.insert(obj_id, ids);
+                }
+                Err(err) => {
+                    return redis_err!(format!(
+                        "Failed to get superseded fragments of {obj_id}: {err}"
+                    ))
+                }
+            }
+        }
+
+        Ok(superseded)
+    }
+
+    async fn clear_superseded_fragments(&mut self, obj_ids: &[String]) -> NetdoxResult<()> {
+        if obj_ids.is_empty() {
+            return Ok(());
+        }
+
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .srem(FRAGMENT_IDS_KEY, obj_ids)
+            .ignore()
+            .sadd(format!("{FRAGMENT_IDS_KEY};regenerated"), obj_ids)
+            .ignore();
+        for obj_id in obj_ids {
+            pipe.del(format!("{FRAGMENT_IDS_KEY};{obj_id}")).ignore();
+        }
+
+        match pipe.query_async::<()>(self).await {
+            Ok(()) => Ok(()),
+            Err(err) => redis_err!(format!("Failed to clear superseded fragments: {err}")),
+        }
+    }
+
+    // Backup
+
+    async fn dump(&mut self) -> NetdoxResult<DataDump> {
+        let mut keys = vec![];
+        let mut cursor = 0;
+        loop {
+            let (next, batch) = match cmd("SCAN")
+                .arg(cursor)
+                .arg("COUNT")
+                .arg(SCAN_COUNT)
+                .query_async::<(u64, Vec<String>)>(self)
+                .await
+            {
+                Ok(page) => page,
+                Err(err) => return redis_err!(format!("Failed to scan keys: {err}")),
+            };
+            keys.extend(batch);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+
+        let mut dump = DataDump::default();
+        for key in keys {
+            // The lock belongs to the run that held it, not to the data.
+            if key == CHANGELOG_KEY || key == RUN_LOCK_KEY {
+                continue;
+            }
+
+            let kind = cmd("TYPE").arg(&key).query_async::<String>(self).await?;
+            let value = match kind.as_str() {
+                "string" => StoredValue::String(self.get(&key).await?),
+                "set" => StoredValue::Set(self.smembers(&key).await?),
+                "hash" => StoredValue::Hash(self.hgetall(&key).await?),
+                "list" => StoredValue::List(self.lrange(&key, 0, -1).await?),
+                // Keys can be removed between being scanned and read.
+                "none" => continue,
+                _ => return redis_err!(format!("Cannot back up key {key} of type {kind}.")),
+            };
+            dump.keys.insert(key, value);
+        }
+
+        dump.changelog = match self
+            .xrange_all::<_, Vec<(String, BTreeMap<String, String>)>>(CHANGELOG_KEY)
+            .await
+        {
+            Ok(entries) => entries
+                .into_iter()
+                .map(|(id, fields)| StoredChange { id, fields })
+                .collect(),
+            Err(err) => return redis_err!(format!("Failed to read changelog: {err}")),
+        };
+
+        debug!(
+            "Dumped {} keys and {} changes.",
+            dump.keys.len(),
+            dump.changelog.len()
+        );
+        Ok(dump)
+    }
+
+    async fn restore(&mut self, dump: &DataDump) -> NetdoxResult<()> {
+        let size = cmd("DBSIZE").query_async::<usize>(self).await?;
+        if size > 0 {
+            return redis_err!(format!(
+                "Refusing to restore a backup into a database that contains {size} keys."
+            ));
+        }
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (key, value) in &dump.keys {
+            match value {
+                StoredValue::String(value) => pipe.set(key, value).ignore(),
+                StoredValue::Set(members) => pipe.sadd(key, members).ignore(),
+                StoredValue::Hash(fields) => pipe
+                    .hset_multiple(key, &fields.iter().collect::<Vec<_>>())
+                    .ignore(),
+                StoredValue::List(values) => pipe.rpush(key, values).ignore(),
+            };
+        }
+        for change in &dump.changelog {
+            pipe.xadd(
+                CHANGELOG_KEY,
+                &change.id,
+                &change.fields.iter().collect::<Vec<_>>(),
+            )
+            .ignore();
+        }
+
+        match pipe.query_async::<()>(self).await {
+            Ok(()) => Ok(()),
+            Err(err) => redis_err!(format!("Failed to restore backup: {err}")),
+        }
+    }
+
+    // Persistence
+
+    async fn write_save(&mut self, strategy: SaveStrategy, timeout: Duration) -> NetdoxResult<()> {
+        if strategy == SaveStrategy::Skip {
+            return Ok(());
+        }
+
+        // Replicas are persisted by their own config, and a save started here
+        // would not contain writes that have not been replicated yet.
+        let replication = cmd("INFO")
+            .arg("replication")
+            .query_async::<String>(self)
+            .await?;
+        if info_field(&replication, "role") == Some("slave") {
+            warn!("Not saving because the redis instance is a replica.");
+            return Ok(());
+        }
+
+        match strategy {
+            SaveStrategy::Skip => Ok(()),
+            SaveStrategy::Save => Ok(cmd("SAVE").query_async::<()>(self).await?),
+            SaveStrategy::Aof => {
+                let persistence = cmd("INFO")
+                    .arg("persistence")
+                    .query_async::<String>(self)
+                    .await?;
+                if info_field(&persistence, "aof_enabled") == Some("1") {
+                    Ok(())
+                } else {
+                    redis_err!(
+                        "Save strategy is aof but the append only file is not enabled.".to_string()
+                    )
+                }
+            }
+            SaveStrategy::Bgsave => {
+                let before = cmd("INFO")
+                    .arg("persistence")
+                    .query_async::<String>(self)
+                    .await?;
+                // Scheduling waits for any save already in progress,
+                // which may have started before the latest writes.
+                cmd("BGSAVE")
+                    .arg("SCHEDULE")
+                    .query_async::<()>(self)
+                    .await?;
+                debug!("Started background save.");
+
+                let start = Instant::now();
+                loop {
+                    sleep(SAVE_POLL_INTERVAL).await;
+                    let persistence = cmd("INFO")
+                        .arg("persistence")
+                        .query_async::<String>(self)
+                        .await?;
+                    let finished = info_field(&persistence, "rdb_bgsave_in_progress") == Some("0")
+                        && save_finished(&before, &persistence);
+
+                    if finished {
+                        return match info_field(&persistence, "rdb_last_bgsave_status") {
+                            Some("ok") => Ok(()),
+                            status => redis_err!(format!(
+                                "Background save failed with status: {}",
+                                status.unwrap_or("unknown")
+                            )),
+                        };
+                    }
+
+                    if start.elapsed() > timeout {
+                        return redis_err!(format!(
+                            "Background save did not finish within {}s.",
+                            timeout.as_secs()
+                        ));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Checks that a redis server can load the netdox functions and allows the commands netdox uses,
+/// so that an unsuitable server is reported when connecting instead of on the first write.
+pub async fn check_server(con: &mut RedisConnection) -> NetdoxResult<()> {
+    let server = match cmd("INFO").arg("server").query_async::<String>(con).await {
+        Ok(server) => server,
+        Err(err) => return redis_err!(format!("Failed to get redis server info: {err}")),
+    };
+    check_version(&server)?;
+    check_mode(&server, matches!(con, RedisConnection::Cluster(_)))?;
+
+    let info = match cmd("COMMAND")
+        .arg("INFO")
+        .arg(&REQUIRED_COMMANDS[..])
+        .query_async::<Vec<Value>>(con)
+        .await
+    {
+        Ok(info) => info,
+        Err(err) => return redis_err!(format!("Failed to get redis command info: {err}")),
+    };
+
+    let missing = REQUIRED_COMMANDS
+        .iter()
+        .zip(&info)
+        .filter(|(_, info)| matches!(info, Value::Nil))
+        .map(|(command, _)| *command)
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return redis_err!(format!(
+            "Redis server does not have the commands {}, which netdox requires. \
+            Check that they are not disabled or renamed with rename-command in the redis config.",
+            missing.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Adds the commands that write a processed node under the prefix of a staged version
+/// of the processed layer to a pipeline.
+fn node_commands(pipe: &mut redis::Pipeline, prefix: &str, node: &Node) -> NetdoxResult<()> {
+    debug!(
+        "Writing processed node to key: {prefix};{PROC_NODES_KEY};{}",
+        node.link_id
+    );
+    if node.dns_names.is_empty() {
+        return redis_err!(format!(
+            "Cannot write node {} with no dns names.",
+            node.name
+        ));
+    } else if node.plugins.is_empty() {
+        return redis_err!(format!(
+            "Cannot write node {} with no source plugins",
+            node.name
+        ));
+    } else if node.raw_ids.is_empty() {
+        return redis_err!(format!(
+            "Cannot write node {} with no source raw ids",
+            node.name
+        ));
+    }
+
+    let key = format!("{prefix};{PROC_NODES_KEY};{}", node.link_id);
+    pipe.del(&key).ignore();
+    pipe.set(&key, &node.name).ignore();
+
+    let alt_names = node.normalised_alt_names();
+    pipe.del(format!("{key};alt_names")).ignore();
+    if !alt_names.is_empty() {
+        pipe.sadd(format!("{key};alt_names"), &alt_names).ignore();
+    }
+
+    for (suffix, members) in [
+        (";dns_names", &node.dns_names),
+        (";plugins", &node.plugins),
+        (";raw_ids", &node.raw_ids),
+    ] {
+        pipe.del(format!("{key}{suffix}")).ignore();
+        pipe.sadd(format!("{key}{suffix}"), members).ignore();
+    }
+
+    Ok(())
+}
+
+/// Adds the commands that swap a staged processed node in for the live one to a pipeline.
+/// Every key of the node replaces the one from earlier runs.
+fn swap_commands(pipe: &mut redis::Pipeline, prefix: &str, node: &Node) {
+    let key = format!("{PROC_NODES_KEY};{}", node.link_id);
+    let staged = format!("{prefix};{key}");
+    pipe.rename(&staged, &key).ignore();
+
+    if node.normalised_alt_names().is_empty() {
+        pipe.del(format!("{key};alt_names")).ignore();
+    } else {
+        pipe.rename(format!("{staged};alt_names"), format!("{key};alt_names"))
+            .ignore();
+    }
+
+    for suffix in [";dns_names", ";plugins", ";raw_ids"] {
+        pipe.rename(format!("{staged}{suffix}"), format!("{key}{suffix}"))
+            .ignore();
+    }
+}
+
+/// Removes every key of a staged version of the processed layer,
+/// which is left behind if processing stops before swapping it in.
+async fn clear_staged(con: &mut RedisConnection) -> NetdoxResult<()> {
+    let pattern = format!("{PROC_STAGING_KEY};*");
+    let mut cursor = 0;
+    loop {
+        let (next, keys) = match cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(&pattern)
+            .arg("COUNT")
+            .arg(SCAN_COUNT)
+            .query_async::<(u64, Vec<String>)>(con)
+            .await
+        {
+            Ok(page) => page,
+            Err(err) => return redis_err!(format!("Failed to scan staged processed data: {err}")),
+        };
+        if !keys.is_empty() {
+            if let Err(err) = con.del::<_, ()>(keys).await {
+                return redis_err!(format!("Failed to remove staged processed data: {err}"));
+            }
+        }
+        if next == 0 {
+            return Ok(());
+        }
+        cursor = next;
+    }
+}
+
+/// Checks that the version in the output of INFO server supports functions.
+fn check_version(server: &str) -> NetdoxResult<()> {
+    let version = info_field(server, "redis_version").unwrap_or("unknown");
+    let parsed = version
+        .split('.')
+        .take(2)
+        .map(|part| part.parse::<u32>().ok())
+        .collect::<Option<Vec<_>>>();
+
+    match parsed.as_deref() {
+        Some([major, minor]) if (*major, *minor) >= MIN_REDIS_VERSION => Ok(()),
+        _ => redis_err!(format!(
+            "Redis server has version {version}, but netdox loads its Lua functions with FUNCTION, \
+            which requires redis {}.{} or later. Upgrade the redis server to use it as a data store.",
+            MIN_REDIS_VERSION.0, MIN_REDIS_VERSION.1
+        )),
+    }
+}
+
+/// Checks that the output of INFO server is from a redis cluster only if netdox connected to it as one,
+/// and is not from a sentinel.
+fn check_mode(server: &str, cluster: bool) -> NetdoxResult<()> {
+    match info_field(server, "redis_mode") {
+        Some("cluster") if !cluster => redis_err!(
+            "Redis server is in cluster mode. Configure its nodes under redis.cluster \
+            to connect to it as a cluster instead."
+                .to_string()
+        ),
+        Some("sentinel") => redis_err!(
+            "Redis server is a sentinel. Configure it under redis.sentinel to connect \
+            to the master it monitors instead."
+                .to_string()
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that an additional Lua library cannot replace netdox functions, and returns its name.
+/// The library must not be named like the netdox library,
+/// and none of the functions it registers may use the netdox prefix.
+fn check_lua_library(library: &str) -> NetdoxResult<String> {
+    let name = match library
+        .lines()
+        .next()
+        .and_then(|line| line.trim().strip_prefix("#!lua"))
+        .and_then(|args| {
+            args.split_whitespace()
+                .find_map(|arg| arg.strip_prefix("name="))
+        }) {
+        Some(name) if !name.is_empty() => name,
+        _ => {
+            return config_err!(
+                "Lua library must start with a shebang like: #!lua name=<library>".to_string()
+            )
+        }
+    };
+
+    if name == LUA_LIBRARY {
+        return config_err!(format!(
+            "Lua library cannot be named {LUA_LIBRARY}, as it would replace the netdox functions."
+        ));
+    }
+
+    for function in registered_functions(library) {
+        if function.starts_with(LUA_FUNCTION_PREFIX) {
+            return config_err!(format!(
+                "Function {function} in Lua library {name} cannot start with {LUA_FUNCTION_PREFIX}, \
+                which is reserved for netdox functions."
+            ));
+        }
+    }
+
+    Ok(name.to_string())
+}
+
+/// Returns the names of the functions registered by a Lua library.
+/// Supports both `register_function('name', callback)` and `register_function{function_name = 'name', ...}`.
+fn registered_functions(library: &str) -> Vec<String> {
+    library
+        .split("register_function")
+        .skip(1)
+        .filter_map(|call| {
+            let call = call.trim_start().trim_start_matches('(').trim_start();
+            let args = if call.starts_with('{') {
+                &call[call.find("function_name")?..]
+            } else {
+                call
+            };
+
+            let start = args.find(['\'', '"'])?;
+            let quote = &args[start..=start];
+            let rest = &args[start + 1..];
+            Some(rest[..rest.find(quote)?].to_string())
+        })
+        .collect()
+}
+
+/// Returns true if a save finished between two outputs of INFO persistence.
+/// The save counter is only available since redis 7,
+/// so older versions compare the time of the last save instead.
+fn save_finished(before: &str, after: &str) -> bool {
+    let field = |info, field| info_field(info, field).and_then(|val| val.parse::<u64>().ok());
+    match (field(before, "rdb_saves"), field(after, "rdb_saves")) {
+        (Some(before), Some(after)) => after > before,
+        _ => match (
+            field(before, "rdb_last_save_time"),
+            field(after, "rdb_last_save_time"),
+        ) {
+            (Some(before), Some(after)) => after > before,
+            _ => false,
+        },
+    }
+}
+
+/// Returns the value of a field in the output of the INFO command.
+fn info_field<'a>(info: &'a str, field: &str) -> Option<&'a str> {
+    info.lines()
+        .find_map(|line| match line.trim().split_once(':') {
+            Some((key, value)) if key == field => Some(value),
+            _ => None,
+        })
+}
+
+/// Gets the DNS records with the given name.
+async fn get_dns_records(con: &mut RedisConnection, qname: &str) -> NetdoxResult<Vec<DNSRecord>> {
+    con.smembers::<_, Vec<String>>(format!("{DNS_KEY};{qname}"))
+        .await?
+        .iter()
+        .map(|record| parse_dns_record(qname, record))
+        .collect()
+}
+
+/// Parses a member of the set of DNS records of a name, like `plugin;rtype;value`.
+fn parse_dns_record(qname: &str, record: &str) -> NetdoxResult<DNSRecord> {
+    let mut rsplit = record.splitn(3, ';');
+    let plugin = match rsplit.next() {
+        Some(val) => val.to_string(),
+        None => return redis_err!(format!("Invalid DNS record (no plugin) on qname {qname}")),
+    };
+
+    let rtype = match rsplit.next() {
+        Some(val) => val.to_string(),
+        None => return redis_err!(format!("Invalid DNS record (no rtype) on qname {qname}")),
+    };
+
+    let value = match rsplit.next() {
+        Some(val) => val.to_string(),
+        None => return redis_err!(format!("Invalid DNS record (no value) on qname {qname}")),
+    };
+
+    Ok(DNSRecord {
+        name: qname.to_string(),
+        value,
+        rtype,
+        plugin,
+    })
+}
+
+/// Reads a batch of about `count` members of a set, starting from a cursor.
+/// Returns the cursor to continue from, which is 0 once every member has been read.
+/// Members may be returned more than once if the set is written to during the scan.
+async fn sscan(
+    con: &mut RedisConnection,
+    key: &str,
+    cursor: u64,
+    count: usize,
+) -> NetdoxResult<(u64, Vec<String>)> {
+    match cmd("SSCAN")
+        .arg(key)
+        .arg(cursor)
+        .arg("COUNT")
+        .arg(count)
+        .query_async(con)
+        .await
+    {
+        Ok(batch) => Ok(batch),
+        Err(err) => redis_err!(format!("Failed to scan set at {key}: {err}")),
+    }
+}
+
+/// Builds a raw node from the key it is stored at and its details.
+fn raw_node_from_details(key: &str, mut details: HashMap<String, String>) -> NetdoxResult<RawNode> {
+    let mut components = key.rsplit(';');
+    let dns_names = match (
+        components.next(), // last component, index
+        components,
+    ) {
+        (Some(_), remainder) => remainder
+            .into_iter()
+            .rev()
+            .skip(1)
+            .map(std::string::ToString::to_string)
+            .collect::<HashSet<String>>(),
+        _ => return redis_err!(format!("Invalid node redis key: {key}")),
+    };
+
+    let plugin = match details.get("plugin") {
+        Some(plugin) => plugin.to_owned(),
+        None => return redis_err!(format!("Node details at key {key} missing plugin field.")),
+    };
+
+    let name = details.get("name").cloned();
+
+    let exclusive = match details.get("exclusive") {
+        Some(val) => match val.as_str().parse::<bool>() {
+            Ok(_val) => _val,
+            Err(_) => {
+                return redis_err!(format!(
+                    "Unable to parse boolean from exclusive value at {key}: {val}"
+                ))
+            }
+        },
+        None => {
+            return redis_err!(format!(
+                "Node details at key {key} missing exclusive field."
+            ))
+        }
+    };
+
+    Ok(RawNode {
+        name,
+        exclusive,
+        link_id: details.remove("link_id"),
+        dns_names,
+        plugin,
+    })
+}
+
+/// Gets the metadata for a node merged with the conflict policy, and the conflicts between its raw nodes.
+async fn get_node_metadata_merged(
+    con: &mut RedisConnection,
+    node: &Node,
+) -> NetdoxResult<(HashMap<String, MetadataProvenance>, Vec<Conflict>)> {
+    let policy = con.get_conflict_policy().await?;
+    let mut raw = vec![];
+    for raw_id in &node.raw_ids {
+        raw.push((
+            raw_id.clone(),
+            get_metadata_provenance(con, &format!("{METADATA_KEY};{NODES_KEY};{raw_id}")).await?,
+        ));
+    }
+    let proc = get_metadata_provenance(
+        con,
+        &format!("{METADATA_KEY};{PROC_NODES_KEY};{}", node.link_id),
+    )
+    .await?;
+
+    Ok(merge_metadata(node, raw, proc, &policy))
+}
+
+/// Gets the metadata stored at a key, with the plugin that last changed each value and when.
+async fn get_metadata_provenance(
+    con: &mut RedisConnection,
+    meta_key: &str,
+) -> NetdoxResult<HashMap<String, MetadataProvenance>> {
+    let (metadata, sources, changed): (
+        HashMap<String, String>,
+        HashMap<String, String>,
+        HashMap<String, String>,
+    ) = match redis::pipe()
+        .hgetall(meta_key)
+        .hgetall(format!("{meta_key};sources"))
+        .hgetall(format!("{meta_key};changed"))
+        .query_async(con)
+        .await
+    {
+        Ok(hashes) => hashes,
+        Err(err) => {
+            return redis_err!(format!(
+                "Failed to get metadata provenance at {meta_key}: {err}"
+            ))
+        }
+    };
+
+    Ok(metadata_provenance(metadata, &sources, &changed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_lua_library, check_mode, check_version, info_field, registered_functions,
+        save_finished,
+    };
+
+    #[test]
+    fn test_info_field() {
+        let info = "# Persistence\r\nloading:0\r\nrdb_bgsave_in_progress:1\r\naof_enabled:0\r\n";
+        assert_eq!(info_field(info, "rdb_bgsave_in_progress"), Some("1"));
+        assert_eq!(info_field(info, "aof_enabled"), Some("0"));
+        assert_eq!(info_field(info, "role"), None);
+    }
+
+    #[test]
+    fn test_check_version() {
+        assert!(check_version("# Server\r\nredis_version:7.2.4\r\n").is_ok());
+        assert!(check_version("redis_version:10.0.0\r\n").is_ok());
+        assert!(check_version("redis_version:6.2.14\r\n").is_err());
+        assert!(check_version("redis_mode:standalone\r\n").is_err());
+    }
+
+    #[test]
+    fn test_check_mode() {
+        assert!(check_mode("redis_version:7.2.4\r\nredis_mode:standalone\r\n", false).is_ok());
+        assert!(check_mode("redis_version:7.2.4\r\n", false).is_ok());
+        assert!(check_mode("redis_mode:cluster\r\n", false).is_err());
+        assert!(check_mode("redis_mode:cluster\r\n", true).is_ok());
+        assert!(check_mode("redis_mode:sentinel\r\n", false).is_err());
+    }
+
+    #[test]
+    fn test_save_finished() {
+        let before = "rdb_saves:3\r\nrdb_last_save_time:1000\r\n";
+        assert!(!save_finished(before, before));
+        assert!(save_finished(
+            before,
+            "rdb_saves:4\r\nrdb_last_save_time:1000\r\n"
+        ));
+        assert!(save_finished(
+            "rdb_last_save_time:1000\r\n",
+            "rdb_last_save_time:1001\r\n"
+        ));
+    }
+
+    #[test]
+    fn test_registered_functions() {
+        let library = "#!lua name=site\n\
+            redis.register_function('site_one', one)\n\
+            redis.register_function{function_name=\"site_two\", callback=two}\n\
+            redis.register_function({\n    callback = three,\n    function_name = 'site_three',\n})\n";
+        assert_eq!(
+            registered_functions(library),
+            vec!["site_one", "site_two", "site_three"]
+        );
+        assert!(registered_functions(super::LUA_FUNCTIONS)
+            .iter()
+            .all(|function| function.starts_with(super::LUA_FUNCTION_PREFIX)));
+    }
 
-    async fn write_save(&mut self) -> NetdoxResult<()> {
-        Ok(redis::cmd("BGSAVE").query_async::<()>(self).await?)
+    #[test]
+    fn test_check_lua_library() {
+        assert_eq!(
+            check_lua_library("#!lua name=site\nredis.register_function('site_fn', f)").unwrap(),
+            "site"
+        );
+        assert!(check_lua_library(super::LUA_FUNCTIONS).is_err());
+        assert!(check_lua_library("redis.register_function('site_fn', f)").is_err());
+        assert!(
+            check_lua_library("#!lua name=site\nredis.register_function('netdox_x', f)").is_err()
+        );
     }
 }
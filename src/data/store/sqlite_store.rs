@@ -0,0 +1,477 @@
+use crate::{
+    data::model::StoredValue,
+    error::{NetdoxError, NetdoxResult},
+    sqlite_err,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use super::keyspace::{Backend, ChangeField, Keyspace, KeyspaceStore};
+
+/// Tables for each type of value, so that keys are laid out the same as in redis.
+/// Each entry in the changelog is stored as one row per field.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS strings (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS sets (
+    key TEXT NOT NULL,
+    member TEXT NOT NULL,
+    PRIMARY KEY (key, member)
+);
+CREATE TABLE IF NOT EXISTS hashes (
+    key TEXT NOT NULL,
+    field TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (key, field)
+);
+CREATE TABLE IF NOT EXISTS lists (
+    key TEXT NOT NULL,
+    idx INTEGER NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (key, idx)
+);
+CREATE TABLE IF NOT EXISTS changelog (
+    millis INTEGER NOT NULL,
+    seq INTEGER NOT NULL,
+    field TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (millis, seq, field)
+);
+";
+
+/// Tables that hold values in the keyspace.
+const VALUE_TABLES: [&str; 4] = ["strings", "sets", "hashes", "lists"];
+
+/// A data store in an SQLite database.
+pub type SqliteStore = KeyspaceStore<SqliteBackend>;
+
+impl SqliteStore {
+    /// Opens the database at the path, creating it if it does not exist.
+    pub fn open(path: &Path) -> NetdoxResult<Self> {
+        match Connection::open(path) {
+            Ok(con) => Self::from_connection(con),
+            Err(err) => sqlite_err!(format!(
+                "Failed to open SQLite database at {}: {err}",
+                path.to_string_lossy()
+            )),
+        }
+    }
+
+    /// Opens a database that only exists in memory.
+    #[cfg(test)]
+    pub fn open_in_memory() -> NetdoxResult<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(con: Connection) -> NetdoxResult<Self> {
+        if let Err(err) = con.execute_batch(SCHEMA) {
+            return sqlite_err!(format!("Failed to create SQLite tables: {err}"));
+        }
+
+        Ok(KeyspaceStore::with_backend(SqliteBackend {
+            con: Arc::new(Mutex::new(con)),
+        }))
+    }
+}
+
+/// Holds the keyspace of a data store in an SQLite database.
+#[derive(Clone)]
+pub struct SqliteBackend {
+    con: Arc<Mutex<Connection>>,
+}
+
+impl SqliteBackend {
+    fn lock(&self) -> NetdoxResult<MutexGuard<'_, Connection>> {
+        match self.con.lock() {
+            Ok(con) => Ok(con),
+            Err(_) => sqlite_err!("SQLite connection was poisoned by a panic.".to_string()),
+        }
+    }
+}
+
+impl Backend for SqliteBackend {
+    fn read<T>(&self, func: impl FnOnce(&dyn Keyspace) -> NetdoxResult<T>) -> NetdoxResult<T> {
+        func(&*self.lock()?)
+    }
+
+    /// Calls the function in a transaction, which is only committed if it succeeds.
+    fn write<T>(&self, func: impl FnOnce(&dyn Keyspace) -> NetdoxResult<T>) -> NetdoxResult<T> {
+        let mut con = self.lock()?;
+        let tx = con.transaction()?;
+        let result = func(&*tx)?;
+        tx.commit()?;
+        Ok(result)
+    }
+}
+
+impl Keyspace for Connection {
+    fn get(&self, key: &str) -> NetdoxResult<Option<String>> {
+        Ok(self
+            .query_row("SELECT value FROM strings WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+
+    fn set(&self, key: &str, value: &str) -> NetdoxResult<()> {
+        self.execute(
+            "INSERT INTO strings (key, value) VALUES (?1, ?2)
+            ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            [key, value],
+        )?;
+        Ok(())
+    }
+
+    fn del(&self, key: &str) -> NetdoxResult<()> {
+        for table in VALUE_TABLES {
+            self.execute(&format!("DELETE FROM {table} WHERE key = ?1"), [key])?;
+        }
+        Ok(())
+    }
+
+    fn keys(&self, prefix: &str) -> NetdoxResult<HashSet<String>> {
+        let mut keys = HashSet::new();
+        for table in VALUE_TABLES {
+            keys.extend(table_keys(self, table, prefix)?);
+        }
+        Ok(keys)
+    }
+
+    fn set_keys(&self, prefix: &str) -> NetdoxResult<HashSet<String>> {
+        table_keys(self, "sets", prefix)
+    }
+
+    fn smembers(&self, key: &str) -> NetdoxResult<HashSet<String>> {
+        let mut stmt = self.prepare("SELECT member FROM sets WHERE key = ?1")?;
+        let rows = stmt.query_map([key], |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn sscan(&self, key: &str, cursor: u64, count: usize) -> NetdoxResult<(u64, Vec<String>)> {
+        // The cursor is the rowid of the last member read.
+        let mut stmt = self.prepare(
+            "SELECT rowid, member FROM sets WHERE key = ?1 AND rowid > ?2 ORDER BY rowid LIMIT ?3",
+        )?;
+        let rows = stmt
+            .query_map(params![key, cursor as i64, count as i64], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let next = match rows.last() {
+            Some((rowid, _)) if rows.len() == count => *rowid as u64,
+            _ => 0,
+        };
+        Ok((next, rows.into_iter().map(|(_, member)| member).collect()))
+    }
+
+    fn sismember(&self, key: &str, member: &str) -> NetdoxResult<bool> {
+        Ok(self.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sets WHERE key = ?1 AND member = ?2)",
+            [key, member],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn scard(&self, key: &str) -> NetdoxResult<usize> {
+        let count: i64 =
+            self.query_row("SELECT COUNT(*) FROM sets WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })?;
+        Ok(usize::try_from(count).unwrap_or_default())
+    }
+
+    fn sadd(&self, key: &str, member: &str) -> NetdoxResult<bool> {
+        Ok(self.execute(
+            "INSERT OR IGNORE INTO sets (key, member) VALUES (?1, ?2)",
+            [key, member],
+        )? > 0)
+    }
+
+    fn srem(&self, key: &str, member: &str) -> NetdoxResult<bool> {
+        Ok(self.execute(
+            "DELETE FROM sets WHERE key = ?1 AND member = ?2",
+            [key, member],
+        )? > 0)
+    }
+
+    fn hgetall(&self, key: &str) -> NetdoxResult<HashMap<String, String>> {
+        let mut stmt = self.prepare("SELECT field, value FROM hashes WHERE key = ?1")?;
+        let rows = stmt.query_map([key], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn hget(&self, key: &str, field: &str) -> NetdoxResult<Option<String>> {
+        Ok(self
+            .query_row(
+                "SELECT value FROM hashes WHERE key = ?1 AND field = ?2",
+                [key, field],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn hset(&self, key: &str, field: &str, value: &str) -> NetdoxResult<()> {
+        self.execute(
+            "INSERT INTO hashes (key, field, value) VALUES (?1, ?2, ?3)
+            ON CONFLICT (key, field) DO UPDATE SET value = excluded.value",
+            [key, field, value],
+        )?;
+        Ok(())
+    }
+
+    fn hsetnx(&self, key: &str, field: &str, value: &str) -> NetdoxResult<()> {
+        self.execute(
+            "INSERT OR IGNORE INTO hashes (key, field, value) VALUES (?1, ?2, ?3)",
+            [key, field, value],
+        )?;
+        Ok(())
+    }
+
+    fn hdel(&self, key: &str, field: &str) -> NetdoxResult<()> {
+        self.execute(
+            "DELETE FROM hashes WHERE key = ?1 AND field = ?2",
+            [key, field],
+        )?;
+        Ok(())
+    }
+
+    fn lrange(&self, key: &str) -> NetdoxResult<Vec<String>> {
+        let mut stmt = self.prepare("SELECT value FROM lists WHERE key = ?1 ORDER BY idx")?;
+        let rows = stmt.query_map([key], |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn rpush(&self, key: &str, value: &str) -> NetdoxResult<()> {
+        self.execute(
+            "INSERT INTO lists (key, idx, value)
+            VALUES (?1, (SELECT COALESCE(MAX(idx) + 1, 0) FROM lists WHERE key = ?1), ?2)",
+            [key, value],
+        )?;
+        Ok(())
+    }
+
+    fn lpush(&self, key: &str, value: &str) -> NetdoxResult<()> {
+        self.execute(
+            "INSERT INTO lists (key, idx, value)
+            VALUES (?1, (SELECT COALESCE(MIN(idx) - 1, 0) FROM lists WHERE key = ?1), ?2)",
+            [key, value],
+        )?;
+        Ok(())
+    }
+
+    fn ltrim(&self, key: &str, len: usize) -> NetdoxResult<()> {
+        self.execute(
+            "DELETE FROM lists WHERE key = ?1 AND idx NOT IN
+            (SELECT idx FROM lists WHERE key = ?1 ORDER BY idx LIMIT ?2)",
+            params![key, i64::try_from(len).unwrap_or(i64::MAX)],
+        )?;
+        Ok(())
+    }
+
+    fn rename_key(&self, key: &str, new_key: &str) -> NetdoxResult<()> {
+        self.del(new_key)?;
+        for table in VALUE_TABLES {
+            self.execute(
+                &format!("UPDATE {table} SET key = ?2 WHERE key = ?1"),
+                [key, new_key],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn values(&self) -> NetdoxResult<BTreeMap<String, StoredValue>> {
+        let mut keys = BTreeMap::new();
+
+        let mut stmt = self.prepare("SELECT key, value FROM strings")?;
+        for row in stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))? {
+            let (key, value): (String, String) = row?;
+            keys.insert(key, StoredValue::String(value));
+        }
+
+        let mut stmt = self.prepare("SELECT key, member FROM sets")?;
+        for row in stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))? {
+            let (key, member): (String, String) = row?;
+            if let StoredValue::Set(members) = keys
+                .entry(key)
+                .or_insert_with(|| StoredValue::Set(BTreeSet::new()))
+            {
+                members.insert(member);
+            }
+        }
+
+        let mut stmt = self.prepare("SELECT key, field, value FROM hashes")?;
+        for row in stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))? {
+            let (key, field, value): (String, String, String) = row?;
+            if let StoredValue::Hash(fields) = keys
+                .entry(key)
+                .or_insert_with(|| StoredValue::Hash(BTreeMap::new()))
+            {
+                fields.insert(field, value);
+            }
+        }
+
+        let mut stmt = self.prepare("SELECT key, value FROM lists ORDER BY key, idx")?;
+        for row in stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))? {
+            let (key, value): (String, String) = row?;
+            if let StoredValue::List(values) =
+                keys.entry(key).or_insert_with(|| StoredValue::List(vec![]))
+            {
+                values.push(value);
+            }
+        }
+
+        Ok(keys)
+    }
+
+    fn clear(&self) -> NetdoxResult<()> {
+        for table in VALUE_TABLES {
+            self.execute(&format!("DELETE FROM {table}"), [])?;
+        }
+        Ok(())
+    }
+
+    fn add_change_field(&self, id: (i64, i64), field: &str, value: &str) -> NetdoxResult<()> {
+        self.execute(
+            "INSERT INTO changelog (millis, seq, field, value) VALUES (?1, ?2, ?3, ?4)",
+            params![id.0, id.1, field, value],
+        )?;
+        Ok(())
+    }
+
+    fn change_fields(&self, start: (i64, i64)) -> NetdoxResult<Vec<ChangeField>> {
+        let mut stmt = self.prepare(
+            "SELECT millis, seq, field, value FROM changelog
+            WHERE millis > ?1 OR (millis = ?1 AND seq > ?2)
+            ORDER BY millis, seq",
+        )?;
+        let rows = stmt.query_map(params![start.0, start.1], |row| {
+            Ok((
+                (row.get::<_, i64>(0)?, row.get::<_, i64>(1)?),
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn last_change_id(&self) -> NetdoxResult<Option<(i64, i64)>> {
+        Ok(self
+            .query_row(
+                "SELECT millis, seq FROM changelog ORDER BY millis DESC, seq DESC LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?)
+    }
+
+    fn change_count(&self) -> NetdoxResult<usize> {
+        let count: i64 = self.query_row(
+            "SELECT COUNT(*) FROM (SELECT DISTINCT millis, seq FROM changelog)",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(usize::try_from(count).unwrap_or_default())
+    }
+
+    fn clear_changes(&self) -> NetdoxResult<()> {
+        self.execute("DELETE FROM changelog", [])?;
+        Ok(())
+    }
+}
+
+fn table_keys(con: &Connection, table: &str, prefix: &str) -> NetdoxResult<HashSet<String>> {
+    let mut stmt = con.prepare(&format!(
+        "SELECT DISTINCT key FROM {table} WHERE substr(key, 1, length(?1)) = ?1"
+    ))?;
+    let rows = stmt.query_map([prefix], |row| row.get(0))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data::store::{keyspace::Keyspace, memory_store::MemoryStore},
+        error::NetdoxResult,
+    };
+
+    use super::SqliteStore;
+
+    /// Writes the same values with every operation that changes them.
+    fn fill(con: &dyn Keyspace) -> NetdoxResult<()> {
+        con.set("string", "value")?;
+        for member in ["a", "b", "c", "d"] {
+            con.sadd("set", member)?;
+        }
+        con.srem("set", "d")?;
+        con.sadd("emptied", "member")?;
+        con.srem("emptied", "member")?;
+        con.hset("hash", "field", "value")?;
+        con.hset("hash", "removed", "value")?;
+        con.hsetnx("hash", "field", "ignored")?;
+        con.hdel("hash", "removed")?;
+        con.rpush("list", "b")?;
+        con.rpush("list", "c")?;
+        con.lpush("list", "a")?;
+        con.rpush("list", "d")?;
+        con.ltrim("list", 3)?;
+        con.set("renamed", "old")?;
+        con.rename_key("renamed", "new")?;
+        con.add_change_field((1, 0), "change", "init")?;
+        con.add_change_field((1, 1), "change", "create dns name")?;
+        con.add_change_field((1, 1), "value", "[net]domain.com")
+    }
+
+    #[test]
+    fn test_matches_memory_store() {
+        let sqlite = SqliteStore::open_in_memory().unwrap();
+        let memory = MemoryStore::new();
+        sqlite.write(fill).unwrap();
+        memory.write(fill).unwrap();
+
+        let sqlite_values = sqlite.read(|con| con.values()).unwrap();
+        assert_eq!(sqlite_values, memory.read(|con| con.values()).unwrap());
+        assert!(!sqlite_values.contains_key("emptied"));
+        assert_eq!(
+            sqlite.read(|con| con.keys("")).unwrap(),
+            memory.read(|con| con.keys("")).unwrap()
+        );
+
+        assert_eq!(
+            sqlite.read(|con| con.change_fields((1, 0))).unwrap(),
+            memory.read(|con| con.change_fields((1, 0))).unwrap()
+        );
+        assert_eq!(sqlite.read(|con| con.change_count()).unwrap(), 2);
+        assert_eq!(
+            memory.read(|con| con.last_change_id()).unwrap(),
+            Some((1, 1))
+        );
+
+        // Both scans read every member exactly once.
+        for store in [sqlite.read(scan_all), memory.read(scan_all)] {
+            let mut members = store.unwrap();
+            members.sort();
+            assert_eq!(members, vec!["a", "b", "c"]);
+        }
+    }
+
+    fn scan_all(con: &dyn Keyspace) -> NetdoxResult<Vec<String>> {
+        let mut members = vec![];
+        let mut cursor = 0;
+        loop {
+            let (next, batch) = con.sscan("set", cursor, 2)?;
+            members.extend(batch);
+            if next == 0 {
+                return Ok(members);
+            }
+            cursor = next;
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use futures::{stream, Stream};
+
+use crate::{
+    data::{
+        model::{Node, RawNode, DNS},
+        DataConn, DataStore,
+    },
+    error::NetdoxResult,
+};
+
+/// Number of objects to read from the data store in each batch.
+pub const BATCH_SIZE: usize = 1000;
+
+/// Streams the DNS data in batches, each with the records of about `count` DNS names.
+/// Combine the batches with `DNS::extend` to get the records implied by other batches.
+pub fn dns_batches(con: DataStore, count: usize) -> impl Stream<Item = NetdoxResult<DNS>> {
+    stream::try_unfold(Some((con, 0)), move |state| async move {
+        let Some((mut con, cursor)) = state else {
+            return Ok(None);
+        };
+        let (next, dns) = con.scan_dns(cursor, count).await?;
+        Ok(Some((dns, (next != 0).then_some((con, next)))))
+    })
+}
+
+/// Streams the raw nodes in batches, each with the raw nodes of about `count` sets of DNS names.
+pub fn raw_node_batches(
+    con: DataStore,
+    count: usize,
+) -> impl Stream<Item = NetdoxResult<Vec<RawNode>>> {
+    stream::try_unfold(Some((con, 0)), move |state| async move {
+        let Some((mut con, cursor)) = state else {
+            return Ok(None);
+        };
+        let (next, nodes) = con.scan_raw_nodes(cursor, count).await?;
+        Ok(Some((nodes, (next != 0).then_some((con, next)))))
+    })
+}
+
+/// Streams the processed nodes in batches of about `count`.
+pub fn node_batches(con: DataStore, count: usize) -> impl Stream<Item = NetdoxResult<Vec<Node>>> {
+    stream::try_unfold(Some((con, 0)), move |state| async move {
+        let Some((mut con, cursor)) = state else {
+            return Ok(None);
+        };
+        let (next, nodes) = con.scan_nodes(cursor, count).await?;
+        Ok(Some((nodes, (next != 0).then_some((con, next)))))
+    })
+}
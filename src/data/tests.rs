@@ -1,9 +1,21 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use itertools::Itertools;
+use redis::{FromRedisValue, Value};
 
 use crate::{data::model::StringType, tests_common::*};
 
 use super::{
-    model::{Data, Node},
+    model::{
+        address_subnet, canonical_ipv6, coalesce_batches, group_metadata, group_subnets,
+        matches_ignore_pattern, merge_metadata, ptr_name, publish_batches, qname_address,
+        reverse_zone, Change, ChangelogEntry, ConflictPolicy, ConflictStrategy, DNSRecord, Data,
+        DataKind, MetadataProvenance, Node, PluginScope, QueuedUpdate, RunKind, RunLock, RunRecord,
+        RunTiming, StageSnapshot,
+    },
     store::DataConn,
 };
 
@@ -46,10 +58,88 @@ async fn test_dns_superset() {
     )
 }
 
+#[tokio::test]
+async fn test_dns_subset() {
+    let mut con = setup_db_con().await;
+    let alias = "[dns-subset]alias.com";
+    let domain = "[dns-subset]domain.com";
+    let ip = "[dns-subset]10.0.0.1";
+
+    call_fn(
+        &mut con,
+        "netdox_create_dns",
+        &["1", alias, PLUGIN, "cname", domain],
+    )
+    .await;
+
+    call_fn(
+        &mut con,
+        "netdox_create_dns",
+        &["1", domain, PLUGIN, "a", ip],
+    )
+    .await;
+
+    call_fn(
+        &mut con,
+        "netdox_create_dns",
+        &[
+            "1",
+            "[dns-subset]unrelated.com",
+            PLUGIN,
+            "a",
+            "[dns-subset]10.0.0.2",
+        ],
+    )
+    .await;
+
+    let subset = con.get_dns_subset(&[ip]).await.unwrap();
+
+    assert_eq!(
+        HashSet::from([alias.to_string(), domain.to_string(), ip.to_string()]),
+        subset.qnames
+    );
+    assert_eq!(subset.get_implied_records(ip).len(), 1);
+}
+
+#[tokio::test]
+async fn test_ipv6_dns() {
+    let mut con = setup_db_con().await;
+    let name = "[dns-ipv6]dual.com";
+
+    call_fn(
+        &mut con,
+        "netdox_create_dns",
+        &["1", name, PLUGIN, "a", "[dns-ipv6]10.0.0.1"],
+    )
+    .await;
+
+    call_fn(
+        &mut con,
+        "netdox_create_dns",
+        &["1", name, PLUGIN, "aaaa", "[dns-ipv6]2001:DB8:0:0:0:0:0:1"],
+    )
+    .await;
+
+    let dns = con.get_dns().await.unwrap();
+    assert_eq!(
+        HashSet::from([
+            name.to_string(),
+            "[dns-ipv6]10.0.0.1".to_string(),
+            "[dns-ipv6]2001:db8::1".to_string()
+        ]),
+        dns.dns_superset(name).unwrap()
+    );
+
+    let implied = dns.get_implied_records("[dns-ipv6]2001:db8::1");
+    assert_eq!(implied.len(), 1);
+    assert_eq!(implied.into_iter().next().unwrap().rtype, "PTR");
+}
+
 // NODES
 
 #[tokio::test]
 async fn test_node_roundtrip() {
+    let _lock = PROC_NODES_LOCK.write().await;
     let mut con = setup_db_con().await;
     let expected = Node {
         link_id: "linkable-id".to_string(),
@@ -60,12 +150,70 @@ async fn test_node_roundtrip() {
         raw_ids: HashSet::from(["[some-net]domain.com".to_string()]),
     };
 
-    con.put_node(&expected).await.unwrap();
+    con.put_processed(&[], &[&expected]).await.unwrap();
     let actual = con.get_node(&expected.link_id).await.unwrap();
 
     assert_eq!(expected, actual);
 }
 
+#[tokio::test]
+async fn test_put_processed_replaces() {
+    let _lock = PROC_NODES_LOCK.write().await;
+    let mut con = setup_db_con().await;
+    let server = Node {
+        link_id: "replaced-server-id".to_string(),
+        name: "server".to_string(),
+        alt_names: HashSet::new(),
+        dns_names: HashSet::from(["[some-net]replaced-a.com".to_string()]),
+        plugins: HashSet::from(["some-plugin".to_string()]),
+        raw_ids: HashSet::from(["[some-net]replaced-a.com".to_string()]),
+    };
+    let old = Node {
+        link_id: "replaced-old-id".to_string(),
+        name: "old".to_string(),
+        dns_names: HashSet::from(["[some-net]replaced-b.com".to_string()]),
+        raw_ids: HashSet::from(["[some-net]replaced-b.com".to_string()]),
+        ..server.clone()
+    };
+    con.put_processed(&[], &[&server, &old]).await.unwrap();
+
+    // Nodes and index entries that the next run does not produce are removed.
+    let server = Node {
+        plugins: HashSet::from(["other-plugin".to_string()]),
+        raw_ids: HashSet::from(["[some-net]replaced-a.com;[some-net]replaced-c.com".to_string()]),
+        ..server
+    };
+    con.put_processed(&[], &[&server]).await.unwrap();
+
+    assert_eq!(
+        con.get_node_ids().await.unwrap(),
+        HashSet::from([server.link_id.clone()])
+    );
+    assert_eq!(con.get_node(&server.link_id).await.unwrap(), server);
+    for raw_id in ["[some-net]replaced-a.com", "[some-net]replaced-b.com"] {
+        assert_eq!(con.get_node_from_raw(raw_id).await.unwrap(), None);
+    }
+}
+
+// NOTES
+
+#[tokio::test]
+async fn test_notes() {
+    let mut con = setup_db_con().await;
+    let obj_id = format!("dns;[{DEFAULT_NETWORK}]notes.com");
+
+    con.put_note(&obj_id, "note", Some("Do not reboot."))
+        .await
+        .unwrap();
+    con.put_note(&obj_id, "owner", Some("ops")).await.unwrap();
+    con.put_note(&obj_id, "owner", None).await.unwrap();
+
+    assert_eq!(
+        con.get_notes(&obj_id).await.unwrap(),
+        HashMap::from([("note".to_string(), "Do not reboot.".to_string())])
+    );
+}
+
 // PLUGIN DATA
 
 #[tokio::test]
@@ -157,3 +305,740 @@ async fn test_plugin_data_list() {
         }]
     );
 }
+
+// RUNS
+
+#[tokio::test]
+async fn test_run_roundtrip() {
+    let mut con = setup_db_con().await;
+    let expected = RunRecord {
+        kind: RunKind::Update,
+        started: 1_700_000_000,
+        millis: 1234,
+        success: false,
+        timings: vec![RunTiming {
+            name: "write-only/some-plugin".to_string(),
+            millis: 1000,
+            success: false,
+            skipped: false,
+        }],
+        stages: vec![StageSnapshot {
+            stage: "write-only".to_string(),
+            start_change: Some("1700000000000-0".to_string()),
+            end_change: Some("1700000001000-0".to_string()),
+            changes: 1,
+            dns_names: 0,
+            nodes: 0,
+        }],
+    };
+
+    con.put_run(&expected).await.unwrap();
+    let actual = con.get_runs(1).await.unwrap();
+
+    assert_eq!(vec![expected], actual);
+}
+
+#[tokio::test]
+async fn test_run_lock() {
+    let mut con = setup_db_con().await;
+    let expires = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + 3600;
+    let lock = |owner: &str| RunLock {
+        owner: owner.to_string(),
+        kind: RunKind::Publish,
+        host: "host".to_string(),
+        pid: 1,
+        acquired: 0,
+        expires,
+    };
+    con.force_run_unlock().await.unwrap();
+
+    assert_eq!(con.acquire_run_lock(&lock("first")).await.unwrap(), None);
+    assert_eq!(con.acquire_run_lock(&lock("first")).await.unwrap(), None);
+    assert_eq!(
+        con.acquire_run_lock(&lock("second")).await.unwrap(),
+        Some(lock("first"))
+    );
+
+    con.release_run_lock("second").await.unwrap();
+    con.release_run_lock("first").await.unwrap();
+    assert_eq!(con.acquire_run_lock(&lock("second")).await.unwrap(), None);
+    assert_eq!(con.force_run_unlock().await.unwrap(), Some(lock("second")));
+}
+
+#[tokio::test]
+async fn test_deferred_plugins() {
+    let mut con = setup_db_con().await;
+    let plugins = HashSet::from(["dns".to_string(), "monitor".to_string()]);
+
+    con.set_deferred_plugins(&plugins).await.unwrap();
+    assert_eq!(con.get_deferred_plugins().await.unwrap(), plugins);
+
+    con.set_deferred_plugins(&HashSet::new()).await.unwrap();
+    assert!(con.get_deferred_plugins().await.unwrap().is_empty());
+}
+
+// PUBLISH QUEUE
+
+#[tokio::test]
+async fn test_publish_queue() {
+    let mut con = setup_db_con().await;
+    let update = |content: &str| {
+        QueuedUpdate::new(
+            "_nd_queue_test_doc".to_string(),
+            "meta".to_string(),
+            None,
+            true,
+            content.to_string(),
+        )
+    };
+    let (old, new) = (update("<old/>"), update("<new/>"));
+
+    con.put_queued_updates(std::slice::from_ref(&old))
+        .await
+        .unwrap();
+    assert!(con.get_queued_updates().await.unwrap().contains(&old));
+
+    // A newer update replaces the old one, so the old one cannot remove it.
+    con.put_queued_updates(std::slice::from_ref(&new))
+        .await
+        .unwrap();
+    con.remove_queued_update(&old).await.unwrap();
+    let queued = con.get_queued_updates().await.unwrap();
+    assert!(queued.contains(&new));
+    assert!(!queued.contains(&old));
+
+    con.remove_queued_update(&new).await.unwrap();
+    assert!(!con.get_queued_updates().await.unwrap().contains(&new));
+}
+
+// ADDRESSES
+
+#[test]
+fn test_normalised_alt_names() {
+    let node = |name: &str, alt_names: &[&str]| Node {
+        name: name.to_string(),
+        link_id: "link-id".to_string(),
+        alt_names: alt_names.iter().map(|name| name.to_string()).collect(),
+        dns_names: HashSet::new(),
+        plugins: HashSet::new(),
+        raw_ids: HashSet::new(),
+    };
+
+    assert_eq!(
+        node(
+            "Server",
+            &[
+                "DB01",
+                "db01.",
+                "Db01",
+                " Backup Host ",
+                "server",
+                "web01",
+                "Web01.corp.com"
+            ]
+        )
+        .normalised_alt_names(),
+        HashSet::from([
+            "db01".to_string(),
+            "Backup Host".to_string(),
+            "Web01.corp.com".to_string()
+        ])
+    );
+    assert_eq!(
+        node("mail.corp.com", &["mail", "MAIL.", "smtp"]).normalised_alt_names(),
+        HashSet::from(["smtp".to_string()])
+    );
+    assert_eq!(
+        node("Server", &["A", "B"]).normalised_alt_names(),
+        HashSet::from(["A".to_string(), "B".to_string()])
+    );
+}
+
+#[test]
+fn test_reverse_dns() {
+    let addr = qname_address("[net]10.1.2.3").unwrap();
+    assert_eq!(ptr_name(&addr), "3.2.1.10.in-addr.arpa");
+    assert_eq!(reverse_zone(&addr), "2.1.10.in-addr.arpa");
+    assert_eq!(address_subnet(&addr).to_string(), "10.1.2.0/24");
+
+    let addr = qname_address("[net]2001:db8::1").unwrap();
+    assert_eq!(
+        ptr_name(&addr),
+        "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+    );
+    assert_eq!(
+        reverse_zone(&addr),
+        "0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+    );
+    assert_eq!(address_subnet(&addr).to_string(), "2001:db8::/64");
+
+    assert_eq!(qname_address("[net]10.1.2.3.example.com"), None);
+    assert_eq!(qname_address("10.1.2.3"), None);
+}
+
+#[test]
+fn test_group_subnets() {
+    let qnames = HashSet::from(
+        [
+            "[net]10.1.2.10",
+            "[net]10.1.2.9",
+            "[net]10.1.3.4",
+            "[net]192.168.0.1",
+            "[other]10.1.2.9",
+            "[net]www.example.com",
+        ]
+        .map(str::to_string),
+    );
+    let configured = ["10.1.0.0/16", "10.1.3.0/24"].map(|net| net.parse().unwrap());
+    let dns_nodes = HashMap::from([("[net]10.1.2.9".to_string(), "node".to_string())]);
+
+    let subnets = group_subnets(&qnames, &configured, &dns_nodes)
+        .into_iter()
+        .map(|subnet| {
+            (
+                format!("[{}]{}", subnet.network, subnet.net),
+                subnet.configured,
+                subnet
+                    .members
+                    .into_iter()
+                    .map(|member| (member.qname, member.node))
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        subnets,
+        vec![
+            (
+                "[net]10.1.0.0/16".to_string(),
+                true,
+                vec![
+                    ("[net]10.1.2.9".to_string(), Some("node".to_string())),
+                    ("[net]10.1.2.10".to_string(), None),
+                ]
+            ),
+            (
+                "[net]10.1.3.0/24".to_string(),
+                true,
+                vec![("[net]10.1.3.4".to_string(), None)]
+            ),
+            (
+                "[net]192.168.0.0/24".to_string(),
+                false,
+                vec![("[net]192.168.0.1".to_string(), None)]
+            ),
+            (
+                "[other]10.1.0.0/16".to_string(),
+                true,
+                vec![("[other]10.1.2.9".to_string(), None)]
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_matches_ignore_pattern() {
+    assert!(matches_ignore_pattern("*.dyn.com", "[net]host.DYN.com"));
+    assert!(matches_ignore_pattern("*.dyn.com", "[net]a.b.dyn.com"));
+    assert!(!matches_ignore_pattern("*.dyn.com", "[net]dyn.com"));
+    assert!(matches_ignore_pattern(
+        "[net]*.dyn.com",
+        "[net]host.dyn.com"
+    ));
+    assert!(!matches_ignore_pattern(
+        "[net]*.dyn.com",
+        "[other]host.dyn.com"
+    ));
+    assert!(matches_ignore_pattern("dhcp-??.com", "[net]dhcp-42.com"));
+    assert!(!matches_ignore_pattern("dhcp-??.com", "[net]dhcp-420.com"));
+    assert!(matches_ignore_pattern("*-*.com", "[net]a-b-c.com"));
+}
+
+#[test]
+fn test_plugin_scope_allows() {
+    let scope = PluginScope {
+        networks: vec!["Net".to_string()],
+        zones: vec!["Corp.com.".to_string()],
+    };
+    assert!(scope.allows("[net]corp.com"));
+    assert!(scope.allows("[net]Web.corp.com"));
+    assert!(scope.allows("[net]10.0.0.1"));
+    assert!(scope.allows("[net]2001:db8::1"));
+    assert!(!scope.allows("[net]evilcorp.com"));
+    assert!(!scope.allows("[other]web.corp.com"));
+    assert!(!scope.allows("web.corp.com"));
+    assert!(PluginScope::default().allows("[any]name.org"));
+}
+
+#[test]
+fn test_conflict_policy_choose() {
+    let value = |plugin: &str, changed: Option<u64>| MetadataProvenance {
+        value: format!("{plugin} value"),
+        plugin: Some(plugin.to_string()),
+        changed,
+    };
+    let values = [
+        value("old", Some(100)),
+        value("new", Some(200)),
+        value("cmdb", Some(150)),
+        value("unknown", None),
+    ];
+
+    let mut policy = ConflictPolicy::default();
+    assert_eq!(policy.choose(&values), 1);
+    policy.strategy = ConflictStrategy::FirstWins;
+    assert_eq!(policy.choose(&values), 3);
+
+    // Listed plugins beat unlisted ones, whatever the strategy.
+    policy.priority = vec!["cmdb".to_string(), "old".to_string()];
+    assert_eq!(policy.choose(&values), 2);
+    policy.strategy = ConflictStrategy::LastWins;
+    assert_eq!(policy.choose(&values), 2);
+    assert_eq!(policy.choose(&values[..2]), 0);
+}
+
+#[test]
+fn test_merge_metadata() {
+    let node = Node {
+        name: "server".to_string(),
+        link_id: "server-id".to_string(),
+        alt_names: HashSet::new(),
+        dns_names: HashSet::new(),
+        plugins: HashSet::new(),
+        raw_ids: HashSet::new(),
+    };
+    let layer = |pairs: &[(&str, &str, &str)]| {
+        pairs
+            .iter()
+            .map(|(key, value, plugin)| {
+                let meta = MetadataProvenance {
+                    value: value.to_string(),
+                    plugin: Some(plugin.to_string()),
+                    changed: None,
+                };
+                (key.to_string(), meta)
+            })
+            .collect::<HashMap<_, _>>()
+    };
+    let raw = vec![
+        (
+            "b".to_string(),
+            layer(&[("os", "windows", "two"), ("site", "syd", "two")]),
+        ),
+        (
+            "a".to_string(),
+            layer(&[("os", "linux", "one"), ("site", "syd", "one")]),
+        ),
+    ];
+
+    // With no change times, the raw node merged last wins, which is the highest raw ID.
+    let (merged, conflicts) = merge_metadata(
+        &node,
+        raw.clone(),
+        HashMap::new(),
+        &ConflictPolicy::default(),
+    );
+    assert_eq!(merged["os"].value, "windows");
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].key.as_deref(), Some("os"));
+    assert_eq!(conflicts[0].rejected[0].value, "linux");
+
+    let policy = ConflictPolicy {
+        priority: vec!["one".to_string()],
+        ..Default::default()
+    };
+    let (merged, _) = merge_metadata(&node, raw.clone(), HashMap::new(), &policy);
+    assert_eq!(merged["os"].value, "linux");
+
+    let (merged, conflicts) = merge_metadata(&node, raw, layer(&[("os", "bsd", "three")]), &policy);
+    assert_eq!(merged["os"].value, "bsd");
+    assert!(conflicts.is_empty());
+}
+
+#[test]
+fn test_canonical_ipv6() {
+    assert_eq!(
+        canonical_ipv6("2001:0DB8:0000:0000:0000:0000:0000:0001").as_deref(),
+        Some("2001:db8::1")
+    );
+    assert_eq!(
+        canonical_ipv6("2001:db8:0:1:0:0:0:1").as_deref(),
+        Some("2001:db8:0:1::1")
+    );
+    // The first of two equally long runs of zeros is compressed, and a single zero is not.
+    assert_eq!(
+        canonical_ipv6("1:0:0:2:0:0:3:4").as_deref(),
+        Some("1::2:0:0:3:4")
+    );
+    assert_eq!(
+        canonical_ipv6("1:0:2:3:4:5:6:7").as_deref(),
+        Some("1:0:2:3:4:5:6:7")
+    );
+    assert_eq!(canonical_ipv6("::").as_deref(), Some("::"));
+
+    assert_eq!(canonical_ipv6("::ffff:10.0.0.1"), None);
+    assert_eq!(canonical_ipv6("10.0.0.1"), None);
+    assert_eq!(canonical_ipv6("dead:beef"), None);
+    assert_eq!(canonical_ipv6("cafe.com"), None);
+}
+
+#[test]
+fn test_implied_rtypes() {
+    let record = |name: &str, rtype: &str, value: &str| DNSRecord {
+        name: name.to_string(),
+        value: value.to_string(),
+        rtype: rtype.to_string(),
+        plugin: "plugin".to_string(),
+    };
+
+    let implied = |record: DNSRecord| record.implies().map(|implied| implied.rtype);
+    assert_eq!(
+        implied(record("[net]a.com", "AAAA", "[net]2001:db8::1")).as_deref(),
+        Some("PTR")
+    );
+    assert_eq!(
+        implied(record("[net]2001:db8::1", "PTR", "[net]a.com")).as_deref(),
+        Some("AAAA")
+    );
+    assert_eq!(
+        implied(record("[net]10.0.0.1", "PTR", "[net]a.com")).as_deref(),
+        Some("A")
+    );
+    assert_eq!(implied(record("[net]a.com", "TXT", "text")), None);
+}
+
+// METADATA
+
+#[test]
+fn test_group_metadata() {
+    let metadata = HashMap::from([
+        ("os".to_string(), "linux".to_string()),
+        ("cpus".to_string(), "4".to_string()),
+        ("owner".to_string(), "ops".to_string()),
+        ("legacy".to_string(), "true".to_string()),
+    ]);
+    let sources = HashMap::from([
+        ("os".to_string(), "inventory".to_string()),
+        ("cpus".to_string(), "inventory".to_string()),
+        ("owner".to_string(), "cmdb".to_string()),
+    ]);
+
+    let groups = group_metadata(metadata, &sources);
+    assert_eq!(
+        groups
+            .iter()
+            .map(|(plugin, meta)| (plugin.as_deref(), meta.keys().sorted().join(",")))
+            .collect::<Vec<_>>(),
+        vec![
+            (None, "legacy".to_string()),
+            (Some("cmdb"), "owner".to_string()),
+            (Some("inventory"), "cpus,os".to_string()),
+        ]
+    );
+}
+
+// CHANGES
+
+#[test]
+fn test_changelog_typed_fields() {
+    let entry = |fields: &[&str]| {
+        Value::Array(vec![
+            Value::BulkString(b"1-0".to_vec()),
+            Value::Array(
+                fields
+                    .iter()
+                    .map(|field| Value::BulkString(field.as_bytes().to_vec()))
+                    .collect(),
+            ),
+        ])
+    };
+    let node_id = "[net]a.com;[net]b.com";
+
+    // Typed fields are used even if the object ID contains semicolons.
+    let typed = ChangelogEntry::from_redis_value(entry(&[
+        "change",
+        "created data",
+        "value",
+        &format!("pdata;nodes;{node_id};data-id"),
+        "plugin",
+        PLUGIN,
+        "obj_id",
+        &format!("nodes;{node_id}"),
+        "data_id",
+        "data-id",
+        "kind",
+        "plugin",
+    ]))
+    .unwrap();
+
+    // Entries without typed fields are parsed from the value.
+    let legacy = ChangelogEntry::from_redis_value(entry(&[
+        "change",
+        "updated metadata",
+        "value",
+        &format!("meta;nodes;{node_id}"),
+        "plugin",
+        PLUGIN,
+    ]))
+    .unwrap();
+
+    assert_eq!(
+        typed.change,
+        Change::CreatedData {
+            plugin: PLUGIN.to_string(),
+            obj_id: format!("nodes;{node_id}"),
+            data_id: "data-id".to_string(),
+            kind: DataKind::Plugin,
+        }
+    );
+    assert_eq!(
+        legacy.change,
+        Change::UpdatedMetadata {
+            plugin: PLUGIN.to_string(),
+            obj_id: format!("nodes;{node_id}"),
+        }
+    );
+
+    let mapping = ChangelogEntry::from_redis_value(entry(&[
+        "change",
+        "updated network mapping",
+        "value",
+        "[net]a.com",
+        "plugin",
+        PLUGIN,
+        "qname",
+        "[net]a.com",
+        "dest",
+        "[other-net]a.com",
+    ]))
+    .unwrap();
+    assert_eq!(
+        mapping.change,
+        Change::UpdatedNetworkMapping {
+            plugin: PLUGIN.to_string(),
+            source: "[net]a.com".to_string(),
+            dest: "[other-net]a.com".to_string(),
+        }
+    );
+
+    let deleted = ChangelogEntry::from_redis_value(entry(&[
+        "change",
+        "deleted data",
+        "value",
+        &format!("pdata;nodes;{node_id};data-id"),
+        "plugin",
+        PLUGIN,
+        "obj_id",
+        &format!("nodes;{node_id}"),
+        "data_id",
+        "data-id",
+        "kind",
+        "plugin",
+    ]))
+    .unwrap();
+    assert_eq!(
+        deleted.change,
+        Change::DeletedData {
+            plugin: PLUGIN.to_string(),
+            obj_id: format!("nodes;{node_id}"),
+            data_id: "data-id".to_string(),
+            kind: DataKind::Plugin,
+        }
+    );
+
+    let service = ChangelogEntry::from_redis_value(entry(&[
+        "change",
+        "updated service",
+        "value",
+        "services;app;443/tcp",
+        "plugin",
+        PLUGIN,
+        "link_id",
+        "app",
+        "service",
+        "443/tcp",
+    ]))
+    .unwrap();
+    assert_eq!(
+        service.change,
+        Change::UpdatedService {
+            plugin: PLUGIN.to_string(),
+            link_id: "app".to_string(),
+            service: "443/tcp".to_string(),
+        }
+    );
+
+    let relation = ChangelogEntry::from_redis_value(entry(&[
+        "change",
+        "updated relation",
+        "value",
+        "vm",
+        "plugin",
+        PLUGIN,
+        "source",
+        "vm",
+        "dest",
+        "host",
+        "relation",
+        "vm-on-host",
+    ]))
+    .unwrap();
+    assert_eq!(
+        relation.change,
+        Change::UpdatedRelation {
+            plugin: PLUGIN.to_string(),
+            source: "vm".to_string(),
+            dest: "host".to_string(),
+            relation: "vm-on-host".to_string(),
+        }
+    );
+}
+
+#[test]
+fn test_coalesce_batches() {
+    let entry = |id: &str, change: Change| ChangelogEntry {
+        id: id.to_string(),
+        change,
+    };
+    let metadata = Change::UpdatedMetadata {
+        plugin: PLUGIN.to_string(),
+        obj_id: "nodes;[net]domain.com".to_string(),
+    };
+    let data = |data_id: &str| Change::CreatedData {
+        plugin: PLUGIN.to_string(),
+        obj_id: "nodes;[net]domain.com".to_string(),
+        data_id: data_id.to_string(),
+        kind: DataKind::Plugin,
+    };
+    let other = Change::UpdatedMetadata {
+        plugin: "other-plugin".to_string(),
+        obj_id: "dns;[net]other.com".to_string(),
+    };
+    let unended = data("unended");
+
+    let changes = coalesce_batches(&[
+        entry(
+            "1-0",
+            Change::BeginBatch {
+                plugin: PLUGIN.to_string(),
+                batch_id: "1-0".to_string(),
+            },
+        ),
+        entry("2-0", metadata),
+        entry("3-0", other.clone()),
+        entry("4-0", data("first")),
+        entry("5-0", data("second")),
+        entry(
+            "6-0",
+            Change::EndBatch {
+                plugin: PLUGIN.to_string(),
+                batch_id: "1-0".to_string(),
+            },
+        ),
+        entry(
+            "7-0",
+            Change::BeginBatch {
+                plugin: PLUGIN.to_string(),
+                batch_id: "7-0".to_string(),
+            },
+        ),
+        entry("8-0", unended.clone()),
+        entry("9-0", other.clone()),
+    ]);
+
+    // Batches keep their place relative to changes made by other plugins.
+    assert_eq!(
+        changes,
+        vec![
+            Change::CreatePluginNode {
+                plugin: PLUGIN.to_string(),
+                node_id: "[net]domain.com".to_string(),
+            },
+            other.clone(),
+            unended,
+            other,
+        ]
+    );
+}
+
+#[test]
+fn test_publish_batches() {
+    let entry = |id: &str, change: Change| ChangelogEntry {
+        id: id.to_string(),
+        change,
+    };
+    let metadata = |obj_id: &str| Change::UpdatedMetadata {
+        plugin: PLUGIN.to_string(),
+        obj_id: obj_id.to_string(),
+    };
+
+    let entries = [
+        entry("1-0", metadata("dns;[net]a.com")),
+        entry(
+            "2-0",
+            Change::BeginBatch {
+                plugin: PLUGIN.to_string(),
+                batch_id: "2-0".to_string(),
+            },
+        ),
+        entry("3-0", metadata("dns;[net]b.com")),
+        entry("4-0", metadata("dns;[net]c.com")),
+        entry(
+            "5-0",
+            Change::EndBatch {
+                plugin: PLUGIN.to_string(),
+                batch_id: "2-0".to_string(),
+            },
+        ),
+        entry("6-0", metadata("dns;[net]d.com")),
+    ];
+
+    let ids = |size| {
+        publish_batches(&entries, size)
+            .into_iter()
+            .map(|batch| batch.iter().map(|entry| entry.id.as_str()).collect_vec())
+            .collect_vec()
+    };
+
+    assert_eq!(
+        ids(4),
+        vec![vec!["1-0", "2-0", "3-0", "4-0", "5-0"], vec!["6-0"]]
+    );
+    // The plugin batch has not ended within 1 or 2 entries, so it does not hold the publish batch open.
+    assert_eq!(
+        ids(1),
+        vec![
+            vec!["1-0"],
+            vec!["2-0", "3-0"],
+            vec!["4-0"],
+            vec!["5-0"],
+            vec!["6-0"]
+        ]
+    );
+    assert_eq!(
+        ids(2),
+        vec![vec!["1-0", "2-0", "3-0", "4-0"], vec!["5-0", "6-0"]]
+    );
+    assert_eq!(ids(10).len(), 1);
+    assert!(publish_batches(&[], 10).is_empty());
+
+    // A plugin that stopped before ending its batch does not put every later change in one batch.
+    let mut unterminated = vec![entry(
+        "1-0",
+        Change::BeginBatch {
+            plugin: PLUGIN.to_string(),
+            batch_id: "1-0".to_string(),
+        },
+    )];
+    unterminated.extend((2..=9).map(|id| entry(&format!("{id}-0"), metadata("dns;[net]a.com"))));
+    let sizes = publish_batches(&unterminated, 3)
+        .into_iter()
+        .map(<[ChangelogEntry]>::len)
+        .collect_vec();
+    assert_eq!(sizes, vec![4, 3, 2]);
+}
@@ -0,0 +1,200 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    config::{PluginConfig, PluginStage, PluginStageConfig},
+    config_err,
+    data::model::{PluginDisplay, PluginScope, SCHEMA_VERSION},
+    error::{NetdoxError, NetdoxResult},
+    io_err,
+};
+
+pub mod function_tests;
+
+const PYTHON_TEMPLATE: &str = include_str!("../templates/plugin/plugin.py");
+const BASH_TEMPLATE: &str = include_str!("../templates/plugin/plugin.sh");
+const RUST_MAIN_TEMPLATE: &str = include_str!("../templates/plugin/main.rs");
+const RUST_MANIFEST_TEMPLATE: &str = include_str!("../templates/plugin/Cargo.toml");
+
+/// Placeholder in the templates that is replaced with the plugin name.
+const NAME_PLACEHOLDER: &str = "{{name}}";
+
+/// Language to generate a plugin skeleton in.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum PluginLang {
+    Python,
+    Rust,
+    Bash,
+}
+
+/// Wraps a plugin config so it serializes as a `[[plugin]]` table.
+#[derive(Serialize)]
+struct PluginStanza<'a> {
+    plugin: [&'a PluginConfig; 1],
+}
+
+/// Writes a plugin skeleton to a new directory named after the plugin,
+/// and prints the config stanza required to run it.
+pub fn new_plugin(name: &str, lang: PluginLang, parent: &Path) -> NetdoxResult<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+    {
+        return config_err!(format!(
+            "Plugin name must be non-empty and contain only lowercase letters, digits, '-' and '_': {name}"
+        ));
+    }
+
+    let dir = parent.join(name);
+    if dir.exists() {
+        return io_err!(format!(
+            "Refusing to overwrite existing path: {}",
+            dir.display()
+        ));
+    }
+
+    let files: Vec<(PathBuf, &str, bool)> = match lang {
+        PluginLang::Python => vec![(PathBuf::from("plugin.py"), PYTHON_TEMPLATE, true)],
+        PluginLang::Bash => vec![(PathBuf::from("plugin.sh"), BASH_TEMPLATE, true)],
+        PluginLang::Rust => vec![
+            (PathBuf::from("Cargo.toml"), RUST_MANIFEST_TEMPLATE, false),
+            (PathBuf::from("src/main.rs"), RUST_MAIN_TEMPLATE, false),
+        ],
+    };
+
+    for (path, template, executable) in files {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            if let Err(err) = fs::create_dir_all(parent) {
+                return io_err!(format!(
+                    "Failed to create directory {}: {err}",
+                    parent.display()
+                ));
+            }
+        }
+
+        if let Err(err) = fs::write(&path, template.replace(NAME_PLACEHOLDER, name)) {
+            return io_err!(format!("Failed to write {}: {err}", path.display()));
+        }
+
+        if executable {
+            set_executable(&path)?;
+        }
+    }
+
+    let dir = match dir.canonicalize() {
+        Ok(dir) => dir,
+        Err(err) => {
+            return io_err!(format!(
+                "Failed to resolve plugin directory {}: {err}",
+                dir.display()
+            ))
+        }
+    };
+
+    let exe_path = match lang {
+        PluginLang::Python => dir.join("plugin.py"),
+        PluginLang::Bash => dir.join("plugin.sh"),
+        PluginLang::Rust => dir.join("target").join("release").join(name),
+    };
+
+    let stanza = plugin_stanza(name, &exe_path.to_string_lossy());
+    let stanza_path = dir.join("config.toml");
+    if let Err(err) = fs::write(&stanza_path, &stanza) {
+        return io_err!(format!("Failed to write {}: {err}", stanza_path.display()));
+    }
+
+    info!("A plugin skeleton has been written to: {}", dir.display());
+    if let PluginLang::Rust = lang {
+        info!("Build it with: cargo build --release");
+    }
+    info!("Add the following to your config file and run: netdox config load <config file>");
+    println!("\n{stanza}");
+
+    Ok(())
+}
+
+/// Config stanza for a plugin with a single write-only stage.
+fn plugin_stanza(name: &str, path: &str) -> String {
+    let config = PluginConfig {
+        name: name.to_string(),
+        display: PluginDisplay::default(),
+        scope: PluginScope::default(),
+        timeout_seconds: None,
+        schema_version: Some(SCHEMA_VERSION),
+        fields: HashMap::from([(
+            "node_name".to_string(),
+            toml::Value::String("Example Node".to_string()),
+        )]),
+        stages: HashMap::from([(
+            PluginStage::WriteOnly,
+            PluginStageConfig {
+                path: path.to_string(),
+                retries: 0,
+                probe: None,
+                workers: None,
+                fields: HashMap::new(),
+            },
+        )]),
+    };
+
+    toml::ser::to_string_pretty(&PluginStanza { plugin: [&config] })
+        .expect("Failed to serialize plugin config stanza.")
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path) -> NetdoxResult<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Err(err) = fs::set_permissions(path, fs::Permissions::from_mode(0o755)) {
+        return io_err!(format!(
+            "Failed to make {} executable: {err}",
+            path.display()
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path) -> NetdoxResult<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::{new_plugin, PluginLang};
+
+    #[test]
+    fn test_new_plugin() {
+        let parent = env::temp_dir().join(format!("netdox-new-plugin-{}", std::process::id()));
+        fs::create_dir_all(&parent).unwrap();
+
+        new_plugin("test-plugin", PluginLang::Rust, &parent).unwrap();
+        let dir = parent.join("test-plugin");
+
+        let manifest = fs::read_to_string(dir.join("Cargo.toml")).unwrap();
+        assert!(manifest.contains("name = \"test-plugin\""));
+        assert!(!fs::read_to_string(dir.join("src/main.rs"))
+            .unwrap()
+            .contains("{{name}}"));
+        assert!(fs::read_to_string(dir.join("config.toml"))
+            .unwrap()
+            .contains("[[plugin]]"));
+
+        assert!(new_plugin("test-plugin", PluginLang::Rust, &parent).is_err());
+        assert!(new_plugin("Invalid Name", PluginLang::Python, &parent).is_err());
+
+        fs::remove_dir_all(parent).unwrap();
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use tracing::{error, info};
+
+use crate::{
+    backup::read_backup,
+    config::LocalConfig,
+    data::{
+        model::{DataDump, StoredValue, DNS_KEY, METADATA_KEY, PROC_NODES_KEY},
+        DataConn,
+    },
+    error::NetdoxResult,
+};
+
+/// Suffixes of the keys that hold the details of a processed node.
+const PROC_NODE_SUFFIXES: [&str; 5] = ["", ";alt_names", ";dns_names", ";plugins", ";raw_ids"];
+
+/// Prints the differences between two backups, or between a backup and the data store.
+#[tokio::main]
+pub async fn diff(old: &Path, new: Option<&PathBuf>) {
+    let old_dump = match read_backup(old) {
+        Ok((_, dump)) => dump,
+        Err(err) => {
+            error!("{err}");
+            exit(1);
+        }
+    };
+
+    let new_dump = match new {
+        Some(path) => read_backup(path).map(|(_, dump)| dump),
+        None => live_dump().await,
+    };
+    let new_dump = match new_dump {
+        Ok(dump) => dump,
+        Err(err) => {
+            error!("Failed to read data to compare with: {err}");
+            exit(1);
+        }
+    };
+
+    let diff = DumpDiff::new(&old_dump, &new_dump);
+    for line in diff.lines() {
+        println!("{line}");
+    }
+
+    info!(
+        "{} DNS names, {} DNS records, {} nodes and {} metadata values differ.",
+        diff.dns_names.len(),
+        diff.records.len(),
+        diff.nodes.len(),
+        diff.metadata.len()
+    );
+}
+
+/// Reads the current contents of the data store.
+async fn live_dump() -> NetdoxResult<DataDump> {
+    let cfg = LocalConfig::read()?;
+    cfg.con().await?.dump().await
+}
+
+/// Whether something was added, removed or changed between two dumps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difference {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl Difference {
+    fn symbol(self) -> char {
+        match self {
+            Self::Added => '+',
+            Self::Removed => '-',
+            Self::Changed => '~',
+        }
+    }
+}
+
+/// A metadata value that differs between two dumps.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MetadataDiff {
+    /// ID of the object the metadata is attached to, like `dns;[net]domain.com`.
+    pub obj_id: String,
+    pub key: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// The DNS names, records, nodes and metadata that differ between two dumps of the data store.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DumpDiff {
+    pub dns_names: Vec<(Difference, String)>,
+    /// DNS records as their qualified name and `plugin;rtype;value`.
+    pub records: Vec<(Difference, String, String)>,
+    /// Processed nodes by link ID.
+    pub nodes: Vec<(Difference, String)>,
+    pub metadata: Vec<MetadataDiff>,
+}
+
+impl DumpDiff {
+    pub fn new(old: &DataDump, new: &DataDump) -> Self {
+        let mut diff = DumpDiff::default();
+
+        let (old_names, new_names) = (members(old, DNS_KEY), members(new, DNS_KEY));
+        for (difference, qname) in set_diff(&old_names, &new_names) {
+            diff.dns_names.push((difference, qname.to_string()));
+        }
+
+        for qname in old_names.union(&new_names) {
+            let key = format!("{DNS_KEY};{qname}");
+            for (difference, record) in set_diff(&members(old, &key), &members(new, &key)) {
+                diff.records
+                    .push((difference, qname.to_string(), record.to_string()));
+            }
+        }
+
+        let (old_nodes, new_nodes) = (members(old, PROC_NODES_KEY), members(new, PROC_NODES_KEY));
+        for (difference, link_id) in set_diff(&old_nodes, &new_nodes) {
+            diff.nodes.push((difference, link_id.to_string()));
+        }
+        for link_id in old_nodes.intersection(&new_nodes) {
+            let changed = PROC_NODE_SUFFIXES.iter().any(|suffix| {
+                let key = format!("{PROC_NODES_KEY};{link_id}{suffix}");
+                old.keys.get(&key) != new.keys.get(&key)
+            });
+            if changed {
+                diff.nodes.push((Difference::Changed, link_id.to_string()));
+            }
+        }
+        diff.nodes.sort_by(|(_, lhs), (_, rhs)| lhs.cmp(rhs));
+
+        let (old_meta, new_meta) = (members(old, METADATA_KEY), members(new, METADATA_KEY));
+        for obj_id in old_meta.union(&new_meta) {
+            let key = format!("{METADATA_KEY};{obj_id}");
+            let (old_values, new_values) = (fields(old, &key), fields(new, &key));
+            let keys = old_values
+                .keys()
+                .chain(new_values.keys())
+                .filter(|key| !key.starts_with('_'))
+                .collect::<BTreeSet<_>>();
+
+            for meta_key in keys {
+                let (old_value, new_value) = (old_values.get(meta_key), new_values.get(meta_key));
+                if old_value != new_value {
+                    diff.metadata.push(MetadataDiff {
+                        obj_id: obj_id.to_string(),
+                        key: meta_key.to_string(),
+                        old: old_value.map(|value| value.to_string()),
+                        new: new_value.map(|value| value.to_string()),
+                    });
+                }
+            }
+        }
+
+        diff
+    }
+
+    /// Returns a line describing each difference.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![];
+        for (difference, qname) in &self.dns_names {
+            lines.push(format!("{} dns {qname}", difference.symbol()));
+        }
+        for (difference, qname, record) in &self.records {
+            lines.push(format!("{} record {qname} {record}", difference.symbol()));
+        }
+        for (difference, link_id) in &self.nodes {
+            lines.push(format!("{} node {link_id}", difference.symbol()));
+        }
+        for meta in &self.metadata {
+            lines.push(match (&meta.old, &meta.new) {
+                (None, Some(new)) => format!("+ meta {} {}: {new}", meta.obj_id, meta.key),
+                (Some(old), None) => format!("- meta {} {}: {old}", meta.obj_id, meta.key),
+                (old, new) => format!(
+                    "~ meta {} {}: {} -> {}",
+                    meta.obj_id,
+                    meta.key,
+                    old.as_deref().unwrap_or_default(),
+                    new.as_deref().unwrap_or_default()
+                ),
+            });
+        }
+        lines
+    }
+}
+
+/// Returns the members of the set at a key, or nothing if there is no set there.
+fn members<'a>(dump: &'a DataDump, key: &str) -> BTreeSet<&'a str> {
+    match dump.keys.get(key) {
+        Some(StoredValue::Set(members)) => members.iter().map(String::as_str).collect(),
+        _ => BTreeSet::new(),
+    }
+}
+
+/// Returns the fields of the hash at a key, or nothing if there is no hash there.
+fn fields<'a>(dump: &'a DataDump, key: &str) -> BTreeMap<&'a str, &'a str> {
+    match dump.keys.get(key) {
+        Some(StoredValue::Hash(fields)) => fields
+            .iter()
+            .map(|(field, value)| (field.as_str(), value.as_str()))
+            .collect(),
+        _ => BTreeMap::new(),
+    }
+}
+
+/// Returns the members added to and removed from a set, in order.
+fn set_diff<'a>(old: &BTreeSet<&'a str>, new: &BTreeSet<&'a str>) -> Vec<(Difference, &'a str)> {
+    let mut diff = new
+        .difference(old)
+        .map(|member| (Difference::Added, *member))
+        .chain(
+            old.difference(new)
+                .map(|member| (Difference::Removed, *member)),
+        )
+        .collect::<Vec<_>>();
+    diff.sort_by_key(|(_, member)| *member);
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use super::{Difference, DumpDiff, MetadataDiff};
+    use crate::data::model::{DataDump, StoredValue};
+
+    fn set(members: &[&str]) -> StoredValue {
+        StoredValue::Set(
+            members
+                .iter()
+                .map(|member| member.to_string())
+                .collect::<BTreeSet<_>>(),
+        )
+    }
+
+    fn hash(fields: &[(&str, &str)]) -> StoredValue {
+        StoredValue::Hash(
+            fields
+                .iter()
+                .map(|(field, value)| (field.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    fn dump(keys: Vec<(&str, StoredValue)>) -> DataDump {
+        DataDump {
+            keys: keys
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect::<BTreeMap<_, _>>(),
+            changelog: vec![],
+        }
+    }
+
+    #[test]
+    fn test_dump_diff() {
+        let old = dump(vec![
+            ("dns", set(&["[net]a.com", "[net]b.com"])),
+            ("dns;[net]a.com", set(&["plugin;A;[net]10.0.0.1"])),
+            ("proc_nodes", set(&["node-a", "node-b"])),
+            ("proc_nodes;node-a", hash(&[("name", "A")])),
+            ("meta", set(&["dns;[net]a.com"])),
+            (
+                "meta;dns;[net]a.com",
+                hash(&[("owner", "ops"), ("site", "hq"), ("_node", "node-a")]),
+            ),
+        ]);
+        let new = dump(vec![
+            ("dns", set(&["[net]a.com", "[net]c.com"])),
+            ("dns;[net]a.com", set(&["plugin;A;[net]10.0.0.2"])),
+            ("proc_nodes", set(&["node-a", "node-c"])),
+            ("proc_nodes;node-a", hash(&[("name", "Renamed")])),
+            ("meta", set(&["dns;[net]a.com"])),
+            (
+                "meta;dns;[net]a.com",
+                hash(&[("owner", "netops"), ("rack", "4"), ("_node", "node-c")]),
+            ),
+        ]);
+
+        let diff = DumpDiff::new(&old, &new);
+        assert_eq!(
+            diff.dns_names,
+            vec![
+                (Difference::Removed, "[net]b.com".to_string()),
+                (Difference::Added, "[net]c.com".to_string()),
+            ]
+        );
+        assert_eq!(
+            diff.records,
+            vec![
+                (
+                    Difference::Removed,
+                    "[net]a.com".to_string(),
+                    "plugin;A;[net]10.0.0.1".to_string()
+                ),
+                (
+                    Difference::Added,
+                    "[net]a.com".to_string(),
+                    "plugin;A;[net]10.0.0.2".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            diff.nodes,
+            vec![
+                (Difference::Changed, "node-a".to_string()),
+                (Difference::Removed, "node-b".to_string()),
+                (Difference::Added, "node-c".to_string()),
+            ]
+        );
+        assert_eq!(
+            diff.metadata,
+            vec![
+                MetadataDiff {
+                    obj_id: "dns;[net]a.com".to_string(),
+                    key: "owner".to_string(),
+                    old: Some("ops".to_string()),
+                    new: Some("netops".to_string()),
+                },
+                MetadataDiff {
+                    obj_id: "dns;[net]a.com".to_string(),
+                    key: "rack".to_string(),
+                    old: None,
+                    new: Some("4".to_string()),
+                },
+                MetadataDiff {
+                    obj_id: "dns;[net]a.com".to_string(),
+                    key: "site".to_string(),
+                    old: Some("hq".to_string()),
+                    new: None,
+                },
+            ]
+        );
+        assert_eq!(
+            diff.lines()[7],
+            "~ meta dns;[net]a.com owner: ops -> netops"
+        );
+    }
+}
@@ -8,6 +8,11 @@ pub enum NetdoxError {
     Plugin(String),
     /// Error with the redis database.
     Redis(String),
+    /// Error with the SQLite database.
+    #[cfg(feature = "sqlite")]
+    Sqlite(String),
+    /// Error with a data store whose logic runs in netdox instead of redis.
+    Store(String),
     /// Error with the processing logic.
     Process(String),
     /// Error with remote server.
@@ -37,6 +42,20 @@ macro_rules! redis_err {
     };
 }
 
+#[macro_export]
+macro_rules! sqlite_err {
+    ($err:expr) => {
+        Err(NetdoxError::Sqlite($err))
+    };
+}
+
+#[macro_export]
+macro_rules! store_err {
+    ($err:expr) => {
+        Err(NetdoxError::Store($err))
+    };
+}
+
 #[macro_export]
 macro_rules! process_err {
     ($err:expr) => {
@@ -64,6 +83,9 @@ impl Display for NetdoxError {
             Self::Config(msg) => write!(f, "Error with netdox config: {msg}"),
             Self::Plugin(msg) => write!(f, "Error with a plugin: {msg}"),
             Self::Redis(msg) => write!(f, "Error with the redis database: {msg}"),
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(msg) => write!(f, "Error with the SQLite database: {msg}"),
+            Self::Store(msg) => write!(f, "Error with the data store: {msg}"),
             Self::Process(msg) => write!(f, "Error during node processing: {msg}"),
             Self::Remote(msg) => write!(f, "Error while communicating with remote: {msg}"),
             Self::IO(msg) => write!(f, "Error during IO: {msg}"),
@@ -88,3 +110,10 @@ impl From<redis::RedisError> for NetdoxError {
         NetdoxError::Redis(value.to_string())
     }
 }
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for NetdoxError {
+    fn from(value: rusqlite::Error) -> Self {
+        NetdoxError::Sqlite(value.to_string())
+    }
+}
@@ -0,0 +1,416 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    fs,
+    path::Path,
+    process::exit,
+};
+
+use clap::ValueEnum;
+use itertools::Itertools;
+use tracing::error;
+
+use crate::{
+    config::LocalConfig,
+    data::{
+        model::{split_qname, Node, ADDRESS_RTYPES, DNS, DNS_KEY, PROC_NODES_KEY},
+        DataConn, DataStore,
+    },
+    error::{NetdoxError, NetdoxResult},
+    io_err, success, ExportCommand,
+};
+
+pub mod inventory;
+
+/// Label of the edges between DNS names in different networks.
+const TRANSLATION_LABEL: &str = "translation";
+
+/// Format to export a graph in.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// DOT, for Graphviz.
+    Graphviz,
+    /// A Mermaid flowchart.
+    Mermaid,
+}
+
+/// Limits the DNS names in a graph.
+#[derive(Debug, Default)]
+pub struct GraphFilter {
+    /// Only include DNS names in this network.
+    pub network: Option<String>,
+    /// Only include DNS names whose qualified or unqualified name starts with this.
+    pub prefix: Option<String>,
+}
+
+impl GraphFilter {
+    /// Returns true if the qualified DNS name is included.
+    fn matches(&self, qname: &str) -> bool {
+        let Some((network, name)) = split_qname(qname) else {
+            return false;
+        };
+
+        self.network.as_ref().is_none_or(|net| net == network)
+            && self.prefix.as_ref().is_none_or(|prefix| {
+                qname.starts_with(prefix.as_str()) || name.starts_with(prefix.as_str())
+            })
+    }
+}
+
+/// Graph of the DNS names that resolve to each other, and the processed nodes that use them.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Graph {
+    /// Maps the object ID of each vertex to its label and whether it is a processed node.
+    vertices: BTreeMap<String, (String, bool)>,
+    /// Edges from one object ID to another, with a label that may be empty.
+    edges: BTreeSet<(String, String, String)>,
+}
+
+impl Graph {
+    /// Builds a graph of the DNS names in the data store that match the filter,
+    /// the names their address records and translations point to,
+    /// and the processed nodes with any of them, with their dependencies and relations.
+    pub async fn build(con: &mut DataStore, filter: &GraphFilter) -> NetdoxResult<Self> {
+        let dns = con.get_dns().await?;
+        let mut graph = Graph::from_dns(&dns, filter);
+
+        let mut link_ids = vec![];
+        for link_id in con.get_node_ids().await?.into_iter().sorted() {
+            let node = con.get_node(&link_id).await?;
+            if graph.add_node(&node, filter) {
+                link_ids.push(link_id);
+            }
+        }
+
+        for link_id in &link_ids {
+            for dep in con.get_node_dependencies(link_id).await? {
+                if &dep.source == link_id {
+                    let label = dep.label.unwrap_or_else(|| "depends on".to_string());
+                    graph.add_edge(node_vertex(&dep.source), node_vertex(&dep.dest), label);
+                }
+            }
+            for relation in con.get_node_relations(link_id).await? {
+                if &relation.source == link_id {
+                    graph.add_edge(
+                        node_vertex(&relation.source),
+                        node_vertex(&relation.dest),
+                        relation.kind,
+                    );
+                }
+            }
+        }
+
+        Ok(graph)
+    }
+
+    /// Builds a graph of the DNS names that match the filter,
+    /// with edges for their address records and translations to the names they point to.
+    pub fn from_dns(dns: &DNS, filter: &GraphFilter) -> Self {
+        let mut graph = Graph::default();
+        for qname in dns.qnames.iter().filter(|qname| filter.matches(qname)) {
+            graph.add_dns(qname);
+            for record in dns.get_records(qname) {
+                if ADDRESS_RTYPES.contains(&record.rtype.as_str()) {
+                    graph.add_dns(&record.value);
+                    graph.add_edge(
+                        dns_vertex(qname),
+                        dns_vertex(&record.value),
+                        record.rtype.clone(),
+                    );
+                }
+            }
+            for dest in dns.get_translations(qname) {
+                graph.add_dns(dest);
+                graph.add_edge(
+                    dns_vertex(qname),
+                    dns_vertex(dest),
+                    TRANSLATION_LABEL.to_string(),
+                );
+            }
+        }
+        graph
+    }
+
+    fn add_dns(&mut self, qname: &str) {
+        self.vertices
+            .insert(dns_vertex(qname), (qname.to_string(), false));
+    }
+
+    /// Adds a processed node with edges to its DNS names, if any of them match the filter.
+    /// Returns true if the node was added.
+    pub fn add_node(&mut self, node: &Node, filter: &GraphFilter) -> bool {
+        if !node.dns_names.iter().any(|qname| filter.matches(qname)) {
+            return false;
+        }
+
+        let vertex = node_vertex(&node.link_id);
+        self.vertices
+            .insert(vertex.clone(), (node.name.clone(), true));
+        for qname in &node.dns_names {
+            self.add_edge(vertex.clone(), dns_vertex(qname), String::new());
+        }
+        true
+    }
+
+    /// Adds an edge if both of its vertices are in the graph.
+    fn add_edge(&mut self, from: String, to: String, label: String) {
+        if self.vertices.contains_key(&from) && self.vertices.contains_key(&to) {
+            self.edges.insert((from, to, label));
+        }
+    }
+
+    /// Renders the graph in a format.
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Graphviz => self.to_dot(),
+            GraphFormat::Mermaid => self.to_mermaid(),
+        }
+    }
+
+    /// Renders the graph as DOT, with processed nodes as boxes.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph netdox {\n    rankdir=LR;\n");
+        for (id, (label, is_node)) in &self.vertices {
+            let shape = if *is_node { ", shape=box" } else { "" };
+            let _ = writeln!(
+                dot,
+                "    \"{}\" [label=\"{}\"{shape}];",
+                dot_escape(id),
+                dot_escape(label)
+            );
+        }
+        for (from, to, label) in &self.edges {
+            let _ = if label.is_empty() {
+                writeln!(
+                    dot,
+                    "    \"{}\" -> \"{}\";",
+                    dot_escape(from),
+                    dot_escape(to)
+                )
+            } else {
+                writeln!(
+                    dot,
+                    "    \"{}\" -> \"{}\" [label=\"{}\"];",
+                    dot_escape(from),
+                    dot_escape(to),
+                    dot_escape(label)
+                )
+            };
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Renders the graph as a Mermaid flowchart, with processed nodes as rounded boxes.
+    /// Vertices are numbered, because Mermaid IDs cannot contain most of the characters in object IDs.
+    pub fn to_mermaid(&self) -> String {
+        let mut mermaid = String::from("flowchart LR\n");
+        let mut ids = BTreeMap::new();
+        for (idx, (id, (label, is_node))) in self.vertices.iter().enumerate() {
+            let label = mermaid_escape(label);
+            let _ = if *is_node {
+                writeln!(mermaid, "    v{idx}(\"{label}\")")
+            } else {
+                writeln!(mermaid, "    v{idx}[\"{label}\"]")
+            };
+            ids.insert(id, idx);
+        }
+        for (from, to, label) in &self.edges {
+            let (from, to) = (ids[from], ids[to]);
+            let _ = if label.is_empty() {
+                writeln!(mermaid, "    v{from} --> v{to}")
+            } else {
+                writeln!(
+                    mermaid,
+                    "    v{from} -->|\"{}\"| v{to}",
+                    mermaid_escape(label)
+                )
+            };
+        }
+        mermaid
+    }
+}
+
+fn dns_vertex(qname: &str) -> String {
+    format!("{DNS_KEY};{qname}")
+}
+
+fn node_vertex(link_id: &str) -> String {
+    format!("{PROC_NODES_KEY};{link_id}")
+}
+
+/// Escapes text for use in a quoted DOT ID.
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Escapes text for use in a quoted Mermaid label.
+fn mermaid_escape(text: &str) -> String {
+    text.replace('"', "#quot;")
+}
+
+/// Performs the given export command.
+#[tokio::main]
+pub async fn export(cmd: &ExportCommand) {
+    match cmd {
+        ExportCommand::Graph {
+            format,
+            network,
+            prefix,
+            output,
+        } => {
+            let filter = GraphFilter {
+                network: network.clone(),
+                prefix: prefix.clone(),
+            };
+            graph(*format, &filter, output.as_deref()).await;
+        }
+        ExportCommand::Inventory {
+            format,
+            meta,
+            output,
+        } => inventory::inventory(*format, meta, output).await,
+    }
+}
+
+/// Writes a graph of the data store to a file, or prints it if there is no path.
+async fn graph(format: GraphFormat, filter: &GraphFilter, output: Option<&Path>) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to export graph: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to export graph: {err}");
+            exit(1);
+        }
+    };
+
+    let graph = match Graph::build(&mut con, filter).await {
+        Ok(graph) => graph,
+        Err(err) => {
+            error!("Failed to build graph: {err}");
+            exit(1);
+        }
+    };
+
+    let content = graph.render(format);
+    match output {
+        Some(path) => match write_graph(path, &content) {
+            Ok(()) => success!(
+                "Wrote graph of {} vertices and {} edges to {}",
+                graph.vertices.len(),
+                graph.edges.len(),
+                path.display()
+            ),
+            Err(err) => {
+                error!("Failed to write graph: {err}");
+                exit(1);
+            }
+        },
+        None => print!("{content}"),
+    }
+}
+
+fn write_graph(path: &Path, content: &str) -> NetdoxResult<()> {
+    match fs::write(path, content) {
+        Ok(()) => Ok(()),
+        Err(err) => io_err!(format!(
+            "Failed to write graph to {}: {err}",
+            path.display()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{Graph, GraphFilter};
+    use crate::data::model::{DNSRecord, Node, DNS};
+
+    fn record(name: &str, rtype: &str, value: &str) -> DNSRecord {
+        DNSRecord {
+            name: name.to_string(),
+            value: value.to_string(),
+            rtype: rtype.to_string(),
+            plugin: "plugin".to_string(),
+        }
+    }
+
+    fn dns() -> DNS {
+        let mut dns = DNS::new();
+        dns.add_record(record("[net]www.com", "CNAME", "[net]web.com"));
+        dns.add_record(record("[net]web.com", "A", "[net]10.0.0.1"));
+        dns.add_record(record("[net]web.com", "TXT", "text"));
+        dns.add_record(record("[other]db.com", "A", "[other]10.0.0.2"));
+        dns.add_translation("[net]10.0.0.1", "[other]10.0.0.2");
+        dns
+    }
+
+    #[test]
+    fn test_graph_filter() {
+        let filter = GraphFilter {
+            network: Some("net".to_string()),
+            prefix: Some("web".to_string()),
+        };
+        let graph = Graph::from_dns(&dns(), &filter);
+
+        // Names that match point to names that don't.
+        assert_eq!(
+            graph.vertices.keys().collect::<Vec<_>>(),
+            vec!["dns;[net]10.0.0.1", "dns;[net]web.com"]
+        );
+        assert_eq!(graph.edges.len(), 1);
+
+        let graph = Graph::from_dns(&dns(), &GraphFilter::default());
+        assert_eq!(graph.vertices.len(), 5);
+        assert_eq!(graph.edges.len(), 4);
+    }
+
+    #[test]
+    fn test_graph_formats() {
+        let filter = GraphFilter {
+            prefix: Some("[net]web".to_string()),
+            ..Default::default()
+        };
+        let mut graph = Graph::from_dns(&dns(), &filter);
+        let node = Node {
+            name: "Web \"1\"".to_string(),
+            link_id: "web".to_string(),
+            alt_names: HashSet::new(),
+            dns_names: HashSet::from(["[net]web.com".to_string()]),
+            plugins: HashSet::new(),
+            raw_ids: HashSet::new(),
+        };
+        assert!(graph.add_node(&node, &filter));
+
+        assert_eq!(
+            graph.to_dot(),
+            "digraph netdox {
+    rankdir=LR;
+    \"dns;[net]10.0.0.1\" [label=\"[net]10.0.0.1\"];
+    \"dns;[net]web.com\" [label=\"[net]web.com\"];
+    \"proc_nodes;web\" [label=\"Web \\\"1\\\"\", shape=box];
+    \"dns;[net]web.com\" -> \"dns;[net]10.0.0.1\" [label=\"A\"];
+    \"proc_nodes;web\" -> \"dns;[net]web.com\";
+}
+"
+        );
+        assert_eq!(
+            graph.to_mermaid(),
+            "flowchart LR
+    v0[\"[net]10.0.0.1\"]
+    v1[\"[net]web.com\"]
+    v2(\"Web #quot;1#quot;\")
+    v1 -->|\"A\"| v0
+    v2 --> v1
+"
+        );
+    }
+}
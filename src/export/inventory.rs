@@ -0,0 +1,316 @@
+use std::{collections::BTreeSet, fs, net::IpAddr, path::Path, process::exit};
+
+use clap::ValueEnum;
+use itertools::Itertools;
+use rust_xlsxwriter::{Format, Workbook, XlsxError};
+use tracing::error;
+
+use crate::{
+    config::LocalConfig,
+    data::{
+        model::{split_qname, Node, DNS, LOCATIONS_META_KEY},
+        DataConn, DataStore,
+    },
+    error::{NetdoxError, NetdoxResult},
+    io_err,
+    query::links::{split_links, TextPart},
+    success,
+};
+
+/// Record types whose value is an address.
+const ADDRESS_RECORDS: [&str; 2] = ["A", "AAAA"];
+/// Separates the values in a cell with more than one.
+const CELL_SEP: &str = ", ";
+
+/// Format to export an inventory in.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InventoryFormat {
+    /// A CSV file for each table.
+    Csv,
+    /// An Excel workbook with a sheet for each table.
+    Xlsx,
+}
+
+/// A table with a header row.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Table {
+    /// Name of the table, used for the name of its file or sheet.
+    pub name: &'static str,
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Builds a table of the processed nodes, with a column for each of the metadata keys,
+/// and a table of the DNS records.
+pub async fn build(con: &mut DataStore, meta_keys: &[String]) -> NetdoxResult<Vec<Table>> {
+    let dns = con.get_dns().await?;
+
+    let mut nodes = vec![];
+    for link_id in con.get_node_ids().await?.into_iter().sorted() {
+        let node = con.get_node(&link_id).await?;
+        let metadata = con.get_node_metadata(&node).await?;
+        let mut values = vec![metadata.get(LOCATIONS_META_KEY).cloned()];
+        values.extend(meta_keys.iter().map(|key| metadata.get(key).cloned()));
+        nodes.push(node_row(&node, &dns, values));
+    }
+
+    let mut header = ["Name", "Link ID", "DNS Names", "Addresses", "Location"]
+        .into_iter()
+        .map(str::to_string)
+        .collect_vec();
+    header.extend(meta_keys.iter().cloned());
+    header.push("Plugins".to_string());
+
+    Ok(vec![
+        Table {
+            name: "nodes",
+            header,
+            rows: nodes,
+        },
+        Table {
+            name: "dns-records",
+            header: ["Network", "Name", "Type", "Value", "Plugin"]
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            rows: dns_rows(&dns),
+        },
+    ])
+}
+
+/// Returns the row for a node, given the values of its location and other metadata columns.
+/// The addresses of a node are its DNS names that are addresses,
+/// and the values of the address records of its other DNS names.
+fn node_row(node: &Node, dns: &DNS, values: Vec<Option<String>>) -> Vec<String> {
+    let mut addresses = BTreeSet::new();
+    for qname in &node.dns_names {
+        if let Some((_, name)) = split_qname(qname) {
+            if name.parse::<IpAddr>().is_ok() {
+                addresses.insert(name.to_string());
+            }
+        }
+        for record in dns.get_records(qname) {
+            if ADDRESS_RECORDS.contains(&record.rtype.as_str()) {
+                if let Some((_, addr)) = split_qname(&record.value) {
+                    addresses.insert(addr.to_string());
+                }
+            }
+        }
+    }
+
+    let mut row = vec![
+        node.name.clone(),
+        node.link_id.clone(),
+        node.dns_names.iter().sorted().join(CELL_SEP),
+        addresses.into_iter().join(CELL_SEP),
+    ];
+    row.extend(
+        values
+            .into_iter()
+            .map(|value| value.map(|value| plain_text(&value)).unwrap_or_default()),
+    );
+    row.push(node.plugins.iter().sorted().join(CELL_SEP));
+    row
+}
+
+/// Returns a row for each DNS record, ordered by name, type and value.
+fn dns_rows(dns: &DNS) -> Vec<Vec<String>> {
+    dns.records
+        .values()
+        .flatten()
+        .map(|record| {
+            let (network, name) = split_qname(&record.name).unwrap_or(("", &record.name));
+            vec![
+                network.to_string(),
+                name.to_string(),
+                record.rtype.clone(),
+                record.value.clone(),
+                record.plugin.clone(),
+            ]
+        })
+        .sorted_by(|a, b| (&a[1], &a[0], &a[2], &a[3]).cmp(&(&b[1], &b[0], &b[2], &b[3])))
+        .collect()
+}
+
+/// Replaces the links in a metadata value with the IDs they link to.
+fn plain_text(value: &str) -> String {
+    split_links(value)
+        .into_iter()
+        .map(|part| match part {
+            TextPart::Text(text) => text,
+            TextPart::Link { id, .. } => id,
+        })
+        .collect()
+}
+
+/// Writes each table to a CSV file in a directory, creating it if it doesn't exist.
+fn write_csv(dir: &Path, tables: &[Table]) -> NetdoxResult<()> {
+    if let Err(err) = fs::create_dir_all(dir) {
+        return io_err!(format!(
+            "Failed to create directory {}: {err}",
+            dir.display()
+        ));
+    }
+
+    for table in tables {
+        let path = dir.join(format!("{}.csv", table.name));
+        let result = csv::Writer::from_path(&path).and_then(|mut writer| {
+            writer.write_record(&table.header)?;
+            for row in &table.rows {
+                writer.write_record(row)?;
+            }
+            writer.flush()?;
+            Ok(())
+        });
+        if let Err(err) = result {
+            return io_err!(format!("Failed to write {}: {err}", path.display()));
+        }
+    }
+    Ok(())
+}
+
+/// Writes the tables to a workbook, with a sheet for each.
+fn write_xlsx(path: &Path, tables: &[Table]) -> NetdoxResult<()> {
+    let mut workbook = Workbook::new();
+    let bold = Format::new().set_bold();
+    let mut write = || -> Result<(), XlsxError> {
+        for table in tables {
+            let sheet = workbook.add_worksheet();
+            sheet.set_name(table.name)?;
+            for (col, title) in (0..).zip(&table.header) {
+                sheet.write_string_with_format(0, col, title, &bold)?;
+            }
+            for (row, values) in (1..).zip(&table.rows) {
+                for (col, value) in (0..).zip(values) {
+                    sheet.write_string(row, col, value)?;
+                }
+            }
+            sheet.set_freeze_panes(1, 0)?;
+            sheet.autofit();
+        }
+        workbook.save(path)
+    };
+
+    match write() {
+        Ok(()) => Ok(()),
+        Err(err) => io_err!(format!("Failed to write {}: {err}", path.display())),
+    }
+}
+
+/// Writes an inventory of the data store in a format.
+pub async fn inventory(format: InventoryFormat, meta_keys: &[String], output: &Path) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to export inventory: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to export inventory: {err}");
+            exit(1);
+        }
+    };
+
+    let tables = match build(&mut con, meta_keys).await {
+        Ok(tables) => tables,
+        Err(err) => {
+            error!("Failed to build inventory: {err}");
+            exit(1);
+        }
+    };
+
+    let result = match format {
+        InventoryFormat::Csv => write_csv(output, &tables),
+        InventoryFormat::Xlsx => write_xlsx(output, &tables),
+    };
+    match result {
+        Ok(()) => success!(
+            "Wrote inventory of {} nodes and {} DNS records to {}",
+            tables[0].rows.len(),
+            tables[1].rows.len(),
+            output.display()
+        ),
+        Err(err) => {
+            error!("Failed to export inventory: {err}");
+            exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{dns_rows, node_row, write_csv, Table};
+    use crate::data::model::{DNSRecord, Node, DNS};
+
+    fn record(name: &str, rtype: &str, value: &str) -> DNSRecord {
+        DNSRecord {
+            name: name.to_string(),
+            value: value.to_string(),
+            rtype: rtype.to_string(),
+            plugin: "plugin".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_inventory_rows() {
+        let mut dns = DNS::new();
+        dns.add_record(record("[net]web.com", "A", "[net]10.0.0.1"));
+        dns.add_record(record("[net]web.com", "TXT", "text"));
+        dns.add_record(record("[net]api.com", "CNAME", "[net]web.com"));
+        let node = Node {
+            name: "Web".to_string(),
+            link_id: "web".to_string(),
+            alt_names: HashSet::new(),
+            dns_names: HashSet::from(["[net]web.com".to_string(), "[net]10.0.0.2".to_string()]),
+            plugins: HashSet::from(["plugin".to_string()]),
+            raw_ids: HashSet::new(),
+        };
+
+        assert_eq!(
+            node_row(
+                &node,
+                &dns,
+                vec![Some("(!(procnode|!|dc-1)!) rack 4".to_string()), None]
+            ),
+            vec![
+                "Web",
+                "web",
+                "[net]10.0.0.2, [net]web.com",
+                "10.0.0.1, 10.0.0.2",
+                "dc-1 rack 4",
+                "",
+                "plugin"
+            ]
+        );
+        assert_eq!(
+            dns_rows(&dns)
+                .iter()
+                .map(|row| row[1..3].join(" "))
+                .collect::<Vec<_>>(),
+            vec!["api.com CNAME", "web.com A", "web.com TXT"]
+        );
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let dir = std::env::temp_dir().join(format!("netdox-inventory-{}", std::process::id()));
+        let table = Table {
+            name: "nodes",
+            header: vec!["Name".to_string(), "DNS Names".to_string()],
+            rows: vec![vec!["Web".to_string(), "a.com, b.com".to_string()]],
+        };
+
+        write_csv(&dir, &[table]).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("nodes.csv")).unwrap(),
+            "Name,DNS Names\nWeb,\"a.com, b.com\"\n"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
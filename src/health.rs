@@ -0,0 +1,282 @@
+use std::{
+    fmt::Display,
+    process::exit,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    config::{HealthConfig, LocalConfig},
+    data::{
+        model::{RunKind, RunRecord, MAX_RUNS},
+        DataConn, DataStore,
+    },
+    error::NetdoxResult,
+    runs::format_millis,
+};
+
+/// Outcome of a health check, in order of severity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HealthStatus {
+    Ok,
+    Warn,
+    Crit,
+}
+
+impl HealthStatus {
+    /// Exit code for the status, as expected by Nagios style monitors.
+    pub fn code(self) -> i32 {
+        match self {
+            Self::Ok => 0,
+            Self::Warn => 1,
+            Self::Crit => 2,
+        }
+    }
+}
+
+impl Display for HealthStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ok => write!(f, "OK"),
+            Self::Warn => write!(f, "WARNING"),
+            Self::Crit => write!(f, "CRITICAL"),
+        }
+    }
+}
+
+/// Result of a single health check.
+#[derive(Debug, PartialEq, Eq)]
+struct Check {
+    status: HealthStatus,
+    message: String,
+}
+
+impl Check {
+    fn new(status: HealthStatus, message: String) -> Self {
+        Check { status, message }
+    }
+}
+
+/// Checks the health of the data store and recent runs,
+/// then prints a summary and exits with the code for the worst status.
+#[tokio::main]
+pub async fn health() {
+    let checks = match LocalConfig::read() {
+        Ok(cfg) => match cfg.con().await {
+            Ok(mut con) => match run_checks(&mut con, &cfg.health).await {
+                Ok(checks) => checks,
+                Err(err) => vec![Check::new(
+                    HealthStatus::Crit,
+                    format!("Failed to read from data store: {err}"),
+                )],
+            },
+            Err(err) => vec![Check::new(
+                HealthStatus::Crit,
+                format!("Data store is unreachable: {err}"),
+            )],
+        },
+        Err(err) => vec![Check::new(
+            HealthStatus::Crit,
+            format!("Failed to read config: {err}"),
+        )],
+    };
+
+    let (status, summary) = summarise(&checks);
+    println!("NETDOX {status} - {summary}");
+    exit(status.code());
+}
+
+/// Runs the checks that require a connection to the data store.
+async fn run_checks(con: &mut DataStore, cfg: &HealthConfig) -> NetdoxResult<Vec<Check>> {
+    let runs = con.get_runs(MAX_RUNS).await?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    let mut checks = vec![update_age(&runs, now, cfg), failed_plugins(&runs, cfg)];
+
+    let last_publish = runs
+        .iter()
+        .find(|run| run.kind == RunKind::Publish && run.success);
+    checks.push(match last_publish {
+        Some(run) => {
+            // Changes made after the last successful publish started were not published by it.
+            let start = format!("{}-{}", (run.started * 1000).saturating_sub(1), u64::MAX);
+            let oldest = con.get_changes(Some(&start)).await?.into_iter().next();
+            let millis = oldest.and_then(|entry| entry.id.split('-').next()?.parse::<u64>().ok());
+            publish_lag(millis, now, cfg)
+        }
+        None => Check::new(
+            HealthStatus::Warn,
+            "No successful publish recorded".to_string(),
+        ),
+    });
+
+    Ok(checks)
+}
+
+/// Checks the time since the last successful update finished.
+fn update_age(runs: &[RunRecord], now: Duration, cfg: &HealthConfig) -> Check {
+    match runs
+        .iter()
+        .find(|run| run.kind == RunKind::Update && run.success)
+    {
+        Some(run) => {
+            let finished = Duration::from_secs(run.started) + Duration::from_millis(run.millis);
+            let age = now.saturating_sub(finished);
+            Check::new(
+                threshold(age, cfg.update_warn, cfg.update_crit),
+                format!("last successful update {} ago", format_age(age)),
+            )
+        }
+        None => Check::new(
+            HealthStatus::Crit,
+            "No successful update recorded".to_string(),
+        ),
+    }
+}
+
+/// Checks for plugins that failed in the most recent update.
+fn failed_plugins(runs: &[RunRecord], cfg: &HealthConfig) -> Check {
+    let failed = match runs.iter().find(|run| run.kind == RunKind::Update) {
+        Some(run) => run
+            .timings
+            .iter()
+            // Plugin timings are named stage/plugin.
+            .filter(|timing| !timing.success && timing.name.contains('/'))
+            .map(|timing| timing.name.as_str())
+            .collect::<Vec<_>>(),
+        None => vec![],
+    };
+
+    if failed.is_empty() {
+        Check::new(HealthStatus::Ok, "no failed plugins".to_string())
+    } else {
+        let status = if cfg.failed_plugins_crit {
+            HealthStatus::Crit
+        } else {
+            HealthStatus::Warn
+        };
+        Check::new(
+            status,
+            format!("failed plugins in last update: {}", failed.join(", ")),
+        )
+    }
+}
+
+/// Checks the age of the oldest unpublished change, given its timestamp in milliseconds.
+fn publish_lag(oldest: Option<u64>, now: Duration, cfg: &HealthConfig) -> Check {
+    match oldest {
+        Some(millis) => {
+            let lag = now.saturating_sub(Duration::from_millis(millis));
+            Check::new(
+                threshold(lag, cfg.publish_warn, cfg.publish_crit),
+                format!("oldest unpublished change {} ago", format_age(lag)),
+            )
+        }
+        None => Check::new(HealthStatus::Ok, "no unpublished changes".to_string()),
+    }
+}
+
+/// Returns the status for an age given thresholds in minutes.
+fn threshold(age: Duration, warn: u64, crit: u64) -> HealthStatus {
+    let mins = age.as_secs() / 60;
+    if mins >= crit {
+        HealthStatus::Crit
+    } else if mins >= warn {
+        HealthStatus::Warn
+    } else {
+        HealthStatus::Ok
+    }
+}
+
+fn format_age(age: Duration) -> String {
+    format_millis(u64::try_from(age.as_millis()).unwrap_or(u64::MAX))
+}
+
+/// Returns the worst status of the checks and their messages, worst first.
+fn summarise(checks: &[Check]) -> (HealthStatus, String) {
+    let mut sorted = checks.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|check| std::cmp::Reverse(check.status));
+
+    let status = sorted
+        .first()
+        .map_or(HealthStatus::Ok, |check| check.status);
+    let summary = sorted
+        .iter()
+        .map(|check| check.message.as_str())
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    (status, summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{failed_plugins, summarise, threshold, update_age, Check, HealthStatus};
+    use crate::{
+        config::HealthConfig,
+        data::model::{RunKind, RunRecord, RunTiming},
+    };
+
+    fn run(kind: RunKind, started: u64, success: bool, timings: Vec<RunTiming>) -> RunRecord {
+        RunRecord {
+            kind,
+            started,
+            millis: 60_000,
+            success,
+            timings,
+            stages: vec![],
+        }
+    }
+
+    #[test]
+    fn test_threshold() {
+        let mins = |mins: u64| Duration::from_secs(mins * 60);
+        assert_eq!(threshold(mins(10), 20, 40), HealthStatus::Ok);
+        assert_eq!(threshold(mins(20), 20, 40), HealthStatus::Warn);
+        assert_eq!(threshold(mins(50), 20, 40), HealthStatus::Crit);
+    }
+
+    #[test]
+    fn test_update_checks() {
+        let cfg = HealthConfig::default();
+        let now = Duration::from_secs(100_000);
+        let failed = RunTiming {
+            name: "write-only/broken".to_string(),
+            millis: 10,
+            success: false,
+            skipped: false,
+        };
+
+        // Newest first, as returned by the data store.
+        let runs = vec![
+            run(RunKind::Publish, 99_000, true, vec![]),
+            run(RunKind::Update, 98_000, false, vec![failed]),
+            run(RunKind::Update, 90_000, true, vec![]),
+        ];
+
+        assert_eq!(update_age(&runs, now, &cfg).status, HealthStatus::Ok);
+        assert_eq!(
+            failed_plugins(&runs, &cfg),
+            Check::new(
+                HealthStatus::Warn,
+                "failed plugins in last update: write-only/broken".to_string()
+            )
+        );
+        assert_eq!(update_age(&runs[..2], now, &cfg).status, HealthStatus::Crit);
+    }
+
+    #[test]
+    fn test_summarise() {
+        let (status, summary) = summarise(&[
+            Check::new(HealthStatus::Ok, "fine".to_string()),
+            Check::new(HealthStatus::Crit, "broken".to_string()),
+            Check::new(HealthStatus::Warn, "odd".to_string()),
+        ]);
+
+        assert_eq!(status, HealthStatus::Crit);
+        assert_eq!(summary, "broken; odd; fine");
+    }
+}
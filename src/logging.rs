@@ -0,0 +1,485 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Debug, Write as _},
+    fs::{File, OpenOptions},
+    io::{stderr, stdout, IsTerminal, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    subscriber::Interest,
+    Event, Level, Metadata, Subscriber,
+};
+
+use crate::{
+    error::{NetdoxError, NetdoxResult},
+    io_err,
+};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables quiet output for the rest of the process.
+/// Quiet output replaces progress bars with periodic log lines.
+pub fn set_quiet(enabled: bool) {
+    QUIET.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns true if quiet output is enabled.
+pub fn quiet_enabled() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Logs that something succeeded, at the info level.
+#[macro_export]
+macro_rules! success {
+    ($($arg:tt)*) => {
+        tracing::info!(success = true, $($arg)*)
+    };
+}
+
+/// Format of log lines.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Coloured lines for reading in a terminal.
+    #[default]
+    Text,
+    /// One JSON object per line, for log pipelines.
+    Json,
+}
+
+/// Field that marks an info event as a success.
+const SUCCESS_FIELD: &str = "success";
+/// Field that holds the message of an event.
+const MESSAGE_FIELD: &str = "message";
+
+/// Fields of an event or span.
+#[derive(Default)]
+struct Fields {
+    message: Option<String>,
+    values: Map<String, Value>,
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == MESSAGE_FIELD {
+            self.message = Some(format!("{value:?}"));
+        } else {
+            self.values
+                .insert(field.name().to_string(), json!(format!("{value:?}")));
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == MESSAGE_FIELD {
+            self.message = Some(value.to_string());
+        } else {
+            self.values.insert(field.name().to_string(), json!(value));
+        }
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.values.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.values.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.values.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.values.insert(field.name().to_string(), json!(value));
+    }
+}
+
+/// A span that has not been closed yet.
+struct SpanData {
+    name: &'static str,
+    fields: Map<String, Value>,
+    parent: Option<Id>,
+    refs: usize,
+}
+
+thread_local! {
+    /// Spans entered on this thread, innermost last.
+    static ENTERED: RefCell<Vec<Id>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Writes events to the terminal, and optionally a file, in some format.
+/// Events and spans from netdox are enabled down to the debug level if debug logging is on,
+/// and events from dependencies only if they are warnings or errors.
+pub struct Logger {
+    level: Level,
+    format: LogFormat,
+    colour: bool,
+    file: Option<Mutex<File>>,
+    spans: Mutex<HashMap<u64, SpanData>>,
+    next_id: AtomicU64,
+}
+
+impl Logger {
+    pub fn new(debug: bool, format: LogFormat, file: Option<&Path>) -> NetdoxResult<Self> {
+        let file = match file {
+            None => None,
+            Some(path) => match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => Some(Mutex::new(file)),
+                Err(err) => {
+                    return io_err!(format!("Failed to open log file {}: {err}", path.display()))
+                }
+            },
+        };
+
+        Ok(Logger {
+            level: if debug { Level::DEBUG } else { Level::INFO },
+            format,
+            colour: format == LogFormat::Text && stdout().is_terminal(),
+            file,
+            spans: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    /// Returns the names and fields of the spans entered on this thread, outermost first.
+    fn context(&self) -> Vec<(&'static str, Map<String, Value>)> {
+        let Ok(spans) = self.spans.lock() else {
+            return vec![];
+        };
+
+        let mut context = vec![];
+        let mut next = ENTERED.with(|entered| entered.borrow().last().cloned());
+        while let Some(id) = next {
+            let Some(span) = spans.get(&id.into_u64()) else {
+                break;
+            };
+            context.push((span.name, span.fields.clone()));
+            next = span.parent.clone();
+        }
+        context.reverse();
+        context
+    }
+
+    /// Formats an event as a line of text, with or without colour.
+    fn text_line(
+        &self,
+        level: Level,
+        fields: &Fields,
+        context: &[(&'static str, Map<String, Value>)],
+        colour: bool,
+    ) -> String {
+        let success = fields.values.get(SUCCESS_FIELD) == Some(&Value::Bool(true));
+        let (icon, code) = match level {
+            Level::ERROR => ("✖", "31"),
+            Level::WARN => ("⚠", "33"),
+            Level::INFO if success => ("✔", "32"),
+            Level::INFO => ("ℹ", "36"),
+            _ => ("[debug]", "2"),
+        };
+
+        let mut line = if colour {
+            format!("\x1b[{code}m{icon}\x1b[0m ")
+        } else {
+            format!("{icon} ")
+        };
+        line.push_str(fields.message.as_deref().unwrap_or_default());
+
+        // Span context and extra fields are only shown when debugging, to keep normal output short.
+        if self.level == Level::DEBUG {
+            let mut extra = String::new();
+            for (name, span_fields) in context {
+                extra.push_str(name);
+                for (key, value) in span_fields {
+                    let _ = write!(extra, " {key}={}", text_value(value));
+                }
+                extra.push_str("; ");
+            }
+            for (key, value) in &fields.values {
+                if key != SUCCESS_FIELD {
+                    let _ = write!(extra, "{key}={} ", text_value(value));
+                }
+            }
+            let extra = extra.trim_end_matches([';', ' ']);
+            if !extra.is_empty() {
+                if colour {
+                    let _ = write!(line, " \x1b[2m[{extra}]\x1b[0m");
+                } else {
+                    let _ = write!(line, " [{extra}]");
+                }
+            }
+        }
+
+        line
+    }
+
+    /// Formats an event as a JSON object.
+    fn json_line(
+        metadata: &Metadata,
+        fields: Fields,
+        context: Vec<(&'static str, Map<String, Value>)>,
+    ) -> String {
+        let spans = context
+            .into_iter()
+            .map(|(name, mut fields)| {
+                fields.insert("name".to_string(), json!(name));
+                Value::Object(fields)
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            "level": metadata.level().as_str(),
+            "target": metadata.target(),
+            "message": fields.message.unwrap_or_default(),
+            "fields": fields.values,
+            "spans": spans,
+        })
+        .to_string()
+    }
+}
+
+/// Formats a field value for a line of text.
+fn text_value(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        other => other.to_string(),
+    }
+}
+
+impl Subscriber for Logger {
+    fn register_callsite(&self, _metadata: &'static Metadata<'static>) -> Interest {
+        // Callsite interest is cached for every logger, so each one checks every callsite itself.
+        Interest::sometimes()
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        if metadata.target().starts_with(env!("CARGO_CRATE_NAME")) {
+            metadata.level() <= &self.level
+        } else {
+            metadata.level() <= &Level::WARN
+        }
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let id = Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let mut fields = Fields::default();
+        span.record(&mut fields);
+        let parent = if span.is_root() {
+            None
+        } else {
+            span.parent()
+                .cloned()
+                .or_else(|| ENTERED.with(|entered| entered.borrow().last().cloned()))
+        };
+
+        if let Ok(mut spans) = self.spans.lock() {
+            if let Some(parent) = parent.as_ref().and_then(|id| spans.get_mut(&id.into_u64())) {
+                parent.refs += 1;
+            }
+            spans.insert(
+                id.into_u64(),
+                SpanData {
+                    name: span.metadata().name(),
+                    fields: fields.values,
+                    parent,
+                    refs: 1,
+                },
+            );
+        }
+        id
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        if let Ok(mut spans) = self.spans.lock() {
+            if let Some(span) = spans.get_mut(&span.into_u64()) {
+                span.fields.extend(fields.values);
+            }
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let metadata = event.metadata();
+        let level = *metadata.level();
+        let context = self.context();
+
+        let (terminal, file) = match self.format {
+            LogFormat::Text => (
+                self.text_line(level, &fields, &context, self.colour),
+                format!(
+                    "{} {}",
+                    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                    self.text_line(level, &fields, &context, false)
+                ),
+            ),
+            LogFormat::Json => {
+                let line = Self::json_line(metadata, fields, context);
+                (line.clone(), line)
+            }
+        };
+
+        // Errors and debug output go to stderr, and everything else to stdout.
+        if level == Level::ERROR || level == Level::DEBUG {
+            let _ = writeln!(stderr(), "{terminal}");
+        } else {
+            let _ = writeln!(stdout(), "{terminal}");
+        }
+
+        if let Some(Ok(mut log_file)) = self.file.as_ref().map(Mutex::lock) {
+            let _ = writeln!(log_file, "{file}");
+        }
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.clone()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(index) = entered.iter().rposition(|id| id == span) {
+                entered.remove(index);
+            }
+        });
+    }
+
+    fn clone_span(&self, id: &Id) -> Id {
+        if let Ok(mut spans) = self.spans.lock() {
+            if let Some(span) = spans.get_mut(&id.into_u64()) {
+                span.refs += 1;
+            }
+        }
+        id.clone()
+    }
+
+    fn try_close(&self, id: Id) -> bool {
+        let Ok(mut spans) = self.spans.lock() else {
+            return false;
+        };
+
+        // Closing a span releases its reference to its parent.
+        let mut next = Some(id.clone());
+        let mut closed = false;
+        while let Some(current) = next.take() {
+            let Some(span) = spans.get_mut(&current.into_u64()) else {
+                break;
+            };
+            span.refs = span.refs.saturating_sub(1);
+            if span.refs > 0 {
+                break;
+            }
+            let span = spans.remove(&current.into_u64());
+            closed |= current == id;
+            next = span.and_then(|span| span.parent);
+        }
+        closed
+    }
+}
+
+/// Returns a URL without any username and password in it, so it can be logged.
+pub fn redact_url(url: &str) -> String {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return url.to_string();
+    };
+    let authority = rest.split('/').next().unwrap_or_default();
+    match authority.rsplit_once('@') {
+        Some((_, host)) => format!("{scheme}://{host}{}", &rest[authority.len()..]),
+        None => url.to_string(),
+    }
+}
+
+/// Sets up logging for the rest of the process.
+pub fn init(debug: bool, format: LogFormat, file: Option<&Path>) -> NetdoxResult<()> {
+    let logger = Logger::new(debug, format, file)?;
+    match tracing::subscriber::set_global_default(logger) {
+        Ok(()) => Ok(()),
+        Err(err) => io_err!(format!("Failed to set up logging: {err}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use serde_json::Value;
+    use tracing::{debug, info_span};
+
+    use super::{redact_url, LogFormat, Logger};
+
+    #[test]
+    fn test_redact_url() {
+        assert_eq!(
+            redact_url("rediss://netdox:p@ss@localhost:6379/15"),
+            "rediss://localhost:6379/15"
+        );
+        assert_eq!(redact_url("redis://:password@host/0"), "redis://host/0");
+        assert_eq!(redact_url("redis://host:6379/0"), "redis://host:6379/0");
+        assert_eq!(
+            redact_url("redis://host/0?user=a@b"),
+            "redis://host/0?user=a@b"
+        );
+        assert_eq!(redact_url("not a url"), "not a url");
+    }
+
+    /// Logs some events with a logger that writes to a file, and returns the lines of the file.
+    fn log_lines(debug: bool, format: LogFormat) -> Vec<String> {
+        let path = env::temp_dir().join(format!(
+            "netdox-log-{}-{debug}-{format:?}.log",
+            std::process::id()
+        ));
+        let logger = Logger::new(debug, format, Some(&path)).unwrap();
+        tracing::subscriber::with_default(logger, || {
+            let span = info_span!("stage", stage = "write-only");
+            let _entered = span.enter();
+            debug!(count = 3, "Found some things.");
+            success!("Finished stage.");
+            debug!(target: "dependency", "Ignored.");
+        });
+
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let lines = log_lines(true, LogFormat::Json)
+            .iter()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+
+        assert_eq!(lines[0]["level"], "DEBUG");
+        assert_eq!(lines[0]["message"], "Found some things.");
+        assert_eq!(lines[0]["fields"]["count"], 3);
+        assert_eq!(lines[0]["spans"][0]["name"], "stage");
+        assert_eq!(lines[0]["spans"][0]["stage"], "write-only");
+        assert_eq!(lines[1]["level"], "INFO");
+        assert_eq!(lines[1]["fields"]["success"], true);
+    }
+
+    #[test]
+    fn test_text_lines() {
+        let lines = log_lines(false, LogFormat::Text);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with(" ✔ Finished stage."), "{}", lines[0]);
+
+        let lines = log_lines(true, LogFormat::Text);
+        assert!(lines[0].ends_with(" [debug] Found some things. [stage stage=write-only; count=3]"));
+    }
+}
@@ -1,12 +1,34 @@
 mod changelog;
 
-use crate::data::model::{DNSRecord, DNS_KEY, NODES_KEY, PDATA_KEY, REPORTS_KEY};
+use crate::data::model::{
+    DNSRecord, Node, Relation, CHANGELOG_KEY, DNS_KEY, NODES_KEY, PDATA_KEY, PROC_NODES_KEY,
+    REPORTS_KEY, ROLE_META_KEY, TAGS_META_KEY,
+};
+use crate::data::store::redis_cluster::KEY_TAG;
 use crate::data::DataConn;
+use crate::dev::function_tests::test_functions;
 use crate::tests_common::*;
 use redis::AsyncCommands;
 use std::collections::{HashMap, HashSet};
+use std::env;
 use std::ops::Index;
 
+/// Name of the environment variable that contains the URL of an empty redis database
+/// to run the tests of `netdox dev test` in.
+const TEST_FUNCTIONS_REDIS_URL_VAR: &str = "NETDOX_TEST_FUNCTIONS_REDIS_URL";
+
+/// Runs the tests of `netdox dev test` in a database of their own,
+/// since they need an empty database and reset the changelog.
+/// They refuse to run if it has any keys.
+#[tokio::test]
+async fn test_dev_function_tests() {
+    let url = env::var(TEST_FUNCTIONS_REDIS_URL_VAR).unwrap_or_else(|_| {
+        panic!("Environment variable {TEST_FUNCTIONS_REDIS_URL_VAR} must be set to test the functions of netdox dev test.")
+    });
+
+    assert_eq!(test_functions(&url, true).await.unwrap(), 0);
+}
+
 #[tokio::test]
 async fn test_create_dns_noval_unqualified() {
     let mut con = setup_db_con().await;
@@ -20,6 +42,95 @@ async fn test_create_dns_noval_unqualified() {
     assert!(con.get_dns_names().await.unwrap().contains(&qname))
 }
 
+#[tokio::test]
+async fn test_qualify_network_suffix() {
+    let mut con = setup_db_con().await;
+    let inferred = format!("host.{SUFFIX}");
+    let qualified = format!("[{DEFAULT_NETWORK}]host.{SUFFIX}");
+
+    let qnames = con
+        .qualify_dns_names(&[&inferred, SUFFIX, &qualified, "other.com"])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        qnames,
+        vec![
+            format!("[{SUFFIX_NETWORK}]{inferred}"),
+            format!("[{SUFFIX_NETWORK}]{SUFFIX}"),
+            qualified,
+            format!("[{DEFAULT_NETWORK}]other.com"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_create_dns_ignore_pattern() {
+    let mut con = setup_db_con().await;
+    let function = "netdox_create_dns";
+    let ignored = "host.DYN.ignored.test";
+    let kept = "dyn.ignored.test";
+
+    call_fn(&mut con, function, &["1", ignored, PLUGIN]).await;
+    call_fn(&mut con, function, &["1", kept, PLUGIN]).await;
+
+    let names = con.get_dns_names().await.unwrap();
+    assert!(!names.contains(&format!("[{DEFAULT_NETWORK}]host.dyn.ignored.test")));
+    assert!(names.contains(&format!("[{DEFAULT_NETWORK}]{kept}")));
+    assert_eq!(
+        con.get_dns_ignore_patterns().await.unwrap(),
+        HashSet::from([IGNORE_PATTERN.to_string()])
+    );
+}
+
+#[tokio::test]
+async fn test_create_dns_plugin_scope() {
+    let mut con = setup_db_con().await;
+    let qualify = |name: &str| format!("[{DEFAULT_NETWORK}]{name}");
+    let inside = format!("www.{SCOPE_ZONE}");
+    let outside = "cdn.outside-scope.test";
+
+    call_fn(
+        &mut con,
+        "netdox_create_dns",
+        &["1", &inside, SCOPED_PLUGIN, "CNAME", outside],
+    )
+    .await;
+    call_fn(
+        &mut con,
+        "netdox_create_dns",
+        &["1", &inside, SCOPED_PLUGIN, "A", "192.168.0.50"],
+    )
+    .await;
+    call_fn(
+        &mut con,
+        "netdox_create_node",
+        &["2", &inside, outside, SCOPED_PLUGIN, "scoped-node"],
+    )
+    .await;
+
+    let dns = con.get_dns().await.unwrap();
+    assert!(dns.qnames.contains(&qualify(&inside)));
+    assert!(!dns.qnames.contains(&qualify(outside)));
+    assert_eq!(
+        dns.get_records(&qualify(&inside))
+            .into_iter()
+            .map(|record| record.rtype.as_str())
+            .collect::<Vec<_>>(),
+        vec!["A"]
+    );
+
+    let node_id = format!("{};{}", qualify(outside), qualify(&inside));
+    assert!(!con
+        .sismember::<_, _, bool>(NODES_KEY, &node_id)
+        .await
+        .unwrap());
+    assert_eq!(
+        con.get_scope_violations().await.unwrap().get(SCOPED_PLUGIN),
+        Some(&HashSet::from([qualify(outside)]))
+    );
+}
+
 #[tokio::test]
 async fn test_create_dns_noval_qualified() {
     let mut con = setup_db_con().await;
@@ -448,6 +559,284 @@ async fn test_create_dns_metadata_new() {
     assert_eq!(result_details.get(key2), Some(&val2.to_string()));
 }
 
+#[tokio::test]
+async fn test_create_dns_metadata_provenance() {
+    let mut con = setup_db_con().await;
+    let function = "netdox_create_dns_metadata";
+    let name = "metadata-provenance.com";
+    let qname = format!("[{DEFAULT_NETWORK}]{name}");
+
+    call_fn(&mut con, function, &["1", name, PLUGIN, "os", "linux"]).await;
+    call_fn(
+        &mut con,
+        function,
+        &["1", name, "other-plugin", "os", "bsd"],
+    )
+    .await;
+    // Reporting the same value again does not change the source.
+    call_fn(&mut con, function, &["1", name, PLUGIN, "os", "bsd"]).await;
+
+    let provenance = con.get_dns_metadata_provenance(&qname).await.unwrap();
+    let os = &provenance["os"];
+    assert_eq!(os.value, "bsd");
+    assert_eq!(os.plugin.as_deref(), Some("other-plugin"));
+    assert!(os.changed.is_some_and(|changed| changed > 0));
+}
+
+#[tokio::test]
+async fn test_create_node_role() {
+    let mut con = setup_db_con().await;
+    let function = "netdox_create_node";
+
+    let domain = "role-node.com";
+    let ip = "192.168.0.40";
+    let qnames = format!("[{DEFAULT_NETWORK}]{ip};[{DEFAULT_NETWORK}]{domain}");
+
+    call_fn(
+        &mut con,
+        function,
+        &[
+            "2",
+            domain,
+            ip,
+            PLUGIN,
+            "role-node",
+            "false",
+            "role-node-id",
+            ROLE,
+        ],
+    )
+    .await;
+
+    let result_details: HashMap<String, String> = con
+        .hgetall(format!("meta;{NODES_KEY};{qnames}"))
+        .await
+        .expect("Failed hgetall.");
+
+    assert_eq!(result_details.get(ROLE_META_KEY), Some(&ROLE.to_string()));
+
+    let invalid_domain = "invalid-role-node.com";
+    let invalid_node: redis::RedisResult<()> = redis::cmd("fcall")
+        .arg(function)
+        .arg(&["1", invalid_domain, PLUGIN, "invalid-role-node"])
+        .arg(&["false", "invalid-role-node-id", "not a role"])
+        .query_async(&mut con)
+        .await;
+    let invalid_meta: redis::RedisResult<()> = redis::cmd("fcall")
+        .arg("netdox_create_node_metadata")
+        .arg(&["2", domain, ip, PLUGIN, ROLE_META_KEY, "not a role"])
+        .query_async(&mut con)
+        .await;
+
+    let invalid_exists: bool = con
+        .sismember(NODES_KEY, format!("[{DEFAULT_NETWORK}]{invalid_domain}"))
+        .await
+        .expect("Failed sismember.");
+    let result_role: Option<String> = con
+        .hget(format!("meta;{NODES_KEY};{qnames}"), ROLE_META_KEY)
+        .await
+        .expect("Failed hget.");
+
+    assert!(invalid_node.is_err());
+    assert!(invalid_meta.is_err());
+    assert!(!invalid_exists);
+    assert_eq!(result_role.as_deref(), Some(ROLE));
+}
+
+#[tokio::test]
+async fn test_create_dns_tags() {
+    let mut con = setup_db_con().await;
+    let name = "tags.com";
+    let qname = format!("[{DEFAULT_NETWORK}]{name}");
+    let obj_id = format!("{DNS_KEY};{qname}");
+
+    call_fn(
+        &mut con,
+        "netdox_create_dns_tags",
+        &["1", name, PLUGIN, "Production", " web "],
+    )
+    .await;
+    call_fn(
+        &mut con,
+        "netdox_create_dns_metadata",
+        &[
+            "1",
+            name,
+            PLUGIN,
+            TAGS_META_KEY,
+            "billing, ,web",
+            "key",
+            "value",
+        ],
+    )
+    .await;
+
+    let invalid: redis::RedisResult<()> = redis::cmd("fcall")
+        .arg("netdox_create_dns_tags")
+        .arg(&["1", name, PLUGIN, "not a tag"])
+        .query_async(&mut con)
+        .await;
+
+    let details: HashMap<String, String> = con
+        .hgetall(format!("meta;{obj_id}"))
+        .await
+        .expect("Failed hgetall.");
+
+    assert!(invalid.is_err());
+    assert_eq!(details.get(TAGS_META_KEY), None);
+    assert_eq!(details.get("key"), Some(&"value".to_string()));
+    assert_eq!(
+        con.get_dns_tags(&qname).await.unwrap(),
+        HashSet::from([
+            "production".to_string(),
+            "web".to_string(),
+            "billing".to_string()
+        ])
+    );
+    assert!(con.get_tags().await.unwrap().contains("billing"));
+    assert!(con.get_tagged("web").await.unwrap().contains(&obj_id));
+}
+
+#[tokio::test]
+async fn test_create_node_tags() {
+    let mut con = setup_db_con().await;
+    let domain = "tags-node.com";
+    let ip = "192.168.0.41";
+    let link_id = "tags-node-id";
+
+    call_fn(
+        &mut con,
+        "netdox_create_node",
+        &["2", domain, ip, PLUGIN, "tags-node", "false", link_id],
+    )
+    .await;
+    call_fn(
+        &mut con,
+        "netdox_create_node_tags",
+        &["2", domain, ip, PLUGIN, "raw-tag"],
+    )
+    .await;
+    call_fn(
+        &mut con,
+        "netdox_create_proc_node_tags",
+        &["1", link_id, PLUGIN, "proc-tag"],
+    )
+    .await;
+
+    let raw_id = format!("[{DEFAULT_NETWORK}]{ip};[{DEFAULT_NETWORK}]{domain}");
+    let node = Node {
+        name: "tags-node".to_string(),
+        link_id: link_id.to_string(),
+        alt_names: HashSet::new(),
+        dns_names: HashSet::new(),
+        plugins: HashSet::new(),
+        raw_ids: HashSet::from([raw_id.clone()]),
+    };
+
+    assert_eq!(
+        con.get_node_tags(&node).await.unwrap(),
+        HashSet::from(["raw-tag".to_string(), "proc-tag".to_string()])
+    );
+    assert!(con
+        .get_tagged("raw-tag")
+        .await
+        .unwrap()
+        .contains(&format!("{NODES_KEY};{raw_id}")));
+}
+
+#[tokio::test]
+async fn test_create_node_relation() {
+    let _lock = PROC_NODES_LOCK.read().await;
+    let mut con = setup_db_con().await;
+    let (vm, host) = ("relation-vm-id", "relation-host-id");
+    let _: () = con.sadd(PROC_NODES_KEY, &[vm, host]).await.unwrap();
+
+    call_fn(
+        &mut con,
+        "netdox_create_node_relation",
+        &["2", vm, host, PLUGIN, "VM-On-Host"],
+    )
+    .await;
+
+    for args in [
+        ["2", vm, host, PLUGIN, "not;a relation"],
+        ["2", vm, vm, PLUGIN, "vm-on-host"],
+        ["2", vm, "no-such-node", PLUGIN, "vm-on-host"],
+    ] {
+        let invalid: redis::RedisResult<()> = redis::cmd("fcall")
+            .arg("netdox_create_node_relation")
+            .arg(&args)
+            .query_async(&mut con)
+            .await;
+        assert!(invalid.is_err());
+    }
+
+    let expected = vec![Relation {
+        source: vm.to_string(),
+        dest: host.to_string(),
+        kind: "vm-on-host".to_string(),
+    }];
+    assert_eq!(con.get_node_relations(vm).await.unwrap(), expected);
+    assert_eq!(con.get_node_relations(host).await.unwrap(), expected);
+}
+
+#[tokio::test]
+async fn test_create_node_service() {
+    let _lock = PROC_NODES_LOCK.read().await;
+    let mut con = setup_db_con().await;
+    let link_id = "service-node-id";
+    let _: () = con.sadd(PROC_NODES_KEY, link_id).await.unwrap();
+
+    call_fn(
+        &mut con,
+        "netdox_create_node_service",
+        &["1", link_id, PLUGIN, "443", "TCP", "product", "nginx"],
+    )
+    .await;
+    call_fn(
+        &mut con,
+        "netdox_create_node_service",
+        &[
+            "1",
+            link_id,
+            "other-plugin",
+            "443",
+            "tcp",
+            "version",
+            "1.25",
+        ],
+    )
+    .await;
+
+    for args in [
+        ["1", link_id, PLUGIN, "70000", "tcp"],
+        ["1", link_id, PLUGIN, "22", "not a protocol"],
+        ["1", "no-such-node", PLUGIN, "22", "tcp"],
+    ] {
+        let invalid: redis::RedisResult<()> = redis::cmd("fcall")
+            .arg("netdox_create_node_service")
+            .arg(&args)
+            .query_async(&mut con)
+            .await;
+        assert!(invalid.is_err());
+    }
+
+    let services = con.get_node_services(link_id).await.unwrap();
+    assert_eq!(services.len(), 1);
+    assert_eq!(services[0].id(), "443/tcp");
+    assert_eq!(
+        services[0].details,
+        HashMap::from([
+            ("product".to_string(), "nginx".to_string()),
+            ("version".to_string(), "1.25".to_string()),
+        ])
+    );
+    assert_eq!(
+        services[0].plugins,
+        HashSet::from([PLUGIN.to_string(), "other-plugin".to_string()])
+    );
+}
+
 #[tokio::test]
 async fn test_create_node_metadata_linkable() {
     let mut con = setup_db_con().await;
@@ -982,6 +1371,58 @@ async fn test_create_node_pdata_table() {
     assert_eq!(result_details.get("title").unwrap(), title);
 }
 
+#[tokio::test]
+async fn test_delete_dns_pdata() {
+    let mut con = setup_db_con().await;
+    let pdata_id = "deleted-data-id";
+    let name = "delete-pdata-dns.com";
+    let qname = format!("[{DEFAULT_NETWORK}]{name}");
+    let data_key = format!("{PDATA_KEY};{DNS_KEY};{qname};{pdata_id}");
+
+    call_fn(
+        &mut con,
+        "netdox_create_dns_plugin_data",
+        &["1", name, PLUGIN, "hash", pdata_id, "Title", "key", "val"],
+    )
+    .await;
+
+    // Plugins cannot delete data created by other plugins.
+    let other: Result<bool, _> = redis::cmd("FCALL")
+        .arg("netdox_delete_dns_plugin_data")
+        .arg(&["1", name, "other-plugin", pdata_id])
+        .query_async(&mut con)
+        .await;
+    assert!(other.is_err());
+
+    let deleted: bool = redis::cmd("FCALL")
+        .arg("netdox_delete_dns_plugin_data")
+        .arg(&["1", name, PLUGIN, pdata_id])
+        .query_async(&mut con)
+        .await
+        .expect("Failed to delete plugin data.");
+    let deleted_again: bool = redis::cmd("FCALL")
+        .arg("netdox_delete_dns_plugin_data")
+        .arg(&["1", name, PLUGIN, pdata_id])
+        .query_async(&mut con)
+        .await
+        .expect("Failed to delete plugin data.");
+
+    let result_member: bool = con
+        .sismember(format!("{PDATA_KEY};{DNS_KEY};{qname}"), pdata_id)
+        .await
+        .expect("Failed sismember.");
+    let result_details: bool = con
+        .exists(format!("{data_key};details"))
+        .await
+        .expect("Failed exists.");
+
+    assert!(deleted);
+    assert!(!deleted_again);
+    assert!(!result_member);
+    assert!(!result_details);
+    assert!(con.exists::<_, bool>(&data_key).await.is_ok_and(|e| !e));
+}
+
 #[tokio::test]
 async fn test_create_report() {
     let mut con = setup_db_con().await;
@@ -1075,3 +1516,257 @@ async fn test_create_report() {
     let actual3: String = con.get(format!("{REPORTS_KEY};{id};2")).await.unwrap();
     assert_eq!(actual3, data3);
 }
+
+#[tokio::test]
+async fn test_rebuild_index_pdata() {
+    let mut con = setup_db_con().await;
+    let pdata_id = "rebuild-index-data";
+    let name = "rebuild-index.com";
+    let qname = format!("[{DEFAULT_NETWORK}]{name}");
+    let set_key = format!("{PDATA_KEY};{DNS_KEY};{qname}");
+
+    call_fn(
+        &mut con,
+        "netdox_create_dns_plugin_data",
+        &[
+            "1", name, PLUGIN, "string", pdata_id, "Title", "plain", "content",
+        ],
+    )
+    .await;
+
+    let _: () = con.srem(&set_key, pdata_id).await.expect("Failed srem.");
+    let _: () = con
+        .sadd(&set_key, "missing-data")
+        .await
+        .expect("Failed sadd.");
+
+    let repairs = con.rebuild_index().await.unwrap();
+    let members: HashSet<String> = con.smembers(&set_key).await.expect("Failed smembers.");
+
+    assert!(repairs.pdata >= 2);
+    assert_eq!(members, HashSet::from([pdata_id.to_string()]));
+}
+
+#[tokio::test]
+async fn test_migrate_metadata() {
+    let mut con = setup_db_con().await;
+    let domain = "legacy-meta.com";
+    let qname = format!("[{DEFAULT_NETWORK}]{domain}");
+
+    call_fn(&mut con, "netdox_create_dns", &["1", domain, PLUGIN]).await;
+    call_fn(
+        &mut con,
+        "netdox_create_dns_metadata",
+        &["1", domain, PLUGIN, "current", "new"],
+    )
+    .await;
+
+    let _: () = con
+        .hset_multiple(
+            format!("meta;{qname}"),
+            &[("legacy", "old"), ("current", "old")],
+        )
+        .await
+        .unwrap();
+    let _: () = con
+        .sadd(format!("meta;{qname};plugins"), "legacy-plugin")
+        .await
+        .unwrap();
+    let _: () = con
+        .hset("meta;[missing-net]gone.com", "key", "value")
+        .await
+        .unwrap();
+
+    let dry_run = con.migrate_metadata(true).await.unwrap();
+    assert!(dry_run
+        .moved
+        .contains(&(qname.clone(), format!("{DNS_KEY};{qname}"))));
+    assert!(dry_run
+        .unresolved
+        .contains(&"[missing-net]gone.com".to_string()));
+    let exists: bool = con.exists(format!("meta;{qname}")).await.unwrap();
+    assert!(exists);
+
+    con.migrate_metadata(false).await.unwrap();
+    let metadata = con.get_dns_metadata(&qname).await.unwrap();
+    assert_eq!(metadata.get("legacy"), Some(&"old".to_string()));
+    assert_eq!(metadata.get("current"), Some(&"new".to_string()));
+
+    let plugins: HashSet<String> = con
+        .smembers(format!("meta;{DNS_KEY};{qname};plugins"))
+        .await
+        .unwrap();
+    assert!(plugins.contains("legacy-plugin"));
+    let exists: bool = con.exists(format!("meta;{qname}")).await.unwrap();
+    assert!(!exists);
+}
+
+#[tokio::test]
+async fn test_rename_network() {
+    let mut con = setup_db_con().await;
+    let (old, new) = ("rename-old", "rename-new");
+    let moved = format!("[{old}]a.com");
+    let pointer = "[rename-other]c.com";
+
+    call_fn(
+        &mut con,
+        "netdox_create_dns",
+        &["1", pointer, PLUGIN, "CNAME", &moved],
+    )
+    .await;
+    call_fn(
+        &mut con,
+        "netdox_create_node",
+        &[
+            "2",
+            &format!("[{old}]b.com"),
+            pointer,
+            PLUGIN,
+            "node",
+            "false",
+        ],
+    )
+    .await;
+    call_fn(
+        &mut con,
+        "netdox_create_dns_metadata",
+        &["1", &moved, PLUGIN, "owner", "ops"],
+    )
+    .await;
+
+    let dry_run = con.rename_network(old, new, true).await.unwrap();
+    assert!(dry_run
+        .dns_names
+        .contains(&(moved.clone(), format!("[{new}]a.com"))));
+    assert_eq!(
+        dry_run.nodes,
+        vec![(
+            format!("[{old}]b.com;{pointer}"),
+            format!("[{new}]b.com;{pointer}")
+        )]
+    );
+    assert!(con.get_dns_names().await.unwrap().contains(&moved));
+
+    con.rename_network(old, new, false).await.unwrap();
+    let names = con.get_dns_names().await.unwrap();
+    assert!(!names.contains(&moved));
+    assert!(names.contains(&format!("[{new}]a.com")));
+
+    let metadata = con
+        .get_dns_metadata(&format!("[{new}]a.com"))
+        .await
+        .unwrap();
+    assert_eq!(metadata.get("owner"), Some(&"ops".to_string()));
+
+    let dns = con.get_dns_subset(&[pointer]).await.unwrap();
+    assert!(dns
+        .get_records(pointer)
+        .iter()
+        .any(|record| record.value == format!("[{new}]a.com")));
+
+    let node_ids: HashSet<String> = con.smembers(NODES_KEY).await.unwrap();
+    assert!(node_ids.contains(&format!("[{new}]b.com;{pointer}")));
+    assert!(!node_ids.contains(&format!("[{old}]b.com;{pointer}")));
+}
+
+#[tokio::test]
+async fn test_prune() {
+    let mut con = setup_db_con().await;
+    let stale = format!("[{DEFAULT_NETWORK}]prune-stale.com");
+    let fresh = format!("[{DEFAULT_NETWORK}]prune-fresh.com");
+    let record = format!("{PLUGIN};CNAME;{fresh}");
+
+    call_fn(
+        &mut con,
+        "netdox_create_dns",
+        &["1", &stale, PLUGIN, "CNAME", &fresh],
+    )
+    .await;
+    call_fn(
+        &mut con,
+        "netdox_create_node",
+        &["1", &stale, PLUGIN, "node", "false"],
+    )
+    .await;
+
+    // Pretend the plugin last reported them long ago.
+    let seen_key = format!("{DNS_KEY};{stale};seen");
+    let _: () = con.hset(&seen_key, &record, 100).await.unwrap();
+    let _: () = con.hset(&seen_key, PLUGIN, 100).await.unwrap();
+    let _: () = con
+        .hset(format!("{NODES_KEY};{stale};seen"), 1, 100)
+        .await
+        .unwrap();
+
+    let dry_run = con.prune(Some(1000), &HashMap::new(), true).await.unwrap();
+    assert_eq!(
+        dry_run.records,
+        vec![DNSRecord {
+            name: stale.clone(),
+            value: fresh.clone(),
+            rtype: "CNAME".to_string(),
+            plugin: PLUGIN.to_string(),
+        }]
+    );
+    assert_eq!(dry_run.nodes, vec![(stale.clone(), PLUGIN.to_string())]);
+    assert_eq!(dry_run.dns_names, vec![stale.clone()]);
+    assert!(con.get_dns_names().await.unwrap().contains(&stale));
+
+    con.prune(Some(1000), &HashMap::new(), false).await.unwrap();
+    let names = con.get_dns_names().await.unwrap();
+    assert!(!names.contains(&stale));
+    // The target of the record was reported along with it, so is not stale yet.
+    assert!(names.contains(&fresh));
+    let implied: HashSet<String> = con
+        .smembers(format!("{DNS_KEY};{fresh};implied"))
+        .await
+        .unwrap();
+    assert!(implied.is_empty());
+    let node_ids: HashSet<String> = con.smembers(NODES_KEY).await.unwrap();
+    assert!(!node_ids.contains(&stale));
+}
+
+#[tokio::test]
+async fn test_key_tag() {
+    let mut con = setup_db_con().await;
+    let name = "key-tag.com";
+    let qname = format!("[{DEFAULT_NETWORK}]{name}");
+
+    // Keys starting with the tag are used as they would be in a cluster.
+    call_fn(
+        &mut con,
+        "netdox_setup",
+        &["1", &format!("{KEY_TAG}{DEFAULT_NETWORK}")],
+    )
+    .await;
+    call_fn(
+        &mut con,
+        "netdox_create_dns",
+        &["1", &format!("{KEY_TAG}{name}"), PLUGIN],
+    )
+    .await;
+    call_fn(&mut con, "netdox_begin_batch", &["1", KEY_TAG, PLUGIN]).await;
+
+    let tagged: bool = con
+        .sismember(format!("{KEY_TAG}{DNS_KEY}"), &qname)
+        .await
+        .unwrap();
+    assert!(tagged);
+    assert!(!con.get_dns_names().await.unwrap().contains(&qname));
+    let changes: usize = con.xlen(format!("{KEY_TAG}{CHANGELOG_KEY}")).await.unwrap();
+    assert_eq!(changes, 2);
+
+    // Every key must have the tag if the first one does.
+    assert!(redis::cmd("FCALL")
+        .arg("netdox_create_node")
+        .arg(2)
+        .arg(format!("{KEY_TAG}{name}"))
+        .arg(name)
+        .arg(PLUGIN)
+        .query_async::<()>(&mut con)
+        .await
+        .is_err());
+
+    let keys: Vec<String> = con.keys(format!("{KEY_TAG}*")).await.unwrap();
+    con.del::<_, ()>(keys).await.unwrap();
+}
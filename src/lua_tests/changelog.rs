@@ -1,5 +1,5 @@
 use crate::{
-    data::model::{CHANGELOG_KEY, DNS_KEY, METADATA_KEY, PDATA_KEY, REPORTS_KEY},
+    data::model::{CHANGELOG_KEY, DNS_KEY, METADATA_KEY, PDATA_KEY, PROC_NODES_KEY, REPORTS_KEY},
     tests_common::*,
 };
 use redis::{streams::StreamRangeReply, AsyncCommands, Value};
@@ -29,6 +29,31 @@ async fn test_changelog_create_dns() {
     assert!(found_change)
 }
 
+#[tokio::test]
+async fn test_changelog_map_dns() {
+    let mut con = setup_db_con().await;
+    let function = "netdox_map_dns";
+    let change = "updated network mapping";
+    let qname = format!("[{DEFAULT_NETWORK}]changelog-map-{}.com", *TIMESTAMP);
+    let dest = format!("[changelog-net]changelog-map-{}.com", *TIMESTAMP);
+
+    call_fn(&mut con, function, &["1", &qname, PLUGIN, "false", &dest]).await;
+
+    let changes: StreamRangeReply = con.xrange(CHANGELOG_KEY, "-", "+").await.unwrap();
+
+    let found_change =
+        changes.ids.iter().any(
+            |id| match (id.map.get("change").unwrap(), id.map.get("dest")) {
+                (Value::BulkString(id_change), Some(Value::BulkString(id_dest))) => {
+                    id_change == change.as_bytes() && id_dest == dest.as_bytes()
+                }
+                _ => false,
+            },
+        );
+
+    assert!(found_change)
+}
+
 #[tokio::test]
 async fn test_changelog_create_node() {
     let mut con = setup_db_con().await;
@@ -1554,3 +1579,257 @@ async fn test_no_update_dns_meta_less() {
 
     assert!(!found_change)
 }
+
+// IDEMPOTENCY
+
+/// Calls the function twice with the same arguments and asserts
+/// the second call logged no changes with a value containing the marker.
+async fn assert_idempotent(function: &str, args: &[&str], marker: &str) {
+    let mut con = setup_db_con().await;
+
+    call_fn(&mut con, function, args).await;
+
+    let changes: StreamRangeReply = con
+        .xrevrange_count(CHANGELOG_KEY, "+", "-", 1)
+        .await
+        .unwrap();
+    let last_change = format!("({}", changes.ids.last().unwrap().id);
+
+    call_fn(&mut con, function, args).await;
+
+    let changes: StreamRangeReply = con.xrange(CHANGELOG_KEY, last_change, "+").await.unwrap();
+
+    let repeated = changes
+        .ids
+        .iter()
+        .filter(|id| match id.map.get("value") {
+            Some(Value::BulkString(value)) => String::from_utf8_lossy(value).contains(marker),
+            _ => false,
+        })
+        .map(|id| id.map.get("change").cloned())
+        .collect::<Vec<_>>();
+
+    assert!(
+        repeated.is_empty(),
+        "Calling {function} again logged changes: {repeated:?}"
+    );
+}
+
+#[tokio::test]
+async fn test_idempotent_create_dns_record() {
+    let qname = format!("[{DEFAULT_NETWORK}]idempotent-dns-{}.com", *TIMESTAMP);
+    let args = ["1", &qname, PLUGIN, "A", "192.168.0.1"];
+
+    assert_idempotent("netdox_create_dns", &args, &qname).await;
+}
+
+#[tokio::test]
+async fn test_idempotent_map_dns() {
+    let qname = format!("[{DEFAULT_NETWORK}]idempotent-map-{}.com", *TIMESTAMP);
+    let dest = format!("[idempotent-net]idempotent-map-{}.com", *TIMESTAMP);
+    let args = ["1", &qname, PLUGIN, "true", &dest];
+
+    assert_idempotent("netdox_map_dns", &args, &qname).await;
+}
+
+#[tokio::test]
+async fn test_idempotent_create_node() {
+    let name = format!("idempotent-node-{}", *TIMESTAMP);
+    let qname = format!("[{DEFAULT_NETWORK}]{name}.com");
+    let link_id = format!("{name}-link-id");
+    let args = ["1", &qname, PLUGIN, &name, "false", &link_id];
+
+    assert_idempotent("netdox_create_node", &args, &name).await;
+}
+
+#[tokio::test]
+async fn test_idempotent_create_dns_metadata() {
+    let qname = format!("[{DEFAULT_NETWORK}]idempotent-dns-meta-{}.com", *TIMESTAMP);
+    let args = ["1", &qname, PLUGIN, "key1", "val1", "key2", "val2"];
+
+    assert_idempotent("netdox_create_dns_metadata", &args, &qname).await;
+}
+
+#[tokio::test]
+async fn test_idempotent_create_node_metadata() {
+    let qname = format!("[{DEFAULT_NETWORK}]idempotent-node-meta-{}.com", *TIMESTAMP);
+    let args = ["1", &qname, PLUGIN, "key1", "val1", "key2", "val2"];
+
+    assert_idempotent("netdox_create_node_metadata", &args, &qname).await;
+}
+
+#[tokio::test]
+async fn test_idempotent_create_proc_node_metadata() {
+    let link_id = format!("idempotent-proc-node-meta-{}", *TIMESTAMP);
+    let args = ["1", &link_id, PLUGIN, "key1", "val1", "key2", "val2"];
+
+    assert_idempotent("netdox_create_proc_node_metadata", &args, &link_id).await;
+}
+
+#[tokio::test]
+async fn test_idempotent_create_dns_plugin_data() {
+    let qname = format!("[{DEFAULT_NETWORK}]idempotent-dns-pdata-{}.com", *TIMESTAMP);
+    let function = "netdox_create_dns_plugin_data";
+
+    let str_args = [
+        "1", &qname, PLUGIN, "string", "str", "title", "plain", "content",
+    ];
+    assert_idempotent(function, &str_args, &format!("{qname};str")).await;
+
+    let hash_args = [
+        "1", &qname, PLUGIN, "hash", "hash", "title", "key2", "val2", "key1", "val1", "key3",
+        "val3",
+    ];
+    assert_idempotent(function, &hash_args, &format!("{qname};hash")).await;
+
+    let list_args = [
+        "1", &qname, PLUGIN, "list", "list", "title", "name1", "title1", "val1", "name2", "title2",
+        "val2",
+    ];
+    assert_idempotent(function, &list_args, &format!("{qname};list")).await;
+
+    let table_args = [
+        "1", &qname, PLUGIN, "table", "table", "title", "2", "col1", "col2", "val1", "val2",
+    ];
+    assert_idempotent(function, &table_args, &format!("{qname};table")).await;
+}
+
+#[tokio::test]
+async fn test_idempotent_create_node_plugin_data() {
+    let qname = format!(
+        "[{DEFAULT_NETWORK}]idempotent-node-pdata-{}.com",
+        *TIMESTAMP
+    );
+    let args = [
+        "1", &qname, PLUGIN, "hash", "hash", "title", "key2", "val2", "key1", "val1",
+    ];
+
+    assert_idempotent("netdox_create_node_plugin_data", &args, &qname).await;
+}
+
+#[tokio::test]
+async fn test_idempotent_create_proc_node_plugin_data() {
+    let link_id = format!("idempotent-proc-node-pdata-{}", *TIMESTAMP);
+    let args = [
+        "1", &link_id, PLUGIN, "hash", "hash", "title", "key2", "val2", "key1", "val1",
+    ];
+
+    assert_idempotent("netdox_create_proc_node_plugin_data", &args, &link_id).await;
+}
+
+#[tokio::test]
+async fn test_idempotent_create_report() {
+    let report = format!("idempotent-report-{}", *TIMESTAMP);
+    let args = ["1", &report, PLUGIN, "title", "4"];
+
+    assert_idempotent("netdox_create_report", &args, &report).await;
+}
+
+#[tokio::test]
+async fn test_idempotent_create_report_data() {
+    let report = format!("idempotent-report-data-{}", *TIMESTAMP);
+    let function = "netdox_create_report_data";
+
+    let str_args = [
+        "1", &report, PLUGIN, "0", "string", "title", "plain", "content",
+    ];
+    assert_idempotent(function, &str_args, &format!("{report};0")).await;
+
+    let hash_args = [
+        "1", &report, PLUGIN, "1", "hash", "title", "key2", "val2", "key1", "val1", "key3", "val3",
+    ];
+    assert_idempotent(function, &hash_args, &format!("{report};1")).await;
+
+    let list_args = [
+        "1", &report, PLUGIN, "2", "list", "title", "name1", "title1", "val1",
+    ];
+    assert_idempotent(function, &list_args, &format!("{report};2")).await;
+
+    let table_args = [
+        "1", &report, PLUGIN, "3", "table", "title", "2", "col1", "col2", "val1", "val2",
+    ];
+    assert_idempotent(function, &table_args, &format!("{report};3")).await;
+}
+
+#[tokio::test]
+async fn test_idempotent_create_node_dependency() {
+    let _lock = PROC_NODES_LOCK.read().await;
+    let mut con = setup_db_con().await;
+    let source = format!("idempotent-dependency-app-{}", *TIMESTAMP);
+    let dest = format!("idempotent-dependency-db-{}", *TIMESTAMP);
+    let _: () = con.sadd(PROC_NODES_KEY, &[&source, &dest]).await.unwrap();
+    let args = ["2", &source, &dest, PLUGIN, "database"];
+
+    assert_idempotent("netdox_create_node_dependency", &args, &source).await;
+}
+
+#[tokio::test]
+async fn test_idempotent_create_node_relation() {
+    let _lock = PROC_NODES_LOCK.read().await;
+    let mut con = setup_db_con().await;
+    let source = format!("idempotent-relation-source-{}", *TIMESTAMP);
+    let dest = format!("idempotent-relation-dest-{}", *TIMESTAMP);
+    let _: () = con.sadd(PROC_NODES_KEY, &[&source, &dest]).await.unwrap();
+    let args = ["2", &source, &dest, PLUGIN, "replicates-to"];
+
+    assert_idempotent("netdox_create_node_relation", &args, &source).await;
+}
+
+#[tokio::test]
+async fn test_idempotent_create_node_service() {
+    let _lock = PROC_NODES_LOCK.read().await;
+    let mut con = setup_db_con().await;
+    let link_id = format!("idempotent-service-{}", *TIMESTAMP);
+    let _: () = con.sadd(PROC_NODES_KEY, &link_id).await.unwrap();
+    let args = ["1", &link_id, PLUGIN, "443", "tcp", "product", "nginx"];
+
+    assert_idempotent("netdox_create_node_service", &args, &link_id).await;
+}
+
+#[tokio::test]
+async fn test_changelog_dns_update_data_type() {
+    let mut con = setup_db_con().await;
+    let function = "netdox_create_dns_plugin_data";
+    let qname = format!(
+        "[{DEFAULT_NETWORK}]changelog-plugin-update-data-type-{}.com",
+        *TIMESTAMP
+    );
+    let data_key = format!("{PDATA_KEY};{DNS_KEY};{qname};1");
+
+    call_fn(
+        &mut con,
+        function,
+        &["1", &qname, PLUGIN, "hash", "1", "title", "key", "val"],
+    )
+    .await;
+
+    let changes: StreamRangeReply = con
+        .xrevrange_count(CHANGELOG_KEY, "+", "-", 1)
+        .await
+        .unwrap();
+    let last_change = format!("({}", changes.ids.last().unwrap().id);
+
+    call_fn(
+        &mut con,
+        function,
+        &["1", &qname, PLUGIN, "string", "1", "title", "plain", "val"],
+    )
+    .await;
+
+    let changes: StreamRangeReply = con.xrange(CHANGELOG_KEY, last_change, "+").await.unwrap();
+    let found_change = changes.ids.iter().any(|id| {
+        match (id.map.get("change").unwrap(), id.map.get("value").unwrap()) {
+            (Value::BulkString(id_change), Value::BulkString(id_data_key)) => {
+                id_change == b"updated data" && id_data_key == data_key.as_bytes()
+            }
+            _ => false,
+        }
+    });
+    assert!(found_change);
+
+    let order: Vec<String> = con
+        .lrange(format!("{data_key};order"), 0, -1)
+        .await
+        .unwrap();
+    assert!(order.is_empty());
+}
@@ -1,38 +1,86 @@
+mod admin;
+mod annotate;
+mod backup;
+mod check;
 mod config;
+mod daemon;
 mod data;
+mod dev;
+mod diff;
 mod error;
+mod export;
+mod health;
 #[cfg(test)]
 mod integration_tests;
+mod logging;
 #[cfg(test)]
 mod lua_tests;
+mod metrics;
+mod migrate;
+mod notify;
 mod process;
+mod progress;
+mod prune;
 mod query;
 mod remote;
+mod runs;
+mod search_index;
+mod snapshot;
+mod support;
 #[cfg(test)]
 mod tests_common;
 mod update;
 
-use config::{LocalConfig, PluginConfig, PluginStage, PluginStageConfig};
+use admin::admin;
+use annotate::annotate;
+use backup::{backup, restore};
+use check::check;
+use config::{
+    LocalConfig, PluginConfig, PluginStage, PluginStageConfig, RemoteConfig, SecretSource,
+};
+use daemon::{daemon, Schedule};
+use dev::{
+    function_tests::{TESTS, TEST_REDIS_URL_VAR},
+    PluginLang,
+};
+use diff::diff;
 use error::{NetdoxError, NetdoxResult};
-use paris::{error, info, success, warn};
+use export::{export, inventory::InventoryFormat, GraphFormat};
+use health::health;
+use logging::{redact_url, LogFormat};
+use migrate::migrate;
+use prune::prune;
 use query::query;
-use remote::{Remote, RemoteInterface};
+use remote::{ObjectSelection, Remote, RemoteInterface};
+use runs::{with_remote_lock, RunLockGuard, RunTimer};
+use search_index::search_index;
+use snapshot::snapshot;
+use support::support_bundle;
 use tokio::join;
-use update::{plugin_error_report, PluginResult};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use update::{
+    field_warnings, plugin_error_report, plugin_health_report, plugin_run_report, Deferrals,
+    PluginResult,
+};
 
 use std::{
-    collections::HashMap,
-    fs,
+    collections::{HashMap, HashSet},
+    env, fs,
     io::{stdin, stdout, Write},
-    path::PathBuf,
+    net::SocketAddr,
+    path::{Path, PathBuf},
     process::exit,
+    time::Duration,
 };
 
-use clap::{Parser, Subcommand};
-use redis::{cmd as redis_cmd, Client};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use toml::Value;
 
-use crate::data::{DataConn, DataStore};
+use crate::data::{
+    model::{PluginDisplay, PluginScope, RunKind, SCHEMA_VERSION},
+    store::{memory_store, read_only},
+    DataConn,
+};
 
 // CLI
 
@@ -43,8 +91,56 @@ struct Cli {
     cmd: Commands,
 
     /// Turn on debug logging.
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     debug: bool,
+
+    /// Print progress as periodic log lines instead of progress bars.
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Refuse to write to the data store, so it can only be queried.
+    #[arg(long, global = true)]
+    read_only: bool,
+
+    /// Read the data store from a backup written by the backup command instead of connecting to it.
+    /// Implies --read-only.
+    #[arg(long, global = true, value_name = "BACKUP")]
+    offline: Option<PathBuf>,
+
+    /// Serve Prometheus metrics over HTTP at this address while the command runs.
+    #[arg(long, global = true, value_name = "ADDR")]
+    metrics_listen: Option<SocketAddr>,
+
+    /// Append log lines to this file as well as printing them.
+    #[arg(long, global = true, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Format of log lines.
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+}
+
+impl Cli {
+    /// Returns the global arguments to pass on to runs started by the daemon.
+    /// Metrics are not served by runs, as they would compete for the address.
+    fn child_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if self.debug {
+            args.push("--debug".to_string());
+        }
+        if self.quiet {
+            args.push("--quiet".to_string());
+        }
+        if let Some(path) = &self.log_file {
+            args.push("--log-file".to_string());
+            args.push(path.to_string_lossy().to_string());
+        }
+        if let Some(format) = self.log_format.to_possible_value() {
+            args.push("--log-format".to_string());
+            args.push(format.get_name().to_string());
+        }
+        args
+    }
 }
 
 #[derive(Subcommand, Debug)]
@@ -67,20 +163,168 @@ enum Commands {
         /// Causes the list of plugins to be treated as an exclusion list.
         #[arg(short = 'x', long)]
         exclude: bool,
+        /// After processing, resolves the nodes twice more from the same data
+        /// and fails if any processed node or DNS name is different the second time.
+        #[arg(long)]
+        verify_processing: bool,
+        /// Removes the lock on the data store held by another update or publish before starting.
+        /// Only use this if that run has died and its lock has not expired yet.
+        #[arg(long)]
+        force_unlock: bool,
+        /// Stops starting plugins once the update has run this long, like 30m or 2h,
+        /// and defers the rest to the next update, which starts them first.
+        /// Plugins that have started are left to finish, and processing still runs.
+        #[arg(long, value_name = "DURATION", value_parser = query::parse_window)]
+        max_duration: Option<Duration>,
     },
     /// Initialises the database and wipes the changelog.
     Init,
-    /// Publishes processed data to the remote.
+    /// Runs updates followed by publishes on the schedule in the config, until stopped.
+    /// Only one daemon can hold the lock file at a time.
+    Daemon {
+        /// Path of the lock file. Defaults to netdox-daemon.lock in the temp directory.
+        #[arg(long, value_name = "PATH")]
+        lock_file: Option<PathBuf>,
+        /// Runs an update and publish as soon as the daemon starts, as well as on the schedule.
+        #[arg(long)]
+        run_now: bool,
+    },
+    /// Publishes processed data to each of the remotes.
     Publish {
         /// An optional path to write a backup of the published data to.
-        #[arg(short, long)]
+        #[arg(short, long, conflicts_with = "dry_run")]
         backup: Option<PathBuf>,
+        /// Prints the documents and fragments that would be published, without changing the remote.
+        #[arg(long)]
+        dry_run: bool,
+        /// Publishes the whole documents for objects with a qname, node link ID or report ID
+        /// matching this glob pattern, instead of the changes since the last publish.
+        #[arg(long, value_name = "PATTERN", conflicts_with = "dry_run")]
+        only: Option<String>,
+        /// Publishes the whole documents for the DNS names and nodes with this tag,
+        /// instead of the changes since the last publish. Combines with --only.
+        #[arg(long, conflicts_with = "dry_run")]
+        tag: Option<String>,
+        /// After publishing, archives or removes the documents on the remote
+        /// whose object is no longer in the data store.
+        #[arg(long, conflicts_with_all = ["only", "tag"])]
+        reconcile: bool,
+        /// Reconciles even if the data store has no processed nodes,
+        /// or more documents would be archived or removed than half the objects in the data store.
+        #[arg(long, requires = "reconcile")]
+        force: bool,
+        /// Removes the lock on the data store held by another update or publish,
+        /// and the lock on the remote held by another publish, before starting.
+        /// Only use this if that run has died and its lock has not expired yet.
+        #[arg(long, conflicts_with = "dry_run")]
+        force_unlock: bool,
+        /// Publishes to the remote with this name, instead of every remote in the config.
+        #[arg(long, value_name = "NAME")]
+        remote: Option<String>,
     },
     /// Commands for querying data store.
     Query {
         #[command(subcommand)]
         cmd: QueryCommand,
     },
+    /// Sets an operator note on a DNS name or node.
+    /// Notes cannot be changed by plugins and are kept across updates.
+    Annotate {
+        /// Object to annotate, like dns;<name> or node;<link id>.
+        object_id: String,
+        /// Name of the note.
+        #[arg(short, long)]
+        key: String,
+        /// Content of the note. Omit to remove the note.
+        #[arg(short, long)]
+        value: Option<String>,
+    },
+    /// Checks the data store and recent runs for monitoring.
+    /// Exits with 0 if healthy, 1 for a warning, or 2 if critical.
+    Health,
+    /// Checks the data store for inconsistent data, and prints each issue found with its severity.
+    /// Exits with 1 if any errors are found.
+    Check {
+        /// Repairs the issues that can be repaired safely, then rebuilds the indexes.
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Moves data stored under legacy key layouts to the current ones.
+    Migrate {
+        /// Print what would be moved without changing anything.
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+    /// Removes DNS records and nodes that their plugins have not reported within some time,
+    /// and then the DNS names and processed nodes left with nothing to describe.
+    Prune {
+        /// Remove anything last reported longer ago than this, like 90s, 30m, 12h, 7d or 2w.
+        #[arg(long, value_name = "DURATION", value_parser = query::parse_window)]
+        older_than: Duration,
+        /// Print what would be removed without changing anything.
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+    /// Commands for maintaining the data store.
+    Admin {
+        #[command(subcommand)]
+        cmd: AdminCommand,
+    },
+    /// Writes every key and change in the data store to a versioned zip of JSON files.
+    Backup {
+        /// Path to write the backup to.
+        path: PathBuf,
+    },
+    /// Loads a backup written by the backup command into an empty data store.
+    Restore {
+        /// Path of the backup to load.
+        path: PathBuf,
+        /// Removes everything from the data store before loading the backup.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Prints the DNS names, DNS records, nodes and metadata that were added, removed or changed
+    /// between two backups, or between a backup and the data store.
+    Diff {
+        /// Path of the older backup.
+        old: PathBuf,
+        /// Path of the newer backup. Defaults to the current contents of the data store.
+        new: Option<PathBuf>,
+    },
+    /// Commands for capturing and comparing digests of the data store.
+    Snapshot {
+        #[command(subcommand)]
+        cmd: SnapshotCommand,
+    },
+    /// Commands for exporting data from the data store to other tools.
+    Export {
+        #[command(subcommand)]
+        cmd: ExportCommand,
+    },
+    /// Writes a JSON search index of the names, metadata values and data titles of every object,
+    /// with where the document for each is published on the remote.
+    #[command(name = "search-index")]
+    SearchIndex {
+        /// Path to write the index to.
+        path: PathBuf,
+    },
+    /// Writes a zip of diagnostic information to attach to bug reports.
+    /// Secrets in the config are redacted and names from the data store are hashed.
+    #[command(name = "support-bundle")]
+    SupportBundle {
+        /// Path to write the zip to. Defaults to netdox-support-<timestamp>.zip.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Log file to include the end of, like the one runs are given with --log-file.
+        /// Can be passed more than once. The --log-file of this command is always included.
+        #[arg(long = "log", value_name = "PATH")]
+        logs: Vec<PathBuf>,
+    },
+    /// Tools for plugin developers.
+    Dev {
+        #[command(subcommand)]
+        cmd: DevCommand,
+    },
 }
 
 #[derive(Subcommand, Debug)]
@@ -93,6 +337,8 @@ enum ConfigCommand {
     Load {
         /// Path to the plain text config file to load.
         config_path: PathBuf,
+        #[command(flatten)]
+        secret: SecretArgs,
     },
     /// Reads the current encrypted and stored config file, and writes it out
     /// in plain text to the given path.
@@ -101,6 +347,56 @@ enum ConfigCommand {
         /// Path to write the plain text config file to.
         config_path: PathBuf,
     },
+    /// Downloads the config on the remote and prints warnings for the exclusions,
+    /// locations and metadata overrides that would not apply as intended to the data store.
+    #[command(name = "check-remote")]
+    CheckRemote,
+    /// Decrypts the stored config and encrypts it again with the secret from a new source,
+    /// which is used to read the config from then on.
+    #[command(name = "rekey")]
+    Rekey {
+        #[command(flatten)]
+        secret: SecretArgs,
+    },
+    /// Decrypts the stored config into a temporary file and opens it in $VISUAL or $EDITOR.
+    /// Once the editor exits, the config is checked like a loaded one, encrypted and stored,
+    /// and the temporary file is removed.
+    #[command(name = "edit")]
+    Edit,
+}
+
+/// Where to read the secret that encrypts the config from.
+/// By default it is read from the environment variable NETDOX_SECRET,
+/// or from the source the config was last stored with.
+#[derive(Args, Debug)]
+#[group(multiple = false)]
+struct SecretArgs {
+    /// Reads the secret from this environment variable.
+    #[arg(long, value_name = "VAR")]
+    secret_env: Option<String>,
+    /// Reads the secret from this file.
+    #[arg(long, value_name = "PATH")]
+    secret_file: Option<PathBuf>,
+    /// Reads the secret from the output of this shell command, like `pass show netdox`.
+    #[arg(long, value_name = "COMMAND")]
+    secret_command: Option<String>,
+}
+
+impl SecretArgs {
+    /// Returns the source of the secret, if one was given.
+    fn source(&self) -> Option<SecretSource> {
+        if let Some(var) = &self.secret_env {
+            Some(SecretSource::Env { var: var.clone() })
+        } else if let Some(path) = &self.secret_file {
+            Some(SecretSource::File { path: path.clone() })
+        } else {
+            self.secret_command
+                .as_ref()
+                .map(|command| SecretSource::Command {
+                    command: command.clone(),
+                })
+        }
+    }
 }
 
 #[derive(Subcommand, Debug)]
@@ -108,6 +404,196 @@ enum QueryCommand {
     /// Prints out the number of each object type in the data store.
     #[command(name = "counts")]
     Counts,
+    /// Prints out each network in the data store and the number of objects in it.
+    #[command(name = "networks")]
+    Networks,
+    /// Prints out each node in the data store and its role.
+    #[command(name = "nodes")]
+    Nodes {
+        /// Only print nodes with this role.
+        #[arg(short, long)]
+        role: Option<String>,
+    },
+    /// Prints each location with the number of DNS names and nodes assigned to it,
+    /// and the number of each with no location.
+    #[command(name = "locations")]
+    Locations,
+    /// Prints the DNS names and nodes with a tag,
+    /// or each tag and the number of objects with it if no tag is given.
+    #[command(name = "tag")]
+    Tag { tag: Option<String> },
+    /// Prints the composition of a processed node: its names, plugins, raw nodes and data.
+    #[command(name = "node")]
+    Node {
+        /// Link ID of a processed node, or ID of a raw node that it consumed.
+        id: String,
+    },
+    /// Prints the duration and outcome of recent updates and publishes.
+    #[command(name = "runs")]
+    Runs {
+        /// Maximum number of runs to print.
+        #[arg(short = 'n', long, default_value_t = 10)]
+        count: usize,
+        /// Also print the duration of each plugin and phase in a run, and the changes each stage made.
+        #[arg(short, long)]
+        timings: bool,
+    },
+    /// Prints the changes in the changelog.
+    #[command(name = "changes")]
+    Changes {
+        /// Only include changes made within this long ago, like 90s, 30m, 12h, 7d or 2w,
+        /// or after the change with this stream ID.
+        #[arg(short, long, value_parser = query::parse_since)]
+        since: Option<query::ChangesSince>,
+        /// Only include changes made by this plugin.
+        #[arg(short, long)]
+        plugin: Option<String>,
+        /// Only include changes of this type, like created-data or "updated metadata".
+        #[arg(short = 't', long = "type")]
+        change_type: Option<String>,
+        /// Print the number of changes and when the first and last were made,
+        /// for each plugin and change type, instead of each change.
+        #[arg(long)]
+        stats: bool,
+    },
+    /// Prints the references to and from an object, and whether their targets exist.
+    /// Object ID must be of the form dns;<name> or node;<link id>.
+    #[command(name = "links")]
+    Links { object_id: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum AdminCommand {
+    /// Regenerates the indexes derived from processed nodes and plugin data.
+    #[command(name = "rebuild-index")]
+    RebuildIndex,
+    /// Moves every DNS name in a network to another network, along with the nodes,
+    /// metadata, notes and plugin data that use them.
+    /// The network must also be renamed in the config afterwards.
+    #[command(name = "rename-network")]
+    RenameNetwork {
+        /// Network to move the DNS names from.
+        old: String,
+        /// Network to move the DNS names to.
+        new: String,
+        /// Print what would be moved without changing anything.
+        #[arg(long)]
+        dry_run: bool,
+        /// Archive the documents of the old DNS names on the remote.
+        #[arg(long, conflicts_with = "dry_run")]
+        archive: bool,
+    },
+    /// Deletes a DNS name with its records, metadata and plugin data,
+    /// and archives its document on the remote.
+    #[command(name = "delete-dns")]
+    DeleteDns {
+        /// DNS name to delete. Qualified with a network if it has none.
+        qname: String,
+        /// Also delete the records that point to the DNS name and the raw nodes that use it,
+        /// and remove it from processed nodes. Without this, a DNS name that is used is not deleted.
+        #[arg(long)]
+        cascade: bool,
+        /// Print what would be deleted without changing anything.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Deletes some plugin data from an object, on behalf of the plugin that created it.
+    #[command(name = "delete-data")]
+    DeleteData {
+        /// Object to delete the data from, of the form dns;<name> or node;<link id>.
+        object_id: String,
+        /// ID of the plugin data to delete.
+        data_id: String,
+        /// Plugin that created the plugin data.
+        #[arg(short, long)]
+        plugin: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SnapshotCommand {
+    /// Writes a digest of every object in the data store to a named snapshot.
+    #[command(name = "create")]
+    Create {
+        /// Name of the snapshot to create.
+        name: String,
+        /// Overwrites an existing snapshot with the same name.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Prints the objects that were added, removed, or changed between two snapshots.
+    #[command(name = "diff")]
+    Diff {
+        /// Name of the older snapshot.
+        old: String,
+        /// Name of the newer snapshot.
+        new: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ExportCommand {
+    /// Writes a graph of the DNS names that resolve to each other and the nodes that use them,
+    /// with the dependencies and relations between nodes.
+    #[command(name = "graph")]
+    Graph {
+        /// Format of the graph.
+        format: GraphFormat,
+        /// Only include DNS names in this network, and the names they point to.
+        #[arg(long)]
+        network: Option<String>,
+        /// Only include DNS names starting with this, and the names they point to.
+        #[arg(long)]
+        prefix: Option<String>,
+        /// Path to write the graph to. Printed if not given.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Writes tables of the processed nodes and DNS records, for spreadsheets.
+    #[command(name = "inventory")]
+    Inventory {
+        /// Format of the tables.
+        #[arg(short, long, value_enum)]
+        format: InventoryFormat,
+        /// Adds a column to the table of nodes with the value of this metadata key.
+        /// Can be given more than once.
+        #[arg(short, long = "meta", value_name = "KEY", default_value = "os")]
+        meta: Vec<String>,
+        /// Path to write to. A directory for CSV, which gets a file for each table,
+        /// or a workbook with a sheet for each table for XLSX.
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum DevCommand {
+    /// Generates a skeleton for a new plugin in a directory with the plugin's name.
+    #[command(name = "new-plugin")]
+    NewPlugin {
+        /// Name of the new plugin.
+        name: String,
+        /// Language to write the plugin in.
+        #[arg(short, long, value_enum, default_value = "python")]
+        lang: PluginLang,
+        /// Directory to create the plugin directory in. Defaults to the current directory.
+        #[arg(short, long)]
+        dir: Option<PathBuf>,
+    },
+    /// Runs tests of the Lua functions against a redis server, to check it before upgrading.
+    /// The database must be empty, and is flushed afterwards.
+    #[command(name = "test")]
+    Test {
+        /// URL of the redis database to test in, like redis://localhost:6379/15.
+        /// Defaults to the value of the NETDOX_TEST_REDIS_URL environment variable.
+        /// For a rediss:// URL, the CA and client certificate are read from the paths in
+        /// NETDOX_TEST_REDIS_CA_CERT, NETDOX_TEST_REDIS_CLIENT_CERT and NETDOX_TEST_REDIS_CLIENT_KEY.
+        url: Option<String>,
+        /// Replaces the netdox Lua functions if they are already loaded on the server.
+        /// Functions are shared by every database on a server, so this changes them for production too.
+        #[arg(long)]
+        allow_function_reload: bool,
+    },
 }
 
 // FUNCTIONALITY
@@ -115,22 +601,108 @@ enum QueryCommand {
 
 fn main() {
     let cli = Cli::parse();
+    if let Err(err) = logging::init(cli.debug, cli.log_format, cli.log_file.as_deref()) {
+        eprintln!("{err}");
+        exit(1);
+    }
+    logging::set_quiet(cli.quiet);
+    read_only::set_read_only(cli.read_only || cli.offline.is_some());
+    if let Some(path) = &cli.offline {
+        if let Err(err) = memory_store::set_offline(path) {
+            error!("Failed to load backup for offline mode: {err}");
+            exit(1);
+        }
+    }
+    if let Some(addr) = cli.metrics_listen {
+        if let Err(err) = metrics::serve(addr) {
+            error!("{err}");
+            exit(1);
+        }
+    }
     match cli.cmd {
         Commands::Init => {
             init();
         }
         Commands::Config { cmd } => match cmd {
             ConfigCommand::Template => template_cfg(),
-            ConfigCommand::Load { config_path } => load_cfg(&config_path),
+            ConfigCommand::Load {
+                config_path,
+                secret,
+            } => load_cfg(&config_path, secret.source()),
             ConfigCommand::Dump { config_path } => dump_cfg(&config_path),
+            ConfigCommand::CheckRemote => check_remote_cfg(),
+            ConfigCommand::Rekey { secret } => rekey_cfg(secret.source()),
+            ConfigCommand::Edit => edit_cfg(),
         },
         Commands::Update {
             reset_db,
             plugin,
             exclude,
-        } => update(reset_db, plugin.as_ref(), exclude),
-        Commands::Publish { backup } => publish(backup),
+            verify_processing,
+            force_unlock,
+            max_duration,
+        } => update(
+            reset_db,
+            plugin.as_ref(),
+            exclude,
+            verify_processing,
+            force_unlock,
+            max_duration,
+        ),
+        Commands::Publish {
+            backup,
+            dry_run,
+            only,
+            tag,
+            reconcile,
+            force,
+            force_unlock,
+            remote,
+        } => publish(
+            backup,
+            dry_run,
+            only,
+            tag,
+            reconcile,
+            force,
+            force_unlock,
+            remote,
+        ),
+        Commands::Daemon {
+            ref lock_file,
+            run_now,
+        } => daemon(cli.child_args(), lock_file.clone(), run_now),
         Commands::Query { ref cmd } => query(cmd),
+        Commands::Annotate {
+            ref object_id,
+            ref key,
+            ref value,
+        } => annotate(object_id, key, value.as_deref()),
+        Commands::Health => health(),
+        Commands::Check { fix } => check(fix),
+        Commands::Migrate { dry_run } => migrate(dry_run),
+        Commands::Prune {
+            older_than,
+            dry_run,
+        } => prune(older_than, dry_run),
+        Commands::Admin { ref cmd } => admin(cmd),
+        Commands::Backup { path } => backup(&path),
+        Commands::Restore { path, force } => restore(&path, force),
+        Commands::Diff { old, new } => diff(&old, new.as_ref()),
+        Commands::Snapshot { ref cmd } => snapshot(cmd),
+        Commands::Export { ref cmd } => export(cmd),
+        Commands::SearchIndex { path } => search_index(&path),
+        Commands::SupportBundle { output, mut logs } => {
+            logs.extend(cli.log_file.clone());
+            support_bundle(output, logs)
+        }
+        Commands::Dev { cmd } => match cmd {
+            DevCommand::NewPlugin { name, lang, dir } => new_plugin(&name, lang, dir),
+            DevCommand::Test {
+                url,
+                allow_function_reload,
+            } => test_functions(url, allow_function_reload),
+        },
     }
     exit(0);
 }
@@ -164,6 +736,34 @@ async fn init() {
     }
 }
 
+/// Generates a plugin skeleton for the user to build on.
+fn new_plugin(name: &str, lang: PluginLang, dir: Option<PathBuf>) {
+    if let Err(err) = dev::new_plugin(name, lang, &dir.unwrap_or_else(|| PathBuf::from("."))) {
+        error!("Failed to generate plugin: {err}");
+        exit(1);
+    }
+}
+
+#[tokio::main]
+async fn test_functions(url: Option<String>, allow_function_reload: bool) {
+    let Some(url) = url.or_else(|| env::var(TEST_REDIS_URL_VAR).ok()) else {
+        error!("Pass the URL of a redis database to test in, or set {TEST_REDIS_URL_VAR}.");
+        exit(1);
+    };
+
+    match dev::function_tests::test_functions(&url, allow_function_reload).await {
+        Ok(0) => success!("All {} tests passed.", TESTS.len()),
+        Ok(failed) => {
+            error!("{failed} of {} tests failed.", TESTS.len());
+            exit(1);
+        }
+        Err(err) => {
+            error!("Failed to run tests: {err}");
+            exit(1);
+        }
+    }
+}
+
 /// Gets the user to choose a remote type and then writes a config template for them to populate.
 fn template_cfg() {
     match fs::write("config.toml", config_template(choose_remote())) {
@@ -183,16 +783,27 @@ fn config_template(remote: Remote) -> String {
     let mut config = LocalConfig::template(remote);
 
     config.plugins.push(PluginConfig {
+        timeout_seconds: None,
+        schema_version: Some(SCHEMA_VERSION),
         fields: HashMap::from([(
             "plugin config key".to_string(),
             Value::String("plugin config value".to_string()),
         )]),
         name: "example plugin name".to_string(),
+        display: PluginDisplay {
+            name: Some("Example Plugin".to_string()),
+            label: Some("example-plugin".to_string()),
+            colour: Some("blue".to_string()),
+        },
+        scope: PluginScope::default(),
         stages: HashMap::from([
             (
                 PluginStage::WriteOnly,
                 PluginStageConfig {
                     path: "/path/to/plugin/binary".to_string(),
+                    retries: 0,
+                    probe: None,
+                    workers: None,
                     fields: HashMap::new(),
                 },
             ),
@@ -200,6 +811,9 @@ fn config_template(remote: Remote) -> String {
                 PluginStage::ReadWrite,
                 PluginStageConfig {
                     path: "/path/to/other/binary".to_string(),
+                    retries: 0,
+                    probe: None,
+                    workers: None,
                     fields: HashMap::new(),
                 },
             ),
@@ -216,9 +830,8 @@ fn config_template(remote: Remote) -> String {
 }
 
 /// Prompt for user choosing remote type.
-/// Currently only pageseeder is implemented.
 fn choose_remote() -> Remote {
-    let mut remotes = String::new();
+    let mut remotes = String::from("html, markdown, ");
 
     #[cfg(feature = "pageseeder")]
     {
@@ -239,6 +852,27 @@ fn choose_remote() -> Remote {
             exit(1);
         }
 
+        if input.trim() == "html" {
+            remote = Some(Remote::Html(remote::html::HtmlRemote {
+                output_dir: PathBuf::from("/path/to/site"),
+                title: "Netdox".to_string(),
+                locations: HashMap::new(),
+                exclusions: HashSet::new(),
+                metadata_by_plugin: false,
+                obfuscation: None,
+            }));
+        }
+
+        if input.trim() == "markdown" {
+            remote = Some(Remote::Markdown(remote::markdown::MarkdownRemote {
+                vault_dir: PathBuf::from("/path/to/vault"),
+                title: "Netdox".to_string(),
+                locations: HashMap::new(),
+                exclusions: HashSet::new(),
+                metadata_by_plugin: false,
+            }));
+        }
+
         #[cfg(feature = "pageseeder")]
         {
             use remote::pageseeder::PSRemote;
@@ -252,6 +886,12 @@ fn choose_remote() -> Remote {
                     client_id: "OAuth2 client ID".to_string(),
                     client_secret: "OAuth2 client secret".to_string(),
                     upload_dir: "directory to upload into".to_string(),
+                    max_report_rows: 1000,
+                    publish_batch_size: 500,
+                    exclude_roles: HashSet::new(),
+                    access: vec![],
+                    max_document_bytes: None,
+                    metadata_by_plugin: false,
                     pstoken: Mutex::default(),
                 }));
             }
@@ -266,8 +906,14 @@ fn choose_remote() -> Remote {
 }
 
 #[tokio::main]
-#[allow(clippy::too_many_lines)]
-async fn update(reset_db: bool, plugins: Option<&Vec<String>>, exclude: bool) {
+async fn update(
+    reset_db: bool,
+    plugins: Option<&Vec<String>>,
+    exclude: bool,
+    verify_processing: bool,
+    force_unlock: bool,
+    max_duration: Option<Duration>,
+) {
     info!("Starting update process.");
 
     let local_cfg = match LocalConfig::read() {
@@ -278,157 +924,416 @@ async fn update(reset_db: bool, plugins: Option<&Vec<String>>, exclude: bool) {
         }
     };
 
+    // Plugins write to the data store directly, so cannot be stopped by a read-only connection.
+    if local_cfg.is_read_only() {
+        error!("Cannot update data in read-only mode.");
+        exit(1);
+    }
+
+    if let Err(err) = update::check_schema_versions(&local_cfg.plugins, plugins, exclude) {
+        error!("{err}");
+        exit(1);
+    }
+
+    let lock = match local_cfg.con().await {
+        Ok(con) => RunLockGuard::acquire(con, RunKind::Update, force_unlock).await,
+        Err(err) => Err(err),
+    };
+    let lock = match lock {
+        Ok(lock) => lock,
+        Err(err) => {
+            error!("Failed to lock the data store for this update: {err}");
+            exit(1);
+        }
+    };
+
+    // Every failure returns here, so the run lock is released before exiting.
+    let succeeded = run_update(
+        &local_cfg,
+        reset_db,
+        plugins,
+        exclude,
+        verify_processing,
+        max_duration,
+    )
+    .await
+        && save_data(&local_cfg).await;
+
+    lock.release().await;
+    if !succeeded {
+        exit(1);
+    }
+}
+
+/// Runs each stage of an update, and records the run.
+/// Returns false if a stage failed, after logging why.
+async fn run_update(
+    cfg: &LocalConfig,
+    reset_db: bool,
+    plugins: Option<&Vec<String>>,
+    exclude: bool,
+    verify_processing: bool,
+    max_duration: Option<Duration>,
+) -> bool {
     if reset_db {
-        match reset(&local_cfg).await {
+        match reset(cfg).await {
             Ok(true) => {
                 success!("Database was reset.");
             }
             Ok(false) => {
                 success!("Aborting database reset — no data will be destroyed.");
-                exit(1);
+                return false;
             }
             Err(err) => {
                 error!("Failed to reset database before updating: {err}");
-                exit(1);
+                return false;
             }
         }
     }
 
-    // Detect if this is the first run - initialise db if so
-    let mut first_run = false;
-    match local_cfg.con().await {
-        Ok(mut con) => match con.total_change_count().await {
-            Ok(count) => {
-                if count == 0 {
-                    info!("Initialising the new database...");
-                    if let Err(err) = con.init().await {
-                        error!("Failed to initialise database: {err}");
-                        exit(1);
-                    }
-                    first_run = true;
-                }
-            }
-            Err(err) => {
-                error!("Failed to determine if changelog is empty: {err}");
-            }
-        },
-        Err(err) => {
-            error!("Failed to get connection to redis: {err}");
-            exit(1);
+    let mut timer = RunTimer::start(RunKind::Update);
+    let succeeded = update_stages(
+        cfg,
+        &mut timer,
+        plugins,
+        exclude,
+        verify_processing,
+        max_duration,
+    )
+    .await
+    .is_some();
+
+    if succeeded {
+        timer.phase("finalise", true);
+    }
+    timer.save(cfg, succeeded).await;
+    succeeded
+}
+
+/// Runs the plugins, processes their data and produces the reports of an update.
+/// Returns none if a stage failed, after logging why.
+async fn update_stages(
+    cfg: &LocalConfig,
+    timer: &mut RunTimer,
+    plugins: Option<&Vec<String>>,
+    exclude: bool,
+    verify_processing: bool,
+    max_duration: Option<Duration>,
+) -> Option<()> {
+    let first_run = init_store(cfg, timer).await?;
+
+    // Changes after this one are made by the plugins in this update.
+    let update_start = match cfg.con().await {
+        Ok(mut con) => con.last_change_id().await.ok(),
+        Err(_) => None,
+    };
+
+    // Scope violations are reported for the plugins in this update only.
+    if let Ok(mut con) = cfg.con().await {
+        if let Err(err) = con.clear_scope_violations().await {
+            warn!("Failed to clear the scope violations of the last update: {err}");
         }
     }
 
-    let write_only_results = match update::run_plugin_stage(
-        &local_cfg,
+    let deferrals = match cfg.con().await {
+        Ok(mut con) => Deferrals::load(&mut con, max_duration).await,
+        Err(err) => Err(err),
+    };
+    let mut deferrals = match deferrals {
+        Ok(deferrals) => deferrals,
+        Err(err) => {
+            error!("Failed to get the plugins deferred by the last update: {err}");
+            return None;
+        }
+    };
+
+    let write_only_results = plugin_stage(
+        cfg,
+        timer,
         PluginStage::WriteOnly,
         plugins,
         exclude,
+        &mut deferrals,
     )
-    .await
-    {
-        Ok(results) => results,
+    .await?;
+
+    warn_legacy_metadata(cfg).await;
+
+    info!("Processing data...");
+    let (processed, remote_res) = join!(
+        process_data(cfg, timer, verify_processing),
+        cfg.remote().config()
+    );
+    processed?;
+    apply_remote_config(cfg, timer, remote_res, first_run).await?;
+
+    let read_write_results = plugin_stage(
+        cfg,
+        timer,
+        PluginStage::ReadWrite,
+        plugins,
+        exclude,
+        &mut deferrals,
+    )
+    .await?;
+
+    let connectors_results = plugin_stage(
+        cfg,
+        timer,
+        PluginStage::Connectors,
+        plugins,
+        exclude,
+        &mut deferrals,
+    )
+    .await?;
+
+    let results = vec![write_only_results, read_write_results, connectors_results]
+        .into_iter()
+        .flatten()
+        .collect();
+
+    report_results(cfg, &deferrals, results, update_start.as_deref()).await
+}
+
+/// Initialises the data store if this is the first update to run against it.
+/// Returns true if it was initialised.
+async fn init_store(cfg: &LocalConfig, timer: &mut RunTimer) -> Option<bool> {
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
         Err(err) => {
-            error!("Failed to run plugins: {err}");
-            exit(1);
+            error!("Failed to get connection to redis: {err}");
+            return None;
         }
     };
 
-    read_results(&write_only_results);
+    match con.total_change_count().await {
+        Ok(0) => {
+            info!("Initialising the new database...");
+            if let Err(err) = con.init().await {
+                error!("Failed to initialise database: {err}");
+                return None;
+            }
+            timer.phase("init", true);
+            Some(true)
+        }
+        Ok(_) => Some(false),
+        Err(err) => {
+            error!("Failed to determine if changelog is empty: {err}");
+            Some(false)
+        }
+    }
+}
+
+/// Runs the plugins for one stage of an update and logs their results.
+async fn plugin_stage(
+    cfg: &LocalConfig,
+    timer: &mut RunTimer,
+    stage: PluginStage,
+    plugins: Option<&Vec<String>>,
+    exclude: bool,
+    deferrals: &mut Deferrals,
+) -> Option<Vec<PluginResult>> {
+    let (snapshot, mut results) =
+        match update::run_plugin_stage(cfg, stage, plugins, exclude, deferrals)
+            .instrument(info_span!("stage", stage = %stage))
+            .await
+        {
+            Ok(results) => results,
+            Err(err) => {
+                error!("Failed to run plugins for the {stage} stage: {err}");
+                return None;
+            }
+        };
 
-    info!("Processing data...");
-    let (proc_res, remote_res) = join!(process(&local_cfg), local_cfg.remote.config());
+    log_results(cfg, timer, &mut results);
+    read_results(&results);
+    timer.stage(stage, snapshot, &results);
+    Some(results)
+}
 
-    if let Err(err) = proc_res {
+/// Warns if any metadata is stored under the legacy key layout.
+async fn warn_legacy_metadata(cfg: &LocalConfig) {
+    if let Ok(mut con) = cfg.con().await {
+        match con.migrate_metadata(true).await {
+            Ok(migration) if !migration.moved.is_empty() => warn!(
+                "Found metadata for {} objects stored under the legacy key layout, which will not be displayed. Run: netdox migrate",
+                migration.moved.len()
+            ),
+            Ok(_) => {}
+            Err(err) => debug!("Failed to check for legacy metadata: {err}"),
+        }
+    }
+}
+
+/// Processes the data from the plugins, and optionally checks that doing so twice gives the same result.
+async fn process_data(cfg: &LocalConfig, timer: &mut RunTimer, verify: bool) -> Option<()> {
+    if let Err(err) = process(cfg).instrument(info_span!("process")).await {
         error!("Failed while processing data: {err}");
-        exit(1);
-    } else {
-        success!("Processed data.");
+        timer.phase("process", false);
+        return None;
     }
+    success!("Processed data.");
+    timer.phase("process", true);
 
-    info!("Applying remote config to data.");
-    if let Ok(remote_cfg) = remote_res {
-        match local_cfg.con().await {
-            Ok(con) => {
-                let (locations_res, metadata_res) = join!(
-                    remote_cfg.set_locations(con.clone()),
-                    remote_cfg.set_metadata(con, &local_cfg.remote)
-                );
+    if !verify {
+        return Some(());
+    }
 
-                let mut failed = false;
-                if let Err(err) = locations_res {
-                    error!("Failed while setting locations: {err}");
-                    failed = true;
-                }
-                if let Err(err) = metadata_res {
-                    error!("Failed while setting metadata overrides: {err}");
-                }
+    let verified = match cfg.con().await {
+        Ok(mut con) => process::verify(&mut con).await,
+        Err(err) => Err(err),
+    };
 
-                if failed {
-                    exit(1);
-                } else {
-                    success!("Applied remote config.");
-                }
-            }
-            Err(err) => {
-                error!("Failed to get connection to redis: {err}");
-                exit(1);
+    match verified {
+        Ok(differences) if differences.is_empty() => {
+            success!("Processing gave the same result twice.");
+            timer.phase("verify-processing", true);
+            Some(())
+        }
+        Ok(differences) => {
+            for key in &differences {
+                error!("Processing gave a different result for {key} the second time.");
             }
+            error!(
+                "Processing is not deterministic: {} keys differed between two runs on the same data.",
+                differences.len()
+            );
+            timer.phase("verify-processing", false);
+            None
+        }
+        Err(err) => {
+            error!("Failed to verify processing: {err}");
+            timer.phase("verify-processing", false);
+            None
         }
-    } else if !first_run {
-        warn!("Failed to pull config from the remote, and this doesn't appear to be the first update. \
-            Error was: {}", remote_res.unwrap_err());
     }
+}
 
-    let read_write_results = match update::run_plugin_stage(
-        &local_cfg,
-        PluginStage::ReadWrite,
-        plugins,
-        exclude,
-    )
-    .await
-    {
-        Ok(results) => results,
+/// Applies the locations and metadata overrides from the remote config to the data.
+async fn apply_remote_config(
+    cfg: &LocalConfig,
+    timer: &mut RunTimer,
+    remote_res: NetdoxResult<RemoteConfig>,
+    first_run: bool,
+) -> Option<()> {
+    info!("Applying remote config to data.");
+    let remote_cfg = match remote_res {
+        Ok(remote_cfg) => remote_cfg,
         Err(err) => {
-            error!("Failed to run plugins for read-write stage: {err}");
-            exit(1);
+            if !first_run {
+                warn!("Failed to pull config from the remote, and this doesn't appear to be the first update. \
+                    Error was: {err}");
+            }
+            return Some(());
         }
     };
 
-    read_results(&read_write_results);
+    let con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get connection to redis: {err}");
+            return None;
+        }
+    };
 
-    let connectors_results =
-        match update::run_plugin_stage(&local_cfg, PluginStage::Connectors, plugins, exclude).await
-        {
-            Ok(results) => results,
-            Err(err) => {
-                error!("Failed to run plugins for connectors stage: {err}");
-                exit(1);
-            }
-        };
+    let (locations_res, metadata_res) = join!(
+        remote_cfg.set_locations(con.clone()),
+        remote_cfg.set_metadata(con, cfg.remote())
+    );
+
+    let mut failed = false;
+    if let Err(err) = locations_res {
+        error!("Failed while setting locations: {err}");
+        failed = true;
+    }
+    if let Err(err) = metadata_res {
+        error!("Failed while setting metadata overrides: {err}");
+    }
 
-    read_results(&connectors_results);
+    timer.phase("remote-config", !failed);
+    if failed {
+        return None;
+    }
+    success!("Applied remote config.");
+    Some(())
+}
 
-    let mut con = match local_cfg.con().await {
+/// Records the deferred plugins and produces the reports on the plugins that ran in an update.
+async fn report_results(
+    cfg: &LocalConfig,
+    deferrals: &Deferrals,
+    results: Vec<PluginResult>,
+    update_start: Option<&str>,
+) -> Option<()> {
+    let mut con = match cfg.con().await {
         Ok(con) => con,
         Err(err) => {
             error!("Failed to get connection to redis: {err}");
-            exit(1);
+            return None;
         }
     };
 
-    let combined_results = vec![write_only_results, read_write_results, connectors_results]
-        .into_iter()
-        .flatten()
-        .collect();
+    if let Err(err) = deferrals.save(&mut con).await {
+        warn!("Failed to record the plugins deferred to the next update: {err}");
+    }
+
+    let field_warnings = field_warnings(&cfg.plugins, &results);
+    for warning in &field_warnings {
+        warn!(
+            "Config fields of plugin {} do not match the fields it read — {}",
+            warning.plugin,
+            warning.describe()
+        );
+    }
+
+    if let Err(err) = plugin_run_report(&mut con, &results, &field_warnings).await {
+        warn!("Failed to produce plugin run report: {err}");
+    }
+
+    let violations = match con.get_scope_violations().await {
+        Ok(violations) => violations,
+        Err(err) => {
+            warn!("Failed to get the DNS names refused to plugins outside their scope: {err}");
+            HashMap::new()
+        }
+    };
+    for (plugin, names) in &violations {
+        warn!(
+            "Refused {} DNS names outside the scope of plugin {plugin}, such as {}",
+            names.len(),
+            names.iter().min().map(String::as_str).unwrap_or_default()
+        );
+    }
+
+    if let Err(err) = plugin_health_report(&mut con, &results, update_start, &violations).await {
+        warn!("Failed to produce plugin health report: {err}");
+    }
 
-    if let Err(err) = plugin_error_report(&mut con, combined_results).await {
+    if let Err(err) = plugin_error_report(&mut con, results).await {
         error!("Failed to produce plugin error report: {err}");
-        exit(1);
+        return None;
     }
 
-    if let Err(err) = con.write_save().await {
-        error!("{err}");
-        exit(1);
+    Some(())
+}
+
+/// Asks the data store to save its data to disk, if configured to.
+/// Returns false if it failed, after logging why.
+async fn save_data(cfg: &LocalConfig) -> bool {
+    let saved = match cfg.con().await {
+        Ok(mut con) => {
+            con.write_save(cfg.redis.save, Duration::from_secs(cfg.redis.save_timeout))
+                .await
+        }
+        Err(err) => Err(err),
+    };
+
+    match saved {
+        Ok(()) => true,
+        Err(err) => {
+            error!("{err}");
+            false
+        }
     }
 }
 
@@ -437,7 +1342,7 @@ async fn update(reset_db: bool, plugins: Option<&Vec<String>>, exclude: bool) {
 async fn reset(cfg: &LocalConfig) -> NetdoxResult<bool> {
     print!(
         "Are you sure you want to reset {}? All data will be lost (y/N): ",
-        cfg.redis.url()
+        cfg.store_name()
     );
     let _ = stdout().flush();
     let mut input = String::new();
@@ -449,28 +1354,8 @@ async fn reset(cfg: &LocalConfig) -> NetdoxResult<bool> {
         return Ok(false);
     }
 
-    let mut con = match Client::open(cfg.redis.url().as_str()) {
-        Ok(client) => match client.get_multiplexed_async_connection().await {
-            Ok(con) => con,
-            Err(err) => {
-                return redis_err!(format!(
-                    "Failed to open redis connection: {}",
-                    err.to_string()
-                ))
-            }
-        },
-        Err(err) => return redis_err!(format!("Failed to open redis client: {}", err.to_string())),
-    };
-
-    if let Some(pass) = &cfg.redis.password {
-        DataStore::Redis(con.clone())
-            .auth(pass, cfg.redis.username.as_ref())
-            .await?;
-    }
-
-    if let Err(err) = redis_cmd("FLUSHALL").query_async::<String>(&mut con).await {
-        return redis_err!(format!("Failed to flush database: {}", err.to_string()));
-    }
+    let mut con = cfg.con().await?;
+    con.flush().await?;
 
     con.setup(cfg).await?;
     con.init().await?;
@@ -478,11 +1363,26 @@ async fn reset(cfg: &LocalConfig) -> NetdoxResult<bool> {
     Ok(true)
 }
 
+/// Writes the output of plugins to the log directory, if one is configured.
+fn log_results(cfg: &LocalConfig, timer: &RunTimer, results: &mut [PluginResult]) {
+    if let Some(dir) = &cfg.plugin_log_dir {
+        if let Err(err) = update::write_plugin_logs(dir, timer.started(), results) {
+            warn!("Failed to write plugin logs: {err}");
+        }
+    }
+}
+
 /// Reads subprocess results and logs warnings or errors where required.
 fn read_results(results: &Vec<PluginResult>) {
     let mut any_err = false;
     for result in results {
-        if let Some(num) = result.code {
+        if result.timed_out {
+            any_err = true;
+            error!(
+                "Plugin {} timed out during {} stage and was killed.",
+                result.name, result.stage
+            );
+        } else if let Some(num) = result.code {
             if num != 0 {
                 any_err = true;
                 error!(
@@ -503,23 +1403,43 @@ fn read_results(results: &Vec<PluginResult>) {
     }
 }
 
-/// Processes raw nodes into linkable nodes.
+/// Removes stale data if configured to, then processes raw nodes into linkable nodes.
 async fn process(config: &LocalConfig) -> NetdoxResult<()> {
-    let con = match config.con().await {
+    let mut con = match config.con().await {
         Ok(con) => con,
         Err(err) => {
             return redis_err!(format!(
                 "Failed to create client for redis server at {}: {err}",
-                &config.redis.url()
+                redact_url(&config.redis.url())
             ))
         }
     };
 
+    if let Some(updates) = config.prune_after_updates {
+        let pruned = prune::prune_stale(&mut con, updates).await?;
+        if !pruned.is_empty() {
+            info!(
+                "Removed {} that were not reported recently.",
+                prune::summary(&pruned)
+            );
+        }
+    }
+
     process::process(con).await
 }
 
 #[tokio::main]
-async fn publish(backup: Option<PathBuf>) {
+#[allow(clippy::too_many_arguments)]
+async fn publish(
+    backup: Option<PathBuf>,
+    dry_run: bool,
+    only: Option<String>,
+    tag: Option<String>,
+    reconcile: bool,
+    force: bool,
+    force_unlock: bool,
+    remote: Option<String>,
+) {
     let cfg = match LocalConfig::read() {
         Ok(cfg) => cfg,
         Err(err) => {
@@ -528,30 +1448,206 @@ async fn publish(backup: Option<PathBuf>) {
         }
     };
 
-    let con = match cfg.con().await {
+    let remotes = match cfg.select_remotes(remote.as_deref()) {
+        Ok(remotes) => remotes,
+        Err(err) => {
+            error!("{err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
         Ok(con) => con,
         Err(err) => {
             error!(
                 "Failed to create connection to redis server at {}: {err}",
-                cfg.redis.url()
+                redact_url(&cfg.redis.url())
             );
             exit(1);
         }
     };
 
-    match cfg.remote.publish(con, backup).await {
-        Ok(()) => success!("Publishing complete."),
+    let cursors = match con.get_publish_cursors().await {
+        Ok(cursors) => cursors,
         Err(err) => {
-            error!("Failed to publish: {err}");
+            error!("Failed to get the publish cursors of the remotes: {err}");
             exit(1);
         }
+    };
+
+    if dry_run {
+        for named in &remotes {
+            println!(
+                "Remote {} (last change published from this data store: {}):",
+                named.name,
+                cursors.get(&named.name).map_or("none", String::as_str)
+            );
+            match named.remote.plan(con.clone()).await {
+                Ok(plan) => print!("{}", plan.summary()),
+                Err(err) => {
+                    error!("Failed to plan publish to remote {}: {err}", named.name);
+                    exit(1);
+                }
+            }
+
+            if reconcile {
+                match named.remote.reconcile(con.clone(), true).await {
+                    Ok(orphans) => {
+                        println!("Orphaned documents that would be archived or removed:");
+                        for orphan in &orphans {
+                            println!("  {orphan}");
+                        }
+                        if let Err(err) =
+                            remote::check_reconcile(&mut con, orphans.len(), force).await
+                        {
+                            println!("The reconcile would be refused: {err}");
+                        }
+                    }
+                    Err(err) => {
+                        error!(
+                            "Failed to find orphaned documents on remote {}: {err}",
+                            named.name
+                        );
+                        exit(1);
+                    }
+                }
+            }
+        }
+        return;
+    }
+
+    let objects = match (only, tag) {
+        (None, None) => None,
+        (only, tag) => {
+            let pattern = only.unwrap_or_else(|| "*".to_string());
+            let mut selected = ObjectSelection::matching(&mut con, &pattern).await;
+            if let (Ok(objects), Some(tag)) = (&mut selected, &tag) {
+                match ObjectSelection::tagged(&mut con, tag).await {
+                    Ok(tagged) => objects.retain(&tagged),
+                    Err(err) => selected = Err(err),
+                }
+            }
+
+            let described = match &tag {
+                Some(tag) => format!("matching {pattern} with tag {tag}"),
+                None => format!("matching {pattern}"),
+            };
+            match selected {
+                Ok(objects) if objects.is_empty() => {
+                    error!("No objects {described}");
+                    exit(1);
+                }
+                Ok(objects) => {
+                    info!("Publishing {} objects {described}", objects.len());
+                    Some(objects)
+                }
+                Err(err) => {
+                    error!("Failed to select objects to publish: {err}");
+                    exit(1);
+                }
+            }
+        }
+    };
+
+    // Taken after selecting objects, so that every failure after this point releases it.
+    let lock = match RunLockGuard::acquire(con.clone(), RunKind::Publish, force_unlock).await {
+        Ok(lock) => lock,
+        Err(err) => {
+            error!("Failed to lock the data store for this publish: {err}");
+            exit(1);
+        }
+    };
+
+    let last_change = match con.last_change_id().await {
+        Ok(id) => id,
+        Err(err) => {
+            error!("Failed to get the last change in the changelog: {err}");
+            lock.release().await;
+            exit(1);
+        }
+    };
+
+    let mut timer = RunTimer::start(RunKind::Publish);
+    let mut failed = false;
+    for named in &remotes {
+        if objects.is_none() && !reconcile && cursors.get(&named.name) == Some(&last_change) {
+            info!(
+                "Remote {} is up to date with change {last_change}, skipping it.",
+                named.name
+            );
+            continue;
+        }
+
+        let backup = match &backup {
+            Some(path) if remotes.len() > 1 => Some(remote::backup_path(path, &named.name)),
+            backup => backup.clone(),
+        };
+        let published = with_remote_lock(&named.remote, force_unlock, async {
+            let published = match &objects {
+                Some(objects) => {
+                    named
+                        .remote
+                        .publish_objects(con.clone(), objects, backup, &mut timer)
+                        .instrument(info_span!("publish", objects = objects.len()))
+                        .await
+                }
+                None => {
+                    named
+                        .remote
+                        .publish(con.clone(), backup, &mut timer)
+                        .instrument(info_span!("publish"))
+                        .await
+                }
+            };
+            match published {
+                Ok(()) if reconcile => {
+                    let reconciled = remote::reconcile(&named.remote, con.clone(), force).await;
+                    timer.phase("reconcile", reconciled.is_ok());
+                    reconciled.map(|orphans| {
+                        for orphan in &orphans {
+                            info!("Archived or removed orphaned document {orphan}");
+                        }
+                        success!("Reconciled {} orphaned documents.", orphans.len());
+                    })
+                }
+                result => result,
+            }
+        })
+        .instrument(info_span!("remote", name = named.name))
+        .await
+        .and_then(|published| published);
+
+        match published {
+            Ok(()) => {
+                success!("Published to remote {}.", named.name);
+                if objects.is_none() {
+                    if let Err(err) = con.put_publish_cursor(&named.name, &last_change).await {
+                        warn!(
+                            "Failed to record the publish cursor of remote {}, so it will be published again: {err}",
+                            named.name
+                        );
+                    }
+                }
+            }
+            Err(err) => {
+                error!("Failed to publish to remote {}: {err}", named.name);
+                failed = true;
+            }
+        }
     }
+
+    timer.save(&cfg, !failed).await;
+    lock.release().await;
+    if failed {
+        exit(1);
+    }
+    success!("Publishing complete.");
 }
 
 // CONFIG
 
 #[tokio::main]
-async fn load_cfg(path: &PathBuf) {
+async fn load_cfg(path: &PathBuf, secret: Option<SecretSource>) {
     let string = match fs::read_to_string(path) {
         Ok(string) => string,
         Err(err) => {
@@ -568,20 +1664,24 @@ async fn load_cfg(path: &PathBuf) {
         }
     };
 
-    if let Err(err) = cfg.remote.test().await {
-        error!("New config remote failed test: {err}");
+    if let Err(err) = check_new_cfg(&cfg).await {
+        error!("{err}");
         exit(1);
     }
 
     let mut con = match cfg.con().await {
-        Ok(DataStore::Redis(con)) => con,
+        Ok(con) => con,
         Err(err) => {
             error!("{err}");
             exit(1);
         }
     };
 
-    if let Err(err) = cfg.write() {
+    let written = match &secret {
+        Some(source) => cfg.write_with_source(source),
+        None => cfg.write(),
+    };
+    if let Err(err) = written {
         error!("Failed to write new config: {err}");
         exit(1);
     }
@@ -594,6 +1694,246 @@ async fn load_cfg(path: &PathBuf) {
     info!("Encrypted and stored config from {path:?}");
 }
 
+/// Checks a config before it is stored, by parsing its schedule and testing each remote.
+async fn check_new_cfg(cfg: &LocalConfig) -> NetdoxResult<()> {
+    if let Some(schedule) = &cfg.schedule {
+        if let Err(err) = schedule.parse::<Schedule>() {
+            return config_err!(err.to_string());
+        }
+    }
+
+    for named in &cfg.remotes {
+        if let Err(err) = named.remote.test().await {
+            return config_err!(format!(
+                "New config remote {} failed test: {err}",
+                named.name
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn edit_cfg() {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to read encrypted local config: {err}");
+            exit(1);
+        }
+    };
+
+    let original = match toml::to_string_pretty(&cfg) {
+        Ok(toml) => toml,
+        Err(err) => {
+            error!("Failed to write config as TOML: {err}");
+            exit(1);
+        }
+    };
+
+    let path = env::temp_dir().join(format!("netdox-config-{}.toml", std::process::id()));
+    let edited = match write_private(&path, &original) {
+        Ok(()) => edit_until_valid(&path, &original).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = fs::remove_file(&path) {
+        warn!(
+            "Failed to remove the plain text config at {}: {err}",
+            path.display()
+        );
+    }
+
+    let cfg = match edited {
+        Ok(Some(cfg)) => cfg,
+        Ok(None) => return,
+        Err(err) => {
+            error!("Failed to edit config: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("{err}");
+            exit(1);
+        }
+    };
+
+    if let Err(err) = cfg.write() {
+        error!("Failed to write edited config: {err}");
+        exit(1);
+    }
+
+    if let Err(err) = con.setup(&cfg).await {
+        error!("Failed to initialise database with edited config: {err}");
+        exit(1);
+    }
+
+    success!("Encrypted and stored edited config.");
+}
+
+/// Writes a file that only the current user can read.
+fn write_private(path: &Path, content: &str) -> NetdoxResult<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    match options
+        .open(path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+    {
+        Ok(()) => Ok(()),
+        Err(err) => io_err!(format!("Failed to write {}: {err}", path.display())),
+    }
+}
+
+/// Opens a config in the editor until it is valid or the changes are discarded.
+/// Returns the edited config, or None if it was not changed or the changes were discarded.
+async fn edit_until_valid(path: &Path, original: &str) -> NetdoxResult<Option<LocalConfig>> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+
+    loop {
+        // The editor may have arguments, like `code --wait`.
+        match std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("{editor} \"$1\""))
+            .arg("sh")
+            .arg(path)
+            .status()
+        {
+            Ok(status) if status.success() => {}
+            Ok(status) => return io_err!(format!("Editor `{editor}` exited with {status}")),
+            Err(err) => return io_err!(format!("Failed to run editor `{editor}`: {err}")),
+        }
+
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) => return io_err!(format!("Failed to read {}: {err}", path.display())),
+        };
+        if content == original {
+            info!("Config was not changed.");
+            return Ok(None);
+        }
+
+        let problem = match toml::from_str::<LocalConfig>(&content) {
+            Ok(cfg) => match check_new_cfg(&cfg).await {
+                Ok(()) => return Ok(Some(cfg)),
+                Err(err) => err.to_string(),
+            },
+            Err(err) => format!("Failed to parse config as TOML: {err}"),
+        };
+        error!("{problem}");
+
+        print!("Edit the config again? Otherwise the changes are discarded (Y/n): ");
+        let _ = stdout().flush();
+        let mut input = String::new();
+        if let Err(err) = stdin().read_line(&mut input) {
+            return io_err!(format!("Failed to read input: {err}"));
+        }
+        if matches!(input.trim(), "n" | "no") {
+            info!("Discarded changes to config.");
+            return Ok(None);
+        }
+    }
+}
+
+#[tokio::main]
+async fn check_remote_cfg() {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to check remote config: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to check remote config: {err}");
+            exit(1);
+        }
+    };
+
+    let remote_cfg = match cfg.remote().config().await {
+        Ok(remote_cfg) => remote_cfg,
+        Err(err) => {
+            error!("Failed to get remote config: {err}");
+            exit(1);
+        }
+    };
+
+    let mut labeled = HashMap::new();
+    for label in remote_cfg.metadata.keys() {
+        match cfg.remote().labeled(label).await {
+            Ok(objects) => {
+                labeled.insert(label.clone(), objects);
+            }
+            Err(err) => {
+                error!("Failed to get documents labelled {label} on the remote: {err}");
+                exit(1);
+            }
+        }
+    }
+
+    let (qnames, node_ids) = match (con.get_dns_names().await, con.get_node_ids().await) {
+        (Ok(qnames), Ok(node_ids)) => (qnames, node_ids),
+        (Err(err), _) | (_, Err(err)) => {
+            error!("Failed to get objects in the data store: {err}");
+            exit(1);
+        }
+    };
+
+    let warnings = remote_cfg.check(&qnames, &node_ids, &labeled);
+    for warning in &warnings {
+        warn!("{warning}");
+    }
+    if warnings.is_empty() {
+        success!(
+            "Remote config with {} exclusions, {} locations and {} metadata overrides has no problems.",
+            remote_cfg.exclusions.len(),
+            remote_cfg.locations.len(),
+            remote_cfg.metadata.len()
+        );
+    } else {
+        info!("Found {} problems with the remote config.", warnings.len());
+    }
+}
+
+fn rekey_cfg(secret: Option<SecretSource>) {
+    let Some(source) = secret else {
+        error!("Pass --secret-env, --secret-file or --secret-command to choose the new secret.");
+        exit(1);
+    };
+
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to read encrypted local config: {err}");
+            exit(1);
+        }
+    };
+
+    match cfg.write_with_source(&source) {
+        Ok(path) => success!(
+            "Encrypted the config at {} with the secret from the {source}.",
+            path.display()
+        ),
+        Err(err) => {
+            error!("Failed to encrypt the config with the new secret: {err}");
+            exit(1);
+        }
+    }
+}
+
 fn dump_cfg(path: &PathBuf) {
     let cfg = match LocalConfig::read() {
         Ok(cfg) => cfg,
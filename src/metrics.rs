@@ -0,0 +1,347 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use tracing::{debug, warn};
+
+use crate::{
+    data::{
+        model::{RunKind, RunRecord, MAX_RUNS},
+        DataConn,
+    },
+    error::{NetdoxError, NetdoxResult},
+    io_err,
+    update::PluginResult,
+};
+
+const RUN_DURATION: &str = "netdox_run_duration_seconds";
+const RUN_SUCCESS: &str = "netdox_run_success";
+const RUN_STARTED: &str = "netdox_run_started_timestamp_seconds";
+const PLUGIN_DURATION: &str = "netdox_plugin_duration_seconds";
+const PLUGIN_EXIT_CODE: &str = "netdox_plugin_exit_code";
+const OBJECTS: &str = "netdox_objects";
+const CHANGES: &str = "netdox_changelog_changes";
+const PENDING_CHANGES: &str = "netdox_changelog_pending_publish";
+
+/// Metrics that describe the data store rather than a run.
+/// They are written to their own textfile so that updates and publishes
+/// do not both write the same series.
+const STORE_METRICS: [&str; 3] = [OBJECTS, CHANGES, PENDING_CHANGES];
+
+/// Gauges with the same name, and their values by label set.
+struct Family {
+    help: &'static str,
+    samples: BTreeMap<Vec<(&'static str, String)>, f64>,
+}
+
+/// Every metric recorded by this process.
+static METRICS: Mutex<BTreeMap<&'static str, Family>> = Mutex::new(BTreeMap::new());
+static SERVING: AtomicBool = AtomicBool::new(false);
+
+/// Returns true if metrics are being served over HTTP.
+pub fn serving() -> bool {
+    SERVING.load(Ordering::Relaxed)
+}
+
+/// Sets the value of a gauge with some labels.
+fn set_gauge(name: &'static str, help: &'static str, labels: &[(&'static str, &str)], value: f64) {
+    let labels = labels
+        .iter()
+        .map(|(label, value)| (*label, value.to_string()))
+        .collect();
+
+    if let Ok(mut metrics) = METRICS.lock() {
+        metrics
+            .entry(name)
+            .or_insert_with(|| Family {
+                help,
+                samples: BTreeMap::new(),
+            })
+            .samples
+            .insert(labels, value);
+    }
+}
+
+/// Escapes a label value for the Prometheus text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Returns the metrics whose name passes a filter in the Prometheus text format.
+fn render(filter: impl Fn(&str) -> bool) -> String {
+    let mut text = String::new();
+    let Ok(metrics) = METRICS.lock() else {
+        return text;
+    };
+
+    for (name, family) in metrics.iter().filter(|(name, _)| filter(name)) {
+        text.push_str(&format!(
+            "# HELP {name} {}\n# TYPE {name} gauge\n",
+            family.help
+        ));
+        for (labels, value) in &family.samples {
+            let labels = labels
+                .iter()
+                .map(|(label, value)| format!("{label}=\"{}\"", escape(value)))
+                .collect::<Vec<_>>();
+            if labels.is_empty() {
+                text.push_str(&format!("{name} {value}\n"));
+            } else {
+                text.push_str(&format!("{name}{{{}}} {value}\n", labels.join(",")));
+            }
+        }
+    }
+
+    text
+}
+
+/// Records the runtime and exit code of each plugin in the results.
+pub fn record_plugins(results: &[PluginResult]) {
+    for result in results {
+        let stage = result.stage.to_string();
+        let labels = [("plugin", result.name.as_str()), ("stage", stage.as_str())];
+        set_gauge(
+            PLUGIN_DURATION,
+            "Time the plugin took to run in its last update.",
+            &labels,
+            result.duration.as_secs_f64(),
+        );
+        set_gauge(
+            PLUGIN_EXIT_CODE,
+            "Exit code of the plugin in its last update, or -1 if it was killed.",
+            &labels,
+            f64::from(result.code.unwrap_or(-1)),
+        );
+    }
+}
+
+/// Records the duration and outcome of a finished run.
+pub fn record_run(record: &RunRecord) {
+    let kind = record.kind.to_string();
+    let labels = [("kind", kind.as_str())];
+    set_gauge(
+        RUN_DURATION,
+        "Time the last run of this kind took.",
+        &labels,
+        record.millis as f64 / 1000.0,
+    );
+    set_gauge(
+        RUN_SUCCESS,
+        "Whether the last run of this kind succeeded.",
+        &labels,
+        f64::from(u8::from(record.success)),
+    );
+    set_gauge(
+        RUN_STARTED,
+        "Time the last run of this kind started, in seconds since the epoch.",
+        &labels,
+        record.started as f64,
+    );
+}
+
+/// Records the number of objects in the data store,
+/// and the number of changes made since the last successful publish started.
+pub async fn record_store(con: &mut impl DataConn) -> NetdoxResult<()> {
+    let help = "Number of objects of this kind in the data store.";
+    let counts = [
+        ("dns_names", con.get_dns_names().await?.len()),
+        ("raw_nodes", con.get_raw_nodes().await?.len()),
+        ("nodes", con.get_node_ids().await?.len()),
+    ];
+    for (kind, count) in counts {
+        set_gauge(OBJECTS, help, &[("kind", kind)], count as f64);
+    }
+
+    set_gauge(
+        CHANGES,
+        "Number of changes in the changelog.",
+        &[],
+        con.total_change_count().await? as f64,
+    );
+
+    let runs = con.get_runs(MAX_RUNS).await?;
+    let last_publish = runs
+        .iter()
+        .find(|run| run.kind == RunKind::Publish && run.success);
+    if let Some(run) = last_publish {
+        // Changes made after the last successful publish started were not published by it.
+        let start = format!("{}-{}", (run.started * 1000).saturating_sub(1), u64::MAX);
+        set_gauge(
+            PENDING_CHANGES,
+            "Number of changes made since the last successful publish started.",
+            &[],
+            con.get_changes(Some(&start)).await?.len() as f64,
+        );
+    }
+
+    Ok(())
+}
+
+/// Writes the metrics for a run to textfiles in a directory,
+/// for the textfile collector of the Prometheus node exporter.
+/// Metrics for the run go in a file named after its kind,
+/// and metrics for the data store in `netdox-store.prom`.
+pub fn write_textfiles(dir: &Path, kind: RunKind) -> NetdoxResult<()> {
+    write_textfile(
+        &dir.join(format!("netdox-{kind}.prom")),
+        &render(|name| !STORE_METRICS.contains(&name)),
+    )?;
+    write_textfile(
+        &dir.join("netdox-store.prom"),
+        &render(|name| STORE_METRICS.contains(&name)),
+    )
+}
+
+/// Writes a textfile by renaming a temporary file over it,
+/// so the collector never reads a partial file.
+fn write_textfile(path: &Path, text: &str) -> NetdoxResult<()> {
+    let tmp = path.with_extension("prom.tmp");
+    if let Err(err) = fs::write(&tmp, text) {
+        return io_err!(format!(
+            "Failed to write metrics to {}: {err}",
+            tmp.display()
+        ));
+    }
+    match fs::rename(&tmp, path) {
+        Ok(()) => Ok(()),
+        Err(err) => io_err!(format!(
+            "Failed to move metrics to {}: {err}",
+            path.display()
+        )),
+    }
+}
+
+/// Serves the metrics over HTTP at an address until the process exits.
+pub fn serve(addr: SocketAddr) -> NetdoxResult<()> {
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(err) => return io_err!(format!("Failed to listen for metrics on {addr}: {err}")),
+    };
+
+    debug!("Serving metrics on http://{addr}/metrics");
+    SERVING.store(true, Ordering::Relaxed);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("Failed to accept metrics request: {err}");
+                    continue;
+                }
+            };
+
+            // Every request gets the metrics, so the request is read up to the end of the headers.
+            if let Ok(read) = stream.try_clone() {
+                let mut reader = BufReader::new(read);
+                let mut line = String::new();
+                while matches!(reader.read_line(&mut line), Ok(read) if read > 0) {
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    line.clear();
+                }
+            }
+
+            let body = render(|_| true);
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            if let Err(err) = stream.write_all(response.as_bytes()) {
+                warn!("Failed to respond to metrics request: {err}");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        time::Duration,
+    };
+
+    use super::{
+        escape, record_plugins, record_run, render, serve, serving, PLUGIN_EXIT_CODE, RUN_DURATION,
+    };
+    use crate::{
+        config::PluginStage,
+        data::model::{RunKind, RunRecord},
+        update::PluginResult,
+    };
+
+    #[test]
+    fn test_render() {
+        assert_eq!(escape("a \"b\"\\c\n"), "a \\\"b\\\"\\\\c\\n");
+
+        record_run(&RunRecord {
+            kind: RunKind::Publish,
+            started: 1_700_000_000,
+            millis: 1500,
+            success: true,
+            timings: vec![],
+            stages: vec![],
+        });
+        record_plugins(&[PluginResult {
+            stage: PluginStage::WriteOnly,
+            name: "dns".to_string(),
+            code: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            duration: Duration::from_secs(2),
+            timed_out: true,
+            log_path: None,
+            skipped: false,
+        }]);
+
+        let text = render(|name| [RUN_DURATION, PLUGIN_EXIT_CODE].contains(&name));
+        let lines = text
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| line.rsplit_once(' ').unwrap())
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(
+            lines.get("netdox_plugin_exit_code{plugin=\"dns\",stage=\"write-only\"}"),
+            Some(&"-1")
+        );
+        assert_eq!(
+            lines.get("netdox_run_duration_seconds{kind=\"publish\"}"),
+            Some(&"1.5")
+        );
+        assert!(text.contains("# TYPE netdox_run_duration_seconds gauge\n"));
+    }
+
+    #[test]
+    fn test_serve() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        serve(addr).unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(serving());
+    }
+}
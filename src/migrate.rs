@@ -0,0 +1,55 @@
+use std::process::exit;
+
+use tracing::{error, info, warn};
+
+use crate::{config::LocalConfig, data::DataConn, success};
+
+/// Moves data stored under legacy key layouts to the current ones.
+#[tokio::main]
+pub async fn migrate(dry_run: bool) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to migrate data: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to migrate data: {err}");
+            exit(1);
+        }
+    };
+
+    // Stores with legacy layouts may have been set up by an older version.
+    if let Err(err) = con.setup(&cfg).await {
+        error!("Failed to set up data store before migrating: {err}");
+        exit(1);
+    }
+
+    let migration = match con.migrate_metadata(dry_run).await {
+        Ok(migration) => migration,
+        Err(err) => {
+            error!("Failed to migrate metadata: {err}");
+            exit(1);
+        }
+    };
+
+    for (legacy, new) in &migration.moved {
+        println!("meta;{legacy} -> meta;{new}");
+    }
+    for legacy in &migration.unresolved {
+        warn!("Could not tell which object legacy metadata belongs to: meta;{legacy}");
+    }
+
+    if dry_run {
+        info!(
+            "Would move metadata for {} objects. Run without --dry-run to migrate.",
+            migration.moved.len()
+        );
+    } else {
+        success!("Moved metadata for {} objects.", migration.moved.len());
+    }
+}
@@ -0,0 +1,392 @@
+use std::time::Duration;
+
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::time::sleep;
+use tracing::warn;
+
+use crate::{
+    config::{EventKind, NotificationsConfig, PluginStage, WebhookConfig, WebhookFormat},
+    data::model::{RunKind, RunRecord},
+    error::{NetdoxError, NetdoxResult},
+    io_err,
+    runs::format_millis,
+    update::PluginResult,
+};
+
+/// Time to wait for a webhook to respond.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Delay before the first retry of a failed webhook. Doubles for each retry after that.
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Something that happened which webhooks can be notified of.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    Update {
+        success: bool,
+        millis: u64,
+        /// Number of plugin stages that ran.
+        plugins: usize,
+        /// Number of plugin stages that failed.
+        failed_plugins: usize,
+        /// Number of changes made to the data store during the update.
+        changes: usize,
+    },
+    PluginFailure {
+        plugin: String,
+        stage: PluginStage,
+        code: Option<i32>,
+        timed_out: bool,
+    },
+    Publish {
+        success: bool,
+        millis: u64,
+        phases: usize,
+        failed_phases: usize,
+    },
+}
+
+impl Event {
+    /// Creates the event for a finished run.
+    /// Changes is the number of changes made to the data store during the run.
+    pub fn finished(record: &RunRecord, changes: usize) -> Self {
+        match record.kind {
+            RunKind::Update => {
+                // Plugin timings are named stage/plugin.
+                let plugins = record
+                    .timings
+                    .iter()
+                    .filter(|timing| timing.name.contains('/'));
+                Event::Update {
+                    success: record.success,
+                    millis: record.millis,
+                    plugins: plugins.clone().count(),
+                    failed_plugins: plugins.filter(|timing| !timing.success).count(),
+                    changes,
+                }
+            }
+            RunKind::Publish => Event::Publish {
+                success: record.success,
+                millis: record.millis,
+                phases: record.timings.len(),
+                failed_phases: record
+                    .timings
+                    .iter()
+                    .filter(|timing| !timing.success)
+                    .count(),
+            },
+        }
+    }
+
+    /// Creates the event for a plugin, if it failed.
+    pub fn plugin_failure(result: &PluginResult) -> Option<Self> {
+        if result.code == Some(0) && !result.timed_out {
+            return None;
+        }
+
+        Some(Event::PluginFailure {
+            plugin: result.name.clone(),
+            stage: result.stage,
+            code: result.code,
+            timed_out: result.timed_out,
+        })
+    }
+
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Self::Update { .. } => EventKind::Update,
+            Self::PluginFailure { .. } => EventKind::PluginFailure,
+            Self::Publish { .. } => EventKind::Publish,
+        }
+    }
+
+    /// False if the event describes something failing.
+    pub fn success(&self) -> bool {
+        match self {
+            Self::Update { success, .. } | Self::Publish { success, .. } => *success,
+            Self::PluginFailure { .. } => false,
+        }
+    }
+
+    /// Returns a sentence describing the event.
+    pub fn summary(&self) -> String {
+        match self {
+            Self::Update {
+                success,
+                millis,
+                plugins,
+                failed_plugins,
+                changes,
+            } => format!(
+                "Netdox update {} after {}: {failed_plugins} of {plugins} plugins failed, {changes} changes were made.",
+                if *success { "succeeded" } else { "failed" },
+                format_millis(*millis),
+            ),
+            Self::PluginFailure {
+                plugin,
+                stage,
+                code,
+                timed_out,
+            } => match (timed_out, code) {
+                (true, _) => format!("Netdox plugin {plugin} timed out in {stage} stage."),
+                (false, Some(code)) => {
+                    format!("Netdox plugin {plugin} failed in {stage} stage with exit code {code}.")
+                }
+                (false, None) => {
+                    format!("Netdox plugin {plugin} failed in {stage} stage with unknown exit code.")
+                }
+            },
+            Self::Publish {
+                success,
+                millis,
+                phases,
+                failed_phases,
+            } => format!(
+                "Netdox publish {} after {}: {failed_phases} of {phases} phases failed.",
+                if *success { "succeeded" } else { "failed" },
+                format_millis(*millis),
+            ),
+        }
+    }
+
+    /// Returns the body to post to a webhook in some format.
+    pub fn body(&self, format: WebhookFormat) -> Value {
+        match format {
+            WebhookFormat::Slack => json!({ "text": self.summary() }),
+            WebhookFormat::Teams => json!({
+                "@type": "MessageCard",
+                "@context": "https://schema.org/extensions",
+                "summary": format!("Netdox {}", self.kind()),
+                "themeColor": if self.success() { "2EB886" } else { "D00000" },
+                "text": self.summary(),
+            }),
+            WebhookFormat::Json => {
+                let mut body = serde_json::to_value(self).unwrap_or_else(|_| json!({}));
+                body["summary"] = Value::String(self.summary());
+                body
+            }
+        }
+    }
+}
+
+impl WebhookConfig {
+    /// Returns true if this webhook should be notified of an event.
+    pub fn accepts(&self, kind: EventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+}
+
+/// Posts events to every webhook that accepts them.
+/// Failing to notify a webhook only produces a warning.
+pub async fn notify(cfg: &NotificationsConfig, events: &[Event]) {
+    if cfg.webhooks.is_empty() || events.is_empty() {
+        return;
+    }
+
+    let client = match Client::builder().timeout(WEBHOOK_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(err) => {
+            warn!("Failed to create client for sending notifications: {err}");
+            return;
+        }
+    };
+
+    for event in events {
+        for (index, webhook) in cfg.webhooks.iter().enumerate() {
+            if !webhook.accepts(event.kind()) {
+                continue;
+            }
+            if let Err(err) = post(&client, webhook, event, cfg.retries).await {
+                warn!(
+                    "Failed to notify webhook {} of {} event: {err}",
+                    index + 1,
+                    event.kind()
+                );
+            }
+        }
+    }
+}
+
+/// Posts an event to a webhook until it succeeds or runs out of retries.
+async fn post(
+    client: &Client,
+    webhook: &WebhookConfig,
+    event: &Event,
+    retries: u32,
+) -> NetdoxResult<()> {
+    let body = event.body(webhook.format);
+    let mut attempt = 0;
+    loop {
+        let error = match client.post(&webhook.url).json(&body).send().await {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => format!("Webhook responded with status {}", response.status()),
+            Err(err) => format!("Failed to send request: {err}"),
+        };
+
+        if attempt >= retries {
+            return io_err!(error);
+        }
+
+        sleep(RETRY_BACKOFF * 2_u32.saturating_pow(attempt)).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::{notify, Event};
+    use crate::{
+        config::{EventKind, NotificationsConfig, PluginStage, WebhookConfig, WebhookFormat},
+        data::model::{RunKind, RunRecord, RunTiming},
+    };
+
+    fn timing(name: &str, success: bool) -> RunTiming {
+        RunTiming {
+            name: name.to_string(),
+            millis: 0,
+            success,
+            skipped: false,
+        }
+    }
+
+    #[test]
+    fn test_event_bodies() {
+        let record = RunRecord {
+            kind: RunKind::Update,
+            started: 0,
+            millis: 1500,
+            success: false,
+            timings: vec![
+                timing("write-only/dns", true),
+                timing("write-only/monitor", false),
+                timing("write-only", true),
+                timing("process", true),
+            ],
+            stages: vec![],
+        };
+        let event = Event::finished(&record, 12);
+        assert_eq!(
+            event,
+            Event::Update {
+                success: false,
+                millis: 1500,
+                plugins: 2,
+                failed_plugins: 1,
+                changes: 12,
+            }
+        );
+
+        let summary =
+            "Netdox update failed after 1.5s: 1 of 2 plugins failed, 12 changes were made.";
+        assert_eq!(event.body(WebhookFormat::Slack)["text"], summary);
+        assert_eq!(event.body(WebhookFormat::Teams)["text"], summary);
+        assert_eq!(event.body(WebhookFormat::Teams)["themeColor"], "D00000");
+
+        let json = event.body(WebhookFormat::Json);
+        assert_eq!(json["event"], "update");
+        assert_eq!(json["failed_plugins"], 1);
+        assert_eq!(json["summary"], summary);
+
+        let failure = Event::PluginFailure {
+            plugin: "dns".to_string(),
+            stage: PluginStage::ReadWrite,
+            code: Some(2),
+            timed_out: false,
+        };
+        let json = failure.body(WebhookFormat::Json);
+        assert_eq!(json["event"], "plugin-failure");
+        assert_eq!(json["stage"], "read-write");
+    }
+
+    #[test]
+    fn test_webhook_accepts() {
+        let mut webhook = WebhookConfig {
+            url: "http://localhost".to_string(),
+            format: WebhookFormat::Json,
+            events: HashSet::new(),
+        };
+        assert!(webhook.accepts(EventKind::Publish));
+
+        webhook.events.insert(EventKind::PluginFailure);
+        assert!(webhook.accepts(EventKind::PluginFailure));
+        assert!(!webhook.accepts(EventKind::Publish));
+    }
+
+    #[tokio::test]
+    async fn test_notify_retries() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        // Fails the first request and accepts the second.
+        let server = tokio::spawn(async move {
+            let mut bodies = vec![];
+            for status in ["500 Internal Server Error", "200 OK"] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![];
+                let mut buf = [0; 4096];
+                loop {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|line| {
+                                line.to_lowercase()
+                                    .strip_prefix("content-length: ")
+                                    .map(str::to_string)
+                            })
+                            .and_then(|length| length.trim().parse::<usize>().ok())
+                            .unwrap_or_default();
+                        if body.len() >= length {
+                            bodies.push(body.to_string());
+                            break;
+                        }
+                    }
+                }
+                stream
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                        )
+                        .as_bytes(),
+                    )
+                    .await
+                    .unwrap();
+            }
+            bodies
+        });
+
+        let cfg = NotificationsConfig {
+            retries: 1,
+            webhooks: vec![WebhookConfig {
+                url,
+                format: WebhookFormat::Slack,
+                events: HashSet::new(),
+            }],
+        };
+        let event = Event::Publish {
+            success: true,
+            millis: 10,
+            phases: 3,
+            failed_phases: 0,
+        };
+        notify(&cfg, std::slice::from_ref(&event)).await;
+
+        let bodies = server.await.unwrap();
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&bodies[1]).unwrap(),
+            event.body(WebhookFormat::Slack)
+        );
+    }
+}
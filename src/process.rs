@@ -1,18 +1,29 @@
 #[cfg(test)]
 mod tests;
 
-use std::collections::{hash_map::Entry, HashMap, HashSet};
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use futures::TryStreamExt;
 use itertools::Itertools;
-use paris::warn;
+use tracing::{debug, info, warn};
 
 use crate::{
     data::{
-        model::{Node, RawNode, DNS, NETDOX_PLUGIN},
+        model::{
+            matches_ignore_pattern, split_qname, Conflict, ConflictPolicy, Data,
+            MetadataProvenance, Node, ProcessRun, RawNode, DNS, DNS_KEY, NETDOX_PLUGIN,
+            PROC_NODES_KEY,
+        },
         store::DataStore,
+        stream::{dns_batches, raw_node_batches, BATCH_SIZE},
         DataConn,
     },
     error::NetdoxResult,
+    runs::format_timestamp,
+    snapshot::ObjectDigest,
 };
 
 /// Processes raw nodes and matches DNS names to a node.
@@ -28,13 +39,182 @@ use crate::{
 /// are copied to the original DNS name. These claims are given lower priority
 /// than regular claims of the same length.
 ///
+/// Linkable raw nodes with the same link ID are merged, and their names and the metadata
+/// of the processed node are chosen between with the conflict policy.
+///
 /// TODO refactor DNS->node matching into pure function
 pub async fn process(mut con: DataStore) -> NetdoxResult<()> {
-    let dns = con.get_dns().await?;
-    let raw_nodes = con.get_raw_nodes().await?;
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+
+    // Results of an interrupted run would otherwise be mixed with the results of this one.
+    if let Some(run) = con.get_interrupted_process().await? {
+        warn!(
+            "Processing that started at {} UTC was interrupted. Clearing the {} nodes and {} DNS names it may have partially written.",
+            format_timestamp(run.started),
+            run.nodes.len(),
+            run.dns_names.len()
+        );
+        con.clear_process(&run).await?;
+    }
+
+    let (dns, raw_nodes) = load(&mut con).await?;
+
+    let networks = con.get_networks().await?;
+    for (network, count) in unregistered_networks(&dns.qnames, &networks) {
+        warn!("Found {count} DNS names in unregistered network: {network}");
+    }
+
+    debug!(
+        "Processing {} DNS names and {} raw nodes.",
+        dns.qnames.len(),
+        raw_nodes.len()
+    );
+
+    let policy = con.get_conflict_policy().await?;
+    let Resolution {
+        nodes: node_map,
+        resolved,
+        mut conflicts,
+    } = resolve(&dns, raw_nodes, &policy)?;
+
+    // Everything this run writes is recorded first,
+    // so that the next run can clear it if this one is interrupted.
+    con.begin_process(&ProcessRun {
+        started,
+        nodes: node_map.keys().cloned().collect(),
+        dns_names: resolved
+            .iter()
+            .map(|(dns_name, _)| dns_name.to_string())
+            .collect(),
+    })
+    .await?;
+
+    // Swapped in all at once, so a publish never sees the nodes of two runs mixed together.
+    con.put_processed(&resolved, &node_map.values().collect_vec())
+        .await?;
+
+    con.end_process().await?;
+
+    for link_id in node_map.keys().sorted() {
+        conflicts.extend(con.get_node_metadata_conflicts(&node_map[link_id]).await?);
+    }
+    for conflict in &conflicts {
+        debug!(
+            "Chose {} for {} of node {} over {}",
+            describe_value(&conflict.chosen),
+            conflict.field(),
+            conflict.link_id,
+            conflict.rejected.iter().map(describe_value).join(", ")
+        );
+    }
+    if !conflicts.is_empty() {
+        info!(
+            "Chose between values from different plugins for {} fields of {} nodes.",
+            conflicts.len(),
+            conflicts
+                .iter()
+                .map(|conflict| &conflict.link_id)
+                .unique()
+                .count()
+        );
+    }
+    if policy.report {
+        conflict_report(&mut con, &conflicts).await?;
+    }
+
+    Ok(())
+}
+
+/// Returns a value with the plugin it came from, like "web-01 (vcenter)".
+fn describe_value(value: &MetadataProvenance) -> String {
+    format!(
+        "{} ({})",
+        value.value,
+        value.plugin.as_deref().unwrap_or("unknown plugin")
+    )
+}
+
+/// Writes a report of the fields of nodes that plugins gave different values for.
+async fn conflict_report(con: &mut DataStore, conflicts: &[Conflict]) -> NetdoxResult<()> {
+    let id = "conflicts";
+    let columns = ["Node", "Field", "Chosen", "Rejected"];
+
+    let mut content = columns.map(str::to_string).to_vec();
+    for conflict in conflicts {
+        content.extend([
+            format!("(!(procnode|!|{})!)", conflict.link_id),
+            conflict.field().to_string(),
+            describe_value(&conflict.chosen),
+            conflict.rejected.iter().map(describe_value).join(", "),
+        ]);
+    }
+
+    con.put_report(id, "Conflicts", 1).await?;
+    con.put_report_data(
+        id,
+        0,
+        &Data::Table {
+            id: "conflicts-table".to_string(),
+            title: "Fields of nodes that plugins gave different values for".to_string(),
+            columns: columns.len(),
+            plugin: NETDOX_PLUGIN.to_string(),
+            content,
+        },
+    )
+    .await
+}
+
+/// Processed nodes and the DNS names resolved to them, before they are written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+    /// Processed nodes by link ID.
+    pub nodes: HashMap<String, Node>,
+    /// Pairs of DNS name and the link ID of the node it resolved to.
+    pub resolved: Vec<(String, String)>,
+    /// Names that linkable raw nodes with the same link ID disagreed on, by link ID.
+    pub conflicts: Vec<Conflict>,
+}
+
+impl Resolution {
+    /// Returns a checksum of what will be written to each processed node key
+    /// and to the node metadata of each DNS name.
+    pub fn checksums(&self) -> BTreeMap<String, String> {
+        let mut checksums = BTreeMap::new();
+        for (link_id, node) in &self.nodes {
+            let mut digest = ObjectDigest::new();
+            digest.fields([&node.name]);
+            for set in [
+                &node.alt_names,
+                &node.dns_names,
+                &node.plugins,
+                &node.raw_ids,
+            ] {
+                digest.fields(set.iter().sorted());
+            }
+            checksums.insert(format!("{PROC_NODES_KEY};{link_id}"), digest.finish());
+        }
+
+        for (dns_name, link_id) in &self.resolved {
+            let mut digest = ObjectDigest::new();
+            digest.fields([link_id]);
+            checksums.insert(format!("{DNS_KEY};{dns_name}"), digest.finish());
+        }
+
+        checksums
+    }
+}
 
+/// Matches DNS names to processed nodes built from the raw nodes.
+pub fn resolve(
+    dns: &DNS,
+    raw_nodes: Vec<RawNode>,
+    policy: &ConflictPolicy,
+) -> NetdoxResult<Resolution> {
     let mut node_map = HashMap::new();
-    let proc_nodes = resolve_nodes(&dns, raw_nodes)?;
+    let (proc_nodes, conflicts) = resolve_nodes(dns, raw_nodes, policy)?;
 
     let mut dns_node_claims = HashMap::new();
     for (superset, node) in proc_nodes {
@@ -84,23 +264,26 @@ pub async fn process(mut con: DataStore) -> NetdoxResult<()> {
         }
     }
 
-    // Set metadata property on DNS names, and add the DNS name to the node's
+    // Resolve DNS names to a node, and add the DNS name to the node's
     // set of DNS names if not already present.
+    let mut resolved = vec![];
     for dns_name in &dns.qnames {
         let best_claim_link_id = match (
             terminal_node_claims.get(dns_name),
             dns_node_claims.get(dns_name),
         ) {
-            (Some(terminal_claims), Some(regular_claims)) => Some(
-                regular_claims
-                    .iter()
-                    .chain(terminal_claims)
-                    .sorted_by(|lhs, rhs| Ord::cmp(&lhs.0, &rhs.0))
-                    .next()
-                    .unwrap()
-                    .1
-                    .clone(),
-            ),
+            // Regular claims beat terminal claims of the same length,
+            // and claims of the same kind and length go to the lowest link ID.
+            (Some(terminal_claims), Some(regular_claims)) => regular_claims
+                .iter()
+                .map(|(len, link_id)| (len, false, link_id))
+                .chain(
+                    terminal_claims
+                        .iter()
+                        .map(|(len, link_id)| (len, true, link_id)),
+                )
+                .min()
+                .map(|(_, _, link_id)| link_id.clone()),
             (Some(terminal_claims), None) => {
                 Some(terminal_claims.iter().sorted().next().unwrap().1.clone())
             }
@@ -111,29 +294,128 @@ pub async fn process(mut con: DataStore) -> NetdoxResult<()> {
         };
 
         if let Some(link_id) = best_claim_link_id {
-            con.put_dns_metadata(
-                dns_name,
-                NETDOX_PLUGIN,
-                HashMap::from([
-                    ("node", format!("(!(procnode|!|{link_id})!)").as_ref()),
-                    ("_node", link_id.as_ref()),
-                ]),
-            )
-            .await?;
-
+            debug!("DNS name {dns_name} resolved to node: {link_id}");
             node_map
                 .get_mut(&link_id)
                 .unwrap()
                 .dns_names
                 .insert(dns_name.to_string());
+            resolved.push((dns_name.to_string(), link_id));
         }
     }
 
-    for node in node_map.values() {
-        con.put_node(node).await?;
+    Ok(Resolution {
+        nodes: node_map,
+        resolved,
+        conflicts,
+    })
+}
+
+/// Resolves the nodes in the data store twice and returns the keys
+/// whose checksums differ between the two, which should be none.
+/// Differences mean that resolution depends on something other than the data,
+/// like the iteration order of a hash map, and cause documents to change between
+/// publishes when nothing else has.
+pub async fn verify(con: &mut DataStore) -> NetdoxResult<Vec<String>> {
+    let (dns, raw_nodes) = load(con).await?;
+    let policy = con.get_conflict_policy().await?;
+
+    let first = resolve(&dns, raw_nodes.clone(), &policy)?.checksums();
+    let second = resolve(&dns, raw_nodes, &policy)?.checksums();
+    debug!("Compared checksums of {} processed keys.", first.len());
+
+    Ok(first
+        .keys()
+        .chain(second.keys())
+        .unique()
+        .filter(|key| first.get(*key) != second.get(*key))
+        .cloned()
+        .collect())
+}
+
+/// Gets the DNS data and raw nodes to process in batches,
+/// without the DNS names that match a pattern in the ignore list.
+async fn load(con: &mut DataStore) -> NetdoxResult<(DNS, Vec<RawNode>)> {
+    let mut dns = dns_batches(con.clone(), BATCH_SIZE)
+        .try_fold(DNS::new(), |mut dns, batch| async move {
+            dns.extend(batch);
+            Ok(dns)
+        })
+        .await?;
+    let mut raw_nodes = raw_node_batches(con.clone(), BATCH_SIZE)
+        .try_concat()
+        .await?;
+
+    let patterns = con.get_dns_ignore_patterns().await?;
+    if !patterns.is_empty() {
+        let ignored = ignore_names(&mut dns, &mut raw_nodes, &patterns);
+        if ignored > 0 {
+            debug!("Ignoring {ignored} DNS names that match patterns in the ignore list.");
+        }
     }
 
-    Ok(())
+    Ok((dns, raw_nodes))
+}
+
+/// Removes the DNS names that match any of the patterns from the DNS data and raw nodes,
+/// along with the records and translations to and from them,
+/// so they are never resolved to a node. Names created before a pattern was added
+/// are still in the data store. Raw nodes with no DNS names left are removed.
+/// Returns the number of DNS names removed.
+pub fn ignore_names(
+    dns: &mut DNS,
+    raw_nodes: &mut Vec<RawNode>,
+    patterns: &HashSet<String>,
+) -> usize {
+    let is_ignored = |qname: &str| {
+        patterns
+            .iter()
+            .any(|pattern| matches_ignore_pattern(pattern, qname))
+    };
+
+    let before = dns.qnames.len();
+    dns.qnames.retain(|qname| !is_ignored(qname));
+    dns.records.retain(|qname, _| !is_ignored(qname));
+    for records in dns.records.values_mut() {
+        records.retain(|record| !is_ignored(&record.value));
+    }
+    dns.net_translations.retain(|qname, _| !is_ignored(qname));
+    for translations in dns.net_translations.values_mut() {
+        translations.retain(|dest| !is_ignored(dest));
+    }
+    dns.implied_records.retain(|qname, _| !is_ignored(qname));
+    for implied in dns.implied_records.values_mut() {
+        implied.retain(|record| !is_ignored(&record.value));
+    }
+
+    for node in raw_nodes.iter_mut() {
+        node.dns_names.retain(|qname| !is_ignored(qname));
+    }
+    raw_nodes.retain(|node| !node.dns_names.is_empty());
+
+    before - dns.qnames.len()
+}
+
+/// Counts the DNS names in each network that is not registered.
+/// Unqualified names are counted under an empty network name.
+/// If there are no registered networks, every network is valid.
+pub fn unregistered_networks(
+    qnames: &HashSet<String>,
+    networks: &HashSet<String>,
+) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    if networks.is_empty() {
+        return counts;
+    }
+
+    for qname in qnames {
+        let network = split_qname(qname).map_or("", |(network, _)| network);
+        if !networks.contains(network) {
+            *counts.entry(network.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    counts
 }
 
 /// Copies the data from each locator into the node that matches based on `cmp`.
@@ -156,16 +438,14 @@ fn consume_locators<'a>(
         if matches.is_empty() {
             unmatched.push(*locator);
         } else {
-            // Let linkable node with smallest matching set of DNS names consume the locator.
+            // Let linkable node with smallest matching set of DNS names consume the locator,
+            // or the one with the lowest link ID if there is more than one.
             if matches.len() > 1 {
-                matches.sort_by(|n1, n2| {
-                    nodes
-                        .get(n1)
-                        .unwrap()
-                        .1
-                        .dns_names
-                        .len()
-                        .cmp(&nodes.get(n2).unwrap().1.dns_names.len())
+                matches.sort_by_cached_key(|link_id| {
+                    (
+                        nodes.get(link_id).unwrap().1.dns_names.len(),
+                        link_id.clone(),
+                    )
                 });
             }
 
@@ -180,31 +460,85 @@ fn consume_locators<'a>(
     Ok(unmatched)
 }
 
+/// The superset of the DNS names of a processed node, and the node.
+type SupersetNode = (HashSet<String>, Node);
+
 /// Processes `RawNodes` into Nodes.
-fn resolve_nodes(dns: &DNS, nodes: Vec<RawNode>) -> NetdoxResult<Vec<(HashSet<String>, Node)>> {
+/// Linkable nodes with the same link ID are merged into one node, whose name is chosen
+/// with the conflict policy. The other names become alternative names.
+/// Returns the nodes with their supersets, and the conflicts between names, ordered by link ID.
+fn resolve_nodes(
+    dns: &DNS,
+    mut nodes: Vec<RawNode>,
+    policy: &ConflictPolicy,
+) -> NetdoxResult<(Vec<SupersetNode>, Vec<Conflict>)> {
+    // Locators change the nodes that consume them, and names are chosen by their position
+    // when the policy can't rank them, so the order nodes are read in must not matter.
+    nodes.sort_by_cached_key(|node| (node.id(), node.plugin.clone()));
     let (linkable, locators): (Vec<_>, Vec<_>) =
         nodes.into_iter().partition(|n| n.link_id.is_some());
 
-    let mut resolved = HashMap::new();
+    let mut resolved: HashMap<String, SupersetNode> = HashMap::new();
+    let mut names: BTreeMap<String, Vec<MetadataProvenance>> = BTreeMap::new();
     for node in linkable {
-        resolved.insert(
-            node.link_id.clone().unwrap(),
-            (
-                if node.exclusive {
-                    HashSet::new()
-                } else {
-                    dns.node_superset(&node)?
-                },
-                Node {
-                    name: node.name.clone().expect("Linkable node without name."),
-                    alt_names: HashSet::new(),
-                    dns_names: node.dns_names.clone(),
-                    link_id: node.link_id.clone().unwrap(),
-                    plugins: HashSet::from([node.plugin.clone()]),
-                    raw_ids: HashSet::from([node.id()]),
-                },
-            ),
-        );
+        let link_id = node.link_id.clone().unwrap();
+        let name = MetadataProvenance {
+            value: node.name.clone().expect("Linkable node without name."),
+            plugin: Some(node.plugin.clone()),
+            changed: None,
+        };
+        let superset = if node.exclusive {
+            HashSet::new()
+        } else {
+            dns.node_superset(&node)?
+        };
+
+        match resolved.entry(link_id.clone()) {
+            Entry::Vacant(entry) => {
+                entry.insert((
+                    superset,
+                    Node {
+                        name: name.value.clone(),
+                        alt_names: HashSet::new(),
+                        dns_names: node.dns_names.clone(),
+                        link_id: link_id.clone(),
+                        plugins: HashSet::from([node.plugin.clone()]),
+                        raw_ids: HashSet::from([node.id()]),
+                    },
+                ));
+            }
+            Entry::Occupied(mut entry) => {
+                let (existing_superset, existing) = entry.get_mut();
+                existing_superset.extend(superset);
+                existing.dns_names.extend(node.dns_names.clone());
+                existing.plugins.insert(node.plugin.clone());
+                existing.raw_ids.insert(node.id());
+            }
+        }
+
+        let candidates = names.entry(link_id).or_default();
+        if !candidates.contains(&name) {
+            candidates.push(name);
+        }
+    }
+
+    let mut conflicts = vec![];
+    for (link_id, mut candidates) in names {
+        let chosen = candidates.remove(policy.choose(&candidates));
+        let node = &mut resolved.get_mut(&link_id).unwrap().1;
+        node.name = chosen.value.clone();
+        candidates.retain(|name| name.value != chosen.value);
+        node.alt_names
+            .extend(candidates.iter().map(|name| name.value.clone()));
+
+        if !candidates.is_empty() {
+            conflicts.push(Conflict {
+                link_id,
+                key: None,
+                chosen,
+                rejected: candidates,
+            });
+        }
     }
 
     // Match the locator against linkable nodes by DNS name set
@@ -232,5 +566,5 @@ fn resolve_nodes(dns: &DNS, nodes: Vec<RawNode>) -> NetdoxResult<Vec<(HashSet<St
         warn!("Failed to match all locators to a node.");
     }
 
-    Ok(resolved.into_values().collect_vec())
+    Ok((resolved.into_values().collect_vec(), conflicts))
 }
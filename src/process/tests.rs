@@ -1,8 +1,12 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use crate::{
-    data::{model::Node, store::DataConn, DataStore},
-    process::process,
+    data::{
+        model::{ConflictPolicy, DNSRecord, Node, ProcessRun, RawNode, DNS, DNS_KEY, NODES_KEY},
+        store::DataConn,
+        DataStore,
+    },
+    process::{ignore_names, process, resolve, unregistered_networks},
     tests_common::*,
 };
 
@@ -108,3 +112,223 @@ async fn test_superset() {
     let node = con.get_node(&mock.link_id).await.unwrap();
     assert_eq!(mock, node);
 }
+
+#[tokio::test]
+async fn test_process_memory() {
+    let mut con = setup_memory_con().await;
+    let mock = Node {
+        name: "memory-node".to_string(),
+        link_id: "memory-node-id".to_string(),
+        alt_names: HashSet::new(),
+        dns_names: HashSet::from([
+            "[default-net]memory.com".to_string(),
+            "[default-net]memory.net".to_string(),
+        ]),
+        plugins: HashSet::from([PLUGIN.to_string()]),
+        raw_ids: HashSet::from(["[default-net]memory.com".to_string()]),
+    };
+
+    // Writes the keys that netdox_create_dns and netdox_create_node would.
+    con.write(|con| {
+        con.sadd(DNS_KEY, "[default-net]memory.com")?;
+        con.sadd(DNS_KEY, "[default-net]memory.net")?;
+        con.sadd(
+            &format!("{DNS_KEY};[default-net]memory.net"),
+            &format!("{PLUGIN};CNAME;[default-net]memory.com"),
+        )?;
+
+        let node_key = format!("{NODES_KEY};[default-net]memory.com");
+        con.sadd(NODES_KEY, "[default-net]memory.com")?;
+        con.set(&node_key, "1")?;
+        for (field, value) in [
+            ("plugin", PLUGIN),
+            ("name", &mock.name),
+            ("exclusive", "false"),
+            ("link_id", &mock.link_id),
+        ] {
+            con.hset(&format!("{node_key};1"), field, value)?;
+        }
+        Ok(())
+    })
+    .unwrap();
+
+    process(DataStore::Memory(con.clone())).await.unwrap();
+
+    let node = con.get_node(&mock.link_id).await.unwrap();
+    assert_eq!(mock, node);
+}
+
+#[test]
+fn test_unregistered_networks() {
+    let qnames = HashSet::from([
+        "[default-net]domain.com".to_string(),
+        "[other-net]domain.com".to_string(),
+        "[typo-net]domain.com".to_string(),
+        "[typo-net]domain.net".to_string(),
+    ]);
+
+    assert_eq!(
+        unregistered_networks(&qnames, &HashSet::new()),
+        HashMap::new()
+    );
+    assert_eq!(
+        unregistered_networks(
+            &qnames,
+            &HashSet::from(["default-net".to_string(), "other-net".to_string()])
+        ),
+        HashMap::from([("typo-net".to_string(), 2)])
+    );
+}
+
+#[test]
+fn test_ignore_names() {
+    let mut dns = DNS::new();
+    dns.add_record(DNSRecord {
+        name: "[net]web.com".to_string(),
+        value: "[net]host.dyn.com".to_string(),
+        rtype: "CNAME".to_string(),
+        plugin: PLUGIN.to_string(),
+    });
+    dns.add_record(DNSRecord {
+        name: "[net]host.dyn.com".to_string(),
+        value: "[net]10.0.0.1".to_string(),
+        rtype: "A".to_string(),
+        plugin: PLUGIN.to_string(),
+    });
+    let raw_node = |names: &[&str]| RawNode {
+        name: None,
+        dns_names: names.iter().map(|name| name.to_string()).collect(),
+        link_id: None,
+        exclusive: false,
+        plugin: PLUGIN.to_string(),
+    };
+    let mut raw_nodes = vec![
+        raw_node(&["[net]web.com", "[net]host.dyn.com"]),
+        raw_node(&["[net]other.dyn.com"]),
+    ];
+
+    let ignored = ignore_names(
+        &mut dns,
+        &mut raw_nodes,
+        &HashSet::from(["*.dyn.com".to_string()]),
+    );
+
+    assert_eq!(ignored, 1);
+    assert_eq!(
+        dns.qnames,
+        HashSet::from(["[net]web.com".to_string(), "[net]10.0.0.1".to_string()])
+    );
+    assert!(dns.get_records("[net]web.com").is_empty());
+    assert!(dns.get_records("[net]host.dyn.com").is_empty());
+    assert!(dns.implied_records.values().flatten().next().is_none());
+    assert_eq!(raw_nodes, vec![raw_node(&["[net]web.com"])]);
+}
+
+#[test]
+fn test_resolve_deterministic() {
+    let name = "[default-net]resolve.com";
+    let mut dns = DNS::new();
+    dns.qnames.insert(name.to_string());
+
+    let raw_node = |link_id: Option<&str>, plugin: &str| RawNode {
+        name: link_id.map(|id| format!("{id} name")),
+        dns_names: HashSet::from([name.to_string()]),
+        link_id: link_id.map(str::to_string),
+        exclusive: false,
+        plugin: plugin.to_string(),
+    };
+    let mut raw_nodes = vec![
+        raw_node(Some("resolve-b"), "plugin-b"),
+        raw_node(Some("resolve-a"), "plugin-a"),
+        raw_node(None, "plugin-c"),
+    ];
+
+    // Nodes with claims of the same size tie, which the lowest link ID must win every time.
+    let expected = resolve(&dns, raw_nodes.clone(), &ConflictPolicy::default()).unwrap();
+    assert_eq!(
+        expected.resolved,
+        vec![(name.to_string(), "resolve-a".to_string())]
+    );
+    assert!(expected.nodes["resolve-a"].plugins.contains("plugin-c"));
+
+    let checksums = expected.checksums();
+    assert_eq!(checksums.len(), 3);
+    for _ in 0..20 {
+        raw_nodes.rotate_left(1);
+        assert_eq!(
+            resolve(&dns, raw_nodes.clone(), &ConflictPolicy::default())
+                .unwrap()
+                .checksums(),
+            checksums
+        );
+    }
+}
+
+#[test]
+fn test_resolve_conflicting_names() {
+    let mut dns = DNS::new();
+    dns.qnames.insert("[net]a.com".to_string());
+    dns.qnames.insert("[net]b.com".to_string());
+
+    let raw_node = |name: &str, qname: &str, plugin: &str| RawNode {
+        name: Some(name.to_string()),
+        dns_names: HashSet::from([qname.to_string()]),
+        link_id: Some("server-id".to_string()),
+        exclusive: false,
+        plugin: plugin.to_string(),
+    };
+    let raw_nodes = vec![
+        raw_node("server-b", "[net]b.com", "vcenter"),
+        raw_node("server-a", "[net]a.com", "cmdb"),
+    ];
+
+    // Linkable nodes with the same link ID are merged, and by default the last name wins.
+    let resolution = resolve(&dns, raw_nodes.clone(), &ConflictPolicy::default()).unwrap();
+    let node = &resolution.nodes["server-id"];
+    assert_eq!(node.name, "server-b");
+    assert_eq!(node.alt_names, HashSet::from(["server-a".to_string()]));
+    assert_eq!(node.raw_ids.len(), 2);
+    assert_eq!(node.plugins.len(), 2);
+    assert_eq!(resolution.resolved.len(), 2);
+    assert_eq!(resolution.conflicts.len(), 1);
+    assert_eq!(resolution.conflicts[0].field(), "name");
+
+    let policy = ConflictPolicy {
+        priority: vec!["cmdb".to_string()],
+        ..Default::default()
+    };
+    let resolution = resolve(&dns, raw_nodes, &policy).unwrap();
+    assert_eq!(resolution.nodes["server-id"].name, "server-a");
+    assert_eq!(
+        resolution.conflicts[0].rejected[0].plugin.as_deref(),
+        Some("vcenter")
+    );
+}
+
+#[tokio::test]
+async fn test_interrupted_process() {
+    let _lock = PROC_NODES_LOCK.write().await;
+    interrupted_process(DataStore::Redis(setup_db_con().await)).await;
+}
+
+#[tokio::test]
+async fn test_interrupted_process_memory() {
+    interrupted_process(DataStore::Memory(setup_memory_con().await)).await;
+}
+
+async fn interrupted_process(mut con: DataStore) {
+    let stale_id = "interrupted-process-id";
+
+    con.begin_process(&ProcessRun {
+        started: 0,
+        nodes: HashSet::from([stale_id.to_string()]),
+        dns_names: HashSet::new(),
+    })
+    .await
+    .unwrap();
+
+    process(con.clone()).await.unwrap();
+
+    assert!(con.get_interrupted_process().await.unwrap().is_none());
+    assert!(!con.get_node_ids().await.unwrap().contains(stale_id));
+}
@@ -0,0 +1,159 @@
+use std::{
+    collections::VecDeque,
+    io::{stderr, IsTerminal, Write},
+    time::{Duration, Instant},
+};
+
+use tracing::info;
+
+use crate::{logging::quiet_enabled, runs::format_millis};
+
+/// Completions within this long ago are used to calculate the rate.
+const RATE_WINDOW: Duration = Duration::from_secs(30);
+/// Minimum time between progress lines when not drawing a bar.
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+/// Width of the progress bar in characters.
+const BAR_WIDTH: usize = 30;
+
+/// Reports progress through a number of tasks, with the rate and estimated time remaining.
+/// Draws a bar when stderr is a terminal, or logs a line periodically otherwise or if quiet.
+pub struct Progress {
+    label: String,
+    total: usize,
+    done: usize,
+    start: Instant,
+    /// Times of completions within the rate window.
+    recent: VecDeque<Instant>,
+    last_log: Instant,
+    bar: bool,
+}
+
+impl Progress {
+    /// Starts reporting progress through some tasks.
+    pub fn new(label: &str, total: usize) -> Self {
+        let now = Instant::now();
+        Progress {
+            label: label.to_string(),
+            total,
+            done: 0,
+            start: now,
+            recent: VecDeque::new(),
+            last_log: now,
+            bar: !quiet_enabled() && stderr().is_terminal(),
+        }
+    }
+
+    /// Records that a task completed.
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        self.record(now);
+
+        if self.bar {
+            let _ = write!(stderr(), "\r{}", self.bar_line(now));
+            let _ = stderr().flush();
+        } else if now.duration_since(self.last_log) >= LOG_INTERVAL {
+            info!("{}", self.status(now));
+            self.last_log = now;
+        }
+    }
+
+    /// Stops reporting progress.
+    pub fn finish(self) {
+        if self.bar && self.done > 0 {
+            let _ = writeln!(stderr());
+        }
+    }
+
+    fn record(&mut self, now: Instant) {
+        self.done += 1;
+        self.recent.push_back(now);
+        while let Some(oldest) = self.recent.front() {
+            if now.duration_since(*oldest) > RATE_WINDOW {
+                self.recent.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Tasks completed per second over the rate window.
+    fn rate(&self, now: Instant) -> f64 {
+        let window = now.duration_since(self.start).min(RATE_WINDOW);
+        if window.is_zero() {
+            0.0
+        } else {
+            self.recent.len() as f64 / window.as_secs_f64()
+        }
+    }
+
+    /// Estimated time until every task is complete, if there is a rate to estimate from.
+    fn eta(&self, now: Instant) -> Option<Duration> {
+        let rate = self.rate(now);
+        if rate > 0.0 {
+            let remaining = self.total.saturating_sub(self.done) as f64;
+            Some(Duration::from_secs_f64(remaining / rate))
+        } else {
+            None
+        }
+    }
+
+    fn status(&self, now: Instant) -> String {
+        let eta = match self.eta(now) {
+            Some(eta) => format_millis(u64::try_from(eta.as_millis()).unwrap_or(u64::MAX)),
+            None => "unknown".to_string(),
+        };
+        format!(
+            "{} {}/{} ({:.1}/s, ETA {eta})",
+            self.label,
+            self.done,
+            self.total,
+            self.rate(now)
+        )
+    }
+
+    fn bar_line(&self, now: Instant) -> String {
+        let filled = (self.done * BAR_WIDTH)
+            .checked_div(self.total)
+            .unwrap_or(BAR_WIDTH)
+            .min(BAR_WIDTH);
+        format!(
+            "[{}{}] {}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            self.status(now)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        time::{Duration, Instant},
+    };
+
+    use super::Progress;
+
+    #[test]
+    fn test_progress_status() {
+        let start = Instant::now();
+        let mut progress = Progress {
+            label: "Applied".to_string(),
+            total: 40,
+            done: 0,
+            start,
+            recent: VecDeque::new(),
+            last_log: start,
+            bar: false,
+        };
+
+        for secs in 1..=10 {
+            progress.record(start + Duration::from_secs(secs));
+        }
+        let now = start + Duration::from_secs(10);
+
+        assert_eq!(progress.eta(now), Some(Duration::from_secs(30)));
+        assert_eq!(progress.status(now), "Applied 10/40 (1.0/s, ETA 30.0s)");
+        assert!(progress.bar_line(now).starts_with("[#######-----"));
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    collections::HashMap,
+    process::exit,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{error, info, warn};
+
+use crate::{
+    config::LocalConfig,
+    data::{
+        model::{Prune, RunKind, RunRecord, MAX_RUNS},
+        DataConn, DataStore,
+    },
+    error::NetdoxResult,
+    success,
+};
+
+/// Removes DNS records and nodes that have not been reported within some time.
+#[tokio::main]
+pub async fn prune(older_than: Duration, dry_run: bool) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to prune data: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to prune data: {err}");
+            exit(1);
+        }
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let cutoff = now.saturating_sub(older_than).as_secs();
+
+    let prune = match con.prune(Some(cutoff), &HashMap::new(), dry_run).await {
+        Ok(prune) => prune,
+        Err(err) => {
+            error!("Failed to prune data: {err}");
+            exit(1);
+        }
+    };
+
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    for record in &prune.records {
+        info!(
+            "{verb} {} record {} -> {} from {}",
+            record.rtype, record.name, record.value, record.plugin
+        );
+    }
+    for (node_id, plugin) in &prune.nodes {
+        info!("{verb} node {node_id} from {plugin}");
+    }
+    for qname in &prune.dns_names {
+        info!("{verb} DNS name {qname}");
+    }
+    for link_id in &prune.proc_nodes {
+        info!("{verb} processed node {link_id}");
+    }
+
+    // Plugins that skip their stages would not report the removed data again.
+    if !dry_run && !prune.is_empty() {
+        if let Err(err) = con.clear_plugin_cursors().await {
+            warn!("Failed to clear plugin cursors, so plugins may skip stages in the next update: {err}");
+        }
+    }
+
+    success!("{verb} {}.", summary(&prune));
+}
+
+/// Removes DNS records and nodes that their plugins have not reported
+/// in the given number of updates where they succeeded.
+pub async fn prune_stale(con: &mut DataStore, updates: usize) -> NetdoxResult<Prune> {
+    let runs = con.get_runs(MAX_RUNS).await?;
+    let cutoffs = plugin_cutoffs(&runs, updates);
+    if cutoffs.is_empty() {
+        return Ok(Prune::default());
+    }
+
+    con.prune(None, &cutoffs, false).await
+}
+
+/// Describes the number of each kind of object in a prune.
+pub fn summary(prune: &Prune) -> String {
+    format!(
+        "{} DNS records, {} raw nodes, {} DNS names and {} processed nodes",
+        prune.records.len(),
+        prune.nodes.len(),
+        prune.dns_names.len(),
+        prune.proc_nodes.len()
+    )
+}
+
+/// Returns the time that the update a given number of successful updates ago started, for each plugin.
+/// Runs must be newest first. Plugins that have succeeded in fewer updates have no cutoff.
+/// Updates where a plugin skipped a stage do not count, since some of its data was not refreshed.
+fn plugin_cutoffs(runs: &[RunRecord], updates: usize) -> HashMap<String, u64> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut cutoffs = HashMap::new();
+    for run in runs.iter().filter(|run| run.kind == RunKind::Update) {
+        // Plugin timings are named stage/plugin, and a plugin may run in more than one stage.
+        let mut plugins: HashMap<&str, bool> = HashMap::new();
+        for timing in &run.timings {
+            if let Some((_, plugin)) = timing.name.split_once('/') {
+                *plugins.entry(plugin).or_insert(true) &= timing.success && !timing.skipped;
+            }
+        }
+
+        for (plugin, success) in plugins {
+            if !success {
+                continue;
+            }
+            let count = counts.entry(plugin).or_default();
+            *count += 1;
+            if *count == updates {
+                cutoffs.insert(plugin.to_string(), run.started);
+            }
+        }
+    }
+
+    cutoffs
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::plugin_cutoffs;
+    use crate::data::model::{RunKind, RunRecord, RunTiming};
+
+    fn run(kind: RunKind, started: u64, timings: &[(&str, bool)]) -> RunRecord {
+        RunRecord {
+            kind,
+            started,
+            millis: 0,
+            success: timings.iter().all(|(_, success)| *success),
+            timings: timings
+                .iter()
+                .map(|(name, success)| RunTiming {
+                    name: name.to_string(),
+                    millis: 0,
+                    success: *success,
+                    skipped: false,
+                })
+                .collect(),
+            stages: vec![],
+        }
+    }
+
+    #[test]
+    fn test_plugin_cutoffs() {
+        let runs = vec![
+            run(
+                RunKind::Update,
+                400,
+                &[("write-only/dns", true), ("write-only/nodes", false)],
+            ),
+            run(RunKind::Publish, 350, &[("upload", true)]),
+            run(
+                RunKind::Update,
+                300,
+                &[
+                    ("write-only/dns", true),
+                    ("write-only/nodes", true),
+                    ("read-write/nodes", true),
+                ],
+            ),
+            run(
+                RunKind::Update,
+                200,
+                &[("write-only/dns", true), ("process", true)],
+            ),
+            run(RunKind::Update, 100, &[("write-only/nodes", true)]),
+        ];
+
+        assert_eq!(
+            plugin_cutoffs(&runs, 2),
+            HashMap::from([("dns".to_string(), 300), ("nodes".to_string(), 100)])
+        );
+        assert_eq!(
+            plugin_cutoffs(&runs, 3),
+            HashMap::from([("dns".to_string(), 200)])
+        );
+        assert!(plugin_cutoffs(&runs, 4).is_empty());
+    }
+
+    #[test]
+    fn test_plugin_cutoffs_skipped() {
+        let mut runs = vec![
+            run(RunKind::Update, 300, &[("write-only/dns", true)]),
+            run(RunKind::Update, 200, &[("write-only/dns", true)]),
+            run(RunKind::Update, 100, &[("write-only/dns", true)]),
+        ];
+        runs[0].timings[0].skipped = true;
+
+        assert_eq!(
+            plugin_cutoffs(&runs, 2),
+            HashMap::from([("dns".to_string(), 100)])
+        );
+    }
+}
@@ -1,14 +1,58 @@
-use std::process::exit;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    process::exit,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use paris::error;
+use ipnet::Ipv4Net;
+use itertools::Itertools;
+use links::{LinkTargets, Reference};
+use tracing::{error, warn};
 
-use crate::{config::LocalConfig, data::DataConn, QueryCommand};
+pub mod links;
+
+use crate::{
+    annotate::resolve_object_id,
+    config::LocalConfig,
+    data::{
+        model::{
+            split_qname, Change, ChangelogEntry, Data, Dependency, MetadataProvenance, Node,
+            LOCATIONS_META_KEY, ROLE_META_KEY,
+        },
+        DataConn, DataStore,
+    },
+    error::NetdoxResult,
+    remote::{ObjectSelection, RemoteInterface},
+    runs::{format_millis, format_timestamp},
+    QueryCommand,
+};
 
 /// Performs the given query command.
 #[tokio::main]
 pub async fn query(cmd: &QueryCommand) {
     match cmd {
         QueryCommand::Counts => counts().await,
+        QueryCommand::Networks => networks().await,
+        QueryCommand::Nodes { role } => nodes(role.as_deref()).await,
+        QueryCommand::Node { id } => node(id).await,
+        QueryCommand::Runs { count, timings } => runs(*count, *timings).await,
+        QueryCommand::Changes {
+            since,
+            plugin,
+            change_type,
+            stats,
+        } => {
+            changes(
+                since.as_ref(),
+                plugin.as_deref(),
+                change_type.as_deref(),
+                *stats,
+            )
+            .await
+        }
+        QueryCommand::Links { object_id } => links(object_id).await,
+        QueryCommand::Locations => locations().await,
+        QueryCommand::Tag { tag } => tags(tag.as_deref()).await,
     }
 }
 
@@ -53,3 +97,1082 @@ async fn counts() {
         }
     }
 }
+
+async fn networks() {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to print networks: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to print networks: {err}");
+            exit(1);
+        }
+    };
+
+    let registered = match con.get_networks().await {
+        Ok(networks) => networks,
+        Err(err) => {
+            error!("Failed to get registered networks: {err}");
+            exit(1);
+        }
+    };
+
+    // Maps network name to number of DNS names and raw nodes.
+    let mut counts: BTreeMap<&str, (usize, usize)> = registered
+        .iter()
+        .map(|net| (net.as_str(), (0, 0)))
+        .collect();
+
+    let names = match con.get_dns_names().await {
+        Ok(names) => names,
+        Err(err) => {
+            error!("Failed to get DNS names for network counts: {err}");
+            exit(1);
+        }
+    };
+
+    for name in &names {
+        if let Some((network, _)) = split_qname(name) {
+            counts.entry(network).or_default().0 += 1;
+        }
+    }
+
+    let raw_nodes = match con.get_raw_nodes().await {
+        Ok(raw_nodes) => raw_nodes,
+        Err(err) => {
+            error!("Failed to get raw nodes for network counts: {err}");
+            exit(1);
+        }
+    };
+
+    for node in &raw_nodes {
+        for network in node
+            .dns_names
+            .iter()
+            .filter_map(|name| split_qname(name).map(|(network, _)| network))
+            .unique()
+        {
+            counts.entry(network).or_default().1 += 1;
+        }
+    }
+
+    for (network, (dns_count, node_count)) in counts {
+        let status = if registered.is_empty() || registered.contains(network) {
+            ""
+        } else {
+            " (unregistered)"
+        };
+        println!("[{network}]{status}: {dns_count} DNS names, {node_count} raw nodes");
+    }
+}
+
+async fn nodes(role: Option<&str>) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to print nodes: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to print nodes: {err}");
+            exit(1);
+        }
+    };
+
+    let roles = match con.get_roles().await {
+        Ok(roles) => roles,
+        Err(err) => {
+            error!("Failed to get configured roles: {err}");
+            exit(1);
+        }
+    };
+
+    let ids = match con.get_node_ids().await {
+        Ok(ids) => ids,
+        Err(err) => {
+            error!("Failed to get node IDs: {err}");
+            exit(1);
+        }
+    };
+
+    for id in ids.iter().sorted() {
+        let node = match con.get_node(id).await {
+            Ok(node) => node,
+            Err(err) => {
+                error!("Failed to get node {id}: {err}");
+                exit(1);
+            }
+        };
+
+        let node_role = match con.get_node_metadata(&node).await {
+            Ok(mut metadata) => metadata.remove(ROLE_META_KEY),
+            Err(err) => {
+                error!("Failed to get metadata for node {}: {err}", node.link_id);
+                exit(1);
+            }
+        };
+
+        if role.is_some() && node_role.as_deref() != role {
+            continue;
+        }
+
+        let status = match &node_role {
+            Some(node_role) if !roles.contains(node_role) => " (unknown role)",
+            _ => "",
+        };
+        println!(
+            "{}: {} [{}]{status}",
+            node.link_id,
+            node.name,
+            node_role.as_deref().unwrap_or("no role")
+        );
+    }
+}
+
+async fn node(id: &str) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to print node: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to print node: {err}");
+            exit(1);
+        }
+    };
+
+    let link_id = match resolve_node_id(&mut con, id).await {
+        Ok(Some(link_id)) => link_id,
+        Ok(None) => {
+            error!("No processed node with link ID or raw node ID: {id}");
+            exit(1);
+        }
+        Err(err) => {
+            error!("Failed to resolve node ID {id}: {err}");
+            exit(1);
+        }
+    };
+
+    let node = match con.get_node(&link_id).await {
+        Ok(node) => node,
+        Err(err) => {
+            error!("Failed to get node {link_id}: {err}");
+            exit(1);
+        }
+    };
+
+    let metadata = match con.get_node_metadata_provenance(&node).await {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            error!("Failed to get metadata for node {link_id}: {err}");
+            exit(1);
+        }
+    };
+
+    let pdata = match con.get_node_pdata(&node).await {
+        Ok(pdata) => pdata,
+        Err(err) => {
+            error!("Failed to get plugin data for node {link_id}: {err}");
+            exit(1);
+        }
+    };
+
+    let dependencies = match con.get_node_dependencies(&link_id).await {
+        Ok(dependencies) => dependencies,
+        Err(err) => {
+            error!("Failed to get dependencies for node {link_id}: {err}");
+            exit(1);
+        }
+    };
+
+    print!("{}", node_summary(&node, &metadata, &pdata, &dependencies));
+}
+
+/// Returns the link ID of the processed node with the given link ID,
+/// or that consumed the raw node with the given ID.
+async fn resolve_node_id(con: &mut DataStore, id: &str) -> NetdoxResult<Option<String>> {
+    if con.get_node_ids().await?.contains(id) {
+        return Ok(Some(id.to_string()));
+    }
+
+    // Raw node IDs are DNS names separated by semicolons.
+    let raw_id = con
+        .get_raw_id_from_qnames(&id.split(';').collect::<Vec<_>>())
+        .await?;
+    con.get_node_from_raw(&raw_id).await
+}
+
+/// Describes where a metadata value came from, like ` (from plugin at 2024-01-01 00:00:00)`.
+fn describe_provenance(meta: &MetadataProvenance) -> String {
+    match (&meta.plugin, meta.changed) {
+        (Some(plugin), Some(changed)) => {
+            format!(" (from {plugin} at {})", format_timestamp(changed))
+        }
+        (Some(plugin), None) => format!(" (from {plugin})"),
+        (None, Some(changed)) => format!(" (at {})", format_timestamp(changed)),
+        (None, None) => String::new(),
+    }
+}
+
+/// Describes the composition of a processed node.
+/// Each metadata value is followed by the plugin that last changed it and when, if they are known.
+fn node_summary(
+    node: &Node,
+    metadata: &HashMap<String, MetadataProvenance>,
+    pdata: &[Data],
+    dependencies: &[Dependency],
+) -> String {
+    let sorted = |set: &HashSet<String>| set.iter().sorted().cloned().collect::<Vec<_>>();
+    let mut summary = format!("Name: {}\nLink ID: {}\n", node.name, node.link_id);
+
+    for (title, values) in [
+        ("Alt names", sorted(&node.alt_names)),
+        ("DNS names", sorted(&node.dns_names)),
+        ("Plugins", sorted(&node.plugins)),
+        ("Raw node IDs", sorted(&node.raw_ids)),
+        (
+            "Metadata",
+            metadata
+                .iter()
+                .sorted_by_key(|(key, _)| *key)
+                .map(|(key, meta)| format!("{key}: {}{}", meta.value, describe_provenance(meta)))
+                .collect(),
+        ),
+        (
+            "Plugin data",
+            pdata
+                .iter()
+                .sorted_by(|lhs, rhs| lhs.id().cmp(rhs.id()))
+                .map(describe_data)
+                .collect(),
+        ),
+        (
+            "Dependencies",
+            dependencies
+                .iter()
+                .map(|dep| describe_dependency(&node.link_id, dep))
+                .collect(),
+        ),
+    ] {
+        summary.push_str(&format!("{title}:\n"));
+        if values.is_empty() {
+            summary.push_str("    (none)\n");
+        }
+        for value in values {
+            summary.push_str(&format!("    {value}\n"));
+        }
+    }
+
+    summary
+}
+
+/// Describes a dependency from the perspective of the node with the given link ID.
+fn describe_dependency(link_id: &str, dep: &Dependency) -> String {
+    let relation = if dep.source == link_id {
+        "depends on"
+    } else {
+        "required by"
+    };
+    match &dep.label {
+        Some(label) => format!("{relation} {} ({label})", dep.other(link_id)),
+        None => format!("{relation} {}", dep.other(link_id)),
+    }
+}
+
+/// Describes a piece of plugin data without its content.
+fn describe_data(data: &Data) -> String {
+    let (kind, title) = match data {
+        Data::Hash { title, .. } => ("hash", title),
+        Data::List { title, .. } => ("list", title),
+        Data::String { title, .. } => ("string", title),
+        Data::Table { title, .. } => ("table", title),
+    };
+    format!("{} ({kind} from {}): {title}", data.id(), data.plugin())
+}
+
+async fn locations() {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to print locations: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to print locations: {err}");
+            exit(1);
+        }
+    };
+
+    // Locations can still be counted from the data store if the remote is unavailable.
+    let subnets = match cfg.remote().config().await {
+        Ok(remote_cfg) => remote_cfg.locations,
+        Err(err) => {
+            warn!("Failed to get remote config, so subnets will not be listed: {err}");
+            HashMap::new()
+        }
+    };
+
+    let dns_names = match con.get_dns_names().await {
+        Ok(names) => names,
+        Err(err) => {
+            error!("Failed to get DNS names: {err}");
+            exit(1);
+        }
+    };
+
+    let mut dns_locations = vec![];
+    for qname in dns_names.iter().sorted() {
+        match con.get_dns_metadata(qname).await {
+            Ok(mut metadata) => dns_locations.push(metadata.remove(LOCATIONS_META_KEY)),
+            Err(err) => {
+                error!("Failed to get metadata for DNS name {qname}: {err}");
+                exit(1);
+            }
+        }
+    }
+
+    let node_ids = match con.get_node_ids().await {
+        Ok(ids) => ids,
+        Err(err) => {
+            error!("Failed to get node IDs: {err}");
+            exit(1);
+        }
+    };
+
+    let mut node_locations = vec![];
+    for id in node_ids.iter().sorted() {
+        let node = match con.get_node(id).await {
+            Ok(node) => node,
+            Err(err) => {
+                error!("Failed to get node {id}: {err}");
+                exit(1);
+            }
+        };
+
+        match con.get_node_metadata(&node).await {
+            Ok(mut metadata) => node_locations.push(metadata.remove(LOCATIONS_META_KEY)),
+            Err(err) => {
+                error!("Failed to get metadata for node {id}: {err}");
+                exit(1);
+            }
+        }
+    }
+
+    let (counts, unassigned) = location_counts(&subnets, &dns_locations, &node_locations);
+    for (location, count) in &counts {
+        let subnets = if count.subnets.is_empty() {
+            "no subnets".to_string()
+        } else {
+            count.subnets.iter().join(", ")
+        };
+        println!(
+            "{location}: {} DNS names, {} nodes ({subnets})",
+            count.dns_names, count.nodes
+        );
+    }
+    println!(
+        "Unassigned: {} DNS names, {} nodes",
+        unassigned.dns_names, unassigned.nodes
+    );
+
+    let unused = counts
+        .values()
+        .filter(|count| count.dns_names == 0 && count.nodes == 0)
+        .count();
+    if unused > 0 {
+        warn!("{unused} locations in the remote config have no DNS names or nodes.");
+    }
+}
+
+/// Number of objects assigned to a location.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct LocationCount {
+    dns_names: usize,
+    nodes: usize,
+    /// Subnets mapped to the location in the remote config.
+    subnets: Vec<Ipv4Net>,
+}
+
+/// Counts the DNS names and nodes with each location,
+/// including locations in the remote config that have none.
+/// Also returns the counts of objects with no location.
+fn location_counts(
+    subnets: &HashMap<Ipv4Net, String>,
+    dns_locations: &[Option<String>],
+    node_locations: &[Option<String>],
+) -> (BTreeMap<String, LocationCount>, LocationCount) {
+    let mut counts = BTreeMap::<String, LocationCount>::new();
+    for (subnet, location) in subnets.iter().sorted() {
+        counts
+            .entry(location.clone())
+            .or_default()
+            .subnets
+            .push(*subnet);
+    }
+
+    let mut unassigned = LocationCount::default();
+    for location in dns_locations {
+        match location {
+            Some(location) => counts.entry(location.clone()).or_default().dns_names += 1,
+            None => unassigned.dns_names += 1,
+        }
+    }
+    for location in node_locations {
+        match location {
+            Some(location) => counts.entry(location.clone()).or_default().nodes += 1,
+            None => unassigned.nodes += 1,
+        }
+    }
+
+    (counts, unassigned)
+}
+
+async fn tags(tag: Option<&str>) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to print tags: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to print tags: {err}");
+            exit(1);
+        }
+    };
+
+    let tag = match tag {
+        Some(tag) => tag,
+        None => {
+            let tags = match con.get_tags().await {
+                Ok(tags) => tags,
+                Err(err) => {
+                    error!("Failed to get tags: {err}");
+                    exit(1);
+                }
+            };
+            for tag in tags.iter().sorted() {
+                match con.get_tagged(tag).await {
+                    Ok(ids) => println!("{tag}: {} objects", ids.len()),
+                    Err(err) => {
+                        error!("Failed to get objects tagged {tag}: {err}");
+                        exit(1);
+                    }
+                }
+            }
+            return;
+        }
+    };
+
+    let tagged = match ObjectSelection::tagged(&mut con, tag).await {
+        Ok(tagged) => tagged,
+        Err(err) => {
+            error!("Failed to get objects tagged {tag}: {err}");
+            exit(1);
+        }
+    };
+
+    println!("DNS names ({}):", tagged.dns.len());
+    for qname in &tagged.dns {
+        println!("  {qname}");
+    }
+
+    println!("Nodes ({}):", tagged.nodes.len());
+    for link_id in &tagged.nodes {
+        match con.get_node(link_id).await {
+            Ok(node) => println!("  {link_id}: {}", node.name),
+            Err(err) => {
+                error!("Failed to get node {link_id}: {err}");
+                exit(1);
+            }
+        }
+    }
+}
+
+async fn runs(count: usize, timings: bool) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to print runs: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to print runs: {err}");
+            exit(1);
+        }
+    };
+
+    let runs = match con.get_runs(count).await {
+        Ok(runs) => runs,
+        Err(err) => {
+            error!("Failed to get recorded runs: {err}");
+            exit(1);
+        }
+    };
+
+    println!(
+        "{:<19}  {:<7}  {:>9}  OUTCOME",
+        "STARTED (UTC)", "KIND", "DURATION"
+    );
+    for run in runs {
+        println!(
+            "{:<19}  {:<7}  {:>9}  {}",
+            format_timestamp(run.started),
+            run.kind.to_string(),
+            format_millis(run.millis),
+            if run.success { "success" } else { "failed" }
+        );
+
+        if timings {
+            for timing in run.timings {
+                let status = if !timing.success {
+                    " (failed)"
+                } else if timing.skipped {
+                    " (skipped)"
+                } else {
+                    ""
+                };
+                println!(
+                    "    {:>9}  {}{status}",
+                    format_millis(timing.millis),
+                    timing.name
+                );
+            }
+            for snapshot in run.stages {
+                println!(
+                    "    {} stage changes: {} to {}",
+                    snapshot.stage,
+                    snapshot.start_change.as_deref().unwrap_or("start"),
+                    snapshot.end_change.as_deref().unwrap_or("unknown")
+                );
+            }
+        }
+    }
+}
+
+async fn changes(
+    since: Option<&ChangesSince>,
+    plugin: Option<&str>,
+    change_type: Option<&str>,
+    stats: bool,
+) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to print changes: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to print changes: {err}");
+            exit(1);
+        }
+    };
+
+    // Stream IDs start with a timestamp in milliseconds, and the start of the range is exclusive.
+    let start = since.map(|since| match since {
+        ChangesSince::Window(window) => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let start_millis = now.saturating_sub(*window).as_millis();
+            format!("{}-{}", start_millis.saturating_sub(1), u64::MAX)
+        }
+        ChangesSince::Id(id) => id.to_string(),
+    });
+
+    let entries = match con.get_changes(start.as_deref()).await {
+        Ok(entries) => entries
+            .into_iter()
+            .filter(|entry| change_matches(&entry.change, plugin, change_type))
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            error!("Failed to get changes: {err}");
+            exit(1);
+        }
+    };
+
+    if stats {
+        println!(
+            "{:<24}  {:<24}  {:>7}  {:<19}  {:<19}",
+            "PLUGIN", "CHANGE", "COUNT", "FIRST (UTC)", "LAST (UTC)"
+        );
+        for ((plugin, change), stat) in change_stats(&entries) {
+            println!(
+                "{plugin:<24}  {change:<24}  {:>7}  {:<19}  {:<19}",
+                stat.count,
+                format_timestamp(stat.first / 1000),
+                format_timestamp(stat.last / 1000)
+            );
+        }
+    } else {
+        for entry in &entries {
+            let millis = entry_millis(&entry.id).unwrap_or_default();
+            println!(
+                "{:<15}  {}  [{}] {}",
+                entry.id,
+                format_timestamp(millis / 1000),
+                entry.change.plugin(),
+                describe_change(&entry.change)
+            );
+        }
+    }
+}
+
+async fn links(object_id: &str) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to print links: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to print links: {err}");
+            exit(1);
+        }
+    };
+
+    let obj_id = match resolve_object_id(&mut con, object_id).await {
+        Ok(obj_id) => obj_id,
+        Err(err) => {
+            error!("{err}");
+            exit(1);
+        }
+    };
+
+    let targets = match LinkTargets::read(&mut con).await {
+        Ok(targets) => targets,
+        Err(err) => {
+            error!("Failed to get objects in the data store: {err}");
+            exit(1);
+        }
+    };
+
+    let mut outgoing = vec![];
+    let mut incoming = vec![];
+    // Incoming references can come from any object, so every object is checked.
+    for source in targets.object_ids() {
+        let references = match targets.references(&mut con, &source).await {
+            Ok(references) => references,
+            Err(err) => {
+                error!("Failed to get references from {source}: {err}");
+                exit(1);
+            }
+        };
+
+        for reference in references {
+            if reference.source == obj_id {
+                outgoing.push(reference);
+            } else if reference.target == obj_id {
+                incoming.push(reference);
+            }
+        }
+    }
+
+    outgoing.sort();
+    incoming.sort();
+
+    let missing = |reference: &Reference| if reference.exists { "" } else { " (missing)" };
+    println!("Outgoing:");
+    for reference in &outgoing {
+        println!(
+            "  -> {} [{}]{}",
+            reference.target,
+            reference.kind,
+            missing(reference)
+        );
+    }
+    println!("Incoming:");
+    for reference in &incoming {
+        println!("  <- {} [{}]", reference.source, reference.kind);
+    }
+
+    let broken = outgoing
+        .iter()
+        .filter(|reference| !reference.exists)
+        .count();
+    if broken > 0 {
+        warn!("{broken} outgoing references point to objects that do not exist.");
+    }
+}
+
+/// Parses a time window like 90s, 30m, 12h, 7d or 2w.
+pub fn parse_window(window: &str) -> Result<Duration, String> {
+    let split = window.len()
+        - window
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .len();
+    let (amount, unit) = window.split_at(split);
+    let amount = match amount.parse::<u64>() {
+        Ok(amount) => amount,
+        Err(_) => return Err(format!("Time window must start with a number: {window}")),
+    };
+
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "Time window must end with s, m, h, d or w: {window}"
+            ))
+        }
+    };
+
+    match amount.checked_mul(unit_secs) {
+        Some(secs) => Ok(Duration::from_secs(secs)),
+        None => Err(format!("Time window is too long: {window}")),
+    }
+}
+
+/// Where to start printing changes from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChangesSince {
+    /// Changes made within this long ago.
+    Window(Duration),
+    /// Changes after the one with this stream ID.
+    Id(String),
+}
+
+/// Parses a time window like 30m, or a changelog stream ID like 1700000000000-0.
+pub fn parse_since(since: &str) -> Result<ChangesSince, String> {
+    let is_stream_id = match since.split_once('-') {
+        Some((millis, seq)) => is_digits(millis) && is_digits(seq),
+        None => is_digits(since),
+    };
+
+    if is_stream_id {
+        Ok(ChangesSince::Id(since.to_string()))
+    } else {
+        parse_window(since).map(ChangesSince::Window)
+    }
+}
+
+fn is_digits(string: &str) -> bool {
+    !string.is_empty() && string.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Returns true if the change was made by the plugin and is of the type, where they are given.
+/// Types match regardless of case, and may use hyphens or underscores instead of spaces.
+fn change_matches(change: &Change, plugin: Option<&str>, change_type: Option<&str>) -> bool {
+    if plugin.is_some_and(|plugin| change.plugin() != plugin) {
+        return false;
+    }
+
+    match change_type {
+        Some(change_type) => change_type
+            .replace(['-', '_'], " ")
+            .eq_ignore_ascii_case(&String::from(change)),
+        None => true,
+    }
+}
+
+/// Number of changes of one type from one plugin, and when the first and last were made.
+#[derive(Debug, PartialEq, Eq)]
+struct ChangeStat {
+    count: usize,
+    /// Timestamp of the first change in milliseconds.
+    first: u64,
+    /// Timestamp of the last change in milliseconds.
+    last: u64,
+}
+
+/// Aggregates changes by plugin and change type.
+fn change_stats(entries: &[ChangelogEntry]) -> BTreeMap<(String, String), ChangeStat> {
+    let mut stats: BTreeMap<(String, String), ChangeStat> = BTreeMap::new();
+    for entry in entries {
+        let millis = entry_millis(&entry.id).unwrap_or_default();
+        let key = (
+            entry.change.plugin().to_string(),
+            String::from(&entry.change),
+        );
+
+        stats
+            .entry(key)
+            .and_modify(|stat| {
+                stat.count += 1;
+                stat.first = stat.first.min(millis);
+                stat.last = stat.last.max(millis);
+            })
+            .or_insert(ChangeStat {
+                count: 1,
+                first: millis,
+                last: millis,
+            });
+    }
+
+    stats
+}
+
+/// Returns the timestamp in milliseconds from a changelog entry ID.
+fn entry_millis(id: &str) -> Option<u64> {
+    id.split('-').next()?.parse().ok()
+}
+
+/// Describes a change and the object it changed.
+fn describe_change(change: &Change) -> String {
+    let object = match change {
+        Change::Init => None,
+        Change::CreateDnsName { qname, .. } => Some(qname.to_string()),
+        Change::CreateDnsRecord { record, .. } => {
+            Some(format!("{} {} {}", record.name, record.rtype, record.value))
+        }
+        Change::DeleteDnsName { qname, .. } => Some(qname.to_string()),
+        Change::DeleteDnsRecord { record, .. } => {
+            Some(format!("{} {} {}", record.name, record.rtype, record.value))
+        }
+        Change::CreatePluginNode { node_id, .. } | Change::DeletePluginNode { node_id, .. } => {
+            Some(node_id.to_string())
+        }
+        Change::DeleteProcNode { link_id, .. } => Some(link_id.to_string()),
+        Change::CreateReport { report_id, .. } => Some(report_id.to_string()),
+        Change::CreatedData {
+            obj_id, data_id, ..
+        }
+        | Change::UpdatedData {
+            obj_id, data_id, ..
+        }
+        | Change::DeletedData {
+            obj_id, data_id, ..
+        } => Some(format!("{data_id} on {obj_id}")),
+        Change::UpdatedMetadata { obj_id, .. }
+        | Change::UpdatedNotes { obj_id, .. }
+        | Change::UpdatedTags { obj_id, .. } => Some(obj_id.to_string()),
+        Change::UpdatedNetworkMapping { source, dest, .. }
+        | Change::UpdatedDependency { source, dest, .. } => Some(format!("{source} -> {dest}")),
+        Change::UpdatedRelation {
+            source,
+            dest,
+            relation,
+            ..
+        } => Some(format!("{source} {relation} {dest}")),
+        Change::UpdatedService {
+            link_id, service, ..
+        } => Some(format!("{service} on {link_id}")),
+        Change::BeginBatch { batch_id, .. } | Change::EndBatch { batch_id, .. } => {
+            Some(batch_id.to_string())
+        }
+    };
+
+    match object {
+        Some(object) => format!("{}: {object}", String::from(change)),
+        None => String::from(change),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        time::Duration,
+    };
+
+    use indexmap::IndexMap;
+    use ipnet::Ipv4Net;
+
+    use super::{
+        change_matches, change_stats, location_counts, node_summary, parse_since, parse_window,
+        ChangeStat, ChangesSince, LocationCount,
+    };
+    use crate::data::model::{Change, ChangelogEntry, Data, Dependency, MetadataProvenance, Node};
+
+    #[test]
+    fn test_parse_window() {
+        assert_eq!(parse_window("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_window("7d"), Ok(Duration::from_secs(604_800)));
+        assert!(parse_window("d").is_err());
+        assert!(parse_window("7y").is_err());
+    }
+
+    #[test]
+    fn test_parse_since() {
+        assert_eq!(
+            parse_since("30m"),
+            Ok(ChangesSince::Window(Duration::from_secs(1800)))
+        );
+        assert_eq!(
+            parse_since("1700000000000-3"),
+            Ok(ChangesSince::Id("1700000000000-3".to_string()))
+        );
+        assert_eq!(
+            parse_since("1700000000000"),
+            Ok(ChangesSince::Id("1700000000000".to_string()))
+        );
+        assert!(parse_since("1700000000000-").is_err());
+    }
+
+    #[test]
+    fn test_change_matches() {
+        let change = Change::UpdatedMetadata {
+            plugin: "plugin-a".to_string(),
+            obj_id: "dns;[net]domain.com".to_string(),
+        };
+
+        assert!(change_matches(&change, None, None));
+        assert!(change_matches(&change, Some("plugin-a"), None));
+        assert!(!change_matches(&change, Some("plugin-b"), None));
+        assert!(change_matches(&change, None, Some("updated metadata")));
+        assert!(change_matches(&change, None, Some("Updated-Metadata")));
+        assert!(change_matches(
+            &change,
+            Some("plugin-a"),
+            Some("updated_metadata")
+        ));
+        assert!(!change_matches(&change, None, Some("updated-notes")));
+    }
+
+    #[test]
+    fn test_change_stats() {
+        let entry = |id: &str, plugin: &str| ChangelogEntry {
+            id: id.to_string(),
+            change: Change::CreateDnsName {
+                plugin: plugin.to_string(),
+                qname: "[net]domain.com".to_string(),
+            },
+        };
+
+        let stats = change_stats(&[
+            entry("2000-0", "a"),
+            entry("1000-0", "a"),
+            entry("3000-1", "a"),
+            entry("1500-0", "b"),
+        ]);
+
+        assert_eq!(
+            stats.get(&("a".to_string(), "create dns name".to_string())),
+            Some(&ChangeStat {
+                count: 3,
+                first: 1000,
+                last: 3000
+            })
+        );
+        assert_eq!(stats.len(), 2);
+    }
+
+    #[test]
+    fn test_node_summary() {
+        let node = Node {
+            name: "db-01".to_string(),
+            link_id: "db-01-id".to_string(),
+            alt_names: HashSet::new(),
+            dns_names: HashSet::from(["[net]b.com".to_string(), "[net]a.com".to_string()]),
+            plugins: HashSet::from(["test-plugin".to_string()]),
+            raw_ids: HashSet::from(["[net]a.com;[net]b.com".to_string()]),
+        };
+        let pdata = [Data::Hash {
+            id: "hardware".to_string(),
+            title: "Hardware".to_string(),
+            plugin: "test-plugin".to_string(),
+            content: IndexMap::new(),
+        }];
+
+        assert_eq!(
+            node_summary(
+                &node,
+                &HashMap::from([
+                    (
+                        "role".to_string(),
+                        MetadataProvenance {
+                            value: "database".to_string(),
+                            plugin: Some("test-plugin".to_string()),
+                            changed: Some(0),
+                        }
+                    ),
+                    (
+                        "owner".to_string(),
+                        MetadataProvenance {
+                            value: "ops".to_string(),
+                            plugin: None,
+                            changed: None,
+                        }
+                    ),
+                ]),
+                &pdata,
+                &[Dependency::new(
+                    "db-01-id",
+                    "san-01-id",
+                    "storage".to_string()
+                )]
+            ),
+            "Name: db-01\nLink ID: db-01-id\n\
+             Alt names:\n    (none)\n\
+             DNS names:\n    [net]a.com\n    [net]b.com\n\
+             Plugins:\n    test-plugin\n\
+             Raw node IDs:\n    [net]a.com;[net]b.com\n\
+             Metadata:\n    owner: ops\n    role: database (from test-plugin at 1970-01-01 00:00:00)\n\
+             Plugin data:\n    hardware (hash from test-plugin): Hardware\n\
+             Dependencies:\n    depends on san-01-id (storage)\n"
+        );
+    }
+
+    #[test]
+    fn test_location_counts() {
+        let subnet = |net: &str| net.parse::<Ipv4Net>().unwrap();
+        let subnets = HashMap::from([
+            (subnet("10.0.0.0/16"), "office".to_string()),
+            (subnet("10.1.0.0/16"), "office".to_string()),
+            (subnet("10.2.0.0/16"), "warehouse".to_string()),
+        ]);
+        let office = Some("office".to_string());
+
+        let (counts, unassigned) = location_counts(
+            &subnets,
+            &[office.clone(), office.clone(), None],
+            &[office, Some("remote".to_string()), None, None],
+        );
+
+        assert_eq!(
+            counts.get("office"),
+            Some(&LocationCount {
+                dns_names: 2,
+                nodes: 1,
+                subnets: vec![subnet("10.0.0.0/16"), subnet("10.1.0.0/16")],
+            })
+        );
+        assert_eq!(
+            counts.get("warehouse"),
+            Some(&LocationCount {
+                dns_names: 0,
+                nodes: 0,
+                subnets: vec![subnet("10.2.0.0/16")],
+            })
+        );
+        assert_eq!(counts.get("remote").map(|count| count.nodes), Some(1));
+        assert_eq!((unassigned.dns_names, unassigned.nodes), (1, 2));
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::HashSet;
+
+use crate::{
+    data::{
+        model::{Data, ADDRESS_RTYPES, DNS, DNS_KEY, NODES_KEY, PROC_NODES_KEY, REPORTS_KEY},
+        DataConn, DataStore,
+    },
+    error::NetdoxResult,
+};
+
+const LINK_START: &str = "(!(";
+const LINK_SEP: &str = "|!|";
+const LINK_END: &str = ")!)";
+
+/// A reference from one object to another.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Reference {
+    /// ID of the object the reference is from.
+    pub source: String,
+    /// ID of the object the reference is to.
+    pub target: String,
+    /// Describes what created the reference.
+    pub kind: String,
+    /// Whether the target exists in the data store.
+    pub exists: bool,
+}
+
+/// The objects in the data store that references can point to.
+pub struct LinkTargets {
+    dns: DNS,
+    node_ids: HashSet<String>,
+    report_ids: HashSet<String>,
+}
+
+impl LinkTargets {
+    pub async fn read(con: &mut DataStore) -> NetdoxResult<Self> {
+        Ok(LinkTargets {
+            dns: con.get_dns().await?,
+            node_ids: con.get_node_ids().await?,
+            report_ids: con.get_report_ids().await?,
+        })
+    }
+
+    /// Returns the IDs of every object that can be the source of a reference.
+    pub fn object_ids(&self) -> Vec<String> {
+        let mut ids = self
+            .dns
+            .qnames
+            .iter()
+            .map(|qname| format!("{DNS_KEY};{qname}"))
+            .chain(
+                self.node_ids
+                    .iter()
+                    .map(|id| format!("{PROC_NODES_KEY};{id}")),
+            )
+            .chain(
+                self.report_ids
+                    .iter()
+                    .map(|id| format!("{REPORTS_KEY};{id}")),
+            )
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    fn exists(&self, obj_id: &str) -> bool {
+        match obj_id.split_once(';') {
+            Some((DNS_KEY, qname)) => self.dns.qnames.contains(qname),
+            Some((PROC_NODES_KEY, id)) => self.node_ids.contains(id),
+            Some((REPORTS_KEY, id)) => self.report_ids.contains(id),
+            _ => false,
+        }
+    }
+
+    /// Returns the references from an object to other objects.
+    pub async fn references(
+        &self,
+        con: &mut DataStore,
+        obj_id: &str,
+    ) -> NetdoxResult<Vec<Reference>> {
+        // Pairs of target object ID and kind of reference.
+        let mut targets = vec![];
+        let mut texts = vec![];
+
+        match obj_id.split_once(';') {
+            Some((DNS_KEY, qname)) => {
+                for record in self.dns.get_records(qname) {
+                    if ADDRESS_RTYPES.contains(&record.rtype.as_str()) {
+                        targets.push((
+                            format!("{DNS_KEY};{}", record.value),
+                            format!("{} record", record.rtype),
+                        ));
+                    }
+                }
+
+                for record in self.dns.get_implied_records(qname) {
+                    targets.push((
+                        format!("{DNS_KEY};{}", record.value),
+                        format!("implied {} record", record.rtype),
+                    ));
+                }
+
+                for dest in self.dns.get_translations(qname) {
+                    targets.push((
+                        format!("{DNS_KEY};{dest}"),
+                        "network translation".to_string(),
+                    ));
+                }
+
+                texts.extend(con.get_dns_metadata(qname).await?.into_values());
+                texts.extend(con.get_notes(obj_id).await?.into_values());
+                texts.extend(
+                    con.get_dns_pdata(qname)
+                        .await?
+                        .iter()
+                        .flat_map(Data::to_args),
+                );
+            }
+            Some((PROC_NODES_KEY, id)) => {
+                let node = con.get_node(id).await?;
+                for qname in &node.dns_names {
+                    targets.push((format!("{DNS_KEY};{qname}"), "DNS name".to_string()));
+                }
+
+                texts.extend(con.get_node_metadata(&node).await?.into_values());
+                texts.extend(con.get_notes(obj_id).await?.into_values());
+                texts.extend(
+                    con.get_node_pdata(&node)
+                        .await?
+                        .iter()
+                        .flat_map(Data::to_args),
+                );
+            }
+            Some((REPORTS_KEY, id)) => {
+                let report = con.get_report(id).await?;
+                texts.extend(report.content.iter().flat_map(Data::to_args));
+            }
+            _ => {}
+        }
+
+        for text in &texts {
+            for (kind, id) in parse_links(text) {
+                if let Some(target) = resolve_link(con, kind, id).await? {
+                    targets.push((target, format!("{kind} link")));
+                }
+            }
+        }
+
+        Ok(targets
+            .into_iter()
+            .map(|(target, kind)| Reference {
+                source: obj_id.to_string(),
+                exists: self.exists(&target),
+                target,
+                kind,
+            })
+            .collect())
+    }
+}
+
+/// Returns the ID of the object a link points to.
+/// External links are not objects, so they are ignored.
+pub async fn resolve_link(
+    con: &mut DataStore,
+    kind: &str,
+    id: &str,
+) -> NetdoxResult<Option<String>> {
+    Ok(match kind {
+        "dns" => con
+            .qualify_dns_names(&[id])
+            .await?
+            .pop()
+            .map(|qname| format!("{DNS_KEY};{qname}")),
+        "procnode" => Some(format!("{PROC_NODES_KEY};{id}")),
+        "rawnode" => {
+            let raw_id = con
+                .get_raw_id_from_qnames(&id.split(';').collect::<Vec<_>>())
+                .await?;
+            match con.get_node_from_raw(&raw_id).await? {
+                Some(proc_id) => Some(format!("{PROC_NODES_KEY};{proc_id}")),
+                // Raw nodes that were not consumed by a processed node have no document.
+                None => Some(format!("{NODES_KEY};{raw_id}")),
+            }
+        }
+        "report" => Some(format!("{REPORTS_KEY};{id}")),
+        _ => None,
+    })
+}
+
+/// Part of some text that may contain links.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TextPart<'a> {
+    Text(&'a str),
+    Link { kind: &'a str, id: &'a str },
+}
+
+/// Splits some text into plain text and links,
+/// using the same syntax that is converted to xrefs when publishing.
+/// Links of an unknown kind are left as text.
+pub fn split_links(text: &str) -> Vec<TextPart<'_>> {
+    let mut parts = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find(LINK_START) {
+        let inner = &rest[start + LINK_START.len()..];
+        let Some(end) = inner.find(LINK_END) else {
+            break;
+        };
+
+        let link = match inner[..end].split_once(LINK_SEP) {
+            Some((kind, id))
+                if matches!(kind, "dns" | "procnode" | "rawnode" | "report" | "external")
+                    && !id.is_empty() =>
+            {
+                Some(TextPart::Link { kind, id })
+            }
+            _ => None,
+        };
+
+        let link_end = start + LINK_START.len() + end + LINK_END.len();
+        match link {
+            Some(link) => {
+                if start > 0 {
+                    parts.push(TextPart::Text(&rest[..start]));
+                }
+                parts.push(link);
+            }
+            None => parts.push(TextPart::Text(&rest[..link_end])),
+        }
+        rest = &rest[link_end..];
+    }
+
+    if !rest.is_empty() {
+        parts.push(TextPart::Text(rest));
+    }
+
+    parts
+}
+
+/// Returns the kind and ID of each link in some text.
+pub fn parse_links(text: &str) -> Vec<(&str, &str)> {
+    split_links(text)
+        .into_iter()
+        .filter_map(|part| match part {
+            TextPart::Link { kind, id } => Some((kind, id)),
+            TextPart::Text(_) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_links, split_links, TextPart};
+
+    #[test]
+    fn test_parse_links() {
+        assert_eq!(
+            parse_links("runs on (!(procnode|!|vm-01)!) behind (!(dns|!|[net]lb.com)!)"),
+            vec![("procnode", "vm-01"), ("dns", "[net]lb.com")]
+        );
+        assert_eq!(
+            parse_links("(!(unknown|!|id)!) (!(dns|!|unterminated"),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_split_links() {
+        assert_eq!(
+            split_links("see (!(report|!|r1)!), not (!(bad|!|x)!)."),
+            vec![
+                TextPart::Text("see "),
+                TextPart::Link {
+                    kind: "report",
+                    id: "r1"
+                },
+                TextPart::Text(", not (!(bad|!|x)!)"),
+                TextPart::Text("."),
+            ]
+        );
+    }
+}
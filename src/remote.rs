@@ -1,17 +1,26 @@
+pub mod html;
+pub mod markdown;
+pub mod obfuscate;
 #[cfg(feature = "pageseeder")]
 pub mod pageseeder;
 
-use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
 use enum_dispatch::enum_dispatch;
 use serde::{Deserialize, Serialize};
 
 use crate::config::RemoteConfig;
-use crate::data::model::ObjectID;
-use crate::data::DataStore;
-use crate::error::NetdoxResult;
+use crate::data::model::{ObjectID, RunLock, DNS_KEY, NODES_KEY, PROC_NODES_KEY, REPORTS_KEY};
+use crate::data::{DataConn, DataStore};
+use crate::error::{NetdoxError, NetdoxResult};
+use crate::runs::RunTimer;
+use crate::{io_err, remote_err};
 
 #[async_trait]
 #[enum_dispatch]
@@ -27,7 +36,55 @@ pub trait RemoteInterface {
     async fn labeled(&self, label: &str) -> NetdoxResult<Vec<ObjectID>>;
 
     /// Publishes processed data from redis to the remote.
-    async fn publish(&self, con: DataStore, backup: Option<PathBuf>) -> NetdoxResult<()>;
+    /// Records the duration of each publishing phase in the timer.
+    async fn publish(
+        &self,
+        con: DataStore,
+        backup: Option<PathBuf>,
+        timer: &mut RunTimer,
+    ) -> NetdoxResult<()>;
+
+    /// Plans a publish of processed data to the remote, without writing anything to it.
+    async fn plan(&self, con: DataStore) -> NetdoxResult<PublishPlan>;
+
+    /// Publishes the whole documents for some objects, regardless of the changelog.
+    /// The changelog cursor on the remote is not moved.
+    async fn publish_objects(
+        &self,
+        con: DataStore,
+        objects: &ObjectSelection,
+        backup: Option<PathBuf>,
+        timer: &mut RunTimer,
+    ) -> NetdoxResult<()>;
+
+    /// Archives the documents of DNS names that were moved to another network,
+    /// given pairs of old and new qualified names. Returns the number of documents archived.
+    async fn archive_moved_dns(&self, moved: &[(String, String)]) -> NetdoxResult<usize>;
+
+    /// Archives the documents of DNS names that were deleted from the data store.
+    /// Returns the number of documents archived.
+    async fn archive_deleted_dns(&self, qnames: &[String]) -> NetdoxResult<usize>;
+
+    /// Returns where the document for an object ID is published on the remote, if it has one.
+    fn document_location(&self, obj_id: &str) -> Option<String>;
+
+    /// Archives or removes the documents on the remote whose object is no longer in the data store.
+    /// If `dry_run` is true, only finds them. Returns where each of the documents is published.
+    /// Only documents of DNS names, nodes and reports are reconciled;
+    /// network overview and subnet documents are left alone.
+    async fn reconcile(&self, con: DataStore, dry_run: bool) -> NetdoxResult<Vec<String>>;
+
+    /// Takes the publish lock on the remote, or refreshes it if the owner of the lock already holds it.
+    /// Returns the lock held by another publish instead if it has not expired.
+    ///
+    /// The lock is best-effort: no remote has a conditional write to take it with,
+    /// so two publishes that take a free lock at the same moment can both succeed.
+    /// The one whose lock was overwritten finds out when it next refreshes the lock, and stops.
+    async fn acquire_publish_lock(&self, lock: &RunLock) -> NetdoxResult<Option<RunLock>>;
+
+    /// Releases the publish lock on the remote if it is held by an owner,
+    /// or by anyone if no owner is given. Returns the lock that was released.
+    async fn release_publish_lock(&self, owner: Option<&str>) -> NetdoxResult<Option<RunLock>>;
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -35,11 +92,422 @@ pub trait RemoteInterface {
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Remote {
     Dummy(DummyRemote),
+    #[serde(rename = "html")]
+    Html(html::HtmlRemote),
+    #[serde(rename = "markdown")]
+    Markdown(markdown::MarkdownRemote),
     #[cfg(feature = "pageseeder")]
     #[serde(rename = "pageseeder")]
     PageSeeder(pageseeder::PSRemote),
 }
 
+/// Returns the name of a file for an object ID, without an extension.
+/// Characters other than letters, digits, dots and hyphens are encoded as `_` and their hex value,
+/// so different IDs never share a file.
+pub fn file_stem(id: &str) -> String {
+    let mut name = String::with_capacity(id.len());
+    for byte in id.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'.' || byte == b'-' {
+            name.push(byte as char);
+        } else {
+            let _ = write!(name, "_{byte:02X}");
+        }
+    }
+    name
+}
+
+/// Returns the path to write the backup of a publish to one of several remotes,
+/// which is the backup path with the name of the remote added to the file name.
+pub fn backup_path(path: &Path, remote: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem}-{remote}.{}", ext.to_string_lossy()),
+        None => format!("{stem}-{remote}"),
+    };
+    path.with_file_name(name)
+}
+
+/// Path of the publish lock relative to the directory of a local remote.
+const PUBLISH_LOCK_PATH: &str = ".netdox/publish-lock.toml";
+
+/// Returns true if a publish lock that is held can be taken by an owner,
+/// because the owner already holds it or it has expired.
+pub fn lock_is_free(held: &RunLock, owner: &str) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    held.owner == owner || held.expires <= now
+}
+
+/// Reads the publish lock in the directory of a local remote, if there is one.
+fn read_file_lock(path: &Path) -> NetdoxResult<Option<RunLock>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return io_err!(format!("Failed to read {}: {err}", path.display())),
+    };
+    match toml::from_str(&content) {
+        Ok(lock) => Ok(Some(lock)),
+        Err(err) => io_err!(format!(
+            "Failed to parse publish lock at {}: {err}",
+            path.display()
+        )),
+    }
+}
+
+/// Takes or refreshes the publish lock in the directory of a local remote,
+/// or returns the lock held by another publish.
+pub fn acquire_file_lock(dir: &Path, lock: &RunLock) -> NetdoxResult<Option<RunLock>> {
+    let path = dir.join(PUBLISH_LOCK_PATH);
+    if let Some(held) = read_file_lock(&path)? {
+        if !lock_is_free(&held, &lock.owner) {
+            return Ok(Some(held));
+        }
+    }
+
+    if let Some(parent) = path.parent() {
+        if let Err(err) = fs::create_dir_all(parent) {
+            return io_err!(format!("Failed to create {}: {err}", parent.display()));
+        }
+    }
+    let content = match toml::to_string(lock) {
+        Ok(content) => content,
+        Err(err) => return io_err!(format!("Failed to serialize publish lock: {err}")),
+    };
+    match fs::write(&path, content) {
+        Ok(()) => Ok(None),
+        Err(err) => io_err!(format!("Failed to write {}: {err}", path.display())),
+    }
+}
+
+/// Releases the publish lock in the directory of a local remote
+/// if it is held by an owner, or by anyone if no owner is given.
+pub fn release_file_lock(dir: &Path, owner: Option<&str>) -> NetdoxResult<Option<RunLock>> {
+    let path = dir.join(PUBLISH_LOCK_PATH);
+    match read_file_lock(&path)? {
+        Some(held) if owner.is_none_or(|owner| owner == held.owner) => {
+            remove_files(dir, [PUBLISH_LOCK_PATH.to_string()])?;
+            Ok(Some(held))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Removes the files at some paths relative to a directory, ignoring any that do not exist.
+/// Returns the number of files removed.
+pub fn remove_files(dir: &Path, paths: impl IntoIterator<Item = String>) -> NetdoxResult<usize> {
+    let mut removed = 0;
+    for rel_path in paths {
+        let path = dir.join(rel_path);
+        match fs::remove_file(&path) {
+            Ok(()) => removed += 1,
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return io_err!(format!("Failed to remove {}: {err}", path.display())),
+        }
+    }
+    Ok(removed)
+}
+
+/// Returns the paths of the files in some directories of a local remote that are not documents
+/// of any object in the data store, relative to the root of the remote.
+pub async fn orphaned_files(
+    con: &mut DataStore,
+    root: &Path,
+    dirs: &[&str],
+    path: impl Fn(&str) -> Option<String>,
+) -> NetdoxResult<Vec<String>> {
+    let objects = ObjectSelection::matching(con, "*").await?;
+    let documents = objects
+        .dns
+        .iter()
+        .map(|qname| format!("{DNS_KEY};{qname}"))
+        .chain(
+            objects
+                .nodes
+                .iter()
+                .map(|id| format!("{PROC_NODES_KEY};{id}")),
+        )
+        .chain(
+            objects
+                .reports
+                .iter()
+                .map(|id| format!("{REPORTS_KEY};{id}")),
+        )
+        .filter_map(|obj_id| path(&obj_id))
+        .collect::<HashSet<_>>();
+
+    let mut orphans = vec![];
+    for dir in dirs {
+        let entries = match fs::read_dir(root.join(dir)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => {
+                return io_err!(format!(
+                    "Failed to list files in {}: {err}",
+                    root.join(dir).display()
+                ))
+            }
+        };
+
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => return io_err!(format!("Failed to list files in {dir}: {err}")),
+            };
+            if !entry.path().is_file() {
+                continue;
+            }
+            let rel_path = format!("{dir}/{}", entry.file_name().to_string_lossy());
+            if !documents.contains(&rel_path) {
+                orphans.push(rel_path);
+            }
+        }
+    }
+
+    orphans.sort();
+    Ok(orphans)
+}
+
+/// Checks that the changelog cursor on the remote is still the one this publish started from,
+/// so that it is never moved backwards by a publish that finished after another.
+pub fn check_cursor(expected: Option<&str>, current: Option<&str>, new: &str) -> NetdoxResult<()> {
+    if current == expected {
+        Ok(())
+    } else {
+        remote_err!(format!(
+            "Changelog on the remote was advanced to change ID {} by another publish \
+            since this one started from {}; refusing to set it to {new}. \
+            The changes were applied, and will be checked again by the next publish.",
+            current.unwrap_or("none"),
+            expected.unwrap_or("none")
+        ))
+    }
+}
+
+/// Checks that reconciling would not archive or remove most of the documents on a remote
+/// because the data store is empty or was reset.
+/// Unless forced, refuses if the data store has no processed nodes,
+/// or if there are more orphaned documents than half the objects in the data store.
+pub async fn check_reconcile(con: &mut DataStore, orphans: usize, force: bool) -> NetdoxResult<()> {
+    if force || orphans == 0 {
+        return Ok(());
+    }
+
+    let nodes = con.get_node_ids().await?.len();
+    if nodes == 0 {
+        return remote_err!(format!(
+            "The data store has no processed nodes, and {orphans} documents would be archived or removed. \
+            Pass --force if this is intended."
+        ));
+    }
+
+    let objects = con.get_dns_names().await?.len() + nodes + con.get_report_ids().await?.len();
+    if orphans * 2 > objects {
+        return remote_err!(format!(
+            "{orphans} documents would be archived or removed, more than half of the {objects} objects in the data store. \
+            Pass --force if this is intended."
+        ));
+    }
+    Ok(())
+}
+
+/// Archives or removes the orphaned documents on a remote, if the check on how many there are passes.
+/// Returns where each of the documents is published.
+pub async fn reconcile(
+    remote: &Remote,
+    mut con: DataStore,
+    force: bool,
+) -> NetdoxResult<Vec<String>> {
+    let orphans = remote.reconcile(con.clone(), true).await?;
+    check_reconcile(&mut con, orphans.len(), force).await?;
+    if orphans.is_empty() {
+        return Ok(orphans);
+    }
+    remote.reconcile(con, false).await
+}
+
+/// Records in the data store that the changelog cursor of a remote is moving to a new change ID,
+/// if it is still the `recorded` one that was read when this publish started.
+/// The swap is atomic, so of two publishes that started from the same cursor only one can advance it.
+pub async fn swap_cursor(
+    con: &mut DataStore,
+    remote: &str,
+    recorded: Option<&str>,
+    new: &str,
+) -> NetdoxResult<()> {
+    if con.swap_remote_cursor(remote, recorded, new).await? {
+        Ok(())
+    } else {
+        remote_err!(format!(
+            "Changelog cursor of {remote} was advanced by another publish \
+            since this one started; refusing to set it to {new}. \
+            The changes were applied, and will be checked again by the next publish."
+        ))
+    }
+}
+
+/// Returns true if the text matches the glob pattern.
+/// `*` matches any number of characters and `?` matches exactly one.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    let (mut p_idx, mut t_idx) = (0, 0);
+    // Position of the last star in the pattern, and of the text it was matched at.
+    let mut backtrack = None;
+    while t_idx < text.len() {
+        match pattern.get(p_idx) {
+            Some('*') => {
+                backtrack = Some((p_idx, t_idx));
+                p_idx += 1;
+            }
+            Some(&char) if char == '?' || char == text[t_idx] => {
+                p_idx += 1;
+                t_idx += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p_idx = star + 1;
+                    t_idx = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p_idx..].iter().all(|char| *char == '*')
+}
+
+/// IDs of the DNS names, processed nodes and reports selected for publishing.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ObjectSelection {
+    pub dns: Vec<String>,
+    pub nodes: Vec<String>,
+    pub reports: Vec<String>,
+}
+
+impl ObjectSelection {
+    /// Selects the objects with a qname, link ID or report ID that matches the glob pattern.
+    pub async fn matching(con: &mut DataStore, pattern: &str) -> NetdoxResult<Self> {
+        let select = |ids: HashSet<String>| {
+            let mut ids = ids
+                .into_iter()
+                .filter(|id| glob_match(pattern, id))
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+
+        Ok(ObjectSelection {
+            dns: select(con.get_dns_names().await?),
+            nodes: select(con.get_node_ids().await?),
+            reports: select(con.get_report_ids().await?),
+        })
+    }
+
+    /// Selects the DNS names and processed nodes with a tag.
+    /// A processed node is selected if it or any of its raw nodes has the tag.
+    pub async fn tagged(con: &mut DataStore, tag: &str) -> NetdoxResult<Self> {
+        let (mut dns, mut nodes) = (BTreeSet::new(), BTreeSet::new());
+        for obj_id in con.get_tagged(&tag.trim().to_lowercase()).await? {
+            match obj_id.split_once(';') {
+                Some((DNS_KEY, qname)) => {
+                    dns.insert(qname.to_string());
+                }
+                Some((NODES_KEY, raw_id)) => {
+                    if let Some(link_id) = con.get_node_from_raw(raw_id).await? {
+                        nodes.insert(link_id);
+                    }
+                }
+                Some((PROC_NODES_KEY, link_id)) => {
+                    nodes.insert(link_id.to_string());
+                }
+                _ => {}
+            }
+        }
+
+        Ok(ObjectSelection {
+            dns: dns.into_iter().collect(),
+            nodes: nodes.into_iter().collect(),
+            reports: vec![],
+        })
+    }
+
+    /// Keeps only the objects that are also selected by another selection.
+    pub fn retain(&mut self, other: &ObjectSelection) {
+        self.dns.retain(|qname| other.dns.contains(qname));
+        self.nodes.retain(|link_id| other.nodes.contains(link_id));
+        self.reports.retain(|id| other.reports.contains(id));
+    }
+
+    /// Returns the number of selected objects.
+    pub fn len(&self) -> usize {
+        self.dns.len() + self.nodes.len() + self.reports.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Summary of what a publish would write to a remote.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PublishPlan {
+    /// ID of the last change published to the remote, if any.
+    pub last_change: Option<String>,
+    /// Number of changelog entries since the last published change.
+    pub changes: usize,
+    /// Documents that would be created or replaced.
+    pub documents: Vec<String>,
+    /// Fragments that would be updated, as pairs of document ID and fragment ID.
+    pub fragments: Vec<(String, String)>,
+}
+
+impl PublishPlan {
+    /// Returns a human readable summary of the plan.
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Last published change: {}\nChanges since then: {}\n",
+            self.last_change.as_deref().unwrap_or("none"),
+            self.changes
+        );
+
+        if self.documents.is_empty() && self.fragments.is_empty() {
+            summary.push_str("Nothing would be published.\n");
+            return summary;
+        }
+
+        if !self.documents.is_empty() {
+            let _ = writeln!(
+                summary,
+                "Documents that would be created or replaced ({}):",
+                self.documents.len()
+            );
+            for docid in &self.documents {
+                let _ = writeln!(summary, "\t{docid}");
+            }
+        }
+
+        if !self.fragments.is_empty() {
+            let _ = writeln!(
+                summary,
+                "Fragments that would be updated ({}):",
+                self.fragments.len()
+            );
+            for (docid, fragment) in &self.fragments {
+                let _ = writeln!(summary, "\t{docid}#{fragment}");
+            }
+        }
+
+        summary
+    }
+}
+
 // Dummy
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -66,7 +534,212 @@ impl RemoteInterface for DummyRemote {
         Ok(vec![])
     }
 
-    async fn publish(&self, _: DataStore, _: Option<PathBuf>) -> NetdoxResult<()> {
+    async fn publish(
+        &self,
+        _: DataStore,
+        _: Option<PathBuf>,
+        _: &mut RunTimer,
+    ) -> NetdoxResult<()> {
+        Ok(())
+    }
+
+    async fn plan(&self, _: DataStore) -> NetdoxResult<PublishPlan> {
+        Ok(PublishPlan::default())
+    }
+
+    async fn publish_objects(
+        &self,
+        _: DataStore,
+        _: &ObjectSelection,
+        _: Option<PathBuf>,
+        _: &mut RunTimer,
+    ) -> NetdoxResult<()> {
         Ok(())
     }
+
+    async fn archive_moved_dns(&self, _: &[(String, String)]) -> NetdoxResult<usize> {
+        Ok(0)
+    }
+
+    async fn archive_deleted_dns(&self, _: &[String]) -> NetdoxResult<usize> {
+        Ok(0)
+    }
+
+    fn document_location(&self, _: &str) -> Option<String> {
+        None
+    }
+
+    async fn reconcile(&self, _: DataStore, _: bool) -> NetdoxResult<Vec<String>> {
+        Ok(vec![])
+    }
+
+    async fn acquire_publish_lock(&self, _: &RunLock) -> NetdoxResult<Option<RunLock>> {
+        Ok(None)
+    }
+
+    async fn release_publish_lock(&self, _: Option<&str>) -> NetdoxResult<Option<RunLock>> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{
+        acquire_file_lock, backup_path, check_cursor, check_reconcile, glob_match,
+        release_file_lock, ObjectSelection, PublishPlan,
+    };
+    use crate::data::{
+        model::{RunKind, RunLock, DNS_KEY, PROC_NODES_KEY},
+        store::memory_store::MemoryStore,
+        DataStore,
+    };
+
+    #[tokio::test]
+    async fn test_check_reconcile() {
+        let store = MemoryStore::new();
+        let mut con = DataStore::Memory(store.clone());
+
+        // An empty data store would orphan every document.
+        assert!(check_reconcile(&mut con, 3, false).await.is_err());
+        assert!(check_reconcile(&mut con, 3, true).await.is_ok());
+        assert!(check_reconcile(&mut con, 0, false).await.is_ok());
+
+        store
+            .write(|con| {
+                for qname in ["[net]a.com", "[net]b.com", "[net]c.com"] {
+                    con.sadd(DNS_KEY, qname)?;
+                }
+                con.sadd(PROC_NODES_KEY, "node")?;
+                Ok(())
+            })
+            .unwrap();
+        assert!(check_reconcile(&mut con, 2, false).await.is_ok());
+        assert!(check_reconcile(&mut con, 3, false).await.is_err());
+        assert!(check_reconcile(&mut con, 3, true).await.is_ok());
+    }
+
+    #[test]
+    fn test_file_lock() {
+        const FAR_FUTURE: u64 = 4_000_000_000;
+        let dir = std::env::temp_dir().join(format!("netdox-lock-{}", std::process::id()));
+        let lock = |owner: &str, expires: u64| RunLock {
+            owner: owner.to_string(),
+            kind: RunKind::Publish,
+            host: "host".to_string(),
+            pid: 1,
+            acquired: 0,
+            expires,
+        };
+
+        assert_eq!(
+            acquire_file_lock(&dir, &lock("a", FAR_FUTURE)).unwrap(),
+            None
+        );
+        // Refreshing a held lock succeeds, but taking it from another owner fails.
+        assert_eq!(
+            acquire_file_lock(&dir, &lock("a", FAR_FUTURE)).unwrap(),
+            None
+        );
+        assert_eq!(
+            acquire_file_lock(&dir, &lock("b", FAR_FUTURE)).unwrap(),
+            Some(lock("a", FAR_FUTURE))
+        );
+        assert_eq!(release_file_lock(&dir, Some("b")).unwrap(), None);
+        assert_eq!(
+            release_file_lock(&dir, Some("a")).unwrap(),
+            Some(lock("a", FAR_FUTURE))
+        );
+
+        // Expired locks can be taken, and any lock can be released without an owner.
+        assert_eq!(acquire_file_lock(&dir, &lock("a", 1)).unwrap(), None);
+        assert_eq!(
+            acquire_file_lock(&dir, &lock("b", FAR_FUTURE)).unwrap(),
+            None
+        );
+        assert_eq!(
+            release_file_lock(&dir, None).unwrap(),
+            Some(lock("b", FAR_FUTURE))
+        );
+        assert_eq!(release_file_lock(&dir, None).unwrap(), None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_backup_path() {
+        assert_eq!(
+            backup_path(Path::new("/tmp/backup.zip"), "mirror"),
+            PathBuf::from("/tmp/backup-mirror.zip")
+        );
+        assert_eq!(
+            backup_path(Path::new("backup"), "mirror"),
+            PathBuf::from("backup-mirror")
+        );
+    }
+
+    #[test]
+    fn test_check_cursor() {
+        assert!(check_cursor(None, None, "1-0").is_ok());
+        assert!(check_cursor(Some("1-0"), Some("1-0"), "2-0").is_ok());
+        assert!(check_cursor(Some("1-0"), Some("3-0"), "2-0").is_err());
+        assert!(check_cursor(None, Some("1-0"), "2-0").is_err());
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("[net]a.com", "[net]a.com"));
+        assert!(glob_match("*.com", "[net]a.com"));
+        assert!(glob_match("[net]?.com", "[net]a.com"));
+        assert!(glob_match("*a*a*", "banana"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("[net]?.com", "[net]ab.com"));
+        assert!(!glob_match("*.net", "[net]a.com"));
+        assert!(!glob_match("a*b", "ab-"));
+    }
+
+    #[test]
+    fn test_selection_retain() {
+        let mut selection = ObjectSelection {
+            dns: vec!["[net]a.com".to_string(), "[net]b.com".to_string()],
+            nodes: vec!["node-a".to_string()],
+            reports: vec!["report".to_string()],
+        };
+        selection.retain(&ObjectSelection {
+            dns: vec!["[net]b.com".to_string()],
+            nodes: vec!["node-a".to_string(), "node-b".to_string()],
+            reports: vec![],
+        });
+
+        assert_eq!(
+            selection,
+            ObjectSelection {
+                dns: vec!["[net]b.com".to_string()],
+                nodes: vec!["node-a".to_string()],
+                reports: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_plan_summary() {
+        assert_eq!(
+            PublishPlan::default().summary(),
+            "Last published change: none\nChanges since then: 0\nNothing would be published.\n"
+        );
+
+        let plan = PublishPlan {
+            last_change: Some("1-0".to_string()),
+            changes: 2,
+            documents: vec!["_nd_dns_a.com".to_string()],
+            fragments: vec![("_nd_node_b".to_string(), "meta".to_string())],
+        };
+        assert_eq!(
+            plan.summary(),
+            "Last published change: 1-0\nChanges since then: 2\n\
+            Documents that would be created or replaced (1):\n\t_nd_dns_a.com\n\
+            Fragments that would be updated (1):\n\t_nd_node_b#meta\n"
+        );
+    }
 }
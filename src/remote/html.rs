@@ -0,0 +1,406 @@
+mod render;
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    pin::pin,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use ipnet::Ipv4Net;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+use zip::{write::FileOptions, ZipWriter};
+
+use crate::{
+    config::RemoteConfig,
+    config_err,
+    data::{
+        model::{ObjectID, RunLock, DNS_KEY},
+        stream::{node_batches, BATCH_SIZE},
+        DataConn, DataStore,
+    },
+    error::{NetdoxError, NetdoxResult},
+    io_err,
+    progress::Progress,
+    remote::{
+        acquire_file_lock,
+        obfuscate::{Obfuscation, Obfuscator},
+        orphaned_files, release_file_lock, remove_files, ObjectSelection, PublishPlan,
+    },
+    runs::{format_timestamp, RunTimer},
+    success,
+};
+use render::{
+    dns_path, file_name, index_page, node_path, object_path, report_path, Renderer, DNS_DIR,
+    INDEX_FILE, NODE_DIR, REPORT_DIR,
+};
+
+fn default_title() -> String {
+    "Netdox".to_string()
+}
+
+/// Remote that renders documents as a static HTML site in a local directory,
+/// which can then be served by any web server.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HtmlRemote {
+    /// Directory to write the site to.
+    pub output_dir: PathBuf,
+    /// Title of the site, displayed on every page.
+    #[serde(default = "default_title")]
+    pub title: String,
+    /// Maps subnets to locations, like the config document of other remotes.
+    #[serde(default)]
+    pub locations: HashMap<String, String>,
+    /// DNS names to exclude from all networks.
+    #[serde(default)]
+    pub exclusions: HashSet<String>,
+    /// Whether to render the metadata set by each plugin under its own heading.
+    #[serde(default)]
+    pub metadata_by_plugin: bool,
+    /// Replaces addresses and hostnames with pseudonyms, for sites shared outside the organisation.
+    #[serde(default)]
+    pub obfuscation: Option<Obfuscation>,
+}
+
+impl HtmlRemote {
+    /// Renders every page of the site, returning pairs of path relative to the output directory and content.
+    async fn render(&self, con: &mut DataStore) -> NetdoxResult<Vec<(String, String)>> {
+        let qnames = con
+            .get_dns_names()
+            .await?
+            .into_iter()
+            .sorted()
+            .collect_vec();
+        let num_nodes = con.get_node_ids().await?.len();
+        let report_ids = con
+            .get_report_ids()
+            .await?
+            .into_iter()
+            .sorted()
+            .collect_vec();
+
+        let mut pages = Vec::with_capacity(qnames.len() + num_nodes + report_ids.len() + 1);
+        let mut nodes = Vec::with_capacity(num_nodes);
+        let mut reports = Vec::with_capacity(report_ids.len());
+
+        let mut progress = Progress::new(
+            "Rendered pages",
+            qnames.len() + num_nodes + report_ids.len(),
+        );
+        let mut node_batches = pin!(node_batches(con.clone(), BATCH_SIZE));
+        let mut renderer = Renderer::new(con, &self.title, self.metadata_by_plugin).await?;
+
+        for qname in &qnames {
+            pages.push((dns_path(qname), renderer.dns_page(qname).await?));
+            progress.tick();
+        }
+
+        let mut node_pages = Vec::with_capacity(num_nodes);
+        while let Some(batch) = node_batches.try_next().await? {
+            for node in batch {
+                let page = renderer.node_content(&node).await?;
+                node_pages.push((node_path(&node.link_id), page));
+                nodes.push((node.link_id, node.name));
+                progress.tick();
+            }
+        }
+        node_pages.sort();
+        pages.extend(node_pages);
+
+        for id in &report_ids {
+            let (title, page) = renderer.report_page(id).await?;
+            pages.push((report_path(id), page));
+            reports.push((id.clone(), title));
+            progress.tick();
+        }
+
+        progress.finish();
+
+        let generated = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| format_timestamp(duration.as_secs()))
+            .unwrap_or_default();
+        nodes.sort_by(|lhs, rhs| lhs.1.cmp(&rhs.1));
+        pages.push((
+            INDEX_FILE.to_string(),
+            index_page(
+                &self.title,
+                &qnames,
+                &nodes,
+                &reports,
+                &format!("{generated} UTC"),
+            ),
+        ));
+
+        self.obfuscate(con, pages).await
+    }
+
+    /// Renders the pages of some objects, without the index.
+    async fn render_objects(
+        &self,
+        con: &mut DataStore,
+        objects: &ObjectSelection,
+    ) -> NetdoxResult<Vec<(String, String)>> {
+        let mut pages = Vec::with_capacity(objects.len());
+        let mut renderer = Renderer::new(con, &self.title, self.metadata_by_plugin).await?;
+
+        for qname in &objects.dns {
+            pages.push((dns_path(qname), renderer.dns_page(qname).await?));
+        }
+        for id in &objects.nodes {
+            pages.push((node_path(id), renderer.node_page(id).await?.1));
+        }
+        for id in &objects.reports {
+            pages.push((report_path(id), renderer.report_page(id).await?.1));
+        }
+
+        self.obfuscate(con, pages).await
+    }
+
+    /// Replaces the addresses and hostnames in the paths and content of pages with their pseudonyms,
+    /// if the site is obfuscated.
+    async fn obfuscate(
+        &self,
+        con: &mut DataStore,
+        pages: Vec<(String, String)>,
+    ) -> NetdoxResult<Vec<(String, String)>> {
+        let Some(method) = &self.obfuscation else {
+            return Ok(pages);
+        };
+
+        let qnames = con.get_dns_names().await?;
+        let node_ids = con.get_node_ids().await?;
+        let obfuscator = Obfuscator::new(method.clone(), &qnames, &node_ids, file_name);
+        Ok(pages
+            .into_iter()
+            .map(|(path, content)| (obfuscator.text(&path), obfuscator.text(&content)))
+            .collect())
+    }
+
+    /// Path of the page for an object ID, if it has one.
+    /// If the site is obfuscated the path is of its pseudonym.
+    fn page_path(&self, obj_id: &str) -> Option<String> {
+        match &self.obfuscation {
+            Some(method) => object_path(&method.object_id(obj_id)),
+            None => object_path(obj_id),
+        }
+    }
+
+    /// Writes pages to the output directory.
+    /// If the pages are the whole site, any pages from previous publishes are removed.
+    fn write(&self, pages: &[(String, String)], full: bool) -> NetdoxResult<()> {
+        for dir in [DNS_DIR, NODE_DIR, REPORT_DIR] {
+            let path = self.output_dir.join(dir);
+            if full && path.exists() {
+                if let Err(err) = fs::remove_dir_all(&path) {
+                    return io_err!(format!(
+                        "Failed to remove old pages in {}: {err}",
+                        path.display()
+                    ));
+                }
+            }
+            if let Err(err) = fs::create_dir_all(&path) {
+                return io_err!(format!("Failed to create {}: {err}", path.display()));
+            }
+        }
+
+        for (rel_path, content) in pages {
+            let path = self.output_dir.join(rel_path);
+            if let Err(err) = fs::write(&path, content) {
+                return io_err!(format!("Failed to write page {}: {err}", path.display()));
+            }
+        }
+
+        debug!(
+            "Wrote {} pages to {}",
+            pages.len(),
+            self.output_dir.display()
+        );
+        Ok(())
+    }
+}
+
+/// Writes a zip of the pages of the site to the path.
+fn write_backup(path: &Path, pages: &[(String, String)]) -> NetdoxResult<()> {
+    let file = match File::create(path) {
+        Ok(file) => file,
+        Err(err) => return io_err!(format!("Failed to create backup {}: {err}", path.display())),
+    };
+
+    let mut zip = ZipWriter::new(file);
+    for (rel_path, content) in pages {
+        if let Err(err) = zip.start_file(rel_path, FileOptions::default()) {
+            return io_err!(format!("Failed to start {rel_path} in backup zip: {err}"));
+        }
+        if let Err(err) = zip.write_all(content.as_bytes()) {
+            return io_err!(format!("Failed to write {rel_path} to backup zip: {err}"));
+        }
+    }
+
+    match zip.finish() {
+        Ok(_) => Ok(()),
+        Err(err) => io_err!(format!("Failed to finish backup zip: {err}")),
+    }
+}
+
+#[async_trait]
+impl crate::remote::RemoteInterface for HtmlRemote {
+    async fn test(&self) -> NetdoxResult<()> {
+        if let Err(err) = fs::create_dir_all(&self.output_dir) {
+            return io_err!(format!(
+                "Failed to create output directory {}: {err}",
+                self.output_dir.display()
+            ));
+        }
+
+        match fs::metadata(&self.output_dir) {
+            Ok(meta) if meta.permissions().readonly() => io_err!(format!(
+                "Output directory {} is read only.",
+                self.output_dir.display()
+            )),
+            Ok(_) => Ok(()),
+            Err(err) => io_err!(format!(
+                "Failed to read output directory {}: {err}",
+                self.output_dir.display()
+            )),
+        }
+    }
+
+    async fn config(&self) -> NetdoxResult<RemoteConfig> {
+        let mut locations = HashMap::new();
+        for (subnet, location) in &self.locations {
+            match subnet.parse::<Ipv4Net>() {
+                Ok(subnet) => {
+                    locations.insert(subnet, location.clone());
+                }
+                Err(err) => {
+                    return config_err!(format!(
+                        "Invalid subnet for location {location}: {subnet} ({err})"
+                    ))
+                }
+            }
+        }
+
+        Ok(RemoteConfig {
+            exclusions: self.exclusions.clone(),
+            locations,
+            metadata: HashMap::new(),
+        })
+    }
+
+    /// Static sites have no labels.
+    async fn labeled(&self, _: &str) -> NetdoxResult<Vec<ObjectID>> {
+        Ok(vec![])
+    }
+
+    async fn publish(
+        &self,
+        mut con: DataStore,
+        backup: Option<PathBuf>,
+        timer: &mut RunTimer,
+    ) -> NetdoxResult<()> {
+        let pages = self.render(&mut con).await;
+        timer.phase("render", pages.is_ok());
+        let pages = pages?;
+
+        let written = self.test().await.and_then(|()| self.write(&pages, true));
+        timer.phase("write", written.is_ok());
+        written?;
+
+        if let Some(path) = backup {
+            write_backup(&path, &pages)?;
+        }
+
+        Ok(())
+    }
+
+    /// Every page of the site is written by each publish.
+    async fn plan(&self, mut con: DataStore) -> NetdoxResult<PublishPlan> {
+        let pages = self.render(&mut con).await?;
+        Ok(PublishPlan {
+            last_change: None,
+            changes: 0,
+            documents: pages.into_iter().map(|(path, _)| path).collect(),
+            fragments: vec![],
+        })
+    }
+
+    /// The index is not written again, so it may show stale names until the next full publish.
+    async fn publish_objects(
+        &self,
+        mut con: DataStore,
+        objects: &ObjectSelection,
+        backup: Option<PathBuf>,
+        timer: &mut RunTimer,
+    ) -> NetdoxResult<()> {
+        let pages = self.render_objects(&mut con, objects).await;
+        timer.phase("render", pages.is_ok());
+        let pages = pages?;
+
+        let written = self.test().await.and_then(|()| self.write(&pages, false));
+        timer.phase("write", written.is_ok());
+        written?;
+
+        if let Some(path) = backup {
+            write_backup(&path, &pages)?;
+        }
+
+        success!("Wrote {} pages to the site.", pages.len());
+        Ok(())
+    }
+
+    /// Pages of moved DNS names are removed, as a full publish would.
+    async fn archive_moved_dns(&self, moved: &[(String, String)]) -> NetdoxResult<usize> {
+        remove_files(
+            &self.output_dir,
+            moved
+                .iter()
+                .filter_map(|(old, _)| self.page_path(&format!("{DNS_KEY};{old}"))),
+        )
+    }
+
+    /// Pages of deleted DNS names are removed, as a full publish would.
+    async fn archive_deleted_dns(&self, qnames: &[String]) -> NetdoxResult<usize> {
+        remove_files(
+            &self.output_dir,
+            qnames
+                .iter()
+                .filter_map(|qname| self.page_path(&format!("{DNS_KEY};{qname}"))),
+        )
+    }
+
+    /// Paths are relative to the root of the site.
+    fn document_location(&self, obj_id: &str) -> Option<String> {
+        self.page_path(obj_id)
+    }
+
+    /// Orphaned pages are removed.
+    async fn reconcile(&self, mut con: DataStore, dry_run: bool) -> NetdoxResult<Vec<String>> {
+        let orphans = orphaned_files(
+            &mut con,
+            &self.output_dir,
+            &[DNS_DIR, NODE_DIR, REPORT_DIR],
+            |obj_id| self.page_path(obj_id),
+        )
+        .await?;
+        if !dry_run {
+            remove_files(&self.output_dir, orphans.clone())?;
+        }
+        Ok(orphans)
+    }
+
+    /// The lock is a file in the state directory of the output.
+    async fn acquire_publish_lock(&self, lock: &RunLock) -> NetdoxResult<Option<RunLock>> {
+        acquire_file_lock(&self.output_dir, lock)
+    }
+
+    async fn release_publish_lock(&self, owner: Option<&str>) -> NetdoxResult<Option<RunLock>> {
+        release_file_lock(&self.output_dir, owner)
+    }
+}
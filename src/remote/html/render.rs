@@ -0,0 +1,680 @@
+use std::{collections::HashMap, fmt::Write as _, net::IpAddr};
+
+use itertools::Itertools;
+
+use crate::{
+    data::{
+        model::{
+            address_subnet, group_metadata, ptr_name, qname_address, reverse_zone, split_qname,
+            status_colour, Data, Node, PluginDisplay, StringType, DNS_KEY, NODE_META_KEY,
+            PROC_NODES_KEY, REPORTS_KEY, ROLE_META_KEY, STATUS_STATE_FIELD,
+        },
+        DataConn, DataStore,
+    },
+    error::{NetdoxError, NetdoxResult},
+    query::links::{resolve_link, split_links, TextPart},
+    redis_err,
+    remote::file_stem,
+};
+
+pub const DNS_DIR: &str = "dns";
+pub const NODE_DIR: &str = "nodes";
+pub const REPORT_DIR: &str = "reports";
+pub const INDEX_FILE: &str = "index.html";
+
+/// Prefix for links from documents to other pages, which are all one directory below the root.
+const DOC_ROOT: &str = "../";
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em auto; max-width: 70em; padding: 0 1em; }
+nav { margin-bottom: 1em; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 0.25em 0.5em; text-align: left; vertical-align: top; }
+th { background: #f4f4f4; }
+section.data { border-left: 0.3em solid #ccc; padding-left: 1em; margin-bottom: 1.5em; }
+.label { background: #eee; border-radius: 0.3em; font-size: 0.8em; padding: 0.1em 0.4em; }
+.source { color: #666; font-size: 0.9em; }
+.status { border: 0.15em solid #999; background: #f4f4f4; padding: 0.5em 1em; margin-bottom: 1em; }
+.status-green { border-color: #2a2; background: #efe; }
+.status-orange { border-color: #e90; background: #fff4e0; }
+.status-red { border-color: #d22; background: #fee; }
+";
+
+/// Escapes text for use in HTML content or attribute values.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Returns the name of the file for an object ID.
+pub fn file_name(id: &str) -> String {
+    format!("{}.html", file_stem(id))
+}
+
+/// Path of the page for a DNS name, relative to the root of the site.
+pub fn dns_path(qname: &str) -> String {
+    format!("{DNS_DIR}/{}", file_name(qname))
+}
+
+/// Path of the page for a processed node, relative to the root of the site.
+pub fn node_path(link_id: &str) -> String {
+    format!("{NODE_DIR}/{}", file_name(link_id))
+}
+
+/// Path of the page for a report, relative to the root of the site.
+pub fn report_path(id: &str) -> String {
+    format!("{REPORT_DIR}/{}", file_name(id))
+}
+
+/// Path of the page for an object ID, if it has one.
+pub fn object_path(obj_id: &str) -> Option<String> {
+    match obj_id.split_once(';')? {
+        (DNS_KEY, qname) => Some(dns_path(qname)),
+        (PROC_NODES_KEY, link_id) => Some(node_path(link_id)),
+        (REPORTS_KEY, id) => Some(report_path(id)),
+        _ => None,
+    }
+}
+
+/// Wraps the body of a page in a complete HTML document.
+/// Root is the prefix for links to other pages.
+pub fn page(site_title: &str, title: &str, root: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title} - {site_title}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n<nav><a href=\"{root}{INDEX_FILE}\">{site_title}</a></nav>\n<h1>{title}</h1>\n{body}</body>\n</html>\n",
+        title = escape(title),
+        site_title = escape(site_title),
+    )
+}
+
+/// Renders rows of a title and some HTML as a two column table.
+fn properties(rows: &[(String, String)]) -> String {
+    if rows.is_empty() {
+        return "<p>None.</p>\n".to_string();
+    }
+
+    let mut table = String::from("<table>\n");
+    for (title, value) in rows {
+        let _ = writeln!(table, "<tr><th>{}</th><td>{value}</td></tr>", escape(title));
+    }
+    table.push_str("</table>\n");
+    table
+}
+
+fn link(root: &str, path: &str, text: &str) -> String {
+    format!(
+        "<a href=\"{}\">{}</a>",
+        escape(&format!("{root}{path}")),
+        escape(text)
+    )
+}
+
+/// Renders the pages of a site from the data store.
+pub struct Renderer<'a> {
+    con: &'a mut DataStore,
+    site_title: &'a str,
+    displays: HashMap<String, PluginDisplay>,
+    /// Whether to render the metadata set by each plugin under its own heading.
+    metadata_by_plugin: bool,
+}
+
+impl<'a> Renderer<'a> {
+    pub async fn new(
+        con: &'a mut DataStore,
+        site_title: &'a str,
+        metadata_by_plugin: bool,
+    ) -> NetdoxResult<Self> {
+        let displays = con.get_plugin_displays().await?;
+        Ok(Renderer {
+            con,
+            site_title,
+            displays,
+            metadata_by_plugin,
+        })
+    }
+
+    /// Renders some text as HTML, replacing links with anchors to their pages.
+    async fn text(&mut self, text: &str) -> NetdoxResult<String> {
+        let mut html = String::new();
+        for part in split_links(text) {
+            match part {
+                TextPart::Text(text) => html.push_str(&escape(text)),
+                TextPart::Link { kind, id } => match self.link_path(kind, id).await? {
+                    Some(path) => html.push_str(&link(DOC_ROOT, &path, id)),
+                    None if kind == "external" => html.push_str(&link("", id, id)),
+                    None => html.push_str(&escape(id)),
+                },
+            }
+        }
+        Ok(html)
+    }
+
+    /// Returns the path of the page a link points to, if it has one.
+    async fn link_path(&mut self, kind: &str, id: &str) -> NetdoxResult<Option<String>> {
+        Ok(resolve_link(self.con, kind, id)
+            .await?
+            .and_then(|obj_id| object_path(&obj_id)))
+    }
+
+    /// Renders a map of metadata or notes, sorted by key.
+    async fn map(&mut self, map: HashMap<String, String>) -> NetdoxResult<String> {
+        let mut rows = vec![];
+        for (key, value) in map.into_iter().sorted() {
+            rows.push((key, self.text(&value).await?));
+        }
+        Ok(properties(&rows))
+    }
+
+    /// Renders metadata, with the keys set by each plugin under its own heading if the sources are given.
+    async fn metadata(
+        &mut self,
+        metadata: HashMap<String, String>,
+        sources: Option<HashMap<String, String>>,
+    ) -> NetdoxResult<String> {
+        let metadata: HashMap<_, _> = metadata
+            .into_iter()
+            .filter(|(key, _)| !key.starts_with('_'))
+            .collect();
+        let groups = match sources {
+            Some(sources) if !metadata.is_empty() => group_metadata(metadata, &sources),
+            _ => return self.map(metadata).await,
+        };
+
+        let mut html = String::new();
+        for (plugin, metadata) in groups {
+            if let Some(plugin) = plugin {
+                let _ = writeln!(html, "<h3>{}</h3>", escape(&plugin));
+            }
+            html.push_str(&self.map(metadata).await?);
+        }
+        Ok(html)
+    }
+
+    /// Renders a piece of plugin data, applying the display settings of its source plugin.
+    async fn data(&mut self, data: &Data) -> NetdoxResult<String> {
+        let display = self
+            .displays
+            .get(data.plugin())
+            .cloned()
+            .unwrap_or_default();
+        let (title, plugin) = match data {
+            Data::Hash { title, plugin, .. }
+            | Data::List { title, plugin, .. }
+            | Data::String { title, plugin, .. }
+            | Data::Table { title, plugin, .. } => (title, plugin),
+        };
+
+        let mut html = match &display.colour {
+            Some(colour) => format!(
+                "<section class=\"data\" style=\"border-left-color: {}\">\n",
+                escape(colour)
+            ),
+            None => "<section class=\"data\">\n".to_string(),
+        };
+        let _ = writeln!(html, "<h3>{}</h3>", escape(title));
+        let _ = write!(
+            html,
+            "<p class=\"source\">Source: {}",
+            escape(display.name.as_deref().unwrap_or(plugin))
+        );
+        if let Some(label) = &display.label {
+            let _ = write!(html, " <span class=\"label\">{}</span>", escape(label));
+        }
+        html.push_str("</p>\n");
+
+        match data {
+            Data::Hash { content, .. } => {
+                let mut rows = vec![];
+                for (key, value) in content {
+                    rows.push((key.clone(), self.text(value).await?));
+                }
+                html.push_str(&properties(&rows));
+            }
+            Data::List { content, .. } => {
+                let mut rows = vec![];
+                for (_, title, value) in content {
+                    rows.push((title.clone(), self.text(value).await?));
+                }
+                html.push_str(&properties(&rows));
+            }
+            Data::String {
+                content_type,
+                content,
+                ..
+            } => match content_type {
+                // Plugins declare this content as markup, so it is included as is.
+                StringType::HtmlMarkup => {
+                    html.push_str(content);
+                    html.push('\n');
+                }
+                StringType::Plain => {
+                    let _ = writeln!(html, "<p>{}</p>", self.text(content).await?);
+                }
+                StringType::Code | StringType::Markdown => {
+                    let _ = writeln!(html, "<pre>{}</pre>", escape(content));
+                }
+            },
+            Data::Table {
+                columns, content, ..
+            } => {
+                html.push_str("<table>\n");
+                for row in content.chunks((*columns).max(1)) {
+                    html.push_str("<tr>");
+                    for cell in row {
+                        let _ = write!(html, "<td>{}</td>", self.text(cell).await?);
+                    }
+                    html.push_str("</tr>\n");
+                }
+                html.push_str("</table>\n");
+            }
+        }
+
+        html.push_str("</section>\n");
+        Ok(html)
+    }
+
+    async fn pdata(&mut self, pdata: Vec<Data>) -> NetdoxResult<String> {
+        let mut html = String::from("<h2>Plugin Data</h2>\n");
+        if pdata.is_empty() {
+            html.push_str("<p>None.</p>\n");
+        }
+        for data in pdata
+            .into_iter()
+            .sorted_by(|lhs, rhs| lhs.id().cmp(rhs.id()))
+        {
+            html.push_str(&self.data(&data).await?);
+        }
+        Ok(html)
+    }
+
+    /// Renders the status data of a node as a banner coloured by its state.
+    async fn status_banner(&mut self, data: &Data) -> NetdoxResult<String> {
+        let Data::Hash {
+            plugin, content, ..
+        } = data
+        else {
+            return Ok(String::new());
+        };
+        let state = data.status_state().unwrap_or_default();
+        let source = self
+            .displays
+            .get(plugin)
+            .and_then(|display| display.name.clone())
+            .unwrap_or_else(|| plugin.clone());
+
+        let mut html = format!(
+            "<div class=\"status status-{}\">\n<p><strong>Status: {}</strong> <span class=\"source\">from {}</span></p>\n",
+            status_colour(state),
+            escape(state),
+            escape(&source)
+        );
+        let mut rows = vec![];
+        for (key, value) in content {
+            if key != STATUS_STATE_FIELD {
+                rows.push((key.clone(), self.text(value).await?));
+            }
+        }
+        if !rows.is_empty() {
+            html.push_str(&properties(&rows));
+        }
+        html.push_str("</div>\n");
+
+        Ok(html)
+    }
+
+    /// Renders the page for a DNS name.
+    pub async fn dns_page(&mut self, qname: &str) -> NetdoxResult<String> {
+        let (network, name) = match split_qname(qname) {
+            Some(tuple) => tuple,
+            None => return redis_err!(format!("Failed to parse network from qname: {qname}")),
+        };
+        let address = qname_address(qname);
+        let dns = self.con.get_dns_subset(&[qname]).await?;
+        let mut metadata = self.con.get_dns_metadata(qname).await?;
+        let mut body = String::new();
+
+        // An address displays the node it resolves to first, instead of with the metadata.
+        if address.is_some() {
+            let node = match metadata.remove(NODE_META_KEY) {
+                Some(node) => self.text(&node).await?,
+                None => "None".to_string(),
+            };
+            let _ = writeln!(body, "<p><strong>Node:</strong> {node}</p>");
+        }
+
+        let mut details = vec![
+            ("Name".to_string(), escape(name)),
+            ("Logical Network".to_string(), escape(network)),
+        ];
+        if let Some(addr) = address {
+            details.push(("PTR Name".to_string(), escape(&ptr_name(&addr))));
+            details.push(("Reverse Zone".to_string(), escape(&reverse_zone(&addr))));
+            details.push((
+                "Subnet".to_string(),
+                escape(&address_subnet(&addr).to_string()),
+            ));
+        }
+        body.push_str(&properties(&details));
+
+        let sources = match self.metadata_by_plugin {
+            true => Some(self.con.get_dns_metadata_sources(qname).await?),
+            false => None,
+        };
+        body.push_str("<h2>Metadata</h2>\n");
+        body.push_str(&self.metadata(metadata, sources).await?);
+
+        let notes = self.con.get_notes(&format!("{DNS_KEY};{qname}")).await?;
+        if !notes.is_empty() {
+            body.push_str("<h2>Notes</h2>\n");
+            body.push_str(&self.map(notes).await?);
+        }
+
+        body.push_str("<h2>DNS Records</h2>\n");
+        let mut records = dns
+            .get_records(qname)
+            .into_iter()
+            .map(|record| (&record.rtype, &record.value, &record.plugin, false))
+            .chain(
+                dns.get_implied_records(qname)
+                    .into_iter()
+                    .map(|record| (&record.rtype, &record.value, &record.plugin, true)),
+            )
+            .collect::<Vec<_>>();
+        records.sort();
+        if records.is_empty() {
+            body.push_str("<p>None.</p>\n");
+        } else {
+            body.push_str("<table>\n<tr><th>Type</th><th>Value</th><th>Source Plugin</th></tr>\n");
+            for (rtype, value, plugin, implied) in records {
+                let value = match rtype.as_str() {
+                    "CNAME" | "A" | "AAAA" | "PTR" | "NAT" => {
+                        link(DOC_ROOT, &dns_path(value), value)
+                    }
+                    _ => escape(value),
+                };
+                let rtype = if implied {
+                    format!("{} (implied)", escape(rtype))
+                } else {
+                    escape(rtype)
+                };
+                let _ = writeln!(
+                    body,
+                    "<tr><td>{rtype}</td><td>{value}</td><td>{}</td></tr>",
+                    escape(plugin)
+                );
+            }
+            body.push_str("</table>\n");
+        }
+
+        let translations = dns.get_translations(qname);
+        if !translations.is_empty() {
+            body.push_str("<h2>Network Translations</h2>\n<ul>\n");
+            for dest in translations.into_iter().sorted() {
+                let _ = writeln!(body, "<li>{}</li>", link(DOC_ROOT, &dns_path(dest), dest));
+            }
+            body.push_str("</ul>\n");
+        }
+
+        let pdata = self.con.get_dns_pdata(qname).await?;
+        body.push_str(&self.pdata(pdata).await?);
+
+        Ok(page(self.site_title, name, DOC_ROOT, &body))
+    }
+
+    /// Renders the page for a processed node, returning the name of the node and the page.
+    pub async fn node_page(&mut self, link_id: &str) -> NetdoxResult<(String, String)> {
+        let node = self.con.get_node(link_id).await?;
+        let page = self.node_content(&node).await?;
+        Ok((node.name, page))
+    }
+
+    /// Renders the page for a processed node that has already been read.
+    pub async fn node_content(&mut self, node: &Node) -> NetdoxResult<String> {
+        // The role is displayed with the details instead of the metadata.
+        let mut metadata = self.con.get_node_metadata(node).await?;
+        let role = metadata.remove(ROLE_META_KEY);
+
+        let mut details = vec![
+            ("Name".to_string(), escape(&node.name)),
+            ("Link ID".to_string(), escape(&node.link_id)),
+        ];
+        if let Some(role) = role {
+            details.push(("Role".to_string(), escape(&role)));
+        }
+        for name in node.alt_names.iter().sorted() {
+            details.push(("Alt Name".to_string(), escape(name)));
+        }
+        for plugin in node.plugins.iter().sorted() {
+            details.push(("Plugin".to_string(), escape(plugin)));
+        }
+        let (status, pdata): (Vec<_>, Vec<_>) = self
+            .con
+            .get_node_pdata(node)
+            .await?
+            .into_iter()
+            .partition(|data| data.status_state().is_some());
+
+        let mut body = String::new();
+        for data in &status {
+            body.push_str(&self.status_banner(data).await?);
+        }
+        body.push_str(&properties(&details));
+
+        let sources = match self.metadata_by_plugin {
+            true => Some(self.con.get_node_metadata_sources(node).await?),
+            false => None,
+        };
+        body.push_str("<h2>Metadata</h2>\n");
+        body.push_str(&self.metadata(metadata, sources).await?);
+
+        let notes = self
+            .con
+            .get_notes(&format!("{PROC_NODES_KEY};{}", node.link_id))
+            .await?;
+        if !notes.is_empty() {
+            body.push_str("<h2>Notes</h2>\n");
+            body.push_str(&self.map(notes).await?);
+        }
+
+        body.push_str("<h2>DNS Names</h2>\n<ul>\n");
+        for qname in node.dns_names.iter().sorted() {
+            let _ = writeln!(body, "<li>{}</li>", link(DOC_ROOT, &dns_path(qname), qname));
+        }
+        body.push_str("</ul>\n");
+
+        let dependencies = self.con.get_node_dependencies(&node.link_id).await?;
+        if !dependencies.is_empty() {
+            body.push_str("<h2>Dependencies</h2>\n<ul>\n");
+            for dep in &dependencies {
+                let other = dep.other(&node.link_id);
+                let name = self.con.get_node(other).await?.name;
+                let relation = if dep.source == node.link_id {
+                    "Depends on"
+                } else {
+                    "Required by"
+                };
+                let label = match &dep.label {
+                    Some(label) => format!(" ({})", escape(label)),
+                    None => String::new(),
+                };
+                let _ = writeln!(
+                    body,
+                    "<li>{relation} {}{label}</li>",
+                    link(DOC_ROOT, &node_path(other), &name)
+                );
+            }
+            body.push_str("</ul>\n");
+        }
+
+        let relations = self.con.get_node_relations(&node.link_id).await?;
+        if !relations.is_empty() {
+            body.push_str("<h2>Relations</h2>\n<ul>\n");
+            for relation in &relations {
+                let other = relation.other(&node.link_id);
+                let name = self.con.get_node(other).await?.name;
+                let direction = if relation.source == node.link_id {
+                    ""
+                } else {
+                    " (from)"
+                };
+                let _ = writeln!(
+                    body,
+                    "<li>{}{direction} {}</li>",
+                    escape(&relation.kind),
+                    link(DOC_ROOT, &node_path(other), &name)
+                );
+            }
+            body.push_str("</ul>\n");
+        }
+
+        let services = self.con.get_node_services(&node.link_id).await?;
+        if !services.is_empty() {
+            body.push_str("<h2>Services</h2>\n<ul>\n");
+            for service in &services {
+                let details = service
+                    .details
+                    .iter()
+                    .sorted()
+                    .map(|(key, value)| format!("{}: {}", escape(key), escape(value)))
+                    .join(", ");
+                let _ = if details.is_empty() {
+                    writeln!(body, "<li>{}</li>", service.id())
+                } else {
+                    writeln!(body, "<li>{} ({details})</li>", service.id())
+                };
+            }
+            body.push_str("</ul>\n");
+        }
+
+        body.push_str(&self.pdata(pdata).await?);
+
+        Ok(page(self.site_title, &node.name, DOC_ROOT, &body))
+    }
+
+    /// Renders the page for a report, returning the title of the report and the page.
+    pub async fn report_page(&mut self, id: &str) -> NetdoxResult<(String, String)> {
+        let report = self.con.get_report(id).await?;
+
+        let mut body = properties(&[
+            ("Report ID".to_string(), escape(&report.id)),
+            ("Plugin".to_string(), escape(&report.plugin)),
+        ]);
+        for data in &report.content {
+            body.push_str(&self.data(data).await?);
+        }
+
+        let page = page(self.site_title, &report.title, DOC_ROOT, &body);
+        Ok((report.title, page))
+    }
+}
+
+/// Renders the index page, which links to every other page.
+/// Takes qualified DNS names, pairs of node link ID and name, and pairs of report ID and title.
+pub fn index_page(
+    site_title: &str,
+    qnames: &[String],
+    nodes: &[(String, String)],
+    reports: &[(String, String)],
+    generated: &str,
+) -> String {
+    let mut body = format!("<p>Generated {}.</p>\n", escape(generated));
+
+    body.push_str("<h2>Reports</h2>\n<ul>\n");
+    for (id, title) in reports {
+        let _ = writeln!(body, "<li>{}</li>", link("", &report_path(id), title));
+    }
+    body.push_str("</ul>\n");
+
+    body.push_str("<h2>Nodes</h2>\n<ul>\n");
+    for (link_id, name) in nodes {
+        let _ = writeln!(body, "<li>{}</li>", link("", &node_path(link_id), name));
+    }
+    body.push_str("</ul>\n");
+
+    body.push_str("<h2>DNS Names</h2>\n");
+    let by_network = qnames
+        .iter()
+        .filter_map(|qname| split_qname(qname).map(|(network, name)| (network, name, qname)))
+        .into_group_map_by(|(network, _, _)| *network);
+    for (network, names) in by_network.into_iter().sorted() {
+        let _ = writeln!(body, "<h3>{}</h3>\n<ul>", escape(network));
+        // Addresses are grouped by their reverse zone after the hostnames.
+        let (addresses, hostnames): (Vec<_>, Vec<_>) = names
+            .into_iter()
+            .partition(|(_, name, _)| name.parse::<IpAddr>().is_ok());
+        for (_, name, qname) in hostnames {
+            let _ = writeln!(body, "<li>{}</li>", link("", &dns_path(qname), name));
+        }
+        body.push_str("</ul>\n");
+
+        let by_zone = addresses
+            .into_iter()
+            .filter_map(|(_, name, qname)| Some((name.parse::<IpAddr>().ok()?, name, qname)))
+            .into_group_map_by(|(addr, _, _)| address_subnet(addr));
+        for (subnet, addresses) in by_zone.into_iter().sorted() {
+            let _ = writeln!(
+                body,
+                "<h4>{} ({})</h4>\n<ul>",
+                escape(&reverse_zone(&subnet.network())),
+                escape(&subnet.to_string())
+            );
+            for (_, name, qname) in addresses.into_iter().sorted() {
+                let _ = writeln!(body, "<li>{}</li>", link("", &dns_path(qname), name));
+            }
+            body.push_str("</ul>\n");
+        }
+    }
+
+    page(site_title, site_title, "", &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dns_path, escape, file_name, index_page};
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("[net]a.com"), "_5Bnet_5Da.com.html");
+        assert_eq!(file_name("a_b"), "a_5Fb.html");
+        assert_ne!(file_name("a b"), file_name("a_b"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            escape("<a href=\"x\">'&'</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn test_index_page() {
+        let index = index_page(
+            "Netdox",
+            &[
+                "[net]a.com".to_string(),
+                "[net]10.0.0.9".to_string(),
+                "[net]10.0.0.10".to_string(),
+                "[other]b.com".to_string(),
+            ],
+            &[("node-1".to_string(), "Server <1>".to_string())],
+            &[],
+            "2024-01-01 00:00:00",
+        );
+
+        assert!(index.contains(&format!("<a href=\"{}\">a.com</a>", dns_path("[net]a.com"))));
+        assert!(index.contains("<a href=\"nodes/node-1.html\">Server &lt;1&gt;</a>"));
+        assert!(index.find("<h3>net</h3>") < index.find("<h3>other</h3>"));
+        assert!(index.contains("<h4>0.0.10.in-addr.arpa (10.0.0.0/24)</h4>"));
+        assert!(index.find(">10.0.0.9<") < index.find(">10.0.0.10<"));
+    }
+}
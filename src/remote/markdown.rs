@@ -0,0 +1,697 @@
+mod render;
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    pin::pin,
+};
+
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use ipnet::Ipv4Net;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use zip::{write::FileOptions, ZipWriter};
+
+use crate::{
+    config::RemoteConfig,
+    config_err,
+    data::{
+        model::{
+            coalesce_batches, Change, ChangelogEntry, ObjectID, RunLock, DNS_KEY, NODES_KEY,
+            PROC_NODES_KEY, REPORTS_KEY,
+        },
+        stream::{node_batches, BATCH_SIZE},
+        DataConn, DataStore,
+    },
+    error::{NetdoxError, NetdoxResult},
+    io_err,
+    progress::Progress,
+    remote::{
+        acquire_file_lock, check_cursor, orphaned_files, release_file_lock, remove_files,
+        swap_cursor, ObjectSelection, PublishPlan, RemoteInterface,
+    },
+    runs::RunTimer,
+    success,
+};
+use render::{
+    dns_path, index_note, node_path, object_path, report_path, Renderer, DNS_DIR, INDEX_FILE,
+    NODE_DIR, REPORT_DIR,
+};
+
+/// Directory in the vault for the state of the remote. Obsidian does not display hidden directories.
+const STATE_DIR: &str = ".netdox";
+/// File in the state directory that holds the ID of the last change published to the vault.
+const LAST_CHANGE_FILE: &str = "last-change";
+
+fn default_title() -> String {
+    "Netdox".to_string()
+}
+
+/// Remote that writes documents as Markdown files with wiki-style links,
+/// which can be opened as an Obsidian vault or built into a site with MkDocs.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MarkdownRemote {
+    /// Directory to write the files to.
+    pub vault_dir: PathBuf,
+    /// Title of the index note.
+    #[serde(default = "default_title")]
+    pub title: String,
+    /// Maps subnets to locations, like the config document of other remotes.
+    #[serde(default)]
+    pub locations: HashMap<String, String>,
+    /// DNS names to exclude from all networks.
+    #[serde(default)]
+    pub exclusions: HashSet<String>,
+    /// Whether to render the metadata set by each plugin under its own heading.
+    #[serde(default)]
+    pub metadata_by_plugin: bool,
+}
+
+/// The notes that some changes affect.
+#[derive(Debug, Default, PartialEq)]
+struct Targets {
+    /// Every note must be written again.
+    all: bool,
+    /// The index must be written again, because an object was created.
+    index: bool,
+    /// Qualified DNS names.
+    dns: BTreeSet<String>,
+    /// Link IDs of processed nodes.
+    nodes: BTreeSet<String>,
+    /// Raw node IDs, whose processed nodes are looked up when rendering.
+    raw_nodes: BTreeSet<String>,
+    /// Report IDs.
+    reports: BTreeSet<String>,
+}
+
+impl Targets {
+    /// Returns the notes affected by some changes.
+    fn from_changes(changes: &[Change]) -> Self {
+        let mut targets = Targets::default();
+        for change in changes {
+            targets.add(change);
+        }
+        targets
+    }
+
+    fn add(&mut self, change: &Change) {
+        use Change as CT;
+        match change {
+            // Network mappings change how names are qualified, so anything may link elsewhere.
+            CT::Init | CT::UpdatedNetworkMapping { .. } => self.all = true,
+            CT::CreateDnsName { qname, .. } => {
+                self.index = true;
+                self.dns.insert(qname.clone());
+            }
+            CT::CreateDnsRecord { record, .. } | CT::DeleteDnsRecord { record, .. } => {
+                self.dns.insert(record.name.clone());
+                if let Some(implied) = record.implies() {
+                    self.dns.insert(implied.name);
+                }
+            }
+            CT::DeleteDnsName { qname, .. } => {
+                self.index = true;
+                self.dns.insert(qname.clone());
+            }
+            CT::CreatePluginNode { node_id, .. } | CT::DeletePluginNode { node_id, .. } => {
+                self.index = true;
+                self.raw_nodes.insert(node_id.clone());
+            }
+            CT::DeleteProcNode { link_id, .. } => {
+                self.index = true;
+                self.nodes.insert(link_id.clone());
+            }
+            CT::CreateReport { report_id, .. } => {
+                self.index = true;
+                self.reports.insert(report_id.clone());
+            }
+            CT::CreatedData { obj_id, .. }
+            | CT::UpdatedData { obj_id, .. }
+            | CT::DeletedData { obj_id, .. }
+            | CT::UpdatedMetadata { obj_id, .. }
+            | CT::UpdatedNotes { obj_id, .. }
+            | CT::UpdatedTags { obj_id, .. } => self.add_object(obj_id),
+            // Dependencies and relations are displayed on the nodes at both ends.
+            CT::UpdatedDependency { source, dest, .. }
+            | CT::UpdatedRelation { source, dest, .. } => {
+                self.nodes.insert(source.clone());
+                self.nodes.insert(dest.clone());
+            }
+            CT::UpdatedService { link_id, .. } => {
+                self.nodes.insert(link_id.clone());
+            }
+            CT::BeginBatch { .. } | CT::EndBatch { .. } => {}
+        }
+    }
+
+    fn add_object(&mut self, obj_id: &str) {
+        match obj_id.split_once(';') {
+            Some((DNS_KEY, qname)) => {
+                self.dns.insert(qname.to_string());
+            }
+            Some((NODES_KEY, raw_id)) => {
+                self.raw_nodes.insert(raw_id.to_string());
+            }
+            Some((PROC_NODES_KEY, link_id)) => {
+                self.nodes.insert(link_id.to_string());
+            }
+            Some((REPORTS_KEY, id)) => {
+                self.reports.insert(id.to_string());
+            }
+            _ => warn!("Ignoring change to object with invalid ID: {obj_id}"),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        !self.all
+            && !self.index
+            && self.dns.is_empty()
+            && self.nodes.is_empty()
+            && self.raw_nodes.is_empty()
+            && self.reports.is_empty()
+    }
+}
+
+impl MarkdownRemote {
+    /// Identifies this vault in the data store.
+    fn cursor_id(&self) -> String {
+        format!("markdown;{}", self.vault_dir.display())
+    }
+
+    fn last_change_path(&self) -> PathBuf {
+        self.vault_dir.join(STATE_DIR).join(LAST_CHANGE_FILE)
+    }
+
+    /// Returns the ID of the last change published to the vault, if any.
+    fn get_last_change(&self) -> NetdoxResult<Option<String>> {
+        let path = self.last_change_path();
+        match fs::read_to_string(&path) {
+            Ok(id) if id.trim().is_empty() => Ok(None),
+            Ok(id) => Ok(Some(id.trim().to_string())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => io_err!(format!("Failed to read {}: {err}", path.display())),
+        }
+    }
+
+    fn set_last_change(&self, id: &str) -> NetdoxResult<()> {
+        let path = self.last_change_path();
+        if let Err(err) = fs::create_dir_all(self.vault_dir.join(STATE_DIR)) {
+            return io_err!(format!("Failed to create state directory in vault: {err}"));
+        }
+        if let Err(err) = fs::write(&path, id) {
+            return io_err!(format!("Failed to write {}: {err}", path.display()));
+        }
+        Ok(())
+    }
+
+    /// Renders the notes affected by some changes,
+    /// returning pairs of path relative to the vault directory and content.
+    async fn render(
+        &self,
+        con: &mut DataStore,
+        targets: &Targets,
+    ) -> NetdoxResult<Vec<(String, String)>> {
+        let all_qnames = con.get_dns_names().await?;
+        let all_node_ids = con.get_node_ids().await?;
+        let all_report_ids = con.get_report_ids().await?;
+
+        let (qnames, node_ids, report_ids) = if targets.all {
+            (
+                all_qnames.iter().cloned().sorted().collect_vec(),
+                all_node_ids.iter().cloned().sorted().collect_vec(),
+                all_report_ids.iter().cloned().sorted().collect_vec(),
+            )
+        } else {
+            let mut node_ids = targets.nodes.clone();
+            for raw_id in &targets.raw_nodes {
+                match con.get_node_from_raw(raw_id).await? {
+                    Some(link_id) => {
+                        node_ids.insert(link_id);
+                    }
+                    None => debug!("Skipping raw node with no processed node: {raw_id}"),
+                }
+            }
+
+            // Objects may have been removed since they were changed.
+            (
+                targets
+                    .dns
+                    .iter()
+                    .filter(|qname| all_qnames.contains(*qname))
+                    .cloned()
+                    .collect_vec(),
+                node_ids
+                    .into_iter()
+                    .filter(|id| all_node_ids.contains(id))
+                    .collect_vec(),
+                targets
+                    .reports
+                    .iter()
+                    .filter(|id| all_report_ids.contains(*id))
+                    .cloned()
+                    .collect_vec(),
+            )
+        };
+
+        let mut notes = Vec::with_capacity(qnames.len() + node_ids.len() + report_ids.len() + 1);
+        let mut progress = Progress::new(
+            "Rendered notes",
+            qnames.len() + node_ids.len() + report_ids.len(),
+        );
+        let mut node_batches = pin!(node_batches(con.clone(), BATCH_SIZE));
+        let mut renderer = Renderer::new(con, self.metadata_by_plugin).await?;
+
+        for qname in &qnames {
+            notes.push((dns_path(qname), renderer.dns_note(qname).await?));
+            progress.tick();
+        }
+
+        let mut nodes = HashMap::new();
+        if targets.all {
+            let mut node_notes = Vec::with_capacity(node_ids.len());
+            while let Some(batch) = node_batches.try_next().await? {
+                for node in batch {
+                    let note = renderer.node_content(&node).await?;
+                    node_notes.push((node_path(&node.link_id), note));
+                    nodes.insert(node.link_id, node.name);
+                    progress.tick();
+                }
+            }
+            node_notes.sort();
+            notes.extend(node_notes);
+        } else {
+            for id in &node_ids {
+                let (name, note) = renderer.node_note(id).await?;
+                notes.push((node_path(id), note));
+                nodes.insert(id.clone(), name);
+                progress.tick();
+            }
+        }
+
+        let mut reports = HashMap::new();
+        for id in &report_ids {
+            let (title, note) = renderer.report_note(id).await?;
+            notes.push((report_path(id), note));
+            reports.insert(id.clone(), title);
+            progress.tick();
+        }
+
+        progress.finish();
+
+        if targets.all || targets.index {
+            // The index needs the name of every node and report, not just those that changed.
+            for id in &all_node_ids {
+                if !nodes.contains_key(id) {
+                    nodes.insert(id.clone(), con.get_node(id).await?.name);
+                }
+            }
+            for id in &all_report_ids {
+                if !reports.contains_key(id) {
+                    reports.insert(id.clone(), con.get_report(id).await?.title);
+                }
+            }
+
+            let qnames = all_qnames.into_iter().sorted().collect_vec();
+            let nodes = nodes
+                .into_iter()
+                .sorted_by(|lhs, rhs| lhs.1.cmp(&rhs.1))
+                .collect_vec();
+            let reports = reports.into_iter().sorted().collect_vec();
+            notes.push((
+                INDEX_FILE.to_string(),
+                index_note(&self.title, &qnames, &nodes, &reports),
+            ));
+        }
+
+        Ok(notes)
+    }
+
+    /// Returns the paths of the notes of targeted DNS names and nodes that no longer exist.
+    async fn removed_paths(
+        &self,
+        con: &mut DataStore,
+        targets: &Targets,
+    ) -> NetdoxResult<Vec<String>> {
+        if targets.all {
+            return Ok(vec![]);
+        }
+
+        let all_qnames = con.get_dns_names().await?;
+        let all_node_ids = con.get_node_ids().await?;
+        Ok(targets
+            .dns
+            .iter()
+            .filter(|qname| !all_qnames.contains(*qname))
+            .map(|qname| dns_path(qname))
+            .chain(
+                targets
+                    .nodes
+                    .iter()
+                    .filter(|id| !all_node_ids.contains(*id))
+                    .map(|id| node_path(id)),
+            )
+            .collect())
+    }
+
+    /// Writes notes to the vault directory.
+    /// If the notes are a full publish, any notes from previous publishes are removed.
+    fn write(&self, notes: &[(String, String)], full: bool) -> NetdoxResult<()> {
+        for dir in [DNS_DIR, NODE_DIR, REPORT_DIR] {
+            let path = self.vault_dir.join(dir);
+            if full && path.exists() {
+                if let Err(err) = fs::remove_dir_all(&path) {
+                    return io_err!(format!(
+                        "Failed to remove old notes in {}: {err}",
+                        path.display()
+                    ));
+                }
+            }
+            if let Err(err) = fs::create_dir_all(&path) {
+                return io_err!(format!("Failed to create {}: {err}", path.display()));
+            }
+        }
+
+        for (rel_path, content) in notes {
+            let path = self.vault_dir.join(rel_path);
+            if let Err(err) = fs::write(&path, content) {
+                return io_err!(format!("Failed to write note {}: {err}", path.display()));
+            }
+        }
+
+        debug!(
+            "Wrote {} notes to {}",
+            notes.len(),
+            self.vault_dir.display()
+        );
+        Ok(())
+    }
+
+    /// Writes the notes affected by some changes to the vault, and then advances the cursor
+    /// if no other publish has advanced it since this one started.
+    /// If there is no cursor every note is written.
+    async fn apply_changes(
+        &self,
+        mut con: DataStore,
+        changes: &[ChangelogEntry],
+        last_change: Option<&str>,
+        backup: Option<PathBuf>,
+        timer: &mut RunTimer,
+    ) -> NetdoxResult<()> {
+        let recorded = con.get_remote_cursor(&self.cursor_id()).await?;
+        let mut targets = Targets::from_changes(&coalesce_batches(changes));
+        targets.all |= last_change.is_none();
+        debug!("Applying {} changelog entries to the vault.", changes.len());
+
+        if targets.is_empty() {
+            debug!("No notes are affected by the changes.");
+        } else {
+            let notes = self.render(&mut con, &targets).await;
+            timer.phase("prepare-changes", notes.is_ok());
+            let notes = notes?;
+
+            let removed = self.removed_paths(&mut con, &targets).await?;
+            let written = self
+                .test()
+                .await
+                .and_then(|()| self.write(&notes, targets.all))
+                .and_then(|()| remove_files(&self.vault_dir, removed));
+            timer.phase("apply-changes", written.is_ok());
+            let removed = written?;
+            if removed > 0 {
+                debug!("Removed {removed} notes of objects that no longer exist.");
+            }
+
+            if let Some(path) = backup {
+                write_backup(&path, &notes)?;
+            }
+        }
+
+        let new_change = match changes.last() {
+            Some(change) => change.id.clone(),
+            None if last_change.is_none() => con.last_change_id().await?,
+            None => return Ok(()),
+        };
+        // A full publish of an empty changelog has no change to record.
+        if new_change.is_empty() {
+            return Ok(());
+        }
+
+        // Another publish may have advanced the cursor while this one was running.
+        swap_cursor(
+            &mut con,
+            &self.cursor_id(),
+            recorded.as_deref(),
+            &new_change,
+        )
+        .await?;
+        let current = self.get_last_change()?;
+        check_cursor(last_change, current.as_deref(), &new_change)?;
+        self.set_last_change(&new_change)?;
+
+        success!("Updated vault to change ID {new_change}");
+        timer.phase("update-changelog", true);
+
+        Ok(())
+    }
+}
+
+/// Writes a zip of the notes to the path.
+fn write_backup(path: &Path, notes: &[(String, String)]) -> NetdoxResult<()> {
+    let file = match File::create(path) {
+        Ok(file) => file,
+        Err(err) => return io_err!(format!("Failed to create backup {}: {err}", path.display())),
+    };
+
+    let mut zip = ZipWriter::new(file);
+    for (rel_path, content) in notes {
+        if let Err(err) = zip.start_file(rel_path, FileOptions::default()) {
+            return io_err!(format!("Failed to start {rel_path} in backup zip: {err}"));
+        }
+        if let Err(err) = zip.write_all(content.as_bytes()) {
+            return io_err!(format!("Failed to write {rel_path} to backup zip: {err}"));
+        }
+    }
+
+    match zip.finish() {
+        Ok(_) => Ok(()),
+        Err(err) => io_err!(format!("Failed to finish backup zip: {err}")),
+    }
+}
+
+#[async_trait]
+impl RemoteInterface for MarkdownRemote {
+    async fn test(&self) -> NetdoxResult<()> {
+        if let Err(err) = fs::create_dir_all(&self.vault_dir) {
+            return io_err!(format!(
+                "Failed to create vault directory {}: {err}",
+                self.vault_dir.display()
+            ));
+        }
+
+        match fs::metadata(&self.vault_dir) {
+            Ok(meta) if meta.permissions().readonly() => io_err!(format!(
+                "Vault directory {} is read only.",
+                self.vault_dir.display()
+            )),
+            Ok(_) => Ok(()),
+            Err(err) => io_err!(format!(
+                "Failed to read vault directory {}: {err}",
+                self.vault_dir.display()
+            )),
+        }
+    }
+
+    async fn config(&self) -> NetdoxResult<RemoteConfig> {
+        let mut locations = HashMap::new();
+        for (subnet, location) in &self.locations {
+            match subnet.parse::<Ipv4Net>() {
+                Ok(subnet) => {
+                    locations.insert(subnet, location.clone());
+                }
+                Err(err) => {
+                    return config_err!(format!(
+                        "Invalid subnet for location {location}: {subnet} ({err})"
+                    ))
+                }
+            }
+        }
+
+        Ok(RemoteConfig {
+            exclusions: self.exclusions.clone(),
+            locations,
+            metadata: HashMap::new(),
+        })
+    }
+
+    /// Vaults have no labels.
+    async fn labeled(&self, _: &str) -> NetdoxResult<Vec<ObjectID>> {
+        Ok(vec![])
+    }
+
+    async fn publish(
+        &self,
+        mut con: DataStore,
+        backup: Option<PathBuf>,
+        timer: &mut RunTimer,
+    ) -> NetdoxResult<()> {
+        let last_change = self.get_last_change()?;
+        debug!(
+            "Last change published to the vault: {}",
+            last_change.as_deref().unwrap_or("none")
+        );
+        let changes = con.get_changes(last_change.as_deref()).await?;
+        timer.phase("fetch-changes", true);
+        self.apply_changes(con, &changes, last_change.as_deref(), backup, timer)
+            .await
+    }
+
+    async fn plan(&self, mut con: DataStore) -> NetdoxResult<PublishPlan> {
+        let last_change = self.get_last_change()?;
+        let changes = con.get_changes(last_change.as_deref()).await?;
+        let mut targets = Targets::from_changes(&coalesce_batches(&changes));
+        targets.all |= last_change.is_none();
+
+        let documents = if targets.is_empty() {
+            vec![]
+        } else {
+            self.render(&mut con, &targets)
+                .await?
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect()
+        };
+
+        Ok(PublishPlan {
+            last_change,
+            changes: changes.len(),
+            documents,
+            fragments: vec![],
+        })
+    }
+
+    /// The index is not written again, so it may show stale names until the next full publish.
+    async fn publish_objects(
+        &self,
+        mut con: DataStore,
+        objects: &ObjectSelection,
+        backup: Option<PathBuf>,
+        timer: &mut RunTimer,
+    ) -> NetdoxResult<()> {
+        let targets = Targets {
+            dns: objects.dns.iter().cloned().collect(),
+            nodes: objects.nodes.iter().cloned().collect(),
+            reports: objects.reports.iter().cloned().collect(),
+            ..Targets::default()
+        };
+
+        let notes = self.render(&mut con, &targets).await;
+        timer.phase("render", notes.is_ok());
+        let notes = notes?;
+
+        let written = self.test().await.and_then(|()| self.write(&notes, false));
+        timer.phase("write", written.is_ok());
+        written?;
+
+        if let Some(path) = backup {
+            write_backup(&path, &notes)?;
+        }
+
+        success!("Wrote {} notes to the vault.", notes.len());
+        Ok(())
+    }
+
+    /// Pages of moved DNS names are removed, as a full publish would.
+    async fn archive_moved_dns(&self, moved: &[(String, String)]) -> NetdoxResult<usize> {
+        remove_files(&self.vault_dir, moved.iter().map(|(old, _)| dns_path(old)))
+    }
+
+    /// Notes of deleted DNS names are removed, as a full publish would.
+    async fn archive_deleted_dns(&self, qnames: &[String]) -> NetdoxResult<usize> {
+        remove_files(&self.vault_dir, qnames.iter().map(|qname| dns_path(qname)))
+    }
+
+    /// Paths are relative to the root of the vault.
+    fn document_location(&self, obj_id: &str) -> Option<String> {
+        object_path(obj_id)
+    }
+
+    /// Orphaned notes are removed.
+    async fn reconcile(&self, mut con: DataStore, dry_run: bool) -> NetdoxResult<Vec<String>> {
+        let orphans = orphaned_files(
+            &mut con,
+            &self.vault_dir,
+            &[DNS_DIR, NODE_DIR, REPORT_DIR],
+            object_path,
+        )
+        .await?;
+        if !dry_run {
+            remove_files(&self.vault_dir, orphans.clone())?;
+        }
+        Ok(orphans)
+    }
+
+    /// The lock is a file in the state directory of the output.
+    async fn acquire_publish_lock(&self, lock: &RunLock) -> NetdoxResult<Option<RunLock>> {
+        acquire_file_lock(&self.vault_dir, lock)
+    }
+
+    async fn release_publish_lock(&self, owner: Option<&str>) -> NetdoxResult<Option<RunLock>> {
+        release_file_lock(&self.vault_dir, owner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::Targets;
+    use crate::data::model::{Change, DNSRecord, DataKind};
+
+    #[test]
+    fn test_targets() {
+        let plugin = "plugin".to_string();
+        let targets = Targets::from_changes(&[
+            Change::CreateDnsRecord {
+                plugin: plugin.clone(),
+                record: DNSRecord {
+                    name: "[net]a.com".to_string(),
+                    value: "[net]10.0.0.1".to_string(),
+                    rtype: "A".to_string(),
+                    plugin: plugin.clone(),
+                },
+            },
+            Change::UpdatedData {
+                plugin: plugin.clone(),
+                obj_id: "nodes;[net]a.com".to_string(),
+                data_id: "data".to_string(),
+                kind: DataKind::Plugin,
+            },
+            Change::UpdatedNotes {
+                plugin: plugin.clone(),
+                obj_id: "proc_nodes;node-1".to_string(),
+            },
+        ]);
+
+        assert_eq!(
+            targets,
+            Targets {
+                dns: BTreeSet::from(["[net]a.com".to_string(), "[net]10.0.0.1".to_string()]),
+                nodes: BTreeSet::from(["node-1".to_string()]),
+                raw_nodes: BTreeSet::from(["[net]a.com".to_string()]),
+                ..Default::default()
+            }
+        );
+
+        let created = Targets::from_changes(&[Change::CreateReport {
+            plugin: plugin.clone(),
+            report_id: "report".to_string(),
+        }]);
+        assert!(created.index && !created.all);
+        assert!(Targets::from_changes(&[Change::Init]).all);
+        assert!(Targets::from_changes(&[]).is_empty());
+    }
+}
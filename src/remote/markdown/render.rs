@@ -0,0 +1,676 @@
+use std::{collections::HashMap, fmt::Write as _, net::IpAddr};
+
+use itertools::Itertools;
+
+use crate::{
+    data::{
+        model::{
+            address_subnet, group_metadata, ptr_name, qname_address, reverse_zone, split_qname,
+            status_colour, Data, Node, PluginDisplay, StringType, DNS_KEY, NODE_META_KEY,
+            PROC_NODES_KEY, REPORTS_KEY, ROLE_META_KEY, STATUS_STATE_FIELD,
+        },
+        DataConn, DataStore,
+    },
+    error::{NetdoxError, NetdoxResult},
+    query::links::{resolve_link, split_links, TextPart},
+    redis_err,
+    remote::file_stem,
+};
+
+pub const DNS_DIR: &str = "dns";
+pub const NODE_DIR: &str = "nodes";
+pub const REPORT_DIR: &str = "reports";
+pub const INDEX_FILE: &str = "index.md";
+
+/// Characters that are escaped in text so they are not read as markdown.
+const SPECIAL_CHARS: [char; 10] = ['\\', '`', '*', '_', '[', ']', '<', '>', '#', '|'];
+
+/// Escapes text so it is displayed as is in markdown.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if SPECIAL_CHARS.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Path of the file for a DNS name, relative to the root of the vault.
+pub fn dns_path(qname: &str) -> String {
+    format!("{DNS_DIR}/{}.md", file_stem(qname))
+}
+
+/// Path of the file for a processed node, relative to the root of the vault.
+pub fn node_path(link_id: &str) -> String {
+    format!("{NODE_DIR}/{}.md", file_stem(link_id))
+}
+
+/// Path of the file for a report, relative to the root of the vault.
+pub fn report_path(id: &str) -> String {
+    format!("{REPORT_DIR}/{}.md", file_stem(id))
+}
+
+/// Path of the file for an object ID, if it has one.
+pub fn object_path(obj_id: &str) -> Option<String> {
+    match obj_id.split_once(';')? {
+        (DNS_KEY, qname) => Some(dns_path(qname)),
+        (PROC_NODES_KEY, link_id) => Some(node_path(link_id)),
+        (REPORTS_KEY, id) => Some(report_path(id)),
+        _ => None,
+    }
+}
+
+/// Returns a wiki-style link to a file, displayed as the text.
+/// Links are relative to the root of the vault, and anything that would end the link is removed from the text.
+pub fn wiki_link(path: &str, text: &str) -> String {
+    let text = text.replace('|', "").replace("[[", "[").replace("]]", "]");
+    format!(
+        "[[{}|{}]]",
+        path.trim_end_matches(".md"),
+        text.trim_end_matches(']')
+    )
+}
+
+/// Returns a note with front matter identifying the object it represents.
+fn note(obj_id: &str, title: &str, body: &str) -> String {
+    format!(
+        "---\nnetdox-id: \"{}\"\n---\n\n# {}\n\n{body}",
+        obj_id.replace('\\', "\\\\").replace('"', "\\\""),
+        escape(title)
+    )
+}
+
+/// Renders pairs of a title and some markdown as a list.
+fn properties(rows: &[(String, String)]) -> String {
+    if rows.is_empty() {
+        return "None.\n".to_string();
+    }
+
+    let mut list = String::new();
+    for (title, value) in rows {
+        let _ = writeln!(list, "- **{}:** {value}", escape(title));
+    }
+    list
+}
+
+/// Renders rows of markdown as a table.
+/// Markdown tables must have a header, so the first row is used.
+fn table(columns: usize, cells: &[String]) -> String {
+    let columns = columns.max(1);
+    let mut rows = cells.chunks(columns);
+    let Some(header) = rows.next() else {
+        return "None.\n".to_string();
+    };
+
+    let mut table = String::new();
+    let _ = writeln!(table, "{}", table_row(header));
+    let _ = writeln!(table, "|{}", " --- |".repeat(columns));
+    for row in rows {
+        let _ = writeln!(table, "{}", table_row(row));
+    }
+    table
+}
+
+/// Renders a row of a table, escaping the pipes in each cell so they don't end it.
+fn table_row(cells: &[String]) -> String {
+    let mut row = String::from("|");
+    for cell in cells {
+        let _ = write!(
+            row,
+            " {} |",
+            cell.replace("\\|", "|")
+                .replace('|', "\\|")
+                .replace('\n', "<br>")
+        );
+    }
+    row
+}
+
+/// Renders the notes of a vault from the data store.
+pub struct Renderer<'a> {
+    con: &'a mut DataStore,
+    displays: HashMap<String, PluginDisplay>,
+    /// Whether to render the metadata set by each plugin under its own heading.
+    metadata_by_plugin: bool,
+}
+
+impl<'a> Renderer<'a> {
+    pub async fn new(con: &'a mut DataStore, metadata_by_plugin: bool) -> NetdoxResult<Self> {
+        let displays = con.get_plugin_displays().await?;
+        Ok(Renderer {
+            con,
+            displays,
+            metadata_by_plugin,
+        })
+    }
+
+    /// Renders some text as markdown, replacing links with wiki links to their notes.
+    async fn text(&mut self, text: &str) -> NetdoxResult<String> {
+        let mut markdown = String::new();
+        for part in split_links(text) {
+            match part {
+                TextPart::Text(text) => markdown.push_str(&escape(text)),
+                TextPart::Link { kind, id } => {
+                    match resolve_link(self.con, kind, id)
+                        .await?
+                        .as_deref()
+                        .and_then(object_path)
+                    {
+                        Some(path) => markdown.push_str(&wiki_link(&path, id)),
+                        None if kind == "external" => {
+                            let _ = write!(markdown, "<{id}>");
+                        }
+                        None => markdown.push_str(&escape(id)),
+                    }
+                }
+            }
+        }
+        Ok(markdown)
+    }
+
+    /// Renders a map of metadata or notes, sorted by key.
+    async fn map(&mut self, map: HashMap<String, String>) -> NetdoxResult<String> {
+        let mut rows = vec![];
+        for (key, value) in map.into_iter().sorted() {
+            rows.push((key, self.text(&value).await?));
+        }
+        Ok(properties(&rows))
+    }
+
+    /// Renders metadata, with the keys set by each plugin under its own heading if the sources are given.
+    async fn metadata(
+        &mut self,
+        metadata: HashMap<String, String>,
+        sources: Option<HashMap<String, String>>,
+    ) -> NetdoxResult<String> {
+        let metadata: HashMap<_, _> = metadata
+            .into_iter()
+            .filter(|(key, _)| !key.starts_with('_'))
+            .collect();
+        let groups = match sources {
+            Some(sources) if !metadata.is_empty() => group_metadata(metadata, &sources),
+            _ => return self.map(metadata).await,
+        };
+
+        let mut sections = vec![];
+        for (plugin, metadata) in groups {
+            let list = self.map(metadata).await?;
+            sections.push(match plugin {
+                Some(plugin) => format!("### {}\n\n{list}", escape(&plugin)),
+                None => list,
+            });
+        }
+        Ok(sections.join("\n"))
+    }
+
+    /// Renders a piece of plugin data, applying the display settings of its source plugin.
+    async fn data(&mut self, data: &Data) -> NetdoxResult<String> {
+        let display = self
+            .displays
+            .get(data.plugin())
+            .cloned()
+            .unwrap_or_default();
+        let (title, plugin) = match data {
+            Data::Hash { title, plugin, .. }
+            | Data::List { title, plugin, .. }
+            | Data::String { title, plugin, .. }
+            | Data::Table { title, plugin, .. } => (title, plugin),
+        };
+
+        let mut markdown = format!("### {}\n\n", escape(title));
+        let _ = write!(
+            markdown,
+            "*Source: {}*",
+            escape(display.name.as_deref().unwrap_or(plugin))
+        );
+        if let Some(label) = &display.label {
+            let _ = write!(markdown, " `{}`", label.replace('`', "'"));
+        }
+        markdown.push_str("\n\n");
+
+        match data {
+            Data::Hash { content, .. } => {
+                let mut rows = vec![];
+                for (key, value) in content {
+                    rows.push((key.clone(), self.text(value).await?));
+                }
+                markdown.push_str(&properties(&rows));
+            }
+            Data::List { content, .. } => {
+                let mut rows = vec![];
+                for (_, title, value) in content {
+                    rows.push((title.clone(), self.text(value).await?));
+                }
+                markdown.push_str(&properties(&rows));
+            }
+            Data::String {
+                content_type,
+                content,
+                ..
+            } => match content_type {
+                // Plugins declare this content as markup, so it is included as is.
+                StringType::Markdown | StringType::HtmlMarkup => {
+                    markdown.push_str(content);
+                    markdown.push('\n');
+                }
+                StringType::Plain => {
+                    let _ = writeln!(markdown, "{}", self.text(content).await?);
+                }
+                StringType::Code => {
+                    let _ = writeln!(markdown, "```\n{content}\n```");
+                }
+            },
+            Data::Table {
+                columns, content, ..
+            } => {
+                let mut cells = vec![];
+                for cell in content {
+                    cells.push(self.text(cell).await?);
+                }
+                markdown.push_str(&table(*columns, &cells));
+            }
+        }
+
+        markdown.push('\n');
+        Ok(markdown)
+    }
+
+    async fn pdata(&mut self, pdata: Vec<Data>) -> NetdoxResult<String> {
+        let mut markdown = String::from("## Plugin Data\n\n");
+        if pdata.is_empty() {
+            markdown.push_str("None.\n");
+        }
+        for data in pdata
+            .into_iter()
+            .sorted_by(|lhs, rhs| lhs.id().cmp(rhs.id()))
+        {
+            markdown.push_str(&self.data(&data).await?);
+        }
+        Ok(markdown)
+    }
+
+    /// Renders the status data of a node as a callout, whose type matches the colour of its state.
+    async fn status_callout(&mut self, data: &Data) -> NetdoxResult<String> {
+        let Data::Hash {
+            plugin, content, ..
+        } = data
+        else {
+            return Ok(String::new());
+        };
+        let state = data.status_state().unwrap_or_default();
+        let source = self
+            .displays
+            .get(plugin)
+            .and_then(|display| display.name.clone())
+            .unwrap_or_else(|| plugin.clone());
+        let callout = match status_colour(state) {
+            "green" => "success",
+            "orange" => "warning",
+            "red" => "failure",
+            _ => "note",
+        };
+
+        let mut markdown = format!(
+            "> [!{callout}] Status: {} *from {}*\n",
+            escape(state),
+            escape(&source)
+        );
+        for (key, value) in content {
+            if key != STATUS_STATE_FIELD {
+                let _ = writeln!(
+                    markdown,
+                    "> - **{}:** {}",
+                    escape(key),
+                    self.text(value).await?
+                );
+            }
+        }
+        markdown.push('\n');
+
+        Ok(markdown)
+    }
+
+    /// Renders the note for a DNS name.
+    pub async fn dns_note(&mut self, qname: &str) -> NetdoxResult<String> {
+        let (network, name) = match split_qname(qname) {
+            Some(tuple) => tuple,
+            None => return redis_err!(format!("Failed to parse network from qname: {qname}")),
+        };
+        let address = qname_address(qname);
+        let dns = self.con.get_dns_subset(&[qname]).await?;
+        let mut metadata = self.con.get_dns_metadata(qname).await?;
+        let mut body = String::new();
+
+        // An address displays the node it resolves to first, instead of with the metadata.
+        if address.is_some() {
+            let node = match metadata.remove(NODE_META_KEY) {
+                Some(node) => self.text(&node).await?,
+                None => "None".to_string(),
+            };
+            let _ = writeln!(body, "**Node:** {node}\n");
+        }
+
+        let mut details = vec![
+            ("Name".to_string(), escape(name)),
+            ("Logical Network".to_string(), escape(network)),
+        ];
+        if let Some(addr) = address {
+            details.push(("PTR Name".to_string(), escape(&ptr_name(&addr))));
+            details.push(("Reverse Zone".to_string(), escape(&reverse_zone(&addr))));
+            details.push((
+                "Subnet".to_string(),
+                escape(&address_subnet(&addr).to_string()),
+            ));
+        }
+        body.push_str(&properties(&details));
+
+        let sources = match self.metadata_by_plugin {
+            true => Some(self.con.get_dns_metadata_sources(qname).await?),
+            false => None,
+        };
+        body.push_str("\n## Metadata\n\n");
+        body.push_str(&self.metadata(metadata, sources).await?);
+
+        let notes = self.con.get_notes(&format!("{DNS_KEY};{qname}")).await?;
+        if !notes.is_empty() {
+            body.push_str("\n## Notes\n\n");
+            body.push_str(&self.map(notes).await?);
+        }
+
+        body.push_str("\n## DNS Records\n\n");
+        let mut records = dns
+            .get_records(qname)
+            .into_iter()
+            .map(|record| (&record.rtype, &record.value, &record.plugin, false))
+            .chain(
+                dns.get_implied_records(qname)
+                    .into_iter()
+                    .map(|record| (&record.rtype, &record.value, &record.plugin, true)),
+            )
+            .collect::<Vec<_>>();
+        records.sort();
+        if records.is_empty() {
+            body.push_str("None.\n");
+        } else {
+            let mut cells = vec![
+                "Type".to_string(),
+                "Value".to_string(),
+                "Source Plugin".to_string(),
+            ];
+            for (rtype, value, plugin, implied) in records {
+                cells.push(if implied {
+                    format!("{} (implied)", escape(rtype))
+                } else {
+                    escape(rtype)
+                });
+                cells.push(match rtype.as_str() {
+                    "CNAME" | "A" | "AAAA" | "PTR" | "NAT" => wiki_link(&dns_path(value), value),
+                    _ => escape(value),
+                });
+                cells.push(escape(plugin));
+            }
+            body.push_str(&table(3, &cells));
+        }
+
+        let translations = dns.get_translations(qname);
+        if !translations.is_empty() {
+            body.push_str("\n## Network Translations\n\n");
+            for dest in translations.into_iter().sorted() {
+                let _ = writeln!(body, "- {}", wiki_link(&dns_path(dest), dest));
+            }
+        }
+
+        body.push('\n');
+        let pdata = self.con.get_dns_pdata(qname).await?;
+        body.push_str(&self.pdata(pdata).await?);
+
+        Ok(note(&format!("{DNS_KEY};{qname}"), name, &body))
+    }
+
+    /// Renders the note for a processed node, returning the name of the node and the note.
+    pub async fn node_note(&mut self, link_id: &str) -> NetdoxResult<(String, String)> {
+        let node = self.con.get_node(link_id).await?;
+        let note = self.node_content(&node).await?;
+        Ok((node.name, note))
+    }
+
+    /// Renders the note for a processed node that has already been read.
+    pub async fn node_content(&mut self, node: &Node) -> NetdoxResult<String> {
+        // The role is displayed with the details instead of the metadata.
+        let mut metadata = self.con.get_node_metadata(node).await?;
+        let role = metadata.remove(ROLE_META_KEY);
+
+        let mut details = vec![
+            ("Name".to_string(), escape(&node.name)),
+            ("Link ID".to_string(), escape(&node.link_id)),
+        ];
+        if let Some(role) = role {
+            details.push(("Role".to_string(), escape(&role)));
+        }
+        for name in node.alt_names.iter().sorted() {
+            details.push(("Alt Name".to_string(), escape(name)));
+        }
+        for plugin in node.plugins.iter().sorted() {
+            details.push(("Plugin".to_string(), escape(plugin)));
+        }
+        let (status, pdata): (Vec<_>, Vec<_>) = self
+            .con
+            .get_node_pdata(node)
+            .await?
+            .into_iter()
+            .partition(|data| data.status_state().is_some());
+
+        let mut body = String::new();
+        for data in &status {
+            body.push_str(&self.status_callout(data).await?);
+        }
+        body.push_str(&properties(&details));
+
+        let sources = match self.metadata_by_plugin {
+            true => Some(self.con.get_node_metadata_sources(node).await?),
+            false => None,
+        };
+        body.push_str("\n## Metadata\n\n");
+        body.push_str(&self.metadata(metadata, sources).await?);
+
+        let obj_id = format!("{PROC_NODES_KEY};{}", node.link_id);
+        let notes = self.con.get_notes(&obj_id).await?;
+        if !notes.is_empty() {
+            body.push_str("\n## Notes\n\n");
+            body.push_str(&self.map(notes).await?);
+        }
+
+        body.push_str("\n## DNS Names\n\n");
+        for qname in node.dns_names.iter().sorted() {
+            let _ = writeln!(body, "- {}", wiki_link(&dns_path(qname), qname));
+        }
+
+        let dependencies = self.con.get_node_dependencies(&node.link_id).await?;
+        if !dependencies.is_empty() {
+            body.push_str("\n## Dependencies\n\n");
+            for dep in &dependencies {
+                let other = dep.other(&node.link_id);
+                let name = self.con.get_node(other).await?.name;
+                let relation = if dep.source == node.link_id {
+                    "Depends on"
+                } else {
+                    "Required by"
+                };
+                let label = match &dep.label {
+                    Some(label) => format!(" ({})", escape(label)),
+                    None => String::new(),
+                };
+                let _ = writeln!(
+                    body,
+                    "- {relation} {}{label}",
+                    wiki_link(&node_path(other), &name)
+                );
+            }
+        }
+
+        let relations = self.con.get_node_relations(&node.link_id).await?;
+        if !relations.is_empty() {
+            body.push_str("\n## Relations\n\n");
+            for relation in &relations {
+                let other = relation.other(&node.link_id);
+                let name = self.con.get_node(other).await?.name;
+                let direction = if relation.source == node.link_id {
+                    ""
+                } else {
+                    " (from)"
+                };
+                let _ = writeln!(
+                    body,
+                    "- {}{direction} {}",
+                    escape(&relation.kind),
+                    wiki_link(&node_path(other), &name)
+                );
+            }
+        }
+
+        let services = self.con.get_node_services(&node.link_id).await?;
+        if !services.is_empty() {
+            body.push_str("\n## Services\n\n");
+            for service in &services {
+                let details = service
+                    .details
+                    .iter()
+                    .sorted()
+                    .map(|(key, value)| format!("{}: {}", escape(key), escape(value)))
+                    .join(", ");
+                let _ = if details.is_empty() {
+                    writeln!(body, "- {}", service.id())
+                } else {
+                    writeln!(body, "- {} ({details})", service.id())
+                };
+            }
+        }
+
+        body.push('\n');
+        body.push_str(&self.pdata(pdata).await?);
+
+        Ok(note(&obj_id, &node.name, &body))
+    }
+
+    /// Renders the note for a report, returning the title of the report and the note.
+    pub async fn report_note(&mut self, id: &str) -> NetdoxResult<(String, String)> {
+        let report = self.con.get_report(id).await?;
+
+        let mut body = properties(&[
+            ("Report ID".to_string(), escape(&report.id)),
+            ("Plugin".to_string(), escape(&report.plugin)),
+        ]);
+        body.push('\n');
+        for data in &report.content {
+            body.push_str(&self.data(data).await?);
+        }
+
+        let note = note(&format!("{REPORTS_KEY};{id}"), &report.title, &body);
+        Ok((report.title, note))
+    }
+}
+
+/// Renders the index note, which links to every other note.
+/// Takes qualified DNS names, pairs of node link ID and name, and pairs of report ID and title.
+pub fn index_note(
+    title: &str,
+    qnames: &[String],
+    nodes: &[(String, String)],
+    reports: &[(String, String)],
+) -> String {
+    let mut markdown = format!("# {}\n\n## Reports\n\n", escape(title));
+    for (id, title) in reports {
+        let _ = writeln!(markdown, "- {}", wiki_link(&report_path(id), title));
+    }
+
+    markdown.push_str("\n## Nodes\n\n");
+    for (link_id, name) in nodes {
+        let _ = writeln!(markdown, "- {}", wiki_link(&node_path(link_id), name));
+    }
+
+    markdown.push_str("\n## DNS Names\n");
+    let by_network = qnames
+        .iter()
+        .filter_map(|qname| split_qname(qname).map(|(network, name)| (network, name, qname)))
+        .into_group_map_by(|(network, _, _)| *network);
+    for (network, names) in by_network.into_iter().sorted() {
+        let _ = writeln!(markdown, "\n### {}\n", escape(network));
+        // Addresses are grouped by their reverse zone after the hostnames.
+        let (addresses, hostnames): (Vec<_>, Vec<_>) = names
+            .into_iter()
+            .partition(|(_, name, _)| name.parse::<IpAddr>().is_ok());
+        for (_, name, qname) in hostnames {
+            let _ = writeln!(markdown, "- {}", wiki_link(&dns_path(qname), name));
+        }
+
+        let by_zone = addresses
+            .into_iter()
+            .filter_map(|(_, name, qname)| Some((name.parse::<IpAddr>().ok()?, name, qname)))
+            .into_group_map_by(|(addr, _, _)| address_subnet(addr));
+        for (subnet, addresses) in by_zone.into_iter().sorted() {
+            let _ = writeln!(
+                markdown,
+                "\n#### {} ({subnet})\n",
+                escape(&reverse_zone(&subnet.network()))
+            );
+            for (_, name, qname) in addresses.into_iter().sorted() {
+                let _ = writeln!(markdown, "- {}", wiki_link(&dns_path(qname), name));
+            }
+        }
+    }
+
+    markdown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dns_path, escape, index_note, table, wiki_link};
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("_dmarc.a.com"), "\\_dmarc.a.com");
+        assert_eq!(escape("a <b> [c]"), "a \\<b\\> \\[c\\]");
+    }
+
+    #[test]
+    fn test_wiki_link() {
+        assert_eq!(
+            wiki_link(&dns_path("[net]a.com"), "a.com"),
+            "[[dns/_5Bnet_5Da.com|a.com]]"
+        );
+        assert_eq!(wiki_link("nodes/x.md", "[[x|y]]"), "[[nodes/x|[xy]]");
+    }
+
+    #[test]
+    fn test_table() {
+        let cells = ["A", "B", "[[a|b]]", "c\nd"].map(String::from);
+        assert_eq!(
+            table(2, &cells),
+            "| A | B |\n| --- | --- |\n| [[a\\|b]] | c<br>d |\n"
+        );
+    }
+
+    #[test]
+    fn test_index_note() {
+        let index = index_note(
+            "Netdox",
+            &[
+                "[net]a.com".to_string(),
+                "[net]10.0.0.1".to_string(),
+                "[other]b.com".to_string(),
+            ],
+            &[("node-1".to_string(), "Server 1".to_string())],
+            &[],
+        );
+
+        assert!(index.contains(&format!(
+            "- {}",
+            wiki_link(&dns_path("[net]a.com"), "a.com")
+        )));
+        assert!(index.contains("- [[nodes/node-1|Server 1]]"));
+        assert!(index.contains("#### 0.0.10.in-addr.arpa (10.0.0.0/24)"));
+        assert!(index.find("### net") < index.find("### other"));
+    }
+}
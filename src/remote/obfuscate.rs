@@ -0,0 +1,284 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    net::IpAddr,
+};
+
+use hmac::{Hmac, Mac};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::data::model::{split_qname, DNS_KEY, PROC_NODES_KEY};
+
+/// Number of bytes of the keyed hash used in each pseudonym.
+const PSEUDONYM_BYTES: usize = 6;
+/// Suffixes of the names in reverse DNS zones, whose labels are the parts of an address.
+const REVERSE_SUFFIXES: [&str; 2] = ["in-addr.arpa", "ip6.arpa"];
+
+/// Method of replacing addresses and hostnames with pseudonyms in documents
+/// that are shared outside the organisation.
+/// A value always gets the same pseudonym, so links between documents still work.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "method")]
+pub enum Obfuscation {
+    /// Replaces each address, and each label of a hostname but the top level domain,
+    /// with a hash keyed by a secret. Changing the secret changes every pseudonym.
+    #[serde(rename = "keyed-hash")]
+    KeyedHash { secret: String },
+}
+
+impl Obfuscation {
+    /// Returns the pseudonym of a single value, as hex.
+    fn hash(&self, value: &str) -> String {
+        match self {
+            Obfuscation::KeyedHash { secret } => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+                    .expect("HMAC accepts keys of any length");
+                mac.update(value.as_bytes());
+                mac.finalize()
+                    .into_bytes()
+                    .iter()
+                    .take(PSEUDONYM_BYTES)
+                    .fold(String::new(), |mut hex, byte| {
+                        let _ = write!(hex, "{byte:02x}");
+                        hex
+                    })
+            }
+        }
+    }
+
+    /// Returns the pseudonym of an address.
+    pub fn address(&self, addr: &IpAddr) -> String {
+        format!("ip-{}", self.hash(&addr.to_string()))
+    }
+
+    /// Returns the pseudonym of an unqualified DNS name, which may be an address.
+    /// Names in reverse zones keep the suffix of the zone.
+    pub fn name(&self, name: &str) -> String {
+        if let Ok(addr) = name.parse::<IpAddr>() {
+            return self.address(&addr);
+        }
+
+        let name = name.to_lowercase();
+        for suffix in REVERSE_SUFFIXES {
+            if let Some(prefix) = name
+                .strip_suffix(suffix)
+                .and_then(|prefix| prefix.strip_suffix('.'))
+            {
+                return format!("{}.{suffix}", self.hash(prefix));
+            }
+        }
+
+        let mut labels = name.split('.').collect::<Vec<_>>();
+        let tld = if labels.len() > 1 { labels.pop() } else { None };
+        labels
+            .into_iter()
+            .map(|label| self.hash(label))
+            .chain(tld.map(str::to_string))
+            .join(".")
+    }
+
+    /// Returns the pseudonym of a qualified DNS name. The network is kept.
+    pub fn qname(&self, qname: &str) -> String {
+        match split_qname(qname) {
+            Some((network, name)) => format!("[{network}]{}", self.name(name)),
+            None => self.name(qname),
+        }
+    }
+
+    /// Returns the object ID with the DNS name or node link ID replaced by its pseudonym.
+    /// Other object IDs are returned as is.
+    pub fn object_id(&self, obj_id: &str) -> String {
+        match obj_id.split_once(';') {
+            Some((DNS_KEY, qname)) => format!("{DNS_KEY};{}", self.qname(qname)),
+            Some((PROC_NODES_KEY, link_id)) => {
+                format!("{PROC_NODES_KEY};node-{}", self.hash(link_id))
+            }
+            _ => obj_id.to_string(),
+        }
+    }
+}
+
+/// Returns true if the character can be part of a DNS name, an address, or the name of a file.
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | ':')
+}
+
+/// Replaces the addresses and hostnames in rendered documents with their pseudonyms.
+pub struct Obfuscator {
+    method: Obfuscation,
+    /// Unqualified DNS names in the data store that are not addresses, in lowercase.
+    names: HashSet<String>,
+    /// Maps the names of the files of objects to the names of the files of their pseudonyms.
+    files: HashMap<String, String>,
+}
+
+impl Obfuscator {
+    /// Takes every qualified DNS name and node link ID in the data store,
+    /// and the function a remote uses to name the file of an object.
+    pub fn new(
+        method: Obfuscation,
+        qnames: &HashSet<String>,
+        node_ids: &HashSet<String>,
+        file_name: impl Fn(&str) -> String,
+    ) -> Self {
+        let names = qnames
+            .iter()
+            .filter_map(|qname| split_qname(qname).map(|(_, name)| name))
+            .filter(|name| name.parse::<IpAddr>().is_err())
+            .map(str::to_lowercase)
+            .collect();
+
+        let mut files = HashMap::new();
+        for qname in qnames {
+            files.insert(file_name(qname), file_name(&method.qname(qname)));
+        }
+        for link_id in node_ids {
+            let obj_id = method.object_id(&format!("{PROC_NODES_KEY};{link_id}"));
+            if let Some((_, pseudonym)) = obj_id.split_once(';') {
+                files.insert(file_name(link_id), file_name(pseudonym));
+            }
+        }
+
+        Obfuscator {
+            method,
+            names,
+            files,
+        }
+    }
+
+    /// Returns true if the name, in lowercase, is a DNS name in the data store or a subdomain of one.
+    fn is_known_name(&self, name: &str) -> bool {
+        let mut rest = name;
+        loop {
+            if self.names.contains(rest) {
+                return true;
+            }
+            match rest.split_once('.') {
+                Some((_, parent)) => rest = parent,
+                None => return false,
+            }
+        }
+    }
+
+    /// Returns the replacement for a run of characters that can be part of a name.
+    fn token(&self, token: &str) -> String {
+        if let Some(file) = self.files.get(token) {
+            return file.clone();
+        }
+
+        // Names at the end of a sentence keep their full stop.
+        let trimmed = token.trim_end_matches('.');
+        let dots = &token[trimmed.len()..];
+        if let Ok(addr) = trimmed.parse::<IpAddr>() {
+            return format!("{}{dots}", self.method.address(&addr));
+        }
+        if trimmed.contains(':') {
+            // Like a name and a port.
+            return token.split(':').map(|part| self.token(part)).join(":");
+        }
+
+        let lower = trimmed.to_lowercase();
+        let reverse = REVERSE_SUFFIXES
+            .iter()
+            .any(|suffix| lower.ends_with(&format!(".{suffix}")));
+        if reverse || (!lower.is_empty() && self.is_known_name(&lower)) {
+            format!("{}{dots}", self.method.name(&lower))
+        } else {
+            token.to_string()
+        }
+    }
+
+    /// Replaces the addresses, the hostnames in or below the DNS names in the data store,
+    /// and the names of the files of objects in some text.
+    pub fn text(&self, text: &str) -> String {
+        let mut replaced = String::with_capacity(text.len());
+        let mut start = None;
+        for (idx, c) in text.char_indices() {
+            if is_token_char(c) {
+                start.get_or_insert(idx);
+            } else {
+                if let Some(start) = start.take() {
+                    replaced.push_str(&self.token(&text[start..idx]));
+                }
+                replaced.push(c);
+            }
+        }
+        if let Some(start) = start {
+            replaced.push_str(&self.token(&text[start..]));
+        }
+        replaced
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{Obfuscation, Obfuscator};
+    use crate::{data::model::PROC_NODES_KEY, remote::file_stem};
+
+    fn method(secret: &str) -> Obfuscation {
+        Obfuscation::KeyedHash {
+            secret: secret.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_pseudonyms() {
+        let keyed = method("secret");
+
+        let name = keyed.name("Web.Corp.com");
+        assert_eq!(name, keyed.name("web.corp.com"));
+        assert!(name.ends_with(".com"));
+        assert!(!name.contains("web") && !name.contains("corp"));
+        // Labels are replaced separately, so subdomains keep their parent.
+        assert!(keyed
+            .name("db.corp.com")
+            .ends_with(&name[name.find('.').unwrap()..]));
+
+        assert!(keyed.name("10.0.0.1").starts_with("ip-"));
+        assert!(keyed
+            .name("1.0.0.10.in-addr.arpa")
+            .ends_with(".in-addr.arpa"));
+        assert_eq!(
+            keyed.qname("[net]web.corp.com"),
+            format!("[net]{}", keyed.name("web.corp.com"))
+        );
+        assert_ne!(method("other").name("web.corp.com"), name);
+    }
+
+    #[test]
+    fn test_obfuscator_text() {
+        let keyed = method("secret");
+        let file_name = |id: &str| format!("{}.html", file_stem(id));
+        let obfuscator = Obfuscator::new(
+            keyed.clone(),
+            &HashSet::from(["[net]corp.com".to_string(), "[net]10.0.0.1".to_string()]),
+            &HashSet::from(["node-id".to_string()]),
+            file_name,
+        );
+
+        let text = obfuscator.text(&format!(
+            "<a href=\"../{}\">web.corp.com</a> at 10.0.0.2:443, not example.com.",
+            file_name("[net]10.0.0.1")
+        ));
+        assert_eq!(
+            text,
+            format!(
+                "<a href=\"../{}\">{}</a> at {}:443, not example.com.",
+                file_name(&keyed.qname("[net]10.0.0.1")),
+                keyed.name("web.corp.com"),
+                keyed.name("10.0.0.2"),
+            )
+        );
+
+        let node = keyed.object_id(&format!("{PROC_NODES_KEY};node-id"));
+        assert_eq!(
+            obfuscator.text("nodes/node-id.html"),
+            format!("nodes/{}.html", node.split_once(';').unwrap().1)
+        );
+        assert_eq!(obfuscator.text("Version 1.25"), "Version 1.25");
+    }
+}
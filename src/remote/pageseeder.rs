@@ -5,6 +5,7 @@ mod remote;
 
 use crate::error::NetdoxError;
 use pageseeder_api::model::PSError;
+pub use publish::MAX_DOCID_LEN;
 pub use remote::PSRemote;
 
 impl From<PSError> for NetdoxError {
@@ -4,11 +4,11 @@ use std::{
 };
 
 use ipnet::Ipv4Net;
-use paris::warn;
 use psml::{
     model::{Document, FragmentContent, PropertyValue, Section, SectionContent},
     text::ParaContent,
 };
+use tracing::warn;
 
 use crate::{
     config::RemoteConfig,
@@ -1,10 +1,19 @@
 mod changelog;
 mod config;
+mod html;
 pub mod links;
+mod lock;
+mod overview;
+mod subnet;
 #[cfg(test)]
 mod tests;
+#[cfg(feature = "psml-validation")]
+pub mod validate;
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+};
 
 use itertools::Itertools;
 use psml::{
@@ -15,24 +24,43 @@ use psml::{
     text::{CharacterStyle, Heading, Para, ParaContent},
 };
 use regex::Regex;
+use sha2::{Digest, Sha256};
 
 use crate::{
     data::{
-        model::{DNSRecord, DNSRecords, Data, ImpliedDNSRecord, Node, ObjectID, StringType},
+        model::{
+            address_subnet, group_metadata, ptr_name, qname_address, reverse_zone,
+            split_provenance, split_qname, status_colour, DNSRecord, DNSRecords, Data, Dependency,
+            DnsBundle, ImpliedDNSRecord, Node, NodeBundle, ObjectID, PluginDisplay, Relation,
+            Service, StringType, NODE_META_KEY, ROLE_META_KEY,
+        },
         DataConn, DataStore,
     },
     error::{NetdoxError, NetdoxResult},
-    redis_err,
-    remote::pageseeder::remote::{node_id_to_docid, report_id_to_docid},
+    process_err, redis_err,
+    remote::pageseeder::remote::{node_id_to_docid, report_id_to_docid, report_part_docid},
 };
 pub use changelog::{changelog_document, CHANGELOG_DOC_TYPE};
 pub use config::{remote_config_document, REMOTE_CONFIG_DOC_TYPE};
 use links::LinkContent;
+pub use lock::{
+    parse_publish_lock, publish_lock_document, publish_lock_fragment, PUBLISH_LOCK_DOC_TYPE,
+};
+pub use overview::{network_overviews, overview_document, overview_networks, OVERVIEW_DOC_TYPE};
+pub use subnet::{subnet_document, subnets, SUBNET_DOC_TYPE};
 
 use super::remote::dns_qname_to_docid;
 
 pub const METADATA_FRAGMENT: &str = "meta";
+pub const DETAILS_FRAGMENT: &str = "details";
+pub const NOTES_FRAGMENT: &str = "operator-notes";
+pub const ADDRESS_NODE_FRAGMENT: &str = "address-node";
 pub const SEARCH_TOKENS_FRAGMENT: &str = "search-tokens";
+pub const DEPENDENCIES_FRAGMENT: &str = "dependencies";
+pub const RELATIONS_FRAGMENT: &str = "relations";
+pub const SERVICES_SECTION: &str = "services";
+pub const TRANSLATIONS_FRAGMENT: &str = "translations";
+pub const CONTINUATIONS_FRAGMENT: &str = "continuations";
 
 pub const OBJECT_NAME_PROPNAME: &str = "name-text";
 const OBJECT_NAME_PROPTITLE: &str = "Name";
@@ -79,25 +107,52 @@ fn generic_details(name: &str, obj_id: ObjectID) -> Vec<Property> {
     ]
 }
 
+/// Returns the labels for a document with some tags, or None if there are no tags.
+fn tag_labels(tags: HashSet<String>) -> Option<Labels> {
+    if tags.is_empty() {
+        None
+    } else {
+        Some(Labels {
+            value: tags.into_iter().sorted().join(","),
+        })
+    }
+}
+
 /// Generates a document representing the DNS name.
-pub async fn dns_name_document(backend: &mut DataStore, name: &str) -> NetdoxResult<Document> {
+/// Names that are IPv4 addresses use a template that displays the node they resolve to first,
+/// and their place in reverse DNS.
+/// If `metadata_by_plugin` is true, the metadata is split into a fragment for each plugin that set it.
+pub async fn dns_name_document(
+    backend: &mut DataStore,
+    name: &str,
+    metadata_by_plugin: bool,
+) -> NetdoxResult<Document> {
     use FragmentContent as FC;
     use Fragments as F;
 
-    let (network, raw_name) = match name.rsplit_once(']') {
-        Some(tuple) => match tuple.0.strip_prefix('[') {
-            Some(net) => (net, tuple.1),
-            None => return redis_err!(format!("Failed to parse network from qname: {name}")),
-        },
+    let (network, raw_name) = match split_qname(name) {
+        Some(tuple) => tuple,
         None => return redis_err!(format!("Failed to parse network from qname: {name}")),
     };
-    let dns = backend.get_dns().await?;
-
-    let mut document = dns_template();
+    let address = qname_address(name);
+    let DnsBundle {
+        dns,
+        tags,
+        metadata,
+        notes,
+        pdata,
+        displays,
+    } = backend.get_dns_bundle(name).await?;
+
+    let mut document = match address {
+        Some(_) => address_template(),
+        None => dns_template(),
+    };
     document.doc_info = Some(DocumentInfo {
         uri: Some(URIDescriptor {
             title: Some(raw_name.to_owned()),
             docid: Some(dns_qname_to_docid(name)),
+            labels: tag_labels(tags),
             ..Default::default()
         }),
         ..Default::default()
@@ -113,6 +168,20 @@ pub async fn dns_name_document(backend: &mut DataStore, name: &str) -> NetdoxRes
         })]),
     ));
 
+    // Node
+
+    // An address displays the node it resolves to first, instead of with the metadata.
+    let (mut metadata, sources) = split_provenance(metadata);
+    if address.is_some() {
+        let node = metadata.remove(NODE_META_KEY);
+        document
+            .get_mut_section(ADDRESS_NODE_SECTION)
+            .unwrap()
+            .add_fragment(F::Properties(
+                address_node_fragment(node).create_links(backend).await?,
+            ));
+    }
+
     // Details
 
     let details = document.get_mut_section("details").unwrap();
@@ -124,16 +193,32 @@ pub async fn dns_name_document(backend: &mut DataStore, name: &str) -> NetdoxRes
                 "network".to_string(),
                 "Logical Network".to_string(),
                 network.to_string().into(),
-            )]),
+            )])
+            .with_properties(
+                address
+                    .map(|addr| address_details(&addr))
+                    .unwrap_or_default(),
+            ),
     ));
 
+    details.add_fragment(F::Properties(translations_fragment(
+        dns.get_translations(name),
+    )));
+
     // Metadata
 
-    details.add_fragment(F::Properties(
-        metadata_fragment(backend.get_dns_metadata(name).await?)
-            .create_links(backend)
-            .await?,
-    ));
+    for fragment in metadata_fragments(metadata, &sources, metadata_by_plugin) {
+        details.add_fragment(F::Properties(fragment.create_links(backend).await?));
+    }
+
+    // Notes
+
+    document
+        .get_mut_section(NOTES_SECTION)
+        .unwrap()
+        .add_fragment(F::Properties(
+            notes_fragment(notes).create_links(backend).await?,
+        ));
 
     // Records
 
@@ -159,28 +244,22 @@ pub async fn dns_name_document(backend: &mut DataStore, name: &str) -> NetdoxRes
     // Plugin data
 
     let pdata_section = document.get_mut_section("plugin-data").unwrap();
-    for pdata in backend.get_dns_pdata(name).await? {
-        pdata_section.add_fragment(pdata.into());
+    for pdata in pdata {
+        pdata_section.add_fragment(data_fragment(pdata, &displays));
     }
 
     // Extras
 
     let extras_section = document.get_mut_section(EXTRAS_SECTION).unwrap();
-    let mut name_parts = name.split('.').rev();
-
-    let mut search_tokens = vec![];
-    let mut combined_parts = name_parts.next().unwrap().to_string();
-    for part in name_parts {
-        search_tokens.push(PropertyValue::Value(combined_parts.clone()));
-        combined_parts = format!("{part}.{combined_parts}");
-    }
-
     extras_section.add_fragment(Fragments::Properties(
         PropertiesFragment::new(SEARCH_TOKENS_FRAGMENT.to_string()).with_properties(vec![
             Property {
                 name: "search-tokens".to_string(),
                 title: Some("Search Tokens".to_string()),
-                values: search_tokens,
+                values: dns_search_tokens(name)
+                    .into_iter()
+                    .map(PropertyValue::Value)
+                    .collect(),
                 attr_value: None,
                 datatype: None,
                 multiple: Some(true),
@@ -191,20 +270,85 @@ pub async fn dns_name_document(backend: &mut DataStore, name: &str) -> NetdoxRes
     document.create_links(backend).await
 }
 
+/// Returns the tokens a DNS name can be found by in search.
+/// Names are found by each of their suffixes, and addresses also by their reverse zone.
+fn dns_search_tokens(qname: &str) -> Vec<String> {
+    let mut name_parts = qname.split('.').rev();
+
+    let mut search_tokens = vec![];
+    let mut combined_parts = name_parts.next().unwrap().to_string();
+    for part in name_parts {
+        search_tokens.push(combined_parts.clone());
+        combined_parts = format!("{part}.{combined_parts}");
+    }
+
+    if let Some(addr) = qname_address(qname) {
+        search_tokens.push(reverse_zone(&addr));
+    }
+    search_tokens
+}
+
+/// Builds the properties that place an address in reverse DNS.
+fn address_details(addr: &IpAddr) -> Vec<Property> {
+    vec![
+        Property::with_value(
+            "ptr-name".to_string(),
+            "PTR Name".to_string(),
+            ptr_name(addr).into(),
+        ),
+        Property::with_value(
+            "reverse-zone".to_string(),
+            "Reverse Zone".to_string(),
+            reverse_zone(addr).into(),
+        ),
+        Property::with_value(
+            "subnet".to_string(),
+            "Subnet".to_string(),
+            address_subnet(addr).to_string().into(),
+        ),
+    ]
+}
+
+/// Builds the fragment for the node an address resolves to, from the link in its metadata.
+pub fn address_node_fragment(node: Option<String>) -> PropertiesFragment {
+    PropertiesFragment::new(ADDRESS_NODE_FRAGMENT.to_string()).with_properties(vec![
+        Property::with_value(
+            "node".to_string(),
+            "Node".to_string(),
+            node.unwrap_or_else(|| "None".to_string()).into(),
+        ),
+    ])
+}
+
+/// Generates a document representing the processed node.
+/// If `metadata_by_plugin` is true, the metadata is split into a fragment for each plugin that set it.
 pub async fn processed_node_document(
     backend: &mut DataStore,
     node: &Node,
+    metadata_by_plugin: bool,
 ) -> NetdoxResult<Document> {
     use CharacterStyle as CS;
     use Fragment as FR;
     use FragmentContent as FC;
     use Fragments as F;
 
+    let NodeBundle {
+        tags,
+        metadata,
+        notes,
+        dependencies,
+        relations,
+        services,
+        pdata,
+        displays,
+    } = backend.get_node_bundle(node).await?;
+
     let mut document = node_template();
     document.doc_info = Some(DocumentInfo {
         uri: Some(URIDescriptor {
             title: Some(node.name.clone()),
             docid: Some(node_id_to_docid(&node.link_id)),
+            labels: tag_labels(tags),
             ..Default::default()
         }),
         ..Default::default()
@@ -224,46 +368,27 @@ pub async fn processed_node_document(
 
     // Details
 
+    // The role is displayed with the details instead of the metadata.
+    let (mut metadata, sources) = split_provenance(metadata);
+    let role = metadata.remove(ROLE_META_KEY);
+
     let details = document.get_mut_section("details").unwrap();
-    details.add_fragment(F::Properties(
-        PropertiesFragment::new("details".to_owned())
-            .with_properties(generic_details(
-                &node.name,
-                ObjectID::Node(node.link_id.clone()),
-            ))
-            .with_properties(
-                node.alt_names
-                    .iter()
-                    .map(|n| {
-                        Property::with_value(
-                            "alt_name".to_owned(),
-                            "Alt Name".to_owned(),
-                            n.to_owned().into(),
-                        )
-                    })
-                    .collect(),
-            )
-            .with_properties(
-                node.plugins
-                    .iter()
-                    .map(|p| {
-                        Property::with_value(
-                            "plugin".to_owned(),
-                            "Plugin".to_owned(),
-                            p.to_owned().into(),
-                        )
-                    })
-                    .collect(),
-            ),
-    ));
+    details.add_fragment(F::Properties(node_details_fragment(node, role.as_deref())));
 
     // Metadata
 
-    details.add_fragment(F::Properties(
-        metadata_fragment(backend.get_node_metadata(node).await?)
-            .create_links(backend)
-            .await?,
-    ));
+    for fragment in metadata_fragments(metadata, &sources, metadata_by_plugin) {
+        details.add_fragment(F::Properties(fragment.create_links(backend).await?));
+    }
+
+    // Notes
+
+    document
+        .get_mut_section(NOTES_SECTION)
+        .unwrap()
+        .add_fragment(F::Properties(
+            notes_fragment(notes).create_links(backend).await?,
+        ));
 
     // DNS Names
 
@@ -283,34 +408,396 @@ pub async fn processed_node_document(
         ),
     ));
 
+    // Dependencies
+
+    document
+        .get_mut_section(DEPENDENCIES_FRAGMENT)
+        .unwrap()
+        .add_fragment(F::Properties(dependencies_fragment(
+            &node.link_id,
+            &dependencies,
+        )));
+
+    // Relations
+
+    document
+        .get_mut_section(RELATIONS_FRAGMENT)
+        .unwrap()
+        .add_fragment(F::Properties(relations_fragment(&node.link_id, &relations)));
+
+    // Services
+
+    let services_section = document.get_mut_section(SERVICES_SECTION).unwrap();
+    for fragment in service_fragments(&services) {
+        services_section.add_fragment(F::Properties(fragment));
+    }
+
     // Plugin data
 
-    let pdata_section = document.get_mut_section("plugin-data").unwrap();
-    for pdata in backend
-        .get_node_pdata(node)
-        .await?
-        .into_iter()
-        .sorted_by(|a, b| a.id().cmp(b.id()))
-    {
-        pdata_section.add_fragment(pdata.into());
+    for pdata in pdata.into_iter().sorted_by(|a, b| a.id().cmp(b.id())) {
+        let (fragment, section) = node_data_fragment(pdata, &displays);
+        document
+            .get_mut_section(section)
+            .unwrap()
+            .add_fragment(fragment);
     }
 
     document.create_links(backend).await
 }
 
-pub async fn report_document(backend: &mut DataStore, id: &str) -> NetdoxResult<Document> {
+/// Generates the documents representing a report.
+/// Reports with more than `max_rows` rows of content are split across
+/// multiple documents which link to their neighbours.
+pub async fn report_document(
+    backend: &mut DataStore,
+    id: &str,
+    max_rows: usize,
+) -> NetdoxResult<Vec<Document>> {
     use CharacterStyle as CS;
     use FragmentContent as FC;
 
-    let mut document = report_template();
     let report = backend.get_report(id).await?;
+    let displays = backend.get_plugin_displays().await?;
+
+    let parts = split_report(report.content, max_rows);
+    let num_parts = parts.len();
+
+    let mut documents = Vec::with_capacity(num_parts);
+    for (index, part) in parts.into_iter().enumerate() {
+        let title = if num_parts == 1 {
+            report.title.clone()
+        } else {
+            format!("{} (part {} of {num_parts})", report.title, index + 1)
+        };
+
+        let mut document = report_template();
+        document.doc_info = Some(DocumentInfo {
+            uri: Some(URIDescriptor {
+                title: Some(title.clone()),
+                docid: Some(report_part_docid(&report.id, index)),
+                labels: Some(Labels {
+                    value: report.plugin.clone(),
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        document
+            .get_mut_section("title")
+            .unwrap()
+            .add_fragment(Fragments::Fragment(
+                Fragment::new("title".to_string()).with_content(vec![FC::Heading(Heading {
+                    level: 1,
+                    content: vec![CS::Text(title)],
+                })]),
+            ));
+
+        let mut details = generic_details(&report.title, ObjectID::Report(report.id.clone()));
+        details.push(Property::with_value(
+            "plugin".to_string(),
+            "Plugin".to_string(),
+            PropertyValue::Value(report.plugin.clone()),
+        ));
+        if num_parts > 1 {
+            details.push(Property::with_value(
+                "part".to_string(),
+                "Part".to_string(),
+                PropertyValue::Value(format!("{} of {num_parts}", index + 1)),
+            ));
+            if index > 0 {
+                details.push(Property::with_value(
+                    "previous-part".to_string(),
+                    "Previous Part".to_string(),
+                    PropertyValue::XRef(Box::new(XRef::docid(report_part_docid(
+                        &report.id,
+                        index - 1,
+                    )))),
+                ));
+            }
+            if index + 1 < num_parts {
+                details.push(Property::with_value(
+                    "next-part".to_string(),
+                    "Next Part".to_string(),
+                    PropertyValue::XRef(Box::new(XRef::docid(report_part_docid(
+                        &report.id,
+                        index + 1,
+                    )))),
+                ));
+            }
+        }
+        document
+            .get_mut_section("details")
+            .unwrap()
+            .add_fragment(Fragments::Properties(
+                PropertiesFragment::new("details".to_string()).with_properties(details),
+            ));
+
+        let content = document.get_mut_section("content").unwrap();
+        for data in part {
+            content.add_fragment(data_fragment(data, &displays));
+        }
+
+        documents.push(document.create_links(backend).await?);
+    }
+
+    Ok(documents)
+}
+
+/// Groups report content into parts with at most `max_rows` rows each.
+/// Data larger than the limit is split first. Always returns at least one part.
+pub fn split_report(content: Vec<Data>, max_rows: usize) -> Vec<Vec<Data>> {
+    let mut parts = vec![vec![]];
+    let mut rows = 0;
+
+    for data in content.into_iter().flat_map(|data| data.split(max_rows)) {
+        let data_rows = data.rows();
+        if rows + data_rows > max_rows && !parts.last().unwrap().is_empty() {
+            parts.push(vec![]);
+            rows = 0;
+        }
+
+        rows += data_rows;
+        parts.last_mut().unwrap().push(data);
+    }
+
+    parts
+}
+
+/// Returns the size of a document in bytes once serialised.
+pub fn document_size(document: &Document) -> NetdoxResult<usize> {
+    match quick_xml::se::to_string(document) {
+        Ok(xml) => Ok(xml.len()),
+        Err(err) => process_err!(format!("Failed to serialise psml document: {err}")),
+    }
+}
+
+/// Returns the size of some section content in bytes once serialised, as part of a section.
+fn content_size(content: &SectionContent) -> NetdoxResult<usize> {
+    let section = |content| {
+        let mut section = Section::new(PDATA_SECTION.to_string());
+        section.content = content;
+        section
+    };
+    let serialise = |section: &Section| match quick_xml::se::to_string_with_root("section", section)
+    {
+        Ok(xml) => Ok(xml.len()),
+        Err(err) => process_err!(format!("Failed to serialise psml section: {err}")),
+    };
+
+    Ok(serialise(&section(vec![content.clone()]))? - serialise(&section(vec![]))?)
+}
+
+/// Returns the docid of a continuation document, which holds plugin data that did not fit in a document.
+pub fn continuation_docid(docid: &str, index: usize) -> String {
+    format!("{docid}_cont{}", index + 1)
+}
+
+/// Moves plugin data from the end of a document that is larger than `max_bytes` into continuation documents,
+/// which are linked from the end of its plugin data.
+/// Returns the document followed by its continuations, or just the document if it is small enough
+/// or has no plugin data to move.
+pub fn split_document(mut document: Document, max_bytes: usize) -> NetdoxResult<Vec<Document>> {
+    let size = document_size(&document)?;
+    let Some(docid) = document.docid().map(str::to_string) else {
+        return Ok(vec![document]);
+    };
+    if size <= max_bytes {
+        return Ok(vec![document]);
+    }
+    let Some(pdata) = document.get_mut_section(PDATA_SECTION) else {
+        return Ok(vec![document]);
+    };
+
+    // Fragments are moved from the end so the data that stays keeps its order.
+    let mut overflow = vec![];
+    let mut remaining = size;
+    while remaining > max_bytes {
+        let Some(content) = pdata.content.pop() else {
+            break;
+        };
+        remaining -= content_size(&content)?;
+        overflow.push(content);
+    }
+    overflow.reverse();
+    if overflow.is_empty() {
+        return Ok(vec![document]);
+    }
+
+    let title = document
+        .doc_info
+        .as_ref()
+        .and_then(|info| info.uri.as_ref())
+        .and_then(|uri| uri.title.clone())
+        .unwrap_or_else(|| docid.clone());
+    let template = continuation_template(&document, &docid, &title);
+    let budget = max_bytes.saturating_sub(document_size(&template)?);
+
+    let mut parts: Vec<Vec<SectionContent>> = vec![vec![]];
+    let mut part_size = 0;
+    for content in overflow {
+        let content_size = content_size(&content)?;
+        if part_size + content_size > budget && !parts.last().unwrap().is_empty() {
+            parts.push(vec![]);
+            part_size = 0;
+        }
+
+        part_size += content_size;
+        parts.last_mut().unwrap().push(content);
+    }
+
+    let num_parts = parts.len();
+    document
+        .get_mut_section(PDATA_SECTION)
+        .unwrap()
+        .add_fragment(Fragments::Properties(
+            PropertiesFragment::new(CONTINUATIONS_FRAGMENT.to_string()).with_properties(
+                (0..num_parts)
+                    .map(|index| {
+                        Property::with_value(
+                            "continuation".to_string(),
+                            "Continued In".to_string(),
+                            PropertyValue::XRef(Box::new(XRef::docid(continuation_docid(
+                                &docid, index,
+                            )))),
+                        )
+                    })
+                    .collect(),
+            ),
+        ));
+
+    let mut documents = vec![document];
+    for (index, content) in parts.into_iter().enumerate() {
+        let mut continuation = template.clone();
+        let part_title = format!("{title} (continued {} of {num_parts})", index + 1);
+        if let Some(uri) = continuation
+            .doc_info
+            .as_mut()
+            .and_then(|info| info.uri.as_mut())
+        {
+            uri.docid = Some(continuation_docid(&docid, index));
+            uri.title = Some(part_title.clone());
+        }
+
+        continuation
+            .get_mut_section("title")
+            .unwrap()
+            .add_fragment(Fragments::Fragment(
+                Fragment::new("title".to_string()).with_content(vec![FragmentContent::Heading(
+                    Heading {
+                        level: 1,
+                        content: vec![CharacterStyle::Text(part_title)],
+                    },
+                )]),
+            ));
+        continuation.get_mut_section(PDATA_SECTION).unwrap().content = content;
+        documents.push(continuation);
+    }
+
+    Ok(documents)
+}
+
+/// Returns an empty continuation of a document, with a details fragment linking to it.
+fn continuation_template(document: &Document, docid: &str, title: &str) -> Document {
+    let mut continuation = document.clone();
+    continuation
+        .sections
+        .retain(|section| ["title", "details", PDATA_SECTION].contains(&section.id.as_str()));
+    for section in &mut continuation.sections {
+        section.content.clear();
+    }
+
+    if let Some(uri) = continuation
+        .doc_info
+        .as_mut()
+        .and_then(|info| info.uri.as_mut())
+    {
+        uri.title = Some(format!("{title} (continued)"));
+    }
+
+    if let Some(details) = continuation.get_mut_section("details") {
+        details.add_fragment(Fragments::Properties(
+            PropertiesFragment::new(DETAILS_FRAGMENT.to_string()).with_properties(vec![
+                Property::with_value(
+                    "continues".to_string(),
+                    "Continues".to_string(),
+                    PropertyValue::XRef(Box::new(XRef::docid(docid.to_string()))),
+                ),
+            ]),
+        ));
+    }
+
+    continuation
+}
+
+/// Label applied to the documents of objects that were moved or removed.
+pub const ARCHIVED_LABEL: &str = "archived";
+
+/// Generates a document to replace that of a DNS name which was moved to another network.
+/// It keeps the details of the old name, and links to the document of the new one.
+pub fn archived_dns_document(old: &str, new: &str) -> Document {
+    let raw_name = split_qname(old).map_or(old, |(_, name)| name);
+
+    let mut details = generic_details(old, ObjectID::DNS(old.to_string()));
+    details.push(Property::with_value(
+        "moved-to".to_string(),
+        "Moved To".to_string(),
+        PropertyValue::XRef(Box::new(XRef::docid(dns_qname_to_docid(new)))),
+    ));
+
+    archived_document(
+        dns_template(),
+        dns_qname_to_docid(old),
+        format!("{raw_name} (moved)"),
+        details,
+    )
+}
+
+/// Generates a document to replace that of a DNS name which was removed because it was no longer reported.
+pub fn deleted_dns_document(qname: &str) -> Document {
+    let raw_name = split_qname(qname).map_or(qname, |(_, name)| name);
+    archived_document(
+        dns_template(),
+        dns_qname_to_docid(qname),
+        format!("{raw_name} (removed)"),
+        generic_details(qname, ObjectID::DNS(qname.to_string())),
+    )
+}
+
+/// Generates a document to replace that of a processed node which was removed
+/// because none of its raw nodes were reported any more.
+pub fn deleted_node_document(link_id: &str) -> Document {
+    archived_document(
+        node_template(),
+        node_id_to_docid(link_id),
+        format!("{link_id} (removed)"),
+        generic_details(link_id, ObjectID::Node(link_id.to_string())),
+    )
+}
+
+/// Generates a document to replace that of a report which no longer exists.
+pub fn deleted_report_document(id: &str) -> Document {
+    archived_document(
+        report_template(),
+        report_id_to_docid(id),
+        format!("{id} (removed)"),
+        generic_details(id, ObjectID::Report(id.to_string())),
+    )
+}
 
+/// Labels a template document as archived, and gives it only a title and some details.
+fn archived_document(
+    mut document: Document,
+    docid: String,
+    title: String,
+    details: Vec<Property>,
+) -> Document {
     document.doc_info = Some(DocumentInfo {
         uri: Some(URIDescriptor {
-            title: Some(report.title.clone()),
-            docid: Some(report_id_to_docid(&report.id)),
+            title: Some(title.clone()),
+            docid: Some(docid),
             labels: Some(Labels {
-                value: report.plugin.clone(),
+                value: ARCHIVED_LABEL.to_string(),
             }),
             ..Default::default()
         }),
@@ -321,31 +808,22 @@ pub async fn report_document(backend: &mut DataStore, id: &str) -> NetdoxResult<
         .get_mut_section("title")
         .unwrap()
         .add_fragment(Fragments::Fragment(
-            Fragment::new("title".to_string()).with_content(vec![FC::Heading(Heading {
-                level: 1,
-                content: vec![CS::Text(report.title.clone())],
-            })]),
+            Fragment::new("title".to_string()).with_content(vec![FragmentContent::Heading(
+                Heading {
+                    level: 1,
+                    content: vec![CharacterStyle::Text(title)],
+                },
+            )]),
         ));
 
-    let mut details = generic_details(&report.title, ObjectID::Report(report.id));
-    details.push(Property::with_value(
-        "plugin".to_string(),
-        "Plugin".to_string(),
-        PropertyValue::Value(report.plugin),
-    ));
     document
         .get_mut_section("details")
         .unwrap()
         .add_fragment(Fragments::Properties(
-            PropertiesFragment::new("details".to_string()).with_properties(details),
+            PropertiesFragment::new(DETAILS_FRAGMENT.to_string()).with_properties(details),
         ));
 
-    let content = document.get_mut_section("content").unwrap();
-    for part in report.content {
-        content.add_fragment(Fragments::from(part));
-    }
-
-    document.create_links(backend).await
+    document
 }
 
 // Template documents
@@ -355,6 +833,29 @@ pub const IMPLIED_RECORD_SECTION: &str = "implied-records";
 pub const PDATA_SECTION: &str = "plugin-data";
 pub const RDATA_SECTION: &str = "content";
 pub const EXTRAS_SECTION: &str = "extras";
+pub const NOTES_SECTION: &str = "notes";
+pub const STATUS_SECTION: &str = "status";
+pub const ADDRESS_NODE_SECTION: &str = "address-node";
+
+/// Returns an empty document for a DNS name that is an IPv4 address.
+/// It has the sections of any other DNS name, with the node the address resolves to first.
+fn address_template() -> Document {
+    let mut document = dns_template();
+    document.sections.insert(
+        1,
+        Section {
+            id: ADDRESS_NODE_SECTION.to_string(),
+            content: vec![],
+            title: Some("Node".to_string()),
+            edit: Some(false),
+            lockstructure: Some(true),
+            content_title: None,
+            fragment_types: None,
+            overwrite: None,
+        },
+    );
+    document
+}
 
 /// Returns an empty document for a DNS name with all sections included.
 fn dns_template() -> Document {
@@ -380,6 +881,16 @@ fn dns_template() -> Document {
                 fragment_types: None,
                 overwrite: None,
             },
+            Section {
+                id: NOTES_SECTION.to_string(),
+                content: vec![],
+                title: Some("Operator Notes".to_string()),
+                edit: Some(false),
+                lockstructure: Some(true),
+                content_title: None,
+                fragment_types: None,
+                overwrite: None,
+            },
             Section {
                 id: DNS_RECORD_SECTION.to_string(),
                 content: vec![],
@@ -441,6 +952,16 @@ fn node_template() -> Document {
                 title: None,
                 overwrite: None,
             },
+            Section {
+                id: STATUS_SECTION.to_string(),
+                content: vec![],
+                title: Some("Status".to_string()),
+                edit: Some(false),
+                lockstructure: Some(true),
+                content_title: None,
+                fragment_types: None,
+                overwrite: None,
+            },
             Section {
                 id: "details".to_string(),
                 content: vec![],
@@ -451,6 +972,16 @@ fn node_template() -> Document {
                 fragment_types: None,
                 overwrite: None,
             },
+            Section {
+                id: NOTES_SECTION.to_string(),
+                content: vec![],
+                title: Some("Operator Notes".to_string()),
+                edit: Some(false),
+                lockstructure: Some(true),
+                content_title: None,
+                fragment_types: None,
+                overwrite: None,
+            },
             Section {
                 id: "dns-names".to_string(),
                 content: vec![],
@@ -461,6 +992,36 @@ fn node_template() -> Document {
                 fragment_types: None,
                 overwrite: None,
             },
+            Section {
+                id: DEPENDENCIES_FRAGMENT.to_string(),
+                content: vec![],
+                title: Some("Dependencies".to_string()),
+                edit: Some(false),
+                lockstructure: Some(true),
+                content_title: None,
+                fragment_types: None,
+                overwrite: None,
+            },
+            Section {
+                id: RELATIONS_FRAGMENT.to_string(),
+                content: vec![],
+                title: Some("Relations".to_string()),
+                edit: Some(false),
+                lockstructure: Some(true),
+                content_title: None,
+                fragment_types: None,
+                overwrite: None,
+            },
+            Section {
+                id: SERVICES_SECTION.to_string(),
+                content: vec![],
+                title: Some("Services".to_string()),
+                edit: Some(false),
+                lockstructure: Some(true),
+                content_title: None,
+                fragment_types: None,
+                overwrite: None,
+            },
             Section {
                 id: PDATA_SECTION.to_string(),
                 content: vec![],
@@ -523,43 +1084,356 @@ fn report_template() -> Document {
 
 // Fragment generators
 
-pub fn metadata_fragment(metadata: HashMap<String, String>) -> PropertiesFragment {
-    PropertiesFragment::new(METADATA_FRAGMENT.to_string()).with_properties(
-        metadata
+/// Builds the fragment for the metadata of an object.
+/// Each key with a known source is followed by a property naming the plugin that last changed it.
+pub fn metadata_fragment(
+    metadata: HashMap<String, String>,
+    sources: &HashMap<String, String>,
+) -> PropertiesFragment {
+    let mut properties = vec![];
+    for (key, val) in metadata {
+        if key.starts_with('_') {
+            continue;
+        }
+
+        let name = Property::sanitize_name(&key, "-").to_string();
+        let source = sources.get(&key).map(|plugin| {
+            Property::with_value(
+                format!("{name}-source"),
+                format!("{key} (source)"),
+                PropertyValue::Value(plugin.clone()),
+            )
+        });
+        properties.push(Property::with_value(name, key, PropertyValue::Value(val)));
+        properties.extend(source);
+    }
+
+    PropertiesFragment::new(METADATA_FRAGMENT.to_string()).with_properties(properties)
+}
+
+/// Builds the metadata fragments for an object.
+/// If `by_plugin` is true, there is one fragment for each plugin that set some of the metadata,
+/// starting with a property naming the plugin.
+pub fn metadata_fragments(
+    metadata: HashMap<String, String>,
+    sources: &HashMap<String, String>,
+    by_plugin: bool,
+) -> Vec<PropertiesFragment> {
+    if !by_plugin {
+        return vec![metadata_fragment(metadata, sources)];
+    }
+
+    let visible = metadata
+        .into_iter()
+        .filter(|(key, _)| !key.starts_with('_'))
+        .collect();
+    let groups = group_metadata(visible, sources);
+    if groups.is_empty() {
+        return vec![metadata_fragment(HashMap::new(), sources)];
+    }
+
+    groups
+        .into_iter()
+        .map(|(plugin, metadata)| match plugin {
+            Some(plugin) => PropertiesFragment::new(format!(
+                "{METADATA_FRAGMENT}-{}",
+                Property::sanitize_name(&plugin, "-")
+            ))
+            .with_properties(vec![Property::with_value(
+                "plugin".to_string(),
+                "Source Plugin".to_string(),
+                PropertyValue::Value(plugin),
+            )])
+            .with_properties(metadata_fragment(metadata, &HashMap::new()).properties),
+            None => metadata_fragment(metadata, &HashMap::new()),
+        })
+        .collect()
+}
+
+/// Builds the details fragment for a processed node.
+pub fn node_details_fragment(node: &Node, role: Option<&str>) -> PropertiesFragment {
+    PropertiesFragment::new(DETAILS_FRAGMENT.to_owned())
+        .with_properties(generic_details(
+            &node.name,
+            ObjectID::Node(node.link_id.clone()),
+        ))
+        .with_properties(
+            role.map(|role| {
+                Property::with_value("role".to_owned(), "Role".to_owned(), role.to_owned().into())
+            })
             .into_iter()
-            .filter_map(|(key, val)| {
-                if key.starts_with('_') {
-                    None
+            .collect(),
+        )
+        .with_properties(
+            node.alt_names
+                .iter()
+                .map(|n| {
+                    Property::with_value(
+                        "alt_name".to_owned(),
+                        "Alt Name".to_owned(),
+                        n.to_owned().into(),
+                    )
+                })
+                .collect(),
+        )
+        .with_properties(
+            node.plugins
+                .iter()
+                .map(|p| {
+                    Property::with_value(
+                        "plugin".to_owned(),
+                        "Plugin".to_owned(),
+                        p.to_owned().into(),
+                    )
+                })
+                .collect(),
+        )
+}
+
+/// Builds the fragment linking a processed node to the nodes it depends on and the nodes that depend on it.
+pub fn dependencies_fragment(link_id: &str, dependencies: &[Dependency]) -> PropertiesFragment {
+    PropertiesFragment::new(DEPENDENCIES_FRAGMENT.to_string()).with_properties(
+        dependencies
+            .iter()
+            .map(|dep| {
+                let (name, title) = if dep.source == link_id {
+                    ("depends-on", "Depends On")
                 } else {
-                    Some(Property::with_value(
-                        Property::sanitize_name(&key, "-").to_string(),
-                        key.to_string(),
-                        PropertyValue::Value(val),
-                    ))
-                }
+                    ("required-by", "Required By")
+                };
+                let title = match &dep.label {
+                    Some(label) => format!("{title} ({label})"),
+                    None => title.to_string(),
+                };
+                Property::with_value(
+                    name.to_string(),
+                    title,
+                    PropertyValue::XRef(Box::new(XRef::docid(node_id_to_docid(
+                        dep.other(link_id),
+                    )))),
+                )
             })
             .collect(),
     )
 }
 
-// From impls
+/// Builds the fragment linking a processed node to the nodes it has relations to and from.
+/// Relations from other nodes are titled with their type followed by "(from)".
+pub fn relations_fragment(link_id: &str, relations: &[Relation]) -> PropertiesFragment {
+    PropertiesFragment::new(RELATIONS_FRAGMENT.to_string()).with_properties(
+        relations
+            .iter()
+            .map(|relation| {
+                let name = Property::sanitize_name(&relation.kind, "-");
+                let (name, title) = if relation.source == link_id {
+                    (name.to_string(), relation.kind.clone())
+                } else {
+                    (format!("{name}-from"), format!("{} (from)", relation.kind))
+                };
+                Property::with_value(
+                    name,
+                    title,
+                    PropertyValue::XRef(Box::new(XRef::docid(node_id_to_docid(
+                        relation.other(link_id),
+                    )))),
+                )
+            })
+            .collect(),
+    )
+}
 
-impl From<DNSRecord> for PropertiesFragment {
-    fn from(value: DNSRecord) -> Self {
-        let pattern = Regex::new("[^a-zA-Z0-9_=,&.-]").unwrap();
-        let mut id = pattern
-            .replace_all(
-                &format!("{}_{}_{}", value.plugin, value.rtype, value.value),
-                "_",
-            )
-            .to_string();
+/// Builds a fragment for each service a processed node exposes,
+/// listing its port and protocol, its details, and the plugins that declared it.
+pub fn service_fragments(services: &[Service]) -> Vec<PropertiesFragment> {
+    services
+        .iter()
+        .map(|service| {
+            let id = service.id();
+            let details = service.details.iter().sorted().map(|(key, value)| {
+                Property::with_value(
+                    Property::sanitize_name(key, "-").to_string(),
+                    key.to_string(),
+                    PropertyValue::Value(value.to_string()),
+                )
+            });
+            let plugins = service.plugins.iter().sorted().map(|plugin| {
+                Property::with_value(
+                    "plugin".to_string(),
+                    "Plugin".to_string(),
+                    PropertyValue::Value(plugin.to_string()),
+                )
+            });
+
+            PropertiesFragment::new(fragment_id("service", &[&service.link_id, &id]))
+                .with_properties(
+                    [Property::with_value(
+                        "service".to_string(),
+                        "Service".to_string(),
+                        PropertyValue::Value(id),
+                    )]
+                    .into_iter()
+                    .chain(details)
+                    .chain(plugins)
+                    .collect(),
+                )
+        })
+        .collect()
+}
+
+/// Builds the fragment linking to the names in other networks that a DNS name maps to.
+pub fn translations_fragment<'a>(
+    translations: impl IntoIterator<Item = &'a String>,
+) -> PropertiesFragment {
+    PropertiesFragment::new(TRANSLATIONS_FRAGMENT.to_string()).with_properties(
+        translations
+            .into_iter()
+            .sorted()
+            .map(|qname| {
+                Property::with_value(
+                    "translation".to_string(),
+                    "Network Translation".to_string(),
+                    PropertyValue::XRef(Box::new(XRef::docid(dns_qname_to_docid(qname)))),
+                )
+            })
+            .collect(),
+    )
+}
+
+pub fn notes_fragment(notes: HashMap<String, String>) -> PropertiesFragment {
+    PropertiesFragment::new(NOTES_FRAGMENT.to_string()).with_properties(
+        notes
+            .into_iter()
+            .sorted()
+            .map(|(key, val)| {
+                Property::with_value(
+                    Property::sanitize_name(&key, "-").to_string(),
+                    key,
+                    PropertyValue::Value(val),
+                )
+            })
+            .collect(),
+    )
+}
+
+/// Converts some data into a fragment, applying the display settings of its source plugin.
+pub fn data_fragment(data: Data, displays: &HashMap<String, PluginDisplay>) -> Fragments {
+    let Some(display) = displays.get(data.plugin()) else {
+        return Fragments::from(data);
+    };
+
+    let mut fragment = Fragments::from(match &display.name {
+        Some(name) => data.with_plugin(name.clone()),
+        None => data,
+    });
+
+    match &mut fragment {
+        Fragments::Fragment(frag) => frag.labels = display.labels(),
+        Fragments::Properties(frag) => frag.labels = display.labels(),
+        Fragments::Xref(_) | Fragments::Media(()) => {}
+    }
+
+    fragment
+}
 
-        if id.chars().count() > 250 {
-            id = id.chars().take(250).collect();
+/// Converts some data on a node into a fragment, and returns it with the section it belongs in.
+/// Status data is displayed as a banner in its own section, labelled with the state and its colour.
+pub fn node_data_fragment(
+    data: Data,
+    displays: &HashMap<String, PluginDisplay>,
+) -> (Fragments, &'static str) {
+    let Some(state) = data.status_state() else {
+        return (data_fragment(data, displays), PDATA_SECTION);
+    };
+
+    let mut labels = format!(
+        "status-{},colour-{}",
+        Property::sanitize_name(&state.to_lowercase(), "-"),
+        status_colour(state)
+    );
+    let mut fragment = data_fragment(data, displays);
+    if let Fragments::Properties(frag) = &mut fragment {
+        if let Some(display_labels) = &frag.labels {
+            labels = format!("{labels},{display_labels}");
+        }
+        frag.labels = Some(labels);
+    }
+
+    (fragment, STATUS_SECTION)
+}
+
+// Fragment IDs
+
+/// Longest fragment ID that was generated before IDs were hashed.
+const LEGACY_FRAGMENT_ID_LEN: usize = 250;
+
+/// Returns a fragment ID that depends only on the identity of what the fragment displays.
+/// The identity is hashed, so the ID is always valid for PageSeeder, has a fixed length,
+/// and does not change unless the identity does.
+pub fn fragment_id(kind: &str, identity: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in identity {
+        // Prefixing each part with its length keeps the parts from running together.
+        hasher.update(part.len().to_le_bytes());
+        hasher.update(part.as_bytes());
+    }
+
+    let hash = hasher
+        .finalize()
+        .iter()
+        .take(16)
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    format!("{kind}_{hash}")
+}
+
+/// Returns the ID of the fragment for a DNS record.
+/// A record is identified by its plugin, type and value.
+pub fn record_fragment_id(record: &DNSRecords) -> String {
+    match record {
+        DNSRecords::Actual(record) => {
+            fragment_id("record", &[&record.plugin, &record.rtype, &record.value])
+        }
+        DNSRecords::Implied(record) => {
+            fragment_id("implied", &[&record.plugin, &record.rtype, &record.value])
+        }
+    }
+}
+
+/// Returns the ID of the fragment for some plugin data, which is identified by its plugin and ID.
+pub fn data_fragment_id(plugin: &str, data_id: &str) -> String {
+    fragment_id("data", &[plugin, data_id])
+}
+
+/// Returns the ID that the fragment for a DNS record had before IDs were hashed.
+/// Different records could share this ID once it was truncated.
+pub fn legacy_record_fragment_id(record: &DNSRecords) -> String {
+    let pattern = Regex::new("[^a-zA-Z0-9_=,&.-]").unwrap();
+    let id = match record {
+        DNSRecords::Actual(record) => {
+            format!("{}_{}_{}", record.plugin, record.rtype, record.value)
+        }
+        DNSRecords::Implied(record) => {
+            format!(
+                "implied_{}_{}_{}",
+                record.plugin, record.rtype, record.value
+            )
         }
+    };
+
+    pattern
+        .replace_all(&id, "_")
+        .chars()
+        .take(LEGACY_FRAGMENT_ID_LEN)
+        .collect()
+}
 
+// From impls
+
+impl From<DNSRecord> for PropertiesFragment {
+    fn from(value: DNSRecord) -> Self {
+        let id = record_fragment_id(&DNSRecords::Actual(value.clone()));
         let pval = match value.rtype.as_ref() {
-            "CNAME" | "A" | "PTR" | "NAT" => {
+            "CNAME" | "A" | "AAAA" | "PTR" | "NAT" => {
                 PropertyValue::XRef(Box::new(XRef::docid(dns_qname_to_docid(&value.value))))
             }
             _ => PropertyValue::Value(value.value),
@@ -583,18 +1457,7 @@ impl From<DNSRecord> for PropertiesFragment {
 
 impl From<ImpliedDNSRecord> for PropertiesFragment {
     fn from(value: ImpliedDNSRecord) -> Self {
-        let pattern = Regex::new("[^a-zA-Z0-9_=,&.-]").unwrap();
-        let mut id = pattern
-            .replace_all(
-                &format!("implied_{}_{}_{}", value.plugin, value.rtype, value.value),
-                "_",
-            )
-            .to_string();
-
-        if id.chars().count() > 250 {
-            id = id.chars().take(250).collect();
-        }
-
+        let id = record_fragment_id(&DNSRecords::Implied(value.clone()));
         PropertiesFragment::new(id).with_properties(vec![
             Property::with_value(
                 "value".to_string(),
@@ -642,7 +1505,7 @@ impl From<Data> for Fragments {
                 content,
             } => match content_type {
                 ST::Plain => F::Fragment(
-                    Fragment::new(id)
+                    Fragment::new(data_fragment_id(&plugin, &id))
                         .with_content(vec![
                             FC::Heading(Heading {
                                 level: 2,
@@ -656,7 +1519,7 @@ impl From<Data> for Fragments {
                         .with_content(vec![FC::Para(Para::new(vec![ParaContent::Text(content)]))]),
                 ),
                 ST::Code => F::Fragment(
-                    Fragment::new(id)
+                    Fragment::new(data_fragment_id(&plugin, &id))
                         .with_content(vec![
                             FC::Heading(Heading {
                                 level: 2,
@@ -672,7 +1535,20 @@ impl From<Data> for Fragments {
                         }]),
                 ),
                 ST::Markdown => todo!("Convert markdown text to psml"),
-                ST::HtmlMarkup => todo!("Convert HtmlMarkup text to psml"),
+                ST::HtmlMarkup => F::Fragment(
+                    Fragment::new(data_fragment_id(&plugin, &id))
+                        .with_content(vec![
+                            FC::Heading(Heading {
+                                level: 2,
+                                content: vec![CS::Text(title)],
+                            }),
+                            FC::Heading(Heading {
+                                level: 3,
+                                content: vec![CS::Text(format!("Source Plugin: {plugin}"))],
+                            }),
+                        ])
+                        .with_content(html::html_to_psml(&content)),
+                ),
             },
             D::Hash {
                 id,
@@ -680,7 +1556,7 @@ impl From<Data> for Fragments {
                 plugin,
                 content,
             } => F::Properties(
-                PropertiesFragment::new(id)
+                PropertiesFragment::new(data_fragment_id(&plugin, &id))
                     .with_properties(vec![
                         Property::with_value(
                             "data-title".to_string(),
@@ -712,7 +1588,7 @@ impl From<Data> for Fragments {
                 plugin,
                 content,
             } => F::Properties(
-                PropertiesFragment::new(id)
+                PropertiesFragment::new(data_fragment_id(&plugin, &id))
                     .with_properties(vec![
                         Property::with_value(
                             "data-title".to_string(),
@@ -757,7 +1633,10 @@ impl From<Data> for Fragments {
                 let mut table = Table::basic(columns, cells, title);
                 table.summary = Some(format!("Source: {plugin}"));
 
-                F::Fragment(Fragment::new(id).with_content(vec![FC::Table(table)]))
+                F::Fragment(
+                    Fragment::new(data_fragment_id(&plugin, &id))
+                        .with_content(vec![FC::Table(table)]),
+                )
             }
         }
     }
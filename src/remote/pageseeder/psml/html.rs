@@ -0,0 +1,567 @@
+use psml::{
+    model::{FragmentContent, Table, TableCell, TableColumn, TablePart, TableRow},
+    text::{
+        Bold, CharacterStyle, Heading, Italic, Monospace, Para, ParaContent, Subscript,
+        Superscript, Underline,
+    },
+};
+
+/// Elements which never have content or a closing tag.
+const VOID_ELEMENTS: [&str; 9] = [
+    "area", "br", "col", "embed", "hr", "img", "input", "meta", "wbr",
+];
+
+/// Elements whose content is dropped entirely.
+const DROPPED_ELEMENTS: [&str; 6] = ["head", "iframe", "noscript", "script", "style", "template"];
+
+/// Elements which start a new block of content.
+const BLOCK_ELEMENTS: [&str; 25] = [
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "main",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+];
+
+/// A parsed HTML node.
+#[derive(Debug, PartialEq, Eq)]
+enum Node {
+    Text(String),
+    Element {
+        name: String,
+        href: Option<String>,
+        children: Vec<Node>,
+    },
+}
+
+/// Converts a string of HTML markup into PSML fragment content.
+/// Only a safe subset of HTML is mapped to PSML: paragraphs, headings, preformatted text,
+/// lists, tables and basic character styles.
+/// Any other elements are replaced by their text content,
+/// and scripts, styles and similar are removed.
+pub fn html_to_psml(html: &str) -> Vec<FragmentContent> {
+    let mut builder = BlockBuilder::default();
+    builder.add_nodes(&parse(html), 0);
+    builder.finish()
+}
+
+// Parsing
+
+/// Parses HTML into a tree of nodes, tolerating unclosed and unbalanced tags.
+fn parse(html: &str) -> Vec<Node> {
+    // Stack of open elements; the root is at index 0.
+    let mut stack: Vec<(String, Option<String>, Vec<Node>)> =
+        vec![(String::new(), None, Vec::new())];
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            push_text(&mut stack, rest);
+            break;
+        };
+        push_text(&mut stack, &rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = match comment.find("-->") {
+                Some(end) => &comment[end + 3..],
+                None => "",
+            };
+            continue;
+        }
+
+        let Some(end) = rest.find('>') else {
+            push_text(&mut stack, rest);
+            break;
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        if let Some(name) = tag.strip_prefix('/') {
+            close_element(&mut stack, &tag_name(name));
+        } else if tag.starts_with('!') || tag.starts_with('?') {
+            // Doctype or processing instruction.
+            continue;
+        } else {
+            let name = tag_name(tag);
+            if name.is_empty() {
+                push_text(&mut stack, &format!("<{tag}>"));
+            } else if DROPPED_ELEMENTS.contains(&name.as_str()) {
+                let close = format!("</{name}");
+                rest = match rest.to_ascii_lowercase().find(&close) {
+                    Some(idx) => match rest[idx..].find('>') {
+                        Some(end) => &rest[idx + end + 1..],
+                        None => "",
+                    },
+                    None => "",
+                };
+            } else if VOID_ELEMENTS.contains(&name.as_str()) || tag.ends_with('/') {
+                let (_, _, children) = stack.last_mut().unwrap();
+                children.push(Node::Element {
+                    name,
+                    href: None,
+                    children: vec![],
+                });
+            } else {
+                let href = match name.as_str() {
+                    "a" => attribute(tag, "href"),
+                    _ => None,
+                };
+                stack.push((name, href, Vec::new()));
+            }
+        }
+    }
+
+    while stack.len() > 1 {
+        pop_element(&mut stack);
+    }
+    stack.pop().unwrap().2
+}
+
+/// Returns the lowercase name of a tag given its content.
+fn tag_name(tag: &str) -> String {
+    tag.chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Returns the value of an attribute in a tag, if present.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut search = 0;
+    while let Some(idx) = lower[search..].find(name) {
+        let idx = search + idx;
+        search = idx + name.len();
+
+        if !lower[..idx].ends_with(char::is_whitespace) {
+            continue;
+        }
+        let value = tag[search..].trim_start();
+        let Some(value) = value.strip_prefix('=') else {
+            continue;
+        };
+        let value = value.trim_start();
+
+        return Some(unescape(match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let value = &value[1..];
+                &value[..value.find(quote).unwrap_or(value.len())]
+            }
+            _ => value
+                .split(|c: char| c.is_whitespace() || c == '/')
+                .next()
+                .unwrap_or_default(),
+        }));
+    }
+    None
+}
+
+fn push_text(stack: &mut [(String, Option<String>, Vec<Node>)], text: &str) {
+    if !text.is_empty() {
+        let (_, _, children) = stack.last_mut().unwrap();
+        children.push(Node::Text(unescape(text)));
+    }
+}
+
+/// Closes the most recently opened element with the given name, and any elements opened after it.
+/// Closing tags with no matching open element are ignored.
+fn close_element(stack: &mut Vec<(String, Option<String>, Vec<Node>)>, name: &str) {
+    if let Some(idx) = stack.iter().skip(1).rposition(|(open, _, _)| open == name) {
+        while stack.len() > idx + 1 {
+            pop_element(stack);
+        }
+    }
+}
+
+fn pop_element(stack: &mut Vec<(String, Option<String>, Vec<Node>)>) {
+    let (name, href, children) = stack.pop().unwrap();
+    let (_, _, parent) = stack.last_mut().unwrap();
+    parent.push(Node::Element {
+        name,
+        href,
+        children,
+    });
+}
+
+/// Replaces HTML character references with the characters they represent.
+/// Unknown references are left as is.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .map(|end| &rest[1..end + 1]);
+
+        let char = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => match entity.strip_prefix('#') {
+                Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16)
+                    .ok()
+                    .and_then(char::from_u32),
+                Some(dec) => dec.parse().ok().and_then(char::from_u32),
+                None => None,
+            },
+        });
+
+        match (entity, char) {
+            (Some(entity), Some(char)) => {
+                unescaped.push(char);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    unescaped.push_str(rest);
+    unescaped
+}
+
+// Conversion
+
+/// Accumulates block level PSML content.
+#[derive(Default)]
+struct BlockBuilder {
+    content: Vec<FragmentContent>,
+    para: Vec<CharacterStyle>,
+}
+
+impl BlockBuilder {
+    /// Adds nodes to the content, nested at the given list depth.
+    fn add_nodes(&mut self, nodes: &[Node], depth: u8) {
+        for node in nodes {
+            match node {
+                Node::Text(text) => {
+                    push_style(&mut self.para, CharacterStyle::Text(collapse(text)))
+                }
+                Node::Element { name, children, .. } => match name.as_str() {
+                    "p" | "dt" | "dd" | "figcaption" => {
+                        self.flush();
+                        self.para = inline(children);
+                        self.flush();
+                    }
+                    "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                        self.flush();
+                        let content = trim(inline(children));
+                        if !content.is_empty() {
+                            self.content.push(FragmentContent::Heading(Heading {
+                                level: name[1..].parse().unwrap_or(1),
+                                content,
+                            }));
+                        }
+                    }
+                    "pre" => {
+                        self.flush();
+                        self.content.push(FragmentContent::Preformat {
+                            child: vec![CharacterStyle::Text(text_content(children, true))],
+                        });
+                    }
+                    "ul" | "ol" => {
+                        self.flush();
+                        self.add_list(children, name == "ol", depth + 1);
+                    }
+                    "table" => {
+                        self.flush();
+                        if let Some(table) = table(children) {
+                            self.content.push(FragmentContent::Table(table));
+                        }
+                    }
+                    "br" | "hr" => self.flush(),
+                    _ if BLOCK_ELEMENTS.contains(&name.as_str()) => {
+                        self.flush();
+                        self.add_nodes(children, depth);
+                        self.flush();
+                    }
+                    _ => {
+                        for style in inline(std::slice::from_ref(node)) {
+                            push_style(&mut self.para, style);
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    /// Adds the items of a list as prefixed paragraphs.
+    fn add_list(&mut self, nodes: &[Node], ordered: bool, depth: u8) {
+        let mut number = 0;
+        for node in nodes {
+            match node {
+                Node::Element { name, children, .. } if name == "li" => {
+                    number += 1;
+                    let prefix = match ordered {
+                        true => format!("{number}."),
+                        false => String::from("•"),
+                    };
+
+                    let (text, nested): (Vec<_>, Vec<_>) = children.iter().partition(|child| {
+                        !matches!(child, Node::Element { name, .. } if name == "ul" || name == "ol")
+                    });
+
+                    let content = trim(inline_refs(&text));
+                    self.content.push(FragmentContent::Para(Para {
+                        indent: Some(depth),
+                        numbered: None,
+                        prefix: Some(prefix),
+                        content: content.into_iter().map(para_content).collect(),
+                    }));
+
+                    for list in nested {
+                        if let Node::Element { name, children, .. } = list {
+                            self.add_list(children, name == "ol", depth + 1);
+                        }
+                    }
+                }
+                Node::Element { name, children, .. } if name == "ul" || name == "ol" => {
+                    self.add_list(children, name == "ol", depth + 1);
+                }
+                Node::Element { .. } => self.add_nodes(std::slice::from_ref(node), depth),
+                Node::Text(_) => {}
+            }
+        }
+        self.flush();
+    }
+
+    /// Moves any pending inline content into a paragraph.
+    fn flush(&mut self) {
+        let content = trim(std::mem::take(&mut self.para));
+        if !content.is_empty() {
+            self.content.push(FragmentContent::Para(Para::new(
+                content.into_iter().map(para_content).collect(),
+            )));
+        }
+    }
+
+    fn finish(mut self) -> Vec<FragmentContent> {
+        self.flush();
+        self.content
+    }
+}
+
+/// Converts nodes to inline character styles.
+fn inline(nodes: &[Node]) -> Vec<CharacterStyle> {
+    inline_refs(&nodes.iter().collect::<Vec<_>>())
+}
+
+fn inline_refs(nodes: &[&Node]) -> Vec<CharacterStyle> {
+    let mut styles = vec![];
+    for node in nodes {
+        match node {
+            Node::Text(text) => push_style(&mut styles, CharacterStyle::Text(collapse(text))),
+            Node::Element {
+                name,
+                href,
+                children,
+            } => {
+                let content = inline(children);
+                let style = match name.as_str() {
+                    "b" | "strong" => CharacterStyle::Bold(Bold::new(content)),
+                    "i" | "em" | "cite" | "var" => CharacterStyle::Italic(Italic::new(content)),
+                    "u" | "ins" => CharacterStyle::Underline(Underline::new(content)),
+                    "sub" => CharacterStyle::Subscript(Subscript::new(content)),
+                    "sup" => CharacterStyle::Superscript(Superscript::new(content)),
+                    "code" | "kbd" | "samp" | "tt" => {
+                        CharacterStyle::Monospace(Monospace::new(content))
+                    }
+                    "br" => CharacterStyle::Text(String::from("\n")),
+                    "a" => {
+                        let text = text_content(children, false);
+                        for style in content {
+                            push_style(&mut styles, style);
+                        }
+                        match href {
+                            Some(href) if safe_href(href) && text.trim() != href => {
+                                CharacterStyle::Text(format!(" ({href})"))
+                            }
+                            _ => continue,
+                        }
+                    }
+                    _ => {
+                        let block = BLOCK_ELEMENTS.contains(&name.as_str()) || name == "li";
+                        if block {
+                            push_style(&mut styles, CharacterStyle::Text(String::from(" ")));
+                        }
+                        for style in content {
+                            push_style(&mut styles, style);
+                        }
+                        if block {
+                            push_style(&mut styles, CharacterStyle::Text(String::from(" ")));
+                        }
+                        continue;
+                    }
+                };
+                push_style(&mut styles, style);
+            }
+        }
+    }
+    styles
+}
+
+/// Converts the children of a table element to a table.
+/// Returns none if the table has no cells.
+fn table(nodes: &[Node]) -> Option<Table> {
+    let mut rows = vec![];
+    collect_rows(nodes, None, &mut rows);
+
+    let cols = rows.iter().map(|row| row.cells.len()).max().unwrap_or(0);
+    if cols == 0 {
+        return None;
+    }
+
+    Some(Table {
+        caption: None,
+        role: None,
+        summary: None,
+        height: None,
+        width: None,
+        cols: (0..cols).map(|_| TableColumn::default()).collect(),
+        rows,
+    })
+}
+
+fn collect_rows(nodes: &[Node], part: Option<TablePart>, rows: &mut Vec<TableRow>) {
+    for node in nodes {
+        if let Node::Element { name, children, .. } = node {
+            match name.as_str() {
+                "thead" => collect_rows(children, Some(TablePart::Header), rows),
+                "tbody" => collect_rows(children, Some(TablePart::Body), rows),
+                "tfoot" => collect_rows(children, Some(TablePart::Footer), rows),
+                "tr" => rows.push(TableRow {
+                    part: part.clone(),
+                    cells: children
+                        .iter()
+                        .filter_map(|cell| match cell {
+                            Node::Element { name, children, .. }
+                                if name == "td" || name == "th" =>
+                            {
+                                Some(TableCell {
+                                    content: trim(inline(children)),
+                                    ..Default::default()
+                                })
+                            }
+                            _ => None,
+                        })
+                        .collect(),
+                    ..Default::default()
+                }),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Returns the text content of some nodes.
+/// Whitespace is preserved if `preserve` is true.
+fn text_content(nodes: &[Node], preserve: bool) -> String {
+    let mut text = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(content) if preserve => text.push_str(content),
+            Node::Text(content) => text.push_str(&collapse(content)),
+            Node::Element { name, .. } if name == "br" => text.push('\n'),
+            Node::Element { children, .. } => text.push_str(&text_content(children, preserve)),
+        }
+    }
+    text
+}
+
+/// Returns true if the link target uses a scheme that is safe to display.
+fn safe_href(href: &str) -> bool {
+    let lower = href.to_lowercase();
+    ["http://", "https://", "mailto:"]
+        .iter()
+        .any(|scheme| lower.starts_with(scheme))
+}
+
+/// Collapses runs of whitespace into a single space.
+fn collapse(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut space = false;
+    for char in text.chars() {
+        if char.is_whitespace() {
+            if !space {
+                collapsed.push(' ');
+            }
+            space = true;
+        } else {
+            collapsed.push(char);
+            space = false;
+        }
+    }
+    collapsed
+}
+
+/// Appends a style, merging adjacent text and collapsing whitespace across the boundary.
+fn push_style(styles: &mut Vec<CharacterStyle>, style: CharacterStyle) {
+    match (styles.last_mut(), style) {
+        (Some(CharacterStyle::Text(prev)), CharacterStyle::Text(text)) => {
+            if prev.ends_with(' ') && text.starts_with(' ') {
+                prev.push_str(&text[1..]);
+            } else {
+                prev.push_str(&text);
+            }
+        }
+        (_, style) => styles.push(style),
+    }
+}
+
+/// Removes leading and trailing whitespace from inline content,
+/// returning nothing if the content is only whitespace.
+fn trim(mut styles: Vec<CharacterStyle>) -> Vec<CharacterStyle> {
+    if let Some(CharacterStyle::Text(text)) = styles.first_mut() {
+        *text = text.trim_start().to_string();
+    }
+    if let Some(CharacterStyle::Text(text)) = styles.last_mut() {
+        *text = text.trim_end().to_string();
+    }
+    styles.retain(|style| !matches!(style, CharacterStyle::Text(text) if text.is_empty()));
+    styles
+}
+
+fn para_content(style: CharacterStyle) -> ParaContent {
+    match style {
+        CharacterStyle::Text(text) => ParaContent::Text(text),
+        CharacterStyle::Bold(bold) => ParaContent::Bold(bold),
+        CharacterStyle::Italic(italic) => ParaContent::Italic(italic),
+        CharacterStyle::Underline(underline) => ParaContent::Underline(underline),
+        CharacterStyle::Subscript(sub) => ParaContent::Subscript(sub),
+        CharacterStyle::Superscript(sup) => ParaContent::Superscript(sup),
+        CharacterStyle::Monospace(mono) => ParaContent::Monospace(mono),
+        CharacterStyle::XRef(xref) => ParaContent::XRef(*xref),
+        CharacterStyle::Link(link) => ParaContent::Link(link),
+    }
+}
@@ -1,7 +1,6 @@
 use std::sync::LazyLock;
 
 use async_trait::async_trait;
-use paris::warn;
 use psml::{
     model::{
         BlockXRef, Document, Fragment, FragmentContent, Fragments, PropertiesFragment, Property,
@@ -10,6 +9,7 @@ use psml::{
     text::{CharacterStyle, Para, ParaContent},
 };
 use regex::{Regex, RegexBuilder};
+use tracing::warn;
 
 use crate::{
     data::{DataConn, DataStore},
@@ -0,0 +1,87 @@
+use psml::{
+    model::{Document, DocumentInfo, Fragment, FragmentContent, Fragments, Section, URIDescriptor},
+    text::{CharacterStyle, Heading, Para, ParaContent},
+};
+
+use crate::{
+    data::model::RunLock,
+    error::{NetdoxError, NetdoxResult},
+    io_err,
+    remote::pageseeder::remote::{PUBLISH_LOCK_DOCID, PUBLISH_LOCK_FRAGMENT},
+};
+
+const TITLE_SECTION_ID: &str = "title";
+const TITLE_FRAGMENT_ID: &str = "title";
+const MAIN_HEADING: &str = "Netdox Publish Lock";
+const SUB_HEADING: &str = "DO NOT EDIT THIS FILE";
+const WARNING: &str = "This document should be modified by netdox ONLY. \
+    It records the publish that is writing to this group, so that no other publish writes at the same time.";
+
+const CONTENT_SECTION_ID: &str = "content";
+pub const PUBLISH_LOCK_DOC_TYPE: &str = "netdox_lock";
+
+/// Generates the fragment that holds a publish lock, or is empty if the lock is free.
+pub fn publish_lock_fragment(lock: Option<&RunLock>) -> NetdoxResult<Fragment> {
+    let text = match lock.map(serde_json::to_string).transpose() {
+        Ok(text) => text.unwrap_or_default(),
+        Err(err) => return io_err!(format!("Failed to serialize publish lock: {err}")),
+    };
+    Ok(
+        Fragment::new(PUBLISH_LOCK_FRAGMENT.to_string()).with_content(vec![FragmentContent::Para(
+            Para::new(vec![ParaContent::Text(text)]),
+        )]),
+    )
+}
+
+/// Generates the document that holds a publish lock.
+pub fn publish_lock_document(lock: Option<&RunLock>) -> NetdoxResult<Document> {
+    use CharacterStyle as CS;
+    use FragmentContent as FC;
+    use ParaContent as PC;
+
+    Ok(Document {
+        doc_type: Some(PUBLISH_LOCK_DOC_TYPE.to_string()),
+        lockstructure: Some(true),
+        edit: Some(false),
+        doc_info: Some(DocumentInfo {
+            uri: Some(URIDescriptor {
+                docid: Some(PUBLISH_LOCK_DOCID.to_string()),
+                title: Some(MAIN_HEADING.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        sections: vec![
+            Section::new(TITLE_SECTION_ID.to_string()).with_fragments(vec![Fragments::Fragment(
+                Fragment::new(TITLE_FRAGMENT_ID.to_string()).with_content(vec![
+                    FC::Heading(Heading {
+                        level: 1,
+                        content: vec![CS::Text(MAIN_HEADING.to_string())],
+                    }),
+                    FC::Heading(Heading {
+                        level: 2,
+                        content: vec![CS::Text(SUB_HEADING.to_string())],
+                    }),
+                    FC::Para(Para {
+                        content: vec![PC::Text(WARNING.to_string())],
+                        ..Default::default()
+                    }),
+                ]),
+            )]),
+            Section::new(CONTENT_SECTION_ID.to_string())
+                .with_fragments(vec![Fragments::Fragment(publish_lock_fragment(lock)?)]),
+        ],
+        ..Default::default()
+    })
+}
+
+/// Parses the publish lock from the text of its fragment, which is empty if the lock is free.
+pub fn parse_publish_lock(text: &str) -> NetdoxResult<Option<RunLock>> {
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
+    match serde_json::from_str(text) {
+        Ok(lock) => Ok(Some(lock)),
+        Err(err) => io_err!(format!("Failed to parse publish lock: {err}")),
+    }
+}
@@ -0,0 +1,427 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use psml::{
+    model::{
+        Document, DocumentInfo, Fragment, FragmentContent, Fragments, PropertiesFragment, Property,
+        PropertyValue, Section, Table, TablePart, URIDescriptor, XRef,
+    },
+    text::{CharacterStyle, Heading, Para, ParaContent},
+};
+
+use crate::{
+    data::{
+        model::{
+            qname_address, reverse_zone, split_qname, Change, ChangelogEntry, Node, DNS_KEY,
+            LOCATIONS_META_KEY, PROC_NODES_KEY,
+        },
+        DataConn, DataStore,
+    },
+    error::NetdoxResult,
+    remote::pageseeder::remote::{dns_qname_to_docid, network_overview_docid, node_id_to_docid},
+};
+
+pub const OVERVIEW_DOC_TYPE: &str = "netdox_overview";
+
+const TITLE_SECTION_ID: &str = "title";
+const TITLE_FRAGMENT_ID: &str = "title";
+const WARNING: &str = "This document is generated by netdox every time it publishes changes. \
+    Any edits will be overwritten.";
+
+const SUMMARY_SECTION_ID: &str = "summary";
+const COUNTS_FRAGMENT_ID: &str = "counts";
+const NEWEST_SECTION_ID: &str = "newest";
+const NEWEST_DNS_FRAGMENT_ID: &str = "newest-dns";
+const NEWEST_NODES_FRAGMENT_ID: &str = "newest-nodes";
+const INDEX_SECTION_ID: &str = "indexes";
+const ZONES_FRAGMENT_ID: &str = "zones";
+const LOCATIONS_FRAGMENT_ID: &str = "locations";
+const CHANGES_SECTION_ID: &str = "recent-changes";
+const CHANGES_FRAGMENT_ID: &str = "changes";
+
+/// Maximum number of newest objects and recent changes listed in an overview.
+const MAX_LISTED: usize = 20;
+/// Changes made within this long ago are used for the newest objects and recent changes.
+const RECENT_WINDOW: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// A summary of one logical network, for its overview document.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct NetworkOverview {
+    pub network: String,
+    pub dns_names: usize,
+    /// Number of DNS names that are IPv4 addresses.
+    pub addresses: usize,
+    /// Number of processed nodes with a DNS name in the network.
+    pub nodes: usize,
+    /// Number of DNS names in each zone.
+    pub zones: BTreeMap<String, usize>,
+    /// Number of DNS names in each location.
+    pub locations: BTreeMap<String, usize>,
+    /// Newest DNS names that still exist, newest first.
+    pub newest_dns: Vec<String>,
+    /// Link IDs of the nodes with the newest plugin nodes, newest first.
+    pub newest_nodes: Vec<String>,
+    /// Recent changes to objects in the network, newest first.
+    pub changes: Vec<ChangelogEntry>,
+}
+
+/// Returns the zone of a DNS name, without the network.
+/// Addresses are in their reverse zone, and other names are in the domain
+/// their first label is under, unless that is a top-level domain.
+pub fn dns_zone(qname: &str) -> Option<String> {
+    if let Some(addr) = qname_address(qname) {
+        return Some(reverse_zone(&addr));
+    }
+
+    let (_, name) = split_qname(qname)?;
+    match name.split_once('.') {
+        Some((_, parent)) if parent.contains('.') => Some(parent.to_string()),
+        _ => Some(name.to_string()),
+    }
+}
+
+/// Returns the networks of the objects a change is to.
+/// Nodes are in the networks of their DNS names.
+fn change_networks(change: &Change, node_networks: &HashMap<&str, HashSet<&str>>) -> Vec<String> {
+    let qname_networks = |qnames: Vec<&str>| {
+        qnames
+            .into_iter()
+            .filter_map(|qname| split_qname(qname).map(|(network, _)| network.to_string()))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect()
+    };
+    let node_networks = |link_id: &str| {
+        node_networks
+            .get(link_id)
+            .map(|networks| networks.iter().map(|net| (*net).to_string()).collect())
+            .unwrap_or_default()
+    };
+
+    match change {
+        Change::CreateDnsName { qname, .. } | Change::DeleteDnsName { qname, .. } => {
+            qname_networks(vec![qname])
+        }
+        Change::CreateDnsRecord { record, .. } | Change::DeleteDnsRecord { record, .. } => {
+            qname_networks(vec![&record.name])
+        }
+        // Plugin node IDs are their sorted DNS names.
+        Change::CreatePluginNode { node_id, .. } | Change::DeletePluginNode { node_id, .. } => {
+            qname_networks(node_id.split(';').collect())
+        }
+        Change::DeleteProcNode { link_id, .. } => node_networks(link_id),
+        Change::UpdatedNetworkMapping { source, .. } => qname_networks(vec![source]),
+        Change::UpdatedDependency {
+            source: link_id, ..
+        }
+        | Change::UpdatedRelation {
+            source: link_id, ..
+        }
+        | Change::UpdatedService { link_id, .. } => node_networks(link_id),
+        Change::CreatedData { obj_id, .. }
+        | Change::UpdatedData { obj_id, .. }
+        | Change::DeletedData { obj_id, .. }
+        | Change::UpdatedMetadata { obj_id, .. }
+        | Change::UpdatedNotes { obj_id, .. }
+        | Change::UpdatedTags { obj_id, .. } => match obj_id.split_once(';') {
+            Some((DNS_KEY, qname)) => qname_networks(vec![qname]),
+            Some((PROC_NODES_KEY, link_id)) => node_networks(link_id),
+            _ => vec![],
+        },
+        Change::Init
+        | Change::CreateReport { .. }
+        | Change::BeginBatch { .. }
+        | Change::EndBatch { .. } => vec![],
+    }
+}
+
+/// Summarises some networks from the DNS names, their locations, the processed nodes,
+/// and the recent changes from the changelog in order.
+pub fn build_overviews(
+    networks: &HashSet<String>,
+    qnames: &HashSet<String>,
+    locations: &HashMap<String, String>,
+    nodes: &[Node],
+    changes: &[ChangelogEntry],
+) -> Vec<NetworkOverview> {
+    let mut overviews = networks
+        .iter()
+        .map(|network| {
+            (
+                network.as_str(),
+                NetworkOverview {
+                    network: network.clone(),
+                    ..Default::default()
+                },
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    for qname in qnames {
+        let Some(overview) = split_qname(qname).and_then(|(net, _)| overviews.get_mut(net)) else {
+            continue;
+        };
+        overview.dns_names += 1;
+        if qname_address(qname).is_some() {
+            overview.addresses += 1;
+        }
+        if let Some(zone) = dns_zone(qname) {
+            *overview.zones.entry(zone).or_default() += 1;
+        }
+        if let Some(location) = locations.get(qname) {
+            *overview.locations.entry(location.clone()).or_default() += 1;
+        }
+    }
+
+    let mut node_networks: HashMap<&str, HashSet<&str>> = HashMap::new();
+    let mut qname_nodes: HashMap<&str, &str> = HashMap::new();
+    for node in nodes {
+        let networks = node
+            .dns_names
+            .iter()
+            .filter_map(|qname| split_qname(qname).map(|(network, _)| network))
+            .collect::<HashSet<_>>();
+        for network in &networks {
+            if let Some(overview) = overviews.get_mut(network) {
+                overview.nodes += 1;
+            }
+        }
+        for qname in &node.dns_names {
+            qname_nodes.insert(qname, &node.link_id);
+        }
+        node_networks.insert(&node.link_id, networks);
+    }
+
+    for entry in changes.iter().rev() {
+        for network in change_networks(&entry.change, &node_networks) {
+            let Some(overview) = overviews.get_mut(network.as_str()) else {
+                continue;
+            };
+            if overview.changes.len() < MAX_LISTED {
+                overview.changes.push(entry.clone());
+            }
+
+            // Objects created recently that have since been removed are not listed.
+            let newest = match &entry.change {
+                Change::CreateDnsName { qname, .. } if qnames.contains(qname) => {
+                    Some((&mut overview.newest_dns, qname.as_str()))
+                }
+                Change::CreatePluginNode { node_id, .. } => node_id
+                    .split(';')
+                    .find(|qname| split_qname(qname).is_some_and(|(net, _)| net == network))
+                    .and_then(|qname| qname_nodes.get(qname))
+                    .map(|link_id| (&mut overview.newest_nodes, *link_id)),
+                _ => None,
+            };
+            if let Some((newest, id)) = newest {
+                if newest.len() < MAX_LISTED && !newest.iter().any(|listed| listed == id) {
+                    newest.push(id.to_string());
+                }
+            }
+        }
+    }
+
+    overviews.into_values().collect()
+}
+
+/// Returns every network that is registered or has a DNS name.
+pub async fn overview_networks(con: &mut DataStore) -> NetdoxResult<HashSet<String>> {
+    let mut networks = con.get_networks().await?;
+    for qname in con.get_dns_names().await? {
+        if let Some((network, _)) = split_qname(&qname) {
+            networks.insert(network.to_string());
+        }
+    }
+    Ok(networks)
+}
+
+/// Summarises every network that is registered or has a DNS name, from the data store.
+pub async fn network_overviews(con: &mut DataStore) -> NetdoxResult<Vec<NetworkOverview>> {
+    let qnames = con.get_dns_names().await?;
+    let networks = overview_networks(con).await?;
+    let mut locations = HashMap::new();
+    for qname in &qnames {
+        if let Some(location) = con
+            .get_dns_metadata(qname)
+            .await?
+            .remove(LOCATIONS_META_KEY)
+        {
+            locations.insert(qname.clone(), location);
+        }
+    }
+
+    let mut nodes = vec![];
+    for id in con.get_node_ids().await? {
+        nodes.push(con.get_node(&id).await?);
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let start = format!("{}-0", now.saturating_sub(RECENT_WINDOW).as_millis());
+    let changes = con.get_changes(Some(&start)).await?;
+
+    Ok(build_overviews(
+        &networks, &qnames, &locations, &nodes, &changes,
+    ))
+}
+
+/// Returns a table with a header row.
+fn table(caption: &str, header: [&str; 2], rows: Vec<[String; 2]>) -> Table {
+    let mut cells = vec![header.map(str::to_string).to_vec()];
+    cells.extend(rows.into_iter().map(|row| row.to_vec()));
+    let mut table = Table::basic(2, cells, caption.to_string());
+    if let Some(header) = table.rows.first_mut() {
+        header.part = Some(TablePart::Header);
+    }
+    table
+}
+
+/// Returns a property linking to a document.
+fn xref_property(name: &str, title: &str, docid: String) -> Property {
+    let mut xref = XRef::docid(docid);
+    // Objects should not all link back to the overview.
+    xref.reverselink = false;
+    Property::with_value(
+        name.to_string(),
+        title.to_string(),
+        PropertyValue::XRef(Box::new(xref)),
+    )
+}
+
+/// Returns the time a change was made as a date and time in UTC.
+fn change_time(id: &str) -> String {
+    id.split('-')
+        .next()
+        .and_then(|millis| millis.parse::<i64>().ok())
+        .and_then(chrono::DateTime::from_timestamp_millis)
+        .map(|time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_else(|| id.to_string())
+}
+
+/// Returns the overview document of a network.
+pub fn overview_document(overview: &NetworkOverview) -> Document {
+    use FragmentContent as FC;
+    use Fragments as F;
+
+    let title = format!("Network Overview: {}", overview.network);
+    let counts = [
+        ("dns-names", "DNS Names", overview.dns_names),
+        ("addresses", "IPv4 Addresses", overview.addresses),
+        ("nodes", "Nodes", overview.nodes),
+        ("zones", "Zones", overview.zones.len()),
+        ("locations", "Locations", overview.locations.len()),
+    ];
+
+    let changes = overview
+        .changes
+        .iter()
+        .map(|entry| {
+            [
+                change_time(&entry.id),
+                format!(
+                    "{} by {}",
+                    String::from(&entry.change),
+                    entry.change.plugin()
+                ),
+            ]
+        })
+        .collect();
+
+    Document {
+        doc_type: Some(OVERVIEW_DOC_TYPE.to_string()),
+        lockstructure: Some(true),
+        edit: Some(false),
+        doc_info: Some(DocumentInfo {
+            uri: Some(URIDescriptor {
+                docid: Some(network_overview_docid(&overview.network)),
+                title: Some(title.clone()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        sections: vec![
+            Section::new(TITLE_SECTION_ID.to_string()).with_fragments(vec![F::Fragment(
+                Fragment::new(TITLE_FRAGMENT_ID.to_string()).with_content(vec![
+                    FC::Heading(Heading {
+                        level: 1,
+                        content: vec![CharacterStyle::Text(title)],
+                    }),
+                    FC::Para(Para::new(vec![ParaContent::Text(WARNING.to_string())])),
+                ]),
+            )]),
+            Section::new(SUMMARY_SECTION_ID.to_string()).with_fragments(vec![F::Properties(
+                PropertiesFragment::new(COUNTS_FRAGMENT_ID.to_string()).with_properties(
+                    counts
+                        .into_iter()
+                        .map(|(name, title, count)| {
+                            Property::with_value(
+                                name.to_string(),
+                                title.to_string(),
+                                PropertyValue::Value(count.to_string()),
+                            )
+                        })
+                        .collect(),
+                ),
+            )]),
+            Section::new(NEWEST_SECTION_ID.to_string()).with_fragments(vec![
+                F::Properties(
+                    PropertiesFragment::new(NEWEST_DNS_FRAGMENT_ID.to_string()).with_properties(
+                        overview
+                            .newest_dns
+                            .iter()
+                            .map(|qname| {
+                                xref_property("dns-name", "New DNS Name", dns_qname_to_docid(qname))
+                            })
+                            .collect(),
+                    ),
+                ),
+                F::Properties(
+                    PropertiesFragment::new(NEWEST_NODES_FRAGMENT_ID.to_string()).with_properties(
+                        overview
+                            .newest_nodes
+                            .iter()
+                            .map(|id| xref_property("node", "New Node", node_id_to_docid(id)))
+                            .collect(),
+                    ),
+                ),
+            ]),
+            Section::new(INDEX_SECTION_ID.to_string()).with_fragments(vec![
+                F::Fragment(Fragment::new(ZONES_FRAGMENT_ID.to_string()).with_content(
+                    vec![FC::Table(
+                        table(
+                            "Zones",
+                            ["Zone", "DNS Names"],
+                            overview
+                                .zones
+                                .iter()
+                                .map(|(zone, count)| [zone.clone(), count.to_string()])
+                                .collect(),
+                        ),
+                    )],
+                )),
+                F::Fragment(
+                    Fragment::new(LOCATIONS_FRAGMENT_ID.to_string()).with_content(vec![FC::Table(
+                        table(
+                            "Locations",
+                            ["Location", "DNS Names"],
+                            overview
+                                .locations
+                                .iter()
+                                .map(|(location, count)| [location.clone(), count.to_string()])
+                                .collect(),
+                        ),
+                    )]),
+                ),
+            ]),
+            Section::new(CHANGES_SECTION_ID.to_string()).with_fragments(vec![F::Fragment(
+                Fragment::new(CHANGES_FRAGMENT_ID.to_string()).with_content(vec![FC::Table(
+                    table("Recent Changes", ["Time", "Change"], changes),
+                )]),
+            )]),
+        ],
+        ..Default::default()
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use psml::{
+    model::{
+        Document, DocumentInfo, Fragment, FragmentContent, Fragments, PropertiesFragment, Property,
+        PropertyValue, Section, URIDescriptor, XRef,
+    },
+    text::{CharacterStyle, Heading, Para, ParaContent},
+};
+
+use crate::{
+    data::{
+        model::{group_subnets, qname_address, Subnet, NODE_ID_META_KEY},
+        DataConn, DataStore,
+    },
+    error::NetdoxResult,
+    remote::pageseeder::remote::{dns_qname_to_docid, node_id_to_docid, subnet_docid},
+};
+
+use super::fragment_id;
+
+pub const SUBNET_DOC_TYPE: &str = "netdox_subnet";
+
+const TITLE_SECTION_ID: &str = "title";
+const TITLE_FRAGMENT_ID: &str = "title";
+const WARNING: &str = "This document is generated by netdox every time it publishes changes. \
+    Any edits will be overwritten.";
+
+const DETAILS_SECTION_ID: &str = "details";
+const DETAILS_FRAGMENT_ID: &str = "details";
+const MEMBERS_SECTION_ID: &str = "addresses";
+
+/// Groups every address in the data store into subnets, using the configured subnets.
+pub async fn subnets(con: &mut DataStore) -> NetdoxResult<Vec<Subnet>> {
+    let configured = con.get_subnets().await?.into_iter().collect::<Vec<_>>();
+    let qnames = con.get_dns_names().await?;
+
+    let mut dns_nodes = HashMap::new();
+    for qname in qnames.iter().filter(|qname| qname_address(qname).is_some()) {
+        if let Some(link_id) = con.get_dns_metadata(qname).await?.remove(NODE_ID_META_KEY) {
+            dns_nodes.insert(qname.clone(), link_id);
+        }
+    }
+
+    Ok(group_subnets(&qnames, &configured, &dns_nodes))
+}
+
+/// Returns a property linking to a document.
+fn xref_property(name: &str, title: &str, docid: String) -> Property {
+    let mut xref = XRef::docid(docid);
+    // Addresses and nodes should not all link back to their subnet.
+    xref.reverselink = false;
+    Property::with_value(
+        name.to_string(),
+        title.to_string(),
+        PropertyValue::XRef(Box::new(xref)),
+    )
+}
+
+/// Returns the document of a subnet, listing its addresses and the nodes they resolve to.
+pub fn subnet_document(subnet: &Subnet) -> Document {
+    use FragmentContent as FC;
+    use Fragments as F;
+
+    let title = format!("Subnet: {} [{}]", subnet.net, subnet.network);
+    let details = [
+        ("network", "Network", subnet.network.clone()),
+        ("subnet", "Subnet", subnet.net.to_string()),
+        (
+            "source",
+            "Source",
+            if subnet.configured {
+                "Configured"
+            } else {
+                "Reverse zone"
+            }
+            .to_string(),
+        ),
+        ("addresses", "Addresses", subnet.members.len().to_string()),
+    ];
+
+    let members = subnet
+        .members
+        .iter()
+        .map(|member| {
+            F::Properties(
+                PropertiesFragment::new(fragment_id("address", &[&member.qname])).with_properties(
+                    vec![
+                        xref_property("address", "Address", dns_qname_to_docid(&member.qname)),
+                        match &member.node {
+                            Some(link_id) => {
+                                xref_property("node", "Node", node_id_to_docid(link_id))
+                            }
+                            None => Property::with_value(
+                                "node".to_string(),
+                                "Node".to_string(),
+                                PropertyValue::Value("None".to_string()),
+                            ),
+                        },
+                    ],
+                ),
+            )
+        })
+        .collect();
+
+    Document {
+        doc_type: Some(SUBNET_DOC_TYPE.to_string()),
+        lockstructure: Some(true),
+        edit: Some(false),
+        doc_info: Some(DocumentInfo {
+            uri: Some(URIDescriptor {
+                docid: Some(subnet_docid(&subnet.network, &subnet.net)),
+                title: Some(title.clone()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        sections: vec![
+            Section::new(TITLE_SECTION_ID.to_string()).with_fragments(vec![F::Fragment(
+                Fragment::new(TITLE_FRAGMENT_ID.to_string()).with_content(vec![
+                    FC::Heading(Heading {
+                        level: 1,
+                        content: vec![CharacterStyle::Text(title)],
+                    }),
+                    FC::Para(Para::new(vec![ParaContent::Text(WARNING.to_string())])),
+                ]),
+            )]),
+            Section::new(DETAILS_SECTION_ID.to_string()).with_fragments(vec![F::Properties(
+                PropertiesFragment::new(DETAILS_FRAGMENT_ID.to_string()).with_properties(
+                    details
+                        .into_iter()
+                        .map(|(name, title, value)| {
+                            Property::with_value(
+                                name.to_string(),
+                                title.to_string(),
+                                PropertyValue::Value(value),
+                            )
+                        })
+                        .collect(),
+                ),
+            )]),
+            Section::new(MEMBERS_SECTION_ID.to_string()).with_fragments(members),
+        ],
+        ..Default::default()
+    }
+}
@@ -1,15 +1,35 @@
 use psml::{
-    model::{PropertiesFragment, Property, PropertyValue, SectionContent},
-    text::{CharacterStyle, Monospace, Para, ParaContent},
+    model::{
+        Document, DocumentInfo, FragmentContent, Fragments, PropertiesFragment, Property,
+        PropertyValue, Section, SectionContent, URIDescriptor,
+    },
+    text::{Bold, CharacterStyle, Heading, Italic, Monospace, Para, ParaContent},
 };
 
-use super::{dns_name_document, processed_node_document};
+use super::html::html_to_psml;
+use super::overview::{build_overviews, dns_zone, NetworkOverview};
+use super::{
+    archived_dns_document, continuation_docid, data_fragment, data_fragment_id,
+    deleted_dns_document, deleted_node_document, dependencies_fragment, dns_name_document,
+    dns_search_tokens, document_size, fragment_id, legacy_record_fragment_id, metadata_fragments,
+    node_data_fragment, overview_document, processed_node_document, record_fragment_id,
+    relations_fragment, service_fragments, split_document, split_report, subnet_document,
+    tag_labels, translations_fragment, ARCHIVED_LABEL, CONTINUATIONS_FRAGMENT, DETAILS_FRAGMENT,
+    METADATA_FRAGMENT, NOTES_SECTION, PDATA_SECTION, STATUS_SECTION,
+};
 use crate::{
-    data::{model::Node, DataStore},
+    data::{
+        model::{
+            Change, ChangelogEntry, DNSRecord, DNSRecords, Data, Dependency, Node, PluginDisplay,
+            Relation, Service, StringType, Subnet, SubnetMember,
+        },
+        DataStore,
+    },
     remote::pageseeder::psml::{links::LinkContent, EXTRAS_SECTION, SEARCH_TOKENS_FRAGMENT},
-    tests_common::{setup_db_con, PLUGIN},
+    tests_common::{setup_db_con, setup_memory_con, PLUGIN},
 };
-use std::collections::HashSet;
+use indexmap::IndexMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use quick_xml::se as xml_se;
 
@@ -17,6 +37,11 @@ async fn backend() -> DataStore {
     DataStore::Redis(setup_db_con().await)
 }
 
+/// Returns a data store in memory, so the documents are also tested without redis.
+async fn memory_backend() -> DataStore {
+    DataStore::Memory(setup_memory_con().await)
+}
+
 #[test]
 fn test_pfrag_se() {
     assert_eq!(
@@ -53,8 +78,540 @@ fn test_para_se() {
     )
 }
 
+#[test]
+fn test_data_fragment_display() {
+    let displays = HashMap::from([(
+        PLUGIN.to_string(),
+        PluginDisplay {
+            name: Some("Test Plugin".to_string()),
+            label: Some("test".to_string()),
+            colour: Some("red".to_string()),
+        },
+    )]);
+
+    let data = Data::Hash {
+        id: "hash-id".to_string(),
+        title: "Hash Title".to_string(),
+        plugin: PLUGIN.to_string(),
+        content: IndexMap::new(),
+    };
+
+    let Fragments::Properties(fragment) = data_fragment(data, &displays) else {
+        panic!("Hash data did not produce a properties fragment.");
+    };
+
+    assert_eq!(Some("test,colour-red".to_string()), fragment.labels);
+    assert!(fragment.properties.iter().any(|prop| {
+        prop.name == "plugin" && prop.values == [PropertyValue::Value("Test Plugin".to_string())]
+    }));
+}
+
+#[test]
+fn test_node_data_fragment_status() {
+    let status = Data::Hash {
+        id: "status".to_string(),
+        title: "Status".to_string(),
+        plugin: PLUGIN.to_string(),
+        content: IndexMap::from([
+            ("state".to_string(), "Down".to_string()),
+            ("last_checked".to_string(), "2024-01-01 00:00".to_string()),
+        ]),
+    };
+
+    let (Fragments::Properties(fragment), section) = node_data_fragment(status, &HashMap::new())
+    else {
+        panic!("Status data did not produce a properties fragment.");
+    };
+    assert_eq!(section, STATUS_SECTION);
+    assert_eq!(Some("status-down,colour-red".to_string()), fragment.labels);
+
+    let other = Data::Hash {
+        id: "other".to_string(),
+        title: "Other".to_string(),
+        plugin: PLUGIN.to_string(),
+        content: IndexMap::from([("state".to_string(), "up".to_string())]),
+    };
+    assert_eq!(node_data_fragment(other, &HashMap::new()).1, PDATA_SECTION);
+}
+
+#[test]
+fn test_dependencies_fragment() {
+    let dependencies = [
+        Dependency::new("app", "db", "database".to_string()),
+        Dependency::new("proxy", "app", String::new()),
+    ];
+
+    let fragment = dependencies_fragment("app", &dependencies);
+    let props = fragment
+        .properties
+        .iter()
+        .map(|prop| {
+            (
+                prop.name.as_str(),
+                prop.title.as_deref().unwrap_or_default(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        props,
+        vec![
+            ("depends-on", "Depends On (database)"),
+            ("required-by", "Required By")
+        ]
+    );
+}
+
+#[test]
+fn test_relations_fragment() {
+    let relation = |source: &str, dest: &str, kind: &str| Relation {
+        source: source.to_string(),
+        dest: dest.to_string(),
+        kind: kind.to_string(),
+    };
+    let relations = [
+        relation("replica", "vm", "replicates-to"),
+        relation("vm", "host", "vm-on-host"),
+    ];
+
+    let fragment = relations_fragment("vm", &relations);
+    let props = fragment
+        .properties
+        .iter()
+        .map(|prop| {
+            (
+                prop.name.as_str(),
+                prop.title.as_deref().unwrap_or_default(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        props,
+        vec![
+            ("replicates-to-from", "replicates-to (from)"),
+            ("vm-on-host", "vm-on-host")
+        ]
+    );
+}
+
+#[test]
+fn test_service_fragments() {
+    let service = Service {
+        link_id: "app".to_string(),
+        port: 443,
+        protocol: "tcp".to_string(),
+        details: HashMap::from([
+            ("version".to_string(), "1.25".to_string()),
+            ("product".to_string(), "nginx".to_string()),
+        ]),
+        plugins: HashSet::from(["scanner".to_string()]),
+    };
+
+    let fragments = service_fragments(&[service]);
+    assert_eq!(fragments.len(), 1);
+    assert_eq!(fragments[0].id, fragment_id("service", &["app", "443/tcp"]));
+
+    let props = fragments[0]
+        .properties
+        .iter()
+        .map(|prop| (prop.name.as_str(), prop.values.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        props,
+        vec![
+            ("service", vec![PropertyValue::Value("443/tcp".to_string())]),
+            ("product", vec![PropertyValue::Value("nginx".to_string())]),
+            ("version", vec![PropertyValue::Value("1.25".to_string())]),
+            ("plugin", vec![PropertyValue::Value("scanner".to_string())]),
+        ]
+    );
+}
+
+#[test]
+fn test_split_document() {
+    let fragment_ids = (0..10).map(|i| format!("data-{i}")).collect::<Vec<_>>();
+    let document = Document {
+        doc_info: Some(DocumentInfo {
+            uri: Some(URIDescriptor {
+                docid: Some("_nd_big".to_string()),
+                title: Some("Big".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        sections: vec![
+            Section::new("title".to_string()),
+            Section::new("details".to_string()),
+            Section::new(NOTES_SECTION.to_string()),
+            Section::new(PDATA_SECTION.to_string()).with_fragments(
+                fragment_ids
+                    .iter()
+                    .map(|id| {
+                        Fragments::Properties(
+                            PropertiesFragment::new(id.to_string()).with_properties(vec![
+                                Property::with_value(
+                                    "value".to_string(),
+                                    "Value".to_string(),
+                                    PropertyValue::Value("x".repeat(500)),
+                                ),
+                            ]),
+                        )
+                    })
+                    .collect(),
+            ),
+        ],
+        ..Default::default()
+    };
+
+    let size = document_size(&document).unwrap();
+    assert_eq!(split_document(document.clone(), size).unwrap().len(), 1);
+
+    let max_bytes = 2000;
+    let parts = split_document(document, max_bytes).unwrap();
+    assert!(parts.len() > 2);
+    assert_eq!(
+        parts[1].docid(),
+        Some(continuation_docid("_nd_big", 0).as_str())
+    );
+
+    let mut moved = vec![];
+    for (index, part) in parts.iter().enumerate() {
+        assert!(document_size(part).unwrap() <= max_bytes + 1000);
+        if index > 0 {
+            assert!(part.get_section(NOTES_SECTION).is_none());
+        }
+
+        for content in &part.get_section(PDATA_SECTION).unwrap().content {
+            if let SectionContent::PropertiesFragment(PropertiesFragment {
+                id, properties, ..
+            }) = content
+            {
+                if id == CONTINUATIONS_FRAGMENT {
+                    assert_eq!(index, 0);
+                    assert_eq!(properties.len(), parts.len() - 1);
+                } else {
+                    moved.push(id.to_string());
+                }
+            }
+        }
+    }
+    assert_eq!(moved, fragment_ids);
+}
+
+#[test]
+fn test_translations_fragment() {
+    let translations = [
+        "[net-b]domain.com".to_string(),
+        "[net-a]domain.com".to_string(),
+    ];
+
+    assert_eq!(
+        "<properties-fragment id=\"translations\">\
+            <property name=\"translation\" title=\"Network Translation\" datatype=\"xref\">\
+                <xref docid=\"_nd_dns__net-a_domain_com\" display=\"document\" frag=\"default\" reverselink=\"true\"/>\
+            </property>\
+            <property name=\"translation\" title=\"Network Translation\" datatype=\"xref\">\
+                <xref docid=\"_nd_dns__net-b_domain_com\" display=\"document\" frag=\"default\" reverselink=\"true\"/>\
+            </property>\
+        </properties-fragment>",
+        xml_se::to_string_with_root(
+            "properties-fragment",
+            &translations_fragment(&translations)
+        )
+        .unwrap()
+    );
+}
+
+#[test]
+fn test_fragment_id() {
+    let record = |value: &str| {
+        DNSRecords::Actual(DNSRecord {
+            name: "[net]domain.com".to_string(),
+            value: value.to_string(),
+            rtype: "TXT".to_string(),
+            plugin: PLUGIN.to_string(),
+        })
+    };
+
+    let id = record_fragment_id(&record("value"));
+    assert_eq!(id, record_fragment_id(&record("value")));
+    assert_ne!(id, record_fragment_id(&record("other value")));
+    assert!(id
+        .chars()
+        .all(|char| char.is_ascii_alphanumeric() || char == '_'));
+
+    // Legacy IDs of long values are truncated, so they collide.
+    let long = "a".repeat(300);
+    assert_eq!(
+        legacy_record_fragment_id(&record(&format!("{long}1"))),
+        legacy_record_fragment_id(&record(&format!("{long}2")))
+    );
+    assert_ne!(
+        record_fragment_id(&record(&format!("{long}1"))),
+        record_fragment_id(&record(&format!("{long}2")))
+    );
+
+    assert_ne!(
+        fragment_id("data", &["ab", "c"]),
+        fragment_id("data", &["a", "bc"])
+    );
+    assert_ne!(data_fragment_id("one", "id"), data_fragment_id("two", "id"));
+}
+
+#[test]
+fn test_archived_dns_document() {
+    let document = archived_dns_document("[old]domain.com", "[new]domain.com");
+
+    let uri = document.doc_info.as_ref().unwrap().uri.as_ref().unwrap();
+    assert_eq!(uri.docid.as_deref(), Some("_nd_dns__old_domain_com"));
+    assert_eq!(uri.labels.as_ref().unwrap().value, ARCHIVED_LABEL);
+
+    let details = match &document.get_section("details").unwrap().content[0] {
+        SectionContent::PropertiesFragment(frag) if frag.id == DETAILS_FRAGMENT => frag,
+        other => panic!("Expected details fragment, found {other:?}"),
+    };
+    let moved_to = details
+        .properties
+        .iter()
+        .find(|prop| prop.name == "moved-to")
+        .unwrap();
+    match moved_to.values.first() {
+        Some(PropertyValue::XRef(xref)) => {
+            assert_eq!(xref.docid.as_deref(), Some("_nd_dns__new_domain_com"))
+        }
+        other => panic!("Expected xref, found {other:?}"),
+    }
+}
+
+#[test]
+fn test_deleted_documents() {
+    for document in [
+        deleted_dns_document("[net]domain.com"),
+        deleted_node_document("server"),
+    ] {
+        let uri = document.doc_info.as_ref().unwrap().uri.as_ref().unwrap();
+        assert_eq!(uri.labels.as_ref().unwrap().value, ARCHIVED_LABEL);
+        assert!(uri.title.as_ref().unwrap().ends_with(" (removed)"));
+    }
+}
+
+#[test]
+fn test_dns_zone() {
+    assert_eq!(dns_zone("[net]host.example.com").unwrap(), "example.com");
+    assert_eq!(dns_zone("[net]example.com").unwrap(), "example.com");
+    assert_eq!(dns_zone("[net]10.1.2.3").unwrap(), "2.1.10.in-addr.arpa");
+    assert_eq!(dns_zone("host.example.com"), None);
+}
+
+#[test]
+fn test_build_overviews() {
+    let set = |items: &[&str]| {
+        items
+            .iter()
+            .map(|item| item.to_string())
+            .collect::<HashSet<_>>()
+    };
+    let entry = |id: &str, change: Change| ChangelogEntry {
+        id: id.to_string(),
+        change,
+    };
+
+    let qnames = set(&[
+        "[lan]a.example.com",
+        "[lan]b.example.com",
+        "[lan]10.0.0.1",
+        "[dmz]c.example.com",
+    ]);
+    let locations = HashMap::from([("[lan]10.0.0.1".to_string(), "hq".to_string())]);
+    let nodes = [Node {
+        name: "Server".to_string(),
+        link_id: "server".to_string(),
+        alt_names: HashSet::new(),
+        dns_names: set(&["[lan]a.example.com", "[lan]10.0.0.1"]),
+        plugins: set(&[PLUGIN]),
+        raw_ids: HashSet::new(),
+    }];
+    let changes = [
+        entry(
+            "1-0",
+            Change::CreateDnsName {
+                plugin: PLUGIN.to_string(),
+                qname: "[lan]a.example.com".to_string(),
+            },
+        ),
+        entry(
+            "2-0",
+            Change::CreateDnsName {
+                plugin: PLUGIN.to_string(),
+                qname: "[lan]removed.example.com".to_string(),
+            },
+        ),
+        entry(
+            "3-0",
+            Change::CreatePluginNode {
+                plugin: PLUGIN.to_string(),
+                node_id: "[lan]a.example.com".to_string(),
+            },
+        ),
+        entry(
+            "4-0",
+            Change::UpdatedMetadata {
+                plugin: PLUGIN.to_string(),
+                obj_id: "proc_nodes;server".to_string(),
+            },
+        ),
+    ];
+
+    let overviews = build_overviews(
+        &set(&["lan", "dmz", "empty"]),
+        &qnames,
+        &locations,
+        &nodes,
+        &changes,
+    );
+    assert_eq!(
+        overviews
+            .iter()
+            .map(|o| o.network.as_str())
+            .collect::<Vec<_>>(),
+        vec!["dmz", "empty", "lan"]
+    );
+    assert_eq!(
+        overviews[1],
+        NetworkOverview {
+            network: "empty".to_string(),
+            ..Default::default()
+        }
+    );
+
+    let lan = &overviews[2];
+    assert_eq!((lan.dns_names, lan.addresses, lan.nodes), (3, 1, 1));
+    assert_eq!(
+        lan.zones,
+        BTreeMap::from([
+            ("example.com".to_string(), 2),
+            ("0.0.10.in-addr.arpa".to_string(), 1)
+        ])
+    );
+    assert_eq!(lan.locations, BTreeMap::from([("hq".to_string(), 1)]));
+    assert_eq!(lan.newest_dns, vec!["[lan]a.example.com"]);
+    assert_eq!(lan.newest_nodes, vec!["server"]);
+    assert_eq!(
+        lan.changes
+            .iter()
+            .map(|e| e.id.as_str())
+            .collect::<Vec<_>>(),
+        vec!["4-0", "3-0", "2-0", "1-0"]
+    );
+    assert!(overviews[0].changes.is_empty());
+
+    let document = overview_document(lan);
+    let uri = document.doc_info.as_ref().unwrap().uri.as_ref().unwrap();
+    assert_eq!(uri.docid.as_deref(), Some("_nd_overview_lan"));
+    assert!(document.get_section("indexes").is_some());
+}
+
+#[test]
+fn test_subnet_document() {
+    let document = subnet_document(&Subnet {
+        network: "lan".to_string(),
+        net: "10.0.0.0/24".parse().unwrap(),
+        configured: false,
+        members: vec![
+            SubnetMember {
+                qname: "[lan]10.0.0.1".to_string(),
+                node: Some("router".to_string()),
+            },
+            SubnetMember {
+                qname: "[lan]10.0.0.2".to_string(),
+                node: None,
+            },
+        ],
+    });
+
+    let uri = document.doc_info.as_ref().unwrap().uri.as_ref().unwrap();
+    assert_eq!(uri.docid.as_deref(), Some("_nd_subnet_lan_10_0_0_0_24"));
+    assert_eq!(uri.title.as_deref(), Some("Subnet: 10.0.0.0/24 [lan]"));
+    assert_eq!(document.get_section("addresses").unwrap().content.len(), 2);
+}
+
+#[test]
+fn test_metadata_fragments() {
+    let metadata = HashMap::from([
+        ("os".to_string(), "linux".to_string()),
+        ("owner".to_string(), "ops".to_string()),
+        ("_hidden".to_string(), "value".to_string()),
+        ("legacy".to_string(), "value".to_string()),
+    ]);
+    let sources = HashMap::from([
+        ("os".to_string(), "inventory plugin".to_string()),
+        ("owner".to_string(), "cmdb".to_string()),
+        ("_hidden".to_string(), "cmdb".to_string()),
+    ]);
+
+    let single = metadata_fragments(metadata.clone(), &sources, false);
+    assert_eq!(single.len(), 1);
+    let titles = single[0]
+        .properties
+        .iter()
+        .filter_map(|prop| prop.title.clone())
+        .collect::<HashSet<_>>();
+    assert_eq!(
+        titles,
+        HashSet::from([
+            "os".to_string(),
+            "os (source)".to_string(),
+            "owner".to_string(),
+            "owner (source)".to_string(),
+            "legacy".to_string(),
+        ])
+    );
+    // The source of a key comes right after it.
+    let os = single[0]
+        .properties
+        .iter()
+        .position(|prop| prop.name == "os")
+        .unwrap();
+    assert_eq!(single[0].properties[os + 1].name, "os-source");
+
+    let fragments = metadata_fragments(metadata, &sources, true)
+        .into_iter()
+        .map(|frag| {
+            (
+                frag.id,
+                frag.properties
+                    .into_iter()
+                    .map(|prop| prop.name)
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        fragments,
+        vec![
+            (METADATA_FRAGMENT.to_string(), vec!["legacy".to_string()]),
+            (
+                format!("{METADATA_FRAGMENT}-cmdb"),
+                vec!["plugin".to_string(), "owner".to_string()]
+            ),
+            (
+                format!("{METADATA_FRAGMENT}-inventory-plugin"),
+                vec!["plugin".to_string(), "os".to_string()]
+            ),
+        ]
+    );
+}
+
 #[tokio::test]
 async fn test_pfrag_links() {
+    pfrag_links(backend().await).await;
+}
+
+#[tokio::test]
+async fn test_pfrag_links_memory() {
+    pfrag_links(memory_backend().await).await;
+}
+
+async fn pfrag_links(mut backend: DataStore) {
     assert_eq!(
         "<properties-fragment id=\"frag-id\">\
             <property name=\"name1\" title=\"First\" datatype=\"xref\">\
@@ -69,7 +626,7 @@ async fn test_pfrag_links() {
                     "First".to_string(),
                     PropertyValue::Value("(!(dns|!|domain.com)!)".to_string()),
                 ),])
-                .create_links(&mut backend().await)
+                .create_links(&mut backend)
                 .await
                 .unwrap(),
         )
@@ -79,14 +636,34 @@ async fn test_pfrag_links() {
 
 #[tokio::test]
 async fn test_dns_doc() {
-    dns_name_document(&mut backend().await, "[doc-network]domain.psml")
+    dns_name_document(&mut backend().await, "[doc-network]domain.psml", false)
         .await
         .unwrap();
 }
 
+#[tokio::test]
+async fn test_dns_doc_memory() {
+    dns_name_document(
+        &mut memory_backend().await,
+        "[doc-network]domain.psml",
+        false,
+    )
+    .await
+    .unwrap();
+}
+
 #[tokio::test]
 async fn test_dns_doc_search_terms() {
-    let doc = dns_name_document(&mut backend().await, "[doc-network]10.11.12.13")
+    dns_doc_search_terms(backend().await).await;
+}
+
+#[tokio::test]
+async fn test_dns_doc_search_terms_memory() {
+    dns_doc_search_terms(memory_backend().await).await;
+}
+
+async fn dns_doc_search_terms(mut backend: DataStore) {
+    let doc = dns_name_document(&mut backend, "[doc-network]10.11.12.13", false)
         .await
         .unwrap();
 
@@ -105,7 +682,7 @@ async fn test_dns_doc_search_terms() {
         })
     {
         assert_eq!(
-            vec!["13", "12.13", "11.12.13"]
+            ["13", "12.13", "11.12.13", "12.11.10.in-addr.arpa"]
                 .iter()
                 .map(|s| PropertyValue::Value(s.to_string()))
                 .collect::<Vec<_>>(),
@@ -116,10 +693,51 @@ async fn test_dns_doc_search_terms() {
     }
 }
 
+#[test]
+fn test_dns_search_tokens() {
+    assert_eq!(
+        dns_search_tokens("[net]www.example.com"),
+        vec!["com".to_string(), "example.com".to_string()]
+    );
+    assert_eq!(
+        dns_search_tokens("[net]192.168.1.20"),
+        vec![
+            "20".to_string(),
+            "1.20".to_string(),
+            "168.1.20".to_string(),
+            "1.168.192.in-addr.arpa".to_string()
+        ]
+    );
+    assert_eq!(
+        dns_search_tokens("[net]2001:db8::1"),
+        vec!["0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa".to_string()]
+    );
+}
+
+#[test]
+fn test_tag_labels() {
+    assert!(tag_labels(HashSet::new()).is_none());
+    assert_eq!(
+        tag_labels(HashSet::from(["web".to_string(), "billing".to_string()]))
+            .unwrap()
+            .value,
+        "billing,web"
+    );
+}
+
 #[tokio::test]
 async fn test_node_doc() {
+    node_doc(backend().await).await;
+}
+
+#[tokio::test]
+async fn test_node_doc_memory() {
+    node_doc(memory_backend().await).await;
+}
+
+async fn node_doc(mut backend: DataStore) {
     processed_node_document(
-        &mut backend().await,
+        &mut backend,
         &Node {
             name: "Node Document".to_string(),
             alt_names: HashSet::from(["Also a Document".to_string()]),
@@ -128,7 +746,131 @@ async fn test_node_doc() {
             raw_ids: HashSet::from(["[doc-network]node.psml".to_string()]),
             link_id: "node-docid-part".to_string(),
         },
+        false,
     )
     .await
     .unwrap();
 }
+
+#[test]
+fn test_split_report() {
+    let list = Data::List {
+        id: "list-id".to_string(),
+        title: "List Title".to_string(),
+        plugin: PLUGIN.to_string(),
+        content: (0..5)
+            .map(|i| (format!("name{i}"), format!("title{i}"), format!("value{i}")))
+            .collect(),
+    };
+    let string = Data::String {
+        id: "str-id".to_string(),
+        title: "String Title".to_string(),
+        plugin: PLUGIN.to_string(),
+        content_type: StringType::Plain,
+        content: "line".to_string(),
+    };
+
+    let parts = split_report(vec![list, string], 2);
+    assert_eq!(
+        parts.iter().map(Vec::len).collect::<Vec<_>>(),
+        vec![1, 1, 2]
+    );
+    assert!(parts.iter().flatten().all(|data| data.rows() <= 2));
+
+    let Data::List { title, .. } = &parts[1][0] else {
+        panic!("Split list data is not a list.");
+    };
+    assert_eq!(title, "List Title (continued)");
+
+    assert_eq!(split_report(vec![], 2).len(), 1);
+}
+
+#[test]
+fn test_html_basic() {
+    let content = html_to_psml(
+        "<h1>Title</h1>\n<p>Some <b>bold</b> and <em>italic</em> text &amp; more.</p>",
+    );
+
+    assert_eq!(
+        content,
+        vec![
+            FragmentContent::Heading(Heading {
+                level: 1,
+                content: vec![CharacterStyle::Text("Title".to_string())]
+            }),
+            FragmentContent::Para(Para::new(vec![
+                ParaContent::Text("Some ".to_string()),
+                ParaContent::Bold(Bold::text("bold".to_string())),
+                ParaContent::Text(" and ".to_string()),
+                ParaContent::Italic(Italic::text("italic".to_string())),
+                ParaContent::Text(" text & more.".to_string()),
+            ]))
+        ]
+    );
+}
+
+#[test]
+fn test_html_unsafe() {
+    let content = html_to_psml(
+        "<script>alert('<p>hi</p>')</script><blink onclick=\"x()\">unknown <span>tags</span></blink>\
+         <a href=\"javascript:evil()\">link</a><a href='https://example.com'>site</a>",
+    );
+
+    assert_eq!(
+        content,
+        vec![FragmentContent::Para(Para::new(vec![ParaContent::Text(
+            "unknown tagslinksite (https://example.com)".to_string()
+        )]))]
+    );
+}
+
+#[test]
+fn test_html_lists_tables() {
+    let content = html_to_psml(
+        "<ol><li>one<ul><li>nested</li></ul></li><li>two</ol>\
+         <table><tr><th>key</th><th>value</th></tr><tr><td>a</td></tr></table>\
+         <pre>  keep\n  spacing &lt;</pre>unclosed <b>bold",
+    );
+
+    let FragmentContent::Para(first) = &content[0] else {
+        panic!("Expected para, got {:?}", content[0]);
+    };
+    assert_eq!(first.prefix.as_deref(), Some("1."));
+    assert_eq!(first.indent, Some(1));
+
+    let FragmentContent::Para(nested) = &content[1] else {
+        panic!("Expected para, got {:?}", content[1]);
+    };
+    assert_eq!(nested.prefix.as_deref(), Some("•"));
+    assert_eq!(nested.indent, Some(2));
+    assert_eq!(
+        nested.content,
+        vec![ParaContent::Text("nested".to_string())]
+    );
+
+    let FragmentContent::Para(second) = &content[2] else {
+        panic!("Expected para, got {:?}", content[2]);
+    };
+    assert_eq!(second.prefix.as_deref(), Some("2."));
+
+    let FragmentContent::Table(table) = &content[3] else {
+        panic!("Expected table, got {:?}", content[3]);
+    };
+    assert_eq!(table.cols.len(), 2);
+    assert_eq!(table.rows.len(), 2);
+
+    assert_eq!(
+        content[4],
+        FragmentContent::Preformat {
+            child: vec![CharacterStyle::Text("  keep\n  spacing <".to_string())]
+        }
+    );
+
+    assert_eq!(
+        content[5],
+        FragmentContent::Para(Para::new(vec![
+            ParaContent::Text("unclosed ".to_string()),
+            ParaContent::Bold(Bold::text("bold".to_string()))
+        ]))
+    );
+}
@@ -0,0 +1,787 @@
+use std::fmt::Display;
+
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+
+/// Block level elements in the PSML schema.
+const BLOCK: &[&str] = &[
+    "block",
+    "blockxref",
+    "heading",
+    "image",
+    "list",
+    "nlist",
+    "para",
+    "preformat",
+    "table",
+];
+
+/// Inline elements in the PSML schema.
+const INLINE: &[&str] = &[
+    "anchor",
+    "bold",
+    "br",
+    "image",
+    "inline",
+    "italic",
+    "link",
+    "monospace",
+    "placeholder",
+    "sub",
+    "sup",
+    "underline",
+    "xref",
+];
+
+/// Block and inline elements, for elements that accept either.
+const MIXED: &[&str] = &[
+    "anchor",
+    "block",
+    "blockxref",
+    "bold",
+    "br",
+    "heading",
+    "image",
+    "inline",
+    "italic",
+    "link",
+    "list",
+    "monospace",
+    "nlist",
+    "para",
+    "placeholder",
+    "preformat",
+    "sub",
+    "sup",
+    "table",
+    "underline",
+    "xref",
+];
+
+/// Attributes of both inline and block cross references.
+const XREF_ATTRS: &[&str] = &[
+    "archived",
+    "config",
+    "display",
+    "docid",
+    "documenttype",
+    "external",
+    "frag",
+    "href",
+    "id",
+    "labels",
+    "level",
+    "mediatype",
+    "reversefrag",
+    "reverselink",
+    "reversetitle",
+    "title",
+    "type",
+    "unresolved",
+    "uriid",
+    "urilabels",
+    "urititle",
+];
+
+/// Attributes that only accept `true` or `false`.
+const BOOLEAN_ATTRS: &[&str] = &[
+    "archived",
+    "edit",
+    "external",
+    "lockstructure",
+    "multiple",
+    "numbered",
+    "overwrite",
+    "reverselink",
+    "unresolved",
+];
+
+/// What an element of the PSML schema may contain.
+struct Rule {
+    name: &'static str,
+    attrs: &'static [&'static str],
+    required: &'static [&'static str],
+    children: &'static [&'static str],
+    /// Whether the element may contain text.
+    text: bool,
+}
+
+/// Content model of the PSML elements that documents uploaded by netdox may contain.
+/// Follows the PSML schema, which is described at: https://dev.pageseeder.com/psml/element_reference.html
+const RULES: &[Rule] = &[
+    Rule {
+        name: "document",
+        attrs: &["date", "edit", "id", "level", "lockstructure", "type"],
+        required: &["level"],
+        children: &["documentinfo", "fragmentinfo", "metadata", "section", "toc"],
+        text: false,
+    },
+    Rule {
+        name: "documentinfo",
+        attrs: &[],
+        required: &[],
+        children: &["publication", "uri", "versions"],
+        text: false,
+    },
+    Rule {
+        name: "uri",
+        attrs: &[
+            "archived",
+            "created",
+            "docid",
+            "documenttype",
+            "external",
+            "folder",
+            "host",
+            "id",
+            "mediatype",
+            "modified",
+            "path",
+            "port",
+            "scheme",
+            "title",
+        ],
+        required: &[],
+        children: &["description", "displaytitle", "labels"],
+        text: false,
+    },
+    Rule {
+        name: "publication",
+        attrs: &["id", "title", "type"],
+        required: &["id"],
+        children: &[],
+        text: false,
+    },
+    Rule {
+        name: "description",
+        attrs: &[],
+        required: &[],
+        children: &[],
+        text: true,
+    },
+    Rule {
+        name: "displaytitle",
+        attrs: &[],
+        required: &[],
+        children: &[],
+        text: true,
+    },
+    Rule {
+        name: "labels",
+        attrs: &[],
+        required: &[],
+        children: &[],
+        text: true,
+    },
+    Rule {
+        name: "fragmentinfo",
+        attrs: &[],
+        required: &[],
+        children: &["locator"],
+        text: false,
+    },
+    Rule {
+        name: "locator",
+        attrs: &["fragment"],
+        required: &["fragment"],
+        children: &["notes", "properties"],
+        text: false,
+    },
+    Rule {
+        name: "notes",
+        attrs: &[],
+        required: &[],
+        children: &["note"],
+        text: false,
+    },
+    Rule {
+        name: "note",
+        attrs: &["id", "labels", "modified", "title"],
+        required: &[],
+        children: &["content", "labels"],
+        text: false,
+    },
+    Rule {
+        name: "content",
+        attrs: &[],
+        required: &[],
+        children: MIXED,
+        text: true,
+    },
+    Rule {
+        name: "section",
+        attrs: &[
+            "edit",
+            "fragmenttype",
+            "id",
+            "lockstructure",
+            "overwrite",
+            "title",
+        ],
+        required: &["id"],
+        children: &[
+            "fragment",
+            "media-fragment",
+            "properties-fragment",
+            "title",
+            "xref-fragment",
+        ],
+        text: false,
+    },
+    Rule {
+        name: "title",
+        attrs: &[],
+        required: &[],
+        children: &[],
+        text: true,
+    },
+    Rule {
+        name: "fragment",
+        attrs: &["id", "labels", "type"],
+        required: &["id"],
+        children: BLOCK,
+        text: true,
+    },
+    Rule {
+        name: "properties-fragment",
+        attrs: &["id", "labels", "type"],
+        required: &["id"],
+        children: &["property"],
+        text: false,
+    },
+    Rule {
+        name: "xref-fragment",
+        attrs: &["id", "labels", "type"],
+        required: &["id"],
+        children: &["blockxref"],
+        text: false,
+    },
+    Rule {
+        name: "media-fragment",
+        attrs: &["id", "labels", "mediatype", "type"],
+        required: &["id"],
+        children: &[],
+        text: true,
+    },
+    Rule {
+        name: "property",
+        attrs: &["count", "datatype", "multiple", "name", "title", "value"],
+        required: &["name"],
+        children: &["link", "markdown", "markup", "value", "xref"],
+        text: false,
+    },
+    Rule {
+        name: "value",
+        attrs: &[],
+        required: &[],
+        children: &[],
+        text: true,
+    },
+    Rule {
+        name: "markdown",
+        attrs: &[],
+        required: &[],
+        children: &[],
+        text: true,
+    },
+    Rule {
+        name: "markup",
+        attrs: &[],
+        required: &[],
+        children: MIXED,
+        text: true,
+    },
+    Rule {
+        name: "blockxref",
+        attrs: XREF_ATTRS,
+        required: &["frag"],
+        children: &[],
+        text: true,
+    },
+    Rule {
+        name: "xref",
+        attrs: XREF_ATTRS,
+        required: &["frag"],
+        children: &[],
+        text: true,
+    },
+    Rule {
+        name: "block",
+        attrs: &["label"],
+        required: &["label"],
+        children: MIXED,
+        text: true,
+    },
+    Rule {
+        name: "heading",
+        attrs: &["id", "level", "numbered", "prefix"],
+        required: &["level"],
+        children: INLINE,
+        text: true,
+    },
+    Rule {
+        name: "para",
+        attrs: &["indent", "numbered", "prefix"],
+        required: &[],
+        children: INLINE,
+        text: true,
+    },
+    Rule {
+        name: "preformat",
+        attrs: &["role"],
+        required: &[],
+        children: INLINE,
+        text: true,
+    },
+    Rule {
+        name: "list",
+        attrs: &["role", "type"],
+        required: &[],
+        children: &["item"],
+        text: false,
+    },
+    Rule {
+        name: "nlist",
+        attrs: &["role", "start", "type"],
+        required: &[],
+        children: &["item"],
+        text: false,
+    },
+    Rule {
+        name: "item",
+        attrs: &[],
+        required: &[],
+        children: MIXED,
+        text: true,
+    },
+    Rule {
+        name: "table",
+        attrs: &["height", "role", "summary", "width"],
+        required: &[],
+        children: &["caption", "col", "row"],
+        text: false,
+    },
+    Rule {
+        name: "caption",
+        attrs: &[],
+        required: &[],
+        children: INLINE,
+        text: true,
+    },
+    Rule {
+        name: "col",
+        attrs: &["align", "part", "role", "width"],
+        required: &[],
+        children: &[],
+        text: false,
+    },
+    Rule {
+        name: "row",
+        attrs: &["align", "part", "role"],
+        required: &[],
+        children: &["cell", "hcell"],
+        text: false,
+    },
+    Rule {
+        name: "cell",
+        attrs: &["align", "colspan", "role", "rowspan", "valign", "width"],
+        required: &[],
+        children: MIXED,
+        text: true,
+    },
+    Rule {
+        name: "hcell",
+        attrs: &["align", "colspan", "role", "rowspan", "valign", "width"],
+        required: &[],
+        children: MIXED,
+        text: true,
+    },
+    Rule {
+        name: "image",
+        attrs: &["alt", "docid", "height", "labels", "src", "uriid", "width"],
+        required: &[],
+        children: &[],
+        text: false,
+    },
+    Rule {
+        name: "link",
+        attrs: &["href", "role"],
+        required: &["href"],
+        children: INLINE,
+        text: true,
+    },
+    Rule {
+        name: "bold",
+        attrs: &[],
+        required: &[],
+        children: INLINE,
+        text: true,
+    },
+    Rule {
+        name: "italic",
+        attrs: &[],
+        required: &[],
+        children: INLINE,
+        text: true,
+    },
+    Rule {
+        name: "underline",
+        attrs: &[],
+        required: &[],
+        children: INLINE,
+        text: true,
+    },
+    Rule {
+        name: "sub",
+        attrs: &[],
+        required: &[],
+        children: INLINE,
+        text: true,
+    },
+    Rule {
+        name: "sup",
+        attrs: &[],
+        required: &[],
+        children: INLINE,
+        text: true,
+    },
+    Rule {
+        name: "monospace",
+        attrs: &[],
+        required: &[],
+        children: INLINE,
+        text: true,
+    },
+    Rule {
+        name: "inline",
+        attrs: &["label"],
+        required: &["label"],
+        children: INLINE,
+        text: true,
+    },
+    Rule {
+        name: "anchor",
+        attrs: &["name"],
+        required: &["name"],
+        children: &[],
+        text: false,
+    },
+    Rule {
+        name: "placeholder",
+        attrs: &["name"],
+        required: &["name"],
+        children: &[],
+        text: true,
+    },
+    Rule {
+        name: "br",
+        attrs: &[],
+        required: &[],
+        children: &[],
+        text: false,
+    },
+];
+
+/// Allowed values of attributes that are an enumeration, by element and attribute name.
+const ENUM_ATTRS: &[(&str, &str, &[&str])] = &[
+    ("document", "level", &["metadata", "portable", "processed"]),
+    ("heading", "level", &["1", "2", "3", "4", "5", "6"]),
+    (
+        "xref",
+        "display",
+        &[
+            "document",
+            "document+manual",
+            "document+fragment",
+            "manual",
+            "template",
+        ],
+    ),
+    (
+        "blockxref",
+        "display",
+        &[
+            "document",
+            "document+manual",
+            "document+fragment",
+            "manual",
+            "template",
+        ],
+    ),
+];
+
+/// A part of a PSML document that does not match the schema.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SchemaViolation {
+    /// Path to the element, with the ID of each element that has one.
+    pub path: String,
+    pub message: String,
+}
+
+impl Display for SchemaViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+fn rule(name: &str) -> Option<&'static Rule> {
+    RULES.iter().find(|rule| rule.name == name)
+}
+
+/// Checks a serialised PSML document against the schema,
+/// and returns every element and attribute that does not match it.
+pub fn validate(xml: &str) -> Vec<SchemaViolation> {
+    let mut reader = Reader::from_str(xml);
+    let mut violations = vec![];
+    // Name of each open element and its path segment.
+    let mut stack: Vec<(String, String)> = vec![];
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(start)) => {
+                let (name, segment) = check_element(&start, &stack, &mut violations);
+                stack.push((name, segment));
+            }
+            Ok(Event::Empty(start)) => {
+                check_element(&start, &stack, &mut violations);
+            }
+            Ok(Event::End(_)) => {
+                stack.pop();
+            }
+            Ok(Event::Text(text)) => {
+                let text = text.unescape().unwrap_or_default();
+                if let Some((parent, _)) = stack.last() {
+                    if !text.trim().is_empty() && rule(parent).is_some_and(|rule| !rule.text) {
+                        violations.push(SchemaViolation {
+                            path: path(&stack),
+                            message: format!("Element {parent} cannot contain text."),
+                        });
+                    }
+                }
+            }
+            Ok(Event::Eof) => break,
+            Ok(_) => {}
+            Err(err) => {
+                violations.push(SchemaViolation {
+                    path: path(&stack),
+                    message: format!("Failed to parse XML: {err}"),
+                });
+                break;
+            }
+        }
+    }
+
+    violations
+}
+
+/// Checks an element against the rules for it and its parent,
+/// and returns its name and path segment.
+fn check_element(
+    start: &BytesStart,
+    stack: &[(String, String)],
+    violations: &mut Vec<SchemaViolation>,
+) -> (String, String) {
+    let name = String::from_utf8_lossy(start.name().as_ref()).to_string();
+    let attrs = start
+        .attributes()
+        .filter_map(Result::ok)
+        .map(|attr| {
+            (
+                String::from_utf8_lossy(attr.key.as_ref()).to_string(),
+                attr.unescape_value().unwrap_or_default().to_string(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let segment = match attrs.iter().find(|(key, _)| key == "id") {
+        Some((_, id)) => format!("{name}[@id='{id}']"),
+        None => name.clone(),
+    };
+    let mut element_path = stack
+        .iter()
+        .map(|(_, seg)| seg.as_str())
+        .collect::<Vec<_>>();
+    element_path.push(&segment);
+    let element_path = element_path.join("/");
+
+    match stack.last() {
+        Some((parent, _))
+            if rule(parent).is_some_and(|rule| !rule.children.contains(&name.as_str())) =>
+        {
+            violations.push(SchemaViolation {
+                path: element_path.clone(),
+                message: format!("Element {name} is not allowed in {parent}."),
+            });
+        }
+        None if name != "document" => violations.push(SchemaViolation {
+            path: element_path.clone(),
+            message: format!("Root element must be document, not {name}."),
+        }),
+        _ => {}
+    }
+
+    let Some(rule) = rule(&name) else {
+        violations.push(SchemaViolation {
+            path: element_path,
+            message: format!("Element {name} is not defined in PSML."),
+        });
+        return (name, segment);
+    };
+
+    for (key, value) in &attrs {
+        if key.starts_with("xmlns") || key.starts_with("xml:") {
+            continue;
+        }
+        if !rule.attrs.contains(&key.as_str()) {
+            violations.push(SchemaViolation {
+                path: element_path.clone(),
+                message: format!("Attribute {key} is not allowed on {name}."),
+            });
+        } else if BOOLEAN_ATTRS.contains(&key.as_str()) && value != "true" && value != "false" {
+            violations.push(SchemaViolation {
+                path: element_path.clone(),
+                message: format!("Attribute {key} must be true or false, not {value:?}."),
+            });
+        } else if let Some((_, _, allowed)) = ENUM_ATTRS
+            .iter()
+            .find(|(element, attr, _)| *element == name && attr == key)
+        {
+            if !allowed.contains(&value.as_str()) {
+                violations.push(SchemaViolation {
+                    path: element_path.clone(),
+                    message: format!(
+                        "Attribute {key} must be one of {}, not {value:?}.",
+                        allowed.join(", ")
+                    ),
+                });
+            }
+        }
+    }
+
+    for required in rule.required {
+        if !attrs.iter().any(|(key, _)| key == required) {
+            violations.push(SchemaViolation {
+                path: element_path.clone(),
+                message: format!("Element {name} is missing required attribute {required}."),
+            });
+        }
+    }
+
+    (name, segment)
+}
+
+/// Returns the path to the innermost open element.
+fn path(stack: &[(String, String)]) -> String {
+    stack
+        .iter()
+        .map(|(_, segment)| segment.as_str())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use indexmap::IndexMap;
+    use psml::model::{Fragment, Fragments, Section};
+    use quick_xml::se as xml_se;
+
+    use super::{validate, SchemaViolation};
+    use crate::{
+        data::model::{Change, ChangelogEntry, Data},
+        remote::pageseeder::psml::{
+            archived_dns_document, data_fragment, deleted_dns_document, deleted_node_document,
+            html::html_to_psml, overview::NetworkOverview, overview_document,
+            translations_fragment,
+        },
+    };
+
+    #[test]
+    fn test_valid_documents() {
+        let mut details = deleted_node_document("server");
+        details.sections.push(
+            Section::new("details".to_string()).with_fragments(vec![
+                Fragments::Fragment(Fragment::new("notes".to_string()).with_content(html_to_psml(
+                    "<h2>Notes</h2><p>Some <b>bold</b>, <code>code</code> and <a href='https://example.com'>a link</a>.</p>\
+                     <ul><li>item</li></ul><table><tr><th>key</th></tr><tr><td>value</td></tr></table><pre>keep</pre>",
+                ))),
+                Fragments::Properties(translations_fragment(&["[net]domain.com".to_string()])),
+                data_fragment(
+                    Data::Hash {
+                        id: "hash".to_string(),
+                        title: "Hash".to_string(),
+                        plugin: "plugin".to_string(),
+                        content: IndexMap::from([("key".to_string(), "value".to_string())]),
+                    },
+                    &HashMap::new(),
+                ),
+            ]),
+        );
+
+        for document in [
+            archived_dns_document("[old]domain.com", "[new]domain.com"),
+            deleted_dns_document("[net]domain.com"),
+            deleted_node_document("server"),
+            details,
+            overview_document(&NetworkOverview {
+                network: "net".to_string(),
+                dns_names: 1,
+                zones: BTreeMap::from([("domain.com".to_string(), 1)]),
+                newest_dns: vec!["[net]domain.com".to_string()],
+                newest_nodes: vec!["server".to_string()],
+                changes: vec![ChangelogEntry {
+                    id: "1700000000000-0".to_string(),
+                    change: Change::Init,
+                }],
+                ..Default::default()
+            }),
+        ] {
+            let xml = xml_se::to_string(&document).unwrap();
+            assert_eq!(validate(&xml), vec![], "{xml}");
+        }
+    }
+
+    #[test]
+    fn test_violations() {
+        let xml = r#"<document level="portable" edit="yes">
+            <section id="details">
+                <fragment id="info"><para>H<subscript>2</subscript>O</para></fragment>
+                <properties-fragment id="props">text<property title="x"/></properties-fragment>
+            </section>
+        </document>"#;
+
+        assert_eq!(
+            validate(xml),
+            vec![
+                SchemaViolation {
+                    path: "document".to_string(),
+                    message: "Attribute edit must be true or false, not \"yes\".".to_string()
+                },
+                SchemaViolation {
+                    path: "document/section[@id='details']/fragment[@id='info']/para/subscript"
+                        .to_string(),
+                    message: "Element subscript is not allowed in para.".to_string()
+                },
+                SchemaViolation {
+                    path: "document/section[@id='details']/fragment[@id='info']/para/subscript"
+                        .to_string(),
+                    message: "Element subscript is not defined in PSML.".to_string()
+                },
+                SchemaViolation {
+                    path: "document/section[@id='details']/properties-fragment[@id='props']"
+                        .to_string(),
+                    message: "Element properties-fragment cannot contain text.".to_string()
+                },
+                SchemaViolation {
+                    path:
+                        "document/section[@id='details']/properties-fragment[@id='props']/property"
+                            .to_string(),
+                    message: "Element property is missing required attribute name.".to_string()
+                },
+            ]
+        );
+    }
+}
@@ -1,92 +1,152 @@
 use std::{
     collections::{hash_map::Entry, HashMap, HashSet},
     io::{Cursor, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    pin::pin,
+    time::{Duration, Instant},
 };
 
 use crate::{
     data::{
         model::{
-            Change, ChangelogEntry, DNSRecords, DataKind, DNS_KEY, NODES_KEY, PDATA_KEY,
-            PROC_NODES_KEY, REPORTS_KEY,
+            coalesce_batches, publish_batches, qname_address, Change, ChangelogEntry, DNSRecords,
+            DataKind, Node, QueuedUpdate, DNS_KEY, NODES_KEY, NODE_META_KEY, PDATA_KEY,
+            PROC_NODES_KEY, REPORTS_KEY, ROLE_META_KEY,
         },
         store::DataStore,
+        stream::{dns_batches, node_batches, BATCH_SIZE},
         DataConn,
     },
     error::{NetdoxError, NetdoxResult},
-    io_err, process_err, redis_err, remote_err,
+    io_err, process_err,
+    progress::Progress,
+    redis_err,
+    remote::{backup_path, check_cursor, swap_cursor, ObjectSelection},
+    remote_err,
+    runs::RunTimer,
+    success,
 };
 
+#[cfg(feature = "psml-validation")]
+use super::psml::validate::validate;
 use super::{
     psml::{
-        changelog_document, dns_name_document, links::LinkContent, metadata_fragment,
-        processed_node_document, remote_config_document, report_document, CHANGELOG_DOC_TYPE,
-        DNS_DOC_TYPE, DNS_RECORD_SECTION, IMPLIED_RECORD_SECTION, METADATA_FRAGMENT, NODE_DOC_TYPE,
-        PDATA_SECTION, RDATA_SECTION, REMOTE_CONFIG_DOC_TYPE, REPORT_DOC_TYPE,
+        address_node_fragment, changelog_document, data_fragment, data_fragment_id,
+        deleted_dns_document, deleted_node_document, dns_name_document, legacy_record_fragment_id,
+        links::LinkContent, metadata_fragment, network_overviews, node_data_fragment,
+        node_details_fragment, notes_fragment, overview_document, overview_networks,
+        processed_node_document, record_fragment_id, remote_config_document, report_document,
+        split_document, subnet_document, subnets, translations_fragment, ADDRESS_NODE_FRAGMENT,
+        CHANGELOG_DOC_TYPE, DETAILS_FRAGMENT, DNS_DOC_TYPE, DNS_RECORD_SECTION,
+        IMPLIED_RECORD_SECTION, METADATA_FRAGMENT, NODE_DOC_TYPE, NOTES_FRAGMENT,
+        OVERVIEW_DOC_TYPE, PDATA_SECTION, PUBLISH_LOCK_DOC_TYPE, RDATA_SECTION,
+        REMOTE_CONFIG_DOC_TYPE, REPORT_DOC_TYPE, SUBNET_DOC_TYPE, TRANSLATIONS_FRAGMENT,
     },
     remote::{
-        dns_qname_to_docid, node_id_to_docid, report_id_to_docid, CHANGELOG_DOCID,
-        CHANGELOG_FRAGMENT,
+        dns_qname_to_docid, network_overview_docid, node_id_to_docid, report_id_to_docid,
+        subnet_docid, CHANGELOG_DOCID, CHANGELOG_FRAGMENT,
     },
     PSRemote,
 };
 use async_trait::async_trait;
 use futures::{
     future::{join_all, BoxFuture},
-    StreamExt,
+    stream, StreamExt, TryStreamExt,
 };
+use itertools::Itertools;
 use pageseeder_api::model::PSError;
-use paris::{success, warn, Logger};
 use psml::{
-    model::{Document, Fragment, FragmentContent, Fragments, PropertiesFragment},
+    model::{Document, Fragment, FragmentContent, Fragments, Labels, PropertiesFragment},
     text::{Para, ParaContent},
 };
 use quick_xml::se as xml_se;
+use tracing::{debug, debug_span, error, info, warn, Instrument};
 use zip::{write::FileOptions, ZipWriter};
 
 const DNS_DIR: &str = "dns";
 const NODE_DIR: &str = "nodes";
 const REPORT_DIR: &str = "reports";
+const SUBNET_DIR: &str = "subnets";
 
-const MAX_DOCID_LEN: usize = 100;
+/// Maximum length of a docid. Documents with longer docids are not published.
+pub const MAX_DOCID_LEN: usize = 100;
+/// Size of a document in bytes to warn about approaching, if no maximum is configured.
+const DEFAULT_DOCUMENT_LIMIT: usize = 10 * 1024 * 1024;
+/// Maximum number of requests to the remote that are in flight at once.
+const MAX_CONCURRENT_UPDATES: usize = 20;
+/// Maximum number of documents generated from the data store at once.
+const MAX_CONCURRENT_DOCUMENTS: usize = 50;
 
 /// Data that can be published by a `PSPublisher`.
 pub enum PublishData<'a> {
     Create {
         target_ids: Vec<String>,
-        document: Box<Document>,
+        documents: Vec<Document>,
     },
     Update {
         target_id: String,
-        future: BoxFuture<'a, NetdoxResult<()>>,
+        future: BoxFuture<'a, NetdoxResult<Vec<QueuedUpdate>>>,
     },
 }
 
+/// Changes that are ready to be applied to the remote.
+pub struct PreparedChanges {
+    /// Fragment updates to apply to existing documents.
+    pub updates: Vec<QueuedUpdate>,
+    /// Documents to upload.
+    pub uploads: Vec<Document>,
+    /// IDs of the objects whose documents will be uploaded.
+    pub upload_ids: HashSet<String>,
+    /// Errors from preparing fragment updates.
+    pub errors: Vec<NetdoxError>,
+}
+
 #[async_trait]
 pub trait PSPublisher {
-    /// Adds a DNS record to relevant document given the changelog change value.
-    async fn add_dns_record(&self, record: DNSRecords) -> NetdoxResult<()>;
+    /// Prepares the update that adds a DNS record to the relevant document.
+    async fn add_dns_record(&self, record: DNSRecords) -> NetdoxResult<Vec<QueuedUpdate>>;
+
+    /// Prepares the updates to the metadata fragment given the change value.
+    async fn update_metadata(
+        &self,
+        mut backend: DataStore,
+        value: &str,
+    ) -> NetdoxResult<Vec<QueuedUpdate>>;
 
-    /// Updates the fragment with the metadata change from the change value.
-    async fn update_metadata(&self, mut backend: DataStore, value: &str) -> NetdoxResult<()>;
+    /// Prepares the update to the operator notes fragment given the changelog change value.
+    async fn update_notes(
+        &self,
+        mut backend: DataStore,
+        obj_id: &str,
+    ) -> NetdoxResult<Vec<QueuedUpdate>>;
 
-    /// Creates the fragment with the data.
+    /// Prepares the update to the network translations fragment of a DNS name.
+    async fn update_translations(
+        &self,
+        mut backend: DataStore,
+        qname: &str,
+    ) -> NetdoxResult<Vec<QueuedUpdate>>;
+
+    /// Prepares the update that creates the fragment with the data.
     async fn create_data(
         &self,
         mut backend: DataStore,
         obj_id: &str,
         data_id: &str,
         kind: &DataKind,
-    ) -> NetdoxResult<()>;
+    ) -> NetdoxResult<Vec<QueuedUpdate>>;
 
-    /// Updates the fragment with the data.
+    /// Prepares the update to the fragment with the data.
     async fn update_data(
         &self,
         mut backend: DataStore,
         obj_id: &str,
         data_id: &str,
         kind: &DataKind,
-    ) -> NetdoxResult<()>;
+    ) -> NetdoxResult<Vec<QueuedUpdate>>;
+
+    /// Applies a single fragment update to the remote.
+    async fn apply_update(&self, update: &QueuedUpdate) -> NetdoxResult<()>;
 
     /// Uploads a set of PSML documents to the server.
     async fn upload_docs(&self, docs: Vec<Document>, backup: Option<PathBuf>) -> NetdoxResult<()>;
@@ -98,34 +158,59 @@ pub trait PSPublisher {
         change: &'a Change,
     ) -> NetdoxResult<Vec<PublishData<'a>>>;
 
-    /// Prepares a set of futures that will apply the given changes.
+    /// Prepares the fragment updates and documents that will apply the given changes.
     async fn prep_changes<'a>(
         &'a self,
         mut con: DataStore,
         changes: HashSet<&'a Change>,
-        backup: Option<PathBuf>,
-    ) -> NetdoxResult<Vec<BoxFuture<'a, NetdoxResult<()>>>>;
+    ) -> NetdoxResult<PreparedChanges>;
 
     /// Applies the given changes to the `PageSeeder` documents on the remote.
     /// Will attempt to update in place where possible.
+    /// Fragment updates are queued in the data store before they are applied,
+    /// and any left over from a previous publish are applied first.
+    /// Changes are applied in batches, and a checkpoint is saved in the data store after each one
+    /// so that an interrupted publish can resume from it.
+    /// The changelog cursor is only advanced if it is still `last_change`,
+    /// and no other publish has advanced it since this one started,
+    /// which is checked atomically in the data store.
     async fn apply_changes<'a>(
         &self,
         mut con: DataStore,
         changes: &'a [ChangelogEntry],
+        last_change: Option<&str>,
         backup: Option<PathBuf>,
+        timer: &mut RunTimer,
     ) -> NetdoxResult<()>;
+
+    /// Returns the IDs of the documents that would be uploaded and the fragments that would be updated
+    /// to apply the given changes, without writing anything to the remote.
+    /// Includes fragment updates left over from a previous publish.
+    async fn plan_changes<'a>(
+        &'a self,
+        mut con: DataStore,
+        changes: &'a [ChangelogEntry],
+    ) -> NetdoxResult<(Vec<String>, Vec<(String, String)>)>;
+
+    /// Generates the whole documents for a batch of DNS names, processed nodes and reports.
+    /// Nodes with an excluded role are skipped.
+    async fn prep_documents(
+        &self,
+        mut con: DataStore,
+        qnames: &[String],
+        nodes: &[Node],
+        reports: &[String],
+    ) -> NetdoxResult<Vec<Document>>;
 }
 
 #[async_trait]
 impl PSPublisher for PSRemote {
-    async fn add_dns_record(&self, record: DNSRecords) -> NetdoxResult<()> {
+    async fn add_dns_record(&self, record: DNSRecords) -> NetdoxResult<Vec<QueuedUpdate>> {
         let docid = dns_qname_to_docid(record.name());
 
         if docid.len() > MAX_DOCID_LEN {
-            Logger::new().warn(format!(
-                "Skip update to document with docid too long: {docid}"
-            ));
-            return Ok(());
+            warn!("Skip update to document with docid too long: {docid}");
+            return Ok(vec![]);
         }
 
         let fragment = PropertiesFragment::from(record.clone());
@@ -135,30 +220,13 @@ impl PSPublisher for PSRemote {
         };
 
         match xml_se::to_string_with_root("properties-fragment", &fragment) {
-            Ok(content) => {
-                match self
-                    .server()
-                    .await?
-                    .add_uri_fragment(
-                        &self.username,
-                        &self.group,
-                        &docid,
-                        &content,
-                        HashMap::from([("section", section), ("fragment", &fragment.id)]),
-                    )
-                    .await
-                {
-                    Err(PSError::ApiError { id, req, msg }) => {
-                        if msg == "The fragment already exists." {
-                            Ok(())
-                        } else {
-                            Err(PSError::ApiError { id, req, msg }.into())
-                        }
-                    }
-                    Err(other_err) => Err(other_err.into()),
-                    Ok(_) => Ok(()),
-                }
-            }
+            Ok(content) => Ok(vec![QueuedUpdate::new(
+                docid,
+                fragment.id,
+                Some(section.to_string()),
+                false,
+                content,
+            )]),
             Err(err) => {
                 io_err!(format!(
                     "Failed to serialise DNS record to PSML: {}",
@@ -169,9 +237,13 @@ impl PSPublisher for PSRemote {
     }
 
     /// Pushes new metadata to the remote.
-    async fn update_metadata(&self, mut backend: DataStore, obj_id: &str) -> NetdoxResult<()> {
+    async fn update_metadata(
+        &self,
+        mut backend: DataStore,
+        obj_id: &str,
+    ) -> NetdoxResult<Vec<QueuedUpdate>> {
         let mut id_parts = obj_id.split(';');
-        let (metadata, docid) = match id_parts.next() {
+        let (mut metadata, docid, node) = match id_parts.next() {
             Some(NODES_KEY) => {
                 if let Some(proc_id) = backend
                     .get_node_from_raw(&id_parts.collect::<Vec<&str>>().join(";"))
@@ -179,32 +251,32 @@ impl PSPublisher for PSRemote {
                 {
                     if let Ok(node) = backend.get_node(&proc_id).await {
                         let metadata = backend.get_node_metadata(&node).await?;
-                        (metadata, node_id_to_docid(&node.link_id))
+                        (metadata, node_id_to_docid(&node.link_id), Some(node))
                     } else {
                         warn!("Failed to retrieve node with id {proc_id} when updating metadata - it is likely the node no longer exists.");
                         let metadata = backend.get_proc_node_metadata(&proc_id).await?;
-                        (metadata, node_id_to_docid(&proc_id))
+                        (metadata, node_id_to_docid(&proc_id), None)
                     }
                 } else {
                     warn!("Wanted to publish changed metadata for unused raw node: {obj_id}");
-                    return Ok(());
+                    return Ok(vec![]);
                 }
             }
             Some(PROC_NODES_KEY) => {
                 let proc_id = id_parts.collect::<Vec<&str>>().join(";");
                 if let Ok(node) = backend.get_node(&proc_id).await {
                     let metadata = backend.get_node_metadata(&node).await?;
-                    (metadata, node_id_to_docid(&node.link_id))
+                    (metadata, node_id_to_docid(&node.link_id), Some(node))
                 } else {
                     warn!("Failed to retrieve node with id {proc_id} when updating metadata - it is likely the node no longer exists.");
                     let metadata = backend.get_proc_node_metadata(&proc_id).await?;
-                    (metadata, node_id_to_docid(&proc_id))
+                    (metadata, node_id_to_docid(&proc_id), None)
                 }
             }
             Some(DNS_KEY) => {
                 let qname = &id_parts.collect::<Vec<&str>>().join(";");
                 let metadata = backend.get_dns_metadata(qname).await?;
-                (metadata, dns_qname_to_docid(qname))
+                (metadata, dns_qname_to_docid(qname), None)
             }
             _ => {
                 return redis_err!(format!(
@@ -214,30 +286,72 @@ impl PSPublisher for PSRemote {
         };
 
         if docid.len() > MAX_DOCID_LEN {
-            Logger::new().warn(format!(
-                "Skip update to document with docid too long: {docid}"
-            ));
-            return Ok(());
+            warn!("Skip update to document with docid too long: {docid}");
+            return Ok(vec![]);
+        }
+
+        // The plugin that set each key is displayed alongside it.
+        let sources = match (&node, obj_id.split_once(';')) {
+            (Some(node), _) => backend.get_node_metadata_sources(node).await?,
+            (None, Some((DNS_KEY, qname))) => backend.get_dns_metadata_sources(qname).await?,
+            _ => HashMap::new(),
+        };
+
+        let mut updates = vec![];
+
+        // The role of a node is displayed with its details,
+        // which must be updated even if the role was removed.
+        if let Some(node) = node {
+            let role = metadata.remove(ROLE_META_KEY);
+            let details = node_details_fragment(&node, role.as_deref());
+            match xml_se::to_string_with_root("properties-fragment", &details) {
+                Ok(content) => updates.push(QueuedUpdate::new(
+                    docid.clone(),
+                    DETAILS_FRAGMENT.to_string(),
+                    None,
+                    true,
+                    content,
+                )),
+                Err(err) => {
+                    return io_err!(format!("Failed to serialise node details to PSML: {err}"))
+                }
+            }
         }
 
-        let fragment = metadata_fragment(metadata)
+        // The node an address resolves to is displayed before its details,
+        // which must be updated even if the node was removed.
+        if let Some((DNS_KEY, qname)) = obj_id.split_once(';') {
+            if qname_address(qname).is_some() {
+                let node = address_node_fragment(metadata.remove(NODE_META_KEY))
+                    .create_links(&mut backend)
+                    .await?;
+                match xml_se::to_string_with_root("properties-fragment", &node) {
+                    Ok(content) => updates.push(QueuedUpdate::new(
+                        docid.clone(),
+                        ADDRESS_NODE_FRAGMENT.to_string(),
+                        None,
+                        true,
+                        content,
+                    )),
+                    Err(err) => {
+                        return io_err!(format!("Failed to serialise address node to PSML: {err}"))
+                    }
+                }
+            }
+        }
+
+        let fragment = metadata_fragment(metadata, &sources)
             .create_links(&mut backend)
             .await?;
 
         match xml_se::to_string_with_root("properties-fragment", &fragment) {
-            Ok(content) => {
-                self.server()
-                    .await?
-                    .put_uri_fragment(
-                        &self.username,
-                        &self.group,
-                        &docid,
-                        METADATA_FRAGMENT,
-                        content,
-                        None,
-                    )
-                    .await?;
-            }
+            Ok(content) => updates.push(QueuedUpdate::new(
+                docid,
+                METADATA_FRAGMENT.to_string(),
+                None,
+                true,
+                content,
+            )),
             Err(err) => {
                 return io_err!(format!(
                     "Failed to serialise metadata to PSML: {}",
@@ -246,7 +360,72 @@ impl PSPublisher for PSRemote {
             }
         }
 
-        Ok(())
+        Ok(updates)
+    }
+
+    async fn update_notes(
+        &self,
+        mut backend: DataStore,
+        obj_id: &str,
+    ) -> NetdoxResult<Vec<QueuedUpdate>> {
+        let docid = match obj_id.split_once(';') {
+            Some((DNS_KEY, qname)) => dns_qname_to_docid(qname),
+            Some((PROC_NODES_KEY, link_id)) => node_id_to_docid(link_id),
+            _ => {
+                return redis_err!(format!(
+                    "Invalid updated notes change object id (wrong first segment): {obj_id}"
+                ))
+            }
+        };
+
+        if docid.len() > MAX_DOCID_LEN {
+            warn!("Skip update to document with docid too long: {docid}");
+            return Ok(vec![]);
+        }
+
+        let fragment = notes_fragment(backend.get_notes(obj_id).await?)
+            .create_links(&mut backend)
+            .await?;
+
+        match xml_se::to_string_with_root("properties-fragment", &fragment) {
+            Ok(content) => Ok(vec![QueuedUpdate::new(
+                docid,
+                NOTES_FRAGMENT.to_string(),
+                None,
+                true,
+                content,
+            )]),
+            Err(err) => io_err!(format!("Failed to serialise operator notes to PSML: {err}")),
+        }
+    }
+
+    async fn update_translations(
+        &self,
+        mut backend: DataStore,
+        qname: &str,
+    ) -> NetdoxResult<Vec<QueuedUpdate>> {
+        let docid = dns_qname_to_docid(qname);
+        if docid.len() > MAX_DOCID_LEN {
+            warn!("Skip update to document with docid too long: {docid}");
+            return Ok(vec![]);
+        }
+
+        let dns = backend.get_dns_subset(&[qname]).await?;
+        let fragment = translations_fragment(dns.get_translations(qname));
+
+        // Documents from before translations were displayed do not have the fragment yet.
+        match xml_se::to_string_with_root("properties-fragment", &fragment) {
+            Ok(content) => Ok(vec![QueuedUpdate::new(
+                docid,
+                TRANSLATIONS_FRAGMENT.to_string(),
+                Some("details".to_string()),
+                true,
+                content,
+            )]),
+            Err(err) => io_err!(format!(
+                "Failed to serialise network translations to PSML: {err}"
+            )),
+        }
     }
 
     async fn create_data(
@@ -255,7 +434,7 @@ impl PSPublisher for PSRemote {
         obj_id: &str,
         data_id: &str,
         kind: &DataKind,
-    ) -> NetdoxResult<()> {
+    ) -> NetdoxResult<Vec<QueuedUpdate>> {
         let (data_key, section) = match kind {
             DataKind::Plugin => (format!("{PDATA_KEY};{obj_id};{data_id}"), PDATA_SECTION),
             DataKind::Report => (format!("{obj_id};{data_id}"), RDATA_SECTION),
@@ -272,7 +451,7 @@ impl PSPublisher for PSRemote {
                     node_id_to_docid(&id)
                 } else {
                     warn!("Data not attached to any processed node was created. Raw id: {raw_id}");
-                    return Ok(());
+                    return Ok(vec![]);
                 }
             }
 
@@ -294,13 +473,17 @@ impl PSPublisher for PSRemote {
         };
 
         if docid.len() > MAX_DOCID_LEN {
-            Logger::new().warn(format!(
-                "Skip update to document with docid too long: {docid}"
-            ));
-            return Ok(());
+            warn!("Skip update to document with docid too long: {docid}");
+            return Ok(vec![]);
         }
 
-        let fragment = Fragments::from(data).create_links(&mut backend).await?;
+        // Status data on nodes is displayed in its own section.
+        let displays = backend.get_plugin_displays().await?;
+        let (fragment, section) = match obj_id.split(';').next() {
+            Some(NODES_KEY | PROC_NODES_KEY) => node_data_fragment(data, &displays),
+            _ => (data_fragment(data, &displays), section),
+        };
+        let fragment = fragment.create_links(&mut backend).await?;
         let id = match &fragment {
             Fragments::Fragment(frag) => &frag.id,
             Fragments::Media(_frag) => todo!("Media fragment in pageseeder-rs"),
@@ -309,30 +492,13 @@ impl PSPublisher for PSRemote {
         };
 
         match xml_se::to_string(&fragment) {
-            Ok(content) => {
-                match self
-                    .server()
-                    .await?
-                    .add_uri_fragment(
-                        &self.username,
-                        &self.group,
-                        &docid,
-                        &content,
-                        HashMap::from([("section", section), ("fragment", id)]),
-                    )
-                    .await
-                {
-                    Err(PSError::ApiError { id, req, msg }) => {
-                        if msg == "The fragment already exists." {
-                            self.update_data(backend, obj_id, data_id, kind).await
-                        } else {
-                            Err(PSError::ApiError { id, req, msg }.into())
-                        }
-                    }
-                    Err(other_err) => Err(other_err.into()),
-                    Ok(_) => Ok(()),
-                }
-            }
+            Ok(content) => Ok(vec![QueuedUpdate::new(
+                docid,
+                id.to_string(),
+                Some(section.to_string()),
+                true,
+                content,
+            )]),
             Err(err) => {
                 io_err!(format!(
                     "Failed to serialise data to PSML: {}",
@@ -348,7 +514,7 @@ impl PSPublisher for PSRemote {
         obj_id: &str,
         data_id: &str,
         kind: &DataKind,
-    ) -> NetdoxResult<()> {
+    ) -> NetdoxResult<Vec<QueuedUpdate>> {
         let data_key = match kind {
             DataKind::Plugin => format!("{PDATA_KEY};{obj_id};{data_id}"),
             DataKind::Report => format!("{obj_id};{data_id}"),
@@ -365,7 +531,7 @@ impl PSPublisher for PSRemote {
                     node_id_to_docid(&id)
                 } else {
                     warn!("Data not attached to any processed node was updated. Raw id: {raw_id}");
-                    return Ok(());
+                    return Ok(vec![]);
                 }
             }
 
@@ -387,13 +553,16 @@ impl PSPublisher for PSRemote {
         };
 
         if docid.len() > MAX_DOCID_LEN {
-            Logger::new().warn(format!(
-                "Skip update to document with docid too long: {docid}"
-            ));
-            return Ok(());
+            warn!("Skip update to document with docid too long: {docid}");
+            return Ok(vec![]);
         }
 
-        let fragment = Fragments::from(data).create_links(&mut backend).await?;
+        let displays = backend.get_plugin_displays().await?;
+        let fragment = match obj_id.split(';').next() {
+            Some(NODES_KEY | PROC_NODES_KEY) => node_data_fragment(data, &displays).0,
+            _ => data_fragment(data, &displays),
+        };
+        let fragment = fragment.create_links(&mut backend).await?;
         let id = match &fragment {
             Fragments::Fragment(frag) => &frag.id,
             Fragments::Media(_frag) => todo!("Media fragment in pageseeder-rs"),
@@ -402,41 +571,135 @@ impl PSPublisher for PSRemote {
         };
 
         match xml_se::to_string(&fragment) {
-            Ok(content) => {
-                self.server()
-                    .await?
-                    .put_uri_fragment(&self.username, &self.group, &docid, id, content, None)
+            Ok(content) => Ok(vec![QueuedUpdate::new(
+                docid,
+                id.to_string(),
+                None,
+                true,
+                content,
+            )]),
+            Err(err) => io_err!(format!(
+                "Failed to serialise data to PSML: {}",
+                err.to_string()
+            )),
+        }
+    }
+
+    async fn apply_update(&self, update: &QueuedUpdate) -> NetdoxResult<()> {
+        let server = self.server().await?;
+        let section = match &update.section {
+            Some(section) => section,
+            None => {
+                server
+                    .put_uri_fragment(
+                        &self.username,
+                        &self.group,
+                        &update.docid,
+                        &update.fragment,
+                        update.content.clone(),
+                        None,
+                    )
                     .await?;
+                return Ok(());
             }
-            Err(err) => {
-                return io_err!(format!(
-                    "Failed to serialise data to PSML: {}",
-                    err.to_string()
-                ))
+        };
+
+        match server
+            .add_uri_fragment(
+                &self.username,
+                &self.group,
+                &update.docid,
+                &update.content,
+                HashMap::from([
+                    ("section", section.as_str()),
+                    ("fragment", update.fragment.as_str()),
+                ]),
+            )
+            .await
+        {
+            Err(PSError::ApiError { id, req, msg }) => {
+                if msg != "The fragment already exists." {
+                    Err(PSError::ApiError { id, req, msg }.into())
+                } else if update.replace {
+                    server
+                        .put_uri_fragment(
+                            &self.username,
+                            &self.group,
+                            &update.docid,
+                            &update.fragment,
+                            update.content.clone(),
+                            None,
+                        )
+                        .await?;
+                    Ok(())
+                } else {
+                    Ok(())
+                }
             }
+            Err(other_err) => Err(other_err.into()),
+            Ok(_) => Ok(()),
         }
-
-        Ok(())
     }
 
     #[allow(clippy::too_many_lines)]
     async fn upload_docs(&self, docs: Vec<Document>, backup: Option<PathBuf>) -> NetdoxResult<()> {
-        let mut log = Logger::new();
+        // Access rules match the metadata, which continuation documents do not have.
+        let mut parts = Vec::with_capacity(docs.len());
+        for doc in docs {
+            let rule = self.access.iter().find(|rule| rule.matches(&doc));
+            match self.max_document_bytes {
+                Some(max_bytes) => parts.extend(
+                    split_document(doc, max_bytes)?
+                        .into_iter()
+                        .map(|part| (part, rule)),
+                ),
+                None => parts.push((doc, rule)),
+            }
+        }
+        let docs = parts;
+        let warn_bytes = self.max_document_bytes.unwrap_or(DEFAULT_DOCUMENT_LIMIT) / 10 * 9;
+
         let num_docs = docs.len();
-        log.info(format!("Started zipping {num_docs} documents..."));
+        info!("Started zipping {num_docs} documents...");
 
         let mut zip_file = vec![];
         let mut zip = ZipWriter::new(Cursor::new(&mut zip_file));
 
-        for outdir in ["nodes", "dns", "reports"] {
-            if let Err(err) = zip.add_directory(outdir, FileOptions::default()) {
+        let mut restricted = vec![];
+        for rule in &self.access {
+            rule.validate()?;
+            if let Some(folder) = &rule.folder {
+                if !restricted.contains(&folder.as_str()) {
+                    restricted.push(folder.as_str());
+                }
+            }
+        }
+
+        let mut outdirs = vec![
+            NODE_DIR.to_string(),
+            DNS_DIR.to_string(),
+            REPORT_DIR.to_string(),
+            SUBNET_DIR.to_string(),
+        ];
+        for folder in &restricted {
+            outdirs.push((*folder).to_string());
+            for subdir in [NODE_DIR, DNS_DIR, REPORT_DIR] {
+                outdirs.push(format!("{folder}/{subdir}"));
+            }
+        }
+
+        for outdir in outdirs {
+            if let Err(err) = zip.add_directory(&outdir, FileOptions::default()) {
                 return io_err!(format!(
                     "Failed to create {outdir} directory in PSML zip: {err}"
                 ));
             }
         }
 
-        for doc in docs {
+        #[cfg(feature = "psml-validation")]
+        let mut violations = vec![];
+
+        for (mut doc, rule) in docs {
             let filename = match &doc.doc_info {
                 None => {
                     return process_err!(format!(
@@ -457,9 +720,7 @@ impl PSPublisher for PSRemote {
                         }
                         Some(docid) => {
                             if docid.len() > MAX_DOCID_LEN {
-                                log.warn(format!(
-                                    "Skip uploading document with docid too long: {docid}"
-                                ));
+                                warn!("Skip uploading document with docid too long: {docid}");
                                 continue;
                             }
                             let mut filename = String::from(docid);
@@ -475,7 +736,11 @@ impl PSPublisher for PSRemote {
                     DNS_DOC_TYPE => Some(DNS_DIR),
                     NODE_DOC_TYPE => Some(NODE_DIR),
                     REPORT_DOC_TYPE => Some(REPORT_DIR),
-                    CHANGELOG_DOC_TYPE | REMOTE_CONFIG_DOC_TYPE => None,
+                    SUBNET_DOC_TYPE => Some(SUBNET_DIR),
+                    CHANGELOG_DOC_TYPE
+                    | REMOTE_CONFIG_DOC_TYPE
+                    | OVERVIEW_DOC_TYPE
+                    | PUBLISH_LOCK_DOC_TYPE => None,
                     other => {
                         return process_err!(format!(
                             "Generated PSML document with unknown doc type: {other}"
@@ -489,18 +754,41 @@ impl PSPublisher for PSRemote {
                 }
             };
 
-            let zip_path = if let Some(folder_name) = folder {
+            let mut zip_path = if let Some(folder_name) = folder {
                 format!("{folder_name}/{filename}")
             } else {
                 filename
             };
 
+            if let Some(rule) = rule {
+                debug!(
+                    "Applying access rule for {}={} to {zip_path}",
+                    rule.key, rule.value
+                );
+                add_labels(&mut doc, &rule.labels);
+                if let Some(restricted) = &rule.folder {
+                    zip_path = format!("{restricted}/{zip_path}");
+                }
+            }
+
             if let Err(err) = zip.start_file(zip_path, FileOptions::default()) {
                 return io_err!(format!("Failed to start file in zip to upload: {err}"));
             }
 
             match quick_xml::se::to_string(&doc) {
                 Ok(xml) => {
+                    if xml.len() > warn_bytes {
+                        warn!(
+                            "Document {} is {} bytes, which is close to or over the size PageSeeder will accept.",
+                            doc.docid().unwrap_or_default(),
+                            xml.len()
+                        );
+                    }
+                    #[cfg(feature = "psml-validation")]
+                    for violation in validate(&xml) {
+                        violations
+                            .push(format!("{}: {violation}", doc.docid().unwrap_or_default()));
+                    }
                     if let Err(err) = zip.write(&xml.into_bytes()) {
                         return io_err!(format!("Failed to write psml document into zip: {err}"));
                     }
@@ -511,6 +799,17 @@ impl PSPublisher for PSRemote {
             }
         }
 
+        #[cfg(feature = "psml-validation")]
+        if !violations.is_empty() {
+            for violation in &violations {
+                error!("Invalid PSML in document {violation}");
+            }
+            return process_err!(format!(
+                "Refusing to upload documents: {} parts of them do not match the PSML schema.",
+                violations.len()
+            ));
+        }
+
         if let Err(err) = zip.finish() {
             return io_err!(format!(
                 "Failed to finished writing zip of psml documents: {err}"
@@ -520,14 +819,14 @@ impl PSPublisher for PSRemote {
 
         if let Some(backup_path) = backup {
             match std::fs::write(&backup_path, &zip_file) {
-                Ok(()) => log.info(format!(
+                Ok(()) => info!(
                     "Wrote backup zip of PSML documents to {}",
                     backup_path.display()
-                )),
-                Err(err) => log.error(format!(
+                ),
+                Err(err) => error!(
                     "Failed to write backup zip of PSML documents to {}: {err}",
                     backup_path.display()
-                )),
+                ),
             };
         }
 
@@ -538,22 +837,21 @@ impl PSPublisher for PSRemote {
             .await?;
 
         if load_clear.files_removed > 0 {
-            log.info(format!(
+            info!(
                 "Cleared {} old files from loading zone.",
                 load_clear.files_removed
-            ));
+            );
         }
 
-        log.info(format!("Started upload of {num_docs} documents..."));
+        debug!("Zip of documents to upload is {} bytes.", zip_file.len());
+        info!("Started upload of {num_docs} documents...");
 
         self.server()
             .await?
             .upload(&self.group, "netdox.zip", zip_file, HashMap::new())
             .await?;
 
-        log.info(format!(
-            "Started unzipping {num_docs} documents in loading zone..."
-        ));
+        info!("Started unzipping {num_docs} documents in loading zone...");
 
         let unzip_thread = self
             .server()
@@ -569,9 +867,7 @@ impl PSPublisher for PSRemote {
 
         self.await_thread(unzip_thread).await?;
 
-        log.info(format!(
-            "Started loading {num_docs} documents into PageSeeder..."
-        ));
+        info!("Started loading {num_docs} documents into PageSeeder...");
 
         let thread = self
             .server()
@@ -590,7 +886,7 @@ impl PSPublisher for PSRemote {
 
         self.await_thread(thread).await?;
 
-        log.success(format!("Uploaded {num_docs} documents to PageSeeder."));
+        success!("Uploaded {num_docs} documents to PageSeeder.");
 
         Ok(())
     }
@@ -602,21 +898,58 @@ impl PSPublisher for PSRemote {
     ) -> NetdoxResult<Vec<PublishData<'a>>> {
         use Change as CT;
         use PublishData as PC;
+        debug!("Preparing data for change: {change:?}");
+        if let Some(role) = excluded_role(&mut con, &self.exclude_roles, change).await? {
+            debug!("Skipping change to node with excluded role {role}: {change:?}");
+            return Ok(vec![]);
+        }
+
+        if let (
+            Some(max_bytes),
+            CT::CreatedData {
+                obj_id,
+                kind: DataKind::Plugin,
+                ..
+            }
+            | CT::UpdatedData {
+                obj_id,
+                kind: DataKind::Plugin,
+                ..
+            }
+            | CT::DeletedData {
+                obj_id,
+                kind: DataKind::Plugin,
+                ..
+            },
+        ) = (self.max_document_bytes, change)
+        {
+            if let Some(data) = self
+                .split_data_documents(con.clone(), obj_id, max_bytes)
+                .await?
+            {
+                return Ok(vec![data]);
+            }
+        }
+
         match change {
             CT::Init => Ok(vec![
                 PC::Create {
                     target_ids: vec!["changelog".to_string()],
-                    document: Box::new(changelog_document(con.last_change_id().await?)),
+                    documents: vec![changelog_document(con.last_change_id().await?)],
                 },
                 PC::Create {
                     target_ids: vec!["config".to_string()],
-                    document: Box::new(remote_config_document()),
+                    documents: vec![remote_config_document()],
                 },
             ]),
-            CT::CreateDnsName { qname, .. } => Ok(vec![PC::Create {
-                target_ids: vec![format!("{DNS_KEY};{qname}")],
-                document: Box::new(dns_name_document(&mut con, qname).await?),
-            }]),
+            CT::CreateDnsName { qname, .. } | CT::DeleteDnsName { qname, .. } => {
+                Ok(vec![PC::Create {
+                    target_ids: vec![format!("{DNS_KEY};{qname}")],
+                    documents: vec![
+                        current_dns_document(&mut con, qname, self.metadata_by_plugin).await?,
+                    ],
+                }])
+            }
 
             CT::CreateDnsRecord { record, .. } => {
                 let mut updates = vec![PC::Update {
@@ -634,6 +967,23 @@ impl PSPublisher for PSRemote {
                 Ok(updates)
             }
 
+            // Removed records are dropped by regenerating the documents they were displayed on.
+            CT::DeleteDnsRecord { record, .. } => {
+                let mut data = vec![];
+                for qname in [Some(record.name.clone()), record.implies().map(|i| i.name)]
+                    .into_iter()
+                    .flatten()
+                {
+                    data.push(PC::Create {
+                        target_ids: vec![format!("{DNS_KEY};{qname}")],
+                        documents: vec![
+                            current_dns_document(&mut con, &qname, self.metadata_by_plugin).await?,
+                        ],
+                    });
+                }
+                Ok(data)
+            }
+
             CT::CreatePluginNode { node_id, .. } => match con.get_node_from_raw(node_id).await? {
                 Some(pnode_id) => {
                     let node = con.get_node(&pnode_id).await?;
@@ -644,7 +994,10 @@ impl PSPublisher for PSRemote {
                             .map(|id| format!("{NODES_KEY};{id}"))
                             .chain([format!("{PROC_NODES_KEY};{pnode_id}")])
                             .collect(),
-                        document: Box::new(processed_node_document(&mut con, &node).await?),
+                        documents: vec![
+                            processed_node_document(&mut con, &node, self.metadata_by_plugin)
+                                .await?,
+                        ],
                     }])
                 }
                 None => {
@@ -655,11 +1008,80 @@ impl PSPublisher for PSRemote {
                 }
             },
 
+            // The processed node is regenerated if it survived, and removed by its own change if not.
+            CT::DeletePluginNode { node_id, .. } => match con.get_node_from_raw(node_id).await? {
+                Some(pnode_id) => {
+                    let node = con.get_node(&pnode_id).await?;
+                    Ok(vec![PC::Create {
+                        target_ids: node
+                            .raw_ids
+                            .iter()
+                            .map(|id| format!("{NODES_KEY};{id}"))
+                            .chain([format!("{PROC_NODES_KEY};{pnode_id}")])
+                            .collect(),
+                        documents: vec![
+                            processed_node_document(&mut con, &node, self.metadata_by_plugin)
+                                .await?,
+                        ],
+                    }])
+                }
+                None => {
+                    debug!("No processed node left for removed raw node: {node_id}");
+                    Ok(vec![])
+                }
+            },
+
+            CT::DeleteProcNode { link_id, .. } => Ok(vec![PC::Create {
+                target_ids: vec![format!("{PROC_NODES_KEY};{link_id}")],
+                documents: vec![match con.get_node(link_id).await {
+                    Ok(node) => {
+                        processed_node_document(&mut con, &node, self.metadata_by_plugin).await?
+                    }
+                    Err(_) => deleted_node_document(link_id),
+                }],
+            }]),
+
+            // Grouped metadata may gain or lose fragments, so regenerate the whole document.
+            CT::UpdatedMetadata { obj_id, .. } if self.metadata_by_plugin => {
+                self.object_documents(con, obj_id).await
+            }
+
             CT::UpdatedMetadata { obj_id, .. } => Ok(vec![PC::Update {
                 target_id: obj_id.to_string(),
                 future: self.update_metadata(con, obj_id),
             }]),
 
+            CT::UpdatedNotes { obj_id, .. } => Ok(vec![PC::Update {
+                target_id: obj_id.to_string(),
+                future: self.update_notes(con, obj_id),
+            }]),
+
+            // Labels can only be set when a document is uploaded, so tagged objects are regenerated.
+            CT::UpdatedTags { obj_id, .. } => self.object_documents(con, obj_id).await,
+
+            // Report data may move between parts, so regenerate the whole report.
+            CT::CreatedData {
+                obj_id,
+                kind: DataKind::Report,
+                ..
+            }
+            | CT::UpdatedData {
+                obj_id,
+                kind: DataKind::Report,
+                ..
+            }
+            | CT::DeletedData {
+                obj_id,
+                kind: DataKind::Report,
+                ..
+            } => match obj_id.strip_prefix(&format!("{REPORTS_KEY};")) {
+                Some(report_id) => Ok(vec![PC::Create {
+                    target_ids: vec![obj_id.to_string()],
+                    documents: report_document(&mut con, report_id, self.max_report_rows).await?,
+                }]),
+                None => redis_err!(format!("Invalid report data key: {obj_id}")),
+            },
+
             CT::CreatedData {
                 obj_id,
                 data_id,
@@ -680,12 +1102,54 @@ impl PSPublisher for PSRemote {
                 future: self.update_data(con, obj_id, data_id, kind),
             }]),
 
+            // Fragments cannot be deleted in place, so regenerate the document without it.
+            CT::DeletedData { obj_id, .. } => self.object_documents(con, obj_id).await,
+
             CT::CreateReport { report_id, .. } => Ok(vec![PC::Create {
                 target_ids: vec![format!("{REPORTS_KEY};{report_id}")],
-                document: Box::new(report_document(&mut con, report_id).await?),
+                documents: report_document(&mut con, report_id, self.max_report_rows).await?,
             }]),
 
-            CT::UpdatedNetworkMapping { .. } => todo!("Update network mappings"),
+            CT::UpdatedService { link_id, .. } => {
+                self.object_documents(con, &format!("{PROC_NODES_KEY};{link_id}"))
+                    .await
+            }
+
+            CT::UpdatedNetworkMapping { source, .. } => Ok(vec![PC::Update {
+                target_id: format!("{DNS_KEY};{source}"),
+                future: self.update_translations(con, source),
+            }]),
+
+            // Dependencies and relations are displayed on the nodes at both ends.
+            CT::UpdatedDependency { source, dest, .. }
+            | CT::UpdatedRelation { source, dest, .. } => {
+                let mut data = vec![];
+                for link_id in [source, dest] {
+                    let node = con.get_node(link_id).await?;
+                    if let Some(role) =
+                        node_excluded_role(&mut con, &self.exclude_roles, &node).await?
+                    {
+                        debug!("Skipping dependency or relation of node with excluded role {role}: {link_id}");
+                        continue;
+                    }
+
+                    data.push(PC::Create {
+                        target_ids: node
+                            .raw_ids
+                            .iter()
+                            .map(|id| format!("{NODES_KEY};{id}"))
+                            .chain([format!("{PROC_NODES_KEY};{link_id}")])
+                            .collect(),
+                        documents: vec![
+                            processed_node_document(&mut con, &node, self.metadata_by_plugin)
+                                .await?,
+                        ],
+                    });
+                }
+                Ok(data)
+            }
+
+            CT::BeginBatch { .. } | CT::EndBatch { .. } => Ok(vec![]),
         }
     }
 
@@ -693,27 +1157,26 @@ impl PSPublisher for PSRemote {
         &'a self,
         con: DataStore,
         changes: HashSet<&'a Change>,
-        backup: Option<PathBuf>,
-    ) -> NetdoxResult<Vec<BoxFuture<'a, NetdoxResult<()>>>> {
-        let mut log = Logger::new();
+    ) -> NetdoxResult<PreparedChanges> {
         let num_changes = changes.len();
 
         // Fetch from redis
 
-        log.loading(format!("Fetching data to prepare {num_changes} changes..."));
+        info!("Fetching data to prepare {num_changes} changes...");
         let mut data_futures = vec![];
         for change in changes {
             data_futures.push(self.prep_data(con.clone(), change));
         }
         let data = join_all(data_futures).await;
-        log.success("Fetched data from datastore.");
+        success!("Fetched data from datastore.");
 
         // Upload and post changes
 
-        log.info(format!("Preparing {num_changes} changes..."));
+        info!("Preparing {num_changes} changes...");
         let mut uploads = vec![];
         let mut upload_ids = HashSet::new();
-        let mut update_map: HashMap<String, Vec<BoxFuture<NetdoxResult<()>>>> = HashMap::new();
+        let mut update_map: HashMap<String, Vec<BoxFuture<NetdoxResult<Vec<QueuedUpdate>>>>> =
+            HashMap::new();
         for result in data {
             match result {
                 Ok(data) => {
@@ -721,14 +1184,16 @@ impl PSPublisher for PSRemote {
                         match datum {
                             PublishData::Create {
                                 target_ids,
-                                document,
+                                documents,
                             } => {
                                 if !target_ids.iter().any(|i| upload_ids.contains(i)) {
-                                    uploads.push(*document);
+                                    debug!("Queued document upload for: {}", target_ids.join(", "));
+                                    uploads.extend(documents);
                                     upload_ids.extend(target_ids);
                                 }
                             }
                             PublishData::Update { target_id, future } => {
+                                debug!("Queued fragment update for: {target_id}");
                                 match update_map.entry(target_id.to_string()) {
                                     Entry::Occupied(mut entry) => entry.get_mut().push(future),
                                     Entry::Vacant(entry) => {
@@ -740,60 +1205,112 @@ impl PSPublisher for PSRemote {
                     }
                 }
                 Err(err) => {
-                    log.error(format!("Failed to prepare change: {err}"));
+                    error!("Failed to prepare change: {err}");
                 }
             }
         }
-        log.success(format!("Prepared {num_changes} changes."));
+        success!("Prepared {num_changes} changes.");
 
-        for id in upload_ids {
+        for id in &upload_ids {
             // Remove updates to documents that will be uploaded
-            update_map.remove(&id);
+            if let Some(removed) = update_map.remove(id) {
+                debug!(
+                    "Dropped {} fragment updates for {id} as its document will be uploaded.",
+                    removed.len()
+                );
+            }
         }
 
-        let mut updates = update_map.into_values().flatten().collect::<Vec<_>>();
-        if !uploads.is_empty() {
-            updates.push(self.upload_docs(uploads, backup));
+        let update_futures = update_map.into_values().flatten().collect::<Vec<_>>();
+        let mut progress = Progress::new("Prepared fragment updates", update_futures.len());
+        let mut update_futures =
+            futures::stream::iter(update_futures).buffer_unordered(MAX_CONCURRENT_UPDATES);
+        let mut updates = vec![];
+        let mut errors = vec![];
+        while let Some(res) = update_futures.next().await {
+            progress.tick();
+            match res {
+                Ok(prepared) => updates.extend(prepared),
+                Err(err) => errors.push(err),
+            }
+        }
+        progress.finish();
+
+        // Catches updates whose target ID differs from that of the created document,
+        // which would otherwise race with the upload.
+        let (updates, folded) = fold_into_uploads(updates, &uploads);
+        if !folded.is_empty() {
+            debug!(
+                "Folded {} fragment updates into documents that will be uploaded.",
+                folded.len()
+            );
         }
 
-        Ok(updates)
+        Ok(PreparedChanges {
+            updates,
+            uploads,
+            upload_ids,
+            errors,
+        })
     }
 
     async fn apply_changes<'a>(
         &self,
-        con: DataStore,
+        mut con: DataStore,
         changes: &'a [ChangelogEntry],
+        last_change: Option<&str>,
         backup: Option<PathBuf>,
+        timer: &mut RunTimer,
     ) -> NetdoxResult<()> {
-        let unique_changes = changes
-            .iter()
-            .map(|entry| &entry.change)
-            .collect::<HashSet<_>>();
+        let checkpoint = self.checkpoint_id();
+        let recorded = con.get_remote_cursor(&checkpoint).await?;
+        let batches = publish_batches(changes, self.publish_batch_size);
+        for (idx, batch) in batches.iter().enumerate() {
+            if batches.len() > 1 {
+                info!(
+                    "Publishing batch {} of {} ({} changelog entries).",
+                    idx + 1,
+                    batches.len(),
+                    batch.len()
+                );
+            }
 
-        let mut errs = vec![];
-        let change_futures = futures::stream::iter(
-            self.prep_changes(con.clone(), unique_changes, backup)
-                .await?,
-        )
-        .buffer_unordered(20);
+            let backup = backup.as_deref().map(|path| batch_backup_path(path, idx));
+            self.apply_batch(con.clone(), batch, backup, timer)
+                .instrument(debug_span!("batch", batch = idx + 1))
+                .await?;
 
-        for res in change_futures.collect::<Vec<_>>().await {
-            if let Err(err) = res {
-                errs.push(err);
+            if let Some(change) = batch.last() {
+                con.put_publish_checkpoint(&checkpoint, &change.id).await?;
             }
         }
 
-        if !errs.is_empty() {
-            return remote_err!(format!(
-                "Some changes could not be published: \n\n\t{}",
-                errs.into_iter()
-                    .map(|e| e.to_string())
-                    .collect::<Vec<String>>()
-                    .join("\n\n\t")
-            ));
+        let removed = self.remove_superseded_fragments(con.clone()).await;
+        timer.phase("remove-superseded-fragments", removed.is_ok());
+        removed?;
+
+        // Overviews and subnet documents only summarise the data,
+        // so failing to update them does not fail the publish.
+        if !changes.is_empty() {
+            let overviews = self.upload_overviews(con.clone()).await;
+            timer.phase("network-overviews", overviews.is_ok());
+            if let Err(err) = overviews {
+                warn!("Failed to update network overview documents: {err}");
+            }
+
+            let subnets = self.upload_subnets(con.clone()).await;
+            timer.phase("subnet-documents", subnets.is_ok());
+            if let Err(err) = subnets {
+                warn!("Failed to update subnet documents: {err}");
+            }
         }
 
         if let Some(change) = changes.last() {
+            // Another publish may have advanced the cursor while this one was running.
+            swap_cursor(&mut con, &checkpoint, recorded.as_deref(), &change.id).await?;
+            let current = self.get_last_change().await?;
+            check_cursor(last_change, current.as_deref(), &change.id)?;
+
             let frag = last_change_fragment(change.id.clone());
             let xml = match quick_xml::se::to_string(&frag) {
                 Ok(string) => string,
@@ -802,7 +1319,6 @@ impl PSPublisher for PSRemote {
                 }
             };
 
-            dbg!("Before");
             self.server()
                 .await?
                 .put_uri_fragment(
@@ -814,13 +1330,650 @@ impl PSPublisher for PSRemote {
                     None,
                 )
                 .await?;
-            dbg!("After");
 
+            con.clear_publish_checkpoint(&checkpoint).await?;
             success!("Updated changelog on the remote to change ID {}", change.id);
+            timer.phase("update-changelog", true);
         }
 
         Ok(())
     }
+
+    async fn plan_changes<'a>(
+        &'a self,
+        mut con: DataStore,
+        changes: &'a [ChangelogEntry],
+    ) -> NetdoxResult<(Vec<String>, Vec<(String, String)>)> {
+        let coalesced = coalesce_batches(changes);
+        let unique_changes = coalesced.iter().collect::<HashSet<_>>();
+
+        let leftovers = con.get_queued_updates().await?;
+        let (superseded, _) = self.superseded_documents(&mut con).await?;
+        let prepared = self.prep_changes(con.clone(), unique_changes).await?;
+        for err in &prepared.errors {
+            warn!("Failed to prepare change: {err}");
+        }
+        let (leftovers, _) = fold_into_uploads(leftovers, &prepared.uploads);
+
+        let mut documents = prepared
+            .uploads
+            .iter()
+            .chain(&superseded)
+            .filter_map(|doc| doc.doc_info.as_ref()?.uri.as_ref()?.docid.clone())
+            .collect::<Vec<_>>();
+        if !changes.is_empty() {
+            documents.extend(
+                overview_networks(&mut con)
+                    .await?
+                    .iter()
+                    .map(|network| network_overview_docid(network)),
+            );
+            documents.extend(
+                subnets(&mut con)
+                    .await?
+                    .iter()
+                    .map(|subnet| subnet_docid(&subnet.network, &subnet.net)),
+            );
+        }
+        documents.sort();
+        documents.dedup();
+
+        let mut fragments = prepared
+            .updates
+            .iter()
+            .chain(&leftovers)
+            .map(|update| (update.docid.clone(), update.fragment.clone()))
+            .collect::<HashSet<_>>();
+        if !changes.is_empty() {
+            fragments.insert((CHANGELOG_DOCID.to_string(), CHANGELOG_FRAGMENT.to_string()));
+        }
+        let mut fragments = fragments.into_iter().collect::<Vec<_>>();
+        fragments.sort();
+
+        Ok((documents, fragments))
+    }
+
+    async fn prep_documents(
+        &self,
+        mut con: DataStore,
+        qnames: &[String],
+        nodes: &[Node],
+        reports: &[String],
+    ) -> NetdoxResult<Vec<Document>> {
+        let mut docs = Vec::with_capacity(qnames.len() + nodes.len() + reports.len());
+        // Documents are generated concurrently, each on a clone of the connection,
+        // so reads for different documents share round trips to the data store.
+        let dns_docs: Vec<Document> = stream::iter(qnames.iter().cloned())
+            .map(|qname| {
+                let mut con = con.clone();
+                async move { dns_name_document(&mut con, &qname, self.metadata_by_plugin).await }
+            })
+            .buffered(MAX_CONCURRENT_DOCUMENTS)
+            .try_collect()
+            .await?;
+        docs.extend(dns_docs);
+
+        let node_docs: Vec<Option<Document>> = stream::iter(nodes.iter().cloned())
+            .map(|node| {
+                let mut con = con.clone();
+                async move {
+                    if let Some(role) = con
+                        .get_node_metadata(&node)
+                        .await?
+                        .remove(ROLE_META_KEY)
+                        .filter(|role| self.exclude_roles.contains(role))
+                    {
+                        debug!("Skipping node {} with excluded role {role}", node.link_id);
+                        return Ok(None);
+                    }
+                    processed_node_document(&mut con, &node, self.metadata_by_plugin)
+                        .await
+                        .map(Some)
+                }
+            })
+            .buffered(MAX_CONCURRENT_DOCUMENTS)
+            .try_collect()
+            .await?;
+        docs.extend(node_docs.into_iter().flatten());
+
+        for id in reports {
+            docs.extend(report_document(&mut con, id, self.max_report_rows).await?);
+        }
+
+        Ok(docs)
+    }
+}
+
+/// Returns the fragments a change writes under a different ID than they had before IDs were hashed,
+/// as the ID of the object whose document has the fragment, and the old and new fragment IDs.
+fn superseded_fragments(change: &Change) -> Vec<(String, (String, String))> {
+    let fragments = match change {
+        Change::CreateDnsRecord { record, .. } => {
+            let mut records = vec![DNSRecords::Actual(record.clone())];
+            records.extend(record.implies().map(DNSRecords::Implied));
+            records
+                .into_iter()
+                .map(|record| {
+                    (
+                        format!("{DNS_KEY};{}", record.name()),
+                        (
+                            legacy_record_fragment_id(&record),
+                            record_fragment_id(&record),
+                        ),
+                    )
+                })
+                .collect()
+        }
+        Change::CreatedData {
+            plugin,
+            obj_id,
+            data_id,
+            kind: DataKind::Plugin,
+        }
+        | Change::UpdatedData {
+            plugin,
+            obj_id,
+            data_id,
+            kind: DataKind::Plugin,
+        } => vec![(
+            obj_id.clone(),
+            (data_id.clone(), data_fragment_id(plugin, data_id)),
+        )],
+        _ => vec![],
+    };
+
+    fragments
+        .into_iter()
+        .filter(|(_, (old, new))| old != new)
+        .collect()
+}
+
+/// Splits fragment updates into those that must be applied, and those to documents that will be uploaded.
+/// Uploaded documents are generated from the current data, so they already contain the latter.
+fn fold_into_uploads(
+    updates: Vec<QueuedUpdate>,
+    uploads: &[Document],
+) -> (Vec<QueuedUpdate>, Vec<QueuedUpdate>) {
+    let uploaded = uploads
+        .iter()
+        .filter_map(|doc| doc.doc_info.as_ref()?.uri.as_ref()?.docid.as_deref())
+        .collect::<HashSet<_>>();
+
+    updates
+        .into_iter()
+        .partition(|update| !uploaded.contains(update.docid.as_str()))
+}
+
+impl PSRemote {
+    /// Generates and uploads the whole documents for some objects a batch at a time,
+    /// reading the DNS names and processed nodes in the batches they are scanned in,
+    /// so that publishing every object never holds all of their documents at once.
+    /// Each batch after the first is backed up with its number added to the file name.
+    /// Records the total time spent preparing and uploading documents in the timer.
+    pub(super) async fn publish_batches(
+        &self,
+        con: DataStore,
+        objects: &ObjectSelection,
+        backup: Option<PathBuf>,
+        timer: &mut RunTimer,
+    ) -> NetdoxResult<()> {
+        let (mut prepare, mut upload) = (Duration::ZERO, Duration::ZERO);
+        let mut num_batches = 0;
+        let published = async {
+            let selected_dns = objects.dns.iter().collect::<HashSet<_>>();
+            let selected_nodes = objects.nodes.iter().collect::<HashSet<_>>();
+            let mut dns_batches = pin!(dns_batches(con.clone(), BATCH_SIZE));
+            let mut node_batches = pin!(node_batches(con.clone(), BATCH_SIZE));
+            let mut report_batches = objects.reports.chunks(BATCH_SIZE);
+
+            loop {
+                let started = Instant::now();
+                let docs = if let Some(dns) = dns_batches.try_next().await? {
+                    let qnames = dns
+                        .qnames
+                        .into_iter()
+                        .filter(|qname| selected_dns.contains(qname))
+                        .sorted()
+                        .collect::<Vec<_>>();
+                    self.prep_documents(con.clone(), &qnames, &[], &[]).await?
+                } else if let Some(nodes) = node_batches.try_next().await? {
+                    let nodes = nodes
+                        .into_iter()
+                        .filter(|node| selected_nodes.contains(&node.link_id))
+                        .collect::<Vec<_>>();
+                    self.prep_documents(con.clone(), &[], &nodes, &[]).await?
+                } else if let Some(reports) = report_batches.next() {
+                    self.prep_documents(con.clone(), &[], &[], reports).await?
+                } else {
+                    break;
+                };
+                prepare += started.elapsed();
+                if docs.is_empty() {
+                    continue;
+                }
+
+                num_batches += 1;
+                let backup = match &backup {
+                    Some(path) if num_batches > 1 => {
+                        Some(backup_path(path, &num_batches.to_string()))
+                    }
+                    backup => backup.clone(),
+                };
+                let started = Instant::now();
+                self.upload_docs(docs, backup).await?;
+                upload += started.elapsed();
+            }
+
+            Ok(())
+        }
+        .await;
+
+        timer.interleaved(
+            &[("prepare-documents", prepare), ("upload", upload)],
+            published.is_ok(),
+        );
+        published
+    }
+
+    /// Uploads the overview document of every network, generated from the current data.
+    async fn upload_overviews(&self, mut con: DataStore) -> NetdoxResult<()> {
+        let docs = network_overviews(&mut con)
+            .await?
+            .iter()
+            .map(overview_document)
+            .collect::<Vec<_>>();
+        if docs.is_empty() {
+            return Ok(());
+        }
+
+        info!("Updating {} network overview documents.", docs.len());
+        self.upload_docs(docs, None).await
+    }
+
+    /// Uploads the document of every subnet, generated from the current data.
+    async fn upload_subnets(&self, mut con: DataStore) -> NetdoxResult<()> {
+        let docs = subnets(&mut con)
+            .await?
+            .iter()
+            .map(subnet_document)
+            .collect::<Vec<_>>();
+        if docs.is_empty() {
+            return Ok(());
+        }
+
+        info!("Updating {} subnet documents.", docs.len());
+        self.upload_docs(docs, None).await
+    }
+
+    /// Applies one batch of changes to the remote, without advancing the changelog cursor.
+    async fn apply_batch(
+        &self,
+        mut con: DataStore,
+        changes: &[ChangelogEntry],
+        backup: Option<PathBuf>,
+        timer: &mut RunTimer,
+    ) -> NetdoxResult<()> {
+        let coalesced = coalesce_batches(changes);
+        let unique_changes = coalesced.iter().collect::<HashSet<_>>();
+        debug!(
+            "Applying {} unique changes from {} changelog entries.",
+            unique_changes.len(),
+            changes.len()
+        );
+
+        // Fragments updated in place under their new IDs leave the old ones in the document,
+        // until it is regenerated.
+        let mut superseded: HashMap<String, Vec<(String, String)>> = HashMap::new();
+        for change in &unique_changes {
+            if excluded_role(&mut con, &self.exclude_roles, change)
+                .await?
+                .is_none()
+            {
+                for (obj_id, ids) in superseded_fragments(change) {
+                    superseded.entry(obj_id).or_default().push(ids);
+                }
+            }
+        }
+
+        let leftovers = con.get_queued_updates().await?;
+        let prepared = self.prep_changes(con.clone(), unique_changes).await?;
+        let mut errs = prepared.errors;
+        for (obj_id, ids) in &superseded {
+            if !prepared.upload_ids.contains(obj_id) {
+                con.put_superseded_fragments(obj_id, ids).await?;
+            }
+        }
+        let upload_ids = prepared.upload_ids.into_iter().collect::<Vec<_>>();
+
+        // Later updates to a fragment replace earlier ones,
+        // and uploaded documents replace any updates to them.
+        let mut updates = HashMap::new();
+        for update in prepared.updates {
+            updates.insert(update.key(), update);
+        }
+        let (leftovers, folded) = fold_into_uploads(leftovers, &prepared.uploads);
+        let leftovers = leftovers
+            .into_iter()
+            .filter(|update| !updates.contains_key(&update.key()))
+            .collect::<Vec<_>>();
+        let updates = updates.into_values().collect::<Vec<_>>();
+
+        for update in &folded {
+            con.remove_queued_update(update).await?;
+        }
+
+        if !leftovers.is_empty() {
+            warn!(
+                "Applying {} fragment updates left over from a previous publish.",
+                leftovers.len()
+            );
+        }
+
+        con.put_queued_updates(&updates).await?;
+        timer.phase("prepare-changes", errs.is_empty());
+
+        // Leftovers that fail again stay queued, but do not fail the publish.
+        let mut leftover_futures = vec![];
+        for update in &leftovers {
+            leftover_futures.push(self.apply_queued_update(con.clone(), update));
+        }
+        let mut progress = Progress::new("Applied left over updates", leftover_futures.len());
+        let mut leftover_futures =
+            futures::stream::iter(leftover_futures).buffer_unordered(MAX_CONCURRENT_UPDATES);
+        while let Some(res) = leftover_futures.next().await {
+            progress.tick();
+            if let Err(err) = res {
+                warn!("Failed to apply left over fragment update: {err}");
+            }
+        }
+        progress.finish();
+
+        let mut change_futures = vec![];
+        for update in &updates {
+            change_futures.push(self.apply_queued_update(con.clone(), update));
+        }
+        if !prepared.uploads.is_empty() {
+            change_futures.push(self.upload_docs(prepared.uploads, backup));
+        }
+
+        let mut progress = Progress::new("Applied changes", change_futures.len());
+        let mut change_futures =
+            futures::stream::iter(change_futures).buffer_unordered(MAX_CONCURRENT_UPDATES);
+        while let Some(res) = change_futures.next().await {
+            progress.tick();
+            if let Err(err) = res {
+                errs.push(err);
+            }
+        }
+        progress.finish();
+        timer.phase("apply-changes", errs.is_empty());
+
+        if !errs.is_empty() {
+            return remote_err!(format!(
+                "Some changes could not be published: \n\n\t{}",
+                errs.into_iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<String>>()
+                    .join("\n\n\t")
+            ));
+        }
+
+        con.clear_superseded_fragments(&upload_ids).await
+    }
+
+    /// Returns the regenerated documents of objects with superseded fragments,
+    /// and the IDs of the objects.
+    async fn superseded_documents(
+        &self,
+        con: &mut DataStore,
+    ) -> NetdoxResult<(Vec<Document>, Vec<String>)> {
+        let obj_ids = con
+            .get_superseded_fragments()
+            .await?
+            .into_keys()
+            .sorted()
+            .collect::<Vec<_>>();
+
+        let mut documents = vec![];
+        for obj_id in &obj_ids {
+            if let Some((_, document)) = self.object_document(con, obj_id).await? {
+                match self.max_document_bytes {
+                    Some(max_bytes) => documents.extend(split_document(document, max_bytes)?),
+                    None => documents.push(document),
+                }
+            }
+        }
+
+        Ok((documents, obj_ids))
+    }
+
+    /// Uploads the regenerated documents of objects with superseded fragments.
+    /// Uploaded documents replace those on the remote, so the superseded fragments are removed.
+    async fn remove_superseded_fragments(&self, mut con: DataStore) -> NetdoxResult<()> {
+        let (documents, obj_ids) = self.superseded_documents(&mut con).await?;
+        if obj_ids.is_empty() {
+            return Ok(());
+        }
+
+        info!(
+            "Regenerating {} documents to remove fragments with old IDs.",
+            documents.len()
+        );
+        if !documents.is_empty() {
+            self.upload_docs(documents, None).await?;
+        }
+        con.clear_superseded_fragments(&obj_ids).await
+    }
+
+    /// Regenerates the document for a DNS name or node from its object ID,
+    /// returning the IDs the document is published for and the document.
+    /// Returns none if the object has no document.
+    async fn object_document(
+        &self,
+        con: &mut DataStore,
+        obj_id: &str,
+    ) -> NetdoxResult<Option<(Vec<String>, Document)>> {
+        let (kind, id) = match obj_id.split_once(';') {
+            Some(parts) => parts,
+            None => return redis_err!(format!("Invalid object id (no separator): {obj_id}")),
+        };
+
+        let proc_id = match kind {
+            DNS_KEY => {
+                return Ok(Some((
+                    vec![obj_id.to_string()],
+                    dns_name_document(con, id, self.metadata_by_plugin).await?,
+                )))
+            }
+            NODES_KEY => match con.get_node_from_raw(id).await? {
+                Some(proc_id) => proc_id,
+                None => {
+                    warn!("Wanted to regenerate the document for unused raw node: {obj_id}");
+                    return Ok(None);
+                }
+            },
+            PROC_NODES_KEY => id.to_string(),
+            _ => return redis_err!(format!("Invalid object id (wrong first segment): {obj_id}")),
+        };
+
+        let node = match con.get_node(&proc_id).await {
+            Ok(node) => node,
+            Err(_) => {
+                warn!("Failed to retrieve node with id {proc_id} when regenerating its document - it is likely the node no longer exists.");
+                return Ok(None);
+            }
+        };
+
+        Ok(Some((
+            node.raw_ids
+                .iter()
+                .map(|id| format!("{NODES_KEY};{id}"))
+                .chain([format!("{PROC_NODES_KEY};{proc_id}")])
+                .collect(),
+            processed_node_document(con, &node, self.metadata_by_plugin).await?,
+        )))
+    }
+
+    /// Returns the regenerated document for an object, to replace the whole document on the remote.
+    async fn object_documents<'a>(
+        &self,
+        mut con: DataStore,
+        obj_id: &str,
+    ) -> NetdoxResult<Vec<PublishData<'a>>> {
+        Ok(match self.object_document(&mut con, obj_id).await? {
+            Some((target_ids, document)) => vec![PublishData::Create {
+                target_ids,
+                documents: vec![document],
+            }],
+            None => vec![],
+        })
+    }
+
+    /// Returns the regenerated documents for an object whose plugin data changed,
+    /// if its document is split into continuation documents.
+    /// The changed data may have moved between them, so it cannot be updated in place.
+    async fn split_data_documents<'a>(
+        &self,
+        mut con: DataStore,
+        obj_id: &str,
+        max_bytes: usize,
+    ) -> NetdoxResult<Option<PublishData<'a>>> {
+        let Some((target_ids, document)) = self.object_document(&mut con, obj_id).await? else {
+            return Ok(None);
+        };
+
+        let documents = split_document(document, max_bytes)?;
+        if documents.len() > 1 {
+            Ok(Some(PublishData::Create {
+                target_ids,
+                documents,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Applies a queued fragment update and removes it from the queue.
+    fn apply_queued_update<'a>(
+        &'a self,
+        mut con: DataStore,
+        update: &'a QueuedUpdate,
+    ) -> BoxFuture<'a, NetdoxResult<()>> {
+        Box::pin(async move {
+            self.apply_update(update).await?;
+            con.remove_queued_update(update).await
+        })
+    }
+}
+
+/// Returns the path to write the backup of one batch of changes to.
+/// Batches after the first have their number appended to the file name.
+fn batch_backup_path(path: &Path, idx: usize) -> PathBuf {
+    if idx == 0 {
+        return path.to_path_buf();
+    }
+
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!("-{}", idx + 1));
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    path.with_file_name(name)
+}
+
+/// Returns the document for a DNS name, or an archived stub if the name no longer exists.
+async fn current_dns_document(
+    con: &mut DataStore,
+    qname: &str,
+    metadata_by_plugin: bool,
+) -> NetdoxResult<Document> {
+    if con.dns_name_exists(qname).await? {
+        dns_name_document(con, qname, metadata_by_plugin).await
+    } else {
+        Ok(deleted_dns_document(qname))
+    }
+}
+
+/// Returns the role of the node affected by a change, if it is one of the excluded roles.
+async fn excluded_role(
+    con: &mut DataStore,
+    exclude_roles: &HashSet<String>,
+    change: &Change,
+) -> NetdoxResult<Option<String>> {
+    if exclude_roles.is_empty() {
+        return Ok(None);
+    }
+
+    use Change as CT;
+    let obj_id = match change {
+        CT::CreatePluginNode { node_id, .. } => format!("{NODES_KEY};{node_id}"),
+        CT::UpdatedService { link_id, .. } => format!("{PROC_NODES_KEY};{link_id}"),
+        CT::CreatedData { obj_id, .. }
+        | CT::UpdatedData { obj_id, .. }
+        | CT::DeletedData { obj_id, .. }
+        | CT::UpdatedMetadata { obj_id, .. }
+        | CT::UpdatedNotes { obj_id, .. }
+        | CT::UpdatedTags { obj_id, .. } => obj_id.to_string(),
+        _ => return Ok(None),
+    };
+
+    let proc_id = match obj_id.split_once(';') {
+        Some((NODES_KEY, raw_id)) => match con.get_node_from_raw(raw_id).await? {
+            Some(proc_id) => proc_id,
+            None => return Ok(None),
+        },
+        Some((PROC_NODES_KEY, proc_id)) => proc_id.to_string(),
+        _ => return Ok(None),
+    };
+
+    let node = match con.get_node(&proc_id).await {
+        Ok(node) => node,
+        Err(_) => return Ok(None),
+    };
+
+    node_excluded_role(con, exclude_roles, &node).await
+}
+
+/// Returns the role of a node, if it is one of the excluded roles.
+async fn node_excluded_role(
+    con: &mut DataStore,
+    exclude_roles: &HashSet<String>,
+    node: &Node,
+) -> NetdoxResult<Option<String>> {
+    if exclude_roles.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(con
+        .get_node_metadata(node)
+        .await?
+        .remove(ROLE_META_KEY)
+        .filter(|role| exclude_roles.contains(role)))
+}
+
+/// Adds labels to the URI of a document, keeping any it already has.
+fn add_labels(doc: &mut Document, labels: &[String]) {
+    if labels.is_empty() {
+        return;
+    }
+
+    if let Some(uri) = doc.doc_info.as_mut().and_then(|info| info.uri.as_mut()) {
+        let mut value = uri
+            .labels
+            .take()
+            .map(|labels| labels.value)
+            .unwrap_or_default();
+        for label in labels {
+            if !value.split(',').any(|existing| existing == label) {
+                if !value.is_empty() {
+                    value.push(',');
+                }
+                value.push_str(label);
+            }
+        }
+        uri.labels = Some(Labels { value });
+    }
 }
 
 fn last_change_fragment(id: String) -> Fragments {
@@ -830,3 +1983,157 @@ fn last_change_fragment(id: String) -> Fragments {
         )]),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::Path};
+
+    use psml::model::{Document, DocumentInfo, Fragments, Labels, Section, URIDescriptor};
+
+    use super::{add_labels, batch_backup_path, fold_into_uploads, superseded_fragments};
+    use crate::{
+        data::model::{Change, DNSRecord, DataKind, QueuedUpdate},
+        remote::pageseeder::{
+            psml::{changelog_document, metadata_fragment, metadata_fragments},
+            remote::{AccessRule, CHANGELOG_DOCID},
+        },
+    };
+
+    #[test]
+    fn test_superseded_fragments() {
+        let record = Change::CreateDnsRecord {
+            plugin: "plugin".to_string(),
+            record: DNSRecord {
+                name: "[net]domain.com".to_string(),
+                value: "[net]192.168.0.1".to_string(),
+                rtype: "A".to_string(),
+                plugin: "plugin".to_string(),
+            },
+        };
+        let superseded = superseded_fragments(&record);
+        assert_eq!(
+            superseded
+                .iter()
+                .map(|(obj_id, (old, _))| (obj_id.as_str(), old.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("dns;[net]domain.com", "plugin_A__net_192.168.0.1"),
+                ("dns;[net]192.168.0.1", "implied_plugin_PTR__net_domain.com"),
+            ]
+        );
+
+        let data = Change::UpdatedData {
+            plugin: "plugin".to_string(),
+            obj_id: "dns;[net]domain.com".to_string(),
+            data_id: "data-id".to_string(),
+            kind: DataKind::Plugin,
+        };
+        let superseded = superseded_fragments(&data);
+        assert_eq!(superseded.len(), 1);
+        assert_eq!(superseded[0].1 .0, "data-id");
+        assert!(superseded[0].1 .1.starts_with("data_"));
+
+        let report = Change::UpdatedData {
+            plugin: "plugin".to_string(),
+            obj_id: "reports;report-id".to_string(),
+            data_id: "0".to_string(),
+            kind: DataKind::Report,
+        };
+        assert!(superseded_fragments(&report).is_empty());
+    }
+
+    #[test]
+    fn test_batch_backup_path() {
+        assert_eq!(
+            batch_backup_path(Path::new("/tmp/backup.zip"), 0),
+            Path::new("/tmp/backup.zip")
+        );
+        assert_eq!(
+            batch_backup_path(Path::new("/tmp/backup.zip"), 2),
+            Path::new("/tmp/backup-3.zip")
+        );
+        assert_eq!(
+            batch_backup_path(Path::new("backup"), 1),
+            Path::new("backup-2")
+        );
+    }
+
+    #[test]
+    fn test_fold_into_uploads() {
+        let update = |docid: &str| {
+            QueuedUpdate::new(
+                docid.to_string(),
+                "meta".to_string(),
+                None,
+                true,
+                "<properties-fragment/>".to_string(),
+            )
+        };
+
+        let (kept, folded) = fold_into_uploads(
+            vec![update(CHANGELOG_DOCID), update("_nd_other_doc")],
+            &[changelog_document("0-0".to_string())],
+        );
+
+        assert_eq!(kept, vec![update("_nd_other_doc")]);
+        assert_eq!(folded, vec![update(CHANGELOG_DOCID)]);
+    }
+
+    #[test]
+    fn test_access_rules() {
+        let mut doc = Document {
+            doc_info: Some(DocumentInfo {
+                uri: Some(URIDescriptor {
+                    docid: Some("_nd_node_secret".to_string()),
+                    labels: Some(Labels {
+                        value: "existing".to_string(),
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            sections: vec![Section::new("details".to_string()).with_fragments(vec![
+                Fragments::Properties(metadata_fragment(
+                    HashMap::from([("confidentiality".to_string(), "high".to_string())]),
+                    &HashMap::from([("confidentiality".to_string(), "cmdb".to_string())]),
+                )),
+            ])],
+            ..Default::default()
+        };
+
+        let rule = |value: &str, folder: &str| AccessRule {
+            key: "confidentiality".to_string(),
+            value: value.to_string(),
+            folder: Some(folder.to_string()),
+            labels: vec!["restricted".to_string(), "existing".to_string()],
+        };
+
+        assert!(rule("high", "restricted").matches(&doc));
+        assert!(!rule("low", "restricted").matches(&doc));
+
+        let grouped = Document {
+            sections: vec![Section::new("details".to_string()).with_fragments(
+                metadata_fragments(
+                    HashMap::from([("confidentiality".to_string(), "high".to_string())]),
+                    &HashMap::from([("confidentiality".to_string(), "cmdb".to_string())]),
+                    true,
+                )
+                .into_iter()
+                .map(Fragments::Properties)
+                .collect(),
+            )],
+            ..Default::default()
+        };
+        assert!(rule("high", "restricted").matches(&grouped));
+
+        assert!(rule("high", "restricted/hosts").validate().is_ok());
+        assert!(rule("high", "../escape").validate().is_err());
+        assert!(rule("high", "/absolute").validate().is_err());
+
+        add_labels(&mut doc, &rule("high", "restricted").labels);
+        assert_eq!(
+            doc.doc_info.unwrap().uri.unwrap().labels.unwrap().value,
+            "existing,restricted"
+        );
+    }
+}
@@ -1,18 +1,29 @@
 use crate::{
     config::RemoteConfig,
     config_err,
-    data::{model::ObjectID, DataConn, DataStore},
+    data::{
+        model::{ObjectID, RunLock, DNS_KEY, PROC_NODES_KEY, REPORTS_KEY},
+        DataConn, DataStore,
+    },
     error::{NetdoxError, NetdoxResult},
     io_err,
     remote::pageseeder::{
         config::parse_config,
-        psml::{DNS_OBJECT_TYPE, NODE_OBJECT_TYPE, OBJECT_ID_PROPNAME, REPORT_OBJECT_TYPE},
+        psml::{
+            archived_dns_document, deleted_dns_document, deleted_node_document,
+            deleted_report_document, parse_publish_lock, publish_lock_document,
+            publish_lock_fragment, ARCHIVED_LABEL, DNS_OBJECT_TYPE, METADATA_FRAGMENT,
+            NODE_OBJECT_TYPE, OBJECT_ID_PROPNAME, REPORT_OBJECT_TYPE,
+        },
         publish::PSPublisher,
     },
+    remote::{lock_is_free, ObjectSelection, PublishPlan},
     remote_err,
+    runs::RunTimer,
 };
 
 use async_trait::async_trait;
+use ipnet::IpNet;
 use pageseeder_api::{
     model::PSError,
     model::{Thread, ThreadStatus, ThreadZip},
@@ -20,20 +31,21 @@ use pageseeder_api::{
     PSServer,
 };
 use psml::{
-    model::{Document, FragmentContent, Fragments},
+    model::{Document, FragmentContent, Fragments, PropertyValue, SectionContent},
     text::ParaContent,
 };
 use quick_xml::de;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fmt::Write as _,
     io::{Cursor, Read},
     path::PathBuf,
     sync::LazyLock,
 };
 use tokio::sync::Mutex;
+use tracing::{debug, warn};
 use zip::ZipArchive;
 
 use super::{
@@ -43,6 +55,8 @@ use super::{
 
 pub const CHANGELOG_DOCID: &str = "_nd_changelog";
 pub const CHANGELOG_FRAGMENT: &str = "last-change";
+pub const PUBLISH_LOCK_DOCID: &str = "_nd_publish_lock";
+pub const PUBLISH_LOCK_FRAGMENT: &str = "lock";
 
 static DOCID_INVALID_CHARS: LazyLock<Regex> =
     LazyLock::new(|| Regex::new("[^a-zA-Z0-9_-]").unwrap());
@@ -70,6 +84,100 @@ pub fn report_id_to_docid(id: &str) -> String {
     )
 }
 
+/// Returns the docid of the overview document of a logical network.
+pub fn network_overview_docid(network: &str) -> String {
+    format!(
+        "_nd_overview_{}",
+        DOCID_INVALID_CHARS.replace_all(network, "_")
+    )
+}
+
+/// Returns the docid of the document of a subnet in a logical network.
+pub fn subnet_docid(network: &str, net: &IpNet) -> String {
+    format!(
+        "_nd_subnet_{}",
+        DOCID_INVALID_CHARS.replace_all(&format!("{network}_{net}"), "_")
+    )
+}
+
+/// Returns the docid of one part of a report's documents.
+/// The first part uses the docid of the report itself.
+pub fn report_part_docid(id: &str, part: usize) -> String {
+    if part == 0 {
+        report_id_to_docid(id)
+    } else {
+        format!("{}_part{}", report_id_to_docid(id), part + 1)
+    }
+}
+
+/// Default number of changelog entries to apply to the remote before saving a checkpoint.
+fn default_publish_batch_size() -> usize {
+    500
+}
+
+/// Default maximum number of rows of report content in one document.
+fn default_max_report_rows() -> usize {
+    1000
+}
+
+/// Places documents with a metadata value into a restricted folder or labels them,
+/// so that PageSeeder permissions can hide them from some group members.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AccessRule {
+    /// Metadata key to match.
+    pub key: String,
+    /// Metadata value to match.
+    pub value: String,
+    /// Folder to place matching documents in, relative to the upload directory.
+    #[serde(default)]
+    pub folder: Option<String>,
+    /// Labels to add to matching documents.
+    #[serde(default)]
+    pub labels: Vec<String>,
+}
+
+impl AccessRule {
+    /// Returns true if a metadata fragment of the document has the value of this rule.
+    /// Metadata grouped by plugin is in fragments with the metadata fragment ID as a prefix.
+    pub fn matches(&self, doc: &Document) -> bool {
+        let plugin_prefix = format!("{METADATA_FRAGMENT}-");
+        doc.sections
+            .iter()
+            .flat_map(|section| &section.content)
+            .filter_map(|content| match content {
+                SectionContent::PropertiesFragment(frag)
+                    if frag.id == METADATA_FRAGMENT || frag.id.starts_with(&plugin_prefix) =>
+                {
+                    Some(frag)
+                }
+                _ => None,
+            })
+            .flat_map(|frag| &frag.properties)
+            .filter(|prop| prop.title.as_deref() == Some(self.key.as_str()))
+            .flat_map(|prop| &prop.values)
+            .any(|val| matches!(val, PropertyValue::Value(val) if *val == self.value))
+    }
+
+    /// Checks that the folder of this rule cannot escape the upload directory.
+    pub fn validate(&self) -> NetdoxResult<()> {
+        if let Some(folder) = &self.folder {
+            if folder.is_empty()
+                || folder.starts_with('/')
+                || folder
+                    .split('/')
+                    .any(|part| part.is_empty() || part == "..")
+            {
+                return config_err!(format!(
+                    "Access rule for {}={} has invalid folder: {folder}",
+                    self.key, self.value
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PSRemote {
     pub url: String,
@@ -78,11 +186,38 @@ pub struct PSRemote {
     pub username: String,
     pub group: String,
     pub upload_dir: String,
+    /// Maximum number of rows of report content in one document.
+    /// Larger reports are split across multiple linked documents.
+    #[serde(default = "default_max_report_rows")]
+    pub max_report_rows: usize,
+    /// Number of changelog entries to apply before saving a checkpoint,
+    /// which an interrupted publish resumes from.
+    #[serde(default = "default_publish_batch_size")]
+    pub publish_batch_size: usize,
+    /// Roles of nodes that will not be published.
+    #[serde(default)]
+    pub exclude_roles: HashSet<String>,
+    /// Rules for placing documents based on their metadata.
+    /// The first matching rule is used.
+    #[serde(default)]
+    pub access: Vec<AccessRule>,
+    /// Size in bytes above which plugin data is moved out of a document into linked continuation documents.
+    /// Documents are not split if this is not set.
+    #[serde(default)]
+    pub max_document_bytes: Option<usize>,
+    /// Whether to split metadata into a fragment for each plugin that set it.
+    #[serde(default)]
+    pub metadata_by_plugin: bool,
     #[serde(skip)]
     pub pstoken: Mutex<Option<PSToken>>,
 }
 
 impl PSRemote {
+    /// Returns the ID of the publish checkpoint for this remote in the data store.
+    pub fn checkpoint_id(&self) -> String {
+        format!("pageseeder;{};{}", self.url, self.group)
+    }
+
     /// Returns a `PSServer` instance with a shared token.
     pub async fn server(&self) -> NetdoxResult<PSServer> {
         let creds = PSCredentials::ClientCredentials {
@@ -313,6 +448,73 @@ impl PSRemote {
             _ => Ok(None),
         }
     }
+
+    /// Gets the publish lock on `PageSeeder`.
+    /// Returns None if the lock document does not exist, and Some(None) if the lock is free.
+    async fn get_publish_lock(&self) -> NetdoxResult<Option<Option<RunLock>>> {
+        let fragment = match self
+            .server()
+            .await?
+            .get_uri_fragment(
+                &self.username,
+                &self.group,
+                PUBLISH_LOCK_DOCID,
+                PUBLISH_LOCK_FRAGMENT,
+                HashMap::new(),
+            )
+            .await
+        {
+            Ok(fragment) => fragment,
+            Err(PSError::ApiError { msg, .. }) if msg == "Unable to find matching uri." => {
+                return Ok(None)
+            }
+            Err(other) => Err(other)?,
+        };
+
+        let text = match fragment.fragment {
+            Some(Fragments::Fragment(frag)) => match frag.content.into_iter().next() {
+                Some(FragmentContent::Para(para)) => match para.content.into_iter().next() {
+                    Some(ParaContent::Text(text)) => text,
+                    _ => String::new(),
+                },
+                _ => String::new(),
+            },
+            _ => {
+                return remote_err!(
+                    "Publish lock on PageSeeder has incorrect content (expected fragment)"
+                        .to_string()
+                )
+            }
+        };
+        parse_publish_lock(&text).map(Some)
+    }
+
+    /// Writes the publish lock on `PageSeeder`, or frees it if there is no lock.
+    /// The lock document is uploaded if it does not exist yet.
+    async fn put_publish_lock(&self, lock: Option<&RunLock>, exists: bool) -> NetdoxResult<()> {
+        if !exists {
+            return self
+                .upload_docs(vec![publish_lock_document(lock)?], None)
+                .await;
+        }
+
+        let xml = match quick_xml::se::to_string(&publish_lock_fragment(lock)?) {
+            Ok(xml) => xml,
+            Err(err) => return io_err!(format!("Failed to serialise publish lock to PSML: {err}")),
+        };
+        self.server()
+            .await?
+            .put_uri_fragment(
+                &self.username,
+                &self.group,
+                PUBLISH_LOCK_DOCID,
+                PUBLISH_LOCK_FRAGMENT,
+                xml,
+                None,
+            )
+            .await?;
+        Ok(())
+    }
 }
 
 static OBJECT_ID_INDEX_PROPERTY: LazyLock<String> =
@@ -321,6 +523,70 @@ static OBJECT_ID_INDEX_PROPERTY: LazyLock<String> =
 static OBJECT_TYPE_INDEX_PROPERTY: LazyLock<String> =
     LazyLock::new(|| format!("psproperty-{OBJECT_TYPE_PROPNAME}"));
 
+/// Name of the search index field with the labels of a document.
+const LABEL_INDEX_FIELD: &str = "pslabel";
+
+impl PSRemote {
+    /// Gets the object of every document on the remote, and whether the document is archived.
+    async fn remote_objects(&self) -> NetdoxResult<Vec<(ObjectID, bool)>> {
+        let server = self.server().await?;
+        let mut objects = vec![];
+        for obj_type in [DNS_OBJECT_TYPE, NODE_OBJECT_TYPE, REPORT_OBJECT_TYPE] {
+            let filter = format!("pstype:document,{}:{obj_type}", *OBJECT_TYPE_INDEX_PROPERTY);
+            let results = server
+                .group_search(&self.group, HashMap::from([("filters", filter.as_str())]))
+                .await?;
+
+            for result in results.into_iter().flat_map(|page| page.results) {
+                let mut obj_id = None;
+                let mut archived = false;
+                for field in result.fields {
+                    if field.name == *OBJECT_ID_INDEX_PROPERTY {
+                        obj_id = Some(field.value);
+                    } else if field.name == LABEL_INDEX_FIELD && field.value == ARCHIVED_LABEL {
+                        archived = true;
+                    }
+                }
+
+                if let Some(obj_id) = obj_id {
+                    objects.push((
+                        match obj_type {
+                            DNS_OBJECT_TYPE => ObjectID::DNS(obj_id),
+                            NODE_OBJECT_TYPE => ObjectID::Node(obj_id),
+                            _ => ObjectID::Report(obj_id),
+                        },
+                        archived,
+                    ));
+                }
+            }
+        }
+
+        Ok(objects)
+    }
+}
+
+/// Returns archived documents to replace those on the remote whose object is no longer in the data store.
+/// Documents that are already archived are skipped.
+fn orphan_documents(
+    remote_objects: Vec<(ObjectID, bool)>,
+    qnames: &HashSet<String>,
+    node_ids: &HashSet<String>,
+    report_ids: &HashSet<String>,
+) -> Vec<Document> {
+    remote_objects
+        .into_iter()
+        .filter(|(_, archived)| !archived)
+        .filter_map(|(obj_id, _)| match obj_id {
+            ObjectID::DNS(qname) if !qnames.contains(&qname) => Some(deleted_dns_document(&qname)),
+            ObjectID::Node(link_id) if !node_ids.contains(&link_id) => {
+                Some(deleted_node_document(&link_id))
+            }
+            ObjectID::Report(id) if !report_ids.contains(&id) => Some(deleted_report_document(&id)),
+            _ => None,
+        })
+        .collect()
+}
+
 #[async_trait]
 impl crate::remote::RemoteInterface for PSRemote {
     async fn test(&self) -> NetdoxResult<()> {
@@ -394,14 +660,145 @@ impl crate::remote::RemoteInterface for PSRemote {
         Ok(labeled)
     }
 
-    async fn publish(&self, mut con: DataStore, backup: Option<PathBuf>) -> NetdoxResult<()> {
+    async fn publish(
+        &self,
+        mut con: DataStore,
+        backup: Option<PathBuf>,
+        timer: &mut RunTimer,
+    ) -> NetdoxResult<()> {
+        let last_change = self.get_last_change().await?;
+        debug!(
+            "Last change published to the remote: {}",
+            last_change.as_deref().unwrap_or("none")
+        );
+        let checkpoint = con.get_publish_checkpoint(&self.checkpoint_id()).await?;
+        if let Some(checkpoint) = &checkpoint {
+            warn!("Resuming an interrupted publish from change ID {checkpoint}.");
+        }
         let changes = con
-            .get_changes(self.get_last_change().await?.as_deref())
+            .get_changes(checkpoint.as_deref().or(last_change.as_deref()))
+            .await?;
+        timer.phase("fetch-changes", true);
+        self.apply_changes(con, &changes, last_change.as_deref(), backup, timer)
             .await?;
-        self.apply_changes(con, &changes, backup).await?;
 
         Ok(())
     }
+
+    async fn plan(&self, mut con: DataStore) -> NetdoxResult<PublishPlan> {
+        let last_change = self.get_last_change().await?;
+        let checkpoint = con.get_publish_checkpoint(&self.checkpoint_id()).await?;
+        let changes = con
+            .get_changes(checkpoint.as_deref().or(last_change.as_deref()))
+            .await?;
+        let (documents, fragments) = self.plan_changes(con, &changes).await?;
+
+        Ok(PublishPlan {
+            last_change: checkpoint.or(last_change),
+            changes: changes.len(),
+            documents,
+            fragments,
+        })
+    }
+
+    async fn publish_objects(
+        &self,
+        con: DataStore,
+        objects: &ObjectSelection,
+        backup: Option<PathBuf>,
+        timer: &mut RunTimer,
+    ) -> NetdoxResult<()> {
+        self.publish_batches(con, objects, backup, timer).await
+    }
+
+    /// PageSeeder has no way to archive documents through its API,
+    /// so the documents are replaced with ones labelled as archived that link to the new names.
+    async fn archive_moved_dns(&self, moved: &[(String, String)]) -> NetdoxResult<usize> {
+        if moved.is_empty() {
+            return Ok(0);
+        }
+
+        let docs = moved
+            .iter()
+            .map(|(old, new)| archived_dns_document(old, new))
+            .collect::<Vec<_>>();
+        let num_docs = docs.len();
+        self.upload_docs(docs, None).await?;
+        Ok(num_docs)
+    }
+
+    /// Documents are replaced with ones labelled as removed, as publishing the deletion would.
+    async fn archive_deleted_dns(&self, qnames: &[String]) -> NetdoxResult<usize> {
+        if qnames.is_empty() {
+            return Ok(0);
+        }
+
+        let docs = qnames
+            .iter()
+            .map(|qname| deleted_dns_document(qname))
+            .collect::<Vec<_>>();
+        let num_docs = docs.len();
+        self.upload_docs(docs, None).await?;
+        Ok(num_docs)
+    }
+
+    /// Locations are docids.
+    fn document_location(&self, obj_id: &str) -> Option<String> {
+        match obj_id.split_once(';')? {
+            (DNS_KEY, qname) => Some(dns_qname_to_docid(qname)),
+            (PROC_NODES_KEY, link_id) => Some(node_id_to_docid(link_id)),
+            (REPORTS_KEY, id) => Some(report_id_to_docid(id)),
+            _ => None,
+        }
+    }
+
+    /// Orphaned documents are replaced with ones labelled as archived, like those of moved DNS names.
+    async fn reconcile(&self, mut con: DataStore, dry_run: bool) -> NetdoxResult<Vec<String>> {
+        let docs = orphan_documents(
+            self.remote_objects().await?,
+            &con.get_dns_names().await?,
+            &con.get_node_ids().await?,
+            &con.get_report_ids().await?,
+        );
+        let docids = docs
+            .iter()
+            .filter_map(|doc| doc.doc_info.as_ref()?.uri.as_ref()?.docid.clone())
+            .collect();
+
+        if !dry_run && !docs.is_empty() {
+            self.upload_docs(docs, None).await?;
+        }
+        Ok(docids)
+    }
+
+    /// The lock is a fragment of a document in the group.
+    /// `PageSeeder` cannot write a fragment only if it has not changed, so the lock is read again
+    /// after it is written, which catches another publish that wrote it first but not one that
+    /// writes it between the write and the read. That publish is caught by the next refresh.
+    async fn acquire_publish_lock(&self, lock: &RunLock) -> NetdoxResult<Option<RunLock>> {
+        let current = self.get_publish_lock().await?;
+        if let Some(Some(held)) = &current {
+            if !lock_is_free(held, &lock.owner) {
+                return Ok(Some(held.clone()));
+            }
+        }
+
+        self.put_publish_lock(Some(lock), current.is_some()).await?;
+        match self.get_publish_lock().await? {
+            Some(Some(held)) if held.owner != lock.owner => Ok(Some(held)),
+            _ => Ok(None),
+        }
+    }
+
+    async fn release_publish_lock(&self, owner: Option<&str>) -> NetdoxResult<Option<RunLock>> {
+        match self.get_publish_lock().await? {
+            Some(Some(held)) if owner.is_none_or(|owner| owner == held.owner) => {
+                self.put_publish_lock(None, true).await?;
+                Ok(Some(held))
+            }
+            _ => Ok(None),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -418,6 +815,34 @@ mod tests {
         parse_config(config).unwrap();
     }
 
+    #[test]
+    fn test_orphan_documents() {
+        let docs = orphan_documents(
+            vec![
+                (ObjectID::DNS("[net]kept.com".to_string()), false),
+                (ObjectID::DNS("[net]gone.com".to_string()), false),
+                (ObjectID::DNS("[net]archived.com".to_string()), true),
+                (ObjectID::Node("gone-node".to_string()), false),
+                (ObjectID::Report("gone-report".to_string()), false),
+            ],
+            &HashSet::from(["[net]kept.com".to_string()]),
+            &HashSet::new(),
+            &HashSet::new(),
+        );
+
+        assert_eq!(
+            docs.iter()
+                .map(|doc| doc.doc_info.as_ref().unwrap().uri.as_ref().unwrap())
+                .map(|uri| (uri.docid.clone().unwrap(), uri.labels.is_some()))
+                .collect::<Vec<_>>(),
+            vec![
+                (dns_qname_to_docid("[net]gone.com"), true),
+                (node_id_to_docid("gone-node"), true),
+                (report_id_to_docid("gone-report"), true),
+            ]
+        );
+    }
+
     // use crate::remote::RemoteInterface;
 
     // fn remote() -> PSRemote {
@@ -0,0 +1,439 @@
+use std::{
+    env, fs,
+    future::Future,
+    process::exit,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{task::JoinHandle, time::sleep};
+use tracing::{error, warn};
+
+use crate::{
+    config::{LocalConfig, PluginStage},
+    data::{
+        model::{RunKind, RunLock, RunRecord, RunTiming, StageSnapshot},
+        DataConn, DataStore,
+    },
+    error::{NetdoxError, NetdoxResult},
+    metrics,
+    notify::{notify, Event},
+    process_err,
+    remote::{Remote, RemoteInterface},
+    update::PluginResult,
+};
+
+/// Time after which the run lock is free if the run holding it stops refreshing it.
+const LOCK_TTL: Duration = Duration::from_secs(60);
+/// Time between refreshes of the run lock by the run holding it.
+const LOCK_REFRESH: Duration = Duration::from_secs(20);
+/// Time after which the publish lock on the remote is free if the publish holding it stops refreshing it.
+const REMOTE_LOCK_TTL: Duration = Duration::from_secs(300);
+/// Time between refreshes of the publish lock on the remote by the publish holding it.
+const REMOTE_LOCK_REFRESH: Duration = Duration::from_secs(60);
+
+/// Measures the duration of a run and its parts.
+pub struct RunTimer {
+    kind: RunKind,
+    started: u64,
+    start: Instant,
+    last_phase: Instant,
+    timings: Vec<RunTiming>,
+    stages: Vec<StageSnapshot>,
+}
+
+impl RunTimer {
+    /// Starts timing a new run.
+    pub fn start(kind: RunKind) -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let now = Instant::now();
+
+        RunTimer {
+            kind,
+            started,
+            start: now,
+            last_phase: now,
+            timings: vec![],
+            stages: vec![],
+        }
+    }
+
+    /// Returns the time the run started, in seconds since the epoch.
+    pub fn started(&self) -> u64 {
+        self.started
+    }
+
+    /// Records the time since the previous phase ended, or since the run started.
+    pub fn phase(&mut self, name: &str, success: bool) {
+        let now = Instant::now();
+        self.record(name, now - self.last_phase, success);
+        self.last_phase = now;
+    }
+
+    /// Records the duration of each plugin in a stage, and then the stage itself
+    /// along with the snapshot of the data store the stage ran against.
+    /// Plugins that skipped the stage are recorded as skipped, so their data is not treated as fresh.
+    pub fn stage(&mut self, stage: PluginStage, snapshot: StageSnapshot, results: &[PluginResult]) {
+        for result in results {
+            self.record(
+                &format!("{stage}/{}", result.name),
+                result.duration,
+                result.code == Some(0),
+            );
+            if let Some(timing) = self.timings.last_mut() {
+                timing.skipped = result.skipped;
+            }
+        }
+        self.phase(&stage.to_string(), true);
+        self.stages.push(snapshot);
+    }
+
+    /// Records a timing that was measured separately.
+    pub fn record(&mut self, name: &str, duration: Duration, success: bool) {
+        self.timings.push(RunTiming {
+            name: name.to_string(),
+            millis: u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+            success,
+            skipped: false,
+        });
+    }
+
+    /// Records phases that were interleaved with each other, with their total durations,
+    /// and ends them now.
+    pub fn interleaved(&mut self, phases: &[(&str, Duration)], success: bool) {
+        for (name, duration) in phases {
+            self.record(name, *duration, success);
+        }
+        self.last_phase = Instant::now();
+    }
+
+    /// Stops the timer and returns the record of the run.
+    /// The run is only successful if every recorded part of it was too.
+    pub fn finish(self, success: bool) -> RunRecord {
+        RunRecord {
+            kind: self.kind,
+            started: self.started,
+            millis: u64::try_from(self.start.elapsed().as_millis()).unwrap_or(u64::MAX),
+            success: success && self.timings.iter().all(|timing| timing.success),
+            timings: self.timings,
+            stages: self.stages,
+        }
+    }
+
+    /// Stops the timer, writes the record of the run to the data store,
+    /// records its metrics if they are enabled, and notifies any webhooks that the run finished.
+    /// Failing to do any of these only produces a warning.
+    pub async fn save(self, cfg: &LocalConfig, success: bool) {
+        let record = self.finish(success);
+        let metrics_enabled = cfg.metrics_dir.is_some() || metrics::serving();
+        if metrics_enabled {
+            metrics::record_run(&record);
+        }
+
+        let mut changes = 0;
+        match cfg.con().await {
+            Ok(mut con) => {
+                if let Err(err) = con.put_run(&record).await {
+                    warn!("Failed to record timings for this {}: {err}", record.kind);
+                }
+                if metrics_enabled {
+                    if let Err(err) = metrics::record_store(&mut con).await {
+                        warn!("Failed to record data store metrics: {err}");
+                    }
+                }
+                if let (Some(first), Ok(total)) =
+                    (record.stages.first(), con.total_change_count().await)
+                {
+                    changes = total.saturating_sub(first.changes);
+                }
+            }
+            Err(err) => warn!(
+                "Failed to get connection to record timings for this {}: {err}",
+                record.kind
+            ),
+        }
+
+        if let Some(dir) = &cfg.metrics_dir {
+            if let Err(err) = metrics::write_textfiles(dir, record.kind) {
+                warn!("Failed to write metrics: {err}");
+            }
+        }
+
+        notify(&cfg.notifications, &[Event::finished(&record, changes)]).await;
+    }
+}
+
+/// Holds the run lock on the data store, which stops updates and publishes from running
+/// against it at the same time. The lock is refreshed in the background until it is released,
+/// so it only expires once the process that holds it has died.
+pub struct RunLockGuard {
+    con: DataStore,
+    owner: String,
+    refresher: JoinHandle<()>,
+}
+
+impl RunLockGuard {
+    /// Takes the run lock for a run, or fails if another run holds it.
+    /// If force is true, removes the lock of any other run first.
+    pub async fn acquire(mut con: DataStore, kind: RunKind, force: bool) -> NetdoxResult<Self> {
+        if force {
+            if let Some(held) = con.force_run_unlock().await? {
+                warn!("Removed the run lock held by {}", describe_lock(&held));
+            }
+        }
+
+        let lock = new_lock(kind, LOCK_TTL);
+        if let Some(held) = con.acquire_run_lock(&lock).await? {
+            return process_err!(format!(
+                "The data store is locked by {}. If it is no longer running, wait {}s for the lock to expire or pass --force-unlock.",
+                describe_lock(&held),
+                held.expires.saturating_sub(lock.acquired)
+            ));
+        }
+
+        Ok(RunLockGuard {
+            owner: lock.owner.clone(),
+            refresher: tokio::spawn(refresh_lock(con.clone(), lock)),
+            con,
+        })
+    }
+
+    /// Stops refreshing the run lock and releases it.
+    /// Failing to release it only produces a warning, as it will expire.
+    pub async fn release(mut self) {
+        self.refresher.abort();
+        if let Err(err) = self.con.release_run_lock(&self.owner).await {
+            warn!(
+                "Failed to release the run lock, which will expire in {}s: {err}",
+                LOCK_TTL.as_secs()
+            );
+        }
+    }
+}
+
+/// Extends the expiry of a run lock until the task is aborted.
+/// Exits if another run has taken the lock, so the two do not write to the data store at once.
+async fn refresh_lock(mut con: DataStore, mut lock: RunLock) {
+    loop {
+        sleep(LOCK_REFRESH).await;
+        lock.expires = now_secs() + LOCK_TTL.as_secs();
+        match con.acquire_run_lock(&lock).await {
+            Ok(None) => {}
+            Ok(Some(held)) => {
+                error!(
+                    "Lost the run lock to {}. Stopping so that the two runs do not write at once.",
+                    describe_lock(&held)
+                );
+                exit(1);
+            }
+            Err(err) => warn!("Failed to refresh the run lock: {err}"),
+        }
+    }
+}
+
+/// Runs a publish while holding the publish lock on the remote, which stops two publishes
+/// from different hosts or data stores writing to the same remote at once.
+/// The lock is refreshed while the publish runs, and released when it finishes.
+/// If force is true, removes the lock of any other publish first.
+pub async fn with_remote_lock<T>(
+    remote: &Remote,
+    force: bool,
+    publish: impl Future<Output = T>,
+) -> NetdoxResult<T> {
+    if force {
+        if let Some(held) = remote.release_publish_lock(None).await? {
+            warn!(
+                "Removed the publish lock on the remote held by {}",
+                describe_lock(&held)
+            );
+        }
+    }
+
+    let mut lock = new_lock(RunKind::Publish, REMOTE_LOCK_TTL);
+    if let Some(held) = remote.acquire_publish_lock(&lock).await? {
+        return process_err!(format!(
+            "The remote is locked by {}. If it is no longer running, wait {}s for the lock to expire or pass --force-unlock.",
+            describe_lock(&held),
+            held.expires.saturating_sub(lock.acquired)
+        ));
+    }
+
+    let owner = lock.owner.clone();
+    let refresh = async {
+        loop {
+            sleep(REMOTE_LOCK_REFRESH).await;
+            lock.expires = now_secs() + REMOTE_LOCK_TTL.as_secs();
+            match remote.acquire_publish_lock(&lock).await {
+                Ok(None) => {}
+                Ok(Some(held)) => {
+                    error!(
+                        "Lost the publish lock on the remote to {}. Stopping so that the two publishes do not write at once.",
+                        describe_lock(&held)
+                    );
+                    exit(1);
+                }
+                Err(err) => warn!("Failed to refresh the publish lock on the remote: {err}"),
+            }
+        }
+    };
+
+    let output = tokio::select! {
+        output = publish => output,
+        () = refresh => unreachable!("the lock is refreshed until the publish finishes"),
+    };
+
+    if let Err(err) = remote.release_publish_lock(Some(&owner)).await {
+        warn!(
+            "Failed to release the publish lock on the remote, which will expire in {}s: {err}",
+            REMOTE_LOCK_TTL.as_secs()
+        );
+    }
+    Ok(output)
+}
+
+/// Creates a lock for this process that expires after some time.
+fn new_lock(kind: RunKind, ttl: Duration) -> RunLock {
+    let now = now_secs();
+    let host = hostname();
+    let pid = std::process::id();
+    RunLock {
+        owner: format!("{host}:{pid}:{}", start_nanos()),
+        kind,
+        host,
+        pid,
+        acquired: now,
+        expires: now + ttl.as_secs(),
+    }
+}
+
+/// Describes the run holding a lock.
+pub fn describe_lock(lock: &RunLock) -> String {
+    format!(
+        "{} on {} with PID {} since {} UTC",
+        lock.kind,
+        lock.host,
+        lock.pid,
+        format_timestamp(lock.acquired)
+    )
+}
+
+/// Returns the name of this host, or "unknown".
+fn hostname() -> String {
+    env::var("HOSTNAME")
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Returns the time in nanoseconds, to tell apart processes that reuse a PID.
+fn start_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or_default()
+}
+
+/// Formats a unix timestamp as a UTC date and time.
+pub fn format_timestamp(secs: u64) -> String {
+    let (days, rem) = (secs / 86400, secs % 86400);
+
+    // Converts days since epoch to a civil date.
+    // See: http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Formats a duration in milliseconds for display.
+pub fn format_millis(millis: u64) -> String {
+    if millis < 1000 {
+        format!("{millis}ms")
+    } else if millis < 60_000 {
+        format!("{:.1}s", millis as f64 / 1000.0)
+    } else {
+        format!("{}m{:02}s", millis / 60_000, millis % 60_000 / 1000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{format_millis, format_timestamp, RunTimer};
+    use crate::{
+        config::PluginStage,
+        data::model::{RunKind, RunRecord, StageSnapshot},
+    };
+
+    #[test]
+    fn test_run_success() {
+        let mut timer = RunTimer::start(RunKind::Update);
+        timer.record("write-only/ok", Duration::from_millis(5), true);
+        timer.phase("write-only", true);
+        assert!(timer.finish(true).success);
+
+        let mut timer = RunTimer::start(RunKind::Update);
+        timer.record("write-only/broken", Duration::from_millis(5), false);
+        timer.phase("write-only", true);
+        let record = timer.finish(true);
+        assert!(!record.success);
+        assert_eq!(record.timings.len(), 2);
+        assert_eq!(record.timings[0].millis, 5);
+    }
+
+    #[test]
+    fn test_run_stages() {
+        let snapshot = StageSnapshot {
+            stage: PluginStage::WriteOnly.to_string(),
+            start_change: None,
+            end_change: Some("1700000000000-0".to_string()),
+            changes: 0,
+            dns_names: 0,
+            nodes: 0,
+        };
+
+        let mut timer = RunTimer::start(RunKind::Update);
+        timer.stage(PluginStage::WriteOnly, snapshot.clone(), &[]);
+        let record = timer.finish(true);
+        assert_eq!(record.stages, vec![snapshot]);
+
+        // Records are stored as TOML.
+        let stored = toml::to_string(&record).unwrap();
+        assert_eq!(toml::from_str::<RunRecord>(&stored).unwrap(), record);
+    }
+
+    #[test]
+    fn test_formatting() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14 22:13:20");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00:00");
+
+        assert_eq!(format_millis(999), "999ms");
+        assert_eq!(format_millis(1500), "1.5s");
+        assert_eq!(format_millis(125_000), "2m05s");
+    }
+}
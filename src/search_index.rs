@@ -0,0 +1,264 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::Path,
+    process::exit,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tracing::error;
+
+use crate::{
+    config::LocalConfig,
+    data::{
+        model::{Data, DNS_KEY, PROC_NODES_KEY, REPORTS_KEY},
+        DataConn, DataStore,
+    },
+    error::{NetdoxError, NetdoxResult},
+    io_err,
+    remote::{Remote, RemoteInterface},
+    success,
+};
+
+/// Version of the search index format. Increased when fields are changed or removed.
+const SEARCH_INDEX_VERSION: u32 = 1;
+
+/// Writes a search index of every object in the data store to a file.
+#[tokio::main]
+pub async fn search_index(path: &Path) {
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to build search index: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to build search index: {err}");
+            exit(1);
+        }
+    };
+
+    let index = match SearchIndex::build(&mut con, cfg.remote()).await {
+        Ok(index) => index,
+        Err(err) => {
+            error!("Failed to build search index: {err}");
+            exit(1);
+        }
+    };
+
+    match index.write(path) {
+        Ok(()) => success!(
+            "Wrote search index of {} objects to {}",
+            index.objects.len(),
+            path.display()
+        ),
+        Err(err) => {
+            error!("Failed to write search index: {err}");
+            exit(1);
+        }
+    }
+}
+
+/// The searchable text of every object in the data store.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct SearchIndex {
+    /// Version of the index format.
+    pub version: u32,
+    /// Unix timestamp of when the index was built.
+    pub created: u64,
+    pub objects: Vec<SearchEntry>,
+}
+
+/// The searchable text of one object.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct SearchEntry {
+    /// ID of the object, like `dns;[net]domain.com`.
+    pub id: String,
+    /// Kind of object: `dns`, `node`, or `report`.
+    pub kind: &'static str,
+    /// Title of the object's document.
+    pub title: String,
+    /// Names the object is known by: DNS names, and the alt names of nodes.
+    pub names: BTreeSet<String>,
+    /// Values of the object's metadata. Hidden metadata is left out.
+    pub metadata: BTreeSet<String>,
+    /// Titles of the plugin data or report data on the object.
+    pub data: BTreeSet<String>,
+    /// Where the document for the object is published on the remote, if anywhere.
+    /// A docid for `PageSeeder`, or a path relative to the output directory for local remotes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+}
+
+impl SearchEntry {
+    fn new(
+        id: String,
+        kind: &'static str,
+        title: String,
+        names: impl IntoIterator<Item = String>,
+        metadata: HashMap<String, String>,
+        data: &[Data],
+        remote: &Remote,
+    ) -> Self {
+        SearchEntry {
+            location: remote.document_location(&id),
+            id,
+            kind,
+            title,
+            names: names.into_iter().collect(),
+            metadata: metadata
+                .into_iter()
+                .filter(|(key, value)| !key.starts_with('_') && !value.is_empty())
+                .map(|(_, value)| value)
+                .collect(),
+            data: data.iter().map(|data| data.title().to_string()).collect(),
+        }
+    }
+}
+
+impl SearchIndex {
+    /// Builds a search index of the current contents of the data store.
+    /// Locations are those of the documents on the given remote.
+    pub async fn build(con: &mut DataStore, remote: &Remote) -> NetdoxResult<Self> {
+        let mut objects = vec![];
+
+        let mut qnames = con.get_dns_names().await?.into_iter().collect::<Vec<_>>();
+        qnames.sort();
+        for qname in qnames {
+            let metadata = con.get_dns_metadata(&qname).await?;
+            let pdata = con.get_dns_pdata(&qname).await?;
+            objects.push(SearchEntry::new(
+                format!("{DNS_KEY};{qname}"),
+                "dns",
+                qname.clone(),
+                [qname],
+                metadata,
+                &pdata,
+                remote,
+            ));
+        }
+
+        let mut link_ids = con.get_node_ids().await?.into_iter().collect::<Vec<_>>();
+        link_ids.sort();
+        for link_id in link_ids {
+            let node = con.get_node(&link_id).await?;
+            let metadata = con.get_node_metadata(&node).await?;
+            let pdata = con.get_node_pdata(&node).await?;
+            objects.push(SearchEntry::new(
+                format!("{PROC_NODES_KEY};{link_id}"),
+                "node",
+                node.name.clone(),
+                node.dns_names.iter().chain(&node.alt_names).cloned(),
+                metadata,
+                &pdata,
+                remote,
+            ));
+        }
+
+        let mut report_ids = con.get_report_ids().await?.into_iter().collect::<Vec<_>>();
+        report_ids.sort();
+        for id in report_ids {
+            let report = con.get_report(&id).await?;
+            objects.push(SearchEntry::new(
+                format!("{REPORTS_KEY};{id}"),
+                "report",
+                report.title,
+                [],
+                HashMap::new(),
+                &report.content,
+                remote,
+            ));
+        }
+
+        let created = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs(),
+            Err(err) => return io_err!(format!("System time is before unix epoch: {err}")),
+        };
+
+        Ok(SearchIndex {
+            version: SEARCH_INDEX_VERSION,
+            created,
+            objects,
+        })
+    }
+
+    /// Writes this index to a file as JSON.
+    pub fn write(&self, path: &Path) -> NetdoxResult<()> {
+        let content = match serde_json::to_string(self) {
+            Ok(content) => content,
+            Err(err) => return io_err!(format!("Failed to serialize search index: {err}")),
+        };
+
+        match fs::write(path, content) {
+            Ok(()) => Ok(()),
+            Err(err) => io_err!(format!(
+                "Failed to write search index to {}: {err}",
+                path.display()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+
+    use indexmap::IndexMap;
+
+    use super::SearchEntry;
+    use crate::{
+        data::model::Data,
+        remote::{html::HtmlRemote, DummyRemote, Remote},
+    };
+
+    #[test]
+    fn test_search_entry() {
+        let metadata = HashMap::from([
+            ("owner".to_string(), "ops".to_string()),
+            ("_node".to_string(), "hidden".to_string()),
+            ("location".to_string(), String::new()),
+        ]);
+        let data = [Data::Hash {
+            id: "data-id".to_string(),
+            title: "Open Ports".to_string(),
+            plugin: "plugin".to_string(),
+            content: IndexMap::new(),
+        }];
+
+        let entry = SearchEntry::new(
+            "proc_nodes;server".to_string(),
+            "node",
+            "Server".to_string(),
+            ["[net]b.com".to_string(), "[net]a.com".to_string()],
+            metadata.clone(),
+            &data,
+            &Remote::Dummy(DummyRemote {
+                field: String::new(),
+            }),
+        );
+        assert_eq!(
+            entry.names,
+            BTreeSet::from(["[net]a.com".to_string(), "[net]b.com".to_string()])
+        );
+        assert_eq!(entry.metadata, BTreeSet::from(["ops".to_string()]));
+        assert_eq!(entry.data, BTreeSet::from(["Open Ports".to_string()]));
+        assert_eq!(entry.location, None);
+
+        let html: HtmlRemote = toml::from_str("output_dir = \"site\"").unwrap();
+        let entry = SearchEntry::new(
+            "proc_nodes;server".to_string(),
+            "node",
+            "Server".to_string(),
+            [],
+            metadata,
+            &data,
+            &Remote::Html(html),
+        );
+        assert_eq!(entry.location.as_deref(), Some("nodes/server.html"));
+    }
+}
@@ -0,0 +1,443 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs,
+    path::{Path, PathBuf},
+    process::exit,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, error, info};
+
+use crate::{
+    config::LocalConfig,
+    config_err,
+    data::{
+        model::{Data, DNS_KEY, PROC_NODES_KEY, REPORTS_KEY},
+        DataConn, DataStore,
+    },
+    error::{NetdoxError, NetdoxResult},
+    io_err, success, SnapshotCommand,
+};
+
+const SNAPSHOT_DIR_VAR: &str = "NETDOX_SNAPSHOT_DIR";
+
+/// Performs the given snapshot command.
+#[tokio::main]
+pub async fn snapshot(cmd: &SnapshotCommand) {
+    match cmd {
+        SnapshotCommand::Create { name, force } => create(name, *force).await,
+        SnapshotCommand::Diff { old, new } => diff(old, new),
+    }
+}
+
+async fn create(name: &str, force: bool) {
+    if let Err(err) = validate_name(name) {
+        error!("{err}");
+        exit(1);
+    }
+
+    let cfg = match LocalConfig::read() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            error!("Failed to get local config in order to create snapshot: {err}");
+            exit(1);
+        }
+    };
+
+    let mut con = match cfg.con().await {
+        Ok(con) => con,
+        Err(err) => {
+            error!("Failed to get data store connection in order to create snapshot: {err}");
+            exit(1);
+        }
+    };
+
+    let snapshot = match Snapshot::create(&mut con, name).await {
+        Ok(snapshot) => snapshot,
+        Err(err) => {
+            error!("Failed to create snapshot: {err}");
+            exit(1);
+        }
+    };
+
+    match snapshot.write(force) {
+        Ok(path) => success!(
+            "Wrote snapshot of {} objects to {}",
+            snapshot.objects.len(),
+            path.display()
+        ),
+        Err(err) => {
+            error!("Failed to write snapshot: {err}");
+            exit(1);
+        }
+    }
+}
+
+fn diff(old: &str, new: &str) {
+    let (old, new) = match (Snapshot::read(old), Snapshot::read(new)) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(err), _) | (_, Err(err)) => {
+            error!("Failed to read snapshot: {err}");
+            exit(1);
+        }
+    };
+
+    let diff = old.diff(&new);
+    for id in &diff.added {
+        println!("+ {id}");
+    }
+    for id in &diff.removed {
+        println!("- {id}");
+    }
+    for id in &diff.changed {
+        println!("~ {id}");
+    }
+
+    info!(
+        "{} added, {} removed, {} changed between {} and {}.",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.changed.len(),
+        old.name,
+        new.name
+    );
+}
+
+/// Directory to store snapshots in.
+fn snapshot_dir() -> NetdoxResult<PathBuf> {
+    if let Ok(dir) = env::var(SNAPSHOT_DIR_VAR) {
+        Ok(PathBuf::from(dir))
+    } else if let Ok(home) = env::var("HOME") {
+        Ok(PathBuf::from(format!("{home}/.config/netdox-snapshots")))
+    } else {
+        io_err!(format!(
+            "Cannot find directory to store snapshots in. Please set ${SNAPSHOT_DIR_VAR}."
+        ))
+    }
+}
+
+/// Checks that a snapshot name can be used as a file name in the snapshot directory.
+fn validate_name(name: &str) -> NetdoxResult<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+        config_err!(format!(
+            "Invalid snapshot name {name:?}: must not be empty, \".\" or \"..\", or contain path separators."
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Path of the snapshot with the given name in dir.
+fn snapshot_path(dir: &Path, name: &str) -> NetdoxResult<PathBuf> {
+    validate_name(name)?;
+    Ok(dir.join(format!("{name}.toml")))
+}
+
+/// A digest of every object in the datastore.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Name of the snapshot.
+    pub name: String,
+    /// Unix timestamp of when the snapshot was created.
+    pub created: u64,
+    /// Maps object ID to a hash of its content.
+    pub objects: BTreeMap<String, String>,
+}
+
+/// Objects that differ between two snapshots.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl Snapshot {
+    /// Creates a snapshot of the current contents of the datastore.
+    pub async fn create(con: &mut DataStore, name: &str) -> NetdoxResult<Self> {
+        let mut objects = BTreeMap::new();
+
+        let dns = con.get_dns().await?;
+        for qname in &dns.qnames {
+            let mut digest = ObjectDigest::new();
+
+            let mut records = dns
+                .records
+                .get(qname)
+                .into_iter()
+                .flatten()
+                .map(|r| [r.plugin.as_str(), r.rtype.as_str(), r.value.as_str()])
+                .collect::<Vec<_>>();
+            records.sort_unstable();
+            for record in records {
+                digest.fields(record);
+            }
+
+            let mut translations = dns
+                .net_translations
+                .get(qname)
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+            translations.sort_unstable();
+            digest.fields(translations);
+
+            digest.metadata(con.get_dns_metadata(qname).await?);
+            digest.pdata(con.get_dns_pdata(qname).await?);
+
+            objects.insert(format!("{DNS_KEY};{qname}"), digest.finish());
+        }
+
+        for id in con.get_node_ids().await? {
+            let node = con.get_node(&id).await?;
+            let mut digest = ObjectDigest::new();
+
+            digest.fields([&node.name]);
+            for set in [
+                &node.alt_names,
+                &node.dns_names,
+                &node.plugins,
+                &node.raw_ids,
+            ] {
+                let mut sorted = set.iter().collect::<Vec<_>>();
+                sorted.sort_unstable();
+                digest.fields(sorted);
+            }
+
+            digest.metadata(con.get_node_metadata(&node).await?);
+            digest.pdata(con.get_node_pdata(&node).await?);
+
+            objects.insert(format!("{PROC_NODES_KEY};{id}"), digest.finish());
+        }
+
+        for id in con.get_report_ids().await? {
+            let report = con.get_report(&id).await?;
+            let mut digest = ObjectDigest::new();
+
+            digest.fields([&report.title, &report.plugin]);
+            for data in &report.content {
+                digest.fields([data.id()]);
+                digest.fields(data.to_args());
+            }
+
+            objects.insert(format!("{REPORTS_KEY};{id}"), digest.finish());
+        }
+
+        debug!("Created digests for {} objects.", objects.len());
+
+        let created = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs(),
+            Err(err) => return io_err!(format!("System time is before unix epoch: {err}")),
+        };
+
+        Ok(Snapshot {
+            name: name.to_string(),
+            created,
+            objects,
+        })
+    }
+
+    /// Writes this snapshot to the snapshot directory and returns the path.
+    /// Refuses to replace an existing snapshot with the same name unless force is set.
+    pub fn write(&self, force: bool) -> NetdoxResult<PathBuf> {
+        self.write_in(&snapshot_dir()?, force)
+    }
+
+    fn write_in(&self, dir: &Path, force: bool) -> NetdoxResult<PathBuf> {
+        let path = snapshot_path(dir, &self.name)?;
+        if !force && path.exists() {
+            return io_err!(format!(
+                "Snapshot {} already exists at {}. Use --force to overwrite it.",
+                self.name,
+                path.display()
+            ));
+        }
+
+        if let Err(err) = fs::create_dir_all(dir) {
+            return io_err!(format!(
+                "Failed to create snapshot directory {}: {err}",
+                dir.display()
+            ));
+        }
+
+        let content = match toml::to_string(self) {
+            Ok(content) => content,
+            Err(err) => return io_err!(format!("Failed to serialize snapshot: {err}")),
+        };
+
+        match fs::write(&path, content) {
+            Ok(()) => Ok(path),
+            Err(err) => io_err!(format!(
+                "Failed to write snapshot to {}: {err}",
+                path.display()
+            )),
+        }
+    }
+
+    /// Reads a snapshot with the given name from the snapshot directory.
+    pub fn read(name: &str) -> NetdoxResult<Self> {
+        Self::read_in(&snapshot_dir()?, name)
+    }
+
+    fn read_in(dir: &Path, name: &str) -> NetdoxResult<Self> {
+        let path = snapshot_path(dir, name)?;
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) => {
+                return io_err!(format!(
+                    "Failed to read snapshot from {}: {err}",
+                    path.display()
+                ))
+            }
+        };
+
+        match toml::from_str(&content) {
+            Ok(snapshot) => Ok(snapshot),
+            Err(err) => config_err!(format!(
+                "Failed to parse snapshot at {}: {err}",
+                path.display()
+            )),
+        }
+    }
+
+    /// Returns the objects that were added, removed or changed in other.
+    pub fn diff(&self, other: &Snapshot) -> SnapshotDiff {
+        let mut diff = SnapshotDiff::default();
+
+        for (id, hash) in &other.objects {
+            match self.objects.get(id) {
+                None => diff.added.push(id.clone()),
+                Some(old_hash) if old_hash != hash => diff.changed.push(id.clone()),
+                Some(_) => {}
+            }
+        }
+
+        for id in self.objects.keys() {
+            if !other.objects.contains_key(id) {
+                diff.removed.push(id.clone());
+            }
+        }
+
+        diff
+    }
+}
+
+/// Builds the hash of an object from a sequence of fields.
+pub struct ObjectDigest(Sha256);
+
+impl ObjectDigest {
+    pub fn new() -> Self {
+        ObjectDigest(Sha256::new())
+    }
+
+    /// Adds a group of fields to the digest.
+    /// Fields are length-prefixed so adjacent fields cannot collide.
+    pub fn fields<T: AsRef<str>>(&mut self, fields: impl IntoIterator<Item = T>) {
+        let mut count: u64 = 0;
+        for field in fields {
+            let field = field.as_ref().as_bytes();
+            self.0.update((field.len() as u64).to_le_bytes());
+            self.0.update(field);
+            count += 1;
+        }
+        self.0.update(count.to_le_bytes());
+    }
+
+    fn metadata(&mut self, metadata: HashMap<String, String>) {
+        let mut pairs = metadata.into_iter().collect::<Vec<_>>();
+        pairs.sort_unstable();
+        for (key, value) in pairs {
+            self.fields([key, value]);
+        }
+    }
+
+    fn pdata(&mut self, mut pdata: Vec<Data>) {
+        pdata.sort_unstable_by(|lhs, rhs| lhs.id().cmp(rhs.id()));
+        for data in pdata {
+            self.fields([data.id()]);
+            self.fields(data.to_args());
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.0
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, env::temp_dir, fs};
+
+    use super::{ObjectDigest, Snapshot, SnapshotDiff};
+
+    fn snapshot(objects: &[(&str, &str)]) -> Snapshot {
+        Snapshot {
+            name: "test".to_string(),
+            created: 0,
+            objects: objects
+                .iter()
+                .map(|(id, hash)| (id.to_string(), hash.to_string()))
+                .collect::<BTreeMap<_, _>>(),
+        }
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let old = snapshot(&[("dns;a", "1"), ("dns;b", "2"), ("dns;c", "3")]);
+        let new = snapshot(&[("dns;a", "1"), ("dns;b", "4"), ("dns;d", "5")]);
+
+        assert_eq!(
+            old.diff(&new),
+            SnapshotDiff {
+                added: vec!["dns;d".to_string()],
+                removed: vec!["dns;c".to_string()],
+                changed: vec!["dns;b".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_digest_field_boundaries() {
+        let mut lhs = ObjectDigest::new();
+        lhs.fields(["ab", "c"]);
+        let mut rhs = ObjectDigest::new();
+        rhs.fields(["a", "bc"]);
+
+        assert_ne!(lhs.finish(), rhs.finish());
+    }
+
+    #[test]
+    fn test_snapshot_invalid_name() {
+        let dir = temp_dir().join(format!("netdox-snapshots-{}", std::process::id()));
+
+        for name in ["", ".", "..", "../test", "sub/test", "sub\\test"] {
+            let mut snapshot = snapshot(&[]);
+            snapshot.name = name.to_string();
+            assert!(snapshot.write_in(&dir, true).is_err(), "{name:?}");
+            assert!(Snapshot::read_in(&dir, name).is_err(), "{name:?}");
+        }
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_snapshot_overwrite() {
+        let dir = temp_dir().join(format!("netdox-snapshot-overwrite-{}", std::process::id()));
+        let old = snapshot(&[("dns;a", "1")]);
+        let new = snapshot(&[("dns;a", "2")]);
+
+        old.write_in(&dir, false).unwrap();
+        assert!(new.write_in(&dir, false).is_err());
+        assert_eq!(Snapshot::read_in(&dir, "test").unwrap(), old);
+
+        new.write_in(&dir, true).unwrap();
+        assert_eq!(Snapshot::read_in(&dir, "test").unwrap(), new);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
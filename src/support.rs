@@ -0,0 +1,566 @@
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::Write,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    process::exit,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use toml::Value;
+use tracing::{error, info, warn};
+use zip::{write::FileOptions, ZipWriter};
+
+use crate::{
+    config::LocalConfig,
+    data::{
+        model::{split_qname, RunRecord, MAX_RUNS},
+        DataConn, DataStore,
+    },
+    error::{NetdoxError, NetdoxResult},
+    io_err, success,
+};
+
+/// Value that replaces secrets in the bundled config.
+const REDACTED: &str = "<redacted>";
+/// Config keys containing any of these are treated as secrets.
+/// Webhook URLs usually contain a token.
+const SECRET_KEY_PARTS: [&str; 6] = ["pass", "secret", "token", "credential", "auth", "webhook"];
+/// Plugin config keys that are netdox's own, and not passed to the plugin.
+const PLUGIN_KEYS: [&str; 3] = ["name", "display", "stages"];
+/// Maximum number of each kind of key to include as a sample.
+const SAMPLE_SIZE: usize = 20;
+/// Number of lines to include from the end of each log file.
+const LOG_LINES: usize = 1000;
+/// Secret values from the config shorter than this are not searched for in logs,
+/// as they would match too much.
+const MIN_SECRET_LEN: usize = 4;
+
+/// Writes a zip of sanitised diagnostic information to attach to bug reports.
+/// Nothing is sent anywhere; the user decides what to do with the file.
+/// The end of each log file and the plugin logs of the last run are included, with secrets redacted.
+#[tokio::main]
+pub async fn support_bundle(output: Option<PathBuf>, logs: Vec<PathBuf>) {
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let path = output.unwrap_or_else(|| PathBuf::from(format!("netdox-support-{created}.zip")));
+
+    let mut files = vec![("versions.txt".to_string(), versions())];
+
+    // The bundle is most useful when something is broken, so failures are recorded instead of fatal.
+    let mut secrets = vec![];
+    let mut plugin_log_dir = None;
+    match LocalConfig::read() {
+        Ok(cfg) => {
+            files.push(("config.toml".to_string(), sanitised_config(&cfg)));
+            if let Ok(value) = Value::try_from(&cfg) {
+                secret_values(&value, &mut secrets);
+            }
+            plugin_log_dir.clone_from(&cfg.plugin_log_dir);
+            match cfg.con().await {
+                Ok(mut con) => files.extend(
+                    store_files(&mut con)
+                        .await
+                        .into_iter()
+                        .map(|(name, content)| (name.to_string(), content)),
+                ),
+                Err(err) => files.push((
+                    "store.txt".to_string(),
+                    format!("Failed to connect to {}: {err}\n", cfg.store_name()),
+                )),
+            }
+        }
+        Err(err) => files.push((
+            "config.toml".to_string(),
+            format!("# Failed to read config: {err}\n"),
+        )),
+    }
+
+    files.extend(log_files(&logs, plugin_log_dir.as_deref(), &secrets));
+
+    if let Err(err) = write_bundle(&path, &files) {
+        error!("Failed to write support bundle: {err}");
+        exit(1);
+    }
+
+    success!("Wrote support bundle to {}", path.display());
+    info!("Check its contents before sharing it: secrets are redacted and names are hashed, but the config still contains hostnames.");
+}
+
+/// Returns the files that are read from the data store.
+async fn store_files(con: &mut DataStore) -> Vec<(&'static str, String)> {
+    let mut files = vec![];
+
+    files.push(("counts.txt", section(counts(con).await)));
+
+    files.push((
+        "runs.toml",
+        section(match con.get_runs(MAX_RUNS).await {
+            Ok(runs) => serialise_runs(&runs),
+            Err(err) => Err(err),
+        }),
+    ));
+
+    files.push(("schema-check.txt", section(schema_check(con).await)));
+
+    files.push(("sample-keys.txt", section(sample_keys(con).await)));
+
+    files
+}
+
+/// Returns the content of a section, or the error that prevented it from being read.
+fn section(content: NetdoxResult<String>) -> String {
+    match content {
+        Ok(content) => content,
+        Err(err) => {
+            warn!("Support bundle will be incomplete: {err}");
+            format!("Failed to read from data store: {err}\n")
+        }
+    }
+}
+
+fn write_bundle(path: &Path, files: &[(String, String)]) -> NetdoxResult<()> {
+    let file = match File::create(path) {
+        Ok(file) => file,
+        Err(err) => return io_err!(format!("Failed to create {}: {err}", path.display())),
+    };
+
+    let mut zip = ZipWriter::new(file);
+    for (name, content) in files {
+        if let Err(err) = zip.start_file(name.as_str(), FileOptions::default()) {
+            return io_err!(format!("Failed to start {name} in zip: {err}"));
+        }
+        if let Err(err) = zip.write_all(content.as_bytes()) {
+            return io_err!(format!("Failed to write {name} to zip: {err}"));
+        }
+    }
+
+    match zip.finish() {
+        Ok(_) => Ok(()),
+        Err(err) => io_err!(format!("Failed to finish zip: {err}")),
+    }
+}
+
+// Sections
+
+fn versions() -> String {
+    let mut features = vec![];
+    if cfg!(feature = "pageseeder") {
+        features.push("pageseeder");
+    }
+    if cfg!(feature = "sqlite") {
+        features.push("sqlite");
+    }
+    if cfg!(feature = "psml-validation") {
+        features.push("psml-validation");
+    }
+
+    format!(
+        "netdox {}\nfeatures: {}\ntarget: {} {}\n",
+        env!("CARGO_PKG_VERSION"),
+        features.join(", "),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+    )
+}
+
+fn sanitised_config(cfg: &LocalConfig) -> String {
+    let mut value = match Value::try_from(cfg) {
+        Ok(value) => value,
+        Err(err) => return format!("# Failed to serialise config: {err}\n"),
+    };
+    redact(&mut value);
+
+    match toml::to_string_pretty(&value) {
+        Ok(content) => content,
+        Err(err) => format!("# Failed to serialise config: {err}\n"),
+    }
+}
+
+/// Replaces secrets in a config with a placeholder.
+/// Plugin config is free-form, so everything but the paths to plugins is replaced.
+fn redact(value: &mut Value) {
+    match value {
+        Value::Table(table) => {
+            for (key, value) in table.iter_mut() {
+                if is_secret_key(key) {
+                    *value = Value::String(REDACTED.to_string());
+                } else if key == "plugin" {
+                    if let Value::Array(plugins) = value {
+                        plugins.iter_mut().for_each(redact_plugin);
+                    }
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(array) => array.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+fn is_secret_key(key: &str) -> bool {
+    let lower = key.to_lowercase();
+    SECRET_KEY_PARTS.iter().any(|part| lower.contains(part))
+}
+
+/// Collects the strings in a config that are under secret keys.
+fn secret_values(value: &Value, secrets: &mut Vec<String>) {
+    match value {
+        Value::Table(table) => {
+            for (key, value) in table {
+                match value {
+                    Value::String(secret) if is_secret_key(key) => secrets.push(secret.clone()),
+                    value => secret_values(value, secrets),
+                }
+            }
+        }
+        Value::Array(array) => array.iter().for_each(|value| secret_values(value, secrets)),
+        _ => {}
+    }
+}
+
+fn redact_plugin(plugin: &mut Value) {
+    let Value::Table(table) = plugin else {
+        return;
+    };
+
+    for (key, value) in table.iter_mut() {
+        if key == "stages" {
+            if let Value::Table(stages) = value {
+                for (_, stage) in stages.iter_mut() {
+                    redact_plugin_stage(stage);
+                }
+            }
+        } else if !PLUGIN_KEYS.contains(&key.as_str()) {
+            *value = Value::String(REDACTED.to_string());
+        }
+    }
+}
+
+fn redact_plugin_stage(stage: &mut Value) {
+    if let Value::Table(table) = stage {
+        for (key, value) in table.iter_mut() {
+            if key != "path" {
+                *value = Value::String(REDACTED.to_string());
+            }
+        }
+    }
+}
+
+/// Returns the end of each log file, and of the plugin logs from the last run in the plugin log directory.
+fn log_files(
+    logs: &[PathBuf],
+    plugin_log_dir: Option<&Path>,
+    secrets: &[String],
+) -> Vec<(String, String)> {
+    let mut paths = logs
+        .iter()
+        .map(|path| {
+            let name = path.file_name().map_or_else(
+                || "netdox.log".to_string(),
+                |name| name.to_string_lossy().to_string(),
+            );
+            (format!("logs/{name}"), path.clone())
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(dir) = plugin_log_dir {
+        match last_run_logs(dir) {
+            Ok(run_logs) => paths.extend(run_logs),
+            Err(err) => warn!("Support bundle will not have plugin logs: {err}"),
+        }
+    }
+
+    paths
+        .into_iter()
+        .map(|(name, path)| {
+            let content = match fs::read_to_string(&path) {
+                Ok(content) => redact_log(&content, secrets),
+                Err(err) => {
+                    warn!(
+                        "Support bundle will be incomplete: failed to read log {}: {err}",
+                        path.display()
+                    );
+                    format!("Failed to read {}: {err}\n", path.display())
+                }
+            };
+            (name, content)
+        })
+        .collect()
+}
+
+/// Returns names in the bundle and paths of the plugin logs in the directory of the last run,
+/// which is the one named with the latest start time.
+fn last_run_logs(dir: &Path) -> NetdoxResult<Vec<(String, PathBuf)>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => return io_err!(format!("Failed to read {}: {err}", dir.display())),
+    };
+    let Some((started, run_dir)) = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let started = entry.file_name().to_str()?.parse::<u64>().ok()?;
+            Some((started, entry.path()))
+        })
+        .max_by_key(|(started, _)| *started)
+    else {
+        return Ok(vec![]);
+    };
+
+    let entries = match fs::read_dir(&run_dir) {
+        Ok(entries) => entries,
+        Err(err) => return io_err!(format!("Failed to read {}: {err}", run_dir.display())),
+    };
+    let mut logs = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_file())
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            (format!("logs/plugins/{started}/{name}"), entry.path())
+        })
+        .collect::<Vec<_>>();
+    logs.sort();
+    Ok(logs)
+}
+
+/// Returns the last lines of a log with secrets replaced by a placeholder.
+/// Values of fields with secret keys are replaced, like `password=...` or `"token":"..."`,
+/// and so is any occurrence of a secret value from the config.
+fn redact_log(content: &str, secrets: &[String]) -> String {
+    let lines = content.lines().collect::<Vec<_>>();
+    let mut redacted = String::new();
+    for line in &lines[lines.len().saturating_sub(LOG_LINES)..] {
+        let mut line = redact_log_line(line);
+        for secret in secrets
+            .iter()
+            .filter(|secret| secret.len() >= MIN_SECRET_LEN)
+        {
+            line = line.replace(secret.as_str(), REDACTED);
+        }
+        redacted.push_str(&line);
+        redacted.push('\n');
+    }
+    redacted
+}
+
+/// Replaces the values of fields with secret keys in a log line.
+/// Keys are followed by `=`, or by `:` if they are quoted like in JSON.
+fn redact_log_line(line: &str) -> String {
+    let mut redacted = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(idx) = rest.find(['=', ':']) {
+        let (before, after) = rest.split_at(idx);
+        let (separator, after) = after.split_at(1);
+        redacted.push_str(before);
+        redacted.push_str(separator);
+        rest = after;
+
+        let quoted = before.ends_with('"');
+        let key = before
+            .trim_end_matches('"')
+            .rsplit(|char: char| !(char.is_alphanumeric() || char == '_' || char == '-'))
+            .next()
+            .unwrap_or_default();
+        if key.is_empty() || (separator == ":" && !quoted) || !is_secret_key(key) {
+            continue;
+        }
+
+        let value = rest.trim_start();
+        redacted.push_str(&rest[..rest.len() - value.len()]);
+        let end = match value.strip_prefix('"') {
+            Some(inner) => {
+                redacted.push('"');
+                rest = inner;
+                inner.find('"').unwrap_or(inner.len())
+            }
+            None => {
+                rest = value;
+                value
+                    .find(|char: char| char.is_whitespace() || ",;&}]".contains(char))
+                    .unwrap_or(value.len())
+            }
+        };
+        redacted.push_str(REDACTED);
+        rest = &rest[end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+async fn counts(con: &mut DataStore) -> NetdoxResult<String> {
+    let mut counts = String::new();
+    let _ = writeln!(counts, "DNS names: {}", con.get_dns_names().await?.len());
+    let _ = writeln!(counts, "raw nodes: {}", con.get_raw_nodes().await?.len());
+    let _ = writeln!(counts, "nodes: {}", con.get_node_ids().await?.len());
+    let _ = writeln!(counts, "reports: {}", con.get_report_ids().await?.len());
+    let _ = writeln!(counts, "networks: {}", con.get_networks().await?.len());
+    let _ = writeln!(counts, "changes: {}", con.total_change_count().await?);
+    Ok(counts)
+}
+
+fn serialise_runs(runs: &[RunRecord]) -> NetdoxResult<String> {
+    #[derive(Serialize)]
+    struct Runs<'a> {
+        run: &'a [RunRecord],
+    }
+
+    match toml::to_string(&Runs { run: runs }) {
+        Ok(content) => Ok(content),
+        Err(err) => io_err!(format!("Failed to serialise runs: {err}")),
+    }
+}
+
+/// Reports data stored under legacy key layouts without moving it.
+async fn schema_check(con: &mut DataStore) -> NetdoxResult<String> {
+    let migration = con.migrate_metadata(true).await?;
+    Ok(format!(
+        "legacy metadata keys that can be migrated: {}\nlegacy metadata keys that match no object: {}\n",
+        migration.moved.len(),
+        migration.unresolved.len()
+    ))
+}
+
+/// Returns some DNS names and node IDs, hashed so they show the shape of the data but not its content.
+async fn sample_keys(con: &mut DataStore) -> NetdoxResult<String> {
+    let mut qnames = con.get_dns_names().await?.into_iter().collect::<Vec<_>>();
+    qnames.sort();
+    let mut node_ids = con.get_node_ids().await?.into_iter().collect::<Vec<_>>();
+    node_ids.sort();
+
+    let mut sample = String::from("# DNS names\n");
+    for qname in qnames.iter().take(SAMPLE_SIZE) {
+        let _ = writeln!(sample, "{}", anonymise_qname(qname));
+    }
+    sample.push_str("\n# Node IDs\n");
+    for id in node_ids.iter().take(SAMPLE_SIZE) {
+        let _ = writeln!(sample, "{}", anonymise(id));
+    }
+
+    Ok(sample)
+}
+
+/// Returns a short hash of a value.
+fn anonymise(value: &str) -> String {
+    Sha256::digest(value.as_bytes())
+        .iter()
+        .take(4)
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+/// Hashes each part of a qualified DNS name except the top level domain,
+/// so the number of labels and the kind of name are preserved.
+fn anonymise_qname(qname: &str) -> String {
+    let Some((network, name)) = split_qname(qname) else {
+        return anonymise(qname);
+    };
+
+    let network = anonymise(network);
+    if name.parse::<IpAddr>().is_ok() {
+        return format!("[{network}]ip-{}", anonymise(name));
+    }
+
+    let mut labels = name.split('.').collect::<Vec<_>>();
+    let tld = if labels.len() > 1 { labels.pop() } else { None };
+    let mut anonymised = labels.into_iter().map(anonymise).collect::<Vec<_>>();
+    if let Some(tld) = tld {
+        anonymised.push(tld.to_string());
+    }
+
+    format!("[{network}]{}", anonymised.join("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use toml::Value;
+
+    use super::{anonymise, anonymise_qname, redact, redact_log, REDACTED};
+
+    #[test]
+    fn test_redact() {
+        let mut config: Value = toml::from_str(
+            r#"
+            default_network = "net"
+
+            [redis]
+            host = "localhost"
+            password = "hunter2"
+
+            [remote.pageseeder]
+            client_secret = "abc"
+
+            [[notifications.webhook]]
+            url = "https://hooks.slack.com/services/T000/B000/XXXX"
+
+            [[plugin]]
+            name = "plugin"
+            api = "key"
+            stages.write-only.path = "/bin/plugin"
+            stages.write-only.token = "xyz"
+            "#,
+        )
+        .unwrap();
+
+        redact(&mut config);
+
+        assert_eq!(config["default_network"].as_str(), Some("net"));
+        assert_eq!(config["redis"]["host"].as_str(), Some("localhost"));
+        assert_eq!(config["redis"]["password"].as_str(), Some(REDACTED));
+        assert_eq!(
+            config["remote"]["pageseeder"]["client_secret"].as_str(),
+            Some(REDACTED)
+        );
+        assert_eq!(config["notifications"]["webhook"].as_str(), Some(REDACTED));
+
+        let plugin = &config["plugin"][0];
+        assert_eq!(plugin["name"].as_str(), Some("plugin"));
+        assert_eq!(plugin["api"].as_str(), Some(REDACTED));
+        let stage = &plugin["stages"]["write-only"];
+        assert_eq!(stage["path"].as_str(), Some("/bin/plugin"));
+        assert_eq!(stage["token"].as_str(), Some(REDACTED));
+    }
+
+    #[test]
+    fn test_redact_log() {
+        let log = "first line\n\
+            [info] Connecting to redis at localhost password=hunter2 db=0\n\
+            {\"fields\":{\"client_secret\": \"abc def\",\"url\":\"https://example.com\"}}\n\
+            plugin printed the key xyz-123 with no name\n";
+
+        assert_eq!(
+            redact_log(log, &["xyz-123".to_string(), "x".to_string()]),
+            "first line\n\
+            [info] Connecting to redis at localhost password=<redacted> db=0\n\
+            {\"fields\":{\"client_secret\": \"<redacted>\",\"url\":\"https://example.com\"}}\n\
+            plugin printed the key <redacted> with no name\n"
+        );
+        assert_eq!(
+            redact_log(&"line\n".repeat(2000), &[]).lines().count(),
+            1000
+        );
+    }
+
+    #[test]
+    fn test_anonymise_qname() {
+        let net = anonymise("net");
+        let (www, example) = (anonymise("www"), anonymise("example"));
+
+        assert_eq!(
+            anonymise_qname("[net]www.example.com"),
+            format!("[{net}]{www}.{example}.com")
+        );
+        assert_eq!(
+            anonymise_qname("[net]10.0.0.1"),
+            format!("[{net}]ip-{}", anonymise("10.0.0.1"))
+        );
+        assert_eq!(anonymise("www").len(), 8);
+    }
+}
@@ -1,9 +1,23 @@
-use std::{env, sync::LazyLock};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    sync::LazyLock,
+};
 
-use redis::{aio::MultiplexedConnection, Client};
+use redis::Client;
 use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
 
-use crate::{config::LocalConfig, data::DataConn, remote::DummyRemote};
+use crate::{
+    config::{local::IgnoreList, LocalConfig, PluginConfig},
+    data::{
+        model::PluginScope,
+        store::{memory_store::MemoryStore, redis_store::RedisConnection},
+        DataConn,
+    },
+    logging::redact_url,
+    remote::DummyRemote,
+};
 
 pub static TIMESTAMP: LazyLock<u64> = LazyLock::new(|| {
     SystemTime::now()
@@ -12,8 +26,13 @@ pub static TIMESTAMP: LazyLock<u64> = LazyLock::new(|| {
         .as_secs()
 });
 
+/// Writing the results of processing to the shared test database deletes every processed node
+/// it did not write. Tests that do so hold this for writing,
+/// and tests that add processed node IDs to the database hold it for reading.
+pub static PROC_NODES_LOCK: RwLock<()> = RwLock::const_new(());
+
 /// Calls a custom function with the specifies args, and unwraps the result.
-pub async fn call_fn(con: &mut MultiplexedConnection, function: &str, args: &[&str]) {
+pub async fn call_fn(con: &mut RedisConnection, function: &str, args: &[&str]) {
     let mut cmd = redis::cmd("fcall");
     cmd.arg(function);
     for arg in args {
@@ -26,37 +45,70 @@ pub async fn call_fn(con: &mut MultiplexedConnection, function: &str, args: &[&s
     }
 }
 
-/// Name of the environment variable that contains the test redis server URL.
-pub const TEST_REDIS_URL_VAR: &str = "NETDOX_TEST_REDIS_URL";
+pub use crate::dev::function_tests::{test_client, TEST_REDIS_URL_VAR};
 
 /// Connects to the database, flushes it, and runs setup commands.
 pub async fn setup_db() -> Client {
     let url = env::var(TEST_REDIS_URL_VAR).unwrap_or_else(|_| {
         panic!("Environment variable {TEST_REDIS_URL_VAR} must be set to test lua functions.")
     });
-    let client = Client::open(url.as_str())
-        .unwrap_or_else(|_| panic!("Failed to create client with url {}", &url));
+    let client = test_client(&url).unwrap_or_else(|err| {
+        panic!(
+            "Failed to create client with url {}: {err}",
+            redact_url(&url)
+        )
+    });
 
-    let mut con = client
-        .get_multiplexed_async_connection()
-        .await
-        .unwrap_or_else(|_| panic!("Failed to open connection with url {}", &url));
-
-    let mut cfg = LocalConfig::template(crate::remote::Remote::Dummy(DummyRemote {
-        field: "".to_string(),
-    }));
-    cfg.default_network = DEFAULT_NETWORK.to_string();
-    con.setup(&cfg).await.unwrap();
+    let mut con = RedisConnection::from(
+        client
+            .get_multiplexed_async_connection()
+            .await
+            .unwrap_or_else(|_| panic!("Failed to open connection with url {}", redact_url(&url))),
+    );
+    con.setup(&test_config()).await.unwrap();
 
     client
 }
 
-pub async fn setup_db_con() -> MultiplexedConnection {
+pub async fn setup_db_con() -> RedisConnection {
     setup_db()
         .await
         .get_multiplexed_async_connection()
         .await
         .expect("Failed to get connection to test redis from client")
+        .into()
+}
+
+/// Creates an empty data store in memory and runs setup commands.
+/// Plugins cannot write to it, as it has no Lua functions.
+pub async fn setup_memory_con() -> MemoryStore {
+    let mut con = MemoryStore::new();
+    con.setup(&test_config()).await.unwrap();
+    con
+}
+
+/// Returns the config that test data stores are set up with.
+fn test_config() -> LocalConfig {
+    let mut cfg = LocalConfig::template(crate::remote::Remote::Dummy(DummyRemote {
+        field: "".to_string(),
+    }));
+    cfg.default_network = DEFAULT_NETWORK.to_string();
+    cfg.roles = HashSet::from([ROLE.to_string()]);
+    cfg.network_suffixes = HashMap::from([(SUFFIX.to_string(), SUFFIX_NETWORK.to_string())]);
+    cfg.dns_ignore = IgnoreList::Set(HashSet::from([IGNORE_PATTERN.to_string()]));
+    cfg.plugins = vec![PluginConfig {
+        name: SCOPED_PLUGIN.to_string(),
+        display: Default::default(),
+        scope: PluginScope {
+            networks: vec![],
+            zones: vec![SCOPE_ZONE.to_string()],
+        },
+        timeout_seconds: None,
+        schema_version: None,
+        fields: HashMap::new(),
+        stages: HashMap::new(),
+    }];
+    cfg
 }
 
 // CONSTANTS
@@ -65,3 +117,15 @@ pub async fn setup_db_con() -> MultiplexedConnection {
 pub const DEFAULT_NETWORK: &str = "default-net";
 /// Plugin to use for testing.
 pub const PLUGIN: &str = "test-plugin";
+/// Valid node role to use for testing.
+pub const ROLE: &str = "database";
+/// DNS suffix that is mapped to a network for testing.
+pub const SUFFIX: &str = "inferred.test";
+/// Network that names with the test suffix are in.
+pub const SUFFIX_NETWORK: &str = "inferred-net";
+/// Pattern in the DNS ignore list for testing.
+pub const IGNORE_PATTERN: &str = "*.dyn.ignored.test";
+/// Plugin that may only create DNS names in the test zone.
+pub const SCOPED_PLUGIN: &str = "scoped-plugin";
+/// Zone the scoped plugin may create DNS names in.
+pub const SCOPE_ZONE: &str = "scoped.test";
@@ -1,18 +1,37 @@
-use std::{collections::HashMap, process::Stdio};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Output, Stdio},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use tokio::{process::Command, task::JoinSet};
+use tokio::{
+    process::Command,
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+    time::{sleep, timeout},
+};
 
-use paris::{info, warn};
+use indexmap::IndexMap;
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
+use tracing::{debug, debug_span, info, warn, Instrument};
 
 use crate::{
-    config::{LocalConfig, PluginStage},
+    config::{LocalConfig, PluginConfig, PluginStage, WorkerPoolConfig},
     data::{
-        model::{Data, StringType, NETDOX_PLUGIN},
+        model::{
+            Change, ChangelogEntry, Data, StageSnapshot, StringType, NETDOX_PLUGIN, SCHEMA_VERSION,
+        },
         DataConn,
     },
     error::{NetdoxError, NetdoxResult},
+    io_err, metrics,
+    notify::{notify, Event},
     plugin_err,
+    remote::file_stem,
 };
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -21,97 +40,696 @@ pub struct PluginResult {
     pub stage: PluginStage,
     pub name: String,
     pub code: Option<i32>,
+    pub stdout: String,
     pub stderr: String,
+    pub duration: Duration,
+    /// Whether the process was killed for running longer than its timeout.
+    #[serde(default)]
+    pub timed_out: bool,
+    /// Path to the file the output of the process was written to, if any.
+    #[serde(default)]
+    pub log_path: Option<PathBuf>,
+    /// Whether the stage was skipped because its probe reported no upstream changes.
+    /// The duration is the time the probe took.
+    #[serde(default)]
+    pub skipped: bool,
+}
+
+/// Prefix of the lines a plugin prints on stdout to report the config fields it reads,
+/// like `netdox-fields: api_url, api_key`.
+pub const FIELDS_PREFIX: &str = "netdox-fields:";
+
+/// Delay before the first retry of a failed plugin stage. Doubles for each retry after that.
+const RETRY_BACKOFF: Duration = Duration::from_secs(2);
+
+/// How a plugin stage process ended.
+enum StageOutcome {
+    Finished(Output),
+    TimedOut(Duration),
+    /// The probe printed the same cursor as before the last successful run.
+    Unchanged,
+    /// Every batch of a worker pool finished or timed out.
+    Pooled(PoolOutcome),
+}
+
+/// Combined outcome of the batches of a worker pool.
+struct PoolOutcome {
+    batches: usize,
+    failed: usize,
+    /// Exit code of the first batch that failed, or 0 if none did.
+    code: Option<i32>,
+    stdout: String,
+    stderr: String,
+    timed_out: bool,
+}
+
+impl PoolOutcome {
+    /// Combines the outcomes of the batches, in the order the batches were given.
+    fn combine(outcomes: Vec<StageOutcome>) -> Self {
+        let mut pool = PoolOutcome {
+            batches: outcomes.len(),
+            failed: 0,
+            code: Some(0),
+            stdout: String::new(),
+            stderr: String::new(),
+            timed_out: false,
+        };
+        for (index, outcome) in (1..).zip(outcomes) {
+            match outcome {
+                StageOutcome::Finished(output) => {
+                    pool.stdout
+                        .push_str(&String::from_utf8_lossy(&output.stdout));
+                    pool.stderr
+                        .push_str(&String::from_utf8_lossy(&output.stderr));
+                    if !output.status.success() {
+                        if pool.failed == 0 {
+                            pool.code = output.status.code();
+                        }
+                        pool.failed += 1;
+                        pool.stderr.push_str(&format!(
+                            "Batch {index} had exit code {:?}.\n",
+                            output.status.code()
+                        ));
+                    }
+                }
+                StageOutcome::TimedOut(limit) => {
+                    if pool.failed == 0 {
+                        pool.code = None;
+                    }
+                    pool.failed += 1;
+                    pool.timed_out = true;
+                    pool.stderr.push_str(&format!(
+                        "Batch {index} was killed after exceeding timeout of {}s.\n",
+                        limit.as_secs()
+                    ));
+                }
+                StageOutcome::Unchanged | StageOutcome::Pooled(_) => {}
+            }
+        }
+        pool
+    }
+}
+
+/// Context a worker pool gives a stage about one processed node.
+#[derive(Serialize, Debug, PartialEq)]
+struct WorkerNode {
+    link_id: String,
+    name: String,
+    alt_names: BTreeSet<String>,
+    dns_names: BTreeSet<String>,
+    plugins: BTreeSet<String>,
+    tags: BTreeSet<String>,
+    metadata: BTreeMap<String, String>,
+}
+
+/// Context given to one run of a stage in a worker pool.
+#[derive(Serialize)]
+struct WorkerBatch<'a> {
+    nodes: &'a [WorkerNode],
+}
+
+/// Batches of nodes to run a stage for.
+struct WorkerPool {
+    /// Maximum number of batches to run at once.
+    concurrency: usize,
+    /// Context for each batch, as TOML.
+    batches: Vec<String>,
+}
+
+impl WorkerPool {
+    /// Finds the processed nodes that match the filter of a worker pool,
+    /// and splits them into batches ordered by link ID.
+    async fn load(con: &mut impl DataConn, config: &WorkerPoolConfig) -> NetdoxResult<Self> {
+        if config.concurrency == 0 || config.batch_size == 0 {
+            return plugin_err!("Worker pool concurrency and batch size cannot be 0.".to_string());
+        }
+
+        let mut nodes = vec![];
+        for link_id in con.get_node_ids().await?.into_iter().sorted() {
+            let node = con.get_node(&link_id).await?;
+            let tags = if config.filter.tags.is_empty() {
+                HashSet::new()
+            } else {
+                con.get_node_tags(&node).await?
+            };
+            let metadata = con.get_node_metadata(&node).await?;
+            if !config.filter.matches(&node, &tags, &metadata) {
+                continue;
+            }
+
+            nodes.push(WorkerNode {
+                link_id: node.link_id,
+                name: node.name,
+                alt_names: node.alt_names.into_iter().collect(),
+                dns_names: node.dns_names.into_iter().collect(),
+                plugins: node.plugins.into_iter().collect(),
+                tags: tags.into_iter().collect(),
+                metadata: metadata.into_iter().collect(),
+            });
+        }
+
+        let mut batches = vec![];
+        for nodes in nodes.chunks(config.batch_size) {
+            match toml::to_string(&WorkerBatch { nodes }) {
+                Ok(batch) => batches.push(batch),
+                Err(err) => {
+                    return plugin_err!(format!("Failed to serialize worker pool batch: {err}"))
+                }
+            }
+        }
+
+        Ok(WorkerPool {
+            concurrency: config.concurrency,
+            batches,
+        })
+    }
+}
+
+/// Settings for running one plugin stage executable.
+struct StageRun {
+    path: String,
+    args: [String; 3],
+    retries: u32,
+    /// Delay before the first retry, which doubles for each retry after that.
+    backoff: Duration,
+    timeout: Option<Duration>,
+    /// Path to the probe executable, if the stage has one.
+    probe: Option<String>,
+    /// Cursor the probe printed before the stage last succeeded.
+    cursor: Option<String>,
+    /// Batches of nodes to run the executable for, if the stage has a worker pool.
+    pool: Option<WorkerPool>,
+}
+
+impl StageRun {
+    /// Returns a command that runs the executable with its arguments,
+    /// and the context of a batch of nodes if there is one.
+    /// The process is killed if the command is dropped before it exits.
+    fn command(&self, batch: Option<&str>) -> Command {
+        let mut cmd = Command::new(&self.path);
+        cmd.args(&self.args)
+            .args(batch)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        cmd
+    }
+
+    /// Runs the probe with the arguments of the stage and the cursor from the last successful run,
+    /// or an empty string if there is none, and returns the cursor it prints.
+    /// Returns None if there is no probe, or if it fails, in which case the stage should run.
+    async fn probe(&self, name: &str, stage: PluginStage) -> Option<String> {
+        let path = self.probe.as_ref()?;
+        let mut cmd = Command::new(path);
+        cmd.args(&self.args)
+            .arg(self.cursor.as_deref().unwrap_or_default())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        let output = match self.timeout {
+            Some(limit) => match timeout(limit, cmd.output()).await {
+                Ok(output) => output,
+                Err(_) => {
+                    warn!("Probe for plugin {name} timed out in {stage} stage, running the stage.");
+                    return None;
+                }
+            },
+            None => cmd.output().await,
+        };
+
+        match output {
+            Ok(output) if output.status.success() => {
+                let cursor = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                if cursor.is_empty() {
+                    warn!("Probe for plugin {name} printed no cursor in {stage} stage, running the stage.");
+                    None
+                } else {
+                    Some(cursor)
+                }
+            }
+            Ok(output) => {
+                warn!(
+                    "Probe for plugin {name} had exit code {:?} in {stage} stage, running the stage. stderr follows:\n{}",
+                    output.status.code(),
+                    String::from_utf8_lossy(&output.stderr)
+                );
+                None
+            }
+            Err(err) => {
+                warn!("Failed to run probe for plugin {name} in {stage} stage, running the stage: {err}");
+                None
+            }
+        }
+    }
+
+    /// Runs the executable until it succeeds, times out, or runs out of retries.
+    async fn run(
+        &self,
+        name: &str,
+        stage: PluginStage,
+        batch: Option<&str>,
+    ) -> io::Result<StageOutcome> {
+        let mut attempt = 0;
+        loop {
+            let proc = self.command(batch).spawn()?;
+            let output = match self.timeout {
+                Some(limit) => match timeout(limit, proc.wait_with_output()).await {
+                    Ok(output) => output?,
+                    Err(_) => return Ok(StageOutcome::TimedOut(limit)),
+                },
+                None => proc.wait_with_output().await?,
+            };
+
+            if output.status.success() || attempt >= self.retries {
+                return Ok(StageOutcome::Finished(output));
+            }
+
+            let delay = self.backoff * 2_u32.saturating_pow(attempt);
+            attempt += 1;
+            warn!(
+                "Plugin {name} failed {stage} stage, retrying in {}s ({attempt}/{})...",
+                delay.as_secs(),
+                self.retries
+            );
+            sleep(delay).await;
+        }
+    }
+
+    /// Runs the executable once for each batch of its worker pool, with a limited number at once,
+    /// or once with no batch if it has no worker pool.
+    async fn run_pool(self: Arc<Self>, name: &str, stage: PluginStage) -> io::Result<StageOutcome> {
+        let Some(pool) = &self.pool else {
+            return self.run(name, stage, None).await;
+        };
+
+        let semaphore = Arc::new(Semaphore::new(pool.concurrency));
+        let mut procs = JoinSet::new();
+        for (index, batch) in pool.batches.iter().enumerate() {
+            // The semaphore is never closed.
+            let permit = Arc::clone(&semaphore).acquire_owned().await.ok();
+            let run = Arc::clone(&self);
+            let (name, batch) = (name.to_string(), batch.clone());
+            procs.spawn(async move {
+                let _permit = permit;
+                (index, run.run(&name, stage, Some(&batch)).await)
+            });
+        }
+
+        let mut outcomes = vec![];
+        while let Some(join_result) = procs.join_next().await {
+            match join_result {
+                Ok((index, Ok(outcome))) => outcomes.push((index, outcome)),
+                Ok((_, Err(err))) => {
+                    procs.abort_all();
+                    return Err(err);
+                }
+                Err(err) => {
+                    procs.abort_all();
+                    return Err(io::Error::other(err));
+                }
+            }
+        }
+
+        outcomes.sort_by_key(|(index, _)| *index);
+        Ok(StageOutcome::Pooled(PoolOutcome::combine(
+            outcomes.into_iter().map(|(_, outcome)| outcome).collect(),
+        )))
+    }
+}
+
+/// Tracks the plugins an update defers to the next one because it ran out of time.
+#[derive(Default)]
+pub struct Deferrals {
+    /// Time after which no more plugins are started.
+    deadline: Option<Instant>,
+    /// Plugins deferred by the last update, which are started before any others.
+    previous: HashSet<String>,
+    /// Plugins deferred by this update, which skip their later stages as well.
+    deferred: HashSet<String>,
+    /// Plugins that started at least one stage in this update.
+    started: HashSet<String>,
+}
+
+impl Deferrals {
+    /// Reads the plugins deferred by the last update,
+    /// and starts the time this update has to start plugins in, if it is limited.
+    pub async fn load(
+        con: &mut impl DataConn,
+        max_duration: Option<Duration>,
+    ) -> NetdoxResult<Self> {
+        Ok(Deferrals {
+            deadline: max_duration.map(|duration| Instant::now() + duration),
+            previous: con.get_deferred_plugins().await?,
+            ..Default::default()
+        })
+    }
+
+    /// Returns true if the time to start plugins in has run out.
+    fn is_expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Waits for a permit to start a plugin, for no longer than the time left to start plugins in.
+    /// Returns none if the time runs out first.
+    async fn permit(&self, semaphore: &Arc<Semaphore>) -> Option<OwnedSemaphorePermit> {
+        if self.is_expired() {
+            return None;
+        }
+
+        // The semaphore is never closed.
+        let acquire = Arc::clone(semaphore).acquire_owned();
+        match self.deadline {
+            Some(deadline) => timeout(deadline.saturating_duration_since(Instant::now()), acquire)
+                .await
+                .ok()?
+                .ok(),
+            None => acquire.await.ok(),
+        }
+    }
+
+    /// Returns the plugins to defer to the next update:
+    /// those deferred by this update, and those deferred by the last one that did not start.
+    pub fn remaining(&self) -> HashSet<String> {
+        self.previous
+            .iter()
+            .filter(|plugin| !self.started.contains(*plugin))
+            .chain(&self.deferred)
+            .cloned()
+            .collect()
+    }
+
+    /// Writes the plugins to defer to the next update to the data store.
+    pub async fn save(&self, con: &mut impl DataConn) -> NetdoxResult<()> {
+        con.set_deferred_plugins(&self.remaining()).await
+    }
+}
+
+/// Returns true if a plugin should run, given a list of plugins to include or exclude.
+fn is_selected(plugin: &PluginConfig, plugin_list: Option<&Vec<String>>, exclude: bool) -> bool {
+    match plugin_list {
+        Some(names) => exclude ^ names.contains(&plugin.name),
+        None => true,
+    }
+}
+
+/// Checks that every plugin that will run was written for the data schema of this netdox.
+/// Plugins that do not declare a schema version are assumed to match.
+pub fn check_schema_versions(
+    plugins: &[PluginConfig],
+    plugin_list: Option<&Vec<String>>,
+    exclude: bool,
+) -> NetdoxResult<()> {
+    let mismatched = plugins
+        .iter()
+        .filter(|plugin| is_selected(plugin, plugin_list, exclude))
+        .filter_map(|plugin| match plugin.schema_version {
+            Some(version) if version != SCHEMA_VERSION => {
+                Some(format!("{} (written for {version})", plugin.name))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    if mismatched.is_empty() {
+        Ok(())
+    } else {
+        plugin_err!(format!(
+            "Plugins must be updated for data schema version {SCHEMA_VERSION} before they can run: {}",
+            mismatched.join(", ")
+        ))
+    }
+}
+
+/// Takes a snapshot of the data store at the start of a plugin stage.
+pub async fn stage_snapshot(
+    con: &mut impl DataConn,
+    stage: PluginStage,
+) -> NetdoxResult<StageSnapshot> {
+    Ok(StageSnapshot {
+        stage: stage.to_string(),
+        start_change: last_change(con).await?,
+        end_change: None,
+        changes: con.total_change_count().await?,
+        dns_names: con.get_dns_names().await?.len(),
+        nodes: con.get_node_ids().await?.len(),
+    })
+}
+
+/// Returns the ID of the last change in the changelog, if there is one.
+async fn last_change(con: &mut impl DataConn) -> NetdoxResult<Option<String>> {
+    match con.total_change_count().await? {
+        0 => Ok(None),
+        _ => Ok(Some(con.last_change_id().await?)),
+    }
 }
 
 /// Runs one stage for all allowed plugins.
+/// Plugins that exit with a nonzero code are run again, up to the number of retries for the stage.
+/// Plugins that run longer than their timeout are killed.
+/// Plugins with a probe for the stage skip it if the probe prints the same cursor as it did
+/// before the stage last succeeded, and the new cursor is stored once the stage succeeds.
+/// Plugins deferred by the last update start first, and once the time the update has to start
+/// plugins in runs out, the rest are deferred to the next update.
+/// Returns the snapshot every plugin was given, with the last change ID at the end of the stage.
 pub async fn run_plugin_stage(
     config: &LocalConfig,
     stage: PluginStage,
     plugin_list: Option<&Vec<String>>,
     exclude: bool,
-) -> NetdoxResult<Vec<PluginResult>> {
-    let datastore_cfg =
-        toml::to_string(&config.redis).expect("Failed to serialise local config to TOML.");
+    deferrals: &mut Deferrals,
+) -> NetdoxResult<(StageSnapshot, Vec<PluginResult>)> {
+    let datastore_cfg = config.store_config().await?;
+    let mut con = config.con().await?;
+    let mut snapshot = stage_snapshot(&mut con, stage).await?;
+    let snapshot_cfg = match toml::to_string(&snapshot) {
+        Ok(snapshot_cfg) => snapshot_cfg,
+        Err(err) => {
+            return plugin_err!(format!(
+                "Failed to serialize snapshot for {stage} stage: {err}"
+            ))
+        }
+    };
 
-    let mut cmds = HashMap::new();
+    let mut runs = IndexMap::new();
     for plugin in &config.plugins {
-        if cmds.contains_key(&plugin.name) {
+        if runs.contains_key(&plugin.name) {
             return plugin_err!(format!(
                 "Plugin name {} appears multiple times.",
                 plugin.name
             ));
         }
 
-        if let Some(names) = &plugin_list {
-            if !(exclude ^ names.contains(&plugin.name)) {
-                continue;
-            }
+        if !is_selected(plugin, plugin_list, exclude) || deferrals.deferred.contains(&plugin.name) {
+            continue;
         }
 
         if let Some(stage_config) = plugin.stages.get(&stage) {
-            let mut cmd = Command::new(&stage_config.path);
             let plugin_cfg = plugin
                 .fields
                 .iter()
                 .chain(&stage_config.fields)
                 .collect::<HashMap<_, _>>();
 
-            match toml::to_string(&plugin_cfg) {
-                Ok(plugin_cfg_str) => {
-                    cmd.arg(&datastore_cfg);
-                    cmd.arg(plugin_cfg_str);
-                }
+            let args = match toml::to_string(&plugin_cfg) {
+                Ok(plugin_cfg_str) => [datastore_cfg.clone(), plugin_cfg_str, snapshot_cfg.clone()],
                 Err(err) => {
                     return plugin_err!(format!(
                         "Failed to serialize additional config fields for {}: {err}",
                         plugin.name
                     ))
                 }
-            }
+            };
+
+            let pool = match &stage_config.workers {
+                Some(_) if stage != PluginStage::Connectors => {
+                    return plugin_err!(format!(
+                        "Plugin {} has a worker pool in the {stage} stage, \
+                        but worker pools are only allowed in the connectors stage.",
+                        plugin.name
+                    ))
+                }
+                Some(workers) => Some(WorkerPool::load(&mut con, workers).await?),
+                None => None,
+            };
+
+            let cursor = match &stage_config.probe {
+                Some(_) => {
+                    con.get_plugin_cursor(&plugin.name, &stage.to_string())
+                        .await?
+                }
+                None => None,
+            };
 
-            cmds.insert(plugin.name.clone(), cmd);
+            debug!(
+                "Plugin {} will run {stage} stage with executable: {}",
+                plugin.name, stage_config.path
+            );
+            runs.insert(
+                plugin.name.clone(),
+                StageRun {
+                    path: stage_config.path.clone(),
+                    args,
+                    retries: stage_config.retries,
+                    backoff: RETRY_BACKOFF,
+                    timeout: plugin.timeout_seconds.map(Duration::from_secs),
+                    probe: stage_config.probe.clone(),
+                    cursor,
+                    pool,
+                },
+            );
         }
     }
 
-    if cmds.is_empty() {
+    // The sort is stable, so other plugins start in the order they are configured.
+    runs.sort_by(|a, _, b, _| {
+        deferrals
+            .previous
+            .contains(b)
+            .cmp(&deferrals.previous.contains(a))
+    });
+
+    if runs.is_empty() {
         info!("No plugins to run for {stage} stage.");
     } else {
         info!(
             "Starting plugins for {stage} stage: {}",
-            cmds.keys()
+            runs.keys()
                 .map(std::string::String::as_str)
                 .collect::<Vec<_>>()
                 .join(", ")
         );
     }
 
+    let permits = match config.max_concurrent_plugins {
+        Some(0) => {
+            return plugin_err!("Maximum number of concurrent plugins cannot be 0.".to_string())
+        }
+        Some(max) => max,
+        None => Semaphore::MAX_PERMITS,
+    };
+    let semaphore = Arc::new(Semaphore::new(permits));
+
     let mut procs = JoinSet::new();
-    for (name, mut cmd) in cmds {
-        match cmd.stderr(Stdio::piped()).spawn() {
-            Ok(proc) => {
-                procs.spawn(async move { (name, proc.wait_with_output().await) });
-            }
-            Err(err) => {
-                warn!("Killing all existing plugin processes due to error spawning new one...");
-                procs.abort_all();
-                return plugin_err!(format!("Failed to spawn process named {name}: {err}"));
+    let mut deferred = vec![];
+    for (name, run) in runs {
+        // Permits are taken in order, so plugins deferred by the last update start first.
+        let Some(permit) = deferrals.permit(&semaphore).await else {
+            deferred.push(name);
+            continue;
+        };
+
+        deferrals.started.insert(name.clone());
+        let span = debug_span!("plugin", plugin = %name);
+        procs.spawn(
+            async move {
+                let _permit = permit;
+                let start = Instant::now();
+                let cursor = run.probe(&name, stage).await;
+                let outcome = if cursor.is_some() && cursor == run.cursor {
+                    Ok(StageOutcome::Unchanged)
+                } else {
+                    Arc::new(run).run_pool(&name, stage).await
+                };
+                (name, outcome, cursor, start.elapsed())
             }
-        }
+            .instrument(span),
+        );
+    }
+
+    if !deferred.is_empty() {
+        warn!(
+            "Ran out of time to start plugins for {stage} stage, deferring to the next update: {}",
+            deferred.join(", ")
+        );
+        deferrals.deferred.extend(deferred);
     }
 
     let mut results = vec![];
     while let Some(join_result) = procs.join_next().await {
         match join_result {
-            Ok((name, proc_result)) => match proc_result {
-                Ok(output) => results.push(PluginResult {
-                    stage,
-                    name,
-                    code: output.status.code(),
-                    stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-                }),
+            Ok((name, outcome, cursor, duration)) => match outcome {
+                Ok(StageOutcome::Finished(output)) => {
+                    debug!(
+                        "Plugin {name} finished {stage} stage in {}ms with exit code: {:?}",
+                        duration.as_millis(),
+                        output.status.code()
+                    );
+                    if let (true, Some(cursor)) = (output.status.success(), cursor) {
+                        con.set_plugin_cursor(&name, &stage.to_string(), &cursor)
+                            .await?;
+                    }
+                    results.push(PluginResult {
+                        stage,
+                        name,
+                        code: output.status.code(),
+                        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+                        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+                        duration,
+                        timed_out: false,
+                        log_path: None,
+                        skipped: false,
+                    });
+                }
+                Ok(StageOutcome::TimedOut(limit)) => {
+                    debug!(
+                        "Plugin {name} was killed after exceeding its timeout of {}s in {stage} stage.",
+                        limit.as_secs()
+                    );
+                    results.push(PluginResult {
+                        stage,
+                        name,
+                        code: None,
+                        stdout: String::new(),
+                        stderr: format!("Killed after exceeding timeout of {}s.", limit.as_secs()),
+                        duration,
+                        timed_out: true,
+                        log_path: None,
+                        skipped: false,
+                    });
+                }
+                Ok(StageOutcome::Pooled(pool)) => {
+                    debug!(
+                        "Plugin {name} finished {stage} stage in {}ms with {} of {} batches failed.",
+                        duration.as_millis(),
+                        pool.failed,
+                        pool.batches
+                    );
+                    if let (0, Some(cursor)) = (pool.failed, cursor) {
+                        con.set_plugin_cursor(&name, &stage.to_string(), &cursor)
+                            .await?;
+                    }
+                    results.push(PluginResult {
+                        stage,
+                        name,
+                        code: pool.code,
+                        stdout: pool.stdout,
+                        stderr: pool.stderr,
+                        duration,
+                        timed_out: pool.timed_out,
+                        log_path: None,
+                        skipped: false,
+                    });
+                }
+                Ok(StageOutcome::Unchanged) => {
+                    info!("Plugin {name} skipped {stage} stage, as its probe reported no upstream changes.");
+                    results.push(PluginResult {
+                        stage,
+                        name,
+                        code: Some(0),
+                        stdout: String::new(),
+                        stderr: String::new(),
+                        duration,
+                        timed_out: false,
+                        log_path: None,
+                        skipped: true,
+                    });
+                }
                 Err(err) => {
-                    return plugin_err!(format!("Error while retrieving plugin output: {err}"))
+                    warn!("Killing all existing plugin processes due to error running {name}...");
+                    procs.abort_all();
+                    return plugin_err!(format!("Failed to run process named {name}: {err}"));
                 }
             },
             Err(err) => {
@@ -122,7 +740,341 @@ pub async fn run_plugin_stage(
         }
     }
 
-    Ok(results)
+    let failures = results
+        .iter()
+        .filter_map(Event::plugin_failure)
+        .collect::<Vec<_>>();
+    notify(&config.notifications, &failures).await;
+    metrics::record_plugins(&results);
+
+    snapshot.end_change = last_change(&mut con).await?;
+    debug!(
+        "Plugins in {stage} stage made changes after {} up to {}.",
+        snapshot.start_change.as_deref().unwrap_or("the start"),
+        snapshot.end_change.as_deref().unwrap_or("nothing")
+    );
+
+    Ok((snapshot, results))
+}
+
+/// Writes the output of each plugin in the results to a file in the log directory for a run,
+/// and records the path in the result. Skipped stages have no output, so they have no file.
+pub fn write_plugin_logs(
+    log_dir: &Path,
+    run_started: u64,
+    results: &mut [PluginResult],
+) -> NetdoxResult<()> {
+    let run_dir = log_dir.join(run_started.to_string());
+    if let Err(err) = fs::create_dir_all(&run_dir) {
+        return io_err!(format!(
+            "Failed to create plugin log directory {}: {err}",
+            run_dir.display()
+        ));
+    }
+
+    for result in results.iter_mut().filter(|result| !result.skipped) {
+        let path = run_dir.join(format!("{}-{}.log", result.stage, file_stem(&result.name)));
+        let log = format!(
+            "== stdout ==\n{}\n== stderr ==\n{}",
+            result.stdout, result.stderr
+        );
+        if let Err(err) = fs::write(&path, log) {
+            return io_err!(format!(
+                "Failed to write plugin log {}: {err}",
+                path.display()
+            ));
+        }
+        result.log_path = Some(path);
+    }
+
+    Ok(())
+}
+
+/// Returns the config fields a plugin reported reading in its output,
+/// or None if it did not report them.
+fn reported_fields(stdout: &str) -> Option<BTreeSet<String>> {
+    let mut reported = None;
+    for line in stdout.lines() {
+        if let Some(fields) = line.trim().strip_prefix(FIELDS_PREFIX) {
+            reported.get_or_insert_with(BTreeSet::new).extend(
+                fields
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|field| !field.is_empty())
+                    .map(str::to_string),
+            );
+        }
+    }
+    reported
+}
+
+/// Config fields of a plugin that do not match the fields it reported reading.
+#[derive(Debug, PartialEq, Eq)]
+pub struct FieldWarning {
+    pub plugin: String,
+    /// Fields that are configured but were not read.
+    pub unused: BTreeSet<String>,
+    /// Fields that were read but are not configured.
+    pub missing: BTreeSet<String>,
+}
+
+impl FieldWarning {
+    /// Describes the unused and missing fields.
+    pub fn describe(&self) -> String {
+        let mut parts = vec![];
+        if !self.unused.is_empty() {
+            parts.push(format!(
+                "configured but not read: {}",
+                self.unused.iter().join(", ")
+            ));
+        }
+        if !self.missing.is_empty() {
+            parts.push(format!(
+                "read but not configured: {}",
+                self.missing.iter().join(", ")
+            ));
+        }
+        parts.join("; ")
+    }
+}
+
+/// Compares the config fields each plugin reported reading, in any of the stages it ran,
+/// with the fields configured for the plugin and those stages.
+/// Plugins that did not report the fields they read, and stages that were skipped, are ignored.
+pub fn field_warnings(plugins: &[PluginConfig], results: &[PluginResult]) -> Vec<FieldWarning> {
+    let mut warnings = vec![];
+    for plugin in plugins {
+        let runs = results
+            .iter()
+            .filter(|result| result.name == plugin.name && !result.skipped)
+            .collect::<Vec<_>>();
+        let Some(read) = runs
+            .iter()
+            .filter_map(|result| reported_fields(&result.stdout))
+            .reduce(|mut read, fields| {
+                read.extend(fields);
+                read
+            })
+        else {
+            continue;
+        };
+
+        let configured = plugin
+            .fields
+            .keys()
+            .chain(
+                runs.iter()
+                    .filter_map(|result| plugin.stages.get(&result.stage))
+                    .flat_map(|stage| stage.fields.keys()),
+            )
+            .cloned()
+            .collect::<BTreeSet<_>>();
+
+        let warning = FieldWarning {
+            plugin: plugin.name.clone(),
+            unused: configured.difference(&read).cloned().collect(),
+            missing: read.difference(&configured).cloned().collect(),
+        };
+        if !warning.unused.is_empty() || !warning.missing.is_empty() {
+            warnings.push(warning);
+        }
+    }
+    warnings
+}
+
+/// Creates a report summarising every plugin in the results,
+/// and the config fields that did not match the fields the plugins reported reading.
+pub async fn plugin_run_report(
+    con: &mut impl DataConn,
+    results: &[PluginResult],
+    field_warnings: &[FieldWarning],
+) -> NetdoxResult<()> {
+    let id = "plugin-runs";
+    let columns = ["Plugin", "Stage", "Exit Code", "Duration", "Log"];
+
+    let mut content = columns.map(str::to_string).to_vec();
+    for result in results {
+        let code = match result.code {
+            _ if result.timed_out => "timed out".to_string(),
+            _ if result.skipped => "skipped".to_string(),
+            Some(code) => code.to_string(),
+            None => "unknown".to_string(),
+        };
+        content.extend([
+            result.name.clone(),
+            result.stage.to_string(),
+            code,
+            format!("{:.1}s", result.duration.as_secs_f64()),
+            result
+                .log_path
+                .as_ref()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+        ]);
+    }
+
+    let length = if field_warnings.is_empty() { 1 } else { 2 };
+    con.put_report(id, "Plugin Runs", length).await?;
+    con.put_report_data(
+        id,
+        0,
+        &Data::Table {
+            id: "plugin-runs-summary".to_string(),
+            title: "Plugins in the last update".to_string(),
+            columns: columns.len(),
+            plugin: NETDOX_PLUGIN.to_string(),
+            content,
+        },
+    )
+    .await?;
+
+    if !field_warnings.is_empty() {
+        con.put_report_data(
+            id,
+            1,
+            &Data::List {
+                id: "plugin-runs-fields".to_string(),
+                title: "Config fields that do not match the fields plugins read".to_string(),
+                plugin: NETDOX_PLUGIN.to_string(),
+                content: field_warnings
+                    .iter()
+                    .map(|warning| {
+                        (
+                            warning.plugin.clone(),
+                            warning.plugin.clone(),
+                            warning.describe(),
+                        )
+                    })
+                    .collect(),
+            },
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Creates a report on the health of each plugin in the results:
+/// the stages it ran, how they exited, how long they took,
+/// how many objects it created in the changes after `since`,
+/// and the DNS names it was refused because they are outside its scope.
+pub async fn plugin_health_report(
+    con: &mut impl DataConn,
+    results: &[PluginResult],
+    since: Option<&str>,
+    violations: &HashMap<String, HashSet<String>>,
+) -> NetdoxResult<()> {
+    let id = "plugin-health";
+    let columns = [
+        "Plugin",
+        "Stages",
+        "Exit Codes",
+        "Duration",
+        "Objects Created",
+        "Refused Names",
+    ];
+
+    let changes = con.get_changes(since).await?;
+    let mut content = columns.map(str::to_string).to_vec();
+    content.extend(
+        plugin_health(results, &changes, violations)
+            .into_iter()
+            .flatten(),
+    );
+
+    let length = if violations.is_empty() { 1 } else { 2 };
+    con.put_report(id, "Plugin Health", length).await?;
+    con.put_report_data(
+        id,
+        0,
+        &Data::Table {
+            id: "plugin-health-summary".to_string(),
+            title: "Plugin health in the last update".to_string(),
+            columns: columns.len(),
+            plugin: NETDOX_PLUGIN.to_string(),
+            content,
+        },
+    )
+    .await?;
+
+    if !violations.is_empty() {
+        con.put_report_data(
+            id,
+            1,
+            &Data::List {
+                id: "plugin-health-scope".to_string(),
+                title: "DNS names refused because they are outside the scope of the plugin"
+                    .to_string(),
+                plugin: NETDOX_PLUGIN.to_string(),
+                content: violations
+                    .iter()
+                    .sorted_by_key(|(plugin, _)| *plugin)
+                    .map(|(plugin, names)| {
+                        (
+                            plugin.clone(),
+                            plugin.clone(),
+                            names.iter().sorted().join(", "),
+                        )
+                    })
+                    .collect(),
+            },
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Returns a row of the plugin health report for each plugin in the results,
+/// in the order they first appear.
+fn plugin_health(
+    results: &[PluginResult],
+    changes: &[ChangelogEntry],
+    violations: &HashMap<String, HashSet<String>>,
+) -> Vec<[String; 6]> {
+    let mut created: HashMap<&str, usize> = HashMap::new();
+    for entry in changes {
+        use Change as CT;
+        if let CT::CreateDnsName { plugin, .. }
+        | CT::CreateDnsRecord { plugin, .. }
+        | CT::CreatePluginNode { plugin, .. }
+        | CT::CreateReport { plugin, .. }
+        | CT::CreatedData { plugin, .. } = &entry.change
+        {
+            *created.entry(plugin).or_default() += 1;
+        }
+    }
+
+    let mut plugins: IndexMap<&str, Vec<&PluginResult>> = IndexMap::new();
+    for result in results {
+        plugins.entry(&result.name).or_default().push(result);
+    }
+
+    plugins
+        .into_iter()
+        .map(|(name, runs)| {
+            let codes = runs.iter().map(|run| match run.code {
+                _ if run.timed_out => "timed out".to_string(),
+                _ if run.skipped => "skipped".to_string(),
+                Some(code) => code.to_string(),
+                None => "unknown".to_string(),
+            });
+            let duration: Duration = runs.iter().map(|run| run.duration).sum();
+
+            [
+                name.to_string(),
+                runs.iter().map(|run| run.stage.to_string()).join(", "),
+                codes.collect::<Vec<_>>().join(", "),
+                format!("{:.1}s", duration.as_secs_f64()),
+                created.get(name).copied().unwrap_or_default().to_string(),
+                violations
+                    .get(name)
+                    .map(HashSet::len)
+                    .unwrap_or_default()
+                    .to_string(),
+            ]
+        })
+        .collect()
 }
 
 /// Creates a report from the plugin results in the list.
@@ -161,3 +1113,368 @@ pub async fn plugin_error_report(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use tokio::sync::Semaphore;
+
+    use super::{
+        check_schema_versions, field_warnings, plugin_health, Deferrals, FieldWarning,
+        PluginResult, StageOutcome, StageRun, WorkerBatch, WorkerNode, WorkerPool,
+    };
+    use crate::{
+        config::{PluginConfig, PluginStage, PluginStageConfig},
+        data::model::{Change, ChangelogEntry, PluginDisplay, PluginScope, SCHEMA_VERSION},
+    };
+
+    fn result(name: &str, stage: PluginStage, code: Option<i32>, secs: u64) -> PluginResult {
+        PluginResult {
+            stage,
+            name: name.to_string(),
+            code,
+            stdout: String::new(),
+            stderr: String::new(),
+            duration: Duration::from_secs(secs),
+            timed_out: false,
+            log_path: None,
+            skipped: false,
+        }
+    }
+
+    #[test]
+    fn test_plugin_health() {
+        let results = [
+            result("dns", PluginStage::WriteOnly, Some(0), 2),
+            result("monitor", PluginStage::ReadWrite, None, 5),
+            result("dns", PluginStage::Connectors, Some(1), 1),
+            PluginResult {
+                skipped: true,
+                ..result("monitor", PluginStage::WriteOnly, Some(0), 0)
+            },
+        ];
+        let changes = [
+            ChangelogEntry {
+                id: "0-1".to_string(),
+                change: Change::CreateDnsName {
+                    plugin: "dns".to_string(),
+                    qname: "[net]a.com".to_string(),
+                },
+            },
+            ChangelogEntry {
+                id: "0-2".to_string(),
+                change: Change::UpdatedMetadata {
+                    plugin: "dns".to_string(),
+                    obj_id: "dns;[net]a.com".to_string(),
+                },
+            },
+            ChangelogEntry {
+                id: "0-3".to_string(),
+                change: Change::CreatePluginNode {
+                    plugin: "dns".to_string(),
+                    node_id: "[net]a.com".to_string(),
+                },
+            },
+        ];
+
+        let violations = HashMap::from([(
+            "monitor".to_string(),
+            HashSet::from(["[net]a.org".to_string(), "[net]b.org".to_string()]),
+        )]);
+
+        assert_eq!(
+            plugin_health(&results, &changes, &violations),
+            vec![
+                [
+                    "dns".to_string(),
+                    "write-only, connectors".to_string(),
+                    "0, 1".to_string(),
+                    "3.0s".to_string(),
+                    "2".to_string(),
+                    "0".to_string(),
+                ],
+                [
+                    "monitor".to_string(),
+                    "read-write, write-only".to_string(),
+                    "unknown, skipped".to_string(),
+                    "5.0s".to_string(),
+                    "0".to_string(),
+                    "2".to_string(),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_deferrals_remaining() {
+        let names = |names: &[&str]| {
+            names
+                .iter()
+                .map(ToString::to_string)
+                .collect::<HashSet<_>>()
+        };
+        let deferrals = Deferrals {
+            deadline: None,
+            previous: names(&["ran", "skipped", "deferred-again"]),
+            deferred: names(&["deferred-again", "new"]),
+            started: names(&["ran", "deferred-again", "other"]),
+        };
+
+        assert_eq!(
+            deferrals.remaining(),
+            names(&["skipped", "deferred-again", "new"])
+        );
+        assert!(!deferrals.is_expired());
+    }
+
+    #[tokio::test]
+    async fn test_deferrals_permit() {
+        let semaphore = Arc::new(Semaphore::new(1));
+        let mut deferrals = Deferrals::default();
+        let held = deferrals.permit(&semaphore).await;
+        assert!(held.is_some());
+
+        // Waiting for a running plugin to finish stops when the time runs out.
+        deferrals.deadline = Some(Instant::now() + Duration::from_millis(50));
+        let waited = Instant::now();
+        assert!(deferrals.permit(&semaphore).await.is_none());
+        assert!(waited.elapsed() < Duration::from_secs(5));
+
+        drop(held);
+        deferrals.deadline = Some(Instant::now() + Duration::from_secs(10));
+        assert!(deferrals.permit(&semaphore).await.is_some());
+
+        deferrals.deadline = Some(Instant::now());
+        assert!(deferrals.permit(&semaphore).await.is_none());
+    }
+
+    fn plugin(name: &str, schema_version: Option<u32>) -> PluginConfig {
+        PluginConfig {
+            name: name.to_string(),
+            display: PluginDisplay::default(),
+            scope: PluginScope::default(),
+            timeout_seconds: None,
+            schema_version,
+            fields: HashMap::new(),
+            stages: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_check_schema_versions() {
+        let plugins = [
+            plugin("current", Some(SCHEMA_VERSION)),
+            plugin("undeclared", None),
+            plugin("outdated", Some(SCHEMA_VERSION - 1)),
+        ];
+
+        let err = check_schema_versions(&plugins, None, false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("outdated"));
+        assert!(!err.contains("current"));
+
+        let outdated = vec!["outdated".to_string()];
+        assert!(check_schema_versions(&plugins, Some(&outdated), true).is_ok());
+        assert!(check_schema_versions(&plugins, Some(&outdated), false).is_err());
+    }
+
+    #[test]
+    fn test_field_warnings() {
+        let mut reporting = plugin("reporting", None);
+        reporting.fields = HashMap::from([
+            (
+                "api_url".to_string(),
+                toml::Value::from("https://example.com"),
+            ),
+            ("old_option".to_string(), toml::Value::from(true)),
+        ]);
+        reporting.stages = HashMap::from([(
+            PluginStage::WriteOnly,
+            PluginStageConfig {
+                path: "plugin".to_string(),
+                retries: 0,
+                probe: None,
+                workers: None,
+                fields: HashMap::from([("zone".to_string(), toml::Value::from("a.com"))]),
+            },
+        )]);
+        let mut silent = plugin("silent", None);
+        silent.fields = HashMap::from([("unread".to_string(), toml::Value::from(1))]);
+
+        let mut reported = result("reporting", PluginStage::WriteOnly, Some(0), 1);
+        reported.stdout =
+            "Starting...\nnetdox-fields: api_url, zone\nnetdox-fields: api_key\n".to_string();
+        let results = [
+            reported,
+            result("silent", PluginStage::WriteOnly, Some(0), 1),
+        ];
+
+        let warnings = field_warnings(&[reporting, silent], &results);
+        assert_eq!(
+            warnings,
+            vec![FieldWarning {
+                plugin: "reporting".to_string(),
+                unused: BTreeSet::from(["old_option".to_string()]),
+                missing: BTreeSet::from(["api_key".to_string()]),
+            }]
+        );
+        assert_eq!(
+            warnings[0].describe(),
+            "configured but not read: old_option; read but not configured: api_key"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stage_probe() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Prints the cursor it is given, so the stage is skipped if there is one.
+        let path = std::env::temp_dir().join(format!("netdox-probe-{}.sh", std::process::id()));
+        std::fs::write(&path, "#!/bin/sh\necho \"$4\"\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut run = StageRun {
+            path: "plugin".to_string(),
+            args: [String::new(), String::new(), String::new()],
+            retries: 0,
+            backoff: Duration::ZERO,
+            timeout: Some(Duration::from_secs(10)),
+            probe: Some(path.display().to_string()),
+            cursor: Some("etag-1".to_string()),
+            pool: None,
+        };
+        assert_eq!(
+            run.probe("plugin", PluginStage::WriteOnly).await,
+            Some("etag-1".to_string())
+        );
+
+        run.cursor = None;
+        assert_eq!(run.probe("plugin", PluginStage::WriteOnly).await, None);
+
+        run.probe = None;
+        run.cursor = Some("etag-1".to_string());
+        assert_eq!(run.probe("plugin", PluginStage::WriteOnly).await, None);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stage_retries() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Records each attempt in a file, and fails until it has been run as many times as its first argument.
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("netdox-retry-{}.sh", std::process::id()));
+        let attempts = dir.join(format!("netdox-retry-{}.log", std::process::id()));
+        std::fs::write(
+            &path,
+            format!(
+                "#!/bin/sh\necho attempt >> {}\n\
+                [ \"$(wc -l < {0})\" -ge \"$1\" ] || {{ echo failed >&2; exit 3; }}\n",
+                attempts.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let run = |succeeds_on: u32, retries: u32| StageRun {
+            path: path.display().to_string(),
+            args: [succeeds_on.to_string(), String::new(), String::new()],
+            retries,
+            backoff: Duration::from_millis(10),
+            timeout: Some(Duration::from_secs(10)),
+            probe: None,
+            cursor: None,
+            pool: None,
+        };
+        let count = || {
+            let count = std::fs::read_to_string(&attempts).unwrap().lines().count();
+            std::fs::remove_file(&attempts).unwrap();
+            count
+        };
+
+        // Fails twice and then succeeds.
+        let Ok(StageOutcome::Finished(output)) =
+            run(3, 3).run("plugin", PluginStage::WriteOnly, None).await
+        else {
+            panic!("Stage did not finish.");
+        };
+        assert!(output.status.success());
+        assert_eq!(count(), 3);
+
+        // Fails every time, so the output of the last attempt is returned.
+        let Ok(StageOutcome::Finished(output)) =
+            run(10, 2).run("plugin", PluginStage::WriteOnly, None).await
+        else {
+            panic!("Stage did not finish.");
+        };
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "failed\n");
+        assert_eq!(count(), 3);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_worker_pool() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Prints the batch it is given, and fails for the node named bad.
+        let path = std::env::temp_dir().join(format!("netdox-worker-{}.sh", std::process::id()));
+        std::fs::write(
+            &path,
+            "#!/bin/sh\nprintf '%s' \"$4\"\ncase \"$4\" in *bad*) exit 3;; esac\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let node = |link_id: &str| WorkerNode {
+            link_id: link_id.to_string(),
+            name: link_id.to_uppercase(),
+            alt_names: BTreeSet::new(),
+            dns_names: BTreeSet::from([format!("[net]{link_id}.com")]),
+            plugins: BTreeSet::from(["plugin".to_string()]),
+            tags: BTreeSet::new(),
+            metadata: BTreeMap::from([("os".to_string(), "linux".to_string())]),
+        };
+        let batches = [vec![node("web"), node("db")], vec![node("bad")]]
+            .iter()
+            .map(|nodes| toml::to_string(&WorkerBatch { nodes }).unwrap())
+            .collect::<Vec<_>>();
+
+        let run = Arc::new(StageRun {
+            path: path.display().to_string(),
+            args: [String::new(), String::new(), String::new()],
+            retries: 0,
+            backoff: Duration::ZERO,
+            timeout: Some(Duration::from_secs(10)),
+            probe: None,
+            cursor: None,
+            pool: Some(WorkerPool {
+                concurrency: 1,
+                batches: batches.clone(),
+            }),
+        });
+        let Ok(StageOutcome::Pooled(pool)) = run.run_pool("plugin", PluginStage::Connectors).await
+        else {
+            panic!("Worker pool did not run.");
+        };
+
+        assert_eq!((pool.batches, pool.failed), (2, 1));
+        assert_eq!(pool.code, Some(3));
+        assert_eq!(pool.stdout, batches.concat());
+        assert_eq!(pool.stderr, "Batch 2 had exit code Some(3).\n");
+        assert!(pool.stdout.contains("link_id = \"web\""));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+//! Netdox plugin: {{name}}
+//!
+//! Netdox runs this binary with three arguments:
+//! 1. TOML string containing the redis config.
+//! 2. TOML string containing the config fields for this plugin.
+//! 3. TOML string describing the data store when the stage started.
+
+use std::{collections::HashMap, env, fs, path::PathBuf};
+
+use redis::{
+    cluster::ClusterClientBuilder,
+    sentinel::{SentinelClientBuilder, SentinelServerType},
+    Client, ClientTlsConfig, ConnectionAddr, ConnectionLike, IntoConnectionInfo,
+    RedisConnectionInfo, TlsCertificates, TlsMode,
+};
+use serde::Deserialize;
+
+const PLUGIN: &str = "{{name}}";
+const DEFAULT_SENTINEL_PORT: u16 = 26379;
+/// Hash tag at the start of every key in a redis cluster.
+const KEY_TAG: &str = "{netdox}";
+
+type Error = Box<dyn std::error::Error>;
+
+#[derive(Deserialize)]
+struct RedisConfig {
+    host: String,
+    port: u16,
+    db: i64,
+    username: Option<String>,
+    password: Option<String>,
+    tls: Option<TlsConfig>,
+    sentinel: Option<SentinelConfig>,
+    cluster: Option<ClusterConfig>,
+    #[serde(default)]
+    allow_insecure: bool,
+}
+
+#[derive(Deserialize)]
+struct TlsConfig {
+    ca_cert: Option<PathBuf>,
+    client_cert: Option<PathBuf>,
+    client_key: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct SentinelConfig {
+    master: String,
+    nodes: Vec<String>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ClusterConfig {
+    nodes: Vec<String>,
+}
+
+/// Reads the certificates in the TLS config.
+fn certificates(tls: &TlsConfig) -> Result<TlsCertificates, Error> {
+    let client_tls = match (&tls.client_cert, &tls.client_key) {
+        (Some(cert), Some(key)) => Some(ClientTlsConfig {
+            client_cert: fs::read(cert)?,
+            client_key: fs::read(key)?,
+        }),
+        _ => None,
+    };
+
+    Ok(TlsCertificates {
+        client_tls,
+        root_cert: tls.ca_cert.as_ref().map(fs::read).transpose()?,
+    })
+}
+
+/// Connects to the redis server or cluster netdox uses, through the sentinels if configured.
+fn connect(cfg: RedisConfig) -> Result<Box<dyn ConnectionLike>, Error> {
+    if cfg.tls.is_none() && cfg.password.is_none() && !cfg.allow_insecure {
+        return Err("Redis has neither TLS nor a password configured. \
+            Set redis.allow_insecure = true to connect anyway."
+            .into());
+    }
+    let certs = cfg.tls.as_ref().map(certificates).transpose()?;
+
+    if let Some(cluster) = cfg.cluster {
+        let scheme = if certs.is_some() { "rediss" } else { "redis" };
+        let nodes = cluster
+            .nodes
+            .iter()
+            .map(|node| format!("{scheme}://{node}"))
+            .collect::<Vec<_>>();
+        let mut builder = ClusterClientBuilder::new(nodes);
+        if let Some(username) = &cfg.username {
+            builder = builder.username(username);
+        }
+        if let Some(password) = &cfg.password {
+            builder = builder.password(password);
+        }
+        if let Some(certs) = certs {
+            builder = builder.tls(TlsMode::Secure).certs(certs);
+        }
+        return Ok(Box::new(builder.build()?.get_connection()?));
+    }
+
+    let Some(sentinel) = cfg.sentinel else {
+        let addr = match certs {
+            Some(_) => ConnectionAddr::TcpTls {
+                host: cfg.host,
+                port: cfg.port,
+                insecure: false,
+                tls_params: None,
+            },
+            None => ConnectionAddr::Tcp(cfg.host, cfg.port),
+        };
+        let mut redis = RedisConnectionInfo::default().set_db(cfg.db);
+        if let Some(username) = &cfg.username {
+            redis = redis.set_username(username);
+        }
+        if let Some(password) = &cfg.password {
+            redis = redis.set_password(password);
+        }
+        let info = addr.into_connection_info()?.set_redis_settings(redis);
+
+        let client = match certs {
+            Some(certs) => Client::build_with_tls(info, certs)?,
+            None => Client::open(info)?,
+        };
+        return Ok(Box::new(client.get_connection()?));
+    };
+
+    let mut addrs = vec![];
+    for node in &sentinel.nodes {
+        let (host, port) = match node.rsplit_once(':') {
+            Some((host, port)) => (host.to_string(), port.parse()?),
+            None => (node.clone(), DEFAULT_SENTINEL_PORT),
+        };
+        addrs.push(match certs {
+            Some(_) => ConnectionAddr::TcpTls {
+                host,
+                port,
+                insecure: false,
+                tls_params: None,
+            },
+            None => ConnectionAddr::Tcp(host, port),
+        });
+    }
+
+    let mut builder =
+        SentinelClientBuilder::new(addrs, &sentinel.master, SentinelServerType::Master)?
+            .set_client_to_redis_db(cfg.db);
+    if let Some(username) = &cfg.username {
+        builder = builder.set_client_to_redis_username(username);
+    }
+    if let Some(password) = &cfg.password {
+        builder = builder.set_client_to_redis_password(password);
+    }
+    if let Some(username) = &sentinel.username {
+        builder = builder.set_client_to_sentinel_username(username);
+    }
+    if let Some(password) = &sentinel.password {
+        builder = builder.set_client_to_sentinel_password(password);
+    }
+    if let Some(certs) = certs {
+        builder = builder
+            .set_client_to_redis_tls_mode(TlsMode::Secure)
+            .set_client_to_redis_certificates(certs.clone())
+            .set_client_to_sentinel_tls_mode(TlsMode::Secure)
+            .set_client_to_sentinel_certificates(certs);
+    }
+
+    Ok(Box::new(builder.build()?.get_connection()?))
+}
+
+fn main() -> Result<(), Error> {
+    let args: Vec<String> = env::args().collect();
+    let redis_cfg: RedisConfig = toml::from_str(&args[1])?;
+    let plugin_cfg: HashMap<String, toml::Value> = toml::from_str(&args[2])?;
+
+    // In a cluster every key starts with the hash tag,
+    // so the calls go to the node holding the data.
+    let key_tag = if redis_cfg.cluster.is_some() {
+        KEY_TAG
+    } else {
+        ""
+    };
+    let mut con = connect(redis_cfg)?;
+
+    // Creates an A record for example.com in the default network.
+    redis::cmd("FCALL")
+        .arg("netdox_create_dns")
+        .arg(1)
+        .arg(format!("{key_tag}example.com"))
+        .arg(PLUGIN)
+        .arg("A")
+        .arg("192.168.0.1")
+        .query::<()>(&mut *con)?;
+
+    // Creates a node that owns example.com.
+    // Reports the config fields this plugin reads, so netdox can warn about any others.
+    println!("netdox-fields: node_name");
+
+    let node_name = plugin_cfg
+        .get("node_name")
+        .and_then(toml::Value::as_str)
+        .unwrap_or("Example Node");
+
+    redis::cmd("FCALL")
+        .arg("netdox_create_node")
+        .arg(1)
+        .arg(format!("{key_tag}example.com"))
+        .arg(PLUGIN)
+        .arg(node_name)
+        .arg("false")
+        .arg(format!("{PLUGIN}-example-node"))
+        .query::<()>(&mut *con)?;
+
+    Ok(())
+}
@@ -0,0 +1,115 @@
+#!/usr/bin/env python3
+"""
+Netdox plugin: {{name}}
+
+Netdox runs this script with three arguments:
+1. TOML string containing the redis config.
+2. TOML string containing the config fields for this plugin.
+3. TOML string describing the data store when the stage started.
+"""
+import sys
+import tomllib
+
+import redis
+from redis.cluster import ClusterNode, RedisCluster
+from redis.sentinel import Sentinel
+
+PLUGIN = "{{name}}"
+DEFAULT_PORT = 6379
+DEFAULT_SENTINEL_PORT = 26379
+# Hash tag at the start of every key in a redis cluster.
+KEY_TAG = "{netdox}"
+
+
+def parse_addrs(nodes, default_port):
+    """Splits addresses like host:port into pairs of host and port."""
+    addrs = []
+    for node in nodes:
+        host, sep, port = node.rpartition(":")
+        addrs.append((host, int(port)) if sep else (node, default_port))
+    return addrs
+
+
+def connect(redis_cfg):
+    """Connects to the redis server netdox uses.
+
+    Connects through the sentinels, or to the cluster, if they are configured.
+    """
+    tls = redis_cfg.get("tls")
+    insecure = tls is None and redis_cfg.get("password") is None
+    if insecure and not redis_cfg.get("allow_insecure"):
+        sys.exit(
+            "Redis has neither TLS nor a password configured. "
+            "Set redis.allow_insecure = true to connect anyway."
+        )
+
+    tls_args = {}
+    if tls is not None:
+        tls_args = {
+            "ssl": True,
+            "ssl_ca_certs": tls.get("ca_cert"),
+            "ssl_certfile": tls.get("client_cert"),
+            "ssl_keyfile": tls.get("client_key"),
+        }
+    args = {
+        "username": redis_cfg.get("username"),
+        "password": redis_cfg.get("password"),
+        **tls_args,
+    }
+
+    cluster_cfg = redis_cfg.get("cluster")
+    if cluster_cfg is not None:
+        nodes = parse_addrs(cluster_cfg["nodes"], DEFAULT_PORT)
+        return RedisCluster(
+            startup_nodes=[ClusterNode(host, port) for host, port in nodes], **args
+        )
+
+    args["db"] = redis_cfg.get("db", 0)
+    sentinel_cfg = redis_cfg.get("sentinel")
+    if sentinel_cfg is None:
+        return redis.Redis(
+            host=redis_cfg["host"], port=redis_cfg.get("port", DEFAULT_PORT), **args
+        )
+
+    sentinel = Sentinel(
+        parse_addrs(sentinel_cfg["nodes"], DEFAULT_SENTINEL_PORT),
+        sentinel_kwargs={
+            "username": sentinel_cfg.get("username"),
+            "password": sentinel_cfg.get("password"),
+            **tls_args,
+        },
+    )
+    return sentinel.master_for(sentinel_cfg["master"], **args)
+
+
+def main():
+    redis_cfg = tomllib.loads(sys.argv[1])
+    plugin_cfg = tomllib.loads(sys.argv[2])
+
+    # Reports the config fields this plugin reads, so netdox can warn about any others.
+    print("netdox-fields: node_name")
+
+    con = connect(redis_cfg)
+    # In a cluster every key starts with the hash tag,
+    # so the calls go to the node holding the data.
+    key_tag = KEY_TAG if "cluster" in redis_cfg else ""
+
+    # Creates an A record for example.com in the default network.
+    con.fcall(
+        "netdox_create_dns", 1, f"{key_tag}example.com", PLUGIN, "A", "192.168.0.1"
+    )
+
+    # Creates a node that owns example.com.
+    con.fcall(
+        "netdox_create_node",
+        1,
+        f"{key_tag}example.com",
+        PLUGIN,
+        plugin_cfg.get("node_name", "Example Node"),
+        "false",
+        "{{name}}-example-node",
+    )
+
+
+if __name__ == "__main__":
+    main()
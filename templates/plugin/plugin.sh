@@ -0,0 +1,115 @@
+#!/usr/bin/env bash
+# Netdox plugin: {{name}}
+#
+# Netdox runs this script with three arguments:
+# 1. TOML string containing the redis config.
+# 2. TOML string containing the config fields for this plugin.
+# 3. TOML string describing the data store when the stage started.
+set -euo pipefail
+
+PLUGIN="{{name}}"
+REDIS_CFG="$1"
+PLUGIN_CFG="$2"
+
+# Reads a top level string or integer value from a TOML string.
+toml_get() {
+    echo "$1" | sed -n "/^\[/q; s/^$2 = \"\{0,1\}\([^\"]*\)\"\{0,1\}$/\1/p"
+}
+
+# Prints the lines of a table from a TOML string.
+toml_table() {
+    echo "$1" | sed -n "/^\[$2\]$/,/^\[/{/^\[/!p}"
+}
+
+REDIS_ARGS=(-n "$(toml_get "$REDIS_CFG" db)")
+KEY_TAG=""
+if [ -n "$(toml_get "$REDIS_CFG" username)" ]; then
+    REDIS_ARGS+=(--user "$(toml_get "$REDIS_CFG" username)")
+fi
+if [ -n "$(toml_get "$REDIS_CFG" password)" ]; then
+    REDIS_ARGS+=(--pass "$(toml_get "$REDIS_CFG" password)")
+fi
+
+TLS_ARGS=()
+if echo "$REDIS_CFG" | grep -qx '\[tls\]'; then
+    TLS_CFG="$(toml_table "$REDIS_CFG" tls)"
+    TLS_ARGS+=(--tls)
+    if [ -n "$(toml_get "$TLS_CFG" ca_cert)" ]; then
+        TLS_ARGS+=(--cacert "$(toml_get "$TLS_CFG" ca_cert)")
+    fi
+    if [ -n "$(toml_get "$TLS_CFG" client_cert)" ]; then
+        TLS_ARGS+=(--cert "$(toml_get "$TLS_CFG" client_cert)" --key "$(toml_get "$TLS_CFG" client_key)")
+    fi
+elif [ -z "$(toml_get "$REDIS_CFG" password)" ] && [ "$(toml_get "$REDIS_CFG" allow_insecure)" != true ]; then
+    echo "Redis has neither TLS nor a password configured." \
+        "Set redis.allow_insecure = true to connect anyway." >&2
+    exit 1
+fi
+REDIS_ARGS+=("${TLS_ARGS[@]}")
+
+# Asks the sentinels for the address of the master, if they are configured.
+if echo "$REDIS_CFG" | grep -qx '\[sentinel\]'; then
+    SENTINEL_CFG="$(toml_table "$REDIS_CFG" sentinel)"
+    SENTINEL_ARGS=("${TLS_ARGS[@]}")
+    if [ -n "$(toml_get "$SENTINEL_CFG" username)" ]; then
+        SENTINEL_ARGS+=(--user "$(toml_get "$SENTINEL_CFG" username)")
+    fi
+    if [ -n "$(toml_get "$SENTINEL_CFG" password)" ]; then
+        SENTINEL_ARGS+=(--pass "$(toml_get "$SENTINEL_CFG" password)")
+    fi
+
+    MASTER=()
+    NODES="$(echo "$SENTINEL_CFG" | sed -n 's/^nodes = \[\(.*\)\]$/\1/p' | tr -d '" ' | tr ',' ' ')"
+    for NODE in $NODES; do
+        HOST="${NODE%:*}"
+        PORT="${NODE##*:}"
+        if [ "$HOST" = "$NODE" ]; then
+            PORT=26379
+        fi
+        mapfile -t MASTER < <(redis-cli -h "$HOST" -p "$PORT" "${SENTINEL_ARGS[@]}" \
+            SENTINEL get-master-addr-by-name "$(toml_get "$SENTINEL_CFG" master)" 2>/dev/null || true)
+        if [ "${#MASTER[@]}" -eq 2 ]; then
+            break
+        fi
+    done
+    if [ "${#MASTER[@]}" -ne 2 ]; then
+        echo "Failed to find the redis master through the sentinels." >&2
+        exit 1
+    fi
+    REDIS_ARGS+=(-h "${MASTER[0]}" -p "${MASTER[1]}")
+elif echo "$REDIS_CFG" | grep -qx '\[cluster\]'; then
+    # Connects to the first node of the cluster that answers.
+    # Every key starts with the hash tag in a cluster, and -c follows redirects to the node holding them.
+    KEY_TAG="{netdox}"
+    CLUSTER_ARGS=()
+    NODES="$(toml_table "$REDIS_CFG" cluster | sed -n 's/^nodes = \[\(.*\)\]$/\1/p' | tr -d '" ' | tr ',' ' ')"
+    for NODE in $NODES; do
+        HOST="${NODE%:*}"
+        PORT="${NODE##*:}"
+        if [ "$HOST" = "$NODE" ]; then
+            PORT=6379
+        fi
+        if redis-cli -h "$HOST" -p "$PORT" "${REDIS_ARGS[@]}" PING >/dev/null 2>&1; then
+            CLUSTER_ARGS=(-c -h "$HOST" -p "$PORT")
+            break
+        fi
+    done
+    if [ "${#CLUSTER_ARGS[@]}" -eq 0 ]; then
+        echo "Failed to connect to any node of the redis cluster." >&2
+        exit 1
+    fi
+    REDIS_ARGS+=("${CLUSTER_ARGS[@]}")
+else
+    REDIS_ARGS+=(-h "$(toml_get "$REDIS_CFG" host)" -p "$(toml_get "$REDIS_CFG" port)")
+fi
+
+# Reports the config fields this plugin reads, so netdox can warn about any others.
+echo "netdox-fields: node_name"
+NODE_NAME="$(toml_get "$PLUGIN_CFG" node_name)"
+
+# Creates an A record for example.com in the default network.
+redis-cli "${REDIS_ARGS[@]}" FCALL netdox_create_dns 1 "${KEY_TAG}example.com" "$PLUGIN" A 192.168.0.1
+
+# Creates a node that owns example.com.
+redis-cli "${REDIS_ARGS[@]}" FCALL netdox_create_node 1 "${KEY_TAG}example.com" "$PLUGIN" \
+    "${NODE_NAME:-Example Node}" false "$PLUGIN-example-node"